@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tiff2::fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = fuzz::decompress(data, &fuzz::limits());
+});
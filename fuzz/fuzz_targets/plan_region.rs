@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tiff2::fuzz;
+
+fuzz_target!(|data: &[u8]| fuzz::plan_region(data));
@@ -0,0 +1,17 @@
+//! Write the seed inputs of each target to `corpus/<target>`, where
+//! `cargo fuzz run` picks them up
+
+use std::{fs, path::Path};
+
+use tiff2::fuzz::{seed_corpus, TARGETS};
+
+fn main() -> std::io::Result<()> {
+    for target in TARGETS {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus").join(target);
+        fs::create_dir_all(&dir)?;
+        for (i, seed) in seed_corpus(target).into_iter().enumerate() {
+            fs::write(dir.join(format!("seed-{i}")), seed)?;
+        }
+    }
+    Ok(())
+}
@@ -10,14 +10,15 @@
 //! the unsafe code guidelines).
 //!
 //! TODO: Would like to use std-lib here.
-use std::{mem, slice};
+use core::{mem, slice};
 
 macro_rules! integral_slice_as_bytes{($int:ty, $const:ident $(,$mut:ident)*) => {
     pub(crate) fn $const(slice: &[$int]) -> &[u8] {
         assert!(mem::align_of::<$int>() <= mem::size_of::<$int>());
         unsafe { slice::from_raw_parts(slice.as_ptr() as *const u8, mem::size_of_val(slice)) }
     }
-    $(pub(crate) fn $mut(slice: &mut [$int]) -> &mut [u8] {
+    $(#[allow(dead_code)]
+    pub(crate) fn $mut(slice: &mut [$int]) -> &mut [u8] {
         assert!(mem::align_of::<$int>() <= mem::size_of::<$int>());
         unsafe { slice::from_raw_parts_mut(slice.as_mut_ptr() as *mut u8, mem::size_of_val(slice)) }
     })*
@@ -0,0 +1,310 @@
+//! Which compressions, photometric interpretations and sample layouts this
+//! build of the crate can decode.
+//!
+//! Servers can use this to reject unsupported files with a precise error
+//! before fetching any image data:
+//! ```
+//! # use tiff2::{capabilities, capabilities::required_features, decoder::Limits, structs::Ifd, ByteOrder};
+//! let ifd_buf = [
+//!     0x01, 0x00,                         // Number of entries (1)
+//!     0x03, 0x01, 0x03, 0x00,             // Tag (Compression), Type (SHORT)
+//!     0x01, 0x00, 0x00, 0x00,             // Count (1)
+//!     0x01, 0x00, 0x00, 0x00,             // Value (1, no compression)
+//! ];
+//! let ifd = Ifd::from_buffer(&ifd_buf, ByteOrder::LittleEndian, false, &Limits::default()).unwrap();
+//! let required = required_features(&ifd).unwrap();
+//! assert!(capabilities().check(&required).is_ok());
+//! ```
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    error::{TiffError, TiffFormatError, TiffResult, TiffUnsupportedError},
+    structs::{
+        tags::{
+            CompressionMethod, PhotometricInterpretation, PlanarConfiguration, Predictor,
+            SampleFormat,
+        },
+        Ifd, Tag,
+    },
+};
+
+/// Features supported by the decoder in this build
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub compression_methods: Vec<CompressionMethod>,
+    pub photometric_interpretations: Vec<PhotometricInterpretation>,
+    pub bits_per_sample: Vec<u8>,
+    pub sample_formats: Vec<SampleFormat>,
+    pub planar_configurations: Vec<PlanarConfiguration>,
+    pub predictors: Vec<Predictor>,
+}
+
+/// Returns the features supported by the decoder in this build
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        compression_methods: vec![
+            CompressionMethod::None,
+            CompressionMethod::Deflate,
+            CompressionMethod::OldDeflate,
+            CompressionMethod::PackBits,
+            CompressionMethod::Fax4,
+            CompressionMethod::LERC,
+            #[cfg(feature = "lzma")]
+            CompressionMethod::LZMA,
+            #[cfg(feature = "zstd")]
+            CompressionMethod::ZSTD,
+            #[cfg(feature = "webp")]
+            CompressionMethod::WebP,
+            #[cfg(feature = "std")]
+            CompressionMethod::JPEG,
+            #[cfg(feature = "std")]
+            CompressionMethod::ModernJPEG,
+        ],
+        // samples are returned as stored, so no color conversion is needed
+        // for these, YCbCr and L*a*b* can be converted to RGB by
+        // `CogDecoder::decode_region_rgb`, the latter only with std
+        photometric_interpretations: vec![
+            PhotometricInterpretation::WhiteIsZero,
+            PhotometricInterpretation::BlackIsZero,
+            PhotometricInterpretation::RGB,
+            PhotometricInterpretation::RGBPalette,
+            PhotometricInterpretation::TransparencyMask,
+            PhotometricInterpretation::CMYK,
+            PhotometricInterpretation::YCbCr,
+            #[cfg(feature = "std")]
+            PhotometricInterpretation::CIELab,
+            #[cfg(feature = "std")]
+            PhotometricInterpretation::ICCLab,
+        ],
+        bits_per_sample: vec![1, 8, 16, 32, 64],
+        sample_formats: vec![SampleFormat::Uint, SampleFormat::Int, SampleFormat::IEEEFP],
+        planar_configurations: vec![PlanarConfiguration::Chunky, PlanarConfiguration::Planar],
+        predictors: vec![
+            Predictor::None,
+            Predictor::Horizontal,
+            Predictor::FloatingPoint,
+        ],
+    }
+}
+
+impl Capabilities {
+    /// Check whether an image with the given features can be decoded.
+    ///
+    /// Features are checked in a fixed order (compression, photometric
+    /// interpretation, bits per sample, sample format, planar configuration,
+    /// predictor), so the same file always results in the same error.
+    pub fn check(&self, required: &RequiredFeatures) -> TiffResult<()> {
+        if !self
+            .compression_methods
+            .contains(&required.compression_method)
+        {
+            return Err(TiffUnsupportedError::UnsupportedCompressionMethod(
+                required.compression_method,
+            )
+            .into());
+        }
+        if !self
+            .photometric_interpretations
+            .contains(&required.photometric_interpretation)
+        {
+            return Err(TiffUnsupportedError::UnsupportedInterpretation(
+                required.photometric_interpretation,
+            )
+            .into());
+        }
+        if let Some(b) = required
+            .bits_per_sample
+            .iter()
+            .find(|b| !self.bits_per_sample.contains(b))
+        {
+            return Err(TiffUnsupportedError::UnsupportedBitsPerChannel(*b).into());
+        }
+        if required
+            .sample_formats
+            .iter()
+            .any(|f| !self.sample_formats.contains(f))
+        {
+            return Err(TiffUnsupportedError::UnsupportedSampleFormat(
+                required.sample_formats.clone(),
+            )
+            .into());
+        }
+        if !self
+            .planar_configurations
+            .contains(&required.planar_configuration)
+        {
+            return Err(TiffUnsupportedError::UnsupportedPlanarConfig(Some(
+                required.planar_configuration,
+            ))
+            .into());
+        }
+        if !self.predictors.contains(&required.predictor) {
+            return Err(TiffUnsupportedError::UnsupportedPredictor(required.predictor).into());
+        }
+        Ok(())
+    }
+}
+
+/// Features the decoder needs to support to decode an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequiredFeatures {
+    pub compression_method: CompressionMethod,
+    pub photometric_interpretation: PhotometricInterpretation,
+    pub bits_per_sample: Vec<u8>,
+    pub sample_formats: Vec<SampleFormat>,
+    pub planar_configuration: PlanarConfiguration,
+    pub predictor: Predictor,
+}
+
+/// Determine the features needed to decode the image described by `ifd`.
+///
+/// Missing tags get their default value. Tags that are present, but not
+/// loaded result in a `UsageError::RequiredTagNotLoaded`.
+pub fn required_features(ifd: &Ifd) -> TiffResult<RequiredFeatures> {
+    let compression_method = ifd
+        .get_tag_value(&Tag::Compression)?
+        .map(u16::try_from)
+        .transpose()?
+        .map(CompressionMethod::from_u16_exhaustive)
+        .unwrap_or(CompressionMethod::None);
+
+    let photometric_interpretation = match ifd.get_tag_value(&Tag::PhotometricInterpretation)? {
+        Some(val) => PhotometricInterpretation::from_u16(u16::try_from(val)?)
+            .ok_or(TiffUnsupportedError::UnknownInterpretation)?,
+        // Baseline readers should treat a missing tag as BlackIsZero
+        None => PhotometricInterpretation::BlackIsZero,
+    };
+
+    let bits_per_sample = match ifd.get_tag_value(&Tag::BitsPerSample)? {
+        Some(val) => (0..usize::try_from(val.count)?)
+            .map(|i| Ok(u8::try_from(val.get_u64(i)?)?))
+            .collect::<TiffResult<Vec<u8>>>()?,
+        None => vec![1],
+    };
+
+    let sample_formats = match ifd.get_tag_value(&Tag::SampleFormat)? {
+        Some(val) => (0..usize::try_from(val.count)?)
+            .map(|i| {
+                Ok(SampleFormat::from_u16_exhaustive(u16::try_from(
+                    val.get_u64(i)?,
+                )?))
+            })
+            .collect::<TiffResult<Vec<SampleFormat>>>()?,
+        None => vec![SampleFormat::Uint],
+    };
+
+    let planar_configuration = match ifd.get_tag_value(&Tag::PlanarConfiguration)? {
+        Some(val) => {
+            let p = u16::try_from(val)?;
+            PlanarConfiguration::from_u16(p).ok_or(TiffError::FormatError(
+                TiffFormatError::UnknownPlanarConfiguration(p),
+            ))?
+        }
+        None => PlanarConfiguration::Chunky,
+    };
+
+    let predictor = match ifd.get_tag_value(&Tag::Predictor)? {
+        Some(val) => {
+            let p = u16::try_from(val)?;
+            Predictor::from_u16(p)
+                .ok_or(TiffError::FormatError(TiffFormatError::UnknownPredictor(p)))?
+        }
+        None => Predictor::None,
+    };
+
+    Ok(RequiredFeatures {
+        compression_method,
+        photometric_interpretation,
+        bits_per_sample,
+        sample_formats,
+        planar_configuration,
+        predictor,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{decoder::Limits, ByteOrder};
+
+    /// Little-endian, non-bigtiff IFD with SHORT entries that fit inline
+    fn ifd_with(entries: &[(Tag, u16)]) -> Ifd {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, value) in entries {
+            buf.extend_from_slice(&tag.to_u16().to_le_bytes());
+            buf.extend_from_slice(&3u16.to_le_bytes());
+            buf.extend_from_slice(&1u32.to_le_bytes());
+            buf.extend_from_slice(&u32::from(*value).to_le_bytes());
+        }
+        Ifd::from_buffer(&buf, ByteOrder::LittleEndian, false, &Limits::default()).unwrap()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let required = required_features(&ifd_with(&[])).unwrap();
+        assert_eq!(
+            required,
+            RequiredFeatures {
+                compression_method: CompressionMethod::None,
+                photometric_interpretation: PhotometricInterpretation::BlackIsZero,
+                bits_per_sample: vec![1],
+                sample_formats: vec![SampleFormat::Uint],
+                planar_configuration: PlanarConfiguration::Chunky,
+                predictor: Predictor::None,
+            }
+        );
+        assert!(capabilities().check(&required).is_ok());
+    }
+
+    #[test]
+    fn test_unsupported_compression() {
+        let ifd = ifd_with(&[(Tag::Compression, 34676), (Tag::BitsPerSample, 12)]);
+        let required = required_features(&ifd).unwrap();
+        // compression is checked before bit depth
+        match capabilities().check(&required).unwrap_err() {
+            TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedCompressionMethod(
+                CompressionMethod::Unknown(34676),
+            )) => {}
+            e => panic!("unexpected error {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_unsupported_bits() {
+        let ifd = ifd_with(&[(Tag::BitsPerSample, 12)]);
+        let required = required_features(&ifd).unwrap();
+        match capabilities().check(&required).unwrap_err() {
+            TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedBitsPerChannel(12)) => {}
+            e => panic!("unexpected error {e:?}"),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_lab() {
+        let ifd = ifd_with(&[
+            (Tag::PhotometricInterpretation, 8),
+            (Tag::SamplesPerPixel, 3),
+            (Tag::BitsPerSample, 8),
+        ]);
+        let required = required_features(&ifd).unwrap();
+        assert_eq!(
+            required.photometric_interpretation,
+            PhotometricInterpretation::CIELab
+        );
+        assert!(capabilities().check(&required).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_predictor() {
+        let ifd = ifd_with(&[(Tag::Predictor, 42)]);
+        assert!(matches!(
+            required_features(&ifd),
+            Err(TiffError::FormatError(TiffFormatError::UnknownPredictor(
+                42
+            )))
+        ));
+    }
+}
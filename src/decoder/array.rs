@@ -0,0 +1,159 @@
+//! Decoded regions as [`ndarray::Array3`]s.
+//!
+//! Chunky regions hold the samples of each pixel together, planar ones each
+//! band in a plane of its own. Either is wrapped as is, the strides of the
+//! array putting its axes in the requested [`AxisOrder`], so an array in the
+//! order the samples are stored in is in standard layout, and one in the
+//! other order can be made so with `as_standard_layout`.
+
+use ndarray::{Array3, ShapeBuilder};
+
+use crate::{
+    decoder::{DecodingResult, SampleType},
+    error::{TiffFormatError, TiffResult, UsageError},
+    f16,
+};
+
+/// Order of the axes of a region array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AxisOrder {
+    /// `[band, row, column]`, as most scientific code expects
+    #[default]
+    BandsRowsCols,
+    /// `[row, column, band]`, as image processing code expects
+    RowsColsBands,
+}
+
+/// Types of samples that regions can be read as
+pub trait ArraySample: Copy + Send + 'static {
+    /// Samples of this type in [`DecodingResult`]s
+    const SAMPLE_TYPE: SampleType;
+
+    /// The samples, if they are of this type
+    fn from_samples(samples: DecodingResult) -> Option<Vec<Self>>;
+}
+
+macro_rules! array_sample {
+    ($type:ty, $variant:ident) => {
+        impl ArraySample for $type {
+            const SAMPLE_TYPE: SampleType = SampleType::$variant;
+
+            fn from_samples(samples: DecodingResult) -> Option<Vec<Self>> {
+                match samples {
+                    DecodingResult::$variant(samples) => Some(samples),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+array_sample!(u8, U8);
+array_sample!(u16, U16);
+array_sample!(u32, U32);
+array_sample!(u64, U64);
+array_sample!(i8, I8);
+array_sample!(i16, I16);
+array_sample!(i32, I32);
+array_sample!(i64, I64);
+array_sample!(f16, F16);
+array_sample!(f32, F32);
+array_sample!(f64, F64);
+
+fn sample_type(samples: &DecodingResult) -> SampleType {
+    match samples {
+        DecodingResult::U8(_) => SampleType::U8,
+        DecodingResult::U16(_) => SampleType::U16,
+        DecodingResult::U32(_) => SampleType::U32,
+        DecodingResult::U64(_) => SampleType::U64,
+        DecodingResult::I8(_) => SampleType::I8,
+        DecodingResult::I16(_) => SampleType::I16,
+        DecodingResult::I32(_) => SampleType::I32,
+        DecodingResult::I64(_) => SampleType::I64,
+        DecodingResult::F16(_) => SampleType::F16,
+        DecodingResult::F32(_) => SampleType::F32,
+        DecodingResult::F64(_) => SampleType::F64,
+    }
+}
+
+/// Samples of a `width` by `height` region of `bands` bands as an array with
+/// axes in `order`. The samples are in planes of one band each if `planar` is
+/// set, as [`CogDecoder::decode_region`] returns them for planar images, and
+/// interleaved otherwise.
+///
+/// Fails with [`UsageError::SampleTypeMismatch`] if the samples aren't of
+/// type `T`, and with [`TiffFormatError::InvalidDimensions`] if there aren't
+/// as many as the size calls for.
+///
+/// [`CogDecoder::decode_region`]: crate::decoder::CogDecoder::decode_region
+pub fn to_array3<T: ArraySample>(
+    samples: DecodingResult,
+    width: u32,
+    height: u32,
+    bands: usize,
+    planar: bool,
+    order: AxisOrder,
+) -> TiffResult<Array3<T>> {
+    let actual = sample_type(&samples);
+    let samples = T::from_samples(samples).ok_or(UsageError::SampleTypeMismatch {
+        requested: T::SAMPLE_TYPE,
+        actual,
+    })?;
+    let (rows, cols) = (height as usize, width as usize);
+    let shape = match order {
+        AxisOrder::BandsRowsCols => (bands, rows, cols),
+        AxisOrder::RowsColsBands => (rows, cols, bands),
+    };
+    // steps in the buffer along each axis of the shape
+    let strides = match (order, planar) {
+        (AxisOrder::BandsRowsCols, true) => (rows * cols, cols, 1),
+        (AxisOrder::BandsRowsCols, false) => (1, cols * bands, bands),
+        (AxisOrder::RowsColsBands, true) => (cols, 1, rows * cols),
+        (AxisOrder::RowsColsBands, false) => (cols * bands, bands, 1),
+    };
+    Array3::from_shape_vec(shape.strides(strides), samples)
+        .map_err(|_| TiffFormatError::InvalidDimensions(width, height).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_array3() {
+        // 2 bands of a 3x2 region, interleaved and planar
+        let interleaved: Vec<u16> = vec![0, 10, 1, 11, 2, 12, 3, 13, 4, 14, 5, 15];
+        let planar: Vec<u16> = vec![0, 1, 2, 3, 4, 5, 10, 11, 12, 13, 14, 15];
+        for (samples, is_planar) in [(interleaved, false), (planar, true)] {
+            let samples = DecodingResult::U16(samples);
+            let bands = to_array3::<u16>(
+                samples.clone(),
+                3,
+                2,
+                2,
+                is_planar,
+                AxisOrder::BandsRowsCols,
+            )
+            .unwrap();
+            assert_eq!(bands.dim(), (2, 2, 3));
+            assert_eq!(bands[[1, 1, 0]], 13);
+            assert_eq!(bands.is_standard_layout(), is_planar);
+            let pixels =
+                to_array3::<u16>(samples, 3, 2, 2, is_planar, AxisOrder::RowsColsBands).unwrap();
+            assert_eq!(pixels.dim(), (2, 3, 2));
+            assert_eq!(pixels[[1, 0, 1]], 13);
+            assert_eq!(pixels.is_standard_layout(), !is_planar);
+            assert_eq!(bands.permuted_axes([1, 2, 0]), pixels);
+        }
+
+        let samples = DecodingResult::U8(vec![0; 6]);
+        let Err(crate::error::TiffError::UsageError(UsageError::SampleTypeMismatch {
+            requested: SampleType::F32,
+            actual: SampleType::U8,
+        })) = to_array3::<f32>(samples.clone(), 3, 2, 1, false, AxisOrder::default())
+        else {
+            panic!("u8 samples aren't f32");
+        };
+        assert!(to_array3::<u8>(samples, 3, 3, 1, false, AxisOrder::default()).is_err());
+    }
+}
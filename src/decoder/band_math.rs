@@ -0,0 +1,83 @@
+//! Per-pixel combinations of bands, computed while a region is assembled.
+//!
+//! Derived layers such as NDVI are a function of the samples of each pixel,
+//! so [`CogDecoder::decode_band_math`] evaluates them straight from the
+//! decoded chunks, without assembling the bands into a buffer first.
+//!
+//! [`CogDecoder::decode_band_math`]: crate::decoder::CogDecoder::decode_band_math
+
+use alloc::sync::Arc;
+use core::fmt;
+
+use crate::decoder::SampleType;
+
+/// Signature of the function of a [`BandMath`]
+type BandFn = dyn Fn(&[f64]) -> f64 + Send + Sync;
+
+/// A function of the samples of a pixel, widened to `f64` and indexed by
+/// band, yielding one sample of the derived layer.
+///
+/// Results are converted to the output [`SampleType`] like
+/// [`ChunkOpts::sample_type`] does: integers saturate, and NaN becomes 0.
+///
+/// [`ChunkOpts::sample_type`]: crate::decoder::ChunkOpts::sample_type
+#[derive(Clone)]
+pub struct BandMath {
+    func: Arc<BandFn>,
+    sample_type: SampleType,
+}
+
+impl fmt::Debug for BandMath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BandMath")
+            .field("sample_type", &self.sample_type)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BandMath {
+    pub fn new(
+        sample_type: SampleType,
+        func: impl Fn(&[f64]) -> f64 + Send + Sync + 'static,
+    ) -> Self {
+        BandMath {
+            func: Arc::new(func),
+            sample_type,
+        }
+    }
+
+    /// `(a - b) / (a + b)` as `F32`, e.g. NDVI with `a` the near infrared
+    /// band and `b` the red one. NaN where both are 0, or a band is missing.
+    pub fn normalized_difference(a: u16, b: u16) -> Self {
+        BandMath::new(SampleType::F32, move |samples| {
+            match (samples.get(usize::from(a)), samples.get(usize::from(b))) {
+                (Some(a), Some(b)) => (a - b) / (a + b),
+                _ => f64::NAN,
+            }
+        })
+    }
+
+    /// Type of the samples of the derived layer
+    pub fn sample_type(&self) -> SampleType {
+        self.sample_type
+    }
+
+    /// Evaluate the function for the samples of one pixel
+    pub fn eval(&self, samples: &[f64]) -> f64 {
+        (self.func)(samples)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalized_difference() {
+        let ndvi = BandMath::normalized_difference(3, 2);
+        assert_eq!(ndvi.sample_type(), SampleType::F32);
+        assert_eq!(ndvi.eval(&[0.0, 0.0, 20.0, 60.0]), 0.5);
+        assert!(ndvi.eval(&[0.0, 0.0, 0.0, 0.0]).is_nan());
+        assert!(ndvi.eval(&[1.0]).is_nan());
+    }
+}
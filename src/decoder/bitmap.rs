@@ -0,0 +1,152 @@
+//! Packed 1-bit output for bilevel images.
+//!
+//! Expanding bilevel documents to one byte per pixel costs 8× the memory.
+//! OCR pipelines and the like usually accept packed rows with a stride, which
+//! is what [`PackedBitmap`] holds.
+
+use alloc::vec::Vec;
+
+use crate::{
+    error::{TiffFormatError, TiffResult},
+    structs::tags::PhotometricInterpretation,
+};
+
+/// How bilevel (1 bit per sample) image data is returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BilevelOutput {
+    /// Expand every pixel to a `u8`: 0 for black, 255 for white
+    #[default]
+    Expanded,
+    /// Keep rows packed, see [`PackedBitmap`]
+    Packed,
+}
+
+/// 1-bit image with packed rows.
+///
+/// Pixels are stored most significant bit first and every row starts at a
+/// byte boundary, like in the TIFF chunk itself. Bits follow the MinIsBlack
+/// (`BlackIsZero`) convention: a set bit is white.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedBitmap {
+    pub width: usize,
+    pub height: usize,
+    /// Number of bytes per row
+    pub stride: usize,
+    pub data: Vec<u8>,
+}
+
+impl PackedBitmap {
+    /// Number of bytes per row for a given width
+    pub fn stride_for(width: usize) -> usize {
+        width.div_ceil(8)
+    }
+
+    /// Wrap decompressed chunk data.
+    ///
+    /// `WhiteIsZero` data is inverted, so the result always is MinIsBlack.
+    pub fn from_rows(
+        mut data: Vec<u8>,
+        width: usize,
+        height: usize,
+        photometric_interpretation: PhotometricInterpretation,
+    ) -> TiffResult<Self> {
+        let stride = Self::stride_for(width);
+        if data.len() != stride * height {
+            return Err(TiffFormatError::UnexpectedCompressedData {
+                actual_bytes: data.len(),
+                required_bytes: stride * height,
+            }
+            .into());
+        }
+        if photometric_interpretation == PhotometricInterpretation::WhiteIsZero {
+            data.iter_mut().for_each(|b| *b = !*b);
+        }
+        Ok(PackedBitmap {
+            width,
+            height,
+            stride,
+            data,
+        })
+    }
+
+    /// Packed bytes of row `y`, including the padding bits at the end
+    pub fn row(&self, y: usize) -> &[u8] {
+        &self.data[y * self.stride..(y + 1) * self.stride]
+    }
+
+    /// Whether the pixel at (`x`, `y`) is white
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.row(y)[x / 8] & (0x80 >> (x % 8)) != 0
+    }
+
+    /// Flip all bits, e.g. for consumers that expect a set bit to be black
+    pub fn invert(&mut self) {
+        self.data.iter_mut().for_each(|b| *b = !*b);
+    }
+
+    /// Expand to one byte per pixel: 0 for black, 255 for white
+    pub fn expand(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            let row = self.row(y);
+            out.extend((0..self.width).map(|x| {
+                if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                    255
+                } else {
+                    0
+                }
+            }));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_packed_rows() {
+        // 10 pixels wide, so 2 bytes per row with 6 padding bits
+        let data = vec![0b1010_0000, 0b1100_0000, 0b0000_0000, 0b0100_0000];
+        let bitmap =
+            PackedBitmap::from_rows(data, 10, 2, PhotometricInterpretation::BlackIsZero).unwrap();
+        assert_eq!(bitmap.stride, 2);
+        assert!(bitmap.get(0, 0));
+        assert!(!bitmap.get(1, 0));
+        assert!(bitmap.get(9, 0));
+        assert!(bitmap.get(9, 1));
+        assert!(!bitmap.get(8, 1));
+        assert_eq!(
+            bitmap.expand(),
+            vec![
+                255, 0, 255, 0, 0, 0, 0, 0, 255, 255, //
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 255,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_white_is_zero() {
+        let bitmap = PackedBitmap::from_rows(
+            vec![0b1000_0000],
+            2,
+            1,
+            PhotometricInterpretation::WhiteIsZero,
+        )
+        .unwrap();
+        assert_eq!(bitmap.expand(), vec![0, 255]);
+        let mut inverted = bitmap.clone();
+        inverted.invert();
+        assert_eq!(inverted.expand(), vec![255, 0]);
+    }
+
+    #[test]
+    fn test_wrong_size() {
+        assert!(
+            PackedBitmap::from_rows(vec![0; 3], 10, 2, PhotometricInterpretation::BlackIsZero)
+                .is_err()
+        );
+    }
+}
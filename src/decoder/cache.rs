@@ -0,0 +1,299 @@
+//! Caches and their keys.
+//!
+//! Chunks are cached by where they are stored, their offset and byte count,
+//! rather than by the level a decoder put their image at, which is just a slot
+//! in that decoder. Two files will happily both have a chunk at the same
+//! offset though, so keys of caches shared between files also include the
+//! identity of the file they were read from, provided by readers through
+//! [`CogReader::source_id`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    hash::Hash,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{decoder::CogReader, error::TiffResult};
+
+/// Identity of a file. Readers with equal keys must return the same bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SourceKey {
+    /// Local file, with its length and modification time so a file replaced
+    /// at the same path gets a new key
+    Path {
+        path: PathBuf,
+        len: u64,
+        modified: Option<SystemTime>,
+    },
+    /// Remote object, with its ETag if known so a replaced object gets a new key
+    Url { url: String, e_tag: Option<String> },
+    /// User-provided id, e.g. a content hash or database key
+    Custom(String),
+    /// Process-unique id for sources without a stable identity, see [`SourceKey::unique`]
+    Unique(u64),
+}
+
+impl SourceKey {
+    /// A key that is different from every other key created in this process.
+    ///
+    /// Data cached under it is never shared with other readers, but also never
+    /// mixed up with theirs.
+    pub fn unique() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        SourceKey::Unique(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Key of `file`, opened from `path`
+    pub fn file(path: &Path, file: &File) -> io::Result<Self> {
+        let metadata = file.metadata()?;
+        Ok(SourceKey::Path {
+            path: path.canonicalize()?,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// Key of a chunk of a source in a shared cache, by the offset and byte count
+/// of the chunk
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub source: Arc<SourceKey>,
+    pub byte_start: u64,
+    pub n_bytes: u64,
+}
+
+impl CacheKey {
+    pub fn new(source: Arc<SourceKey>, byte_start: u64, n_bytes: u64) -> Self {
+        CacheKey {
+            source,
+            byte_start,
+            n_bytes,
+        }
+    }
+}
+
+/// Least-recently-used cache that holds at most `capacity` bytes of values
+///
+/// ```
+/// # use tiff2::decoder::LruCache;
+/// let mut cache = LruCache::new(8);
+/// cache.insert("a", vec![0u8; 4]);
+/// cache.insert("b", vec![1u8; 4]);
+/// cache.get(&"a");
+/// // evicts "b", which was used least recently
+/// cache.insert("c", vec![2u8; 4]);
+/// assert!(cache.get(&"b").is_none());
+/// assert_eq!(cache.size(), 8);
+/// ```
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    size: usize,
+    /// incremented on every access, so lower is less recently used
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, V: AsRef<[u8]>> LruCache<K, V> {
+    /// Create an empty cache. A capacity of 0 disables it.
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Maximum number of bytes held
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of bytes currently held
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether `key` is cached, without marking it as used
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Get a value, marking it as most recently used
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.tick += 1;
+        let (value, used) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        self.order.insert(self.tick, key.clone());
+        *used = self.tick;
+        Some(value)
+    }
+
+    /// Insert a value, evicting the least recently used ones until it fits.
+    /// Values larger than the whole cache are not inserted.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.remove(&key);
+        let len = value.as_ref().len();
+        if len > self.capacity {
+            return;
+        }
+        while self.size + len > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.size -= evicted.as_ref().len();
+            }
+        }
+        self.tick += 1;
+        self.size += len;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, used) = self.entries.remove(key)?;
+        self.order.remove(&used);
+        self.size -= value.as_ref().len();
+        Some(value)
+    }
+
+    /// Remove all entries whose key doesn't satisfy `keep`
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        let removed: Vec<K> = self.entries.keys().filter(|k| !keep(k)).cloned().collect();
+        for key in removed {
+            self.remove(&key);
+        }
+    }
+}
+
+/// Wraps a [`CogReader`] with a user-provided [`SourceKey`], e.g. for in-memory
+/// files or readers that don't know their identity themselves.
+///
+/// ```
+/// # use tiff2::decoder::{CogReader, SourceKey, WithSourceId};
+/// let reader = WithSourceId::new(vec![0u8; 16], SourceKey::Custom("sha256:e3b0c442".into()));
+/// assert_eq!(reader.source_id(), SourceKey::Custom("sha256:e3b0c442".into()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct WithSourceId<R> {
+    inner: R,
+    source: SourceKey,
+}
+
+impl<R> WithSourceId<R> {
+    pub fn new(inner: R, source: SourceKey) -> Self {
+        WithSourceId { inner, source }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<R: CogReader> CogReader for WithSourceId<R> {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.inner.read_ifd(byte_start, n_bytes).await
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.inner.read_tag_data(byte_start, n_bytes).await
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.inner.read_image_data(byte_start, n_bytes).await
+    }
+
+    async fn read_vectored(&self, ranges: &[Range<u64>]) -> TiffResult<Vec<Bytes>> {
+        self.inner.read_vectored(ranges).await
+    }
+
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        self.inner.read_header(n_bytes).await
+    }
+
+    fn file_len(&self) -> Option<u64> {
+        self.inner.file_len()
+    }
+
+    fn source_id(&self) -> SourceKey {
+        self.source.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_keys_differ_per_source() {
+        let a = Arc::new(SourceKey::Path {
+            path: "a.tif".into(),
+            len: 1000,
+            modified: None,
+        });
+        let b = Arc::new(SourceKey::Url {
+            url: "https://example.com/a.tif".into(),
+            e_tag: Some("\"1\"".into()),
+        });
+        let mut cache = HashMap::new();
+        cache.insert(CacheKey::new(a.clone(), 8, 3), 1);
+        cache.insert(CacheKey::new(b.clone(), 8, 3), 2);
+        assert_eq!(cache[&CacheKey::new(a, 8, 3)], 1);
+        assert_eq!(cache[&CacheKey::new(b, 8, 3)], 2);
+        let replaced = Arc::new(SourceKey::Url {
+            url: "https://example.com/a.tif".into(),
+            e_tag: Some("\"2\"".into()),
+        });
+        assert!(!cache.contains_key(&CacheKey::new(replaced, 8, 3)));
+    }
+
+    #[test]
+    fn test_lru_budget() {
+        let mut cache = LruCache::new(10);
+        cache.insert(1, vec![0u8; 4]);
+        cache.insert(2, vec![0u8; 4]);
+        cache.insert(3, vec![0u8; 4]);
+        assert_eq!((cache.len(), cache.size()), (2, 8));
+        assert!(cache.get(&1).is_none());
+        // replacing a value updates the size
+        cache.insert(2, vec![0u8; 6]);
+        assert_eq!((cache.len(), cache.size()), (2, 10));
+        // too large to ever fit
+        cache.insert(4, vec![0u8; 11]);
+        assert_eq!(cache.get(&4), None);
+        assert_eq!(cache.size(), 10);
+        cache.retain(|k| *k != 2);
+        assert_eq!((cache.len(), cache.size()), (1, 4));
+    }
+
+    #[test]
+    fn test_unique() {
+        assert_ne!(SourceKey::unique(), SourceKey::unique());
+    }
+}
@@ -0,0 +1,1562 @@
+//! Decompression of a single chunk, given its metadata.
+//!
+//! This doesn't do any I/O, so it's available without `std` for the codecs
+//! that allow it.
+
+use alloc::{borrow::Cow, string::String, vec, vec::Vec};
+#[cfg(feature = "std")]
+use core::ops::Range;
+
+use half::f16;
+use miniz_oxide::inflate::{self, TINFLStatus};
+
+use crate::{
+    decoder::Limits,
+    error::{TiffError, TiffFormatError, TiffResult, TiffUnsupportedError, UsageError},
+    fax, lerc, predictor,
+    structs::{
+        tags::{CompressionMethod, PlanarConfiguration, Predictor, SampleFormat},
+        ChunkDims, ChunkMetaData, Tag,
+    },
+    util::fix_endianness,
+    ChunkType,
+};
+
+/// Upper bounds on plausible compression ratios (uncompressed size divided by
+/// compressed size) per compression method.
+///
+/// Forged byte counts can make decoders allocate far more than a file could
+/// ever legitimately decompress to. Chunks exceeding these bounds are flagged
+/// by [`check_compression_ratio`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionRatioLimits {
+    pub deflate: f64,
+    pub lzw: f64,
+    /// A run of 128 bytes takes at least 2, so anything above 64 is invalid
+    pub packbits: f64,
+    pub jpeg: f64,
+}
+
+impl Default for CompressionRatioLimits {
+    fn default() -> Self {
+        CompressionRatioLimits {
+            deflate: 10_000.0,
+            lzw: 10_000.0,
+            packbits: 64.0,
+            jpeg: 10_000.0,
+        }
+    }
+}
+
+impl CompressionRatioLimits {
+    /// Limit for a compression method, `None` if it isn't checked
+    pub fn max_ratio(&self, method: CompressionMethod) -> Option<f64> {
+        match method {
+            CompressionMethod::Deflate | CompressionMethod::OldDeflate => Some(self.deflate),
+            CompressionMethod::LZW => Some(self.lzw),
+            CompressionMethod::PackBits => Some(self.packbits),
+            CompressionMethod::JPEG | CompressionMethod::ModernJPEG => Some(self.jpeg),
+            _ => None,
+        }
+    }
+}
+
+/// Check whether a chunk of `chunk_bytes` compressed bytes decompressing to a
+/// full chunk of the first (and largest) sample plane is plausible, logging a
+/// warning and returning `false` if not.
+///
+/// Chunks whose decompressed size can't be determined pass.
+pub fn check_compression_ratio(
+    chunk_meta: &ChunkMetaData,
+    chunk_bytes: u64,
+    limits: &CompressionRatioLimits,
+) -> bool {
+    let (Some(max_ratio), Some(chunk_len)) = (
+        limits.max_ratio(chunk_meta.compression_method),
+        chunk_meta.stored_chunk_len(0),
+    ) else {
+        return true;
+    };
+    let ratio = chunk_len as f64 / chunk_bytes.max(1) as f64;
+    if ratio > max_ratio {
+        log::warn!(
+            "chunk of {chunk_bytes} {:?} compressed bytes should decompress to {chunk_len} bytes, a ratio of {ratio:.0}:1 (at most {max_ratio}:1 expected)",
+            chunk_meta.compression_method
+        );
+        return false;
+    }
+    true
+}
+
+/// Decompress the raw bytes of chunk `i_chunk`, undoing its predictor,
+/// converting samples to native byte order and upsampling subsampled chroma,
+/// so every pixel has all its samples.
+///
+/// Fails with [`TiffError::LimitsExceeded`] if the compressed or the full
+/// decompressed chunk is larger than [`Limits::max_chunk_bytes`].
+///
+/// [`TiffError::LimitsExceeded`]: crate::error::TiffError::LimitsExceeded
+pub fn decode_chunk_data(
+    data: Vec<u8>,
+    i_chunk: usize,
+    chunk_meta: &ChunkMetaData,
+    limits: &Limits,
+) -> TiffResult<Vec<u8>> {
+    unpredict(decompress(data, chunk_meta, limits)?, i_chunk, chunk_meta)
+}
+
+/// The decoded chunk of a sparse chunk, one with a byte count of 0 that GDAL
+/// writes for chunks of nothing but nodata: a full chunk of
+/// [`ChunkMetaData::nodata`], or of zeros if there is none.
+///
+/// Fails with [`TiffError::LimitsExceeded`] if the full chunk is larger than
+/// [`Limits::max_chunk_bytes`].
+///
+/// [`TiffError::LimitsExceeded`]: crate::error::TiffError::LimitsExceeded
+pub fn sparse_chunk_data(chunk_meta: &ChunkMetaData, limits: &Limits) -> TiffResult<Vec<u8>> {
+    let chunk_len = chunk_meta
+        .chunk_len()
+        .ok_or(TiffFormatError::RequiredTagNotFound(
+            match chunk_meta.chunk_type {
+                ChunkType::Tile => Tag::TileWidth,
+                ChunkType::Strip => Tag::RowsPerStrip,
+            },
+        ))?;
+    Limits::check(chunk_len, limits.max_chunk_bytes)?;
+    let chunk_len = usize::try_from(chunk_len)?;
+    let fill = SampleType::from_format(chunk_meta.sample_format, chunk_meta.bits_per_sample)
+        .zip(chunk_meta.nodata)
+        .and_then(|(sample_type, nodata)| nodata.to_sample(sample_type));
+    Ok(match fill {
+        Some(sample) => sample.repeat(chunk_len / sample.len()),
+        None => vec![0; chunk_len],
+    })
+}
+
+/// First stage of [`decode_chunk_data`]: checking limits and decompressing
+///
+/// Uncompressed data is taken as it is, which doesn't copy it if `data` is a
+/// `Vec` or a `Bytes` that isn't shared.
+pub(crate) fn decompress<D: AsRef<[u8]> + Into<Vec<u8>>>(
+    data: D,
+    chunk_meta: &ChunkMetaData,
+    limits: &Limits,
+) -> TiffResult<Vec<u8>> {
+    Limits::check(data.as_ref().len() as u64, limits.max_chunk_bytes)?;
+    Limits::check(chunk_meta.chunk_len().unwrap_or(0), limits.max_chunk_bytes)?;
+    if chunk_meta.compression_method == CompressionMethod::None {
+        return Ok(data.into());
+    }
+    let data = data.as_ref();
+    match chunk_meta.compression_method {
+        CompressionMethod::Deflate | CompressionMethod::OldDeflate => {
+            inflate(data, chunk_meta, limits)
+        }
+        CompressionMethod::PackBits => unpack_bits(data, chunk_meta, limits),
+        CompressionMethod::Fax4 => unfax4(data, chunk_meta),
+        CompressionMethod::LERC => unlerc(data, chunk_meta, limits),
+        #[cfg(feature = "lzma")]
+        CompressionMethod::LZMA => unlzma(data, chunk_meta, limits),
+        #[cfg(feature = "zstd")]
+        CompressionMethod::ZSTD => unzstd(data, chunk_meta, limits),
+        #[cfg(feature = "webp")]
+        CompressionMethod::WebP => unwebp(data, chunk_meta, limits),
+        #[cfg(feature = "std")]
+        CompressionMethod::JPEG | CompressionMethod::ModernJPEG => unjpeg(data, chunk_meta, limits),
+        method => Err(TiffUnsupportedError::UnsupportedCompressionMethod(method).into()),
+    }
+}
+
+/// Inflate a zlib stream, stopping at the size of a full chunk
+fn inflate(data: &[u8], chunk_meta: &ChunkMetaData, limits: &Limits) -> TiffResult<Vec<u8>> {
+    let chunk_len = chunk_meta.stored_chunk_len(0);
+    let max_len = chunk_len.unwrap_or(limits.max_chunk_bytes);
+    match inflate::decompress_to_vec_zlib_with_limit(
+        data,
+        usize::try_from(max_len).unwrap_or(usize::MAX),
+    ) {
+        Ok(data) => Ok(data),
+        // like libtiff, ignore anything past a full chunk
+        Err(e) if e.status == TINFLStatus::HasMoreOutput && chunk_len.is_some() => Ok(e.output),
+        Err(e) if e.status == TINFLStatus::HasMoreOutput => Err(TiffError::LimitsExceeded),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Undo PackBits run-length encoding, stopping at the size of a full chunk
+fn unpack_bits(data: &[u8], chunk_meta: &ChunkMetaData, limits: &Limits) -> TiffResult<Vec<u8>> {
+    let chunk_len = chunk_meta.stored_chunk_len(0);
+    let max_len =
+        usize::try_from(chunk_len.unwrap_or(limits.max_chunk_bytes)).unwrap_or(usize::MAX);
+    let cut_off = || TiffFormatError::Format(String::from("PackBits compressed data cut off"));
+    let mut out = Vec::with_capacity(max_len.min(data.len().saturating_mul(64)));
+    let mut data = data;
+    while let Some((&header, rest)) = data.split_first() {
+        if out.len() >= max_len {
+            break;
+        }
+        data = match header as i8 {
+            // a run of a single byte
+            n @ -127..=-1 => {
+                let (&byte, rest) = rest.split_first().ok_or_else(cut_off)?;
+                out.resize(out.len() + usize::from(n.unsigned_abs()) + 1, byte);
+                rest
+            }
+            // a no-op
+            -128 => rest,
+            n => {
+                let len = n as usize + 1;
+                let literal = rest.get(..len).ok_or_else(cut_off)?;
+                out.extend_from_slice(literal);
+                &rest[len..]
+            }
+        };
+    }
+    if out.len() > max_len {
+        if chunk_len.is_none() {
+            return Err(TiffError::LimitsExceeded);
+        }
+        // like libtiff, ignore anything past a full chunk
+        out.truncate(max_len);
+    }
+    Ok(out)
+}
+
+/// Decompress Zstandard frames, stopping at the size of a full chunk
+#[cfg(feature = "zstd")]
+fn unzstd(data: &[u8], chunk_meta: &ChunkMetaData, limits: &Limits) -> TiffResult<Vec<u8>> {
+    let chunk_len = chunk_meta.stored_chunk_len(0);
+    let max_len = chunk_len.unwrap_or(limits.max_chunk_bytes);
+    let mut out = read_zstd(data, max_len)?;
+    if out.len() as u64 > max_len {
+        if chunk_len.is_none() {
+            return Err(TiffError::LimitsExceeded);
+        }
+        // like for Deflate, ignore anything past a full chunk
+        out.truncate(out.len() - 1);
+    }
+    Ok(out)
+}
+
+/// Decompress Zstandard frames up to one byte more than `max_len`, to tell a
+/// stream of `max_len` bytes from a longer one
+#[cfg(feature = "zstd")]
+fn read_zstd(data: &[u8], max_len: u64) -> TiffResult<Vec<u8>> {
+    // `std::io::Read` if something enables ruzstd's std feature
+    use ruzstd::{decoding::StreamingDecoder, io::Read};
+
+    let corrupt = |e: &dyn core::fmt::Display| {
+        TiffFormatError::Format(alloc::format!("Zstd compressed data corrupted: {e}"))
+    };
+    let mut out = Vec::new();
+    StreamingDecoder::new(data)
+        .map_err(|e| corrupt(&e))?
+        .take(max_len.saturating_add(1))
+        .read_to_end(&mut out)
+        .map_err(|e| corrupt(&e))?;
+    Ok(out)
+}
+
+/// Decode a Group 4 fax compressed chunk of a bilevel image
+fn unfax4(data: &[u8], chunk_meta: &ChunkMetaData) -> TiffResult<Vec<u8>> {
+    if chunk_meta.bits_per_sample != 1 || chunk_meta.samples != 1 {
+        return Err(TiffFormatError::Format(alloc::format!(
+            "Group 4 fax compressed chunk of {} samples of {} bits",
+            chunk_meta.samples,
+            chunk_meta.bits_per_sample
+        ))
+        .into());
+    }
+    let (width, rows) = chunk_meta.full_chunk_dims().ok_or_else(|| {
+        TiffFormatError::Format(String::from(
+            "Group 4 fax compressed chunk of unknown dimensions",
+        ))
+    })?;
+    fax::decode_g4(data, usize::try_from(width)?, usize::try_from(rows)?)
+}
+
+/// Decode a LERC blob, which LERC_DEFLATE and LERC_ZSTD compress again, see
+/// [`lerc`]
+fn unlerc(data: &[u8], chunk_meta: &ChunkMetaData, limits: &Limits) -> TiffResult<Vec<u8>> {
+    // a blob is a fraction of the size of its samples, unless it stores them
+    // as they are
+    let max_blob = limits.max_chunk_bytes.saturating_add(4096);
+    let decompressed;
+    let blob = match data {
+        [b'L', b'e', b'r', b'c', ..] => data,
+        #[cfg(feature = "zstd")]
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => {
+            decompressed = read_zstd(data, max_blob)?;
+            &decompressed
+        }
+        #[cfg(not(feature = "zstd"))]
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => {
+            return Err(TiffUnsupportedError::UnsupportedLercFeature("Zstandard").into())
+        }
+        _ => {
+            decompressed = inflate::decompress_to_vec_zlib_with_limit(
+                data,
+                usize::try_from(max_blob).unwrap_or(usize::MAX),
+            )?;
+            &decompressed
+        }
+    };
+    let decoded = lerc::decode(blob, chunk_meta.byte_order, limits.max_chunk_bytes)?;
+    let samples = match chunk_meta.planar_config {
+        PlanarConfiguration::Planar => 1,
+        _ => u32::from(chunk_meta.samples),
+    };
+    let (width, height) = chunk_meta.full_chunk_dims().unwrap_or((0, 0));
+    if decoded.data_type.size() * 8 != usize::from(chunk_meta.bits_per_sample)
+        || decoded.data_type.is_float() != (chunk_meta.sample_format == SampleFormat::IEEEFP)
+        || decoded.depth != samples
+        || u64::from(decoded.width) != width
+        // the last strip may be shorter
+        || u64::from(decoded.height) > height
+    {
+        return Err(TiffFormatError::Format(alloc::format!(
+            "LERC blob of {}x{}x{} {:?} samples in a chunk of {width}x{height}x{samples} {} bit {:?} samples",
+            decoded.width,
+            decoded.height,
+            decoded.depth,
+            decoded.data_type,
+            chunk_meta.bits_per_sample,
+            chunk_meta.sample_format,
+        ))
+        .into());
+    }
+    Ok(decoded.data)
+}
+
+/// Decompress an xz stream, stopping at the size of a full chunk
+#[cfg(feature = "lzma")]
+fn unlzma(data: &[u8], chunk_meta: &ChunkMetaData, limits: &Limits) -> TiffResult<Vec<u8>> {
+    /// Output that takes at most `max` bytes, failing the decoder after that
+    struct Capped {
+        out: Vec<u8>,
+        max: usize,
+    }
+
+    impl std::io::Write for Capped {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.max - self.out.len());
+            if len == 0 && !buf.is_empty() {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            self.out.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let chunk_len = chunk_meta.stored_chunk_len(0);
+    let max_len = chunk_len.unwrap_or(limits.max_chunk_bytes);
+    let mut out = Capped {
+        out: Vec::new(),
+        // one byte more, to tell a full chunk from a longer stream
+        max: usize::try_from(max_len.saturating_add(1)).unwrap_or(usize::MAX),
+    };
+    let result = lzma_rs::xz_decompress(&mut &data[..], &mut out);
+    let mut out = out.out;
+    if out.len() as u64 > max_len {
+        if chunk_len.is_none() {
+            return Err(TiffError::LimitsExceeded);
+        }
+        // like for Deflate, ignore anything past a full chunk
+        out.truncate(out.len() - 1);
+        return Ok(out);
+    }
+    match result {
+        Ok(()) => Ok(out),
+        Err(e) => Err(TiffFormatError::Format(alloc::format!(
+            "LZMA compressed data corrupted: {e}"
+        ))
+        .into()),
+    }
+}
+
+/// Decode a WebP image, which GDAL writes for 8-bit chunky RGB and RGBA.
+///
+/// WebP images only have an alpha channel if it isn't all opaque, so one is
+/// added or dropped to match the samples of the TIFF.
+#[cfg(feature = "webp")]
+fn unwebp(data: &[u8], chunk_meta: &ChunkMetaData, limits: &Limits) -> TiffResult<Vec<u8>> {
+    if chunk_meta.bits_per_sample != 8 {
+        return Err(
+            TiffUnsupportedError::UnsupportedBitsPerChannel(chunk_meta.bits_per_sample).into(),
+        );
+    }
+    if chunk_meta.planar_config != PlanarConfiguration::Chunky
+        || !matches!(chunk_meta.samples, 3 | 4)
+    {
+        return Err(TiffUnsupportedError::UnsupportedDataType.into());
+    }
+    let corrupted =
+        |e| TiffFormatError::Format(alloc::format!("WebP compressed data corrupted: {e}"));
+    let mut decoder =
+        image_webp::WebPDecoder::new(std::io::Cursor::new(data)).map_err(corrupted)?;
+    let len = decoder
+        .output_buffer_size()
+        .ok_or(TiffError::LimitsExceeded)?;
+    Limits::check(len as u64, limits.max_chunk_bytes)?;
+    decoder.set_memory_limit(usize::try_from(limits.max_chunk_bytes).unwrap_or(usize::MAX));
+    let mut out = vec![0; len];
+    decoder.read_image(&mut out).map_err(corrupted)?;
+    Ok(match (decoder.has_alpha(), chunk_meta.samples) {
+        (true, 3) => out.chunks_exact(4).flat_map(|p| &p[..3]).copied().collect(),
+        (false, 4) => out
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xff])
+            .collect(),
+        _ => out,
+    })
+}
+
+/// Decode a JPEG stream, joined with the tables of `JPEGTables` if there are
+/// any, as abbreviated streams of tiles and strips leave them out.
+///
+/// For old-style JPEG, `JPEGTables` holds the header of the stream
+/// `JPEGInterchangeFormat` points at. Chunks may be streams of their own, or
+/// only the data of a scan, which is decoded with the frame and scan headers
+/// of that header.
+///
+/// Samples come out as stored, like for other codecs: YCbCr stays YCbCr, with
+/// its chroma upsampled by the JPEG decoder.
+#[cfg(feature = "std")]
+fn unjpeg(data: &[u8], chunk_meta: &ChunkMetaData, limits: &Limits) -> TiffResult<Vec<u8>> {
+    const SOI: [u8; 2] = [0xff, 0xd8];
+    const EOI: [u8; 2] = [0xff, 0xd9];
+
+    if chunk_meta.bits_per_sample != 8 {
+        return Err(
+            TiffUnsupportedError::UnsupportedBitsPerChannel(chunk_meta.bits_per_sample).into(),
+        );
+    }
+    let tables = chunk_meta.jpeg_tables.as_ref().map(|t| t.data());
+    let stream = match (tables, data.strip_prefix(&SOI)) {
+        (None, _) => Cow::Borrowed(data),
+        // the tables go in place of the SOI of the chunk, without what
+        // belongs to another frame
+        (Some(tables), Some(data)) => {
+            let mut stream = SOI.to_vec();
+            for (marker, range) in jpeg_segments(tables) {
+                if is_sof(marker) {
+                    break;
+                }
+                stream.extend_from_slice(&tables[range]);
+            }
+            stream.extend_from_slice(data);
+            Cow::Owned(stream)
+        }
+        // scan data of an old-style JPEG frame, which may have been split in
+        // chunks
+        (Some(tables), None) => {
+            let (width, rows) = chunk_meta
+                .full_chunk_dims()
+                .ok_or(TiffUnsupportedError::UnsupportedDataType)?;
+            let mut stream = SOI.to_vec();
+            for (marker, range) in jpeg_segments(tables) {
+                let at = stream.len();
+                stream.extend_from_slice(&tables[range]);
+                if is_sof(marker) {
+                    let dims = [u16::try_from(rows)?, u16::try_from(width)?];
+                    for (i, dim) in dims.into_iter().enumerate() {
+                        // marker, length and precision first
+                        if let Some(b) = stream.get_mut(at + 5 + 2 * i..at + 7 + 2 * i) {
+                            b.copy_from_slice(&dim.to_be_bytes());
+                        }
+                    }
+                }
+            }
+            stream.extend_from_slice(data);
+            if !data.ends_with(&EOI) {
+                stream.extend_from_slice(&EOI);
+            }
+            Cow::Owned(stream)
+        }
+    };
+    let samples = match chunk_meta.planar_config {
+        PlanarConfiguration::Chunky => chunk_meta.samples,
+        PlanarConfiguration::Planar => 1,
+    };
+    // jpeg-decoder panics on frames of 2 or more than 4 components, whose
+    // count follows the marker, length, precision, height and width
+    let components = jpeg_segments(&stream)
+        .find(|(marker, _)| is_sof(*marker))
+        .and_then(|(_, range)| stream.get(range.start + 9).copied());
+    match components {
+        Some(n @ (1 | 3 | 4)) if u16::from(n) == samples => {}
+        _ => {
+            return Err(TiffFormatError::Format(alloc::format!(
+                "JPEG stream doesn't hold a frame of {samples} components"
+            ))
+            .into())
+        }
+    }
+    let mut decoder = jpeg::Decoder::new(&stream[..]);
+    // `ColorTransform::None` panics for more than one component, but the RGB
+    // transform only interleaves, and the CMYK one inverts
+    let inverted = samples == 4;
+    decoder.set_color_transform(match inverted {
+        true => jpeg::ColorTransform::CMYK,
+        false => jpeg::ColorTransform::RGB,
+    });
+    decoder.set_max_decoding_buffer_size(
+        usize::try_from(limits.max_chunk_bytes).unwrap_or(usize::MAX),
+    );
+    let mut out = decoder.decode()?;
+    if inverted {
+        out.iter_mut().for_each(|b| *b = 255 - *b);
+    }
+    Ok(out)
+}
+
+/// Whether a JPEG marker starts a frame: SOF0 to SOF15, but DHT, JPG and DAC
+#[cfg(feature = "std")]
+fn is_sof(marker: u8) -> bool {
+    matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc)
+}
+
+/// Markers and byte ranges of the segments of a JPEG stream, from after its
+/// SOI up to and including the header of its first scan
+#[cfg(feature = "std")]
+pub(crate) fn jpeg_segments(stream: &[u8]) -> impl Iterator<Item = (u8, Range<usize>)> + '_ {
+    let mut at = if stream.starts_with(&[0xff, 0xd8]) {
+        2
+    } else {
+        stream.len()
+    };
+    core::iter::from_fn(move || {
+        if stream.get(at) != Some(&0xff) {
+            return None;
+        }
+        // markers may be padded with any number of 0xff
+        let code = at + stream[at..].iter().position(|&b| b != 0xff)?;
+        let marker = stream[code];
+        // EOI has no length
+        if marker == 0xd9 {
+            return None;
+        }
+        let len = u16::from_be_bytes([*stream.get(code + 1)?, *stream.get(code + 2)?]);
+        let end = code + 1 + usize::from(len);
+        if end > stream.len() {
+            return None;
+        }
+        // SOS ends the header
+        at = if marker == 0xda { stream.len() } else { end };
+        Some((marker, code - 1..end))
+    })
+}
+
+/// Second stage of [`decode_chunk_data`]: undoing the predictor, fixing the
+/// byte order and upsampling chroma of decompressed chunk `i_chunk`
+pub(crate) fn unpredict(
+    mut data: Vec<u8>,
+    i_chunk: usize,
+    chunk_meta: &ChunkMetaData,
+) -> TiffResult<Vec<u8>> {
+    let plane = chunk_meta.chunk_rect(i_chunk).map_or(0, |(plane, _)| plane);
+    let samples = match chunk_meta.planar_config {
+        PlanarConfiguration::Chunky => usize::from(chunk_meta.samples),
+        PlanarConfiguration::Planar => 1,
+    };
+    let row_samples = || -> TiffResult<usize> {
+        let width = chunk_meta
+            .chunk_width()
+            .ok_or(TiffUnsupportedError::UnsupportedDataType)?;
+        let width = match (chunk_meta.planar_config, plane) {
+            (_, _) if !chunk_meta.is_subsampled() => width,
+            // rows of blocks mix luma and chroma, so there are no rows of
+            // samples to difference
+            (PlanarConfiguration::Chunky, _) => {
+                return Err(TiffUnsupportedError::UnsupportedPredictor(chunk_meta.predictor).into())
+            }
+            (PlanarConfiguration::Planar, 0) => width,
+            (PlanarConfiguration::Planar, _) => {
+                width.div_ceil(u32::from(chunk_meta.ycbcr_subsampling.0))
+            }
+        };
+        Ok(usize::try_from(width)? * samples)
+    };
+    let bits = chunk_meta.bits_per_sample;
+    match chunk_meta.predictor {
+        Predictor::None => fix_endianness(&mut data, chunk_meta.byte_order, bits),
+        Predictor::Horizontal => {
+            fix_endianness(&mut data, chunk_meta.byte_order, bits);
+            predictor::horizontal_decode(&mut data, bits, samples, row_samples()?)?;
+        }
+        // byte planes don't depend on the byte order
+        Predictor::FloatingPoint => {
+            predictor::float_decode(&mut data, bits, samples, row_samples()?)?
+        }
+    }
+    upsample(data, plane, chunk_meta)
+}
+
+/// Repeat each subsampled chroma sample of a chunk of sample `plane` for all
+/// the pixels it covers, dropping an incomplete last row of blocks.
+///
+/// Rows of a chunk of `n` rows come out as `n` rounded up to the vertical
+/// subsampling factor.
+fn upsample(data: Vec<u8>, plane: usize, chunk_meta: &ChunkMetaData) -> TiffResult<Vec<u8>> {
+    if !chunk_meta.is_subsampled()
+        || (chunk_meta.planar_config == PlanarConfiguration::Planar && plane == 0)
+    {
+        return Ok(data);
+    }
+    if !chunk_meta.bits_per_sample.is_multiple_of(8) {
+        return Err(
+            TiffUnsupportedError::UnsupportedBitsPerChannel(chunk_meta.bits_per_sample).into(),
+        );
+    }
+    let size = usize::from(chunk_meta.bits_per_sample / 8);
+    let width = usize::try_from(
+        chunk_meta
+            .chunk_width()
+            .ok_or(TiffUnsupportedError::UnsupportedDataType)?,
+    )?;
+    let (h, v) = (
+        usize::from(chunk_meta.ycbcr_subsampling.0),
+        usize::from(chunk_meta.ycbcr_subsampling.1),
+    );
+    let blocks_across = width.div_ceil(h);
+    let mut out = Vec::new();
+    match chunk_meta.planar_config {
+        PlanarConfiguration::Chunky => {
+            // h * v luma samples, then Cb and Cr
+            let block_len = (h * v + 2) * size;
+            let block_rows = data.chunks_exact(blocks_across * block_len);
+            out.reserve(block_rows.len() * v * width * 3 * size);
+            for block_row in block_rows {
+                for dy in 0..v {
+                    for x in 0..width {
+                        let block = &block_row[x / h * block_len..][..block_len];
+                        let luma = (dy * h + x % h) * size;
+                        out.extend_from_slice(&block[luma..luma + size]);
+                        out.extend_from_slice(&block[h * v * size..]);
+                    }
+                }
+            }
+        }
+        PlanarConfiguration::Planar => {
+            let rows = data.chunks_exact(blocks_across * size);
+            out.reserve(rows.len() * v * width * size);
+            for row in rows {
+                for _ in 0..v {
+                    for x in 0..width {
+                        out.extend_from_slice(&row[x / h * size..][..size]);
+                    }
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Samples of a decoded chunk, typed
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodingResult {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+    U64(Vec<u64>),
+    I8(Vec<i8>),
+    I16(Vec<i16>),
+    I32(Vec<i32>),
+    I64(Vec<i64>),
+    F16(Vec<f16>),
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+}
+
+macro_rules! typed {
+    ($data:expr, $type:ty) => {
+        $data
+            .chunks_exact(core::mem::size_of::<$type>())
+            .map(|b| <$type>::from_ne_bytes(b.try_into().unwrap()))
+            .collect()
+    };
+}
+
+impl DecodingResult {
+    /// Type a chunk as returned by [`decode_chunk_data`]
+    pub fn new(data: &[u8], chunk_meta: &ChunkMetaData) -> TiffResult<Self> {
+        let sample_type =
+            SampleType::from_format(chunk_meta.sample_format, chunk_meta.bits_per_sample)
+                .ok_or_else(|| {
+                    TiffUnsupportedError::UnsupportedSampleFormat(vec![chunk_meta.sample_format])
+                })?;
+        Ok(match sample_type {
+            SampleType::U8 => DecodingResult::U8(data.to_vec()),
+            SampleType::U16 => DecodingResult::U16(typed!(data, u16)),
+            SampleType::U32 => DecodingResult::U32(typed!(data, u32)),
+            SampleType::U64 => DecodingResult::U64(typed!(data, u64)),
+            SampleType::I8 => DecodingResult::I8(data.iter().map(|&b| b as i8).collect()),
+            SampleType::I16 => DecodingResult::I16(typed!(data, i16)),
+            SampleType::I32 => DecodingResult::I32(typed!(data, i32)),
+            SampleType::I64 => DecodingResult::I64(typed!(data, i64)),
+            SampleType::F16 => DecodingResult::F16(typed!(data, f16)),
+            SampleType::F32 => DecodingResult::F32(typed!(data, f32)),
+            SampleType::F64 => DecodingResult::F64(typed!(data, f64)),
+        })
+    }
+
+    /// Type chunk `i_chunk` as returned by [`decode_chunk_data`], along with
+    /// its dimensions from [`ChunkMetaData::chunk_dims`]. Fails with
+    /// [`UsageError::InvalidChunkIndex`] if those are unknown.
+    pub fn with_dims(
+        data: &[u8],
+        i_chunk: usize,
+        chunk_meta: &ChunkMetaData,
+    ) -> TiffResult<DecodedSamples> {
+        let dims = chunk_meta
+            .chunk_dims(i_chunk)
+            .ok_or(UsageError::InvalidChunkIndex(
+                u32::try_from(i_chunk).unwrap_or(u32::MAX),
+            ))?;
+        Ok(DecodedSamples {
+            samples: Self::new(data, chunk_meta)?,
+            dims,
+        })
+    }
+}
+
+/// Typed samples of a decoded chunk along with its dimensions, which tell the
+/// samples of the image apart from the padding of tiles in the last column and
+/// row
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedSamples {
+    pub samples: DecodingResult,
+    pub dims: ChunkDims,
+}
+
+/// Type of the samples in a decoded chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SampleType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    /// Half-precision float, which [`ChunkOpts::sample_type`] can widen to
+    /// [`SampleType::F32`]
+    F16,
+    F32,
+    F64,
+}
+
+/// A sample widened to the largest type of its kind
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Sample {
+    Uint(u64),
+    Int(i64),
+    Float(f64),
+}
+
+/// Convert to an integer type, saturating values out of its range
+macro_rules! to_int {
+    ($sample:expr, $type:ty) => {
+        match $sample {
+            Sample::Uint(v) => <$type>::try_from(v).unwrap_or(<$type>::MAX),
+            Sample::Int(v) => {
+                <$type>::try_from(v).unwrap_or(if v < 0 { <$type>::MIN } else { <$type>::MAX })
+            }
+            Sample::Float(v) => v as $type,
+        }
+        .to_ne_bytes()
+    };
+}
+
+macro_rules! to_float {
+    ($sample:expr, $type:ty) => {
+        match $sample {
+            Sample::Uint(v) => v as $type,
+            Sample::Int(v) => v as $type,
+            Sample::Float(v) => v as $type,
+        }
+        .to_ne_bytes()
+    };
+}
+
+impl SampleType {
+    /// Type of samples as stored, `None` if that isn't a byte-aligned number
+    pub fn from_format(sample_format: SampleFormat, bits_per_sample: u8) -> Option<Self> {
+        Some(match (sample_format, bits_per_sample) {
+            (SampleFormat::Uint, 8) => SampleType::U8,
+            (SampleFormat::Uint, 16) => SampleType::U16,
+            (SampleFormat::Uint, 32) => SampleType::U32,
+            (SampleFormat::Uint, 64) => SampleType::U64,
+            (SampleFormat::Int, 8) => SampleType::I8,
+            (SampleFormat::Int, 16) => SampleType::I16,
+            (SampleFormat::Int, 32) => SampleType::I32,
+            (SampleFormat::Int, 64) => SampleType::I64,
+            (SampleFormat::IEEEFP, 16) => SampleType::F16,
+            (SampleFormat::IEEEFP, 32) => SampleType::F32,
+            (SampleFormat::IEEEFP, 64) => SampleType::F64,
+            _ => return None,
+        })
+    }
+
+    /// Size of a sample in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            SampleType::U8 | SampleType::I8 => 1,
+            SampleType::U16 | SampleType::I16 | SampleType::F16 => 2,
+            SampleType::U32 | SampleType::I32 | SampleType::F32 => 4,
+            SampleType::U64 | SampleType::I64 | SampleType::F64 => 8,
+        }
+    }
+
+    /// Read a native-endian sample of `self.size()` bytes
+    pub(crate) fn read(&self, b: &[u8]) -> Sample {
+        match self {
+            SampleType::U8 => Sample::Uint(b[0].into()),
+            SampleType::U16 => Sample::Uint(u16::from_ne_bytes([b[0], b[1]]).into()),
+            SampleType::U32 => Sample::Uint(u32::from_ne_bytes(b.try_into().unwrap()).into()),
+            SampleType::U64 => Sample::Uint(u64::from_ne_bytes(b.try_into().unwrap())),
+            SampleType::I8 => Sample::Int((b[0] as i8).into()),
+            SampleType::I16 => Sample::Int(i16::from_ne_bytes([b[0], b[1]]).into()),
+            SampleType::I32 => Sample::Int(i32::from_ne_bytes(b.try_into().unwrap()).into()),
+            SampleType::I64 => Sample::Int(i64::from_ne_bytes(b.try_into().unwrap())),
+            SampleType::F16 => Sample::Float(f16::from_ne_bytes([b[0], b[1]]).into()),
+            SampleType::F32 => Sample::Float(f32::from_ne_bytes(b.try_into().unwrap()).into()),
+            SampleType::F64 => Sample::Float(f64::from_ne_bytes(b.try_into().unwrap())),
+        }
+    }
+
+    /// Read a native-endian sample of `self.size()` bytes as a float
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn read_f64(&self, b: &[u8]) -> f64 {
+        match self.read(b) {
+            Sample::Uint(v) => v as f64,
+            Sample::Int(v) => v as f64,
+            Sample::Float(v) => v,
+        }
+    }
+
+    /// Append a float converted to this type, in native byte order
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn write_f64(&self, v: f64, out: &mut Vec<u8>) {
+        self.write(Sample::Float(v), out)
+    }
+
+    /// Append a sample converted to this type, in native byte order
+    pub(crate) fn write(&self, sample: Sample, out: &mut Vec<u8>) {
+        match self {
+            SampleType::U8 => out.extend_from_slice(&to_int!(sample, u8)),
+            SampleType::U16 => out.extend_from_slice(&to_int!(sample, u16)),
+            SampleType::U32 => out.extend_from_slice(&to_int!(sample, u32)),
+            SampleType::U64 => out.extend_from_slice(&to_int!(sample, u64)),
+            SampleType::I8 => out.extend_from_slice(&to_int!(sample, i8)),
+            SampleType::I16 => out.extend_from_slice(&to_int!(sample, i16)),
+            SampleType::I32 => out.extend_from_slice(&to_int!(sample, i32)),
+            SampleType::I64 => out.extend_from_slice(&to_int!(sample, i64)),
+            // rounds to nearest, and saturates to infinity
+            SampleType::F16 => {
+                let v = match sample {
+                    Sample::Uint(v) => v as f64,
+                    Sample::Int(v) => v as f64,
+                    Sample::Float(v) => v,
+                };
+                out.extend_from_slice(&f16::from_f64(v).to_ne_bytes())
+            }
+            SampleType::F32 => out.extend_from_slice(&to_float!(sample, f32)),
+            SampleType::F64 => out.extend_from_slice(&to_float!(sample, f64)),
+        }
+    }
+}
+
+/// Output layout of a decoded chunk, applied per call by
+/// [`Image::decode_chunk`](crate::structs::Image::decode_chunk) and
+/// [`CogDecoder::get_chunk_with`](crate::decoder::CogDecoder::get_chunk_with).
+///
+/// The default keeps chunks as they are decoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkOpts {
+    /// Bytes from the start of one row to the next, padding rows with zeros.
+    /// Rows are packed if `None`.
+    pub row_stride: Option<usize>,
+    /// Samples to keep, in this order, by their index within a pixel. Chunks
+    /// of planar images hold a single sample, with index 0. All samples if
+    /// `None`.
+    pub bands: Option<Vec<u16>>,
+    /// Type to convert samples to. Values out of its range saturate, floats
+    /// are truncated towards zero when converted to integers. Samples are
+    /// kept as stored if `None`.
+    pub sample_type: Option<SampleType>,
+}
+
+impl ChunkOpts {
+    /// Lay out a chunk as returned by [`decode_chunk_data`]
+    pub fn apply(&self, data: Vec<u8>, chunk_meta: &ChunkMetaData) -> TiffResult<Vec<u8>> {
+        if *self == ChunkOpts::default() {
+            return Ok(data);
+        }
+        if !chunk_meta.bits_per_sample.is_multiple_of(8) {
+            return Err(TiffUnsupportedError::UnsupportedBitsPerChannel(
+                chunk_meta.bits_per_sample,
+            )
+            .into());
+        }
+        let source = SampleType::from_format(chunk_meta.sample_format, chunk_meta.bits_per_sample)
+            .ok_or_else(|| {
+                TiffUnsupportedError::UnsupportedSampleFormat(vec![chunk_meta.sample_format])
+            })?;
+        let target = self.sample_type.unwrap_or(source);
+        let samples = match chunk_meta.planar_config {
+            PlanarConfiguration::Chunky => chunk_meta.samples,
+            PlanarConfiguration::Planar => 1,
+        };
+        let bands = match &self.bands {
+            Some(bands) => Cow::Borrowed(&bands[..]),
+            None => Cow::Owned((0..samples).collect()),
+        };
+        if let Some(&band) = bands.iter().find(|&&b| b >= samples) {
+            return Err(UsageError::InvalidBand(band).into());
+        }
+        let width = chunk_meta
+            .chunk_width()
+            .ok_or(TiffUnsupportedError::UnsupportedDataType)? as usize;
+        let pixel_len = usize::from(samples) * source.size();
+        let row_len = width * pixel_len;
+        let out_row_len = width * bands.len() * target.size();
+        let stride = self.row_stride.unwrap_or(out_row_len);
+        if stride < out_row_len {
+            return Err(UsageError::RowStrideTooSmall {
+                stride,
+                row_len: out_row_len,
+            }
+            .into());
+        }
+
+        let rows = data.len() / row_len.max(1);
+        let mut out = Vec::with_capacity(rows * stride);
+        for row in data.chunks_exact(row_len.max(1)) {
+            let row_start = out.len();
+            for pixel in row.chunks_exact(pixel_len) {
+                for &band in bands.iter() {
+                    let sample = &pixel[usize::from(band) * source.size()..][..source.size()];
+                    if target == source {
+                        out.extend_from_slice(sample);
+                    } else {
+                        target.write(source.read(sample), &mut out);
+                    }
+                }
+            }
+            out.resize(row_start + stride, 0);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::structs::BufferedEntry;
+    use crate::{
+        error::TiffError,
+        structs::{
+            tags::{PhotometricInterpretation, PlanarConfiguration, SampleFormat},
+            TileAttributes,
+        },
+        ByteOrder,
+    };
+
+    /// 256x256 RGB tile
+    fn tile_meta(compression_method: CompressionMethod) -> ChunkMetaData {
+        ChunkMetaData {
+            byte_order: ByteOrder::LittleEndian,
+            image_width: 1000,
+            image_height: 1000,
+            bits_per_sample: 8,
+            samples: 3,
+            sample_format: SampleFormat::Uint,
+            photometric_interpretation: PhotometricInterpretation::RGB,
+            compression_method,
+            predictor: Predictor::None,
+            jpeg_tables: None,
+            planar_config: PlanarConfiguration::Chunky,
+            chunk_type: ChunkType::Tile,
+            strip_decoder: None,
+            tile_attributes: Some(TileAttributes {
+                image_width: 1000,
+                image_height: 1000,
+                tile_width: 256,
+                tile_length: 256,
+            }),
+            ycbcr_subsampling: (1, 1),
+            nodata: None,
+        }
+    }
+
+    #[test]
+    fn test_compression_ratio() {
+        let limits = CompressionRatioLimits::default();
+        let meta = tile_meta(CompressionMethod::Deflate);
+        assert_eq!(meta.chunk_len(), Some(256 * 256 * 3));
+        assert!(check_compression_ratio(&meta, 1000, &limits));
+        assert!(!check_compression_ratio(&meta, 10, &limits));
+        assert!(!check_compression_ratio(&meta, 0, &limits));
+        let meta = tile_meta(CompressionMethod::PackBits);
+        assert!(!check_compression_ratio(&meta, 1000, &limits));
+        // uncompressed chunks aren't checked here
+        let meta = tile_meta(CompressionMethod::None);
+        assert!(check_compression_ratio(&meta, 10, &limits));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        // known by name, so the error says what's missing
+        let meta = tile_meta(CompressionMethod::from_u16_exhaustive(50001));
+        assert_eq!(meta.compression_method, CompressionMethod::WebP);
+        #[cfg(not(feature = "webp"))]
+        let Err(TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedCompressionMethod(
+            CompressionMethod::WebP,
+        ))) = decode_chunk_data(vec![0; 16], 0, &meta, &Limits::default())
+        else {
+            panic!("WebP isn't supported without the webp feature");
+        };
+    }
+
+    #[cfg(feature = "webp")]
+    #[test]
+    fn test_webp() {
+        use image_webp::{ColorType, WebPEncoder};
+
+        let webp = |data: &[u8], color| {
+            let mut compressed = Vec::new();
+            WebPEncoder::new(&mut compressed)
+                .encode(data, 256, 256, color)
+                .unwrap();
+            compressed
+        };
+        let mut meta = tile_meta(CompressionMethod::WebP);
+        let rgb: Vec<u8> = (0..256 * 256 * 3).map(|i| (i % 251) as u8).collect();
+        let rgba: Vec<u8> = rgb
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], p[0]])
+            .collect();
+        let limits = Limits::default();
+        assert_eq!(
+            decode_chunk_data(webp(&rgb, ColorType::Rgb8), 0, &meta, &limits).unwrap(),
+            rgb
+        );
+        // alpha is dropped if the TIFF has none
+        assert_eq!(
+            decode_chunk_data(webp(&rgba, ColorType::Rgba8), 0, &meta, &limits).unwrap(),
+            rgb
+        );
+
+        meta.samples = 4;
+        assert_eq!(
+            decode_chunk_data(webp(&rgba, ColorType::Rgba8), 0, &meta, &limits).unwrap(),
+            rgba
+        );
+        // and opaque if WebP left it out
+        let opaque: Vec<u8> = rgb
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xff])
+            .collect();
+        assert_eq!(
+            decode_chunk_data(webp(&rgb, ColorType::Rgb8), 0, &meta, &limits).unwrap(),
+            opaque
+        );
+
+        let Err(TiffError::FormatError(_)) = decode_chunk_data(vec![0; 16], 0, &meta, &limits)
+        else {
+            panic!("garbage isn't WebP");
+        };
+        meta.samples = 1;
+        let Err(TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedDataType)) =
+            decode_chunk_data(webp(&rgb, ColorType::Rgb8), 0, &meta, &limits)
+        else {
+            panic!("WebP is RGB or RGBA");
+        };
+    }
+
+    #[test]
+    fn test_chunk_limits() {
+        let meta = tile_meta(CompressionMethod::None);
+        let data = vec![0; 256 * 256 * 3];
+        let limits = Limits {
+            max_chunk_bytes: 256 * 256 * 3,
+            ..Default::default()
+        };
+        assert!(decode_chunk_data(data.clone(), 0, &meta, &limits).is_ok());
+        let limits = Limits {
+            max_chunk_bytes: 1024,
+            ..Default::default()
+        };
+        let Err(TiffError::LimitsExceeded) = decode_chunk_data(data, 0, &meta, &limits) else {
+            panic!("the chunk should exceed the limits");
+        };
+        // the decoded size counts, even if the data is tiny
+        let Err(TiffError::LimitsExceeded) = decode_chunk_data(vec![0; 10], 0, &meta, &limits)
+        else {
+            panic!("the decoded chunk should exceed the limits");
+        };
+    }
+
+    #[test]
+    fn test_deflate() {
+        let meta = tile_meta(CompressionMethod::Deflate);
+        let tile: Vec<u8> = (0..256 * 256 * 3).map(|i| (i % 251) as u8).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&tile, 6);
+        assert!(compressed.len() < tile.len() / 10);
+        let limits = Limits::default();
+        assert_eq!(
+            decode_chunk_data(compressed.clone(), 0, &meta, &limits).unwrap(),
+            tile
+        );
+        let meta = tile_meta(CompressionMethod::OldDeflate);
+        assert_eq!(
+            decode_chunk_data(compressed.clone(), 0, &meta, &limits).unwrap(),
+            tile
+        );
+
+        // anything past a full chunk is ignored
+        let mut long = tile.clone();
+        long.extend_from_slice(&[1; 100]);
+        let long = miniz_oxide::deflate::compress_to_vec_zlib(&long, 6);
+        assert_eq!(decode_chunk_data(long, 0, &meta, &limits).unwrap(), tile);
+
+        // a truncated or corrupt stream is an error
+        let truncated = compressed[..compressed.len() / 2].to_vec();
+        let Err(TiffError::FormatError(_)) = decode_chunk_data(truncated, 0, &meta, &limits) else {
+            panic!("a truncated stream should fail");
+        };
+        assert!(decode_chunk_data(vec![1, 2, 3, 4], 0, &meta, &limits).is_err());
+    }
+
+    #[test]
+    fn test_packbits() {
+        // gray tiles of 12x2
+        let mut meta = tile_meta(CompressionMethod::PackBits);
+        meta.samples = 1;
+        meta.photometric_interpretation = PhotometricInterpretation::BlackIsZero;
+        let full = meta.tile_attributes.clone().unwrap();
+        let tile = |width| TileAttributes {
+            tile_width: width,
+            tile_length: 2,
+            ..full.clone()
+        };
+        meta.tile_attributes = Some(tile(12));
+        let limits = Limits::default();
+        // the example of the TIFF spec, 24 bytes, with a no-op
+        let packed = [
+            0xfe, 0xaa, 0x02, 0x80, 0x00, 0x2a, 0x80, 0xfd, 0xaa, 0x03, 0x80, 0x00, 0x2a, 0x22,
+            0xf7, 0xaa,
+        ];
+        let unpacked = [
+            0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0xaa, 0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0x22,
+            0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+        ];
+        assert_eq!(
+            decode_chunk_data(packed.to_vec(), 0, &meta, &limits).unwrap(),
+            unpacked
+        );
+        // anything past a full chunk is ignored
+        meta.tile_attributes = Some(tile(5));
+        assert_eq!(
+            decode_chunk_data(packed.to_vec(), 0, &meta, &limits).unwrap(),
+            unpacked[..10]
+        );
+        // a literal or run without its data
+        meta.tile_attributes = Some(tile(12));
+        for cut_off in [&[0x02, 0x80][..], &[0xfe][..]] {
+            let Err(TiffError::FormatError(_)) =
+                decode_chunk_data(cut_off.to_vec(), 0, &meta, &limits)
+            else {
+                panic!("{cut_off:?} should be cut off");
+            };
+        }
+    }
+
+    #[cfg(feature = "lzma")]
+    #[test]
+    fn test_lzma() {
+        let xz = |data: &[u8]| {
+            let mut compressed = Vec::new();
+            lzma_rs::xz_compress(&mut &data[..], &mut compressed).unwrap();
+            compressed
+        };
+        let meta = tile_meta(CompressionMethod::LZMA);
+        let tile: Vec<u8> = (0..256 * 256 * 3).map(|i| (i % 251) as u8).collect();
+        let compressed = xz(&tile);
+        let limits = Limits::default();
+        assert_eq!(
+            decode_chunk_data(compressed.clone(), 0, &meta, &limits).unwrap(),
+            tile
+        );
+
+        // anything past a full chunk is ignored
+        let mut long = tile.clone();
+        long.extend_from_slice(&[1; 100]);
+        assert_eq!(
+            decode_chunk_data(xz(&long), 0, &meta, &limits).unwrap(),
+            tile
+        );
+
+        let truncated = compressed[..compressed.len() / 2].to_vec();
+        let Err(TiffError::FormatError(_)) = decode_chunk_data(truncated, 0, &meta, &limits) else {
+            panic!("a truncated stream should fail");
+        };
+        assert!(decode_chunk_data(vec![1, 2, 3, 4], 0, &meta, &limits).is_err());
+    }
+
+    #[test]
+    fn test_fax4() {
+        // 10x3 with a black row in the middle, as coded by libtiff 4
+        let mut meta = tile_meta(CompressionMethod::Fax4);
+        meta.samples = 1;
+        meta.bits_per_sample = 1;
+        meta.photometric_interpretation = PhotometricInterpretation::WhiteIsZero;
+        meta.tile_attributes = Some(TileAttributes {
+            image_width: 10,
+            image_height: 3,
+            tile_width: 10,
+            tile_length: 3,
+        });
+        let limits = Limits::default();
+        let data = b"\x93\x50\x84\xe1\xb8\x00\x80\x08".to_vec();
+        assert_eq!(
+            decode_chunk_data(data.clone(), 0, &meta, &limits).unwrap(),
+            [0, 0, 0xff, 0xc0, 0, 0]
+        );
+        meta.bits_per_sample = 8;
+        let Err(TiffError::FormatError(_)) = decode_chunk_data(data, 0, &meta, &limits) else {
+            panic!("Group 4 only codes bilevel images");
+        };
+    }
+
+    #[test]
+    fn test_lerc() {
+        // 4x4 bytes of 7, as written by libLerc 4
+        let blob = b"\
+            \x4c\x65\x72\x63\x32\x20\x06\x00\x00\x00\xc0\x98\x50\x1f\x04\x00\x00\x00\x04\x00\
+            \x00\x00\x01\x00\x00\x00\x10\x00\x00\x00\x08\x00\x00\x00\x5e\x00\x00\x00\x01\x00\
+            \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xe0\x3f\x00\x00\
+            \x00\x00\x00\x00\x1c\x40\x00\x00\x00\x00\x00\x00\x1c\x40\x00\x00\x00\x00\x00\x00\
+            \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+        ";
+        let mut meta = tile_meta(CompressionMethod::LERC);
+        meta.samples = 1;
+        meta.tile_attributes = Some(TileAttributes {
+            image_width: 4,
+            image_height: 4,
+            tile_width: 4,
+            tile_length: 4,
+        });
+        let limits = Limits::default();
+        // LERC, and LERC_DEFLATE
+        let deflated = miniz_oxide::deflate::compress_to_vec_zlib(blob, 6);
+        for data in [blob.to_vec(), deflated] {
+            assert_eq!(decode_chunk_data(data, 0, &meta, &limits).unwrap(), [7; 16]);
+        }
+        #[cfg(feature = "zstd")]
+        {
+            use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+            let zstd = compress_to_vec(&blob[..], CompressionLevel::Fastest);
+            assert_eq!(decode_chunk_data(zstd, 0, &meta, &limits).unwrap(), [7; 16]);
+        }
+
+        // the blob must fit the chunk
+        meta.samples = 3;
+        let Err(TiffError::FormatError(_)) = decode_chunk_data(blob.to_vec(), 0, &meta, &limits)
+        else {
+            panic!("1 sample per pixel instead of 3");
+        };
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+
+        let meta = tile_meta(CompressionMethod::ZSTD);
+        let tile: Vec<u8> = (0..256 * 256 * 3).map(|i| (i % 251) as u8).collect();
+        let compressed = compress_to_vec(&tile[..], CompressionLevel::Fastest);
+        assert!(compressed.len() < tile.len() / 10);
+        let limits = Limits::default();
+        assert_eq!(
+            decode_chunk_data(compressed.clone(), 0, &meta, &limits).unwrap(),
+            tile
+        );
+
+        // anything past a full chunk is ignored
+        let mut long = tile.clone();
+        long.extend_from_slice(&[1; 100]);
+        let long = compress_to_vec(&long[..], CompressionLevel::Fastest);
+        assert_eq!(decode_chunk_data(long, 0, &meta, &limits).unwrap(), tile);
+
+        let truncated = compressed[..compressed.len() / 2].to_vec();
+        let Err(TiffError::FormatError(_)) = decode_chunk_data(truncated, 0, &meta, &limits) else {
+            panic!("a truncated stream should fail");
+        };
+        assert!(decode_chunk_data(vec![1, 2, 3, 4], 0, &meta, &limits).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_jpeg() {
+        use crate::test_util::{jpeg_frame, jpeg_tables};
+
+        let limits = Limits::default();
+        let tables = BufferedEntry {
+            tag_type: crate::structs::tags::TagType::UNDEFINED,
+            count: jpeg_tables().len() as u64,
+            data: jpeg_tables(),
+        };
+        // 16x16 YCbCr with 2x2 subsampled chroma, luma 144 and chroma 128:
+        // a DC of 128 and blocks of nothing else
+        let mut meta = tile_meta(CompressionMethod::ModernJPEG);
+        meta.photometric_interpretation = PhotometricInterpretation::YCbCr;
+        let full = meta.tile_attributes.clone().unwrap();
+        let tile = |size| TileAttributes {
+            tile_width: size,
+            tile_length: size,
+            ..full.clone()
+        };
+        meta.tile_attributes = Some(tile(16));
+        meta.jpeg_tables = Some(tables.clone());
+        let ycbcr = jpeg_frame(16, &[(1, 0x22), (2, 0x11), (3, 0x11)], &[0x60, 0, 0, 0x3f]);
+        // kept YCbCr, upsampled
+        let decoded = decode_chunk_data(ycbcr.clone(), 0, &meta, &limits).unwrap();
+        assert_eq!(decoded, [144, 128, 128].repeat(16 * 16));
+
+        // a gray stream has the wrong number of components
+        let gray = jpeg_frame(8, &[(1, 0x11)], &[0x60, 0x1f]);
+        let Err(TiffError::FormatError(_)) = decode_chunk_data(gray.clone(), 0, &meta, &limits)
+        else {
+            panic!("3 samples can't come from a gray stream");
+        };
+
+        meta.samples = 1;
+        meta.photometric_interpretation = PhotometricInterpretation::BlackIsZero;
+        meta.tile_attributes = Some(tile(8));
+        assert_eq!(
+            decode_chunk_data(gray.clone(), 0, &meta, &limits).unwrap(),
+            [144; 64]
+        );
+        // a stream with its own tables
+        meta.jpeg_tables = None;
+        let mut full_stream = jpeg_tables();
+        full_stream.truncate(full_stream.len() - 2);
+        full_stream.extend_from_slice(&gray[2..]);
+        assert_eq!(
+            decode_chunk_data(full_stream, 0, &meta, &limits).unwrap(),
+            [144; 64]
+        );
+        // or without them
+        assert!(decode_chunk_data(gray.clone(), 0, &meta, &limits).is_err());
+
+        meta.jpeg_tables = Some(tables);
+        meta.bits_per_sample = 16;
+        let Err(TiffError::UnsupportedError(_)) = decode_chunk_data(gray, 0, &meta, &limits) else {
+            panic!("only 8-bit JPEG is supported");
+        };
+    }
+
+    #[test]
+    fn test_chunk_opts() {
+        // 2x1 RGB strip
+        let mut meta = tile_meta(CompressionMethod::None);
+        meta.image_width = 2;
+        meta.chunk_type = ChunkType::Strip;
+        let data = vec![1, 2, 3, 4, 5, 255];
+        assert_eq!(
+            ChunkOpts::default().apply(data.clone(), &meta).unwrap(),
+            data
+        );
+        let opts = ChunkOpts {
+            row_stride: Some(6),
+            bands: Some(vec![2, 0]),
+            sample_type: None,
+        };
+        assert_eq!(
+            opts.apply(data.clone(), &meta).unwrap(),
+            [3, 1, 255, 4, 0, 0]
+        );
+        let opts = ChunkOpts {
+            bands: Some(vec![2]),
+            sample_type: Some(SampleType::I8),
+            ..Default::default()
+        };
+        // 255 saturates
+        assert_eq!(opts.apply(data.clone(), &meta).unwrap(), [3, 127]);
+        let opts = ChunkOpts {
+            bands: Some(vec![1]),
+            sample_type: Some(SampleType::F32),
+            ..Default::default()
+        };
+        let floats: Vec<u8> = [2f32, 5.0].iter().flat_map(|f| f.to_ne_bytes()).collect();
+        assert_eq!(opts.apply(data.clone(), &meta).unwrap(), floats);
+
+        let opts = ChunkOpts {
+            bands: Some(vec![3]),
+            ..Default::default()
+        };
+        assert!(opts.apply(data.clone(), &meta).is_err());
+        let opts = ChunkOpts {
+            row_stride: Some(5),
+            ..Default::default()
+        };
+        assert!(opts.apply(data, &meta).is_err());
+    }
+
+    #[test]
+    fn test_with_dims() {
+        let meta = tile_meta(CompressionMethod::None);
+        let data = vec![0; 256 * 256 * 3];
+        // the last of 4x4 tiles holds 232x232 pixels of the image
+        let decoded = DecodingResult::with_dims(&data, 15, &meta).unwrap();
+        assert_eq!(decoded.samples, DecodingResult::U8(data.clone()));
+        assert_eq!((decoded.dims.width, decoded.dims.height), (256, 256));
+        assert_eq!(decoded.dims.padding(), (24, 24));
+        let decoded = DecodingResult::with_dims(&data, 1, &meta).unwrap();
+        assert_eq!(decoded.dims.padding(), (0, 0));
+        let Err(TiffError::UsageError(UsageError::InvalidChunkIndex(16))) =
+            DecodingResult::with_dims(&data, 16, &meta)
+        else {
+            panic!("there are 16 tiles");
+        };
+    }
+
+    #[test]
+    fn test_f16() {
+        // 3x1 strip of half floats, in a big-endian file
+        let mut meta = tile_meta(CompressionMethod::None);
+        meta.byte_order = ByteOrder::BigEndian;
+        meta.image_width = 3;
+        meta.image_height = 1;
+        meta.chunk_type = ChunkType::Strip;
+        meta.samples = 1;
+        meta.bits_per_sample = 16;
+        meta.sample_format = SampleFormat::IEEEFP;
+        let halves = [f16::from_f32(1.5), f16::NEG_INFINITY, f16::from_f32(-0.1)];
+        let stored: Vec<u8> = halves.iter().flat_map(|h| h.to_be_bytes()).collect();
+        let data = decode_chunk_data(stored, 0, &meta, &Limits::default()).unwrap();
+        assert_eq!(
+            DecodingResult::new(&data, &meta).unwrap(),
+            DecodingResult::F16(halves.to_vec())
+        );
+
+        meta.predictor = Predictor::FloatingPoint;
+        let mut predicted: Vec<u8> = halves.iter().flat_map(|h| h.to_ne_bytes()).collect();
+        predictor::float_encode(&mut predicted, 16, 1, 3).unwrap();
+        let decoded = decode_chunk_data(predicted, 0, &meta, &Limits::default()).unwrap();
+        assert_eq!(decoded, data);
+
+        // widened to f32
+        let opts = ChunkOpts {
+            sample_type: Some(SampleType::F32),
+            ..Default::default()
+        };
+        let floats: Vec<u8> = halves
+            .iter()
+            .flat_map(|h| h.to_f32().to_ne_bytes())
+            .collect();
+        assert_eq!(opts.apply(data, &meta).unwrap(), floats);
+        // too large for a half float
+        let mut out = Vec::new();
+        SampleType::F16.write_f64(1e6, &mut out);
+        assert_eq!(out, f16::INFINITY.to_ne_bytes());
+    }
+
+    #[test]
+    fn test_float_predictor() {
+        // 2x2 strip of doubles, in a big-endian file
+        let mut meta = tile_meta(CompressionMethod::None);
+        meta.byte_order = ByteOrder::BigEndian;
+        meta.image_width = 2;
+        meta.image_height = 2;
+        meta.chunk_type = ChunkType::Strip;
+        meta.samples = 1;
+        meta.bits_per_sample = 64;
+        meta.sample_format = SampleFormat::IEEEFP;
+        meta.predictor = Predictor::FloatingPoint;
+        let floats = [1.5f64, -0.25, 1e300, f64::MIN_POSITIVE];
+        let mut data: Vec<u8> = floats.iter().flat_map(|f| f.to_ne_bytes()).collect();
+        predictor::float_encode(&mut data, 64, 1, 2).unwrap();
+        let data = decode_chunk_data(data, 0, &meta, &Limits::default()).unwrap();
+        assert_eq!(
+            DecodingResult::new(&data, &meta).unwrap(),
+            DecodingResult::F64(floats.to_vec())
+        );
+    }
+
+    #[test]
+    fn test_float_predictor_f32() {
+        // 4x2 strip of two-sample floats, as written by libtiff with Deflate
+        let mut meta = tile_meta(CompressionMethod::Deflate);
+        meta.image_width = 4;
+        meta.image_height = 2;
+        meta.chunk_type = ChunkType::Strip;
+        meta.samples = 2;
+        meta.bits_per_sample = 32;
+        meta.sample_format = SampleFormat::IEEEFP;
+        meta.predictor = Predictor::FloatingPoint;
+        let data = b"\x78\x9c\x73\x3a\xc6\xc0\xc0\xc0\xc0\xc0\xd0\x1e\xc6\xc8\xc0\xc8\xc0\xc8\x60\x22\xc3\xf0\x83\xe1\x07\xc3\x0f\x86\x07\x10\x71\x27\xa8\x7c\x37\x54\xde\xe0\x0f\x54\x1e\x0a\x00\x87\x17\x0c\x01";
+        let data = decode_chunk_data(data.to_vec(), 0, &meta, &Limits::default()).unwrap();
+        let floats = (0..16)
+            .map(|i| match i % 2 {
+                0 => 100.5 + i as f32 * 0.25,
+                _ => -9999.0 + i as f32,
+            })
+            .collect();
+        assert_eq!(
+            DecodingResult::new(&data, &meta).unwrap(),
+            DecodingResult::F32(floats)
+        );
+    }
+
+    #[test]
+    fn test_horizontal_predictor() {
+        for byte_order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            for bits in [8u8, 16, 32] {
+                for samples in 1..=5u16 {
+                    // 5x3 strip
+                    let mut meta = tile_meta(CompressionMethod::None);
+                    meta.byte_order = byte_order;
+                    meta.image_width = 5;
+                    meta.image_height = 3;
+                    meta.chunk_type = ChunkType::Strip;
+                    meta.samples = samples;
+                    meta.bits_per_sample = bits;
+                    meta.predictor = Predictor::Horizontal;
+                    let size = usize::from(bits / 8);
+                    let native: Vec<u8> = (0..15 * u32::from(samples))
+                        .flat_map(|i| {
+                            let v = i.wrapping_mul(0x9e37_79b9);
+                            match size {
+                                1 => vec![(v >> 24) as u8],
+                                2 => ((v >> 16) as u16).to_ne_bytes().to_vec(),
+                                _ => v.to_ne_bytes().to_vec(),
+                            }
+                        })
+                        .collect();
+                    let mut data = native.clone();
+                    let row_samples = 5 * usize::from(samples);
+                    predictor::horizontal_encode(&mut data, bits, samples.into(), row_samples)
+                        .unwrap();
+                    // samples as stored in the file
+                    if (byte_order == ByteOrder::BigEndian) == cfg!(target_endian = "little") {
+                        data.chunks_exact_mut(size).for_each(<[u8]>::reverse);
+                    }
+                    let data = decode_chunk_data(data, 0, &meta, &Limits::default()).unwrap();
+                    assert_eq!(
+                        data, native,
+                        "{byte_order:?}, {bits} bits, {samples} samples"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Range-request coalescing for [`CogReader`]s.
+//!
+//! Cloud COG readers live or die on request count. Decoding a region usually
+//! asks for a handful of neighbouring tiles at once, which in a COG are mostly
+//! stored back to back. [`CoalescingReader`] collects the requests that come in
+//! within a short window, merges adjacent or overlapping ranges and fetches
+//! each merged range with a single request to the underlying reader.
+
+use std::{
+    io,
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+use crate::{
+    decoder::{reader::slice_bytes, CogReader, SourceKey},
+    error::{TiffError, TiffResult},
+};
+
+/// Which [`CogReader`] method a batch of requests is forwarded to
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    TagData,
+    ImageData,
+}
+
+struct Request {
+    byte_start: u64,
+    n_bytes: u64,
+    tx: oneshot::Sender<TiffResult<Bytes>>,
+}
+
+impl Request {
+    fn byte_end(&self) -> u64 {
+        self.byte_start.saturating_add(self.n_bytes)
+    }
+}
+
+/// Requests waiting for their batch to be sent off
+#[derive(Default)]
+struct Pending {
+    tag_data: Mutex<Vec<Request>>,
+    image_data: Mutex<Vec<Request>>,
+}
+
+impl Pending {
+    fn get(&self, kind: Kind) -> &Mutex<Vec<Request>> {
+        match kind {
+            Kind::TagData => &self.tag_data,
+            Kind::ImageData => &self.image_data,
+        }
+    }
+}
+
+/// Wraps a [`CogReader`], merging tag and image data requests that are made
+/// within `window` of each other and lie at most `max_gap` bytes apart.
+///
+/// IFD reads are passed through unchanged, since they are sequential anyway.
+/// Batches are dispatched on the tokio runtime, so this must be used from
+/// within one.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use tiff2::decoder::CoalescingReader;
+/// let reader = CoalescingReader::new(vec![0u8; 1024])
+///     .with_window(Duration::from_millis(2))
+///     .with_max_gap(512);
+/// ```
+pub struct CoalescingReader<R> {
+    inner: Arc<R>,
+    window: Duration,
+    max_gap: u64,
+    pending: Arc<Pending>,
+}
+
+impl<R: CogReader + 'static> CoalescingReader<R> {
+    /// Wraps `inner` with a window of 1ms, only merging ranges that touch or overlap
+    pub fn new(inner: R) -> Self {
+        CoalescingReader {
+            inner: Arc::new(inner),
+            window: Duration::from_millis(1),
+            max_gap: 0,
+            pending: Default::default(),
+        }
+    }
+
+    /// How long to wait for more requests after the first one of a batch
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Maximum number of unrequested bytes between two ranges that still get
+    /// merged. Fetching a few bytes too many is usually cheaper than a request.
+    pub fn with_max_gap(mut self, max_gap: u64) -> Self {
+        self.max_gap = max_gap;
+        self
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    async fn read_coalesced(&self, kind: Kind, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        let (tx, rx) = oneshot::channel();
+        let is_first = {
+            let mut pending = self.pending.get(kind).lock()?;
+            pending.push(Request {
+                byte_start,
+                n_bytes,
+                tx,
+            });
+            pending.len() == 1
+        };
+        if is_first {
+            // The batch is sent off from its own task, so that it still goes
+            // out if this future gets dropped.
+            let (inner, pending) = (self.inner.clone(), self.pending.clone());
+            let (window, max_gap) = (self.window, self.max_gap);
+            tokio::spawn(async move {
+                tokio::time::sleep(window).await;
+                let Ok(mut batch) = pending.get(kind).lock() else {
+                    return;
+                };
+                let batch = std::mem::take(&mut *batch);
+                for group in merge_ranges(batch, max_gap) {
+                    tokio::spawn(fetch_group(inner.clone(), kind, group));
+                }
+            });
+        }
+        rx.await
+            .map_err(|_| TiffError::from(io::Error::other("coalesced request was dropped")))?
+    }
+}
+
+/// Sort requests and split them into groups that can be fetched at once
+fn merge_ranges(mut batch: Vec<Request>, max_gap: u64) -> Vec<Vec<Request>> {
+    batch.sort_by_key(|r| r.byte_start);
+    let mut groups: Vec<Vec<Request>> = Vec::new();
+    let mut group_end = 0u64;
+    for request in batch {
+        match groups.last_mut() {
+            Some(group) if request.byte_start <= group_end.saturating_add(max_gap) => {
+                group_end = group_end.max(request.byte_end());
+                group.push(request);
+            }
+            _ => {
+                group_end = request.byte_end();
+                groups.push(vec![request]);
+            }
+        }
+    }
+    groups
+}
+
+async fn read<R: CogReader>(
+    inner: &R,
+    kind: Kind,
+    byte_start: u64,
+    n_bytes: u64,
+) -> TiffResult<Bytes> {
+    match kind {
+        Kind::TagData => inner.read_tag_data(byte_start, n_bytes).await,
+        Kind::ImageData => inner.read_image_data(byte_start, n_bytes).await,
+    }
+}
+
+/// Fetch a group of requests as one range and hand out the slices
+async fn fetch_group<R: CogReader>(inner: Arc<R>, kind: Kind, group: Vec<Request>) {
+    let start = group[0].byte_start;
+    let end = group.iter().map(Request::byte_end).max().unwrap_or(start);
+    let merged = read(&*inner, kind, start, end - start).await;
+    match merged {
+        Ok(data) => {
+            for request in group {
+                let result = slice_bytes(&data, request.byte_start - start, request.n_bytes);
+                // the receiver may have been dropped, that's fine
+                let _ = request.tx.send(result);
+            }
+        }
+        Err(e) if group.len() == 1 => {
+            let _ = group.into_iter().next().map(|r| r.tx.send(Err(e)));
+        }
+        Err(_) => {
+            // errors can't be cloned, so let every request find out for itself
+            for request in group {
+                let result = read(&*inner, kind, request.byte_start, request.n_bytes).await;
+                let _ = request.tx.send(result);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<R: CogReader + 'static> CogReader for CoalescingReader<R> {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.inner.read_ifd(byte_start, n_bytes).await
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_coalesced(Kind::TagData, byte_start, n_bytes)
+            .await
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_coalesced(Kind::ImageData, byte_start, n_bytes)
+            .await
+    }
+
+    /// Passed through unchanged, the ranges being batched already
+    async fn read_vectored(&self, ranges: &[Range<u64>]) -> TiffResult<Vec<Bytes>> {
+        self.inner.read_vectored(ranges).await
+    }
+
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        self.inner.read_header(n_bytes).await
+    }
+
+    fn file_len(&self) -> Option<u64> {
+        self.inner.file_len()
+    }
+
+    fn source_id(&self) -> SourceKey {
+        self.inner.source_id()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// In-memory reader that counts the requests it gets
+    struct Counting {
+        data: Vec<u8>,
+        requests: AtomicUsize,
+    }
+
+    impl Counting {
+        fn new(len: u8) -> Self {
+            Counting {
+                data: (0..len).collect(),
+                requests: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl CogReader for Counting {
+        async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.data.read_ifd(byte_start, n_bytes).await
+        }
+
+        async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.data.read_tag_data(byte_start, n_bytes).await
+        }
+
+        async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.data.read_image_data(byte_start, n_bytes).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_adjacent_ranges_are_merged() {
+        let reader = CoalescingReader::new(Counting::new(32));
+        let (a, b, c) = tokio::join!(
+            reader.read_image_data(4, 4),
+            reader.read_image_data(0, 4),
+            reader.read_image_data(6, 6),
+        );
+        assert_eq!(a.unwrap(), vec![4, 5, 6, 7]);
+        assert_eq!(b.unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(c.unwrap(), vec![6, 7, 8, 9, 10, 11]);
+        assert_eq!(reader.inner().requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gaps() {
+        let reader = CoalescingReader::new(Counting::new(32));
+        let (a, b) = tokio::join!(reader.read_image_data(0, 4), reader.read_image_data(8, 4));
+        assert_eq!(a.unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(b.unwrap(), vec![8, 9, 10, 11]);
+        assert_eq!(reader.inner().requests.load(Ordering::SeqCst), 2);
+
+        let reader = CoalescingReader::new(Counting::new(32)).with_max_gap(4);
+        let (a, b) = tokio::join!(reader.read_image_data(0, 4), reader.read_image_data(8, 4));
+        assert_eq!(a.unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(b.unwrap(), vec![8, 9, 10, 11]);
+        assert_eq!(reader.inner().requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_merge() {
+        let reader = CoalescingReader::new(Counting::new(32));
+        let (a, b) = tokio::join!(reader.read_image_data(28, 4), reader.read_image_data(30, 4));
+        assert_eq!(a.unwrap(), vec![28, 29, 30, 31]);
+        assert!(b.is_err());
+    }
+}
@@ -1,88 +1,3749 @@
-// mod test {
-//     use crate::{decoder::CogReader, structs::Image};
-
-//     use std::{collections::HashMap, sync::Arc};
-//     type OverviewLevel = u8;
-//     struct CogDecoder {
-//         /// OverviewLevel->Image map (could be a vec)
-//         images: HashMap<OverviewLevel, Arc<Image>>,
-//         // geo_data: Idk,
-//         reader: Arc<dyn CogReader>,
-//     }
-
-//     impl CogDecoder {
-//         /// requiring mutable access to self is suboptimal
-//         fn get_chunk(
-//             &mut self,
-//             i_chunk: u64,
-//             zoom_level: OverviewLevel,
-//         ) -> impl Future<Output = DecodingResult> {
-//             match self.images.get(&zoom_level) {
-//                 None => panic!(), // in this piece of code, we'd have to await IFD retrieval+decoding
-//                 Some(img) => img.clone().decode_chunk(i_chunk), // since this returns a future that doesn't reference self, we are happy
-//             }
-//         }
-//     }
-
-//     impl Image {
-//         // better move this to decoder, only make image return the offset and length
-//         fn decode_chunk<R>(&self, reader: R, i_chunk: u64) -> impl Future<Output = DecodingResult> {
-//             let chunk_offset = self.chunk_offsets[i_chunk];
-//             let chunk_bytes = self.chunk_bytes[i_chunk];
-//             ChunkDecoder::decode(r, chunk_offset, chunk_bytes, self.chunk_opts.clone())
-//         }
-//     }
-
-//     #[tokio::test]
-//     fn test_concurrency() {
-//         let decoder = CogDecoder::from_url("https://enourmous-cog.com")
-//             .await
-//             .expect("Decoder should build");
-//         decoder
-//             .read_overviews(vec![0, 5])
-//             .await
-//             .expect("Decoder should read ifds");
-//         // get a chunk from the highest resolution image
-//         let chunk_1 = decoder.get_chunk(42, 0);
-//         // get a chunk from a lower resolution image
-//         let chunk_2 = decoder.get_chunk(42, 5);
-//         let data = (chunk_1.await, chunk_2.await);
-//     }
-
-//     #[tokio::test]
-//     fn test_concurrency_fail() {
-//         let decoder = CogDecoder::from_url("https://enourmous-cog.com")
-//             .await
-//             .expect("Decoder should build");
-//         decoder
-//             .read_overviews(vec![0])
-//             .await
-//             .expect("decoder should read ifds");
-//         // get a chunk from the highest resolution image
-//         let chunk_1 = decoder.get_chunk(42, 0);
-//         // get a chunk from a lower resolution image
-//         let chunk_2 = decoder.get_chunk(42, 5); //panic!
-//         let data = (chunk_1.await, chunk_2.await);
-//     }
-
-//     // how HeroicKatana would do it if I understand correctly:
-//     #[tokio::test]
-//     fn test_concurrency_recover() {
-//         let decoder = CogDecoder::from_url("https://enourmous-cog.com")
-//             .await
-//             .expect("Decoder should build");
-//         decoder
-//             .read_overviews(vec![0])
-//             .await
-//             .expect("decoder should read ifds");
-//         // get a chunk from the highest resolution image
-//         let chunk_1 = decoder.get_chunk(42, 0).unwrap();
-//         // get a chunk from a lower resolution image
-//         if let OverviewNotLoadedError(chunk_err) = decoder.get_chunk(42, 5).unwrap_err() {
-//             // read_overviews changes state of the decoder to LoadingIfds
-//             decoder.read_overviews(chunk_err).await;
-//         }
-//         let chunk_2 = decoder.get_chunk(42, 5);
-//         let data = (chunk_1.await, chunk_2.await);
-//     }
-// }
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    future::Future,
+    hash::{Hash, Hasher},
+    io,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use futures_lite::{future, stream, Stream};
+use tokio::{
+    sync::{mpsc, OnceCell, Semaphore},
+    task::JoinSet,
+};
+
+#[cfg(feature = "profiling")]
+use crate::decoder::DecodeProfile;
+use crate::{
+    decoder::{
+        check_compression_ratio,
+        chunk::{decode_chunk_data, decompress, sparse_chunk_data, unpredict},
+        window::Window,
+        BandMath, CacheKey, ChunkOpts, CogReader, CompressionRatioLimits, DecodedSamples,
+        DecodingResult, EdgePolicy, LabEncoding, Limits, LruCache, PackedBitmap, Palette,
+        SampleType, SourceKey, YCbCrConversion,
+    },
+    error::{TiffError, TiffFormatError, TiffResult, TiffUnsupportedError, UsageError},
+    progress::{ProgressObserver, ProgressTracker},
+    structs::{
+        geo::{self, GeoKeys, GeoTransform},
+        tags::{CompressionMethod, Orientation, PhotometricInterpretation, PlanarConfiguration},
+        ChunkMetaData, Ifd, Image, Rect, Tag, Tiff,
+    },
+};
+
+#[cfg(feature = "image")]
+use crate::decoder::{dynamic_image, to_dynamic_image};
+#[cfg(feature = "ndarray")]
+use crate::decoder::{to_array3, ArraySample, AxisOrder};
+
+/// Evaluate `$e` as `$stage` of decoding the chunk with key `$key`, recording
+/// how long it took in the `Option<Arc<DecodeProfile>>` `$profile` with the
+/// `profiling` feature
+macro_rules! timed {
+    ($profile:expr, $key:expr, $stage:ident, $e:expr) => {{
+        #[cfg(feature = "profiling")]
+        let start = std::time::Instant::now();
+        let result = $e;
+        #[cfg(feature = "profiling")]
+        if let Some(profile) = &$profile {
+            let (level, chunk) = $key;
+            profile.record(level, chunk, crate::decoder::Stage::$stage, start.elapsed());
+        }
+        result
+    }};
+}
+
+/// Options for opening a file with [`CogDecoder::open`]
+#[derive(Debug, Clone)]
+pub struct DecoderOptions {
+    /// Number of bytes to read from the start of the file in one go, 16 KiB by
+    /// default. The header, IFDs and tag data that lie within it don't need
+    /// separate requests, which for a COG usually means all of them.
+    pub header_prefetch: u64,
+    /// How deep SubIfds may be nested, IFDs in the main chain being at depth
+    /// 0. Deeper files are rejected with [`TiffFormatError::IfdTooDeep`].
+    ///
+    /// [`TiffFormatError::IfdTooDeep`]: crate::error::TiffFormatError::IfdTooDeep
+    pub max_ifd_depth: usize,
+    /// How many SubIfds a single IFD may point to. More are rejected with
+    /// [`TiffFormatError::TooManySubIfds`].
+    ///
+    /// [`TiffFormatError::TooManySubIfds`]: crate::error::TiffFormatError::TooManySubIfds
+    pub max_sub_ifds: usize,
+    /// Bytes of compressed chunks to keep around, so decoding overlapping
+    /// regions doesn't fetch them again. 16 MiB by default, 0 disables caching.
+    pub raw_cache_size: usize,
+    /// Bytes of decoded chunks to keep around, so e.g. a tile server rendering
+    /// adjacent tiles from the same chunk doesn't decompress it twice. 0, which
+    /// disables caching, by default.
+    pub decoded_cache_size: usize,
+    /// If set, chunks whose byte count implies an implausible compression
+    /// ratio are logged as warnings, see [`check_compression_ratio`]. Off by
+    /// default.
+    pub compression_ratio_limits: Option<CompressionRatioLimits>,
+    /// If set, only images compressed with one of these methods are
+    /// accepted. Others are refused with
+    /// [`TiffUnsupportedError::UnsupportedCompressionMethod`] when their IFD
+    /// is read, and by [`CogDecoder`] before any of their chunks are fetched.
+    /// `None`, accepting every method this build decodes, by default.
+    pub compression_methods: Option<Vec<CompressionMethod>>,
+    /// Maximum number of chunks fetched at once by
+    /// [`CogDecoder::prefetch_region`]
+    pub prefetch_concurrency: usize,
+    /// Bounds on the entry counts, tag data, chunks and regions a file can
+    /// make the decoder allocate
+    pub limits: Limits,
+    /// Maximum number of IFDs whose tag data and SubIfds are read at once
+    /// while opening a file. Files with many IFDs open faster when the reader
+    /// has high latency.
+    pub ifd_concurrency: usize,
+    /// Chunk offsets and byte counts taking more bytes than this are left in
+    /// the file when opening it, unless they were prefetched, to be paged in
+    /// as chunks are requested, see [`MaybePartial`]. Unlimited by default.
+    ///
+    /// [`MaybePartial`]: crate::structs::MaybePartial
+    pub max_eager_offsets_bytes: u64,
+    /// Lenient mode for files from writers that put the wrong byte order mark
+    /// in the header. If the magic number or the first IFD only make sense in
+    /// the other byte order, that one is used instead, with a warning, and
+    /// [`Tiff::byte_order_mismatch`] is set. Off by default.
+    ///
+    /// [`Tiff::byte_order_mismatch`]: crate::structs::Tiff::byte_order_mismatch
+    pub detect_byte_order: bool,
+    /// Size in bytes of the values of vendor-defined tag types, by type code,
+    /// so entries of those types are read like those of the standard types.
+    /// Entries of other unknown types keep their raw offset field, see
+    /// [`TagType::Unknown`]. Empty by default.
+    ///
+    /// [`TagType::Unknown`]: crate::structs::TagType::Unknown
+    pub vendor_tag_types: BTreeMap<u16, u8>,
+    /// If set, [`CogDecoder::decode_region_interleaved`] and
+    /// [`CogDecoder::decode_region_rgb`] flip and rotate regions according
+    /// to the image's `Orientation`, like
+    /// [`CogDecoder::decode_region_oriented`] does, taking windows in
+    /// displayed pixels too. Off by default, regions being as stored.
+    pub apply_orientation: bool,
+    /// If set, chunks are decompressed on this pool instead of on the task
+    /// awaiting them, so decoding many chunks uses all cores. `None` by
+    /// default.
+    #[cfg(feature = "rayon")]
+    pub rayon_pool: Option<Arc<rayon::ThreadPool>>,
+    /// If set, the time each chunk spends in each [`Stage`] of decoding is
+    /// recorded here. `None` by default.
+    ///
+    /// [`Stage`]: crate::decoder::Stage
+    #[cfg(feature = "profiling")]
+    pub profile: Option<Arc<DecodeProfile>>,
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        DecoderOptions {
+            header_prefetch: 16 * 1024,
+            max_ifd_depth: 8,
+            max_sub_ifds: 256,
+            raw_cache_size: 16 * 1024 * 1024,
+            decoded_cache_size: 0,
+            compression_ratio_limits: None,
+            compression_methods: None,
+            prefetch_concurrency: 8,
+            limits: Limits::default(),
+            ifd_concurrency: 8,
+            max_eager_offsets_bytes: u64::MAX,
+            detect_byte_order: false,
+            vendor_tag_types: BTreeMap::new(),
+            apply_orientation: false,
+            #[cfg(feature = "rayon")]
+            rayon_pool: None,
+            #[cfg(feature = "profiling")]
+            profile: None,
+        }
+    }
+}
+
+impl DecoderOptions {
+    /// Fail with [`TiffUnsupportedError::UnsupportedCompressionMethod`] if
+    /// `method` isn't one of [`Self::compression_methods`]
+    pub(crate) fn check_compression(&self, method: CompressionMethod) -> TiffResult<()> {
+        check_compression(self.compression_methods.as_deref(), method)
+    }
+}
+
+fn check_compression(
+    allowed: Option<&[CompressionMethod]>,
+    method: CompressionMethod,
+) -> TiffResult<()> {
+    match allowed {
+        Some(allowed) if !allowed.contains(&method) => {
+            Err(TiffUnsupportedError::UnsupportedCompressionMethod(method).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Chunks by source, level and chunk index, possibly of several decoders
+type ChunkCache = LruCache<CacheKey, Bytes>;
+
+/// Index of an image in a COG: 0 is full resolution, 1 the first overview etc.
+pub type OverviewLevel = u8;
+
+/// Index of a chunk within an image, counting row by row and then plane by
+/// plane
+pub type ChunkIndex = usize;
+
+/// What [`CogDecoder::select_level`] picks an overview level for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LevelTarget {
+    /// Width and height in pixels that the whole image is shown at
+    Dims(u32, u32),
+    /// Width and height of a pixel in model units, e.g. the meters per pixel
+    /// of a zoom level of a web map
+    PixelSize(f64),
+}
+
+/// A decoded chunk and where it goes in the image
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedChunk {
+    /// Sample plane, always 0 for chunky images
+    pub plane: usize,
+    /// Part of the image covered by the chunk, without padding
+    pub rect: Rect,
+    /// Decoded samples, including padding
+    pub data: Vec<u8>,
+}
+
+/// Decoder that holds the images of a COG, handing out chunk futures that don't
+/// borrow it.
+///
+/// Decoding chunks only needs shared access, so chunks of different overview
+/// levels can be awaited concurrently while e.g. a map is being panned and
+/// zoomed.
+pub struct CogDecoder {
+    /// Metadata of the whole file
+    tiff: Tiff,
+    /// OverviewLevel->Image map (could be a vec)
+    images: HashMap<OverviewLevel, Arc<Image>>,
+    /// Transparency masks of the overview levels that have one
+    masks: HashMap<OverviewLevel, Arc<Image>>,
+    reader: Arc<dyn CogReader>,
+    /// see [`CogReader::source_id`]
+    source: Arc<SourceKey>,
+    /// Compressed chunks
+    raw_cache: Arc<Mutex<ChunkCache>>,
+    /// Decoded chunks, as returned by [`CogDecoder::get_chunk`]
+    decoded_cache: Arc<Mutex<ChunkCache>>,
+    compression_ratio_limits: Option<CompressionRatioLimits>,
+    /// see [`DecoderOptions::compression_methods`]
+    compression_methods: Option<Vec<CompressionMethod>>,
+    prefetch_concurrency: usize,
+    limits: Limits,
+    /// see [`DecoderOptions::apply_orientation`]
+    apply_orientation: bool,
+    #[cfg(feature = "rayon")]
+    rayon_pool: Option<Arc<rayon::ThreadPool>>,
+    #[cfg(feature = "profiling")]
+    profile: Option<Arc<DecodeProfile>>,
+}
+
+impl CogDecoder {
+    /// Read the header and IFDs of a file
+    pub async fn open(reader: Arc<dyn CogReader>, options: DecoderOptions) -> TiffResult<Self> {
+        let tiff = Tiff::read(&*reader, &options).await?;
+        Ok(Self::new(reader, tiff, &options))
+    }
+
+    /// Create a decoder from already read metadata, without any images loaded
+    pub fn new(reader: Arc<dyn CogReader>, tiff: Tiff, options: &DecoderOptions) -> Self {
+        CogDecoder {
+            tiff,
+            images: HashMap::new(),
+            masks: HashMap::new(),
+            source: Arc::new(reader.source_id()),
+            reader,
+            raw_cache: Arc::new(Mutex::new(LruCache::new(options.raw_cache_size))),
+            decoded_cache: Arc::new(Mutex::new(LruCache::new(options.decoded_cache_size))),
+            compression_ratio_limits: options.compression_ratio_limits.clone(),
+            compression_methods: options.compression_methods.clone(),
+            prefetch_concurrency: options.prefetch_concurrency.max(1),
+            limits: options.limits.clone(),
+            apply_orientation: options.apply_orientation,
+            #[cfg(feature = "rayon")]
+            rayon_pool: options.rayon_pool.clone(),
+            #[cfg(feature = "profiling")]
+            profile: options.profile.clone(),
+        }
+    }
+
+    pub fn tiff(&self) -> &Tiff {
+        &self.tiff
+    }
+
+    /// Keep chunks in the caches of `other` instead of in caches of its own,
+    /// e.g. so a tile server has one budget for all the files it serves.
+    ///
+    /// Chunks are cached by the [`CogReader::source_id`] of the reader they
+    /// were read from and where they are stored in it, so decoders of
+    /// different files never get each other's chunks, and decoders of the
+    /// same file share them whatever levels they put its images at. What was
+    /// cached by this decoder before is dropped.
+    pub fn share_caches(&mut self, other: &CogDecoder) {
+        self.raw_cache = other.raw_cache.clone();
+        self.decoded_cache = other.decoded_cache.clone();
+    }
+
+    /// Add an image, returning the one previously at that level
+    pub fn insert_image(&mut self, level: OverviewLevel, image: Image) -> Option<Arc<Image>> {
+        self.images.insert(level, Arc::new(image))
+    }
+
+    pub fn image(&self, level: OverviewLevel) -> Option<&Arc<Image>> {
+        self.images.get(&level)
+    }
+
+    /// Add the transparency mask of an overview level, returning the one
+    /// previously there
+    pub fn insert_mask(&mut self, level: OverviewLevel, mask: Image) -> Option<Arc<Image>> {
+        self.masks.insert(level, Arc::new(mask))
+    }
+
+    pub fn mask(&self, level: OverviewLevel) -> Option<&Arc<Image>> {
+        self.masks.get(&level)
+    }
+
+    /// Add the images of `ifds`, e.g. those of [`Tiff::ifds`], as overview
+    /// levels in the order of the IFD chain, and the transparency masks among
+    /// them as masks of those levels, returning the number of levels.
+    ///
+    /// An IFD is a mask if [`Ifd::is_transparency_mask`], and belongs to the
+    /// last level of the same size. GDAL puts it in the chain after its level,
+    /// other writers in the `SubIfds` of the level, which are searched too.
+    /// Masks without a level of their size are left out. Each level keeps its
+    /// own tile size, as some pyramids tile their overviews differently.
+    pub fn insert_levels(&mut self, ifds: Vec<Ifd>) -> TiffResult<OverviewLevel> {
+        let byte_order = self.tiff.byte_order();
+        let mut level = 0;
+        for mut ifd in ifds {
+            let sub_ifds = ifd.take_sub_ifds();
+            if ifd.is_transparency_mask()? {
+                self.insert_level_mask(level, Image::from_ifd(ifd, byte_order)?);
+                continue;
+            }
+            self.insert_image(level, Image::from_ifd(ifd, byte_order)?);
+            level = level.checked_add(1).ok_or(TiffError::LimitsExceeded)?;
+            for sub_ifd in sub_ifds {
+                if sub_ifd.is_transparency_mask()? {
+                    self.insert_level_mask(level, Image::from_ifd(sub_ifd, byte_order)?);
+                }
+            }
+        }
+        Ok(level)
+    }
+
+    /// Add a mask to the last of the first `n_levels` levels of its size
+    fn insert_level_mask(&mut self, n_levels: OverviewLevel, mask: Image) {
+        let size = |image: &Image| (image.chunk_meta.image_width, image.chunk_meta.image_height);
+        let level = (0..n_levels)
+            .rev()
+            .find(|level| self.images.get(level).map(|image| size(image)) == Some(size(&mask)));
+        if let Some(level) = level {
+            self.insert_mask(level, mask);
+        }
+    }
+
+    /// Transform from pixel to model coordinates of an overview level, `None`
+    /// if the file isn't georeferenced. See [`Tiff::geotransform`], overview
+    /// levels being IFDs of the main chain.
+    pub fn geotransform(&self, level: OverviewLevel) -> TiffResult<Option<GeoTransform>> {
+        self.tiff.geotransform(level.into())
+    }
+
+    /// GeoKeys of an overview level, `None` if the file has none. See
+    /// [`Tiff::geo_keys`].
+    pub fn geo_keys(&self, level: OverviewLevel) -> TiffResult<Option<GeoKeys>> {
+        self.tiff.geo_keys(level.into())
+    }
+
+    /// The coarsest loaded level that is at least as detailed as `target`, so
+    /// nothing has to be upsampled, or the most detailed one if none is.
+    ///
+    /// Levels are compared by their `ImageWidth` and `ImageLength`, or by the
+    /// pixel size of their geotransform, overviews without geo tags of their
+    /// own having that of level 0 scaled to their size. Fails with
+    /// [`UsageError::OverviewNotLoaded`] if no level was added, and with
+    /// [`TiffFormatError::RequiredTagNotFound`] for a pixel size if the file
+    /// isn't georeferenced.
+    pub fn select_level(&self, target: LevelTarget) -> TiffResult<OverviewLevel> {
+        let mut levels = Vec::with_capacity(self.images.len());
+        for (&level, img) in &self.images {
+            let resolves = match target {
+                LevelTarget::Dims(width, height) => {
+                    img.chunk_meta.image_width >= width && img.chunk_meta.image_height >= height
+                }
+                LevelTarget::PixelSize(size) => {
+                    let (x, y) = self.pixel_size(img)?;
+                    // overviews of exactly the target size may be a rounding
+                    // error off
+                    let size = size * (1.0 + 1e-9);
+                    x <= size && y <= size
+                }
+            };
+            let pixels =
+                u64::from(img.chunk_meta.image_width) * u64::from(img.chunk_meta.image_height);
+            levels.push((resolves, pixels, level));
+        }
+        // coarsest of the levels that resolve the target, otherwise finest
+        let best = match levels.iter().filter(|(resolves, ..)| *resolves).min() {
+            Some(best) => best,
+            None => levels
+                .iter()
+                .max()
+                .ok_or(UsageError::OverviewNotLoaded(0))?,
+        };
+        Ok(best.2)
+    }
+
+    /// Width and height in model units of a pixel of an image
+    fn pixel_size(&self, img: &Image) -> TiffResult<(f64, f64)> {
+        let transform = match (geo::geotransform(&img.ifd)?, self.images.get(&0)) {
+            (Some(transform), _) => transform,
+            (None, Some(full)) => {
+                let transform = geo::geotransform(&full.ifd)?.ok_or(
+                    TiffFormatError::RequiredTagNotFound(Tag::ModelPixelScaleTag),
+                )?;
+                let factor = |full: u32, len: u32| f64::from(full) / f64::from(len);
+                geo::scale_geotransform(
+                    transform,
+                    factor(full.chunk_meta.image_width, img.chunk_meta.image_width),
+                    factor(full.chunk_meta.image_height, img.chunk_meta.image_height),
+                )
+            }
+            (None, None) => {
+                return Err(TiffFormatError::RequiredTagNotFound(Tag::ModelPixelScaleTag).into())
+            }
+        };
+        Ok((
+            transform[1].hypot(transform[4]),
+            transform[2].hypot(transform[5]),
+        ))
+    }
+
+    /// Read the JPEG stream that `JPEGInterchangeFormat` and
+    /// `JPEGInterchangeFormatLength` of an overview level point at, as is,
+    /// e.g. to hand old-style JPEG images to another decoder.
+    ///
+    /// Fails with [`UsageError::OverviewNotLoaded`] if the level's IFD wasn't
+    /// read yet, with [`TiffFormatError::RequiredTagNotFound`] if it has no
+    /// such stream, and with [`TiffError::LimitsExceeded`] if the stream is
+    /// larger than [`Limits::max_chunk_bytes`]. The returned future doesn't
+    /// reference `self`, and returns the stream as the reader handed it out.
+    pub fn extract_embedded_jpeg(
+        &self,
+        level: OverviewLevel,
+    ) -> TiffResult<impl Future<Output = TiffResult<Bytes>> + Send + 'static> {
+        let ifd = &self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?
+            .ifd;
+        let value = |tag| -> TiffResult<u64> { ifd.require_tag_value(&tag)?.get_u64(0) };
+        let offset = value(Tag::JPEGInterchangeFormat)?;
+        let n_bytes = value(Tag::JPEGInterchangeFormatLength)?;
+        Limits::check(n_bytes, self.limits.max_chunk_bytes)?;
+        let reader = self.reader.clone();
+        Ok(async move { reader.read_image_data(offset, n_bytes).await })
+    }
+
+    /// Get a chunk of an overview level.
+    ///
+    /// Fails with [`UsageError::OverviewNotLoaded`] if the level's IFD wasn't
+    /// read yet. The returned future doesn't reference `self`. Chunks are
+    /// cached, see [`DecoderOptions::raw_cache_size`] and
+    /// [`DecoderOptions::decoded_cache_size`]. Sparse chunks, with a byte
+    /// count of 0, aren't fetched but decoded as zeros, see
+    /// [`sparse_chunk_data`].
+    ///
+    /// [`sparse_chunk_data`]: crate::decoder::sparse_chunk_data
+    pub fn get_chunk(
+        &self,
+        i_chunk: usize,
+        level: OverviewLevel,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        let mut request = self.chunk_request(level, i_chunk)?;
+        Ok(async move { request.decoded().await.map(Vec::from) })
+    }
+
+    /// Like [`CogDecoder::get_chunk`], with the samples typed according to
+    /// the `BitsPerSample` and `SampleFormat` of the level, see
+    /// [`DecodingResult`], along with the dimensions of the chunk.
+    ///
+    /// Fails with [`TiffUnsupportedError::UnsupportedSampleFormat`] for
+    /// samples that aren't one of those types, e.g. 1-bit ones, before
+    /// anything is fetched.
+    pub fn get_chunk_typed(
+        &self,
+        i_chunk: usize,
+        level: OverviewLevel,
+    ) -> TiffResult<impl Future<Output = TiffResult<DecodedSamples>> + Send + 'static> {
+        let mut request = self.chunk_request(level, i_chunk)?;
+        Region::source_type(&request.chunk_meta)?;
+        Ok(async move {
+            let data = request.decoded().await?;
+            DecodingResult::with_dims(&data, i_chunk, &request.chunk_meta)
+        })
+    }
+
+    /// Like [`CogDecoder::get_chunk`], as an [`image::DynamicImage`] of the
+    /// part of the chunk that lies within the image.
+    ///
+    /// Fails with [`TiffUnsupportedError::UnsupportedPlanarConfig`] for
+    /// planar images, of which a chunk holds a single band, and with
+    /// [`TiffUnsupportedError::UnsupportedColorType`] for images that
+    /// [`to_dynamic_image`] can't convert, before anything is fetched.
+    ///
+    /// [`to_dynamic_image`]: crate::decoder::to_dynamic_image
+    #[cfg(feature = "image")]
+    pub fn get_chunk_image(
+        &self,
+        i_chunk: usize,
+        level: OverviewLevel,
+    ) -> TiffResult<impl Future<Output = TiffResult<image::DynamicImage>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let chunk_meta = img.chunk_meta();
+        if chunk_meta.planar_config == PlanarConfiguration::Planar && chunk_meta.samples > 1 {
+            return Err(TiffUnsupportedError::UnsupportedPlanarConfig(Some(
+                chunk_meta.planar_config,
+            ))
+            .into());
+        }
+        let color_type = img.color_type()?;
+        dynamic_image::check_supported(color_type, Region::source_type(&chunk_meta)?)?;
+        let chunk = self.get_chunk_typed(i_chunk, level)?;
+        Ok(async move {
+            let DecodedSamples { samples, dims } = chunk.await?;
+            let image = to_dynamic_image(samples, dims.width, dims.height, color_type)?;
+            if (dims.valid_width, dims.valid_height) == (dims.width, dims.height) {
+                Ok(image)
+            } else {
+                Ok(image.crop_imm(0, 0, dims.valid_width, dims.valid_height))
+            }
+        })
+    }
+
+    /// Like [`CogDecoder::get_chunk`], laying out the chunk according to
+    /// `opts`. Chunks are cached as decoded, so differently laid out requests
+    /// for the same chunk share cache entries.
+    pub fn get_chunk_with(
+        &self,
+        i_chunk: usize,
+        level: OverviewLevel,
+        opts: ChunkOpts,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        let mut request = self.chunk_request(level, i_chunk)?;
+        Ok(async move {
+            let data = request.decoded().await?;
+            timed!(
+                request.profile,
+                request.key,
+                Layout,
+                opts.apply(data.into(), &request.chunk_meta)
+            )
+        })
+    }
+
+    /// Get a chunk of an overview level together with the same chunk of its
+    /// mask, decoded.
+    ///
+    /// If the ghost header says GDAL interleaved the mask with the imagery,
+    /// and the mask chunk indeed follows the image chunk, both are fetched in
+    /// a single read. Otherwise they are fetched concurrently, sparse chunks
+    /// not at all. Neither is cached. Fails with [`UsageError::MaskNotLoaded`]
+    /// if no mask was added for the level with [`CogDecoder::insert_mask`].
+    pub fn read_chunk_with_mask(
+        &self,
+        i_chunk: usize,
+        level: OverviewLevel,
+    ) -> TiffResult<impl Future<Output = TiffResult<(Vec<u8>, Vec<u8>)>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let mask = self
+            .masks
+            .get(&level)
+            .ok_or(UsageError::MaskNotLoaded(level))?;
+        for image in [img, mask] {
+            check_compression(
+                self.compression_methods.as_deref(),
+                image.chunk_meta.compression_method,
+            )?;
+        }
+        let (img_start, img_bytes) = (img.chunk_offset(i_chunk)?, img.chunk_bytes(i_chunk)?);
+        let (mask_start, mask_bytes) = (mask.chunk_offset(i_chunk)?, mask.chunk_bytes(i_chunk)?);
+        for n_bytes in [img_bytes, mask_bytes] {
+            Limits::check(n_bytes, self.limits.max_chunk_bytes)?;
+        }
+        let interleaved = self
+            .tiff
+            .ghost_header
+            .as_ref()
+            .and_then(|ghost| ghost.mask_gap())
+            .filter(|_| img_bytes > 0)
+            .and_then(|gap| img_bytes.checked_add(gap))
+            .and_then(|n| img_start.checked_add(n))
+            == Some(mask_start);
+        let (img_meta, mask_meta) = (img.chunk_meta(), mask.chunk_meta());
+        let reader = self.reader.clone();
+        let limits = self.limits.clone();
+        Ok(async move {
+            let (img_data, mask_data) = if interleaved {
+                let mask_offset = mask_start - img_start;
+                let n_bytes = mask_offset
+                    .checked_add(mask_bytes)
+                    .ok_or(TiffError::IntSizeError)?;
+                let mut data = reader.read_image_data(img_start, n_bytes).await?;
+                let mask_offset = usize::try_from(mask_offset)?;
+                if data.len() < mask_offset {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                let mask_data = data.split_off(mask_offset);
+                data.truncate(usize::try_from(img_bytes)?);
+                (data, mask_data)
+            } else {
+                let reader = &reader;
+                let read = |byte_start, n_bytes| async move {
+                    match n_bytes {
+                        0 => Ok(Bytes::new()),
+                        _ => reader.read_image_data(byte_start, n_bytes).await,
+                    }
+                };
+                future::try_zip(read(img_start, img_bytes), read(mask_start, mask_bytes)).await?
+            };
+            let decode = |data: Bytes, n_bytes, meta| match n_bytes {
+                0 => sparse_chunk_data(meta, &limits),
+                _ => decode_chunk_data(data.into(), i_chunk, meta, &limits),
+            };
+            Ok((
+                decode(img_data, img_bytes, &img_meta)?,
+                decode(mask_data, mask_bytes, &mask_meta)?,
+            ))
+        })
+    }
+
+    /// Fetch all chunks of an overview level that overlap `rect` into the
+    /// caches, so decoding them later doesn't have to wait for the reader.
+    ///
+    /// Chunks whose offsets and byte counts are loaded are fetched with a
+    /// single [`CogReader::read_vectored`], others at most
+    /// [`DecoderOptions::prefetch_concurrency`] at once. Chunks are decoded as
+    /// well if the decoded cache is enabled. Like
+    /// [`CogDecoder::get_chunk`], the returned future doesn't reference `self`.
+    /// Fetches are spawned on the tokio runtime, and aborted when the future is
+    /// dropped, so e.g. a tile server can stop reading once its client is gone.
+    pub fn prefetch_region(
+        &self,
+        level: OverviewLevel,
+        rect: Rect,
+    ) -> TiffResult<impl Future<Output = TiffResult<()>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let mut requests = img
+            .chunk_meta()
+            .chunks_covering(&rect)
+            .into_iter()
+            .map(|i_chunk| self.chunk_request(level, i_chunk))
+            .collect::<TiffResult<Vec<_>>>()?;
+        let concurrency = self.prefetch_concurrency;
+        Ok(async move {
+            fetch_vectored(&mut requests).await?;
+            spawn_bounded(requests, concurrency, |mut request| async move {
+                request.prefetch().await
+            })
+            .await?;
+            Ok(())
+        })
+    }
+
+    /// Decode a window of an overview level, assembled from all chunks that
+    /// overlap it.
+    ///
+    /// The result holds `width * height` pixels row by row, with samples
+    /// interleaved for chunky images and one sample plane after the other for
+    /// planar ones. Chunks are fetched and cached like in
+    /// [`CogDecoder::prefetch_region`], and the returned future doesn't
+    /// reference `self`. Dropping it aborts the fetches. Chunks stored in the
+    /// same place are fetched once, and chunks with identical compressed data,
+    /// such as uniform nodata tiles, are decoded once. Fails with
+    /// [`UsageError::RegionOutOfBounds`] if the window doesn't lie within the
+    /// image.
+    ///
+    /// See [`CogDecoder::decode_region_interleaved`] and
+    /// [`CogDecoder::decode_region_bands`] for the same layout regardless of
+    /// the planar configuration.
+    pub fn decode_region(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        self.region(level, Rect::new(x, y, width, height), None, Output::Stored)
+    }
+
+    /// Like [`CogDecoder::decode_region`], as an [`ndarray::Array3`] with its
+    /// axes in `order`, see [`to_array3`].
+    ///
+    /// Fails with [`UsageError::SampleTypeMismatch`] if the samples of the
+    /// level aren't of type `T`, before anything is fetched.
+    ///
+    /// [`to_array3`]: crate::decoder::to_array3
+    #[cfg(feature = "ndarray")]
+    pub fn decode_region_array<T: ArraySample>(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        order: AxisOrder,
+    ) -> TiffResult<impl Future<Output = TiffResult<ndarray::Array3<T>>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let chunk_meta = img.chunk_meta();
+        let actual = Region::source_type(&chunk_meta)?;
+        if actual != T::SAMPLE_TYPE {
+            return Err(UsageError::SampleTypeMismatch {
+                requested: T::SAMPLE_TYPE,
+                actual,
+            }
+            .into());
+        }
+        let region = self.decode_region(level, x, y, width, height)?;
+        Ok(async move {
+            let samples = DecodingResult::new(&region.await?, &chunk_meta)?;
+            to_array3(
+                samples,
+                width,
+                height,
+                usize::from(chunk_meta.samples),
+                chunk_meta.planar_config == PlanarConfiguration::Planar,
+                order,
+            )
+        })
+    }
+
+    /// Like [`CogDecoder::decode_region`], with the samples of each pixel
+    /// interleaved for planar images too, as [`render_rgba`] and most image
+    /// libraries expect. With [`DecoderOptions::apply_orientation`], this is
+    /// [`CogDecoder::decode_region_oriented`].
+    ///
+    /// [`render_rgba`]: crate::decoder::render_rgba
+    pub fn decode_region_interleaved(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        self.interleaved_region(
+            level,
+            Rect::new(x, y, width, height),
+            self.apply_orientation,
+        )
+    }
+
+    /// Width and height of an overview level in the pixels of
+    /// [`CogDecoder::decode_region_interleaved`]: those of the displayed image
+    /// with [`DecoderOptions::apply_orientation`], swapped for rotated images,
+    /// and those of the stored image otherwise
+    pub fn interleaved_size(&self, level: OverviewLevel) -> TiffResult<(u32, u32)> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let (width, height) = (img.chunk_meta.image_width, img.chunk_meta.image_height);
+        if !self.apply_orientation {
+            return Ok((width, height));
+        }
+        let orientation = Orientation::from_ifd(self.describing_ifd(img, Tag::Orientation))?;
+        Ok(orientation.displayed_size(width, height))
+    }
+
+    /// Like [`CogDecoder::decode_region_interleaved`], as an
+    /// [`image::DynamicImage`], e.g. to save a PNG preview.
+    ///
+    /// Fails with [`TiffUnsupportedError::UnsupportedColorType`] for images
+    /// that [`to_dynamic_image`] can't convert, before anything is fetched.
+    ///
+    /// [`to_dynamic_image`]: crate::decoder::to_dynamic_image
+    #[cfg(feature = "image")]
+    pub fn decode_region_image(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> TiffResult<impl Future<Output = TiffResult<image::DynamicImage>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let chunk_meta = img.chunk_meta();
+        let color_type = img.color_type()?;
+        dynamic_image::check_supported(color_type, Region::source_type(&chunk_meta)?)?;
+        let region = self.decode_region_interleaved(level, x, y, width, height)?;
+        Ok(async move {
+            let samples = DecodingResult::new(&region.await?, &chunk_meta)?;
+            to_dynamic_image(samples, width, height, color_type)
+        })
+    }
+
+    /// Like [`CogDecoder::decode_region_interleaved`] without orientation,
+    /// together with the window of the level's transparency mask, in which
+    /// the bits of valid pixels are set. A tile server can e.g. hand both to
+    /// [`render_rgba`] to make nodata transparent.
+    ///
+    /// Mask chunks aren't cached. Fails with [`UsageError::MaskNotLoaded`] if
+    /// no mask was added for the level, and with
+    /// [`TiffFormatError::InvalidDimensions`] if it isn't a 1-bit image of the
+    /// level's size.
+    ///
+    /// [`render_rgba`]: crate::decoder::render_rgba
+    pub fn decode_region_with_mask(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> TiffResult<impl Future<Output = TiffResult<(Vec<u8>, PackedBitmap)>> + Send + 'static>
+    {
+        let rect = Rect::new(x, y, width, height);
+        let mask = self.mask_region(level, rect)?;
+        let region = self.region(level, rect, None, Output::Interleaved)?;
+        Ok(async move { future::try_zip(region, mask).await })
+    }
+
+    /// The window `rect` of the transparency mask of a level
+    fn mask_region(
+        &self,
+        level: OverviewLevel,
+        rect: Rect,
+    ) -> TiffResult<impl Future<Output = TiffResult<PackedBitmap>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let mask = self
+            .masks
+            .get(&level)
+            .ok_or(UsageError::MaskNotLoaded(level))?;
+        let mask_meta = mask.chunk_meta();
+        let size = (mask_meta.image_width, mask_meta.image_height);
+        if mask_meta.bits_per_sample != 1
+            || mask_meta.samples != 1
+            || size != (img.chunk_meta.image_width, img.chunk_meta.image_height)
+        {
+            return Err(TiffFormatError::InvalidDimensions(size.0, size.1).into());
+        }
+        if !rect.fits_in(size.0, size.1) {
+            return Err(UsageError::RegionOutOfBounds(rect).into());
+        }
+        let mut requests = mask_meta
+            .chunks_covering(&rect)
+            .into_iter()
+            .map(|i_chunk| self.image_chunk_request(mask, (level, i_chunk)))
+            .collect::<TiffResult<Vec<_>>>()?;
+        let concurrency = self.prefetch_concurrency;
+        Ok(async move {
+            fetch_vectored(&mut requests).await?;
+            let keys: Vec<_> = requests.iter().map(|request| request.key.1).collect();
+            let decoded = spawn_bounded(requests, concurrency, |mut request| async move {
+                request.decoded().await
+            })
+            .await?;
+            let (width, height) = (usize::try_from(rect.width)?, usize::try_from(rect.height)?);
+            let mut bitmap = PackedBitmap {
+                width,
+                height,
+                stride: PackedBitmap::stride_for(width),
+                data: vec![0; PackedBitmap::stride_for(width) * height],
+            };
+            for (i_chunk, chunk) in keys.into_iter().zip(decoded) {
+                copy_mask_chunk(&mut bitmap, &mask_meta, rect, i_chunk, &chunk)?;
+            }
+            Ok(bitmap)
+        })
+    }
+
+    /// The colors that the indices of a palette image stand for, to expand
+    /// them to 16-bit RGB with [`Palette::to_rgb16`] or keep them as is.
+    ///
+    /// Overviews without a `ColorMap` of their own take that of the full
+    /// resolution image. Fails with [`TiffFormatError::InvalidTagValueType`]
+    /// for images that aren't palette images or have no `ColorMap`.
+    pub fn palette(&self, level: OverviewLevel) -> TiffResult<Palette> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let chunk_meta = img.chunk_meta();
+        if chunk_meta.photometric_interpretation != PhotometricInterpretation::RGBPalette {
+            return Err(TiffFormatError::InvalidTagValueType(Tag::ColorMap.to_u16()).into());
+        }
+        Palette::from_ifd(
+            self.describing_ifd(img, Tag::ColorMap),
+            chunk_meta.bits_per_sample,
+        )?
+        .ok_or_else(|| TiffFormatError::InvalidTagValueType(Tag::ColorMap.to_u16()).into())
+    }
+
+    /// IFD of `img` if it has `tag`, otherwise that of the full resolution
+    /// image, as overviews may leave out tags that describe the whole image
+    fn describing_ifd<'a>(&'a self, img: &'a Image, tag: Tag) -> &'a Ifd {
+        match self.images.get(&0) {
+            Some(full) if !img.ifd.contains_key(&tag) => &full.ifd,
+            _ => &img.ifd,
+        }
+    }
+
+    /// Like [`CogDecoder::decode_region_interleaved`], for a window of the
+    /// image as displayed according to its `Orientation`.
+    ///
+    /// `x`, `y`, `width` and `height` are in displayed pixels, so their bounds
+    /// are swapped for rotated images. The window of the stored image that
+    /// holds them is decoded, and its pixels reordered to the displayed
+    /// orientation. Overviews without an `Orientation` of their own take that
+    /// of the full resolution image.
+    pub fn decode_region_oriented(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        self.interleaved_region(level, Rect::new(x, y, width, height), true)
+    }
+
+    /// Interleaved pixels of `rect`, which is a window of the displayed image
+    /// if `oriented` is set, and of the stored image otherwise
+    fn interleaved_region(
+        &self,
+        level: OverviewLevel,
+        rect: Rect,
+        oriented: bool,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let orientation = if oriented {
+            Orientation::from_ifd(self.describing_ifd(img, Tag::Orientation))?
+        } else {
+            Orientation::TopLeft
+        };
+        let chunk_meta = img.chunk_meta();
+        let (displayed_width, displayed_height) =
+            orientation.displayed_size(chunk_meta.image_width, chunk_meta.image_height);
+        if !rect.fits_in(displayed_width, displayed_height) {
+            return Err(UsageError::RegionOutOfBounds(rect).into());
+        }
+        let stored = orientation.stored_rect(rect, chunk_meta.image_width, chunk_meta.image_height);
+        let (_, pixel_bytes) = Region::layout(&chunk_meta, &Output::Interleaved)?;
+        let region = self.region(level, stored, None, Output::Interleaved)?;
+        Ok(async move {
+            let data = region.await?;
+            if orientation == Orientation::TopLeft {
+                return Ok(data);
+            }
+            Ok(orientation.to_displayed(
+                &data,
+                stored.width as usize,
+                stored.height as usize,
+                pixel_bytes,
+            ))
+        })
+    }
+
+    /// Like [`CogDecoder::decode_region_interleaved`], converting 8-bit YCbCr
+    /// to RGB with the image's [`YCbCrConversion`], expanding 8 or 16-bit
+    /// palette indices to 8-bit RGB with its [`Palette`], and converting 8 or
+    /// 16-bit L*a*b* to 8-bit sRGB, see [`LabEncoding::to_rgb8`].
+    ///
+    /// Subsampled chroma, of uncompressed as well as JPEG images, is upsampled
+    /// before the conversion. 8-bit RGB is returned as is. Other images fail
+    /// with [`TiffUnsupportedError::UnsupportedInterpretation`], or
+    /// [`TiffUnsupportedError::InterpretationWithBits`] for RGB, YCbCr, L*a*b*
+    /// and palettes with other samples. See [`CogDecoder::palette`] for 16-bit
+    /// colors.
+    pub fn decode_region_rgb(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let chunk_meta = img.chunk_meta();
+        let photometric = chunk_meta.photometric_interpretation;
+        let bits = chunk_meta.bits_per_sample;
+        let supported = match photometric {
+            PhotometricInterpretation::RGB | PhotometricInterpretation::YCbCr => {
+                chunk_meta.samples == 3 && bits == 8
+            }
+            PhotometricInterpretation::RGBPalette => {
+                chunk_meta.samples == 1 && matches!(bits, 8 | 16)
+            }
+            PhotometricInterpretation::CIELab | PhotometricInterpretation::ICCLab => {
+                chunk_meta.samples == 3 && matches!(bits, 8 | 16)
+            }
+            _ => return Err(TiffUnsupportedError::UnsupportedInterpretation(photometric).into()),
+        };
+        if !supported {
+            return Err(TiffUnsupportedError::InterpretationWithBits(
+                photometric,
+                vec![bits; usize::from(chunk_meta.samples)],
+            )
+            .into());
+        }
+        let (conversion, palette) = match photometric {
+            PhotometricInterpretation::YCbCr => (Some(YCbCrConversion::from_ifd(&img.ifd)?), None),
+            PhotometricInterpretation::RGBPalette => (None, Some(self.palette(level)?)),
+            _ => (None, None),
+        };
+        let lab = LabEncoding::from_photometric(photometric);
+        let region = self.interleaved_region(
+            level,
+            Rect::new(x, y, width, height),
+            self.apply_orientation,
+        )?;
+        Ok(async move {
+            let mut data = region.await?;
+            if let Some(conversion) = conversion {
+                conversion.to_rgb(&mut data);
+            }
+            if let Some(palette) = palette {
+                data = palette.to_rgb8(&data, bits)?;
+            }
+            if let Some(lab) = lab {
+                data = lab.to_rgb8(&data, bits)?;
+            }
+            Ok(data)
+        })
+    }
+
+    /// Like [`CogDecoder::decode_region_interleaved`], for L*a*b* images,
+    /// returning the L* (0 to 100), a* and b* of each pixel relative to D50
+    /// rather than converting them to RGB like
+    /// [`CogDecoder::decode_region_rgb`] does.
+    ///
+    /// Fails with [`TiffUnsupportedError::UnsupportedInterpretation`] for
+    /// images of other photometric interpretations, and with
+    /// [`TiffUnsupportedError::InterpretationWithBits`] for ones of other than
+    /// three 8 or 16-bit samples.
+    pub fn decode_region_lab(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<f32>>> + Send + 'static> {
+        let chunk_meta = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?
+            .chunk_meta();
+        let photometric = chunk_meta.photometric_interpretation;
+        let bits = chunk_meta.bits_per_sample;
+        let lab = LabEncoding::from_photometric(photometric)
+            .ok_or(TiffUnsupportedError::UnsupportedInterpretation(photometric))?;
+        if chunk_meta.samples != 3 || !matches!(bits, 8 | 16) {
+            return Err(TiffUnsupportedError::InterpretationWithBits(
+                photometric,
+                vec![bits; usize::from(chunk_meta.samples)],
+            )
+            .into());
+        }
+        let region = self.interleaved_region(
+            level,
+            Rect::new(x, y, width, height),
+            self.apply_orientation,
+        )?;
+        Ok(async move { lab.to_lab(&region.await?, bits) })
+    }
+
+    /// Like [`CogDecoder::decode_region`], with the `width * height` samples
+    /// of each band in a buffer of its own, for chunky and planar images
+    /// alike
+    pub fn decode_region_bands(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<Vec<u8>>>> + Send + 'static> {
+        let chunk_meta = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?
+            .chunk_meta();
+        let samples = usize::from(chunk_meta.samples);
+        let sample_bytes = usize::from(chunk_meta.bits_per_sample / 8);
+        let planar = chunk_meta.planar_config == PlanarConfiguration::Planar;
+        let region = self.region(level, Rect::new(x, y, width, height), None, Output::Stored)?;
+        Ok(async move {
+            let data = region.await?;
+            if planar || samples == 1 {
+                let band_len = data.len() / samples;
+                return Ok((0..samples)
+                    .map(|band| data[band * band_len..(band + 1) * band_len].to_vec())
+                    .collect());
+            }
+            let mut bands = vec![Vec::with_capacity(data.len() / samples); samples];
+            for pixel in data.chunks_exact(samples * sample_bytes) {
+                for (band, sample) in bands.iter_mut().zip(pixel.chunks_exact(sample_bytes)) {
+                    band.extend_from_slice(sample);
+                }
+            }
+            Ok(bands)
+        })
+    }
+
+    /// Like [`CogDecoder::decode_region`], for a window with its top left
+    /// pixel at `(x, y)` that may lie partly or wholly outside the image.
+    ///
+    /// Only the part of the image the window needs is decoded, the rest is
+    /// filled in according to `edge`. With [`EdgePolicy::Error`], fails with
+    /// [`UsageError::WindowOutOfBounds`] if the window doesn't lie within the
+    /// image.
+    pub fn decode_window(
+        &self,
+        level: OverviewLevel,
+        x: i64,
+        y: i64,
+        width: u32,
+        height: u32,
+        edge: EdgePolicy,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let chunk_meta = img.chunk_meta();
+        let window = Window {
+            x,
+            y,
+            width,
+            height,
+        };
+        let source = window.source(edge, chunk_meta.image_width, chunk_meta.image_height)?;
+        let (planes, pixel_bytes) = Region::layout(&chunk_meta, &Output::Stored)?;
+        Limits::check(
+            u64::from(width) * u64::from(height) * (planes * pixel_bytes) as u64,
+            self.limits.max_total_bytes,
+        )?;
+        let fill = match edge {
+            EdgePolicy::Fill(value) => {
+                let sample_type = Region::source_type(&chunk_meta)?;
+                let mut fill = Vec::with_capacity(pixel_bytes);
+                for _ in 0..pixel_bytes / sample_type.size() {
+                    sample_type.write_f64(value, &mut fill);
+                }
+                fill
+            }
+            _ => vec![0; pixel_bytes],
+        };
+        let region = match source {
+            Some(rect) => Some((rect, self.region(level, rect, None, Output::Stored)?)),
+            None => None,
+        };
+        Ok(async move {
+            let source = match region {
+                Some((rect, region)) => Some((rect, region.await?)),
+                None => None,
+            };
+            Ok(window.assemble(edge, source, planes, pixel_bytes, &fill))
+        })
+    }
+
+    /// Like [`CogDecoder::decode_region`], reporting each chunk that was
+    /// fetched and decoded to `observer`
+    pub fn decode_region_with_progress(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        observer: Arc<dyn ProgressObserver>,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        self.region(
+            level,
+            Rect::new(x, y, width, height),
+            Some(observer),
+            Output::Stored,
+        )
+    }
+
+    /// Like [`CogDecoder::decode_region`], combining the samples of each pixel
+    /// with `band_math` while the chunks are assembled.
+    ///
+    /// The result holds `width * height` samples of
+    /// [`BandMath::sample_type`], row by row, for chunky and planar images
+    /// alike. Fails with [`TiffUnsupportedError::UnsupportedSampleFormat`] if
+    /// the samples of the image aren't byte-aligned numbers.
+    pub fn decode_band_math(
+        &self,
+        level: OverviewLevel,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        band_math: BandMath,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        self.region(
+            level,
+            Rect::new(x, y, width, height),
+            None,
+            Output::BandMath(band_math),
+        )
+    }
+
+    fn region(
+        &self,
+        level: OverviewLevel,
+        rect: Rect,
+        observer: Option<Arc<dyn ProgressObserver>>,
+        output: Output,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let chunk_meta = img.chunk_meta();
+        if !rect.fits_in(chunk_meta.image_width, chunk_meta.image_height) {
+            return Err(UsageError::RegionOutOfBounds(rect).into());
+        }
+        if !chunk_meta.bits_per_sample.is_multiple_of(8) {
+            return Err(TiffUnsupportedError::UnsupportedBitsPerChannel(
+                chunk_meta.bits_per_sample,
+            )
+            .into());
+        }
+        let (planes, pixel_bytes) = Region::layout(&chunk_meta, &output)?;
+        Limits::check(
+            u64::from(rect.width) * u64::from(rect.height) * (planes * pixel_bytes) as u64,
+            self.limits.max_total_bytes,
+        )?;
+        // chunks stored at the same place, e.g. uniform tiles written once,
+        // are fetched and decoded once
+        let mut locations = HashMap::new();
+        let mut requests = Vec::new();
+        let mut sources = Vec::new();
+        for i_chunk in chunk_meta.chunks_covering(&rect) {
+            let plane = chunk_meta.chunk_rect(i_chunk).map_or(0, |(plane, _)| plane);
+            let location = img
+                .chunk_offset(i_chunk)
+                .and_then(|offset| Ok((offset, img.chunk_bytes(i_chunk)?, plane)))
+                .ok();
+            let i_request = match location.and_then(|location| locations.get(&location)) {
+                Some(&i_request) => i_request,
+                None => {
+                    if let Some(location) = location {
+                        locations.insert(location, requests.len());
+                    }
+                    requests.push(self.chunk_request(level, i_chunk)?);
+                    requests.len() - 1
+                }
+            };
+            sources.push((i_chunk, i_request));
+        }
+        if let Some(observer) = observer {
+            let progress = Arc::new(ProgressTracker::new(observer, requests.len()));
+            for request in &mut requests {
+                request.progress = Some(progress.clone());
+            }
+        }
+        let concurrency = self.prefetch_concurrency;
+        #[cfg(feature = "profiling")]
+        let profile = self.profile.clone();
+        Ok(async move {
+            let dedup = Arc::new(Dedup::default());
+            fetch_vectored(&mut requests).await?;
+            let decoded = spawn_bounded(requests, concurrency, |mut request| {
+                let dedup = dedup.clone();
+                async move { request.decoded_dedup(&dedup).await }
+            })
+            .await?;
+            let chunks = sources
+                .into_iter()
+                .map(|(i_chunk, i_request)| (i_chunk, decoded[i_request].clone()))
+                .collect();
+            let mut region = Region::new(&chunk_meta, rect, output)?;
+            #[cfg(feature = "profiling")]
+            {
+                region.profile = profile;
+                region.level = level;
+            }
+            region.copy_chunks(chunks)?;
+            Ok(region.data)
+        })
+    }
+
+    /// Decode a range of chunks of an overview level, yielding each one as
+    /// soon as it is decoded, so consumers don't have to wait for all of them.
+    ///
+    /// Chunks are yielded in the order they finish, at most
+    /// [`DecoderOptions::prefetch_concurrency`] being fetched at once. They go
+    /// through the caches like [`CogDecoder::get_chunk`], and the stream
+    /// doesn't reference `self`. Dropping the stream stops fetching chunks that
+    /// weren't started yet. Fetches are spawned on the tokio runtime once the
+    /// stream is first polled.
+    pub fn stream_chunks(
+        &self,
+        level: OverviewLevel,
+        range: Range<ChunkIndex>,
+    ) -> TiffResult<impl Stream<Item = TiffResult<(ChunkIndex, DecodedChunk)>> + Send + 'static>
+    {
+        let requests = range
+            .map(|i_chunk| self.chunk_request(level, i_chunk))
+            .collect::<TiffResult<Vec<_>>>()?;
+        let concurrency = self.prefetch_concurrency;
+        Ok(stream::unfold(
+            ChunkStream::Pending(requests, concurrency),
+            |state| async move {
+                let mut receiver = match state {
+                    ChunkStream::Pending(requests, concurrency) => {
+                        spawn_streamed(requests, concurrency)
+                    }
+                    ChunkStream::Running(receiver) => receiver,
+                };
+                let chunk = receiver.recv().await?;
+                Some((chunk, ChunkStream::Running(receiver)))
+            },
+        ))
+    }
+
+    /// Gather everything needed to get a chunk
+    fn chunk_request(&self, level: OverviewLevel, i_chunk: usize) -> TiffResult<ChunkRequest> {
+        let img = self
+            .images
+            .get(&level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        self.image_chunk_request(img, (level, i_chunk))
+    }
+
+    /// Like [`CogDecoder::chunk_request`], for a chunk of any image, `key`
+    /// being what it is cached as
+    fn image_chunk_request(
+        &self,
+        img: &Arc<Image>,
+        key: (OverviewLevel, usize),
+    ) -> TiffResult<ChunkRequest> {
+        let i_chunk = key.1;
+        check_compression(
+            self.compression_methods.as_deref(),
+            img.chunk_meta.compression_method,
+        )?;
+        // before anything gets fetched, if the byte count is loaded
+        let n_bytes = match img.chunk_bytes(i_chunk) {
+            Ok(n_bytes) => {
+                Limits::check(n_bytes, self.limits.max_chunk_bytes)?;
+                Some(n_bytes)
+            }
+            Err(TiffError::UsageError(UsageError::PageNotLoaded(_))) => None,
+            Err(e) => return Err(e),
+        };
+        let request = ChunkRequest {
+            key,
+            image: img.clone(),
+            chunk_meta: img.chunk_meta(),
+            limits: self.limits.clone(),
+            reader: self.reader.clone(),
+            source: self.source.clone(),
+            raw_cache: self.raw_cache.clone(),
+            decoded_cache: self.decoded_cache.clone(),
+            #[cfg(feature = "rayon")]
+            rayon_pool: self.rayon_pool.clone(),
+            #[cfg(feature = "profiling")]
+            profile: self.profile.clone(),
+            progress: None,
+            fetched: None,
+        };
+        if let (Some(limits), Some(n_bytes @ 1..)) = (&self.compression_ratio_limits, n_bytes) {
+            check_compression_ratio(&request.chunk_meta, n_bytes, limits);
+        }
+        Ok(request)
+    }
+}
+
+/// A chunk to get, owning everything needed so it doesn't borrow the decoder
+struct ChunkRequest {
+    key: (OverviewLevel, usize),
+    /// Where the chunk offsets and byte counts come from, which may still
+    /// have to be paged in
+    image: Arc<Image>,
+    chunk_meta: Arc<ChunkMetaData>,
+    limits: Limits,
+    reader: Arc<dyn CogReader>,
+    source: Arc<SourceKey>,
+    raw_cache: Arc<Mutex<ChunkCache>>,
+    decoded_cache: Arc<Mutex<ChunkCache>>,
+    #[cfg(feature = "rayon")]
+    rayon_pool: Option<Arc<rayon::ThreadPool>>,
+    #[cfg(feature = "profiling")]
+    profile: Option<Arc<DecodeProfile>>,
+    progress: Option<Arc<ProgressTracker>>,
+    /// Compressed chunk fetched along with others by [`fetch_vectored`]
+    fetched: Option<Bytes>,
+}
+
+impl ChunkRequest {
+    /// Offset and byte count of the chunk, loading them if needed
+    async fn location(&self) -> TiffResult<(u64, u64)> {
+        let i_chunk = self.key.1;
+        let reader = &*self.reader;
+        let location = future::try_zip(
+            self.image.chunk_offsets.load_u64(i_chunk, reader),
+            self.image.chunk_bytes.load_u64(i_chunk, reader),
+        )
+        .await?;
+        Limits::check(location.1, self.limits.max_chunk_bytes)?;
+        Ok(location)
+    }
+
+    /// Whether the chunk is sparse, having a byte count of 0 and nothing to
+    /// fetch
+    async fn is_sparse(&self) -> TiffResult<bool> {
+        Ok(self.fetched.is_none() && self.location().await?.1 == 0)
+    }
+
+    /// The decoded chunk if it is sparse, filled without fetching anything
+    async fn sparse(&self) -> TiffResult<Option<Vec<u8>>> {
+        if !self.is_sparse().await? {
+            return Ok(None);
+        }
+        if let Some(progress) = &self.progress {
+            progress.fetched(0);
+            progress.done(0);
+        }
+        sparse_chunk_data(&self.chunk_meta, &self.limits).map(Some)
+    }
+
+    /// Key of the chunk in the caches, loading its location if needed
+    async fn cache_key(&self) -> TiffResult<CacheKey> {
+        let (byte_start, n_bytes) = self.location().await?;
+        Ok(CacheKey::new(self.source.clone(), byte_start, n_bytes))
+    }
+
+    /// Whether the chunk stored at `(byte_start, n_bytes)` is in either
+    /// cache, so it needn't be fetched
+    fn is_cached(&self, byte_start: u64, n_bytes: u64) -> TiffResult<bool> {
+        let key = CacheKey::new(self.source.clone(), byte_start, n_bytes);
+        Ok(self.decoded_cache.lock()?.contains_key(&key)
+            || self.raw_cache.lock()?.contains_key(&key))
+    }
+
+    /// Compressed chunk, from the cache, what was fetched before or the reader
+    async fn raw(&mut self) -> TiffResult<Bytes> {
+        let key = self.cache_key().await?;
+        if let Some(data) = cached(&self.raw_cache, &key)? {
+            if let Some(progress) = &self.progress {
+                progress.fetched(0);
+            }
+            return Ok(data);
+        }
+        let data = match self.fetched.take() {
+            Some(data) => data,
+            None => {
+                let (byte_start, n_bytes) = (key.byte_start, key.n_bytes);
+                timed!(
+                    self.profile,
+                    self.key,
+                    Fetch,
+                    self.reader.read_image_data(byte_start, n_bytes).await
+                )?
+            }
+        };
+        if let Some(progress) = &self.progress {
+            progress.fetched(data.len() as u64);
+        }
+        store(&self.raw_cache, key, data.clone())?;
+        Ok(data)
+    }
+
+    async fn decoded(&mut self) -> TiffResult<Bytes> {
+        let key = self.cache_key().await?;
+        if let Some(decoded) = cached(&self.decoded_cache, &key)? {
+            if let Some(progress) = &self.progress {
+                progress.fetched(0);
+                progress.done(0);
+            }
+            return Ok(decoded);
+        }
+        if let Some(decoded) = self.sparse().await? {
+            return Ok(decoded.into());
+        }
+        let raw = self.raw().await?;
+        let decoded = Bytes::from(self.decompress(raw).await?);
+        if let Some(progress) = &self.progress {
+            progress.done(0);
+        }
+        store(&self.decoded_cache, key, decoded.clone())?;
+        Ok(decoded)
+    }
+
+    /// Like [`ChunkRequest::decoded`], decoding chunks with the same
+    /// compressed payload as a chunk decoded before with `dedup` only once
+    async fn decoded_dedup(&mut self, dedup: &Dedup) -> TiffResult<Bytes> {
+        let key = self.cache_key().await?;
+        if let Some(decoded) = cached(&self.decoded_cache, &key)? {
+            if let Some(progress) = &self.progress {
+                progress.fetched(0);
+                progress.done(0);
+            }
+            return Ok(decoded);
+        }
+        if let Some(decoded) = self.sparse().await? {
+            return Ok(decoded.into());
+        }
+        let raw = self.raw().await?;
+        let plane = self
+            .chunk_meta
+            .chunk_rect(self.key.1)
+            .map_or(0, |(plane, _)| plane);
+        let decoded = dedup
+            .cell(plane, raw.clone())?
+            .get_or_try_init(|| async {
+                Ok::<_, TiffError>(Bytes::from(self.decompress(raw).await?))
+            })
+            .await?
+            .clone();
+        if let Some(progress) = &self.progress {
+            progress.done(0);
+        }
+        store(&self.decoded_cache, key, decoded.clone())?;
+        Ok(decoded)
+    }
+
+    /// Decompress a chunk, on the rayon pool if there is one
+    async fn decompress(&self, raw: Bytes) -> TiffResult<Vec<u8>> {
+        #[cfg(feature = "rayon")]
+        if let Some(pool) = &self.rayon_pool {
+            let (sender, receiver) = tokio::sync::oneshot::channel();
+            let chunk_meta = self.chunk_meta.clone();
+            let limits = self.limits.clone();
+            let key = self.key;
+            #[cfg(feature = "profiling")]
+            let profile = self.profile.clone();
+            pool.spawn(move || {
+                let data = timed!(
+                    profile,
+                    key,
+                    Decompress,
+                    decompress(raw, &chunk_meta, &limits)
+                )
+                .and_then(|data| {
+                    timed!(profile, key, Predictor, unpredict(data, key.1, &chunk_meta))
+                });
+                let _ = sender.send(data);
+            });
+            return receiver
+                .await
+                .map_err(|e| TiffError::from(io::Error::other(e)))?;
+        }
+        let data = timed!(
+            self.profile,
+            self.key,
+            Decompress,
+            decompress(raw, &self.chunk_meta, &self.limits)
+        )?;
+        timed!(
+            self.profile,
+            self.key,
+            Predictor,
+            unpredict(data, self.key.1, &self.chunk_meta)
+        )
+    }
+
+    /// Decoded chunk together with its placement
+    async fn decoded_chunk(&mut self) -> TiffResult<(ChunkIndex, DecodedChunk)> {
+        let i_chunk = self.key.1;
+        let (plane, rect) = self
+            .chunk_meta
+            .chunk_rect(i_chunk)
+            .ok_or(UsageError::InvalidChunkIndex(u32::try_from(i_chunk)?))?;
+        let data = self.decoded().await?;
+        Ok((
+            i_chunk,
+            DecodedChunk {
+                plane,
+                rect,
+                data: data.into(),
+            },
+        ))
+    }
+
+    /// Put the chunk in the caches, decoding it only if decoded chunks are
+    /// cached
+    async fn prefetch(&mut self) -> TiffResult<()> {
+        if self.decoded_cache.lock()?.capacity() > 0 {
+            self.decoded().await?;
+        } else if !self.is_sparse().await? {
+            let key = self.cache_key().await?;
+            if !self.raw_cache.lock()?.contains_key(&key) {
+                self.raw().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fetch the compressed chunks of `requests` that aren't cached and whose
+/// location is loaded with a single [`CogReader::read_vectored`], for the
+/// requests to pick up. A single chunk is left to be read as usual.
+async fn fetch_vectored(requests: &mut [ChunkRequest]) -> TiffResult<()> {
+    let mut ranges = Vec::new();
+    let mut fetching = Vec::new();
+    for (i, request) in requests.iter().enumerate() {
+        let i_chunk = request.key.1;
+        let (Ok(offset), Ok(n_bytes)) = (
+            request.image.chunk_offset(i_chunk),
+            request.image.chunk_bytes(i_chunk),
+        ) else {
+            continue;
+        };
+        if n_bytes > 0 && !request.is_cached(offset, n_bytes)? {
+            ranges.push(offset..offset.checked_add(n_bytes).ok_or(TiffError::IntSizeError)?);
+            fetching.push(i);
+        }
+    }
+    let [first, _, ..] = fetching[..] else {
+        return Ok(());
+    };
+    let request = &requests[first];
+    // timed as a fetch of the first chunk
+    let data = timed!(
+        request.profile,
+        request.key,
+        Fetch,
+        request.reader.read_vectored(&ranges).await
+    )?;
+    if data.len() != ranges.len() {
+        return Err(io::Error::other("vectored read returned the wrong number of ranges").into());
+    }
+    for (i, data) in fetching.into_iter().zip(data) {
+        requests[i].fetched = Some(data);
+    }
+    Ok(())
+}
+
+/// Decoded chunks of a region by compressed payload, so identical chunks
+/// stored in different places, such as uniform nodata tiles, are decoded once
+#[derive(Default)]
+struct Dedup {
+    /// (sample plane, payload, decoded chunk) by hash of the payload
+    chunks: Mutex<DedupChunks>,
+}
+
+type DedupChunks = HashMap<u64, Vec<(usize, Bytes, Arc<OnceCell<Bytes>>)>>;
+
+impl Dedup {
+    /// Where the decoded chunk of sample `plane` with payload `raw` goes
+    ///
+    /// The sample plane matters for planar images with subsampled chroma.
+    fn cell(&self, plane: usize, raw: Bytes) -> TiffResult<Arc<OnceCell<Bytes>>> {
+        let mut hasher = DefaultHasher::new();
+        raw.hash(&mut hasher);
+        let mut chunks = self.chunks.lock()?;
+        let same_hash = chunks.entry(hasher.finish()).or_default();
+        if let Some((.., cell)) = same_hash
+            .iter()
+            .find(|(p, payload, _)| *p == plane && *payload == raw)
+        {
+            return Ok(cell.clone());
+        }
+        let cell = Arc::new(OnceCell::new());
+        same_hash.push((plane, raw, cell.clone()));
+        Ok(cell)
+    }
+}
+
+/// Run `f` on every request in its own tokio task, at most `concurrency` at
+/// once, returning the results in the order of the requests.
+///
+/// Tasks that are still running are aborted when the returned future is
+/// dropped, or when one of them fails.
+async fn spawn_bounded<T, F, Fut>(
+    requests: Vec<ChunkRequest>,
+    concurrency: usize,
+    f: F,
+) -> TiffResult<Vec<T>>
+where
+    T: Send + 'static,
+    F: Fn(ChunkRequest) -> Fut,
+    Fut: Future<Output = TiffResult<T>> + Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let n_requests = requests.len();
+    // aborts its tasks when dropped
+    let mut tasks = JoinSet::new();
+    for (i, request) in requests.into_iter().enumerate() {
+        let semaphore = semaphore.clone();
+        let task = f(request);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            task.await.map(|result| (i, result))
+        });
+    }
+    let mut results: Vec<Option<T>> = (0..n_requests).map(|_| None).collect();
+    while let Some(task) = tasks.join_next().await {
+        let (i, result) = task.map_err(|e| TiffError::from(io::Error::other(e)))??;
+        results[i] = Some(result);
+    }
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// State of a [`CogDecoder::stream_chunks`] stream
+enum ChunkStream {
+    /// Not polled yet, so nothing was spawned
+    Pending(Vec<ChunkRequest>, usize),
+    Running(mpsc::Receiver<TiffResult<(ChunkIndex, DecodedChunk)>>),
+}
+
+/// Decode every request in its own tokio task, at most `concurrency` at once,
+/// sending chunks as they are done. Stops starting new tasks once the
+/// receiver is dropped.
+fn spawn_streamed(
+    requests: Vec<ChunkRequest>,
+    concurrency: usize,
+) -> mpsc::Receiver<TiffResult<(ChunkIndex, DecodedChunk)>> {
+    let (sender, receiver) = mpsc::channel(concurrency);
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    tokio::spawn(async move {
+        for mut request in requests {
+            let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                break;
+            };
+            if sender.is_closed() {
+                break;
+            }
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let chunk = request.decoded_chunk().await;
+                let _ = sender.send(chunk).await;
+                drop(permit);
+            });
+        }
+    });
+    receiver
+}
+
+/// What a region holds
+enum Output {
+    /// The samples, laid out like in the chunks
+    Stored,
+    /// The samples, interleaved even if the chunks are planar
+    Interleaved,
+    /// A combination of the samples of each pixel
+    BandMath(BandMath),
+}
+
+/// Output buffer of [`CogDecoder::decode_region`], that decoded chunks are
+/// copied into
+struct Region<'a> {
+    chunk_meta: &'a ChunkMetaData,
+    rect: Rect,
+    /// Bytes per pixel within a sample plane
+    pixel_bytes: usize,
+    /// Whether the samples of planar chunks are interleaved
+    interleave: bool,
+    /// Combination of the samples of each pixel to store instead of the
+    /// samples, and the type of the samples of the image
+    band_math: Option<(BandMath, SampleType)>,
+    data: Vec<u8>,
+    /// Where to record how long copying each chunk took, and the level the
+    /// chunks belong to
+    #[cfg(feature = "profiling")]
+    profile: Option<Arc<DecodeProfile>>,
+    #[cfg(feature = "profiling")]
+    level: OverviewLevel,
+}
+
+impl<'a> Region<'a> {
+    fn new(chunk_meta: &'a ChunkMetaData, rect: Rect, output: Output) -> TiffResult<Self> {
+        let (planes, pixel_bytes) = Self::layout(chunk_meta, &output)?;
+        let len = usize::try_from(rect.width)? * usize::try_from(rect.height)? * pixel_bytes;
+        let interleave = matches!(output, Output::Interleaved)
+            && chunk_meta.planar_config == PlanarConfiguration::Planar;
+        let band_math = match output {
+            Output::BandMath(math) => Some((math, Self::source_type(chunk_meta)?)),
+            _ => None,
+        };
+        Ok(Region {
+            chunk_meta,
+            rect,
+            pixel_bytes,
+            interleave,
+            band_math,
+            data: vec![0; len * planes],
+            #[cfg(feature = "profiling")]
+            profile: None,
+            #[cfg(feature = "profiling")]
+            level: 0,
+        })
+    }
+
+    /// Number of sample planes and bytes per pixel within a plane
+    fn layout(chunk_meta: &ChunkMetaData, output: &Output) -> TiffResult<(usize, usize)> {
+        let samples = usize::from(chunk_meta.samples);
+        let (planes, plane_samples) = match (output, chunk_meta.planar_config) {
+            (Output::BandMath(math), _) => {
+                Self::source_type(chunk_meta)?;
+                return Ok((1, math.sample_type().size()));
+            }
+            (Output::Stored, PlanarConfiguration::Planar) => (samples, 1),
+            _ => (1, samples),
+        };
+        Ok((
+            planes,
+            usize::from(chunk_meta.bits_per_sample / 8) * plane_samples,
+        ))
+    }
+
+    /// Type of the samples of the image, which band math needs to read them
+    fn source_type(chunk_meta: &ChunkMetaData) -> TiffResult<SampleType> {
+        SampleType::from_format(chunk_meta.sample_format, chunk_meta.bits_per_sample).ok_or_else(
+            || TiffUnsupportedError::UnsupportedSampleFormat(vec![chunk_meta.sample_format]).into(),
+        )
+    }
+
+    /// Copy decoded chunks into the region, or combine their samples if there
+    /// is band math
+    fn copy_chunks(&mut self, chunks: Vec<(ChunkIndex, Bytes)>) -> TiffResult<()> {
+        let Some((math, source)) = self.band_math.clone() else {
+            for (i_chunk, chunk) in chunks {
+                timed!(
+                    self.profile,
+                    (self.level, i_chunk),
+                    Layout,
+                    self.copy_chunk(i_chunk, &chunk)
+                )?;
+            }
+            return Ok(());
+        };
+        // the sample planes of a pixel are in chunks covering the same rect
+        let mut groups = BTreeMap::<(u32, u32), (Rect, Vec<(usize, ChunkIndex, Bytes)>)>::new();
+        for (i_chunk, chunk) in chunks {
+            let (plane, rect) = self
+                .chunk_meta
+                .chunk_rect(i_chunk)
+                .ok_or(UsageError::InvalidChunkIndex(u32::try_from(i_chunk)?))?;
+            groups
+                .entry((rect.y, rect.x))
+                .or_insert_with(|| (rect, Vec::new()))
+                .1
+                .push((plane, i_chunk, chunk));
+        }
+        for (rect, mut planes) in groups.into_values() {
+            planes.sort_by_key(|(plane, ..)| *plane);
+            // timed as a layout of the chunk of the first plane
+            timed!(
+                self.profile,
+                (
+                    self.level,
+                    planes.first().map_or(0, |(_, i_chunk, _)| *i_chunk)
+                ),
+                Layout,
+                self.combine_chunk(&math, source, rect, &planes)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Evaluate band math for the pixels of a chunk that lie within the
+    /// region, given the decoded chunk of each of its sample planes
+    fn combine_chunk(
+        &mut self,
+        math: &BandMath,
+        source: SampleType,
+        chunk_rect: Rect,
+        planes: &[(usize, ChunkIndex, Bytes)],
+    ) -> TiffResult<()> {
+        let Some(chunk_width) = self.chunk_meta.chunk_width() else {
+            return Err(TiffUnsupportedError::UnsupportedDataType.into());
+        };
+        let Some(overlap) = chunk_rect.intersection(&self.rect) else {
+            return Ok(());
+        };
+        let size = source.size();
+        let samples = usize::from(self.chunk_meta.samples);
+        // (chunk, offset within a pixel, bytes per pixel) of each band
+        let bands: Vec<(&[u8], usize, usize)> = match self.chunk_meta.planar_config {
+            PlanarConfiguration::Chunky => match planes {
+                [(_, _, chunk)] => (0..samples)
+                    .map(|band| (&chunk[..], band * size, samples * size))
+                    .collect(),
+                _ => return Err(TiffUnsupportedError::UnsupportedDataType.into()),
+            },
+            PlanarConfiguration::Planar => planes
+                .iter()
+                .map(|(_, _, chunk)| (&chunk[..], 0, size))
+                .collect(),
+        };
+        if bands.len() != samples {
+            return Err(TiffFormatError::InconsistentStripSamples {
+                actual_samples: bands.len(),
+                required_samples: samples,
+            }
+            .into());
+        }
+        let mut pixel = vec![0.0; samples];
+        let mut row = Vec::with_capacity(overlap.width as usize * self.pixel_bytes);
+        for y in overlap.y..overlap.y + overlap.height {
+            row.clear();
+            let first = (y - chunk_rect.y) as usize * chunk_width as usize
+                + (overlap.x - chunk_rect.x) as usize;
+            for i in first..first + overlap.width as usize {
+                for (value, &(chunk, offset, pixel_len)) in pixel.iter_mut().zip(&bands) {
+                    let start = i * pixel_len + offset;
+                    let sample = chunk.get(start..start + size).ok_or(
+                        TiffFormatError::UnexpectedCompressedData {
+                            actual_bytes: chunk.len(),
+                            required_bytes: start + size,
+                        },
+                    )?;
+                    *value = source.read_f64(sample);
+                }
+                math.sample_type().write_f64(math.eval(&pixel), &mut row);
+            }
+            let dst = ((y - self.rect.y) as usize * self.rect.width as usize
+                + (overlap.x - self.rect.x) as usize)
+                * self.pixel_bytes;
+            self.data[dst..dst + row.len()].copy_from_slice(&row);
+        }
+        Ok(())
+    }
+
+    /// Copy the part of a decoded chunk that lies within the region, leaving
+    /// out padding
+    fn copy_chunk(&mut self, i_chunk: usize, chunk: &[u8]) -> TiffResult<()> {
+        let (Some((plane, chunk_rect)), Some(chunk_width)) = (
+            self.chunk_meta.chunk_rect(i_chunk),
+            self.chunk_meta.chunk_width(),
+        ) else {
+            return Err(UsageError::InvalidChunkIndex(u32::try_from(i_chunk)?).into());
+        };
+        let Some(overlap) = chunk_rect.intersection(&self.rect) else {
+            return Ok(());
+        };
+        let px = self.pixel_bytes;
+        // bytes per pixel of the chunk, and where its plane starts
+        let (chunk_px, plane_start) = if self.interleave {
+            let sample_bytes = px / usize::from(self.chunk_meta.samples);
+            (sample_bytes, plane * sample_bytes)
+        } else {
+            (
+                px,
+                plane * self.rect.width as usize * self.rect.height as usize * px,
+            )
+        };
+        let row_len = overlap.width as usize * chunk_px;
+        for y in overlap.y..overlap.y + overlap.height {
+            let start = ((y - chunk_rect.y) as usize * chunk_width as usize
+                + (overlap.x - chunk_rect.x) as usize)
+                * chunk_px;
+            let dst = plane_start
+                + ((y - self.rect.y) as usize * self.rect.width as usize
+                    + (overlap.x - self.rect.x) as usize)
+                    * px;
+            let src = chunk.get(start..start + row_len).ok_or(
+                TiffFormatError::UnexpectedCompressedData {
+                    actual_bytes: chunk.len(),
+                    required_bytes: start + row_len,
+                },
+            )?;
+            if chunk_px == px {
+                self.data[dst..dst + row_len].copy_from_slice(src);
+            } else {
+                // one sample of each pixel
+                let dst = self.data[dst..].chunks_mut(px);
+                for (pixel, sample) in dst.zip(src.chunks_exact(chunk_px)) {
+                    pixel[..chunk_px].copy_from_slice(sample);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copy the bits of a decoded chunk of a 1-bit image that lie within `rect`
+/// into `bitmap`, which holds that window
+fn copy_mask_chunk(
+    bitmap: &mut PackedBitmap,
+    chunk_meta: &ChunkMetaData,
+    rect: Rect,
+    i_chunk: usize,
+    chunk: &[u8],
+) -> TiffResult<()> {
+    let (Some((_, chunk_rect)), Some(chunk_width)) =
+        (chunk_meta.chunk_rect(i_chunk), chunk_meta.chunk_width())
+    else {
+        return Err(UsageError::InvalidChunkIndex(u32::try_from(i_chunk)?).into());
+    };
+    let Some(overlap) = chunk_rect.intersection(&rect) else {
+        return Ok(());
+    };
+    let chunk_stride = PackedBitmap::stride_for(chunk_width as usize);
+    let required_bytes = (overlap.y + overlap.height - chunk_rect.y) as usize * chunk_stride;
+    for y in overlap.y..overlap.y + overlap.height {
+        let src_row = (y - chunk_rect.y) as usize * chunk_stride;
+        let dst_row = (y - rect.y) as usize * bitmap.stride;
+        for x in overlap.x..overlap.x + overlap.width {
+            let src_x = (x - chunk_rect.x) as usize;
+            let byte = chunk.get(src_row + src_x / 8).ok_or(
+                TiffFormatError::UnexpectedCompressedData {
+                    actual_bytes: chunk.len(),
+                    required_bytes,
+                },
+            )?;
+            if byte & (0x80 >> (src_x % 8)) != 0 {
+                let dst_x = (x - rect.x) as usize;
+                bitmap.data[dst_row + dst_x / 8] |= 0x80 >> (dst_x % 8);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Cached chunk, sharing the data of the cache entry
+fn cached(cache: &Mutex<ChunkCache>, key: &CacheKey) -> TiffResult<Option<Bytes>> {
+    Ok(cache.lock()?.get(key).cloned())
+}
+
+/// Put `data` in the cache, if it fits at all
+fn store(cache: &Mutex<ChunkCache>, key: CacheKey, data: Bytes) -> TiffResult<()> {
+    let mut cache = cache.lock()?;
+    if data.len() <= cache.capacity() {
+        cache.insert(key, data);
+    }
+    Ok(())
+}
+
+impl Image {
+    /// Read and decode a chunk, laid out according to `opts`.
+    ///
+    /// Everything needed is copied or `Arc`-cloned up front, so the returned
+    /// future doesn't borrow the image and can be awaited concurrently with
+    /// other chunks. Chunks larger than [`Limits::max_chunk_bytes`] are
+    /// rejected before they are read, sparse chunks aren't read at all.
+    pub fn decode_chunk(
+        &self,
+        reader: Arc<dyn CogReader>,
+        i_chunk: usize,
+        opts: ChunkOpts,
+        limits: &Limits,
+    ) -> TiffResult<impl Future<Output = TiffResult<Vec<u8>>> + Send + 'static> {
+        let chunk_offset = self.chunk_offset(i_chunk)?;
+        let chunk_bytes = self.chunk_bytes(i_chunk)?;
+        Limits::check(chunk_bytes, limits.max_chunk_bytes)?;
+        let chunk_meta = self.chunk_meta();
+        let limits = limits.clone();
+        Ok(async move {
+            // don't mention `self` in here, see [stackoverflow](https://stackoverflow.com/a/77845970/14681457)
+            let data = match chunk_bytes {
+                0 => sparse_chunk_data(&chunk_meta, &limits)?,
+                _ => {
+                    let data = reader.read_image_data(chunk_offset, chunk_bytes).await?;
+                    decode_chunk_data(data.into(), i_chunk, &chunk_meta, &limits)?
+                }
+            };
+            opts.apply(data, &chunk_meta)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_trait::async_trait;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use crate::{
+        decoder::{ObservedReader, ReadKind, ReadMetrics, WithSourceId},
+        encoder::{
+            directory::{entry, EncodedDirectory},
+            photometric::PhotometricPolicy,
+            test_cog, CogEncoder, CogLayout, Level,
+        },
+        error::TiffError,
+        nodata::Nodata,
+        progress::Progress,
+        structs::{
+            tags::{
+                CompressionMethod, PhotometricInterpretation, PlanarConfiguration, Predictor,
+                SampleFormat,
+            },
+            BufferedEntry, ChunkMetaData, Ifd, IfdEntry, MaybePartial, StripDecodeState, Tag,
+            TagType, TileAttributes,
+        },
+        test_util::{jpeg_frame, jpeg_tables, FixtureIfd, TiffBuilder},
+        ByteOrder, ChunkType, ColorType,
+    };
+
+    /// 1x1 pixel, 16-bit big-endian image with a single uncompressed chunk at
+    /// `offset`
+    fn image(offset: u8) -> Image {
+        Image {
+            ifd: Ifd::default(),
+            chunk_meta: Arc::new(ChunkMetaData {
+                byte_order: ByteOrder::BigEndian,
+                image_width: 1,
+                image_height: 1,
+                bits_per_sample: 16,
+                samples: 1,
+                sample_format: SampleFormat::Uint,
+                photometric_interpretation: PhotometricInterpretation::BlackIsZero,
+                compression_method: CompressionMethod::None,
+                predictor: Predictor::None,
+                jpeg_tables: None,
+                planar_config: PlanarConfiguration::Chunky,
+                chunk_type: ChunkType::Strip,
+                strip_decoder: None,
+                tile_attributes: None,
+                ycbcr_subsampling: (1, 1),
+                nodata: None,
+            }),
+            chunk_offsets: bytes(vec![offset]),
+            chunk_bytes: bytes(vec![2]),
+        }
+    }
+
+    /// Chunk offsets or byte counts of type BYTE
+    fn bytes(data: Vec<u8>) -> MaybePartial {
+        MaybePartial::Whole(BufferedEntry {
+            tag_type: TagType::BYTE,
+            count: data.len() as u64,
+            data,
+        })
+    }
+
+    fn decoder() -> CogDecoder {
+        let (tiff, _) = Tiff::from_header(b"MM\0*\0\0\0\0").unwrap();
+        let mut decoder = CogDecoder::new(
+            Arc::new(vec![0u8, 1, 0, 2]),
+            tiff,
+            &DecoderOptions::default(),
+        );
+        decoder.insert_image(0, image(0));
+        decoder.insert_image(5, image(2));
+        decoder
+    }
+
+    #[tokio::test]
+    async fn test_concurrency() {
+        let decoder = decoder();
+        // get a chunk from the highest resolution image
+        let chunk_1 = decoder.get_chunk(0, 0).unwrap();
+        // get a chunk from a lower resolution image
+        let chunk_2 = decoder.get_chunk(0, 5).unwrap();
+        // the futures outlive the decoder
+        drop(decoder);
+        let data = (chunk_1.await.unwrap(), chunk_2.await.unwrap());
+        assert_eq!(data.0, 1u16.to_ne_bytes());
+        assert_eq!(data.1, 2u16.to_ne_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_recover() {
+        let mut decoder = decoder();
+        decoder.images.remove(&5);
+        let chunk_1 = decoder.get_chunk(0, 0).unwrap();
+        let Err(TiffError::UsageError(UsageError::OverviewNotLoaded(level))) =
+            decoder.get_chunk(0, 5)
+        else {
+            panic!("level 5 should not be loaded");
+        };
+        decoder.insert_image(level, image(2));
+        let chunk_2 = decoder.get_chunk(0, 5).unwrap();
+        assert_eq!(
+            (chunk_1.await.unwrap(), chunk_2.await.unwrap()),
+            (1u16.to_ne_bytes().to_vec(), 2u16.to_ne_bytes().to_vec())
+        );
+    }
+
+    async fn open(cog: Vec<u8>, options: DecoderOptions) -> (CogDecoder, Arc<ReadMetrics>) {
+        let metrics = Arc::new(ReadMetrics::default());
+        let reader = Arc::new(ObservedReader::new(cog, metrics.clone()));
+        (CogDecoder::open(reader, options).await.unwrap(), metrics)
+    }
+
+    fn cog() -> Vec<u8> {
+        georeferenced_cog(EncodedDirectory::new())
+    }
+
+    /// RGB COG with two overviews, writing `extra_tags` on every level
+    fn georeferenced_cog(extra_tags: EncodedDirectory) -> Vec<u8> {
+        test_cog([64, 32, 16].map(|size| {
+            let tiles = vec![vec![0u8; 16 * 16 * 3]; (size as usize / 16).pow(2)];
+            Level {
+                extra_tags: extra_tags.clone(),
+                ..Level::tiled(size, size, 16, ColorType::RGB(8), tiles)
+            }
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_geotransform() {
+        let mut extra_tags = EncodedDirectory::new();
+        extra_tags.insert(
+            Tag::ModelTiepointTag,
+            entry(&[0.0f64, 0.0, 0.0, 5.0, 60.0, 0.0][..]),
+        );
+        extra_tags.insert(Tag::ModelPixelScaleTag, entry(&[0.1f64, 0.1, 0.0][..]));
+        let (decoder, _) = open(
+            georeferenced_cog(extra_tags),
+            DecoderOptions {
+                // geo tags don't come with the prefetched header
+                header_prefetch: 0,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(
+            decoder.geotransform(0).unwrap(),
+            Some([5.0, 0.1, 0.0, 60.0, 0.0, -0.1])
+        );
+        // overviews don't carry geo tags, but inherit them
+        assert!(!decoder.tiff().ifds[2].contains_key(&Tag::ModelTiepointTag));
+        assert_eq!(
+            decoder.geotransform(2).unwrap(),
+            Some([5.0, 0.4, 0.0, 60.0, 0.0, -0.4])
+        );
+        assert!(decoder.geotransform(3).is_err());
+
+        let (decoder, _) = open(cog(), DecoderOptions::default()).await;
+        assert_eq!(decoder.geotransform(1).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_header_prefetch() {
+        let (decoder, metrics) = open(cog(), DecoderOptions::default()).await;
+        // everything is in the first 16 KiB
+        assert_eq!(metrics.total().requests, 1);
+        assert_eq!(decoder.tiff().ifds.len(), 3);
+        let tile_offsets = decoder.tiff().ifds[0]
+            .require_tag_value(&Tag::TileOffsets)
+            .unwrap();
+        assert_eq!(tile_offsets.count, 16);
+
+        let (small, metrics) = open(
+            cog(),
+            DecoderOptions {
+                header_prefetch: 16,
+                ..Default::default()
+            },
+        )
+        .await;
+        // the header, then the entry count and entries of each IFD, except for
+        // the first count which directly follows the header
+        assert_eq!(metrics.get(ReadKind::Ifd).requests, 1 + 2 * 3 - 1);
+        // BitsPerSample, SampleFormat, TileOffsets and TileByteCounts follow
+        // each IFD, so they take a single read per IFD
+        assert_eq!(metrics.get(ReadKind::TagData).requests, 3);
+        assert_eq!(small.tiff().ifds, decoder.tiff().ifds);
+    }
+
+    #[tokio::test]
+    async fn test_image_from_ifd() {
+        // 2x2 tiles of 16-bit samples holding the tile index
+        let tiles = (0..4u16).map(|i| i.to_ne_bytes().repeat(16 * 16)).collect();
+        let level = Level::tiled(32, 32, 16, ColorType::Gray(16), tiles);
+        let reader: Arc<dyn CogReader> = Arc::new(test_cog([level]));
+        let mut tiff = Tiff::read(&*reader, &DecoderOptions::default())
+            .await
+            .unwrap();
+        let image = Image::from_ifd(tiff.ifds.remove(0), ByteOrder::LittleEndian).unwrap();
+        let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+        decoder.insert_image(0, image);
+        // the 2x2 pixels around the center
+        let region = decoder
+            .decode_region(0, 15, 15, 2, 2)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(
+            region,
+            [0u16, 1, 2, 3]
+                .iter()
+                .flat_map(|i| i.to_ne_bytes())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// Decoder for the first IFD of a fixture
+    async fn fixture_decoder(file: Vec<u8>) -> TiffResult<CogDecoder> {
+        let reader: Arc<dyn CogReader> = Arc::new(file);
+        let mut tiff = Tiff::read(&*reader, &DecoderOptions::default()).await?;
+        let image = Image::from_ifd(tiff.ifds.remove(0), ByteOrder::LittleEndian)?;
+        let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+        decoder.insert_image(0, image);
+        Ok(decoder)
+    }
+
+    /// 20x12 YCbCr with 2x2 subsampling in 2 planar 16x16 tiles, the Cb and Cr
+    /// tiles of `chroma_bytes` bytes each
+    fn planar_ycbcr(chroma_bytes: u32) -> Vec<u8> {
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        let mut offsets = Vec::new();
+        for plane in 0..3u32 {
+            for tile_x in 0..2u32 {
+                let data: Vec<u8> = if plane == 0 {
+                    (0..16 * 16)
+                        .map(|i| (tile_x * 16 + i % 16 + i / 16 * 20) as u8)
+                        .collect()
+                } else {
+                    // one sample per 2x2 pixels, so a tile of 8x8 samples
+                    (0..8 * 8)
+                        .map(|i| (plane * 100 + tile_x * 8 + i % 8 + i / 8 * 10) as u8)
+                        .collect()
+                };
+                offsets.push(u32::try_from(builder.push_data(&data)).unwrap());
+            }
+        }
+        let bytes = [
+            256,
+            256,
+            chroma_bytes,
+            chroma_bytes,
+            chroma_bytes,
+            chroma_bytes,
+        ];
+        builder.push_ifd(
+            FixtureIfd::new()
+                .entry(Tag::ImageWidth, &20u32)
+                .entry(Tag::ImageLength, &12u32)
+                .entry(Tag::BitsPerSample, &[8u16, 8, 8][..])
+                .entry(Tag::PhotometricInterpretation, &6u16)
+                .entry(Tag::SamplesPerPixel, &3u16)
+                .entry(Tag::PlanarConfiguration, &2u16)
+                .entry(Tag::TileWidth, &16u32)
+                .entry(Tag::TileLength, &16u32)
+                .entry(Tag::TileOffsets, &offsets[..])
+                .entry(Tag::TileByteCounts, &bytes[..])
+                .entry(Tag::YCbCrSubSampling, &[2u16, 2][..]),
+        );
+        builder.build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_planar() {
+        // 20x12 RGB in 2 tiles of 16x16 per plane, sample p of pixel (x, y)
+        // holding p * 100 + x + y * 20
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        let mut offsets = Vec::new();
+        for plane in 0..3u32 {
+            for tile_x in 0..2u32 {
+                let data: Vec<u8> = (0..16 * 16)
+                    .map(|i| (plane * 100 + tile_x * 16 + i % 16 + i / 16 * 20) as u8)
+                    .collect();
+                offsets.push(u32::try_from(builder.push_data(&data)).unwrap());
+            }
+        }
+        builder.push_ifd(
+            FixtureIfd::new()
+                .entry(Tag::ImageWidth, &20u32)
+                .entry(Tag::ImageLength, &12u32)
+                .entry(Tag::BitsPerSample, &[8u16, 8, 8][..])
+                .entry(Tag::PhotometricInterpretation, &2u16)
+                .entry(Tag::SamplesPerPixel, &3u16)
+                .entry(Tag::PlanarConfiguration, &2u16)
+                .entry(Tag::TileWidth, &16u32)
+                .entry(Tag::TileLength, &16u32)
+                .entry(Tag::TileOffsets, &offsets[..])
+                .entry(Tag::TileByteCounts, &[256u32; 6][..]),
+        );
+        let decoder = fixture_decoder(builder.build().unwrap()).await.unwrap();
+        let sample = |plane: usize, x: usize, y: usize| (plane * 100 + x + y * 20) as u8;
+        let band = |plane| -> Vec<u8> {
+            (3..10)
+                .flat_map(|y| (13..18).map(move |x| sample(plane, x, y)))
+                .collect()
+        };
+
+        // a region crossing the tile boundary
+        let planes = decoder
+            .decode_region(0, 13, 3, 5, 7)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(planes, [band(0), band(1), band(2)].concat());
+        let interleaved = decoder
+            .decode_region_interleaved(0, 13, 3, 5, 7)
+            .unwrap()
+            .await
+            .unwrap();
+        let expected: Vec<u8> = (3..10)
+            .flat_map(|y| (13..18).flat_map(move |x| (0..3).map(move |p| sample(p, x, y))))
+            .collect();
+        assert_eq!(interleaved, expected);
+        let bands = decoder
+            .decode_region_bands(0, 13, 3, 5, 7)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(bands, [band(0), band(1), band(2)]);
+
+        // chunky images are split into bands too, and interleaved as stored
+        let rgb: Vec<u8> = (0..16 * 16 * 3u16)
+            .flat_map(|s| (s % 3 * 1000 + s / 3).to_ne_bytes())
+            .collect();
+        let level = Level::tiled(16, 16, 16, ColorType::RGB(16), vec![rgb]);
+        let decoder = fixture_decoder(test_cog([level])).await.unwrap();
+        let bands = decoder
+            .decode_region_bands(0, 1, 0, 2, 1)
+            .unwrap()
+            .await
+            .unwrap();
+        let expected = |band: u16| -> Vec<u8> {
+            [band * 1000 + 1, band * 1000 + 2]
+                .iter()
+                .flat_map(|v| v.to_ne_bytes())
+                .collect()
+        };
+        assert_eq!(bands, [expected(0), expected(1), expected(2)]);
+        assert_eq!(
+            decoder
+                .decode_region_interleaved(0, 1, 0, 2, 1)
+                .unwrap()
+                .await
+                .unwrap(),
+            decoder.decode_region(0, 1, 0, 2, 1).unwrap().await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_planar_subsampled() {
+        let decoder = fixture_decoder(planar_ycbcr(64)).await.unwrap();
+        let meta = decoder.images[&0].chunk_meta();
+        assert_eq!(meta.ycbcr_subsampling, (2, 2));
+        assert_eq!(meta.chunk_len(), Some(256));
+        assert_eq!(meta.stored_chunk_len(0), Some(256));
+        assert_eq!(meta.stored_chunk_len(2), Some(64));
+
+        // a region crossing the tile boundary, on odd coordinates
+        let region = decoder
+            .decode_region(0, 13, 3, 5, 7)
+            .unwrap()
+            .await
+            .unwrap();
+        let mut expected = Vec::new();
+        for plane in 0..3usize {
+            for y in 3..10usize {
+                for x in 13..18usize {
+                    expected.push(match plane {
+                        0 => (x + y * 20) as u8,
+                        _ => (plane * 100 + x / 2 + y / 2 * 10) as u8,
+                    });
+                }
+            }
+        }
+        assert_eq!(region, expected);
+
+        // converted to RGB after being interleaved
+        let rgb = decoder
+            .decode_region_rgb(0, 13, 3, 5, 7)
+            .unwrap()
+            .await
+            .unwrap();
+        let mut expected = decoder
+            .decode_region_interleaved(0, 13, 3, 5, 7)
+            .unwrap()
+            .await
+            .unwrap();
+        YCbCrConversion::default().to_rgb(&mut expected);
+        assert_eq!(rgb, expected);
+        assert_eq!(rgb[..3], [196, 14, 52]);
+
+        // a chroma tile is upsampled to a full one
+        let chunk = decoder.get_chunk(5, 0).unwrap().await.unwrap();
+        assert_eq!(chunk.len(), 256);
+        assert_eq!(chunk[..4], [208, 208, 209, 209]);
+        assert_eq!(chunk[16..18], [208, 208]);
+        assert_eq!(chunk[32], 218);
+
+        // too short chroma tiles fail instead of panicking
+        let decoder = fixture_decoder(planar_ycbcr(10)).await.unwrap();
+        let Err(TiffError::FormatError(TiffFormatError::UnexpectedCompressedData { .. })) =
+            decoder.decode_region(0, 0, 0, 20, 12).unwrap().await
+        else {
+            panic!("short chroma tiles should be rejected");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_chunky_subsampled() {
+        // 5x3 YCbCr with 2x1 subsampling in strips of 2 rows: 3 blocks of
+        // Y Y Cb Cr per row, the last one covering a pixel of padding
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        let block_row = |y: u8| -> Vec<u8> {
+            (0..3u8)
+                .flat_map(|b| [10 * y + 2 * b, 10 * y + 2 * b + 1, 100 + b, 200 + y])
+                .collect()
+        };
+        let strips = [[block_row(0), block_row(1)].concat(), block_row(2)];
+        let offsets: Vec<u32> = strips
+            .iter()
+            .map(|strip| u32::try_from(builder.push_data(strip)).unwrap())
+            .collect();
+        builder.push_ifd(
+            FixtureIfd::new()
+                .entry(Tag::ImageWidth, &5u32)
+                .entry(Tag::ImageLength, &3u32)
+                .entry(Tag::BitsPerSample, &[8u16, 8, 8][..])
+                .entry(Tag::PhotometricInterpretation, &6u16)
+                .entry(Tag::SamplesPerPixel, &3u16)
+                .entry(Tag::RowsPerStrip, &2u32)
+                .entry(Tag::StripOffsets, &offsets[..])
+                .entry(Tag::StripByteCounts, &[24u32, 12][..])
+                .entry(Tag::YCbCrSubSampling, &[2u16, 1][..]),
+        );
+        let decoder = fixture_decoder(builder.build().unwrap()).await.unwrap();
+        let meta = decoder.images[&0].chunk_meta();
+        assert_eq!(meta.stored_chunk_len(0), Some(24));
+        assert_eq!(meta.chunk_len(), Some(30));
+
+        let region = decoder.decode_region(0, 0, 0, 5, 3).unwrap().await.unwrap();
+        let mut expected = Vec::new();
+        for y in 0..3u8 {
+            for x in 0..5u8 {
+                expected.extend_from_slice(&[10 * y + x, 100 + x / 2, 200 + y]);
+            }
+        }
+        assert_eq!(region, expected);
+
+        let rgb = decoder
+            .decode_region_rgb(0, 0, 0, 5, 3)
+            .unwrap()
+            .await
+            .unwrap();
+        YCbCrConversion::default().to_rgb(&mut expected);
+        assert_eq!(rgb, expected);
+    }
+
+    #[tokio::test]
+    async fn test_decode_oriented() {
+        // 32x16 in 2 tiles, pixel (x, y) holding x + y * 32, rotated 90°
+        // clockwise for display
+        let tile = |i: u16| -> Vec<u8> {
+            (0..16 * 16u16)
+                .flat_map(|p| (i * 16 + p % 16 + p / 16 * 32).to_ne_bytes())
+                .collect()
+        };
+        let mut extra_tags = EncodedDirectory::new();
+        extra_tags.insert(Tag::Orientation, entry(&6u16));
+        let level = Level {
+            extra_tags,
+            ..Level::tiled(32, 16, 16, ColorType::Gray(16), vec![tile(0), tile(1)])
+        };
+        let file = test_cog([level]);
+        let decoder = fixture_decoder(file.clone()).await.unwrap();
+        assert_eq!(
+            decoder.image(0).unwrap().orientation().unwrap(),
+            Orientation::RightTop
+        );
+
+        // a displayed window crossing the stored tile boundary
+        let region = decoder
+            .decode_region_oriented(0, 3, 10, 9, 14)
+            .unwrap()
+            .await
+            .unwrap();
+        let expected: Vec<u8> = (10..24u16)
+            .flat_map(|y| (3..12u16).map(move |x| y + (15 - x) * 32))
+            .flat_map(u16::to_ne_bytes)
+            .collect();
+        assert_eq!(region, expected);
+        // regions are as stored unless the decoder applies the orientation
+        let stored = decoder.decode_region_interleaved(0, 0, 0, 32, 16).unwrap();
+        let expected_stored: Vec<u8> = (0..32 * 16u16).flat_map(u16::to_ne_bytes).collect();
+        assert_eq!(stored.await.unwrap(), expected_stored);
+        let reader: Arc<dyn CogReader> = Arc::new(file);
+        let options = DecoderOptions {
+            apply_orientation: true,
+            ..Default::default()
+        };
+        let mut tiff = Tiff::read(&*reader, &options).await.unwrap();
+        let image = Image::from_ifd(tiff.ifds.remove(0), ByteOrder::LittleEndian).unwrap();
+        let mut oriented = CogDecoder::new(reader, tiff, &options);
+        oriented.insert_image(0, image);
+        let region = oriented.decode_region_interleaved(0, 3, 10, 9, 14).unwrap();
+        assert_eq!(region.await.unwrap(), expected);
+
+        // bounds are those of the displayed image
+        assert!(decoder.decode_region_oriented(0, 0, 0, 16, 32).is_ok());
+        let Err(TiffError::UsageError(UsageError::RegionOutOfBounds(_))) =
+            decoder.decode_region_oriented(0, 0, 0, 32, 16)
+        else {
+            panic!("the displayed image is 16 pixels wide");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_palette() {
+        // 4x2 indices into a palette of color i being (i, 255 - i, 7) * 257
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        let indices = [0u8, 1, 2, 3, 255, 254, 253, 252];
+        let offset = u32::try_from(builder.push_data(&indices)).unwrap();
+        let map: Vec<u16> = (0..256u16)
+            .chain((0..256).map(|i| 255 - i))
+            .chain([7; 256])
+            .map(|v| v * 257)
+            .collect();
+        builder.push_ifd(
+            FixtureIfd::new()
+                .entry(Tag::ImageWidth, &4u32)
+                .entry(Tag::ImageLength, &2u32)
+                .entry(Tag::BitsPerSample, &8u16)
+                .entry(Tag::PhotometricInterpretation, &3u16)
+                .entry(Tag::StripOffsets, &offset)
+                .entry(Tag::StripByteCounts, &8u32)
+                .entry(Tag::ColorMap, &map[..]),
+        );
+        let decoder = fixture_decoder(builder.build().unwrap()).await.unwrap();
+        let palette = decoder.palette(0).unwrap();
+        assert_eq!(palette.colors().len(), 256);
+        assert_eq!(palette.colors()[2], [2 * 257, 253 * 257, 7 * 257]);
+
+        // indices are kept by default, and expanded to RGB on request
+        let region = decoder.decode_region(0, 1, 0, 2, 2).unwrap().await.unwrap();
+        assert_eq!(region, [1, 2, 254, 253]);
+        let rgb = decoder
+            .decode_region_rgb(0, 1, 0, 2, 2)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(rgb, [1, 254, 7, 2, 253, 7, 254, 1, 7, 253, 2, 7]);
+
+        // other images have no palette
+        let decoder = fixture_decoder(cog()).await.unwrap();
+        let Err(TiffError::FormatError(TiffFormatError::InvalidTagValueType(320))) =
+            decoder.palette(0)
+        else {
+            panic!("RGB has no palette");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_decode_rgb() {
+        // RGB written as YCbCr without subsampling, with the reference black
+        // and white that the conversion needs
+        let pixels: Vec<u8> = (0..16 * 16u32)
+            .flat_map(|p| [p as u8, (p * 7) as u8, 255 - p as u8])
+            .collect();
+        let level = Level::tiled(16, 16, 16, ColorType::RGB(8), vec![pixels.clone()]);
+        let file = CogEncoder::new(Vec::new(), CogLayout::HeaderFirst)
+            .unwrap()
+            .with_photometric(PhotometricPolicy::YCbCr)
+            .encode_levels([level.clone()]);
+        let decoder = fixture_decoder(file).await.unwrap();
+        let meta = decoder.images[&0].chunk_meta();
+        assert_eq!(
+            meta.photometric_interpretation,
+            PhotometricInterpretation::YCbCr
+        );
+        let rgb = decoder
+            .decode_region_rgb(0, 0, 0, 16, 16)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(rgb.len(), pixels.len());
+        assert!(rgb.iter().zip(&pixels).all(|(a, b)| a.abs_diff(*b) <= 1));
+
+        // RGB is passed through
+        let decoder = fixture_decoder(test_cog([level.clone()])).await.unwrap();
+        let rgb = decoder
+            .decode_region_rgb(0, 2, 3, 4, 5)
+            .unwrap()
+            .await
+            .unwrap();
+        let expected = decoder.decode_region(0, 2, 3, 4, 5).unwrap().await.unwrap();
+        assert_eq!(rgb, expected);
+
+        // gray has no RGB to convert to
+        let gray = Level::gray(16, 16, 16, |_, _| 0);
+        let decoder = fixture_decoder(test_cog([gray])).await.unwrap();
+        let Err(TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedInterpretation(
+            PhotometricInterpretation::BlackIsZero,
+        ))) = decoder.decode_region_rgb(0, 0, 0, 1, 1)
+        else {
+            panic!("gray can't be decoded to RGB");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_decode_lab() {
+        // white, black and a* and b* of -1 and 1 at L* 50, with signed a* and b*
+        let tile = [255, 0, 0, 0, 0, 0, 128, 0xff, 1]
+            .into_iter()
+            .chain(core::iter::repeat(0))
+            .take(16 * 16 * 3)
+            .collect();
+        let level = Level::tiled(3, 1, 16, ColorType::Lab(8), vec![tile]);
+        let decoder = fixture_decoder(test_cog([level])).await.unwrap();
+        assert_eq!(decoder.images[&0].color_type().unwrap(), ColorType::Lab(8));
+        let lab = decoder
+            .decode_region_lab(0, 0, 0, 3, 1)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(lab[..6], [100.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(lab[7..], [-1.0, 1.0]);
+        let rgb = decoder
+            .decode_region_rgb(0, 0, 0, 3, 1)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(rgb[..6], [255, 255, 255, 0, 0, 0]);
+        assert!(rgb[6..].iter().all(|s| s.abs_diff(119) <= 3));
+
+        // other images hold no L*a*b*
+        let decoder = fixture_decoder(cog()).await.unwrap();
+        let Err(TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedInterpretation(
+            PhotometricInterpretation::RGB,
+        ))) = decoder.decode_region_lab(0, 0, 0, 1, 1)
+        else {
+            panic!("RGB isn't L*a*b*");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_old_jpeg() {
+        // 8x24 gray in strips of 8 rows, a gray block of 144 each: two with
+        // only the data of a scan, the last a stream of its own
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        let gray = [(1, 0x11)];
+        let scan = [0x60, 0x1f];
+        let mut header = jpeg_tables();
+        header.truncate(header.len() - 2);
+        header.extend_from_slice(&jpeg_frame(8, &gray, &scan)[2..]);
+        let header_offset = u32::try_from(builder.push_data(&header)).unwrap();
+        let strips = [scan.to_vec(), scan.to_vec(), jpeg_frame(8, &gray, &scan)];
+        let offsets: Vec<u32> = strips
+            .iter()
+            .map(|strip| u32::try_from(builder.push_data(strip)).unwrap())
+            .collect();
+        let bytes: Vec<u32> = strips.iter().map(|s| s.len() as u32).collect();
+        builder.push_ifd(
+            FixtureIfd::new()
+                .entry(Tag::ImageWidth, &8u32)
+                .entry(Tag::ImageLength, &24u32)
+                .entry(Tag::BitsPerSample, &8u16)
+                .entry(Tag::Compression, &6u16)
+                .entry(Tag::PhotometricInterpretation, &1u16)
+                .entry(Tag::RowsPerStrip, &8u32)
+                .entry(Tag::StripOffsets, &offsets[..])
+                .entry(Tag::StripByteCounts, &bytes[..])
+                .entry(Tag::JPEGInterchangeFormat, &header_offset)
+                .entry(Tag::JPEGInterchangeFormatLength, &(header.len() as u32)),
+        );
+        let decoder = fixture_decoder(builder.build().unwrap()).await.unwrap();
+        // the header, up to the scan
+        let tables = decoder.images[&0].chunk_meta().jpeg_tables.clone().unwrap();
+        assert_eq!(tables.data.len(), header.len() - 2);
+        assert_eq!(tables.data[tables.data.len() - 2..], [0xff, 0xd9]);
+
+        let region = decoder
+            .decode_region(0, 0, 0, 8, 24)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(region, [144; 8 * 24]);
+
+        let embedded = decoder.extract_embedded_jpeg(0).unwrap().await.unwrap();
+        assert_eq!(embedded, header);
+        let Err(TiffError::UsageError(UsageError::OverviewNotLoaded(1))) =
+            decoder.extract_embedded_jpeg(1)
+        else {
+            panic!("there is no overview");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_invalid_subsampling() {
+        for subsampling in [[3u16, 1], [1, 2], [0, 0]] {
+            let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+            builder.push_ifd(
+                FixtureIfd::new()
+                    .entry(Tag::ImageWidth, &4u32)
+                    .entry(Tag::ImageLength, &4u32)
+                    .entry(Tag::BitsPerSample, &[8u16, 8, 8][..])
+                    .entry(Tag::PhotometricInterpretation, &6u16)
+                    .entry(Tag::SamplesPerPixel, &3u16)
+                    .entry(Tag::StripOffsets, &8u32)
+                    .entry(Tag::StripByteCounts, &24u32)
+                    .entry(Tag::YCbCrSubSampling, &subsampling[..]),
+            );
+            let file = builder.build().unwrap();
+            let Err(TiffError::FormatError(TiffFormatError::InvalidTagValueType(530))) =
+                fixture_decoder(file).await
+            else {
+                panic!("{subsampling:?} should be rejected");
+            };
+        }
+    }
+
+    #[tokio::test]
+    async fn test_band_math() {
+        // 2 chunky 16x16 tiles of RGB, R holding x, G y and B the tile index
+        let tile = |i: u16| -> Vec<u8> {
+            (0..16 * 16u16)
+                .flat_map(|p| [i * 16 + p % 16, p / 16, i])
+                .flat_map(u16::to_ne_bytes)
+                .collect()
+        };
+        let level = Level::tiled(32, 16, 16, ColorType::RGB(16), vec![tile(0), tile(1)]);
+        let decoder = fixture_decoder(test_cog([level])).await.unwrap();
+        let diff = BandMath::new(SampleType::I16, |s| s[1] - s[0] + s[2]);
+        let region = decoder
+            .decode_band_math(0, 14, 3, 4, 2, diff)
+            .unwrap()
+            .await
+            .unwrap();
+        let expected: Vec<u8> = [-11i16, -12, -12, -13, -10, -11, -11, -12]
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect();
+        assert_eq!(region, expected);
+
+        // planar, NDVI of Cr and Cb
+        let decoder = fixture_decoder(planar_ycbcr(64)).await.unwrap();
+        let region = decoder
+            .decode_band_math(0, 15, 4, 2, 1, BandMath::normalized_difference(2, 1))
+            .unwrap()
+            .await
+            .unwrap();
+        let ndvi = |x: f32| (100.0) / (300.0 + 2.0 * (x + 20.0));
+        let expected: Vec<u8> = [ndvi(7.0), ndvi(8.0)]
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect();
+        assert_eq!(region, expected);
+
+        // the samples need a type
+        let level = Level {
+            sample_format: SampleFormat::Void,
+            ..Level::gray(16, 16, 16, |_, _| 0)
+        };
+        let decoder = fixture_decoder(test_cog([level])).await.unwrap();
+        assert!(decoder
+            .decode_band_math(0, 0, 0, 1, 1, BandMath::new(SampleType::U8, |s| s[0]))
+            .is_err());
+    }
+
+    #[cfg(feature = "profiling")]
+    #[tokio::test]
+    async fn test_profile() {
+        use crate::decoder::{DecodeProfile, Stage};
+
+        let profile = Arc::new(DecodeProfile::default());
+        let options = DecoderOptions {
+            profile: Some(profile.clone()),
+            ..Default::default()
+        };
+        let reader: Arc<dyn CogReader> = Arc::new(cog());
+        let mut tiff = Tiff::read(&*reader, &options).await.unwrap();
+        let image = Image::from_ifd(tiff.ifds.remove(0), ByteOrder::LittleEndian).unwrap();
+        let mut decoder = CogDecoder::new(reader, tiff, &options);
+        decoder.insert_image(0, image);
+
+        decoder
+            .decode_region(0, 8, 8, 16, 16)
+            .unwrap()
+            .await
+            .unwrap();
+        // the tiles are fetched at once, timed as a fetch of the first, and
+        // they are all equal, so only one of them is decoded
+        let (mut fetched, mut decoded) = (0, 0);
+        for i_chunk in [0, 1, 4, 5] {
+            let stages: Vec<_> = profile.chunk(0, i_chunk).iter().map(|t| t.stage).collect();
+            assert!(stages.contains(&Stage::Layout), "{i_chunk}: {stages:?}");
+            fetched += usize::from(stages.contains(&Stage::Fetch));
+            if stages.contains(&Stage::Decompress) {
+                assert!(stages.contains(&Stage::Predictor));
+                decoded += 1;
+            }
+        }
+        assert_eq!((fetched, decoded), (1, 1));
+        assert_eq!(profile.timings().len(), 4 + 1 + 2);
+
+        // the compressed chunk is cached, so it isn't fetched again
+        profile.clear();
+        decoder
+            .get_chunk_with(0, 0, ChunkOpts::default())
+            .unwrap()
+            .await
+            .unwrap();
+        let stages: Vec<_> = profile.chunk(0, 0).iter().map(|t| t.stage).collect();
+        assert_eq!(stages, [Stage::Decompress, Stage::Predictor, Stage::Layout]);
+        assert_eq!(profile.folded().lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_paged_offsets() {
+        let options = DecoderOptions {
+            header_prefetch: 0,
+            max_eager_offsets_bytes: 0,
+            ..Default::default()
+        };
+        let metrics = Arc::new(ReadMetrics::default());
+        let reader: Arc<dyn CogReader> = Arc::new(ObservedReader::new(cog(), metrics.clone()));
+        let mut tiff = Tiff::read(&*reader, &options).await.unwrap();
+        let ifd = tiff.ifds.remove(0);
+        assert!(matches!(
+            ifd.get_tag(&Tag::TileOffsets),
+            Some(IfdEntry::Offset { .. })
+        ));
+        let image = Image::from_ifd(ifd, ByteOrder::LittleEndian).unwrap();
+        assert!(matches!(image.chunk_offsets, MaybePartial::Partial(_)));
+        let mut decoder = CogDecoder::new(reader, tiff, &options);
+        decoder.insert_image(0, image);
+
+        let tag_reads = metrics.get(ReadKind::TagData).requests;
+        let chunk = decoder.get_chunk(5, 0).unwrap().await.unwrap();
+        assert_eq!(chunk.len(), 16 * 16 * 3);
+        // one page of offsets and one of byte counts
+        assert_eq!(metrics.get(ReadKind::TagData).requests, tag_reads + 2);
+        decoder.get_chunk(6, 0).unwrap().await.unwrap();
+        assert_eq!(metrics.get(ReadKind::TagData).requests, tag_reads + 2);
+        assert_eq!(
+            decoder.image(0).unwrap().chunk_offset(15).unwrap(),
+            decoder.image(0).unwrap().chunk_offset(14).unwrap() + 16 * 16 * 3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunk_with_mask() {
+        let ghost = "MASK_INTERLEAVED_WITH_IMAGERY=YES\nBLOCK_LEADER=SIZE_AS_UINT4\nBLOCK_TRAILER=LAST_4_BYTES_REPEATED\n";
+        let header = format!(
+            "MM\0*\0\0\0\0GDAL_STRUCTURAL_METADATA_SIZE={:06} bytes\n{ghost}",
+            ghost.len()
+        );
+        // image chunk, its trailer, the mask chunk's leader and the mask chunk
+        let data = vec![0u8, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0x80];
+        let mask = || {
+            let mut mask = image(10);
+            let mut mask_meta = (*mask.chunk_meta).clone();
+            mask_meta.bits_per_sample = 1;
+            mask_meta.photometric_interpretation = PhotometricInterpretation::TransparencyMask;
+            mask.chunk_meta = Arc::new(mask_meta);
+            mask.chunk_bytes = bytes(vec![1]);
+            mask
+        };
+
+        for (header, requests) in [(header.as_bytes(), 1), (b"MM\0*\0\0\0\0", 2)] {
+            let metrics = Arc::new(ReadMetrics::default());
+            let reader = Arc::new(ObservedReader::new(data.clone(), metrics.clone()));
+            let (tiff, _) = Tiff::from_header(header).unwrap();
+            let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+            decoder.insert_image(0, image(0));
+            let Err(TiffError::UsageError(UsageError::MaskNotLoaded(0))) =
+                decoder.read_chunk_with_mask(0, 0)
+            else {
+                panic!("the mask wasn't added yet");
+            };
+            decoder.insert_mask(0, mask());
+            let (chunk, mask) = decoder.read_chunk_with_mask(0, 0).unwrap().await.unwrap();
+            assert_eq!((chunk, mask), (1u16.to_ne_bytes().to_vec(), vec![0x80]));
+            assert_eq!(metrics.get(ReadKind::ImageData).requests, requests);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raw_cache() {
+        for (raw_cache_size, requests) in [(16, 1), (0, 3)] {
+            let metrics = Arc::new(ReadMetrics::default());
+            let reader = Arc::new(ObservedReader::new(vec![0u8, 1, 0, 2], metrics.clone()));
+            let (tiff, _) = Tiff::from_header(b"MM\0*\0\0\0\0").unwrap();
+            let options = DecoderOptions {
+                raw_cache_size,
+                ..Default::default()
+            };
+            let mut decoder = CogDecoder::new(reader, tiff, &options);
+            decoder.insert_image(0, image(0));
+            for _ in 0..3 {
+                let chunk = decoder.get_chunk(0, 0).unwrap().await.unwrap();
+                assert_eq!(chunk, 1u16.to_ne_bytes());
+            }
+            assert_eq!(metrics.get(ReadKind::ImageData).requests, requests);
+            // replacing the image drops its cached chunks
+            decoder.insert_image(0, image(2));
+            let chunk = decoder.get_chunk(0, 0).unwrap().await.unwrap();
+            assert_eq!(chunk, 2u16.to_ne_bytes());
+            assert_eq!(metrics.get(ReadKind::ImageData).requests, requests + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decoded_cache() {
+        let metrics = Arc::new(ReadMetrics::default());
+        let reader = Arc::new(ObservedReader::new(vec![0u8, 1, 0, 2], metrics.clone()));
+        let (tiff, _) = Tiff::from_header(b"MM\0*\0\0\0\0").unwrap();
+        let options = DecoderOptions {
+            raw_cache_size: 0,
+            decoded_cache_size: 16,
+            ..Default::default()
+        };
+        let mut decoder = CogDecoder::new(reader, tiff, &options);
+        decoder.insert_image(0, image(0));
+        let first = decoder.get_chunk(0, 0).unwrap().await.unwrap();
+        let second = decoder.get_chunk(0, 0).unwrap().await.unwrap();
+        assert_eq!(
+            (first, second),
+            (1u16.to_ne_bytes().to_vec(), 1u16.to_ne_bytes().to_vec())
+        );
+        assert_eq!(metrics.get(ReadKind::ImageData).requests, 1);
+        assert_eq!(decoder.decoded_cache.lock().unwrap().size(), 2);
+        assert!(decoder.raw_cache.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shared_cache() {
+        let open = |reader: Arc<dyn CogReader>| {
+            let (tiff, _) = Tiff::from_header(b"MM\0*\0\0\0\0").unwrap();
+            let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+            decoder.insert_image(0, image(0));
+            decoder
+        };
+        // chunks at the same offsets of different files
+        let a = open(Arc::new(vec![0u8, 1]));
+        let mut b = open(Arc::new(vec![0u8, 2]));
+        b.share_caches(&a);
+        for _ in 0..2 {
+            assert_eq!(
+                a.get_chunk(0, 0).unwrap().await.unwrap(),
+                1u16.to_ne_bytes()
+            );
+            assert_eq!(
+                b.get_chunk(0, 0).unwrap().await.unwrap(),
+                2u16.to_ne_bytes()
+            );
+        }
+        assert_eq!(a.raw_cache.lock().unwrap().len(), 2);
+
+        // decoders of the same file with different images at the same level
+        // get the chunks of their own image
+        let source = SourceKey::Custom("ab".into());
+        let reader: Arc<dyn CogReader> = Arc::new(WithSourceId::new(vec![0u8, 1, 0, 2], source));
+        let a = open(reader.clone());
+        let mut b = open(reader);
+        b.insert_image(0, image(2));
+        b.share_caches(&a);
+        for _ in 0..2 {
+            assert_eq!(
+                a.get_chunk(0, 0).unwrap().await.unwrap(),
+                1u16.to_ne_bytes()
+            );
+            assert_eq!(
+                b.get_chunk(0, 0).unwrap().await.unwrap(),
+                2u16.to_ne_bytes()
+            );
+        }
+        // the same image at another level is served from the cache
+        b.insert_image(3, image(0));
+        b.get_chunk(0, 3).unwrap().await.unwrap();
+        assert_eq!(a.raw_cache.lock().unwrap().len(), 2);
+
+        // readers of the same source share chunks, so a file that claims to
+        // be another gets its chunks
+        let source = SourceKey::Custom("a".into());
+        let a = open(Arc::new(WithSourceId::new(vec![0u8, 1], source.clone())));
+        let mut b = open(Arc::new(WithSourceId::new(vec![0u8, 2], source)));
+        b.share_caches(&a);
+        assert_eq!(
+            a.get_chunk(0, 0).unwrap().await.unwrap(),
+            1u16.to_ne_bytes()
+        );
+        assert_eq!(
+            b.get_chunk(0, 0).unwrap().await.unwrap(),
+            1u16.to_ne_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_raw_cache_shares_data() {
+        let decoder = decoder();
+        let first = decoder.chunk_request(0, 0).unwrap().raw().await.unwrap();
+        let second = decoder.chunk_request(0, 0).unwrap().raw().await.unwrap();
+        assert_eq!(first.as_ptr(), second.as_ptr());
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_region() {
+        let metrics = Arc::new(ReadMetrics::default());
+        let data: Vec<u8> = (0..8).collect();
+        let reader = Arc::new(ObservedReader::new(data, metrics.clone()));
+        let (tiff, _) = Tiff::from_header(b"MM\0*\0\0\0\0").unwrap();
+        let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+        // 2x2 image of 1x1 tiles
+        let mut img = image(0);
+        let mut chunk_meta = (*img.chunk_meta).clone();
+        chunk_meta.chunk_type = ChunkType::Tile;
+        chunk_meta.tile_attributes = Some(TileAttributes {
+            image_width: 2,
+            image_height: 2,
+            tile_width: 1,
+            tile_length: 1,
+        });
+        img.chunk_meta = Arc::new(chunk_meta);
+        img.chunk_offsets = bytes(vec![0, 2, 4, 6]);
+        img.chunk_bytes = bytes(vec![2; 4]);
+        decoder.insert_image(0, img);
+
+        // the right column
+        let prefetch = decoder.prefetch_region(0, Rect::new(1, 0, 1, 2)).unwrap();
+        prefetch.await.unwrap();
+        // in a single vectored read
+        assert_eq!(metrics.get(ReadKind::ImageData).requests, 1);
+        for i_chunk in [1, 3] {
+            let chunk = decoder.get_chunk(i_chunk, 0).unwrap().await.unwrap();
+            assert_eq!(
+                chunk,
+                u16::from_be_bytes([2 * i_chunk as u8, 2 * i_chunk as u8 + 1]).to_ne_bytes()
+            );
+        }
+        assert_eq!(metrics.get(ReadKind::ImageData).requests, 1);
+        decoder.get_chunk(0, 0).unwrap().await.unwrap();
+        assert_eq!(metrics.get(ReadKind::ImageData).requests, 2);
+    }
+
+    /// 3x3 8-bit image with pixel values 0..9, in 2x2 tiles padded with 0xff
+    /// or in strips of 2 rows
+    fn region_decoder(chunk_type: ChunkType) -> CogDecoder {
+        let (data, offsets, bytes) = match chunk_type {
+            ChunkType::Tile => (
+                vec![
+                    0, 1, 3, 4, 2, 0xff, 5, 0xff, 6, 7, 0xff, 0xff, 8, 0xff, 0xff, 0xff,
+                ],
+                vec![0, 4, 8, 12],
+                vec![4; 4],
+            ),
+            ChunkType::Strip => ((0..9).collect(), vec![0, 6], vec![6, 3]),
+        };
+        let (tiff, _) = Tiff::from_header(b"MM\0*\0\0\0\0").unwrap();
+        let mut decoder = CogDecoder::new(Arc::new(data), tiff, &DecoderOptions::default());
+        let mut img = image(0);
+        let mut chunk_meta = (*img.chunk_meta).clone();
+        chunk_meta.image_width = 3;
+        chunk_meta.image_height = 3;
+        chunk_meta.bits_per_sample = 8;
+        chunk_meta.chunk_type = chunk_type;
+        match chunk_type {
+            ChunkType::Tile => {
+                chunk_meta.tile_attributes = Some(TileAttributes {
+                    image_width: 3,
+                    image_height: 3,
+                    tile_width: 2,
+                    tile_length: 2,
+                })
+            }
+            ChunkType::Strip => {
+                chunk_meta.strip_decoder = Some(StripDecodeState { rows_per_strip: 2 })
+            }
+        }
+        img.chunk_meta = Arc::new(chunk_meta);
+        img.chunk_offsets = self::bytes(offsets);
+        img.chunk_bytes = self::bytes(bytes);
+        decoder.insert_image(0, img);
+        decoder
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        fn assert_shareable<T: Send + Sync + 'static>() {}
+        assert_shareable::<CogDecoder>();
+        assert_shareable::<Arc<dyn CogReader>>();
+        let reader: Arc<dyn CogReader> = Arc::new(cog());
+        let options = DecoderOptions::default();
+        let read = Tiff::read(&*reader, &options);
+        assert_send(&read);
+        let mut tiff = read.await.unwrap();
+        let image = Image::from_ifd(tiff.ifds.remove(0), ByteOrder::LittleEndian).unwrap();
+        let load = image.chunk_offsets.load_u64(0, &*reader);
+        assert_send(&load);
+        load.await.unwrap();
+        let open = CogDecoder::open(reader.clone(), options.clone());
+        assert_send(&open);
+        // e.g. opened and used within a handler spawned by a web server
+        let task = tokio::spawn(async move {
+            let mut decoder = CogDecoder::open(reader, options).await?;
+            decoder.insert_image(0, image);
+            decoder.decode_region(0, 0, 0, 1, 1)?.await
+        });
+        assert_eq!(task.await.unwrap().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_decode_region() {
+        for chunk_type in [ChunkType::Tile, ChunkType::Strip] {
+            let decoder = region_decoder(chunk_type);
+            let region = decoder.decode_region(0, 0, 0, 3, 3).unwrap();
+            assert_eq!(region.await.unwrap(), (0..9).collect::<Vec<u8>>());
+            let region = decoder.decode_region(0, 1, 1, 2, 2).unwrap();
+            assert_eq!(region.await.unwrap(), [4, 5, 7, 8]);
+            let region = decoder.decode_region(0, 2, 0, 1, 3).unwrap();
+            assert_eq!(region.await.unwrap(), [2, 5, 8]);
+            let Err(TiffError::UsageError(UsageError::RegionOutOfBounds(_))) =
+                decoder.decode_region(0, 2, 2, 2, 1)
+            else {
+                panic!("the region should be out of bounds");
+            };
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        // tiles 0 and 3 are stored in one place, and tiles 1 and 2 are equal
+        // but stored in two places
+        let mut decoder = region_decoder(ChunkType::Tile);
+        let metrics = Arc::new(ReadMetrics::default());
+        let data = vec![7, 7, 7, 7, 1, 2, 3, 4, 1, 2, 3, 4];
+        decoder.reader = Arc::new(ObservedReader::new(data, metrics.clone()));
+        let mut img = Arc::try_unwrap(decoder.images.remove(&0).unwrap()).unwrap();
+        img.chunk_offsets = self::bytes(vec![0, 4, 8, 0]);
+        decoder.insert_image(0, img);
+        let region = decoder.decode_region(0, 0, 0, 3, 3).unwrap();
+        assert_eq!(region.await.unwrap(), [7, 7, 1, 7, 7, 3, 1, 2, 7]);
+        // the 3 places, in a single vectored read
+        let stats = metrics.get(ReadKind::ImageData);
+        assert_eq!((stats.requests, stats.bytes), (1, 12));
+
+        let dedup = Dedup::default();
+        let cell = dedup.cell(0, Bytes::from_static(&[1, 2])).unwrap();
+        assert!(Arc::ptr_eq(
+            &cell,
+            &dedup.cell(0, Bytes::from_static(&[1, 2])).unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            &cell,
+            &dedup.cell(1, Bytes::from_static(&[1, 2])).unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            &cell,
+            &dedup.cell(0, Bytes::from_static(&[2, 1])).unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sparse() {
+        // tiles 1 and 2 are sparse, as GDAL writes them
+        let mut decoder = region_decoder(ChunkType::Tile);
+        let metrics = Arc::new(ReadMetrics::default());
+        let data = vec![0, 1, 3, 4, 8, 0xff, 0xff, 0xff];
+        decoder.reader = Arc::new(ObservedReader::new(data, metrics.clone()));
+        let mut img = Arc::try_unwrap(decoder.images.remove(&0).unwrap()).unwrap();
+        img.chunk_offsets = self::bytes(vec![0, 0, 0, 4]);
+        img.chunk_bytes = self::bytes(vec![4, 0, 0, 4]);
+        decoder.insert_image(0, img);
+        decoder
+            .prefetch_region(0, Rect::new(0, 0, 3, 3))
+            .unwrap()
+            .await
+            .unwrap();
+        let region = decoder.decode_region(0, 0, 0, 3, 3).unwrap();
+        assert_eq!(region.await.unwrap(), [0, 1, 0, 3, 4, 0, 0, 0, 8]);
+        assert_eq!(decoder.get_chunk(2, 0).unwrap().await.unwrap(), [0; 4]);
+        // only the stored tiles, in a single vectored read
+        let stats = metrics.get(ReadKind::ImageData);
+        assert_eq!((stats.requests, stats.bytes), (1, 8));
+
+        // filled with nodata if there is any
+        let mut img = Arc::try_unwrap(decoder.images.remove(&0).unwrap()).unwrap();
+        let mut chunk_meta = (*img.chunk_meta).clone();
+        chunk_meta.nodata = Some(Nodata::new(9.0));
+        img.chunk_meta = Arc::new(chunk_meta);
+        decoder.insert_image(0, img);
+        let region = decoder.decode_region(0, 0, 0, 3, 3).unwrap();
+        assert_eq!(region.await.unwrap(), [0, 1, 9, 3, 4, 9, 9, 9, 8]);
+    }
+
+    #[tokio::test]
+    async fn test_compression_methods() {
+        let mut decoder = region_decoder(ChunkType::Tile);
+        decoder.compression_methods = Some(vec![CompressionMethod::Deflate]);
+        let Err(TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedCompressionMethod(
+            CompressionMethod::None,
+        ))) = decoder.get_chunk(0, 0)
+        else {
+            panic!("uncompressed chunks should be refused");
+        };
+        assert!(decoder.decode_region(0, 0, 0, 3, 3).is_err());
+        decoder.compression_methods = Some(vec![CompressionMethod::None]);
+        assert!(decoder.get_chunk(0, 0).unwrap().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_region_limits() {
+        let mut decoder = region_decoder(ChunkType::Tile);
+        decoder.limits.max_total_bytes = 8;
+        let Err(TiffError::LimitsExceeded) = decoder.decode_region(0, 0, 0, 3, 3) else {
+            panic!("9 bytes should exceed the limits");
+        };
+        let region = decoder.decode_region(0, 1, 1, 2, 2).unwrap();
+        assert_eq!(region.await.unwrap(), [4, 5, 7, 8]);
+        // chunks are rejected before they are fetched
+        decoder.limits.max_chunk_bytes = 3;
+        let Err(TiffError::LimitsExceeded) = decoder.decode_region(0, 0, 0, 1, 1) else {
+            panic!("4 byte chunks should exceed the limits");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_decode_window() {
+        let decoder = &region_decoder(ChunkType::Tile);
+        let window = |x, y, width, height, edge| async move {
+            let window = decoder.decode_window(0, x, y, width, height, edge)?;
+            window.await
+        };
+        assert_eq!(
+            window(1, 1, 2, 2, EdgePolicy::Error).await.unwrap(),
+            [4, 5, 7, 8]
+        );
+        let Err(TiffError::UsageError(UsageError::WindowOutOfBounds { x: -1, .. })) =
+            window(-1, 0, 2, 2, EdgePolicy::Error).await
+        else {
+            panic!("the window should be out of bounds");
+        };
+        // straddling the bottom right corner
+        assert_eq!(
+            window(2, 1, 2, 3, EdgePolicy::Fill(300.0)).await.unwrap(),
+            [5, 255, 8, 255, 255, 255]
+        );
+        assert_eq!(
+            window(2, 1, 2, 3, EdgePolicy::Clamp).await.unwrap(),
+            [5, 5, 8, 8, 8, 8]
+        );
+        // beside the image
+        assert_eq!(
+            window(-5, 1, 2, 1, EdgePolicy::Fill(7.0)).await.unwrap(),
+            [7, 7]
+        );
+        assert_eq!(
+            window(-5, -5, 2, 1, EdgePolicy::Clamp).await.unwrap(),
+            [0, 0]
+        );
+        // around the whole image
+        assert_eq!(
+            window(-1, -1, 5, 5, EdgePolicy::Clamp).await.unwrap(),
+            [0, 0, 1, 2, 2, 0, 0, 1, 2, 2, 3, 3, 4, 5, 5, 6, 6, 7, 8, 8, 6, 6, 7, 8, 8]
+        );
+    }
+
+    /// Counts reads that are in flight, which never finish
+    struct Stalled(Arc<AtomicUsize>);
+
+    struct InFlight(Arc<AtomicUsize>);
+
+    impl Drop for InFlight {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl CogReader for Stalled {
+        async fn read_ifd(&self, _: u64, _: u64) -> TiffResult<Bytes> {
+            Err(io::Error::other("not used").into())
+        }
+
+        async fn read_tag_data(&self, _: u64, _: u64) -> TiffResult<Bytes> {
+            Err(io::Error::other("not used").into())
+        }
+
+        async fn read_image_data(&self, _: u64, _: u64) -> TiffResult<Bytes> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            let _in_flight = InFlight(self.0.clone());
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancellation() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let mut decoder = region_decoder(ChunkType::Tile);
+        decoder.reader = Arc::new(Stalled(in_flight.clone()));
+        let region = decoder.decode_region(0, 0, 0, 3, 3).unwrap();
+        let prefetch = decoder.prefetch_region(0, Rect::new(0, 0, 1, 1)).unwrap();
+        let timeout = Duration::from_millis(20);
+        // the client went away while all reads were in flight
+        assert!(tokio::time::timeout(timeout, region).await.is_err());
+        assert!(tokio::time::timeout(timeout, prefetch).await.is_err());
+        for _ in 0..100 {
+            if in_flight.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(in_flight.load(Ordering::SeqCst), 0);
+    }
+
+    /// Keeps the last progress report
+    #[derive(Default)]
+    struct LastProgress(Mutex<Progress>);
+
+    impl ProgressObserver for LastProgress {
+        fn on_progress(&self, progress: &Progress) {
+            *self.0.lock().unwrap() = *progress;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_region_progress() {
+        let decoder = region_decoder(ChunkType::Tile);
+        let observer = Arc::new(LastProgress::default());
+        let region = decoder
+            .decode_region_with_progress(0, 0, 0, 3, 3, observer.clone())
+            .unwrap();
+        region.await.unwrap();
+        let expected = Progress {
+            chunks_total: 4,
+            chunks_fetched: 4,
+            chunks_done: 4,
+            bytes: 16,
+            level: 0,
+        };
+        assert_eq!(*observer.0.lock().unwrap(), expected);
+        // cached chunks aren't read again
+        let observer = Arc::new(LastProgress::default());
+        let region = decoder
+            .decode_region_with_progress(0, 1, 1, 2, 2, observer.clone())
+            .unwrap();
+        region.await.unwrap();
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            Progress {
+                bytes: 0,
+                ..expected
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunk_opts() {
+        let decoder = decoder();
+        let opts = ChunkOpts {
+            row_stride: Some(4),
+            sample_type: Some(SampleType::U8),
+            ..Default::default()
+        };
+        let chunk = decoder.get_chunk_with(0, 5, opts.clone()).unwrap();
+        assert_eq!(chunk.await.unwrap(), [2, 0, 0, 0]);
+        let img = decoder.image(0).unwrap();
+        let chunk = img
+            .decode_chunk(decoder.reader.clone(), 0, opts.clone(), &Limits::default())
+            .unwrap();
+        assert_eq!(chunk.await.unwrap(), [1, 0, 0, 0]);
+        // a sparse chunk past the end of the file isn't read
+        let mut sparse = image(16);
+        let mut chunk_meta = (*sparse.chunk_meta).clone();
+        chunk_meta.chunk_type = ChunkType::Tile;
+        chunk_meta.tile_attributes = Some(TileAttributes {
+            image_width: 1,
+            image_height: 1,
+            tile_width: 1,
+            tile_length: 1,
+        });
+        sparse.chunk_meta = Arc::new(chunk_meta);
+        sparse.chunk_bytes = bytes(vec![0]);
+        let chunk = sparse
+            .decode_chunk(decoder.reader.clone(), 0, opts, &Limits::default())
+            .unwrap();
+        assert_eq!(chunk.await.unwrap(), [0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_chunks() {
+        use futures_lite::StreamExt;
+
+        let decoder = region_decoder(ChunkType::Tile);
+        let stream = decoder.stream_chunks(0, 1..4).unwrap();
+        drop(decoder);
+        let mut chunks = stream.try_collect::<_, _, Vec<_>>().await.unwrap();
+        chunks.sort_by_key(|(i_chunk, _)| *i_chunk);
+        let expected = [
+            (1, Rect::new(2, 0, 1, 2), vec![2, 0xff, 5, 0xff]),
+            (2, Rect::new(0, 2, 2, 1), vec![6, 7, 0xff, 0xff]),
+            (3, Rect::new(2, 2, 1, 1), vec![8, 0xff, 0xff, 0xff]),
+        ];
+        assert_eq!(chunks.len(), expected.len());
+        for ((i_chunk, chunk), (i, rect, data)) in chunks.into_iter().zip(expected) {
+            assert_eq!(i_chunk, i);
+            assert_eq!(
+                chunk,
+                DecodedChunk {
+                    plane: 0,
+                    rect,
+                    data
+                }
+            );
+        }
+
+        let decoder = region_decoder(ChunkType::Strip);
+        assert!(decoder.stream_chunks(0, 1..3).is_err());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[tokio::test]
+    async fn test_rayon_pool() {
+        let (tiff, _) = Tiff::from_header(b"MM\0*\0\0\0\0").unwrap();
+        let options = DecoderOptions {
+            rayon_pool: Some(Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(2)
+                    .build()
+                    .unwrap(),
+            )),
+            ..Default::default()
+        };
+        let mut decoder = CogDecoder::new(Arc::new(vec![0u8, 1, 0, 2]), tiff, &options);
+        decoder.insert_image(0, image(0));
+        decoder.insert_image(1, image(2));
+        let chunks = (
+            decoder.get_chunk(0, 0).unwrap().await.unwrap(),
+            decoder.get_chunk(0, 1).unwrap().await.unwrap(),
+        );
+        assert_eq!(
+            chunks,
+            (1u16.to_ne_bytes().to_vec(), 2u16.to_ne_bytes().to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spawn() {
+        let decoder = decoder();
+        let chunk = tokio::spawn(decoder.get_chunk(0, 5).unwrap());
+        assert!(decoder.get_chunk(1, 0).is_err());
+        assert_eq!(chunk.await.unwrap().unwrap(), 2u16.to_ne_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_region_with_mask() {
+        let level = |size, color_type, tiles| Level::tiled(size, size, 16, color_type, tiles);
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::HeaderFirst).unwrap();
+        // every other nibble of the right tiles is masked
+        let mask = [0xff, 0xf0, 0xff, 0xf0]
+            .map(|v| vec![v; 16 * 16 / 8])
+            .to_vec();
+        encoder
+            .write_level_with_mask(
+                level(32, ColorType::Gray(8), vec![vec![1; 16 * 16]; 4]),
+                level(32, ColorType::Gray(1), mask),
+                false,
+            )
+            .unwrap();
+        encoder
+            .write_level_with_mask(
+                level(16, ColorType::Gray(8), vec![vec![2; 16 * 16]]),
+                level(16, ColorType::Gray(1), vec![vec![0; 16 * 16 / 8]]),
+                true,
+            )
+            .unwrap();
+        let reader: Arc<dyn CogReader> = Arc::new(encoder.finish().unwrap());
+        let mut tiff = Tiff::read(&*reader, &DecoderOptions::default())
+            .await
+            .unwrap();
+        let ifds = std::mem::take(&mut tiff.ifds);
+        assert!(ifds[1].is_transparency_mask().unwrap());
+        let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+        assert_eq!(decoder.insert_levels(ifds).unwrap(), 2);
+
+        // a window across both tiles
+        let region = decoder.decode_region_with_mask(0, 10, 3, 16, 2).unwrap();
+        let (data, mask) = region.await.unwrap();
+        assert_eq!(data, vec![1; 16 * 2]);
+        assert_eq!((mask.width, mask.height, mask.stride), (16, 2, 2));
+        assert_eq!(mask.data, [0xff, 0b1100_0011, 0xff, 0b1100_0011]);
+        let region = decoder.decode_region_with_mask(1, 0, 0, 16, 16).unwrap();
+        let (data, mask) = region.await.unwrap();
+        assert_eq!(data, vec![2; 16 * 16]);
+        assert_eq!(mask.data, [0; 2 * 16]);
+        // mask chunks don't end up in the caches of the level
+        assert_eq!(
+            decoder.get_chunk(1, 0).unwrap().await.unwrap(),
+            vec![1; 16 * 16]
+        );
+
+        assert!(decoder.decode_region_with_mask(0, 30, 0, 4, 1).is_err());
+        decoder.masks.clear();
+        let Err(TiffError::UsageError(UsageError::MaskNotLoaded(1))) =
+            decoder.decode_region_with_mask(1, 0, 0, 1, 1)
+        else {
+            panic!("the mask wasn't added");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_select_level() {
+        let load = |cog: Vec<u8>| async move {
+            let reader: Arc<dyn CogReader> = Arc::new(cog);
+            let mut tiff = Tiff::read(&*reader, &DecoderOptions::default())
+                .await
+                .unwrap();
+            let ifds = std::mem::take(&mut tiff.ifds);
+            let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+            decoder.insert_levels(ifds).unwrap();
+            decoder
+        };
+        let mut extra_tags = EncodedDirectory::new();
+        extra_tags.insert(
+            Tag::ModelTiepointTag,
+            entry(&[0.0f64, 0.0, 0.0, 5.0, 60.0, 0.0][..]),
+        );
+        extra_tags.insert(Tag::ModelPixelScaleTag, entry(&[0.1f64, 0.1, 0.0][..]));
+        // levels of 64x64, 32x32 and 16x16, only the first with geo tags
+        let georeferenced = load(georeferenced_cog(extra_tags)).await;
+        for (target, level) in [
+            (LevelTarget::Dims(64, 64), 0),
+            (LevelTarget::Dims(40, 10), 0),
+            (LevelTarget::Dims(32, 20), 1),
+            (LevelTarget::Dims(10, 10), 2),
+            // larger than full resolution
+            (LevelTarget::Dims(100, 100), 0),
+            (LevelTarget::PixelSize(0.1), 0),
+            (LevelTarget::PixelSize(0.15), 0),
+            (LevelTarget::PixelSize(0.2), 1),
+            (LevelTarget::PixelSize(0.5), 2),
+            (LevelTarget::PixelSize(0.05), 0),
+        ] {
+            assert_eq!(
+                georeferenced.select_level(target).unwrap(),
+                level,
+                "{target:?}"
+            );
+        }
+
+        let plain = load(cog()).await;
+        assert_eq!(plain.select_level(LevelTarget::Dims(20, 20)).unwrap(), 1);
+        assert!(plain.select_level(LevelTarget::PixelSize(0.2)).is_err());
+        let (tiff, _) = Tiff::from_header(b"II*\0\x08\0\0\0").unwrap();
+        let empty = CogDecoder::new(Arc::new(Vec::new()), tiff, &DecoderOptions::default());
+        let Err(TiffError::UsageError(UsageError::OverviewNotLoaded(0))) =
+            empty.select_level(LevelTarget::Dims(1, 1))
+        else {
+            panic!("no level was added");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_level_tile_sizes() {
+        // 64x64 gray in 32x32 tiles and a 32x32 overview in 16x16 ones,
+        // pixels being their column, plus 100 in the overview
+        let levels = [(64, 32, 0), (32, 16, 100)].map(|(size, tile, offset)| {
+            Level::gray(size, size, tile, |i, j| {
+                (offset + i % (size / tile) * tile + j % tile) as u8
+            })
+        });
+        let reader: Arc<dyn CogReader> = Arc::new(test_cog(levels));
+        let mut tiff = Tiff::read(&*reader, &DecoderOptions::default())
+            .await
+            .unwrap();
+        let ifds = std::mem::take(&mut tiff.ifds);
+        let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+        assert_eq!(decoder.insert_levels(ifds).unwrap(), 2);
+
+        let tile_size = |level| {
+            let meta = decoder.images[&level].chunk_meta();
+            meta.tile_attributes
+                .as_ref()
+                .map(|tile| (tile.tile_width, tile.tile_length))
+        };
+        assert_eq!(tile_size(0), Some((32, 32)));
+        assert_eq!(tile_size(1), Some((16, 16)));
+        // windows across tile boundaries of either level
+        let region = decoder.decode_region(0, 30, 31, 4, 2).unwrap();
+        assert_eq!(region.await.unwrap(), [30, 31, 32, 33, 30, 31, 32, 33]);
+        let region = decoder.decode_region(1, 14, 15, 4, 2).unwrap();
+        assert_eq!(
+            region.await.unwrap(),
+            [114, 115, 116, 117, 114, 115, 116, 117]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunk_typed() {
+        let mut decoder = region_decoder(ChunkType::Tile);
+        let chunk = decoder.get_chunk_typed(1, 0).unwrap().await.unwrap();
+        assert_eq!(chunk.samples, DecodingResult::U8(vec![2, 0xff, 5, 0xff]));
+        assert_eq!((chunk.dims.valid_width, chunk.dims.valid_height), (1, 2));
+
+        let mut bilevel = image(0);
+        let mut chunk_meta = (*bilevel.chunk_meta).clone();
+        chunk_meta.bits_per_sample = 1;
+        bilevel.chunk_meta = Arc::new(chunk_meta);
+        decoder.insert_image(1, bilevel);
+        let Err(TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedSampleFormat(_))) =
+            decoder.get_chunk_typed(0, 1)
+        else {
+            panic!("1-bit samples have no type");
+        };
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[tokio::test]
+    async fn test_region_array() {
+        let decoder = region_decoder(ChunkType::Tile);
+        let region = decoder
+            .decode_region_array::<u8>(0, 1, 0, 2, 2, AxisOrder::BandsRowsCols)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(region, ndarray::array![[[1, 2], [4, 5]]]);
+        let Err(TiffError::UsageError(UsageError::SampleTypeMismatch { .. })) =
+            decoder.decode_region_array::<u16>(0, 0, 0, 1, 1, AxisOrder::RowsColsBands)
+        else {
+            panic!("u8 samples aren't u16");
+        };
+    }
+
+    #[cfg(feature = "image")]
+    #[tokio::test]
+    async fn test_image() {
+        let decoder = region_decoder(ChunkType::Tile);
+        let region = decoder
+            .decode_region_image(0, 1, 0, 2, 2)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(region.color(), image::ColorType::L8);
+        assert_eq!((region.width(), region.height()), (2, 2));
+        assert_eq!(region.as_bytes(), [1, 2, 4, 5]);
+        let chunk = decoder.get_chunk_image(1, 0).unwrap().await.unwrap();
+        // the padding of the tile is cropped
+        assert_eq!((chunk.width(), chunk.height()), (1, 2));
+        assert_eq!(chunk.as_bytes(), [2, 5]);
+        assert!(decoder.get_chunk_image(0, 1).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! Converting samples between 8 and 16 bits per sample.
+//!
+//! Both directions map the full range onto the full range, so black stays
+//! black and white stays white: 255 becomes 65535 and back.
+
+use alloc::vec::Vec;
+
+/// How to reduce 16-bit samples to 8 bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// Round to the nearest value
+    #[default]
+    None,
+    /// Add a 4x4 Bayer pattern before truncating, so smooth gradients don't
+    /// turn into visible bands
+    Ordered,
+}
+
+/// 4x4 Bayer matrix, thresholds in sixteenths
+const BAYER: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Scale 8-bit samples to 16 bits
+pub fn u8_to_u16(samples: &[u8]) -> Vec<u16> {
+    samples.iter().map(|&v| u16::from(v) * 257).collect()
+}
+
+/// Scale 16-bit samples to 8 bits.
+///
+/// `samples` holds pixel-interleaved rows of `width` pixels with
+/// `samples_per_pixel` samples each, which is where [`Dither::Ordered`] puts
+/// its pattern. All samples of a pixel get the same threshold, so gray stays
+/// gray.
+pub fn u16_to_u8(
+    samples: &[u16],
+    width: usize,
+    samples_per_pixel: usize,
+    dither: Dither,
+) -> Vec<u8> {
+    let row_len = (width * samples_per_pixel).max(1);
+    samples
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| {
+            let offset = match dither {
+                Dither::None => 65535 / 2,
+                Dither::Ordered => {
+                    let x = i % row_len / samples_per_pixel.max(1);
+                    let y = i / row_len;
+                    // centers of the sixteenths, so the pattern averages out to rounding
+                    (2 * BAYER[y % 4][x % 4] + 1) * 65535 / 32
+                }
+            };
+            ((u32::from(v) * 255 + offset) / 65535).min(255) as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_u8_to_u16() {
+        assert_eq!(u8_to_u16(&[0, 1, 128, 255]), vec![0, 257, 32896, 65535]);
+    }
+
+    #[test]
+    fn test_u16_to_u8() {
+        assert_eq!(
+            u16_to_u8(&[0, 128, 129, 32896, 65535], 5, 1, Dither::None),
+            vec![0, 0, 1, 128, 255]
+        );
+        // round trips
+        let all: Vec<u8> = (0..=255).collect();
+        for dither in [Dither::None, Dither::Ordered] {
+            assert_eq!(u16_to_u8(&u8_to_u16(&all), 16, 1, dither), all);
+        }
+    }
+
+    #[test]
+    fn test_ordered_dither() {
+        // halfway between 100 and 101
+        let v = 100 * 257 + 128;
+        let dithered = u16_to_u8(&[v; 16], 4, 1, Dither::Ordered);
+        assert_eq!(dithered.iter().filter(|&&v| v == 101).count(), 8);
+        assert!(dithered.iter().all(|&v| v == 100 || v == 101));
+        // the pattern is per pixel, not per sample
+        let rgb = u16_to_u8(&[v; 12], 4, 3, Dither::Ordered);
+        assert!(rgb
+            .chunks_exact(3)
+            .all(|px| px[0] == px[1] && px[1] == px[2]));
+        assert_eq!(
+            rgb.iter().step_by(3).copied().collect::<Vec<_>>(),
+            dithered[..4]
+        );
+    }
+}
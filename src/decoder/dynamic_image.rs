@@ -0,0 +1,113 @@
+//! Decoded samples as an [`image::DynamicImage`].
+//!
+//! 8 and 16-bit gray and RGB, with or without alpha, map onto the buffers of
+//! the `image` crate as they are, as does 32-bit float RGB(A). Other color
+//! types, like palette or YCbCr images, would need converting first, see
+//! [`CogDecoder::decode_region_rgb`].
+//!
+//! [`CogDecoder::decode_region_rgb`]: crate::decoder::CogDecoder::decode_region_rgb
+
+use image::{DynamicImage, ImageBuffer};
+
+use crate::{
+    decoder::{DecodingResult, SampleType},
+    error::{TiffFormatError, TiffResult, TiffUnsupportedError},
+    ColorType,
+};
+
+/// Check that samples of `sample_type` of an image of `color_type` make a
+/// [`DynamicImage`], failing with
+/// [`TiffUnsupportedError::UnsupportedColorType`] otherwise
+pub(crate) fn check_supported(color_type: ColorType, sample_type: SampleType) -> TiffResult<()> {
+    image_color_type(color_type, sample_type).map(|_| ())
+}
+
+/// Color type of the [`DynamicImage`] that samples of `sample_type` of an
+/// image of `color_type` make, failing like [`check_supported`]
+pub(crate) fn image_color_type(
+    color_type: ColorType,
+    sample_type: SampleType,
+) -> TiffResult<image::ColorType> {
+    Ok(match (color_type, sample_type) {
+        (ColorType::Gray(8), SampleType::U8) => image::ColorType::L8,
+        (ColorType::GrayA(8), SampleType::U8) => image::ColorType::La8,
+        (ColorType::RGB(8), SampleType::U8) => image::ColorType::Rgb8,
+        (ColorType::RGBA(8), SampleType::U8) => image::ColorType::Rgba8,
+        (ColorType::Gray(16), SampleType::U16) => image::ColorType::L16,
+        (ColorType::GrayA(16), SampleType::U16) => image::ColorType::La16,
+        (ColorType::RGB(16), SampleType::U16) => image::ColorType::Rgb16,
+        (ColorType::RGBA(16), SampleType::U16) => image::ColorType::Rgba16,
+        (ColorType::RGB(32), SampleType::F32) => image::ColorType::Rgb32F,
+        (ColorType::RGBA(32), SampleType::F32) => image::ColorType::Rgba32F,
+        _ => return Err(TiffUnsupportedError::UnsupportedColorType(color_type).into()),
+    })
+}
+
+/// Interleaved samples of a `width` by `height` image of `color_type` as a
+/// [`DynamicImage`].
+///
+/// Fails with [`TiffUnsupportedError::UnsupportedColorType`] for color types
+/// and samples the `image` crate has no buffer for, and with
+/// [`TiffFormatError::InvalidDimensions`] if there aren't as many samples as
+/// the size calls for.
+pub fn to_dynamic_image(
+    samples: DecodingResult,
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+) -> TiffResult<DynamicImage> {
+    macro_rules! buffer {
+        ($variant:ident, $data:expr) => {
+            ImageBuffer::from_raw(width, height, $data)
+                .map(DynamicImage::$variant)
+                .ok_or(TiffFormatError::InvalidDimensions(width, height))?
+        };
+    }
+    Ok(match (color_type, samples) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => buffer!(ImageLuma8, data),
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => buffer!(ImageLumaA8, data),
+        (ColorType::RGB(8), DecodingResult::U8(data)) => buffer!(ImageRgb8, data),
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => buffer!(ImageRgba8, data),
+        (ColorType::Gray(16), DecodingResult::U16(data)) => buffer!(ImageLuma16, data),
+        (ColorType::GrayA(16), DecodingResult::U16(data)) => buffer!(ImageLumaA16, data),
+        (ColorType::RGB(16), DecodingResult::U16(data)) => buffer!(ImageRgb16, data),
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => buffer!(ImageRgba16, data),
+        (ColorType::RGB(32), DecodingResult::F32(data)) => buffer!(ImageRgb32F, data),
+        (ColorType::RGBA(32), DecodingResult::F32(data)) => buffer!(ImageRgba32F, data),
+        _ => return Err(TiffUnsupportedError::UnsupportedColorType(color_type).into()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_dynamic_image() {
+        let image = to_dynamic_image(
+            DecodingResult::U8(vec![1, 2, 3, 4]),
+            2,
+            1,
+            ColorType::GrayA(8),
+        )
+        .unwrap();
+        assert_eq!(image.color(), image::ColorType::La8);
+        assert_eq!(image.as_bytes(), [1, 2, 3, 4]);
+
+        let rgb16 = DecodingResult::U16(vec![0, 1000, 65535]);
+        let image = to_dynamic_image(rgb16, 1, 1, ColorType::RGB(16)).unwrap();
+        assert_eq!(image.to_rgb16().get_pixel(0, 0).0, [0, 1000, 65535]);
+
+        // too few samples
+        assert!(to_dynamic_image(DecodingResult::U8(vec![1]), 2, 1, ColorType::Gray(8)).is_err());
+        // samples of the wrong type, and color types without a buffer
+        assert!(to_dynamic_image(DecodingResult::I8(vec![1]), 1, 1, ColorType::Gray(8)).is_err());
+        assert!(check_supported(ColorType::Gray(8), SampleType::I8).is_err());
+        assert!(check_supported(ColorType::YCbCr(8), SampleType::U8).is_err());
+        assert!(check_supported(ColorType::RGBA(32), SampleType::F32).is_ok());
+        assert_eq!(
+            image_color_type(ColorType::GrayA(16), SampleType::U16).unwrap(),
+            image::ColorType::La16
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! [`CogReader`] over a local file, through positioned reads.
+//!
+//! Every read passes its offset to the operating system (`pread` on Unix,
+//! `ReadFile` with an offset on Windows) instead of seeking the cursor of the
+//! file first, so one [`FileReader`] can be shared by any number of decoders
+//! and threads without reads stepping on each other. Reads run on tokio's
+//! blocking thread pool.
+//!
+//! Leaving the cursor alone is not guaranteed on Windows, where positioned
+//! reads still move it. [`FileReader::open`] opens a handle of its own, whose
+//! cursor nothing else sees. A file given to [`FileReader::from_file`] should
+//! be treated as owned by the reader: on Windows, its cursor, and that of
+//! handles cloned from it, ends up anywhere.
+//!
+//! Ranges are checked against the length of the file when it was opened, and
+//! reads past it fail with [`io::ErrorKind::UnexpectedEof`] without touching
+//! the file. Vectored reads read all their ranges in a single blocking task.
+
+use std::{fs::File, io, ops::Range, path::Path, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{
+    decoder::{reader::range_len, CogReader, SourceKey},
+    error::{TiffError, TiffResult},
+};
+
+/// Reads from a local file, without moving its cursor
+#[derive(Debug, Clone)]
+pub struct FileReader {
+    file: Arc<File>,
+    len: u64,
+    source: SourceKey,
+}
+
+impl FileReader {
+    /// Open the file at `path`, with a handle of its own
+    pub fn open<P: AsRef<Path>>(path: P) -> TiffResult<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let source = SourceKey::file(path, &file)?;
+        let mut reader = Self::from_file(file)?;
+        reader.source = source;
+        Ok(reader)
+    }
+
+    /// Read from an already opened file, taking ownership of its cursor
+    ///
+    /// The cursor of `file`, and of handles cloned from it, is never used, so
+    /// reads don't depend on it. On Unix it isn't moved either, but on Windows
+    /// reads leave it past the last range read, so it shouldn't be relied on
+    /// through cloned handles. The path of the file is unknown, so its
+    /// [`SourceKey`] is [unique](SourceKey::unique).
+    pub fn from_file(file: File) -> TiffResult<Self> {
+        let len = file.metadata()?.len();
+        Ok(FileReader {
+            file: Arc::new(file),
+            len,
+            source: SourceKey::unique(),
+        })
+    }
+
+    /// Length of the file when it was opened
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    async fn read_range(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        let mut data = self.read_ranges(vec![(byte_start, n_bytes)]).await?;
+        Ok(data.remove(0))
+    }
+
+    /// Read `(byte_start, n_bytes)` ranges on a single blocking thread
+    async fn read_ranges(&self, ranges: Vec<(u64, u64)>) -> TiffResult<Vec<Bytes>> {
+        for &(byte_start, n_bytes) in &ranges {
+            let end = byte_start
+                .checked_add(n_bytes)
+                .ok_or(TiffError::IntSizeError)?;
+            if end > self.len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            usize::try_from(n_bytes)?;
+        }
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || {
+            ranges
+                .into_iter()
+                .map(|(byte_start, n_bytes)| {
+                    let mut buf = vec![0; n_bytes as usize];
+                    read_exact_at(&file, &mut buf, byte_start)?;
+                    Ok(Bytes::from(buf))
+                })
+                .collect()
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(_file: &File, _buf: &mut [u8], _offset: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "positioned reads are not supported on this platform",
+    ))
+}
+
+#[async_trait]
+impl CogReader for FileReader {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(byte_start, n_bytes).await
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(byte_start, n_bytes).await
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(byte_start, n_bytes).await
+    }
+
+    async fn read_vectored(&self, ranges: &[Range<u64>]) -> TiffResult<Vec<Bytes>> {
+        let ranges = ranges
+            .iter()
+            .map(|range| Ok((range.start, range_len(range)?)))
+            .collect::<TiffResult<_>>()?;
+        self.read_ranges(ranges).await
+    }
+
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(0, n_bytes.min(self.len)).await
+    }
+
+    fn file_len(&self) -> Option<u64> {
+        Some(self.len)
+    }
+
+    fn source_id(&self) -> SourceKey {
+        self.source.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        decoder::{CogDecoder, DecoderOptions},
+        encoder::{test_cog, Level},
+        structs::{Image, Tiff},
+        ByteOrder,
+    };
+    use std::io::{Seek, SeekFrom, Write};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("tiff2-{name}-{}.tif", std::process::id()))
+    }
+
+    /// Sample of pixel `(x, y)` of the test image
+    fn sample(x: u32, y: u32) -> u8 {
+        (x * 7 + y * 13) as u8
+    }
+
+    /// 128x128 gray image in tiles of 16x16, with samples given by [`sample`]
+    fn cog() -> Vec<u8> {
+        test_cog([Level::gray(128, 128, 16, |i, j| {
+            sample(i % 8 * 16 + j % 16, i / 8 * 16 + j / 16)
+        })])
+    }
+
+    #[tokio::test]
+    async fn test_read_file() {
+        let path = temp_path("file");
+        File::create(&path)
+            .unwrap()
+            .write_all(b"II*\0\x08\0\0\0abcdef")
+            .unwrap();
+        let reader = FileReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 14);
+        let SourceKey::Path {
+            path: key_path,
+            len,
+            ..
+        } = reader.source_id()
+        else {
+            panic!("an opened file should be keyed by its path");
+        };
+        assert_eq!((key_path, len), (path.canonicalize().unwrap(), 14));
+        assert_eq!(reader.read_ifd(0, 4).await.unwrap(), &b"II*\0"[..]);
+        assert_eq!(reader.read_image_data(8, 6).await.unwrap(), &b"abcdef"[..]);
+        assert_eq!(reader.read_header(100).await.unwrap().len(), 14);
+        let TiffError::IoError(e) = reader.read_tag_data(12, 4).await.unwrap_err() else {
+            panic!("reading past the end should be an io error");
+        };
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(reader.read_tag_data(u64::MAX, 2).await.is_err());
+        assert_eq!(
+            reader.read_vectored(&[10..14, 0..2]).await.unwrap(),
+            [&b"cdef"[..], b"II"]
+        );
+        assert!(reader.read_vectored(&[0..2, 12..16]).await.is_err());
+        // a file replaced at the same path gets a new key
+        File::create(&path).unwrap().write_all(b"II*\0").unwrap();
+        let replaced = FileReader::open(&path).unwrap();
+        assert_ne!(replaced.source_id(), reader.source_id());
+        drop((reader, replaced));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_shared_reader() {
+        let path = temp_path("shared");
+        File::create(&path).unwrap().write_all(&cog()).unwrap();
+        let file = File::open(&path).unwrap();
+        // shares its cursor with `file`
+        let mut handle = file.try_clone().unwrap();
+        let reader = Arc::new(FileReader::from_file(file).unwrap());
+
+        // moving the cursor meanwhile doesn't affect reads, on Windows too,
+        // though there reads move the cursor of `handle` in turn
+        let seeker = std::thread::spawn(move || {
+            for i in 0..2000u64 {
+                handle.seek(SeekFrom::Start(i * 31 % 4096)).unwrap();
+            }
+        });
+
+        let mut tasks = Vec::new();
+        for d in 0..8u32 {
+            let options = DecoderOptions {
+                raw_cache_size: 0,
+                decoded_cache_size: 0,
+                ..Default::default()
+            };
+            let mut tiff = Tiff::read(&*reader, &options).await.unwrap();
+            let image = Image::from_ifd(tiff.ifds.remove(0), ByteOrder::LittleEndian).unwrap();
+            let mut decoder = CogDecoder::new(reader.clone(), tiff, &options);
+            decoder.insert_image(0, image);
+            for r in 0..16u32 {
+                let x = (d * 37 + r * 11) % 100;
+                let y = (d * 13 + r * 29) % 100;
+                let (width, height) = (1 + (r * 7) % 28, 1 + (d * 5 + r) % 28);
+                let region = decoder.decode_region(0, x, y, width, height).unwrap();
+                tasks.push(tokio::spawn(async move {
+                    (x, y, width, height, region.await.unwrap())
+                }));
+            }
+        }
+        for task in tasks {
+            let (x, y, width, height, data) = task.await.unwrap();
+            let expected: Vec<u8> = (y..y + height)
+                .flat_map(|row| (x..x + width).map(move |col| sample(col, row)))
+                .collect();
+            assert_eq!(data, expected, "region at ({x}, {y}) of {width}x{height}");
+        }
+        seeker.join().unwrap();
+        drop(reader);
+        std::fs::remove_file(path).unwrap();
+    }
+}
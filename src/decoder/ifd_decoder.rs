@@ -0,0 +1,1035 @@
+//! Reading the header and IFD chain of a TIFF.
+//!
+//! COGs put the header, all IFDs and most tag data at the start of the file,
+//! so the first [`DecoderOptions::header_prefetch`] bytes are fetched in a
+//! single read. Everything that lies within that buffer is taken from it,
+//! only the rest goes through `read_ifd`/`read_tag_data`, with tag data that
+//! lies close together fetched in one read, see [`Ifd::load_tags`].
+//!
+//! SubIfds are followed as well, within the depth and fan-out limits of the
+//! [`DecoderOptions`], so crafted files can't make us recurse forever. A
+//! chain that points back to an IFD it already went through is rejected with
+//! [`TiffFormatError::CycleInOffsets`] instead of being walked forever.
+//! Entry counts and tag data sizes are checked against
+//! [`DecoderOptions::limits`] before anything is allocated for them, with the
+//! tag data of all IFDs counting towards [`Limits::max_total_bytes`].
+//!
+//! Only the chain itself has to be walked one IFD after the other. Once an
+//! IFD's entries are read, loading its tag data and SubIfds doesn't depend on
+//! the other IFDs, so that is done for up to
+//! [`DecoderOptions::ifd_concurrency`] IFDs at once. Parsing is cheap next to
+//! the reads, so this is plain async concurrency on the calling task.
+//!
+//! [`Limits::max_total_bytes`]: crate::decoder::Limits::max_total_bytes
+
+use alloc::{boxed::Box, collections::BTreeSet, vec, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context as TaskContext, Poll},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    decoder::{chunk::jpeg_segments, CogReader, DecoderOptions, Limits},
+    error::{TiffFormatError, TiffResult},
+    io,
+    structs::{
+        geo::GEO_TAGS, tags::CompressionMethod, tiff::HEADER_LEN, BufferedEntry, Ifd, IfdEntry,
+        Tag, TagType, Tiff, CHUNK_TAGS, IMAGE_TAGS,
+    },
+    util::fix_endianness,
+    ByteOrder,
+};
+
+/// The first bytes of a file
+struct Prefetched(Bytes);
+
+impl Prefetched {
+    /// The range, if it was prefetched, sharing memory with the prefetch
+    fn get(&self, byte_start: u64, n_bytes: u64) -> Option<Bytes> {
+        let start = usize::try_from(byte_start).ok()?;
+        let end = start.checked_add(usize::try_from(n_bytes).ok()?)?;
+        (end <= self.0.len()).then(|| self.0.slice(start..end))
+    }
+}
+
+impl Tiff {
+    /// Read the header and all IFDs in the chain, including their SubIfds.
+    ///
+    /// Tag data needed for decoding images is loaded, as is any other tag data
+    /// that was prefetched anyway. Other tags are left as `IfdEntry::Offset`.
+    pub async fn read<R: CogReader + ?Sized>(
+        reader: &R,
+        options: &DecoderOptions,
+    ) -> TiffResult<Tiff> {
+        let prefetched = Prefetched(
+            reader
+                .read_header(options.header_prefetch.max(HEADER_LEN))
+                .await?,
+        );
+        let (mut tiff, mut next_ifd) = match Tiff::from_header(&prefetched.0) {
+            Err(e) if options.detect_byte_order => from_swapped_header(&prefetched.0).ok_or(e)?,
+            header => header?,
+        };
+        tiff.file_len = reader.file_len();
+        if options.detect_byte_order && !tiff.byte_order_mismatch && next_ifd != 0 {
+            detect_ifd_byte_order(reader, &prefetched, &mut tiff, next_ifd, options).await;
+        }
+        let ctx = Context {
+            prefetched,
+            byte_order: tiff.byte_order,
+            bigtiff: tiff.bigtiff,
+            options,
+            tag_bytes: AtomicU64::new(0),
+        };
+        let mut ifds = Vec::new();
+        let mut visited = BTreeSet::new();
+        while next_ifd != 0 {
+            if !visited.insert(next_ifd) {
+                return Err(TiffFormatError::CycleInOffsets.into());
+            }
+            let (ifd, next) = read_ifd(reader, &ctx, next_ifd).await?;
+            ifds.push(ifd);
+            next_ifd = next;
+        }
+        let loaded = JoinBounded::new(
+            ifds.into_iter()
+                .map(|ifd| load_ifd(reader, &ctx, ifd, 0))
+                .collect(),
+            options.ifd_concurrency,
+        );
+        tiff.ifds = loaded.await.into_iter().collect::<TiffResult<_>>()?;
+        Ok(tiff)
+    }
+}
+
+fn swapped(byte_order: ByteOrder) -> ByteOrder {
+    match byte_order {
+        ByteOrder::LittleEndian => ByteOrder::BigEndian,
+        ByteOrder::BigEndian => ByteOrder::LittleEndian,
+    }
+}
+
+/// Parse a header whose byte order mark contradicts its magic number, as if
+/// it had the other mark
+fn from_swapped_header(buf: &[u8]) -> Option<(Tiff, u64)> {
+    let mut buf = buf.to_vec();
+    let mark: &[u8; 2] = match buf.get(..2)? {
+        b"II" => b"MM",
+        b"MM" => b"II",
+        _ => return None,
+    };
+    buf[..2].copy_from_slice(mark);
+    let (mut tiff, first_ifd) = Tiff::from_header(&buf).ok()?;
+    log::warn!(
+        "the byte order mark of the header contradicts its magic number, reading it as {:?}",
+        tiff.byte_order
+    );
+    tiff.byte_order_mismatch = true;
+    Some((tiff, first_ifd))
+}
+
+/// Whether `buf`, the entry count of an IFD followed by the tag and type of
+/// its first entry, makes sense in `byte_order`: read in the wrong byte order,
+/// counts are huge or 0 and types unknown.
+fn plausible_ifd(buf: &[u8], byte_order: ByteOrder, max_entries: u64) -> bool {
+    if buf.len() < 4 {
+        return false;
+    }
+    let (count_buf, entry) = buf.split_at(buf.len() - 4);
+    let num_entries = count(count_buf, byte_order);
+    let tag_type = byte_order.u16([entry[2], entry[3]]);
+    num_entries > 0 && num_entries <= max_entries && TagType::from_u16(tag_type).is_some()
+}
+
+/// Switch `tiff` to the other byte order if the first IFD, at `offset`, is
+/// only plausible in that one
+async fn detect_ifd_byte_order<R: CogReader + ?Sized>(
+    reader: &R,
+    prefetched: &Prefetched,
+    tiff: &mut Tiff,
+    offset: u64,
+    options: &DecoderOptions,
+) {
+    let len = if tiff.bigtiff { 8 } else { 2 } + 4;
+    let buf = match prefetched.get(offset, len) {
+        Some(buf) => buf,
+        None => match reader.read_ifd(offset, len).await {
+            Ok(buf) => buf,
+            // reading the IFD itself reports that
+            Err(_) => return,
+        },
+    };
+    let max_entries = options.limits.max_ifd_entries;
+    let other = swapped(tiff.byte_order);
+    if !plausible_ifd(&buf, tiff.byte_order, max_entries) && plausible_ifd(&buf, other, max_entries)
+    {
+        log::warn!(
+            "the first IFD contradicts the byte order of the header, reading it as {other:?}"
+        );
+        tiff.byte_order = other;
+        tiff.byte_order_mismatch = true;
+    }
+}
+
+/// Everything about the file that stays the same while walking its IFDs
+struct Context<'a> {
+    prefetched: Prefetched,
+    byte_order: ByteOrder,
+    bigtiff: bool,
+    options: &'a DecoderOptions,
+    /// Tag data loaded so far, by all IFDs
+    tag_bytes: AtomicU64,
+}
+
+impl Context<'_> {
+    /// Account for `n_bytes` more tag data, failing if that makes the total
+    /// exceed the limits
+    fn reserve(&self, n_bytes: u64) -> TiffResult<()> {
+        let total = self
+            .tag_bytes
+            .fetch_add(n_bytes, Ordering::Relaxed)
+            .saturating_add(n_bytes);
+        Limits::check(total, self.options.limits.max_total_bytes)
+    }
+}
+
+/// Future of an IFD, boxed so it can be awaited recursively for SubIfds
+type IfdFuture<'a> = Pin<Box<dyn Future<Output = TiffResult<Ifd>> + Send + 'a>>;
+
+/// Load the tag data and SubIfds of an IFD whose entries were read
+fn load_ifd<'a, R: CogReader + ?Sized>(
+    reader: &'a R,
+    ctx: &'a Context<'_>,
+    mut ifd: Ifd,
+    depth: usize,
+) -> IfdFuture<'a> {
+    Box::pin(async move {
+        load_tags(reader, ctx, &mut ifd).await?;
+        load_jpeg_header(reader, ctx, &mut ifd).await?;
+        let sub_ifds = sub_ifd_offsets(reader, ctx, &ifd).await?;
+        if !sub_ifds.is_empty() && depth >= ctx.options.max_ifd_depth {
+            return Err(TiffFormatError::IfdTooDeep(ctx.options.max_ifd_depth).into());
+        }
+        let sub_ifds = sub_ifds
+            .into_iter()
+            .map(|offset| -> IfdFuture<'a> {
+                Box::pin(async move {
+                    // sub-IFDs are only reachable through the SubIfds tag, not the chain
+                    let (sub_ifd, _) = read_ifd(reader, ctx, offset).await?;
+                    load_ifd(reader, ctx, sub_ifd, depth + 1).await
+                })
+            })
+            .collect();
+        for sub_ifd in JoinBounded::new(sub_ifds, ctx.options.ifd_concurrency).await {
+            ifd.push_sub_ifd(sub_ifd?);
+        }
+        Ok(ifd)
+    })
+}
+
+/// Polls futures concurrently, at most `limit` at once, resolving to their
+/// outputs in order. Unlike spawned tasks, the futures may borrow.
+pub(crate) struct JoinBounded<F: Future> {
+    futures: Vec<Option<Pin<Box<F>>>>,
+    outputs: Vec<Option<F::Output>>,
+    limit: usize,
+}
+
+impl<F: Future> JoinBounded<F> {
+    pub(crate) fn new(futures: Vec<F>, limit: usize) -> Self {
+        JoinBounded {
+            outputs: futures.iter().map(|_| None).collect(),
+            futures: futures.into_iter().map(|f| Some(Box::pin(f))).collect(),
+            limit: limit.max(1),
+        }
+    }
+}
+
+// futures are boxed and outputs are never pinned
+impl<F: Future> Unpin for JoinBounded<F> {}
+
+impl<F: Future> Future for JoinBounded<F> {
+    type Output = Vec<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // the first `limit` unfinished futures are running
+        let mut running = 0;
+        for (future, output) in this.futures.iter_mut().zip(&mut this.outputs) {
+            if running == this.limit {
+                break;
+            }
+            let Some(f) = future else { continue };
+            match f.as_mut().poll(cx) {
+                Poll::Ready(out) => {
+                    *output = Some(out);
+                    *future = None;
+                }
+                Poll::Pending => running += 1,
+            }
+        }
+        if running > 0 {
+            return Poll::Pending;
+        }
+        Poll::Ready(this.outputs.iter_mut().filter_map(Option::take).collect())
+    }
+}
+
+/// Offsets in the SubIfds tag of `ifd`, if any
+async fn sub_ifd_offsets<R: CogReader + ?Sized>(
+    reader: &R,
+    ctx: &Context<'_>,
+    ifd: &Ifd,
+) -> TiffResult<Vec<u64>> {
+    let count = match ifd.get_tag(&Tag::SubIfds) {
+        None => return Ok(Vec::new()),
+        Some(IfdEntry::Offset { count, .. }) => *count,
+        Some(IfdEntry::Value(entry)) => entry.count,
+    };
+    if count > ctx.options.max_sub_ifds as u64 {
+        return Err(TiffFormatError::TooManySubIfds {
+            count,
+            max: ctx.options.max_sub_ifds,
+        }
+        .into());
+    }
+    let entry = match *ifd.require_tag(&Tag::SubIfds)? {
+        // the offset field holds the offset of the only sub-IFD
+        IfdEntry::Offset {
+            tag_type: TagType::IFD | TagType::IFD8,
+            count: 1,
+            offset,
+        } => return Ok(vec![offset]),
+        IfdEntry::Offset {
+            tag_type,
+            count,
+            offset,
+        } => {
+            ctx.reserve(count * tag_type.size() as u64)?;
+            let mut entry = BufferedEntry::new(tag_type, count)?;
+            let n_bytes = entry.data.len() as u64;
+            match ctx.prefetched.get(offset, n_bytes) {
+                Some(buf) => entry.data.copy_from_slice(&buf),
+                None => {
+                    let data = reader.read_tag_data(offset, n_bytes).await?;
+                    if data.len() != entry.data.len() {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                    }
+                    entry.data = data.into();
+                }
+            }
+            fix_endianness(
+                &mut entry.data,
+                ctx.byte_order,
+                8 * tag_type.primitive_size(),
+            );
+            entry
+        }
+        IfdEntry::Value(ref entry) => entry.clone(),
+    };
+    (0..usize::try_from(count)?)
+        .map(|i| entry.get_u64(i))
+        .collect()
+}
+
+async fn read_ifd<R: CogReader + ?Sized>(
+    reader: &R,
+    ctx: &Context<'_>,
+    offset: u64,
+) -> TiffResult<(Ifd, u64)> {
+    let Context {
+        prefetched,
+        byte_order,
+        bigtiff,
+        options,
+        ..
+    } = ctx;
+    let count_len = if *bigtiff { 8 } else { 2 };
+    let num_entries = match prefetched.get(offset, count_len) {
+        Some(buf) => count(&buf, *byte_order),
+        None => count(&reader.read_ifd(offset, count_len).await?, *byte_order),
+    };
+    // before reading a buffer of that size
+    Limits::check(num_entries, options.limits.max_ifd_entries)?;
+    let ifd_len = Ifd::encoded_len(num_entries, *bigtiff);
+    let (mut ifd, next) = match prefetched.get(offset, ifd_len) {
+        Some(buf) => Ifd::from_buffer_with_next(&buf, *byte_order, *bigtiff, &options.limits)?,
+        None => Ifd::from_buffer_with_next(
+            &reader.read_ifd(offset, ifd_len).await?,
+            *byte_order,
+            *bigtiff,
+            &options.limits,
+        )?,
+    };
+    resolve_vendor_types(&mut ifd, ctx)?;
+    // before any tag data or chunks of a refused image are fetched
+    if ifd.contains_key(&Tag::StripOffsets) || ifd.contains_key(&Tag::TileOffsets) {
+        let method = match ifd.get_tag_value(&Tag::Compression)? {
+            Some(value) => CompressionMethod::from_u16_exhaustive(u16::try_from(value)?),
+            None => CompressionMethod::None,
+        };
+        options.check_compression(method)?;
+    }
+    Ok((ifd, next))
+}
+
+/// Apply the sizes of [`DecoderOptions::vendor_tag_types`] to the entries of
+/// those types
+fn resolve_vendor_types(ifd: &mut Ifd, ctx: &Context<'_>) -> TiffResult<()> {
+    let vendor_entries: Vec<_> = ifd
+        .iter()
+        .filter_map(|(tag, entry)| match *entry {
+            IfdEntry::Offset {
+                tag_type: tag_type @ TagType::Unknown(code, 0),
+                count,
+                offset,
+            } => {
+                let size = *ctx.options.vendor_tag_types.get(&code)?;
+                Some((*tag, tag_type, count, offset, size))
+            }
+            _ => None,
+        })
+        .collect();
+    for (tag, tag_type, count, offset, size) in vendor_entries {
+        Limits::check(
+            count.saturating_mul(size.into()),
+            ctx.options.limits.max_tag_bytes,
+        )?;
+        let entry = IfdEntry::Offset {
+            tag_type,
+            count,
+            offset,
+        };
+        ifd.insert(
+            tag,
+            entry.with_size_hint(size, ctx.byte_order, ctx.bigtiff)?,
+        );
+    }
+    Ok(())
+}
+
+/// Entry count at the start of an IFD, which is 2 or 8 bytes long
+fn count(buf: &[u8], byte_order: ByteOrder) -> u64 {
+    match *buf {
+        [a, b] => byte_order.u16([a, b]).into(),
+        [a, b, c, d, e, f, g, h] => byte_order.u64([a, b, c, d, e, f, g, h]),
+        _ => 0,
+    }
+}
+
+/// Tag data less than this many bytes apart is fetched in a single read by
+/// [`Ifd::load_tags`]. Reading the gap costs less than another round trip.
+pub const TAG_COALESCE_GAP: u64 = 4096;
+
+impl Ifd {
+    /// Load the data of those `tags` that are in the IFD but not loaded yet.
+    ///
+    /// Their offsets are sorted, and data that lies less than
+    /// [`TAG_COALESCE_GAP`] bytes apart is fetched in a single read, so e.g.
+    /// TileOffsets, TileByteCounts and BitsPerSample usually take one round
+    /// trip instead of three. `byte_order` is that of the file.
+    pub async fn load_tags<R: CogReader + ?Sized>(
+        &mut self,
+        tags: &[Tag],
+        reader: &R,
+        byte_order: ByteOrder,
+    ) -> TiffResult<()> {
+        let unloaded = unloaded_tags(self)
+            .into_iter()
+            .filter(|(tag, ..)| tags.contains(tag))
+            .collect();
+        read_tags(reader, self, unloaded, byte_order).await
+    }
+}
+
+/// Tags whose data isn't loaded yet, as (tag, type, count, offset)
+fn unloaded_tags(ifd: &Ifd) -> Vec<(Tag, TagType, u64, u64)> {
+    ifd.iter()
+        .filter_map(|(tag, entry)| match *entry {
+            // sub-IFDs are not tag data
+            IfdEntry::Offset {
+                tag_type: TagType::IFD | TagType::IFD8,
+                ..
+            } => None,
+            // nor is the raw offset field of an entry of unknown size
+            IfdEntry::Offset {
+                tag_type: TagType::Unknown(_, 0),
+                ..
+            } => None,
+            IfdEntry::Offset {
+                tag_type,
+                count,
+                offset,
+            } => Some((*tag, tag_type, count, offset)),
+            IfdEntry::Value(_) => None,
+        })
+        .collect()
+}
+
+/// Read the data of `(tag, type, count, offset)` entries into the IFD
+async fn read_tags<R: CogReader + ?Sized>(
+    reader: &R,
+    ifd: &mut Ifd,
+    entries: Vec<(Tag, TagType, u64, u64)>,
+    byte_order: ByteOrder,
+) -> TiffResult<()> {
+    let ranges: Vec<_> = entries
+        .iter()
+        .map(|&(_, tag_type, count, offset)| (offset, count * tag_type.size() as u64))
+        .collect();
+    let data = read_coalesced(reader, &ranges).await?;
+    for ((tag, tag_type, count, _), data) in entries.into_iter().zip(data) {
+        insert_tag_data(ifd, tag, tag_type, count, data.into(), byte_order);
+    }
+    Ok(())
+}
+
+/// Read the `(offset, n_bytes)` ranges, merging those less than
+/// [`TAG_COALESCE_GAP`] apart into a single read. Merged reads are done
+/// concurrently, and the data is returned in the order of `ranges`, as slices
+/// of what was read.
+async fn read_coalesced<R: CogReader + ?Sized>(
+    reader: &R,
+    ranges: &[(u64, u64)],
+) -> TiffResult<Vec<Bytes>> {
+    let mut order: Vec<usize> = (0..ranges.len()).collect();
+    order.sort_unstable_by_key(|&i| ranges[i].0);
+    // (start, end, indices of the ranges within)
+    let mut merged: Vec<(u64, u64, Vec<usize>)> = Vec::new();
+    for i in order {
+        let (start, n_bytes) = ranges[i];
+        let end = start
+            .checked_add(n_bytes)
+            .ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(TAG_COALESCE_GAP) => {
+                last.1 = last.1.max(end);
+                last.2.push(i);
+            }
+            _ => merged.push((start, end, vec![i])),
+        }
+    }
+    let reads = JoinBounded::new(
+        merged
+            .iter()
+            .map(|(start, end, _)| reader.read_tag_data(*start, end - start))
+            .collect(),
+        merged.len(),
+    );
+    let mut data = vec![Bytes::new(); ranges.len()];
+    for ((start, end, indices), read) in merged.iter().zip(reads.await) {
+        let read = read?;
+        if read.len() as u64 != end - start {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        for &i in indices {
+            let (offset, n_bytes) = ranges[i];
+            let from = usize::try_from(offset - start)?;
+            data[i] = read.slice(from..from + usize::try_from(n_bytes)?);
+        }
+    }
+    Ok(data)
+}
+
+/// Insert tag data as read from the file
+fn insert_tag_data(
+    ifd: &mut Ifd,
+    tag: Tag,
+    tag_type: TagType,
+    count: u64,
+    mut data: Vec<u8>,
+    byte_order: ByteOrder,
+) {
+    fix_endianness(&mut data, byte_order, 8 * tag_type.primitive_size());
+    ifd.insert_tag_data_from_buffer(
+        &tag,
+        BufferedEntry {
+            tag_type,
+            count,
+            data,
+        },
+    );
+}
+
+/// Load the data of tags that were prefetched or are needed for decoding or
+/// georeferencing
+async fn load_tags<R: CogReader + ?Sized>(
+    reader: &R,
+    ctx: &Context<'_>,
+    ifd: &mut Ifd,
+) -> TiffResult<()> {
+    let mut to_read = Vec::new();
+    for (tag, tag_type, count, offset) in unloaded_tags(ifd) {
+        let n_bytes = count * tag_type.size() as u64;
+        let prefetched = ctx.prefetched.get(offset, n_bytes);
+        if prefetched.is_none() && !IMAGE_TAGS.contains(&tag) && !GEO_TAGS.contains(&tag) {
+            continue;
+        }
+        // paged in by `Image` instead
+        if prefetched.is_none()
+            && CHUNK_TAGS.contains(&tag)
+            && n_bytes > ctx.options.max_eager_offsets_bytes
+        {
+            continue;
+        }
+        ctx.reserve(n_bytes)?;
+        match prefetched {
+            Some(buf) => insert_tag_data(ifd, tag, tag_type, count, buf.into(), ctx.byte_order),
+            None => to_read.push((tag, tag_type, count, offset)),
+        }
+    }
+    read_tags(reader, ifd, to_read, ctx.byte_order).await
+}
+
+/// Largest header of an old-style JPEG stream that is read
+const MAX_JPEG_HEADER: u64 = 64 * 1024;
+
+/// Load the header of the stream `JPEGInterchangeFormat` points at, up to its
+/// first scan, as the `JPEGTables` of an old-style JPEG image. That is what
+/// it is to the chunks, and this way [`Image::from_ifd`] needs no I/O.
+///
+/// [`Image::from_ifd`]: crate::structs::Image::from_ifd
+async fn load_jpeg_header<R: CogReader + ?Sized>(
+    reader: &R,
+    ctx: &Context<'_>,
+    ifd: &mut Ifd,
+) -> TiffResult<()> {
+    let value = |tag| -> TiffResult<Option<u64>> {
+        ifd.get_tag_value(&tag)?.map(|v| v.get_u64(0)).transpose()
+    };
+    if value(Tag::Compression)? != Some(CompressionMethod::JPEG.to_u16().into())
+        || ifd.contains_key(&Tag::JPEGTables)
+    {
+        return Ok(());
+    }
+    let (Some(offset), Some(len)) = (
+        value(Tag::JPEGInterchangeFormat)?,
+        value(Tag::JPEGInterchangeFormatLength)?,
+    ) else {
+        return Ok(());
+    };
+    let n_bytes = len.min(MAX_JPEG_HEADER);
+    ctx.reserve(n_bytes)?;
+    let mut data: Vec<u8> = match ctx.prefetched.get(offset, n_bytes) {
+        Some(buf) => buf,
+        None => reader.read_tag_data(offset, n_bytes).await?,
+    }
+    .into();
+    let Some((_, last)) = jpeg_segments(&data).last() else {
+        log::warn!("JPEGInterchangeFormat doesn't point at a JPEG stream");
+        return Ok(());
+    };
+    data.truncate(last.end);
+    data.extend_from_slice(&[0xff, 0xd9]);
+    let entry = BufferedEntry {
+        tag_type: TagType::UNDEFINED,
+        count: data.len() as u64,
+        data,
+    };
+    ifd.insert_tag_data_from_buffer(&Tag::JPEGTables, entry);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        decoder::{ObservedReader, ReadKind, ReadMetrics},
+        encoder::directory::{encode_ifd, entry, ifd_len, EncodedDirectory},
+        error::{TiffError, TiffUnsupportedError},
+        test_util::{FixtureIfd, NextIfd, TiffBuilder},
+    };
+    use async_trait::async_trait;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    /// Little-endian file with a single IFD at offset 8 that has `n_sub_ifds`
+    /// SubIfds, pointing either to a leaf IFD or back to itself
+    fn file(n_sub_ifds: usize, to_self: bool) -> Vec<u8> {
+        let mut root = EncodedDirectory::new();
+        root.insert(Tag::ImageWidth, entry(&[1u32][..]));
+        let sub_ifds = |offset: u32| {
+            let mut entry = entry(&vec![offset; n_sub_ifds][..]);
+            entry.tag_type = TagType::IFD;
+            entry
+        };
+        root.insert(Tag::SubIfds, sub_ifds(8));
+        let leaf_offset = 8 + ifd_len(&root, false);
+        if !to_self {
+            root.insert(Tag::SubIfds, sub_ifds(leaf_offset as u32));
+        }
+
+        let mut leaf = EncodedDirectory::new();
+        leaf.insert(Tag::ImageWidth, entry(&[2u32][..]));
+
+        let mut file = b"II*\0\x08\0\0\0".to_vec();
+        file.extend(encode_ifd(&root, 8, 0, ByteOrder::LittleEndian, false).unwrap());
+        file.extend(encode_ifd(&leaf, leaf_offset, 0, ByteOrder::LittleEndian, false).unwrap());
+        file
+    }
+
+    /// Reads with a delay, keeping track of how many tag reads overlap
+    #[derive(Default)]
+    struct Slow {
+        file: Vec<u8>,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CogReader for Slow {
+        async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+            self.file.read_ifd(byte_start, n_bytes).await
+        }
+
+        async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.file.read_tag_data(byte_start, n_bytes).await
+        }
+
+        async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+            self.file.read_image_data(byte_start, n_bytes).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_ifds() {
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        for i in 0..20u32 {
+            builder.push_ifd(
+                FixtureIfd::new()
+                    .entry(Tag::ImageWidth, &i)
+                    .entry(Tag::StripOffsets, &[i; 3][..]),
+            );
+        }
+        let reader = Slow {
+            file: builder.build().unwrap(),
+            ..Default::default()
+        };
+        let options = DecoderOptions {
+            header_prefetch: 0,
+            ifd_concurrency: 4,
+            ..Default::default()
+        };
+        let tiff = Tiff::read(&reader, &options).await.unwrap();
+        // in the order of the chain
+        for (i, ifd) in tiff.ifds.iter().enumerate() {
+            assert_eq!(
+                ifd.require_tag_value(&Tag::StripOffsets).unwrap(),
+                &entry(&[i as u32; 3][..])
+            );
+        }
+        assert_eq!(tiff.ifds.len(), 20);
+        assert_eq!(reader.max_in_flight.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        for _ in 0..2 {
+            builder.push_ifd(
+                FixtureIfd::new()
+                    .entry(Tag::ImageWidth, &10u32)
+                    .entry(Tag::ImageLength, &10u32)
+                    .entry(Tag::StripOffsets, &[0u32; 100][..]),
+            );
+        }
+        let file = builder.build().unwrap();
+        let read = |limits: Limits| {
+            let file = &file;
+            async move {
+                let options = DecoderOptions {
+                    header_prefetch: 0,
+                    limits,
+                    ..Default::default()
+                };
+                Tiff::read(file, &options).await
+            }
+        };
+        assert!(read(Limits::default()).await.is_ok());
+        for limits in [
+            Limits {
+                max_ifd_entries: 2,
+                ..Default::default()
+            },
+            Limits {
+                max_tag_bytes: 399,
+                ..Default::default()
+            },
+            // the offsets of both IFDs together
+            Limits {
+                max_total_bytes: 799,
+                ..Default::default()
+            },
+        ] {
+            let Err(TiffError::LimitsExceeded) = read(limits.clone()).await else {
+                panic!("{limits:?} should be exceeded");
+            };
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detect_byte_order() {
+        let file = |byte_order| {
+            let mut builder = TiffBuilder::new(byte_order, false);
+            builder.push_ifd(
+                FixtureIfd::new()
+                    .entry(Tag::ImageWidth, &10u32)
+                    .entry(Tag::StripOffsets, &[7u32; 3][..]),
+            );
+            builder.build().unwrap()
+        };
+        // a wrong mark, the rest of the header and the IFDs being big-endian
+        let mut wrong_mark = file(ByteOrder::BigEndian);
+        wrong_mark[..2].copy_from_slice(b"II");
+        // a big-endian header, with little-endian IFDs
+        let mut wrong_header = file(ByteOrder::LittleEndian);
+        let first_ifd = u32::from_le_bytes(wrong_header[4..8].try_into().unwrap());
+        wrong_header[..4].copy_from_slice(b"MM\0*");
+        wrong_header[4..8].copy_from_slice(&first_ifd.to_be_bytes());
+
+        let lenient = DecoderOptions {
+            detect_byte_order: true,
+            ..Default::default()
+        };
+        for (file, byte_order) in [
+            (wrong_mark, ByteOrder::BigEndian),
+            (wrong_header, ByteOrder::LittleEndian),
+        ] {
+            assert!(Tiff::read(&file, &DecoderOptions::default()).await.is_err());
+            let tiff = Tiff::read(&file, &lenient).await.unwrap();
+            assert!(tiff.byte_order_mismatch);
+            assert_eq!(tiff.byte_order, byte_order);
+            assert_eq!(
+                tiff.ifds[0].require_tag_value(&Tag::StripOffsets).unwrap(),
+                &entry(&[7u32; 3][..])
+            );
+        }
+        // consistent files are left alone
+        let tiff = Tiff::read(&file(ByteOrder::BigEndian), &lenient)
+            .await
+            .unwrap();
+        assert!(!tiff.byte_order_mismatch);
+    }
+
+    #[tokio::test]
+    async fn test_vendor_tag_types() {
+        let vendor = |code, data: &[u8]| BufferedEntry {
+            tag_type: TagType::Unknown(code, 2),
+            count: data.len() as u64 / 2,
+            data: data.to_vec(),
+        };
+        let inline = vendor(40, &[1, 2, 3, 4]);
+        let long = vendor(40, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        let unregistered = vendor(41, &[5, 6, 7, 8]);
+        let file = |entries: [&BufferedEntry; 3]| {
+            let mut builder = TiffBuilder::new(ByteOrder::BigEndian, false);
+            builder.push_ifd(
+                FixtureIfd::new()
+                    .entry(Tag::ImageWidth, &1u32)
+                    .raw_entry(Tag::Unknown(65000), entries[0].clone())
+                    .raw_entry(Tag::Unknown(65001), entries[1].clone())
+                    .raw_entry(Tag::Unknown(65002), entries[2].clone()),
+            );
+            builder.build().unwrap()
+        };
+        let original = file([&inline, &long, &unregistered]);
+
+        // not knowing their sizes, the offset field is kept as is
+        let tiff = Tiff::read(&original, &DecoderOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            tiff.ifds[0].get_tag(&Tag::Unknown(65000)),
+            Some(&IfdEntry::Offset {
+                tag_type: TagType::Unknown(40, 0),
+                count: 2,
+                offset: 0x01020304,
+            })
+        );
+
+        let options = DecoderOptions {
+            vendor_tag_types: [(40, 2)].into(),
+            ..Default::default()
+        };
+        let tiff = Tiff::read(&original, &options).await.unwrap();
+        let ifd = &tiff.ifds[0];
+        assert_eq!(
+            ifd.require_tag_value(&Tag::Unknown(65000)).unwrap(),
+            &inline
+        );
+        // offset entries are loaded if they were prefetched
+        assert_eq!(ifd.require_tag_value(&Tag::Unknown(65001)).unwrap(), &long);
+        let Some(&IfdEntry::Offset { tag_type, .. }) = ifd.get_tag(&Tag::Unknown(65002)) else {
+            panic!("an unregistered type should be kept as is");
+        };
+        assert_eq!(tag_type, TagType::Unknown(41, 0));
+        // and written back as they were
+        let written = file([
+            ifd.require_tag_value(&Tag::Unknown(65000)).unwrap(),
+            ifd.require_tag_value(&Tag::Unknown(65001)).unwrap(),
+            &unregistered,
+        ]);
+        assert_eq!(written, original);
+    }
+
+    #[tokio::test]
+    async fn test_cycle() {
+        for cycle_to in [0, 1] {
+            let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+            builder.push_ifd(FixtureIfd::new().entry(Tag::ImageWidth, &1u32));
+            builder.push_ifd(FixtureIfd::new().entry(Tag::ImageWidth, &2u32));
+            builder.push_ifd(
+                FixtureIfd::new()
+                    .entry(Tag::ImageWidth, &3u32)
+                    .next_ifd(NextIfd::Ifd(cycle_to)),
+            );
+            let file = builder.build().unwrap();
+            let Err(TiffError::FormatError(TiffFormatError::CycleInOffsets)) =
+                Tiff::read(&file, &DecoderOptions::default()).await
+            else {
+                panic!("the chain should have a cycle");
+            };
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_coalesced() {
+        let file: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let metrics = Arc::new(ReadMetrics::default());
+        let reader = ObservedReader::new(file.clone(), metrics.clone());
+        let ranges = [(4000, 10), (0, 10), (9000, 4), (100, 10), (105, 2)];
+        let data = read_coalesced(&reader, &ranges).await.unwrap();
+        for ((offset, n_bytes), data) in ranges.iter().zip(data) {
+            assert_eq!(data, file[*offset as usize..(offset + n_bytes) as usize]);
+        }
+        // 0..4010 and 9000..9004
+        let stats = metrics.get(ReadKind::TagData);
+        assert_eq!((stats.requests, stats.bytes), (2, 4014));
+    }
+
+    #[tokio::test]
+    async fn test_load_tags() {
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        builder.push_ifd(
+            FixtureIfd::new()
+                .entry(Tag::ImageWidth, &1u32)
+                .entry(Tag::ImageDescription, "a description")
+                .entry(Tag::Software, "some software")
+                .entry(Tag::Artist, "an artist"),
+        );
+        let metrics = Arc::new(ReadMetrics::default());
+        let file = builder.build().unwrap();
+        let file_len = file.len() as u64;
+        let reader = ObservedReader::new(file, metrics.clone());
+        let options = DecoderOptions {
+            header_prefetch: 0,
+            ..Default::default()
+        };
+        let mut tiff = Tiff::read(&reader, &options).await.unwrap();
+        assert_eq!(tiff.file_len(), Some(file_len));
+        let ifd = &mut tiff.ifds[0];
+        let loaded = |ifd: &Ifd, tag| matches!(ifd.get_tag(&tag), Some(IfdEntry::Value(_)));
+        assert!(!loaded(ifd, Tag::Software));
+        ifd.load_tags(
+            &[Tag::Software, Tag::ImageDescription],
+            &reader,
+            ByteOrder::LittleEndian,
+        )
+        .await
+        .unwrap();
+        assert_eq!(metrics.get(ReadKind::TagData).requests, 1);
+        assert_eq!(
+            ifd.get_tag_value(&Tag::Software).unwrap(),
+            Some(&entry("some software"))
+        );
+        assert!(loaded(ifd, Tag::ImageDescription));
+        assert!(!loaded(ifd, Tag::Artist));
+    }
+
+    #[tokio::test]
+    async fn test_compression_methods() {
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        // no image data, so no compression to refuse
+        builder.push_ifd(FixtureIfd::new().entry(Tag::ImageWidth, &1u32));
+        builder.push_ifd(
+            FixtureIfd::new()
+                .entry(Tag::Compression, &8u16)
+                .entry(Tag::StripOffsets, &[1u32; 3][..]),
+        );
+        let metrics = Arc::new(ReadMetrics::default());
+        let reader = ObservedReader::new(builder.build().unwrap(), metrics.clone());
+        let options = DecoderOptions {
+            header_prefetch: 0,
+            compression_methods: Some(vec![CompressionMethod::None]),
+            ..Default::default()
+        };
+        let Err(TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedCompressionMethod(
+            CompressionMethod::Deflate,
+        ))) = Tiff::read(&reader, &options).await
+        else {
+            panic!("deflate should be refused");
+        };
+        // before the offsets were fetched
+        assert_eq!(metrics.get(ReadKind::TagData).requests, 0);
+
+        let options = DecoderOptions {
+            compression_methods: Some(vec![CompressionMethod::Deflate]),
+            ..options
+        };
+        assert_eq!(Tiff::read(&reader, &options).await.unwrap().ifds.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sub_ifds() {
+        let options = DecoderOptions::default();
+        let tiff = Tiff::read(&file(3, false), &options).await.unwrap();
+        assert_eq!(tiff.ifds.len(), 1);
+        let sub_ifds = tiff.ifds[0].sub_ifds();
+        assert_eq!(sub_ifds.len(), 3);
+        assert_eq!(
+            sub_ifds[0].require_tag_value(&Tag::ImageWidth).unwrap(),
+            &entry(&[2u32][..])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sub_ifd_fan_out() {
+        let options = DecoderOptions {
+            max_sub_ifds: 2,
+            ..Default::default()
+        };
+        let Err(TiffError::FormatError(TiffFormatError::TooManySubIfds { count: 3, max: 2 })) =
+            Tiff::read(&file(3, false), &options).await
+        else {
+            panic!("3 SubIfds should be too many");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_sub_ifd_depth() {
+        // the IFD is its own sub-IFD
+        let file = file(1, true);
+        let options = DecoderOptions {
+            max_ifd_depth: 3,
+            ..Default::default()
+        };
+        let Err(TiffError::FormatError(TiffFormatError::IfdTooDeep(3))) =
+            Tiff::read(&file, &options).await
+        else {
+            panic!("self-referencing SubIfds should be too deep");
+        };
+    }
+}
@@ -0,0 +1,257 @@
+//! [`image::ImageDecoder`] for an overview level, so the `image` crate can
+//! read TIFFs through this one.
+//!
+//! Decoding is async, so [`TiffImageDecoder`] drives it on a current-thread
+//! tokio runtime of its own, blocking the calling thread until it is done. It
+//! must not be used from within an async context, where the decode methods of
+//! [`CogDecoder`] can be awaited instead.
+
+use std::sync::Arc;
+
+use image::{
+    error::{
+        DecodingError, ImageFormatHint, LimitError, LimitErrorKind, ParameterError,
+        ParameterErrorKind, UnsupportedError, UnsupportedErrorKind,
+    },
+    ImageDecoder, ImageDecoderRect, ImageError, ImageFormat, ImageResult,
+};
+use tokio::runtime::{self, Runtime};
+
+use crate::{
+    decoder::{dynamic_image, CogDecoder, CogReader, DecoderOptions, OverviewLevel, SampleType},
+    error::{TiffError, TiffResult, TiffUnsupportedError, UsageError},
+    structs::Tiff,
+};
+
+/// Blocking decoder of one overview level, for the `image` crate
+///
+/// Pixels are those of [`CogDecoder::decode_region_interleaved`], so images
+/// are oriented with [`DecoderOptions::apply_orientation`]. Only color types
+/// that [`to_dynamic_image`] supports can be decoded.
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use image::DynamicImage;
+/// # use tiff2::decoder::{DecoderOptions, FileReader, TiffImageDecoder};
+/// let reader = Arc::new(FileReader::open("cog.tif").unwrap());
+/// let decoder = TiffImageDecoder::open(reader, DecoderOptions::default()).unwrap();
+/// let image = DynamicImage::from_decoder(decoder).unwrap();
+/// ```
+///
+/// [`to_dynamic_image`]: crate::decoder::to_dynamic_image
+pub struct TiffImageDecoder {
+    decoder: CogDecoder,
+    level: OverviewLevel,
+    runtime: Runtime,
+    dimensions: (u32, u32),
+    color_type: image::ColorType,
+}
+
+impl TiffImageDecoder {
+    /// Read the header and IFDs of a file, to decode its full resolution
+    /// image
+    pub fn open(reader: Arc<dyn CogReader>, options: DecoderOptions) -> TiffResult<Self> {
+        let runtime = new_runtime()?;
+        let mut tiff = runtime.block_on(Tiff::read(&*reader, &options))?;
+        let ifds = std::mem::take(&mut tiff.ifds);
+        let mut decoder = CogDecoder::new(reader, tiff, &options);
+        decoder.insert_levels(ifds)?;
+        Self::with_runtime(decoder, 0, runtime)
+    }
+
+    /// Decode an overview level of an opened decoder.
+    ///
+    /// Fails with [`UsageError::OverviewNotLoaded`] if the level isn't loaded,
+    /// and with [`TiffUnsupportedError::UnsupportedColorType`] if the `image`
+    /// crate has no color type for its samples.
+    pub fn new(decoder: CogDecoder, level: OverviewLevel) -> TiffResult<Self> {
+        Self::with_runtime(decoder, level, new_runtime()?)
+    }
+
+    fn with_runtime(
+        decoder: CogDecoder,
+        level: OverviewLevel,
+        runtime: Runtime,
+    ) -> TiffResult<Self> {
+        let img = decoder
+            .image(level)
+            .ok_or(UsageError::OverviewNotLoaded(level))?;
+        let meta = img.chunk_meta();
+        let color_type = img.color_type()?;
+        let sample_type = SampleType::from_format(meta.sample_format, meta.bits_per_sample)
+            .ok_or(TiffUnsupportedError::UnsupportedColorType(color_type))?;
+        Ok(TiffImageDecoder {
+            color_type: dynamic_image::image_color_type(color_type, sample_type)?,
+            dimensions: decoder.interleaved_size(level)?,
+            decoder,
+            level,
+            runtime,
+        })
+    }
+
+    pub fn decoder(&self) -> &CogDecoder {
+        &self.decoder
+    }
+
+    pub fn into_decoder(self) -> CogDecoder {
+        self.decoder
+    }
+
+    /// Decode whole rows starting at row `y`, as many as fit in `buf` and the
+    /// image, returning their number. Reading an image a strip at a time
+    /// this way needs no buffer for all of it.
+    pub fn read_scanlines(&mut self, y: u32, buf: &mut [u8]) -> ImageResult<u32> {
+        let (width, height) = self.dimensions;
+        let row_bytes = self.row_bytes(width);
+        let fit = u32::try_from(buf.len() / row_bytes.max(1)).unwrap_or(u32::MAX);
+        let rows = fit.min(height.saturating_sub(y));
+        if rows > 0 {
+            self.read_rect(0, y, width, rows, buf, row_bytes)?;
+        }
+        Ok(rows)
+    }
+
+    fn row_bytes(&self, width: u32) -> usize {
+        width as usize * usize::from(self.color_type.bytes_per_pixel())
+    }
+
+    /// Interleaved pixels of a window of the level
+    fn decode(&self, x: u32, y: u32, width: u32, height: u32) -> ImageResult<Vec<u8>> {
+        let region = self
+            .decoder
+            .decode_region_interleaved(self.level, x, y, width, height)
+            .map_err(image_error)?;
+        self.runtime.block_on(region).map_err(image_error)
+    }
+}
+
+impl ImageDecoder for TiffImageDecoder {
+    fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    fn color_type(&self) -> image::ColorType {
+        self.color_type
+    }
+
+    fn read_image(mut self, buf: &mut [u8]) -> ImageResult<()> {
+        let (width, height) = self.dimensions;
+        let row_bytes = self.row_bytes(width);
+        self.read_rect(0, 0, width, height, buf, row_bytes)
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+impl ImageDecoderRect for TiffImageDecoder {
+    fn read_rect(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        buf: &mut [u8],
+        row_pitch: usize,
+    ) -> ImageResult<()> {
+        let row_bytes = self.row_bytes(width);
+        let needed = (height as usize).checked_sub(1).map_or(Some(0), |rows| {
+            rows.checked_mul(row_pitch)?.checked_add(row_bytes)
+        });
+        if row_pitch < row_bytes || needed.is_none_or(|needed| buf.len() < needed) {
+            return Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::DimensionMismatch,
+            )));
+        }
+        let data = self.decode(x, y, width, height)?;
+        if row_bytes > 0 {
+            for (row, out) in data.chunks_exact(row_bytes).zip(buf.chunks_mut(row_pitch)) {
+                out[..row_bytes].copy_from_slice(row);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn new_runtime() -> TiffResult<Runtime> {
+    Ok(runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()?)
+}
+
+/// The error as the `image` crate reports it
+fn image_error(err: TiffError) -> ImageError {
+    let format = ImageFormatHint::Exact(ImageFormat::Tiff);
+    match err {
+        TiffError::IoError(e) => ImageError::IoError(e),
+        TiffError::LimitsExceeded => {
+            ImageError::Limits(LimitError::from_kind(LimitErrorKind::InsufficientMemory))
+        }
+        TiffError::UnsupportedError(e) => {
+            ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+                format,
+                UnsupportedErrorKind::GenericFeature(e.to_string()),
+            ))
+        }
+        e => ImageError::Decoding(DecodingError::new(format, e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        encoder::{test_cog, Level},
+        ColorType,
+    };
+    use image::DynamicImage;
+
+    /// 20x12 RGB in 16x16 tiles, red and green being x and y
+    fn rgb_cog(color_type: ColorType) -> Arc<dyn CogReader> {
+        let tiles = (0..2u8)
+            .map(|tile_x| {
+                (0..16 * 16u8 as usize)
+                    .flat_map(|i| [tile_x * 16 + (i % 16) as u8, (i / 16) as u8, 7])
+                    .collect()
+            })
+            .collect();
+        Arc::new(test_cog([Level::tiled(20, 12, 16, color_type, tiles)]))
+    }
+
+    #[test]
+    fn test_image_decoder() {
+        let decoder =
+            TiffImageDecoder::open(rgb_cog(ColorType::RGB(8)), DecoderOptions::default()).unwrap();
+        assert_eq!(decoder.dimensions(), (20, 12));
+        assert_eq!(decoder.color_type(), image::ColorType::Rgb8);
+        let image = DynamicImage::from_decoder(decoder).unwrap().to_rgb8();
+        assert_eq!(image.get_pixel(17, 5).0, [17, 5, 7]);
+        assert_eq!(image.get_pixel(19, 11).0, [19, 11, 7]);
+
+        // a window into a wider buffer
+        let mut decoder =
+            TiffImageDecoder::open(rgb_cog(ColorType::RGB(8)), DecoderOptions::default()).unwrap();
+        let mut buf = [0; 2 * 12 + 6];
+        decoder.read_rect(15, 3, 2, 2, &mut buf, 12).unwrap();
+        assert_eq!(buf[..6], [15, 3, 7, 16, 3, 7]);
+        assert_eq!(buf[6..12], [0; 6]);
+        assert_eq!(buf[12..18], [15, 4, 7, 16, 4, 7]);
+        // too small a buffer or row pitch
+        assert!(decoder.read_rect(0, 0, 4, 3, &mut buf, 12).is_err());
+        assert!(decoder.read_rect(0, 0, 5, 1, &mut buf, 12).is_err());
+
+        // scanlines, as many as fit
+        let mut rows = vec![0; 5 * 20 * 3];
+        assert_eq!(decoder.read_scanlines(10, &mut rows).unwrap(), 2);
+        assert_eq!(rows[20 * 3..20 * 3 + 3], [0, 11, 7]);
+        assert_eq!(decoder.read_scanlines(12, &mut rows).unwrap(), 0);
+
+        // the image crate has no L*a*b*
+        let Err(TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedColorType(_))) =
+            TiffImageDecoder::open(rgb_cog(ColorType::Lab(8)), DecoderOptions::default())
+        else {
+            panic!("L*a*b* can't be decoded for the image crate");
+        };
+    }
+}
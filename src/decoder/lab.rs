@@ -0,0 +1,188 @@
+//! Converting L*a*b* samples to RGB.
+//!
+//! `CIELab` images hold L* as unsigned and a* and b* as signed samples,
+//! `ICCLab` ones hold all three unsigned, a* and b* offset by half their
+//! range, as ICC profiles encode them. Either is relative to the D50 white
+//! point, which is adapted to the D65 one of sRGB with the Bradford transform
+//! on conversion, which needs `std` for its floating point functions.
+
+use alloc::vec::Vec;
+
+use crate::{
+    error::{TiffResult, TiffUnsupportedError},
+    structs::tags::PhotometricInterpretation,
+};
+
+/// How the L*, a* and b* of an image are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabEncoding {
+    /// `PhotometricInterpretation::CIELab`: a* and b* signed
+    CieLab,
+    /// `PhotometricInterpretation::ICCLab`: a* and b* offset by half their
+    /// range
+    IccLab,
+}
+
+impl LabEncoding {
+    /// The encoding of images of a photometric interpretation, `None` if they
+    /// don't hold L*a*b*
+    pub fn from_photometric(photometric: PhotometricInterpretation) -> Option<Self> {
+        match photometric {
+            PhotometricInterpretation::CIELab => Some(LabEncoding::CieLab),
+            PhotometricInterpretation::ICCLab => Some(LabEncoding::IccLab),
+            _ => None,
+        }
+    }
+
+    /// L* (0 to 100), a* and b* of pixels of 8 or 16-bit samples in native
+    /// byte order.
+    ///
+    /// Fails with [`TiffUnsupportedError::UnsupportedBitsPerChannel`] for
+    /// other samples.
+    pub fn to_lab(&self, data: &[u8], bits_per_sample: u8) -> TiffResult<Vec<f32>> {
+        let samples: Vec<[f32; 3]> = match bits_per_sample {
+            8 => data
+                .chunks_exact(3)
+                .map(|pixel| self.lab8([pixel[0], pixel[1], pixel[2]]))
+                .collect(),
+            16 => data
+                .chunks_exact(6)
+                .map(|pixel| {
+                    let sample = |i: usize| u16::from_ne_bytes([pixel[2 * i], pixel[2 * i + 1]]);
+                    self.lab16([sample(0), sample(1), sample(2)])
+                })
+                .collect(),
+            _ => {
+                return Err(TiffUnsupportedError::UnsupportedBitsPerChannel(bits_per_sample).into())
+            }
+        };
+        Ok(samples.into_iter().flatten().collect())
+    }
+
+    /// Convert pixels of 8 or 16-bit samples in native byte order to 8-bit
+    /// sRGB, clipping colors outside its gamut.
+    ///
+    /// Fails like [`Self::to_lab`].
+    #[cfg(feature = "std")]
+    pub fn to_rgb8(&self, data: &[u8], bits_per_sample: u8) -> TiffResult<Vec<u8>> {
+        let lab = self.to_lab(data, bits_per_sample)?;
+        Ok(lab
+            .chunks_exact(3)
+            .flat_map(|lab| lab_to_rgb8([lab[0], lab[1], lab[2]]))
+            .collect())
+    }
+
+    fn lab8(&self, [l, a, b]: [u8; 3]) -> [f32; 3] {
+        let l = f32::from(l) * 100.0 / 255.0;
+        match self {
+            LabEncoding::CieLab => [l, f32::from(a as i8), f32::from(b as i8)],
+            LabEncoding::IccLab => [l, f32::from(a) - 128.0, f32::from(b) - 128.0],
+        }
+    }
+
+    fn lab16(&self, [l, a, b]: [u16; 3]) -> [f32; 3] {
+        match self {
+            LabEncoding::CieLab => [
+                f32::from(l) * 100.0 / 65535.0,
+                f32::from(a as i16) / 256.0,
+                f32::from(b as i16) / 256.0,
+            ],
+            // the ICC's 16-bit encoding, white at 0xff00
+            LabEncoding::IccLab => [
+                f32::from(l) * 100.0 / 65280.0,
+                f32::from(a) / 256.0 - 128.0,
+                f32::from(b) / 256.0 - 128.0,
+            ],
+        }
+    }
+}
+
+/// XYZ of the D50 white point
+#[cfg(feature = "std")]
+const D50: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+/// D50 XYZ to linear sRGB, Bradford adapted
+#[cfg(feature = "std")]
+const XYZ_TO_RGB: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_142, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+
+#[cfg(feature = "std")]
+fn lab_to_rgb8([l, a, b]: [f32; 3]) -> [u8; 3] {
+    let fy = (l + 16.0) / 116.0;
+    let f = [fy + a / 500.0, fy, fy - b / 200.0];
+    let xyz: [f32; 3] = core::array::from_fn(|i| D50[i] * f_inv(f[i]));
+    XYZ_TO_RGB.map(|row| {
+        let linear = row[0] * xyz[0] + row[1] * xyz[1] + row[2] * xyz[2];
+        (gamma(linear.clamp(0.0, 1.0)) * 255.0 + 0.5) as u8
+    })
+}
+
+/// Inverse of the cube root like function of the CIE L*a*b* definition
+#[cfg(feature = "std")]
+fn f_inv(t: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    if t > DELTA {
+        t * t * t
+    } else {
+        3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+    }
+}
+
+/// The sRGB transfer function
+#[cfg(feature = "std")]
+fn gamma(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_lab() {
+        // white, and a* and b* of -1 and 1
+        let cie = LabEncoding::CieLab.to_lab(&[255, 0xff, 1], 8).unwrap();
+        assert_eq!(cie, [100.0, -1.0, 1.0]);
+        let icc = LabEncoding::IccLab.to_lab(&[255, 127, 129], 8).unwrap();
+        assert_eq!(icc, cie);
+        let samples: Vec<u8> = [0xff00u16, 0x7f00, 0x8100]
+            .iter()
+            .flat_map(|s| s.to_ne_bytes())
+            .collect();
+        assert_eq!(LabEncoding::IccLab.to_lab(&samples, 16).unwrap(), cie);
+        let samples: Vec<u8> = [0xffffu16, 0xff00, 0x0100]
+            .iter()
+            .flat_map(|s| s.to_ne_bytes())
+            .collect();
+        assert_eq!(LabEncoding::CieLab.to_lab(&samples, 16).unwrap(), cie);
+        assert!(LabEncoding::CieLab.to_lab(&[0; 3], 4).is_err());
+        assert_eq!(
+            LabEncoding::from_photometric(PhotometricInterpretation::ICCLab),
+            Some(LabEncoding::IccLab)
+        );
+        assert_eq!(
+            LabEncoding::from_photometric(PhotometricInterpretation::RGB),
+            None
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_to_rgb8() {
+        // white, black, mid gray, and sRGB red at L* 53.24, a* 80.09, b* 67.20
+        // (D65), which is 54.29, 80.80, 69.89 adapted to D50
+        let lab = [255, 0, 0, 0, 0, 0, 128, 0, 0, 138, 81, 70];
+        let rgb = LabEncoding::CieLab.to_rgb8(&lab, 8).unwrap();
+        assert_eq!(rgb[..6], [255, 255, 255, 0, 0, 0]);
+        assert!(rgb[6..9].iter().all(|&s| s.abs_diff(rgb[6]) <= 1));
+        assert!(rgb[6].abs_diff(119) <= 1);
+        assert!(rgb[9] >= 250 && rgb[10] <= 8 && rgb[11] <= 8);
+    }
+}
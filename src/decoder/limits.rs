@@ -0,0 +1,57 @@
+//! Upper bounds on what a file can make the decoder allocate.
+//!
+//! Counts and sizes in a TIFF are just numbers, so a forged file can ask for
+//! gigabytes with a handful of bytes. Everything that allocates based on them
+//! checks against a [`Limits`] first, failing with
+//! [`TiffError::LimitsExceeded`].
+//!
+//! [`Limits`]: crate::decoder::Limits
+//! [`TiffError::LimitsExceeded`]: crate::error::TiffError::LimitsExceeded
+
+use crate::error::{TiffError, TiffResult};
+
+/// Limits on allocations driven by a file's contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of entries in a single IFD, 4096 by default
+    pub max_ifd_entries: u64,
+    /// Maximum size of a single tag's data, 64 MiB by default
+    pub max_tag_bytes: u64,
+    /// Maximum size of a single chunk, compressed or decoded, 256 MiB by
+    /// default
+    pub max_chunk_bytes: u64,
+    /// Maximum total size of the tag data of a file, and of a decoded region,
+    /// 1 GiB by default
+    pub max_total_bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_ifd_entries: 4096,
+            max_tag_bytes: 64 * 1024 * 1024,
+            max_chunk_bytes: 256 * 1024 * 1024,
+            max_total_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// No limits at all, for trusted files
+    pub fn unlimited() -> Self {
+        Limits {
+            max_ifd_entries: u64::MAX,
+            max_tag_bytes: u64::MAX,
+            max_chunk_bytes: u64::MAX,
+            max_total_bytes: u64::MAX,
+        }
+    }
+
+    /// Fail with [`TiffError::LimitsExceeded`] if `n` is above `limit`
+    pub(crate) fn check(n: u64, limit: u64) -> TiffResult<()> {
+        if n > limit {
+            return Err(TiffError::LimitsExceeded);
+        }
+        Ok(())
+    }
+}
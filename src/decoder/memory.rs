@@ -0,0 +1,207 @@
+//! [`CogReader`] implementations for in-memory files, so small TIFFs and unit
+//! tests can go through the full async decode pipeline without touching the
+//! network or filesystem.
+//!
+//! `Vec<u8>` and [`Bytes`] are readers as they are, the latter handing out
+//! slices of itself rather than copies. [`MemoryReader`] does the same and
+//! adds a [`SourceKey`] for sharing caches, and [`MemoryReader::slice`] for
+//! getting at ranges of the file without going through the async methods,
+//! e.g. to pass compressed tiles on as they are.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{
+    decoder::{
+        reader::{slice_bytes, slice_prefix, slice_range},
+        CogReader, SourceKey,
+    },
+    error::TiffResult,
+};
+
+#[async_trait]
+impl CogReader for Vec<u8> {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        Ok(Bytes::copy_from_slice(slice_range(
+            self, byte_start, n_bytes,
+        )?))
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        Ok(Bytes::copy_from_slice(slice_range(
+            self, byte_start, n_bytes,
+        )?))
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        Ok(Bytes::copy_from_slice(slice_range(
+            self, byte_start, n_bytes,
+        )?))
+    }
+
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        Ok(Bytes::copy_from_slice(slice_prefix(self, n_bytes)))
+    }
+
+    fn file_len(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+}
+
+#[async_trait]
+impl CogReader for Bytes {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        slice_bytes(self, byte_start, n_bytes)
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        slice_bytes(self, byte_start, n_bytes)
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        slice_bytes(self, byte_start, n_bytes)
+    }
+
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        Ok(self.slice_ref(slice_prefix(self, n_bytes)))
+    }
+
+    fn file_len(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+}
+
+/// Reads from a file held in memory
+#[derive(Debug, Clone)]
+pub struct MemoryReader {
+    data: Bytes,
+    source: SourceKey,
+}
+
+impl MemoryReader {
+    /// Read from `data`, with a [unique](SourceKey::unique) [`SourceKey`]
+    pub fn new(data: impl Into<Bytes>) -> Self {
+        MemoryReader {
+            data: data.into(),
+            source: SourceKey::unique(),
+        }
+    }
+
+    /// Cache data under `source` instead, e.g. the URL the file was fetched
+    /// from
+    pub fn with_source(mut self, source: SourceKey) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// The whole file
+    pub fn bytes(&self) -> &Bytes {
+        &self.data
+    }
+
+    /// `n_bytes` bytes starting at `byte_start`, sharing memory with the file
+    pub fn slice(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        slice_bytes(&self.data, byte_start, n_bytes)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.data
+    }
+}
+
+impl From<Vec<u8>> for MemoryReader {
+    fn from(data: Vec<u8>) -> Self {
+        MemoryReader::new(data)
+    }
+}
+
+impl From<Bytes> for MemoryReader {
+    fn from(data: Bytes) -> Self {
+        MemoryReader::new(data)
+    }
+}
+
+#[async_trait]
+impl CogReader for MemoryReader {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        slice_bytes(&self.data, byte_start, n_bytes)
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        slice_bytes(&self.data, byte_start, n_bytes)
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        slice_bytes(&self.data, byte_start, n_bytes)
+    }
+
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        Ok(self.data.slice_ref(slice_prefix(&self.data, n_bytes)))
+    }
+
+    fn file_len(&self) -> Option<u64> {
+        Some(self.len())
+    }
+
+    fn source_id(&self) -> SourceKey {
+        self.source.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::TiffError;
+
+    #[tokio::test]
+    async fn test_vec_reader() {
+        let reader = b"II*\0\x08\0\0\0abcdef".to_vec();
+        assert_eq!(reader.read_ifd(0, 4).await.unwrap(), &b"II*\0"[..]);
+        assert_eq!(reader.read_tag_data(8, 3).await.unwrap(), &b"abc"[..]);
+        assert_eq!(reader.read_image_data(11, 3).await.unwrap(), &b"def"[..]);
+        assert!(matches!(
+            reader.read_image_data(11, 4).await,
+            Err(TiffError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bytes_reader() {
+        let reader = Bytes::from_static(b"MM\0*\0\0\0\x08abcdef");
+        assert_eq!(reader.read_ifd(0, 4).await.unwrap(), &b"MM\0*"[..]);
+        assert_eq!(reader.read_image_data(8, 6).await.unwrap(), &b"abcdef"[..]);
+        assert!(reader.read_tag_data(u64::MAX, 1).await.is_err());
+        // prefetching more than the whole file just returns the file
+        assert_eq!(reader.read_header(16 * 1024).await.unwrap(), reader);
+        // reads share memory with the file
+        let data = reader.read_tag_data(9, 2).await.unwrap();
+        assert_eq!(data.as_ptr(), reader[9..].as_ptr());
+    }
+
+    #[tokio::test]
+    async fn test_memory_reader() {
+        let reader = MemoryReader::new(b"II*\0\x08\0\0\0abcdef".to_vec());
+        assert_eq!(reader.len(), 14);
+        assert_ne!(
+            reader.source_id(),
+            MemoryReader::new(Vec::new()).source_id()
+        );
+        assert_eq!(reader.read_ifd(0, 4).await.unwrap(), &b"II*\0"[..]);
+        assert_eq!(reader.read_header(100).await.unwrap().len(), 14);
+        let slice = reader.slice(8, 6).unwrap();
+        assert_eq!(slice, &b"abcdef"[..]);
+        assert_eq!(slice.as_ptr(), reader.bytes()[8..].as_ptr());
+        assert!(reader.slice(8, 7).is_err());
+        assert!(reader.slice(u64::MAX, 1).is_err());
+        let reader = reader.with_source(SourceKey::Custom("tile".into()));
+        assert_eq!(reader.source_id(), SourceKey::Custom("tile".into()));
+    }
+}
@@ -0,0 +1,299 @@
+//! Read metrics for [`CogReader`]s.
+//!
+//! Wrap a reader in an [`ObservedReader`] to report every read to a
+//! [`ReadObserver`], e.g. to find out how many requests a tile server needed
+//! for a single tile. [`ReadMetrics`] is a ready-made observer that sums
+//! everything up per category.
+
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{
+    decoder::{CogReader, SourceKey},
+    error::TiffResult,
+};
+
+/// Which [`CogReader`] method was called
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReadKind {
+    Ifd,
+    TagData,
+    ImageData,
+}
+
+/// A single read, as reported to a [`ReadObserver`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadEvent {
+    pub kind: ReadKind,
+    /// Start of the range, or of the first range of a vectored read
+    pub byte_start: u64,
+    /// Number of bytes requested, in all ranges of a vectored read
+    pub n_bytes: u64,
+    /// Time until the read returned
+    pub latency: Duration,
+    pub success: bool,
+}
+
+/// Gets notified of reads. Implement this to forward reads to e.g. a metrics
+/// library.
+pub trait ReadObserver: Send + Sync {
+    fn on_read(&self, event: &ReadEvent);
+}
+
+/// Wraps a [`CogReader`], reporting each read to an observer
+pub struct ObservedReader<R> {
+    inner: R,
+    observer: Arc<dyn ReadObserver>,
+}
+
+impl<R: CogReader> ObservedReader<R> {
+    pub fn new(inner: R, observer: Arc<dyn ReadObserver>) -> Self {
+        ObservedReader { inner, observer }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    async fn observe<T, F>(
+        &self,
+        kind: ReadKind,
+        byte_start: u64,
+        n_bytes: u64,
+        read: F,
+    ) -> TiffResult<T>
+    where
+        F: core::future::Future<Output = TiffResult<T>>,
+    {
+        let start = Instant::now();
+        let result = read.await;
+        self.observer.on_read(&ReadEvent {
+            kind,
+            byte_start,
+            n_bytes,
+            latency: start.elapsed(),
+            success: result.is_ok(),
+        });
+        result
+    }
+}
+
+#[async_trait]
+impl<R: CogReader> CogReader for ObservedReader<R> {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.observe(
+            ReadKind::Ifd,
+            byte_start,
+            n_bytes,
+            self.inner.read_ifd(byte_start, n_bytes),
+        )
+        .await
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.observe(
+            ReadKind::TagData,
+            byte_start,
+            n_bytes,
+            self.inner.read_tag_data(byte_start, n_bytes),
+        )
+        .await
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.observe(
+            ReadKind::ImageData,
+            byte_start,
+            n_bytes,
+            self.inner.read_image_data(byte_start, n_bytes),
+        )
+        .await
+    }
+
+    /// Reported as a single image data read
+    async fn read_vectored(&self, ranges: &[Range<u64>]) -> TiffResult<Vec<Bytes>> {
+        let n_bytes = ranges.iter().map(|r| r.end.saturating_sub(r.start)).sum();
+        self.observe(
+            ReadKind::ImageData,
+            ranges.first().map_or(0, |r| r.start),
+            n_bytes,
+            self.inner.read_vectored(ranges),
+        )
+        .await
+    }
+
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        self.observe(ReadKind::Ifd, 0, n_bytes, self.inner.read_header(n_bytes))
+            .await
+    }
+
+    fn file_len(&self) -> Option<u64> {
+        self.inner.file_len()
+    }
+
+    fn source_id(&self) -> SourceKey {
+        self.inner.source_id()
+    }
+}
+
+/// Totals for one [`ReadKind`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadStats {
+    pub requests: u64,
+    pub failed_requests: u64,
+    /// Bytes requested by successful reads
+    pub bytes: u64,
+    /// Summed latency of all requests
+    pub latency: Duration,
+}
+
+#[derive(Debug, Default)]
+struct AtomicStats {
+    requests: AtomicU64,
+    failed_requests: AtomicU64,
+    bytes: AtomicU64,
+    latency_nanos: AtomicU64,
+}
+
+impl AtomicStats {
+    fn add(&self, event: &ReadEvent) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if event.success {
+            self.bytes.fetch_add(event.n_bytes, Ordering::Relaxed);
+        } else {
+            self.failed_requests.fetch_add(1, Ordering::Relaxed);
+        }
+        let nanos = u64::try_from(event.latency.as_nanos()).unwrap_or(u64::MAX);
+        self.latency_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    fn load(&self) -> ReadStats {
+        ReadStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            failed_requests: self.failed_requests.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            latency: Duration::from_nanos(self.latency_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Observer that counts requests, bytes and latency per [`ReadKind`]
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use tiff2::decoder::{CogReader, ObservedReader, ReadKind, ReadMetrics};
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let metrics = Arc::new(ReadMetrics::default());
+/// let reader = ObservedReader::new(vec![0u8; 1024], metrics.clone());
+/// reader.read_image_data(0, 512).await.unwrap();
+/// assert_eq!(metrics.get(ReadKind::ImageData).requests, 1);
+/// assert_eq!(metrics.get(ReadKind::ImageData).bytes, 512);
+/// # });
+/// ```
+#[derive(Debug, Default)]
+pub struct ReadMetrics {
+    ifd: AtomicStats,
+    tag_data: AtomicStats,
+    image_data: AtomicStats,
+}
+
+impl ReadMetrics {
+    fn stats(&self, kind: ReadKind) -> &AtomicStats {
+        match kind {
+            ReadKind::Ifd => &self.ifd,
+            ReadKind::TagData => &self.tag_data,
+            ReadKind::ImageData => &self.image_data,
+        }
+    }
+
+    /// Totals for one kind of read
+    pub fn get(&self, kind: ReadKind) -> ReadStats {
+        self.stats(kind).load()
+    }
+
+    /// Totals over all kinds of reads
+    pub fn total(&self) -> ReadStats {
+        [ReadKind::Ifd, ReadKind::TagData, ReadKind::ImageData]
+            .into_iter()
+            .map(|kind| self.get(kind))
+            .fold(ReadStats::default(), |acc, s| ReadStats {
+                requests: acc.requests + s.requests,
+                failed_requests: acc.failed_requests + s.failed_requests,
+                bytes: acc.bytes + s.bytes,
+                latency: acc.latency + s.latency,
+            })
+    }
+}
+
+impl ReadObserver for ReadMetrics {
+    fn on_read(&self, event: &ReadEvent) {
+        self.stats(event.kind).add(event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<ReadEvent>>);
+
+    impl ReadObserver for Recorder {
+        fn on_read(&self, event: &ReadEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observer() {
+        let recorder = Arc::new(Recorder::default());
+        let reader = ObservedReader::new(vec![0u8; 16], recorder.clone());
+        reader.read_ifd(0, 8).await.unwrap();
+        reader.read_tag_data(8, 4).await.unwrap();
+        assert!(reader.read_image_data(12, 8).await.is_err());
+        let events = recorder.0.lock().unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.kind, e.byte_start, e.n_bytes, e.success))
+                .collect::<Vec<_>>(),
+            vec![
+                (ReadKind::Ifd, 0, 8, true),
+                (ReadKind::TagData, 8, 4, true),
+                (ReadKind::ImageData, 12, 8, false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let metrics = Arc::new(ReadMetrics::default());
+        let reader = ObservedReader::new(vec![0u8; 16], metrics.clone());
+        reader.read_image_data(0, 4).await.unwrap();
+        reader.read_image_data(4, 4).await.unwrap();
+        reader.read_image_data(12, 8).await.unwrap_err();
+        reader.read_ifd(0, 2).await.unwrap();
+        let image_data = metrics.get(ReadKind::ImageData);
+        assert_eq!(
+            (
+                image_data.requests,
+                image_data.failed_requests,
+                image_data.bytes
+            ),
+            (3, 1, 8)
+        );
+        assert_eq!(metrics.get(ReadKind::TagData), ReadStats::default());
+        assert_eq!(metrics.total().requests, 4);
+        assert_eq!(metrics.total().bytes, 10);
+    }
+}
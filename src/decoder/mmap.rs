@@ -0,0 +1,139 @@
+//! [`CogReader`] over a memory-mapped local file.
+//!
+//! Reads are slices of the map that share it rather than copy it, which makes
+//! this useful for benchmarking the async decode path against local data
+//! without any (fake) async file I/O.
+
+use std::{fmt, fs::File, path::Path};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use memmap2::Mmap;
+
+use crate::{
+    decoder::{
+        reader::{slice_bytes, slice_prefix},
+        CogReader, SourceKey,
+    },
+    error::TiffResult,
+};
+
+/// Reads from a memory-mapped file
+pub struct MmapReader {
+    mmap: Bytes,
+    source: SourceKey,
+}
+
+impl MmapReader {
+    /// Open and memory-map the file at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> TiffResult<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let mut reader = Self::from_file(&file)?;
+        reader.source = SourceKey::file(path, &file)?;
+        Ok(reader)
+    }
+
+    /// Memory-map an already opened file
+    ///
+    /// As with any memory map, the file should not be modified while it is
+    /// mapped. The path of the file is unknown, so its [`SourceKey`] is
+    /// [unique](SourceKey::unique).
+    pub fn from_file(file: &File) -> TiffResult<Self> {
+        // SAFETY: the map is read-only. Modifying the underlying file while
+        // it is mapped is undefined behaviour, which is documented above.
+        let mmap = unsafe { Mmap::map(file)? };
+        Ok(MmapReader {
+            mmap: Bytes::from_owner(mmap),
+            source: SourceKey::unique(),
+        })
+    }
+
+    /// Length of the mapped file
+    pub fn len(&self) -> u64 {
+        self.mmap.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mmap.is_empty()
+    }
+
+    fn read_range(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        slice_bytes(&self.mmap, byte_start, n_bytes)
+    }
+}
+
+impl fmt::Debug for MmapReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmapReader")
+            .field("len", &self.mmap.len())
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl CogReader for MmapReader {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(byte_start, n_bytes)
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(byte_start, n_bytes)
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(byte_start, n_bytes)
+    }
+
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        Ok(self.mmap.slice_ref(slice_prefix(&self.mmap, n_bytes)))
+    }
+
+    fn file_len(&self) -> Option<u64> {
+        Some(self.len())
+    }
+
+    fn source_id(&self) -> SourceKey {
+        self.source.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::TiffError;
+    use std::io::{self, Write};
+
+    #[tokio::test]
+    async fn test_read_mapped_file() {
+        let path = std::env::temp_dir().join(format!("tiff2-mmap-{}.tif", std::process::id()));
+        File::create(&path)
+            .unwrap()
+            .write_all(b"II*\0\x08\0\0\0abcdef")
+            .unwrap();
+        let reader = MmapReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 14);
+        let SourceKey::Path {
+            path: key_path,
+            len,
+            ..
+        } = reader.source_id()
+        else {
+            panic!("an opened file should be keyed by its path");
+        };
+        assert_eq!((key_path, len), (path.canonicalize().unwrap(), 14));
+        assert_eq!(reader.read_ifd(0, 4).await.unwrap(), &b"II*\0"[..]);
+        let data = reader.read_image_data(8, 6).await.unwrap();
+        assert_eq!(data, &b"abcdef"[..]);
+        // slices of the map rather than copies
+        let header = reader.read_header(100).await.unwrap();
+        assert_eq!(data.as_ptr(), header[8..].as_ptr());
+        let TiffError::IoError(e) = reader.read_tag_data(12, 4).await.unwrap_err() else {
+            panic!("reading past the end should be an io error");
+        };
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        drop(reader);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,3 +1,90 @@
+#[cfg(feature = "ndarray")]
+mod array;
+#[cfg(feature = "ndarray")]
+pub use array::{to_array3, ArraySample, AxisOrder};
+mod band_math;
+pub use band_math::BandMath;
+mod bitmap;
+pub use bitmap::{BilevelOutput, PackedBitmap};
+mod depth;
+#[cfg(feature = "image")]
+mod dynamic_image;
+pub use depth::{u16_to_u8, u8_to_u16, Dither};
+#[cfg(feature = "image")]
+pub use dynamic_image::to_dynamic_image;
+#[cfg(feature = "image")]
+mod image_decoder;
+#[cfg(feature = "image")]
+pub use image_decoder::TiffImageDecoder;
+mod lab;
+pub use lab::LabEncoding;
+mod limits;
+pub use limits::Limits;
+mod orientation;
+mod palette;
+pub use palette::Palette;
+mod render;
+pub use render::{apply_mask, render_rgba};
+mod ycbcr;
+pub use ycbcr::YCbCrConversion;
 mod reader;
-pub use reader::{CogReader, EndianReader};
+pub use reader::EndianReader;
+#[cfg(feature = "std")]
+pub use reader::{is_transient, CogReader, RetryingReader};
+#[cfg(feature = "std")]
+mod cache;
+#[cfg(feature = "std")]
+pub use cache::{CacheKey, LruCache, SourceKey, WithSourceId};
+#[cfg(feature = "std")]
+mod coalesce;
+#[cfg(feature = "std")]
+pub use coalesce::CoalescingReader;
+#[cfg(feature = "std")]
+mod file;
+#[cfg(feature = "std")]
+pub use file::FileReader;
+#[cfg(feature = "std")]
+mod memory;
+#[cfg(feature = "std")]
+pub use memory::MemoryReader;
+#[cfg(feature = "std")]
+mod metrics;
+#[cfg(feature = "std")]
+pub use metrics::{ObservedReader, ReadEvent, ReadKind, ReadMetrics, ReadObserver, ReadStats};
+#[cfg(feature = "profiling")]
+mod profile;
+#[cfg(feature = "profiling")]
+pub use profile::{DecodeProfile, Stage, StageTiming};
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "mmap")]
+pub use mmap::MmapReader;
+#[cfg(feature = "object_store")]
+mod object_store;
+#[cfg(feature = "object_store")]
+pub use object_store::ObjectStoreReader;
+mod chunk;
+pub(crate) use chunk::Sample;
+pub use chunk::{
+    check_compression_ratio, decode_chunk_data, sparse_chunk_data, ChunkOpts,
+    CompressionRatioLimits, DecodedSamples, DecodingResult, SampleType,
+};
+#[cfg(feature = "std")]
+#[allow(clippy::module_inception)]
 mod decoder;
+#[cfg(feature = "std")]
+pub use decoder::{
+    ChunkIndex, CogDecoder, DecodedChunk, DecoderOptions, LevelTarget, OverviewLevel,
+};
+#[cfg(feature = "std")]
+mod ifd_decoder;
+#[cfg(feature = "std")]
+pub use ifd_decoder::TAG_COALESCE_GAP;
+#[cfg(feature = "std")]
+mod verify;
+#[cfg(feature = "std")]
+pub use verify::{verify_full, ChunkHash, ChunkReport, ImageReport, VerifyOptions, VerifyReport};
+#[cfg(feature = "std")]
+mod window;
+#[cfg(feature = "std")]
+pub use window::EdgePolicy;
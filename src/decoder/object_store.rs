@@ -0,0 +1,165 @@
+//! [`CogReader`] on top of the [`object_store`] crate.
+//!
+//! Credentials, retries on the HTTP level and the like are configured on the
+//! `ObjectStore` itself, e.g. through `AmazonS3Builder`. Vectored reads go
+//! through [`ObjectStore::get_ranges`], which stores may serve with fewer
+//! requests than ranges.
+
+use std::{ops::Range, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use object_store::{path::Path, ObjectStore};
+
+use crate::{
+    decoder::{reader::range_len, CogReader, SourceKey},
+    error::{TiffError, TiffResult},
+};
+
+/// Reads a single object (file) from an [`ObjectStore`]
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use object_store::{http::HttpBuilder, path::Path};
+/// # use tiff2::decoder::ObjectStoreReader;
+/// let store = HttpBuilder::new().with_url("https://example.com").build().unwrap();
+/// let reader = ObjectStoreReader::new(Arc::new(store), Path::from("cog.tif"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ObjectStoreReader {
+    store: Arc<dyn ObjectStore>,
+    path: Path,
+    e_tag: Option<String>,
+    size: Option<u64>,
+}
+
+impl ObjectStoreReader {
+    pub fn new(store: Arc<dyn ObjectStore>, path: Path) -> Self {
+        ObjectStoreReader {
+            store,
+            path,
+            e_tag: None,
+            size: None,
+        }
+    }
+
+    /// Fetch the object's ETag, so its [`SourceKey`] changes when the object
+    /// is replaced, and its size
+    pub async fn with_head(mut self) -> TiffResult<Self> {
+        let meta = self.store.head(&self.path).await?;
+        self.e_tag = meta.e_tag;
+        self.size = Some(meta.size as u64);
+        Ok(self)
+    }
+
+    pub fn e_tag(&self) -> Option<&str> {
+        self.e_tag.as_deref()
+    }
+
+    pub fn store(&self) -> &Arc<dyn ObjectStore> {
+        &self.store
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn read_range(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        let start = usize::try_from(byte_start)?;
+        let end = start
+            .checked_add(usize::try_from(n_bytes)?)
+            .ok_or(TiffError::IntSizeError)?;
+        let range: Range<usize> = start..end;
+        Ok(self.store.get_range(&self.path, range).await?)
+    }
+
+    async fn read_ranges(&self, ranges: &[Range<u64>]) -> TiffResult<Vec<Bytes>> {
+        let ranges = ranges
+            .iter()
+            .map(|range| {
+                range_len(range)?;
+                Ok(usize::try_from(range.start)?..usize::try_from(range.end)?)
+            })
+            .collect::<TiffResult<Vec<_>>>()?;
+        Ok(self.store.get_ranges(&self.path, &ranges).await?)
+    }
+}
+
+#[async_trait]
+impl CogReader for ObjectStoreReader {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(byte_start, n_bytes).await
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(byte_start, n_bytes).await
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_range(byte_start, n_bytes).await
+    }
+
+    async fn read_vectored(&self, ranges: &[Range<u64>]) -> TiffResult<Vec<Bytes>> {
+        self.read_ranges(ranges).await
+    }
+
+    /// Known after [`ObjectStoreReader::with_head`]
+    fn file_len(&self) -> Option<u64> {
+        self.size
+    }
+
+    fn source_id(&self) -> SourceKey {
+        SourceKey::Url {
+            url: format!("{}/{}", self.store, self.path),
+            e_tag: self.e_tag.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use object_store::{memory::InMemory, PutPayload};
+
+    async fn reader_with(data: &'static [u8]) -> ObjectStoreReader {
+        let store = InMemory::new();
+        let path = Path::from("test.tif");
+        store
+            .put(&path, PutPayload::from_static(data))
+            .await
+            .unwrap();
+        ObjectStoreReader::new(Arc::new(store), path)
+    }
+
+    #[tokio::test]
+    async fn test_read_ranges() {
+        let reader = reader_with(b"II*\0\x08\0\0\0abcdef").await;
+        assert_eq!(reader.read_ifd(0, 4).await.unwrap(), &b"II*\0"[..]);
+        assert_eq!(reader.read_tag_data(8, 3).await.unwrap(), &b"abc"[..]);
+        assert_eq!(reader.read_image_data(11, 3).await.unwrap(), &b"def"[..]);
+        assert_eq!(
+            reader.read_vectored(&[11..14, 0..2]).await.unwrap(),
+            [&b"def"[..], b"II"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_source_id() {
+        let reader = reader_with(b"II*\0").await;
+        let without_e_tag = reader.source_id();
+        assert_eq!(reader.file_len(), None);
+        let reader = reader.with_head().await.unwrap();
+        assert!(reader.e_tag().is_some());
+        assert_eq!(reader.file_len(), Some(4));
+        assert_ne!(reader.source_id(), without_e_tag);
+    }
+
+    #[tokio::test]
+    async fn test_missing_object() {
+        let reader = ObjectStoreReader::new(Arc::new(InMemory::new()), Path::from("nope.tif"));
+        let TiffError::IoError(e) = reader.read_ifd(0, 4).await.unwrap_err() else {
+            panic!("object store errors should surface as io errors");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    }
+}
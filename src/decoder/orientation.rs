@@ -0,0 +1,180 @@
+//! Mapping between stored and displayed pixels of an oriented image.
+//!
+//! An image with an `Orientation` other than `TopLeft` is stored flipped or
+//! rotated. A window of the displayed image maps to a window of the stored
+//! image, which is decoded as usual and has its pixels reordered for display.
+
+use alloc::vec::Vec;
+
+use crate::{
+    error::{TiffFormatError, TiffResult},
+    structs::{tags::Orientation, Ifd, Rect, Tag},
+};
+
+impl Orientation {
+    /// Orientation of an IFD, `TopLeft` if it has none.
+    ///
+    /// Fails with [`TiffFormatError::InvalidTagValueType`] for values that
+    /// aren't orientations.
+    pub fn from_ifd(ifd: &Ifd) -> TiffResult<Self> {
+        let Some(entry) = ifd.get_tag_value(&Tag::Orientation)? else {
+            return Ok(Orientation::TopLeft);
+        };
+        u16::try_from(entry.get_u64(0)?)
+            .ok()
+            .and_then(Orientation::from_u16)
+            .ok_or_else(|| TiffFormatError::InvalidTagValueType(Tag::Orientation.to_u16()).into())
+    }
+
+    /// Whether stored rows are displayed as columns
+    pub fn is_transposed(&self) -> bool {
+        matches!(
+            self,
+            Orientation::LeftTop
+                | Orientation::RightTop
+                | Orientation::RightBottom
+                | Orientation::LeftBottom
+        )
+    }
+
+    /// Whether, after transposing, stored columns and rows run against the
+    /// displayed ones
+    fn flips(&self) -> (bool, bool) {
+        match self {
+            Orientation::TopRight | Orientation::LeftBottom => (true, false),
+            Orientation::BottomRight | Orientation::RightBottom => (true, true),
+            Orientation::BottomLeft | Orientation::RightTop => (false, true),
+            _ => (false, false),
+        }
+    }
+
+    /// Width and height of a stored image of `width` by `height` pixels when
+    /// displayed
+    pub fn displayed_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.is_transposed() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// The window of a stored image of `width` by `height` pixels that holds
+    /// the pixels of `rect` of the displayed image.
+    ///
+    /// `rect` must lie within the displayed image.
+    pub fn stored_rect(&self, rect: Rect, width: u32, height: u32) -> Rect {
+        let mut stored = if self.is_transposed() {
+            Rect::new(rect.y, rect.x, rect.height, rect.width)
+        } else {
+            rect
+        };
+        let (flip_x, flip_y) = self.flips();
+        if flip_x {
+            stored.x = width - stored.x - stored.width;
+        }
+        if flip_y {
+            stored.y = height - stored.y - stored.height;
+        }
+        stored
+    }
+
+    /// Reorder the pixels of a stored window of `width` by `height` pixels of
+    /// `pixel_bytes` bytes each for display, rows of the result holding
+    /// `height` pixels if the orientation is transposed.
+    pub fn to_displayed(
+        &self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        pixel_bytes: usize,
+    ) -> Vec<u8> {
+        if *self == Orientation::TopLeft {
+            return data.to_vec();
+        }
+        let (displayed_width, displayed_height) = if self.is_transposed() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let (flip_x, flip_y) = self.flips();
+        let mut displayed = Vec::with_capacity(data.len());
+        for dy in 0..displayed_height {
+            for dx in 0..displayed_width {
+                let (mut x, mut y) = if self.is_transposed() {
+                    (dy, dx)
+                } else {
+                    (dx, dy)
+                };
+                if flip_x {
+                    x = width - 1 - x;
+                }
+                if flip_y {
+                    y = height - 1 - y;
+                }
+                let start = (y * width + x) * pixel_bytes;
+                displayed.extend_from_slice(&data[start..start + pixel_bytes]);
+            }
+        }
+        displayed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Displayed 2x3 image of pixels 0 to 5, stored in each orientation
+    const STORED: [(Orientation, u32, [u8; 6]); 8] = [
+        (Orientation::TopLeft, 2, [0, 1, 2, 3, 4, 5]),
+        (Orientation::TopRight, 2, [1, 0, 3, 2, 5, 4]),
+        (Orientation::BottomRight, 2, [5, 4, 3, 2, 1, 0]),
+        (Orientation::BottomLeft, 2, [4, 5, 2, 3, 0, 1]),
+        (Orientation::LeftTop, 3, [0, 2, 4, 1, 3, 5]),
+        (Orientation::RightTop, 3, [1, 3, 5, 0, 2, 4]),
+        (Orientation::RightBottom, 3, [5, 3, 1, 4, 2, 0]),
+        (Orientation::LeftBottom, 3, [4, 2, 0, 5, 3, 1]),
+    ];
+
+    #[test]
+    fn test_to_displayed() {
+        for (orientation, width, stored) in STORED {
+            let height = 6 / width;
+            assert_eq!(orientation.displayed_size(width, height), (2, 3));
+            let displayed = orientation.to_displayed(&stored, width as usize, height as usize, 1);
+            assert_eq!(displayed, [0, 1, 2, 3, 4, 5], "{orientation:?}");
+        }
+        // whole pixels are moved
+        let stored = [0, 1, 2, 3, 4, 5];
+        let displayed = Orientation::RightTop.to_displayed(&stored, 3, 1, 2);
+        assert_eq!(displayed, stored);
+        let displayed = Orientation::TopRight.to_displayed(&stored, 3, 1, 2);
+        assert_eq!(displayed, [4, 5, 2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn test_stored_rect() {
+        for (orientation, width, stored) in STORED {
+            let height = 6 / width;
+            // every displayed window holds the right pixels
+            for rect in [
+                Rect::new(0, 0, 2, 3),
+                Rect::new(1, 0, 1, 2),
+                Rect::new(0, 1, 2, 2),
+                Rect::new(1, 2, 1, 1),
+            ] {
+                let s = orientation.stored_rect(rect, width, height);
+                let window: Vec<u8> = (s.y..s.y + s.height)
+                    .flat_map(|y| {
+                        (s.x..s.x + s.width).map(move |x| stored[(y * width + x) as usize])
+                    })
+                    .collect();
+                let displayed =
+                    orientation.to_displayed(&window, s.width as usize, s.height as usize, 1);
+                let expected: Vec<u8> = (rect.y..rect.y + rect.height)
+                    .flat_map(|y| (rect.x..rect.x + rect.width).map(move |x| (y * 2 + x) as u8))
+                    .collect();
+                assert_eq!(displayed, expected, "{orientation:?} {rect:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Expanding palette indices to RGB.
+//!
+//! Images with `PhotometricInterpretation::RGBPalette` hold an index per
+//! pixel into their `ColorMap`, which lists `2^BitsPerSample` colors as
+//! 16-bit red, green and blue samples, all reds first, then all greens and
+//! then all blues.
+
+use alloc::vec::Vec;
+
+use crate::{
+    error::{TiffFormatError, TiffResult, TiffUnsupportedError},
+    structs::{Ifd, Tag, TagType},
+};
+
+/// Colors of a `ColorMap`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<[u16; 3]>,
+}
+
+impl Palette {
+    /// Palette of a color map of `colors.len()` colors
+    pub fn new(colors: Vec<[u16; 3]>) -> Self {
+        Palette { colors }
+    }
+
+    /// The `ColorMap` of an IFD with indices of `bits_per_sample` bits, `None`
+    /// if it has none.
+    ///
+    /// Fails with [`TiffFormatError::InconsistentSizesEncountered`] if it
+    /// doesn't hold a color for every index.
+    pub fn from_ifd(ifd: &Ifd, bits_per_sample: u8) -> TiffResult<Option<Self>> {
+        let Some(entry) = ifd.get_tag_value(&Tag::ColorMap)? else {
+            return Ok(None);
+        };
+        let n_colors = 1usize.checked_shl(bits_per_sample.into()).unwrap_or(0);
+        if entry.tag_type != TagType::SHORT
+            || bits_per_sample > 16
+            || entry.data().len() != 3 * 2 * n_colors
+        {
+            return Err(TiffFormatError::InconsistentSizesEncountered(entry.into()).into());
+        }
+        let samples: Vec<u16> = entry
+            .data()
+            .chunks_exact(2)
+            .map(|s| u16::from_ne_bytes([s[0], s[1]]))
+            .collect();
+        let (red, rest) = samples.split_at(n_colors);
+        let (green, blue) = rest.split_at(n_colors);
+        Ok(Some(Palette {
+            colors: (0..n_colors).map(|i| [red[i], green[i], blue[i]]).collect(),
+        }))
+    }
+
+    /// Red, green and blue of each index
+    pub fn colors(&self) -> &[[u16; 3]] {
+        &self.colors
+    }
+
+    /// Whether the colors are 8-bit, as some old writers put them in the
+    /// `ColorMap` unscaled. Such maps are used as is by [`Self::to_rgb8`].
+    pub fn is_8_bit(&self) -> bool {
+        self.colors.iter().flatten().all(|&s| s <= 255)
+    }
+
+    /// Expand indices of `bits_per_sample` bits, 8 or 16 in native byte
+    /// order, to 16-bit RGB in native byte order.
+    ///
+    /// Fails with [`TiffFormatError::InvalidTagValueType`] for an index the
+    /// palette has no color for.
+    pub fn to_rgb16(&self, indices: &[u8], bits_per_sample: u8) -> TiffResult<Vec<u8>> {
+        let mut rgb = Vec::with_capacity(indices.len() * 6 * 8 / usize::from(bits_per_sample));
+        for index in self.indices(indices, bits_per_sample)? {
+            for sample in self.color(index)? {
+                rgb.extend_from_slice(&sample.to_ne_bytes());
+            }
+        }
+        Ok(rgb)
+    }
+
+    /// Like [`Self::to_rgb16`], to 8-bit RGB
+    pub fn to_rgb8(&self, indices: &[u8], bits_per_sample: u8) -> TiffResult<Vec<u8>> {
+        let shift = if self.is_8_bit() { 0 } else { 8 };
+        let mut rgb = Vec::with_capacity(indices.len() * 3 * 8 / usize::from(bits_per_sample));
+        for index in self.indices(indices, bits_per_sample)? {
+            rgb.extend(self.color(index)?.map(|sample| (sample >> shift) as u8));
+        }
+        Ok(rgb)
+    }
+
+    fn color(&self, index: usize) -> TiffResult<[u16; 3]> {
+        self.colors
+            .get(index)
+            .copied()
+            .ok_or_else(|| TiffFormatError::InvalidTagValueType(Tag::ColorMap.to_u16()).into())
+    }
+
+    fn indices<'a>(
+        &self,
+        indices: &'a [u8],
+        bits_per_sample: u8,
+    ) -> TiffResult<impl Iterator<Item = usize> + 'a> {
+        let width = match bits_per_sample {
+            8 => 1,
+            16 => 2,
+            _ => {
+                return Err(TiffUnsupportedError::UnsupportedBitsPerChannel(bits_per_sample).into())
+            }
+        };
+        Ok(indices.chunks_exact(width).map(|index| match *index {
+            [i] => usize::from(i),
+            [a, b] => usize::from(u16::from_ne_bytes([a, b])),
+            _ => unreachable!(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoder::directory::entry;
+
+    #[test]
+    fn test_palette() {
+        // 2-bit gray ramp in red and green, blue fixed
+        let mut ifd = Ifd::default();
+        let map: [u16; 12] = [
+            0, 0x5555, 0xaaaa, 0xffff, 0, 0x5555, 0xaaaa, 0xffff, 7, 7, 7, 7,
+        ];
+        ifd.insert_tag_data_from_buffer(&Tag::ColorMap, entry(&map[..]));
+        let palette = Palette::from_ifd(&ifd, 2).unwrap().unwrap();
+        assert_eq!(palette.colors()[1], [0x5555, 0x5555, 7]);
+        assert!(!palette.is_8_bit());
+        assert_eq!(
+            palette.to_rgb8(&[3, 0], 8).unwrap(),
+            [0xff, 0xff, 0, 0, 0, 0]
+        );
+        let rgb16: Vec<u8> = [0xaaaa, 0xaaaa, 7u16]
+            .iter()
+            .flat_map(|s| s.to_ne_bytes())
+            .collect();
+        assert_eq!(palette.to_rgb16(&2u16.to_ne_bytes(), 16).unwrap(), rgb16);
+        // an index past the palette
+        assert!(palette.to_rgb8(&[4], 8).is_err());
+
+        // unscaled 8-bit colors are used as is
+        let palette = Palette::new(vec![[0, 128, 255]]);
+        assert_eq!(palette.to_rgb8(&[0], 8).unwrap(), [0, 128, 255]);
+
+        // a color map of the wrong size
+        assert!(Palette::from_ifd(&ifd, 8).is_err());
+        assert_eq!(Palette::from_ifd(&Ifd::default(), 8).unwrap(), None);
+    }
+}
@@ -0,0 +1,168 @@
+//! Per-chunk timings of the stages of decoding, with the `profiling` feature.
+//!
+//! Set [`DecoderOptions::profile`] to a [`DecodeProfile`] to record how long
+//! each chunk spent being fetched, decompressed, un-predicted and laid out,
+//! e.g. to find out which codec paths are worth optimizing next.
+//! [`DecodeProfile::folded`] writes the timings as folded stacks, which
+//! `inferno-flamegraph` and `flamegraph.pl` turn into a flame graph.
+//!
+//! Chunks served from the caches skip the stages that were cached.
+//!
+//! [`DecoderOptions::profile`]: crate::decoder::DecoderOptions::profile
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use crate::decoder::{ChunkIndex, OverviewLevel};
+
+/// Stage of decoding a chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    /// Reading the compressed chunk
+    Fetch,
+    /// Decompressing it
+    Decompress,
+    /// Undoing the predictor, fixing the byte order and upsampling chroma
+    Predictor,
+    /// Copying it into a region, or applying [`ChunkOpts`]
+    ///
+    /// [`ChunkOpts`]: crate::decoder::ChunkOpts
+    Layout,
+}
+
+impl Stage {
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Fetch => "fetch",
+            Stage::Decompress => "decompress",
+            Stage::Predictor => "predictor",
+            Stage::Layout => "layout",
+        }
+    }
+}
+
+/// How long a stage of decoding a chunk took
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageTiming {
+    pub level: OverviewLevel,
+    pub chunk: ChunkIndex,
+    pub stage: Stage,
+    pub duration: Duration,
+}
+
+/// Timings recorded by a decoder, shared by all chunk futures it hands out
+#[derive(Debug, Default)]
+pub struct DecodeProfile {
+    timings: Mutex<Vec<StageTiming>>,
+}
+
+impl DecodeProfile {
+    fn lock(&self) -> MutexGuard<'_, Vec<StageTiming>> {
+        // a panic while pushing can't leave the timings inconsistent
+        self.timings.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn record(
+        &self,
+        level: OverviewLevel,
+        chunk: ChunkIndex,
+        stage: Stage,
+        duration: Duration,
+    ) {
+        self.lock().push(StageTiming {
+            level,
+            chunk,
+            stage,
+            duration,
+        });
+    }
+
+    /// All timings, in the order they were recorded
+    pub fn timings(&self) -> Vec<StageTiming> {
+        self.lock().clone()
+    }
+
+    /// Timings of one chunk
+    pub fn chunk(&self, level: OverviewLevel, chunk: ChunkIndex) -> Vec<StageTiming> {
+        self.lock()
+            .iter()
+            .filter(|t| t.level == level && t.chunk == chunk)
+            .copied()
+            .collect()
+    }
+
+    /// Time spent in a stage, summed over all chunks
+    pub fn total(&self, stage: Stage) -> Duration {
+        self.lock()
+            .iter()
+            .filter(|t| t.stage == stage)
+            .map(|t| t.duration)
+            .sum()
+    }
+
+    /// The `n` slowest timings of a stage, slowest first
+    pub fn slowest(&self, stage: Stage, n: usize) -> Vec<StageTiming> {
+        let mut timings: Vec<_> = self
+            .lock()
+            .iter()
+            .filter(|t| t.stage == stage)
+            .copied()
+            .collect();
+        timings.sort_by_key(|t| core::cmp::Reverse(t.duration));
+        timings.truncate(n);
+        timings
+    }
+
+    /// Forget all timings recorded so far
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Timings as folded stacks of `level;chunk;stage`, with microseconds
+    /// summed over repeated decodes of a chunk
+    pub fn folded(&self) -> String {
+        let mut stacks = BTreeMap::<_, Duration>::new();
+        for t in self.lock().iter() {
+            *stacks.entry((t.level, t.chunk, t.stage)).or_default() += t.duration;
+        }
+        let mut out = String::new();
+        for ((level, chunk, stage), duration) in stacks {
+            let _ = writeln!(
+                out,
+                "level {level};chunk {chunk};{} {}",
+                stage.name(),
+                duration.as_micros()
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let profile = DecodeProfile::default();
+        let ms = Duration::from_millis;
+        profile.record(0, 1, Stage::Fetch, ms(5));
+        profile.record(0, 1, Stage::Decompress, ms(2));
+        profile.record(0, 2, Stage::Decompress, ms(3));
+        profile.record(0, 1, Stage::Decompress, ms(1));
+        assert_eq!(profile.total(Stage::Decompress), ms(6));
+        assert_eq!(profile.chunk(0, 1).len(), 3);
+        let slowest = profile.slowest(Stage::Decompress, 1);
+        assert_eq!((slowest[0].chunk, slowest[0].duration), (2, ms(3)));
+        assert_eq!(
+            profile.folded(),
+            "level 0;chunk 1;fetch 5000\nlevel 0;chunk 1;decompress 3000\nlevel 0;chunk 2;decompress 3000\n"
+        );
+        profile.clear();
+        assert!(profile.timings().is_empty());
+    }
+}
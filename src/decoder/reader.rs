@@ -1,16 +1,291 @@
-use std::io::{self, Read};
-
-use crate::ByteOrder;
+use crate::{
+    error::{TiffFormatError, TiffResult},
+    io::{self, Read},
+    structs::{value::PrimitiveValue, TagType},
+    ByteOrder,
+};
 
+#[cfg(feature = "std")]
+use crate::{decoder::SourceKey, error::TiffError};
+#[cfg(feature = "std")]
 use async_trait::async_trait;
+#[cfg(feature = "std")]
+use bytes::Bytes;
+#[cfg(feature = "std")]
+use std::{ops::Range, time::Duration};
 
 /// Trait for a CogReader to implement. In fact these are all the same, but caching can be optimized based on which part of the tiff we're reading in.
+///
+/// Readers are shared by the decoder and the chunk futures it hands out,
+/// which may run on any thread of a multi-threaded runtime, so readers are
+/// `Send + Sync` and the futures of their methods are `Send`. That makes
+/// `Arc<dyn CogReader>` thread-safe as it is, e.g. to keep in the state of an
+/// axum handler. `Arc` and `Box` of a reader are readers too.
+///
+/// Data is returned as [`Bytes`], so readers of memory maps, in-memory files
+/// or HTTP bodies can hand out reference-counted slices of what they hold
+/// instead of copying it, and the decoder can in turn slice tag data out of
+/// the header it prefetched.
+#[cfg(feature = "std")]
 #[async_trait]
-pub trait CogReader {
+pub trait CogReader: Send + Sync {
     // https://blog.rust-lang.org/2023/12/21/async-fn-rpit-in-traits.html#where-the-gaps-lie
-    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> Vec<u8>;
-    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> Vec<u8>;
-    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> Vec<u8>;
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes>;
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes>;
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes>;
+
+    /// Read several ranges of image data, returning their bytes in the same
+    /// order. Used by [`CogDecoder`] to fetch the chunks of a region at once.
+    ///
+    /// The default implementation reads the ranges one after another with
+    /// `read_image_data`. Readers whose backend can fetch several ranges in a
+    /// single request or system call, like multi-range GETs or `preadv`, can
+    /// override this.
+    ///
+    /// [`CogDecoder`]: crate::decoder::CogDecoder
+    async fn read_vectored(&self, ranges: &[Range<u64>]) -> TiffResult<Vec<Bytes>> {
+        let mut data = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            data.push(self.read_image_data(range.start, range_len(range)?).await?);
+        }
+        Ok(data)
+    }
+
+    /// Read the first `n_bytes` of the file, or the whole file if it is
+    /// shorter. Used for prefetching the header and first IFDs.
+    ///
+    /// The default implementation relies on `read_ifd` returning short reads
+    /// past the end of the file, like HTTP range requests do.
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        self.read_ifd(0, n_bytes).await
+    }
+
+    /// Length of the file, if it is known without reading anything
+    fn file_len(&self) -> Option<u64> {
+        None
+    }
+
+    /// Identity of the file, which keys its chunks in caches that may be
+    /// shared with decoders of other files, see [`CogDecoder::share_caches`].
+    /// Readers returning equal keys must return the same bytes.
+    ///
+    /// The default implementation returns a [unique](SourceKey::unique) key
+    /// on every call, so nothing read through the reader is shared. The
+    /// decoder asks for it once, when it is created.
+    ///
+    /// [`CogDecoder::share_caches`]: crate::decoder::CogDecoder::share_caches
+    fn source_id(&self) -> SourceKey {
+        SourceKey::unique()
+    }
+}
+
+/// Forward all methods of a [`CogReader`] to the reader a wrapper points to
+#[cfg(feature = "std")]
+macro_rules! forward_reader {
+    ($($wrapper:ident)::+) => {
+        #[async_trait]
+        impl<R: CogReader + ?Sized> CogReader for $($wrapper)::+<R> {
+            async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+                (**self).read_ifd(byte_start, n_bytes).await
+            }
+
+            async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+                (**self).read_tag_data(byte_start, n_bytes).await
+            }
+
+            async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+                (**self).read_image_data(byte_start, n_bytes).await
+            }
+
+            async fn read_vectored(&self, ranges: &[Range<u64>]) -> TiffResult<Vec<Bytes>> {
+                (**self).read_vectored(ranges).await
+            }
+
+            async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+                (**self).read_header(n_bytes).await
+            }
+
+            fn file_len(&self) -> Option<u64> {
+                (**self).file_len()
+            }
+
+            fn source_id(&self) -> SourceKey {
+                (**self).source_id()
+            }
+        }
+    };
+}
+
+#[cfg(feature = "std")]
+forward_reader!(std::sync::Arc);
+#[cfg(feature = "std")]
+forward_reader!(Box);
+
+/// Number of bytes in `range`, failing if it ends before it starts
+#[cfg(feature = "std")]
+pub(crate) fn range_len(range: &Range<u64>) -> TiffResult<u64> {
+    range
+        .end
+        .checked_sub(range.start)
+        .ok_or(TiffError::IntSizeError)
+}
+
+/// Get `n_bytes` starting at `byte_start` from an in-memory file, failing with
+/// `UnexpectedEof` if the range extends past its end
+#[cfg(feature = "std")]
+pub(crate) fn slice_range(data: &[u8], byte_start: u64, n_bytes: u64) -> TiffResult<&[u8]> {
+    let start = usize::try_from(byte_start)?;
+    let end = start
+        .checked_add(usize::try_from(n_bytes)?)
+        .ok_or(TiffError::IntSizeError)?;
+    data.get(start..end)
+        .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof).into())
+}
+
+/// Like [`slice_range`], sharing memory with `data`
+#[cfg(feature = "std")]
+pub(crate) fn slice_bytes(data: &Bytes, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+    Ok(data.slice_ref(slice_range(data, byte_start, n_bytes)?))
+}
+
+/// Whether an error is likely to go away when trying again: I/O errors other
+/// than missing files, permissions, invalid requests and reading past the end.
+///
+/// `object_store` errors such as HTTP 503s end up as `ErrorKind::Other`.
+#[cfg(feature = "std")]
+pub fn is_transient(err: &TiffError) -> bool {
+    match err {
+        TiffError::IoError(e) => !matches!(
+            e.kind(),
+            io::ErrorKind::NotFound
+                | io::ErrorKind::PermissionDenied
+                | io::ErrorKind::InvalidInput
+                | io::ErrorKind::InvalidData
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::Unsupported
+        ),
+        _ => false,
+    }
+}
+
+/// Wraps a [`CogReader`], retrying failed reads with exponential backoff.
+///
+/// By default, reads are retried 3 times on [transient](is_transient) errors,
+/// waiting 100ms, 200ms and 400ms (capped at 5s) in between.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use tiff2::{decoder::RetryingReader, error::TiffError};
+/// let reader = RetryingReader::new(vec![0u8; 1024])
+///     .with_max_retries(5)
+///     .with_backoff(Duration::from_millis(50), Duration::from_secs(2))
+///     .with_retry_on(|e| matches!(e, TiffError::IoError(_)));
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct RetryingReader<R> {
+    inner: R,
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    retry_on: fn(&TiffError) -> bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: CogReader> RetryingReader<R> {
+    pub fn new(inner: R) -> Self {
+        RetryingReader {
+            inner,
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retry_on: is_transient,
+        }
+    }
+
+    /// Number of retries after the first attempt
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Wait `initial` before the first retry, doubling every retry up to `max`
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Which errors to retry, [`is_transient`] by default
+    pub fn with_retry_on(mut self, retry_on: fn(&TiffError) -> bool) -> Self {
+        self.retry_on = retry_on;
+        self
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+
+    async fn retry<'a, T, F>(&'a self, read: impl Fn(&'a R) -> F) -> TiffResult<T>
+    where
+        F: core::future::Future<Output = TiffResult<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match read(&self.inner).await {
+                Err(e) if retry < self.max_retries && (self.retry_on)(&e) => {
+                    log::debug!("retrying read after error: {e}");
+                    tokio::time::sleep(self.backoff(retry)).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[async_trait]
+impl<R: CogReader> CogReader for RetryingReader<R> {
+    async fn read_ifd(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.retry(|r| r.read_ifd(byte_start, n_bytes)).await
+    }
+
+    async fn read_tag_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.retry(|r| r.read_tag_data(byte_start, n_bytes)).await
+    }
+
+    async fn read_image_data(&self, byte_start: u64, n_bytes: u64) -> TiffResult<Bytes> {
+        self.retry(|r| r.read_image_data(byte_start, n_bytes)).await
+    }
+
+    async fn read_vectored(&self, ranges: &[Range<u64>]) -> TiffResult<Vec<Bytes>> {
+        self.retry(|r| r.read_vectored(ranges)).await
+    }
+
+    async fn read_header(&self, n_bytes: u64) -> TiffResult<Bytes> {
+        self.retry(|r| r.read_header(n_bytes)).await
+    }
+
+    fn file_len(&self) -> Option<u64> {
+        self.inner.file_len()
+    }
+
+    fn source_id(&self) -> SourceKey {
+        self.inner.source_id()
+    }
+}
+
+/// Get at most `n_bytes` from the start of an in-memory file
+#[cfg(feature = "std")]
+pub(crate) fn slice_prefix(data: &[u8], n_bytes: u64) -> &[u8] {
+    &data[..usize::try_from(n_bytes).map_or(data.len(), |n| n.min(data.len()))]
 }
 
 pub struct EndianReader<R> {
@@ -30,7 +305,7 @@ macro_rules! read_fn {
         /// reads an $type, respecting byte order
         #[inline(always)]
         pub fn $name(&mut self) -> Result<$type, io::Error> {
-            let mut n = [0u8; std::mem::size_of::<$type>()];
+            let mut n = [0u8; core::mem::size_of::<$type>()];
             self.read_exact(&mut n)?;
             Ok(match self.byte_order() {
                 ByteOrder::LittleEndian => <$type>::from_le_bytes(n),
@@ -61,4 +336,122 @@ impl<R: io::Read> EndianReader<R> {
 
     read_fn!(read_f32, f32);
     read_fn!(read_f64, f64);
+
+    /// Reads a single value of `tag_type`, respecting byte order.
+    ///
+    /// Fails with [`TiffFormatError::InvalidTagValueType`] for vendor types,
+    /// of which the size of a value isn't known.
+    pub fn read_value(&mut self, tag_type: TagType) -> TiffResult<PrimitiveValue> {
+        Ok(match tag_type {
+            TagType::BYTE => PrimitiveValue::Byte(self.read_u8()?),
+            TagType::SBYTE => PrimitiveValue::SByte(self.read_i8()?),
+            TagType::UNDEFINED => PrimitiveValue::Undefined(self.read_u8()?),
+            TagType::ASCII => PrimitiveValue::Ascii(self.read_u8()?),
+            TagType::SHORT => PrimitiveValue::Short(self.read_u16()?),
+            TagType::SSHORT => PrimitiveValue::SShort(self.read_i16()?),
+            TagType::LONG => PrimitiveValue::Long(self.read_u32()?),
+            TagType::SLONG => PrimitiveValue::SLong(self.read_i32()?),
+            TagType::IFD => PrimitiveValue::Ifd(self.read_u32()?),
+            TagType::LONG8 => PrimitiveValue::Long8(self.read_u64()?),
+            TagType::SLONG8 => PrimitiveValue::SLong8(self.read_i64()?),
+            TagType::IFD8 => PrimitiveValue::Ifd8(self.read_u64()?),
+            TagType::FLOAT => PrimitiveValue::Float(self.read_f32()?),
+            TagType::DOUBLE => PrimitiveValue::Double(self.read_f64()?),
+            TagType::RATIONAL => PrimitiveValue::Rational(self.read_u32()?, self.read_u32()?),
+            TagType::SRATIONAL => PrimitiveValue::SRational(self.read_i32()?, self.read_i32()?),
+            TagType::Unknown(code, _) => {
+                return Err(TiffFormatError::InvalidTagValueType(code).into())
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails with an `ErrorKind::Other` a number of times before succeeding
+    struct Flaky {
+        failures: AtomicU32,
+    }
+
+    impl Flaky {
+        async fn read(&self, n_bytes: u64) -> TiffResult<Bytes> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                Err(io::Error::other("503 Service Unavailable").into())
+            } else {
+                Ok(vec![42; n_bytes as usize].into())
+            }
+        }
+    }
+
+    #[async_trait]
+    impl CogReader for Flaky {
+        async fn read_ifd(&self, _: u64, n_bytes: u64) -> TiffResult<Bytes> {
+            self.read(n_bytes).await
+        }
+
+        async fn read_tag_data(&self, _: u64, n_bytes: u64) -> TiffResult<Bytes> {
+            self.read(n_bytes).await
+        }
+
+        async fn read_image_data(&self, _: u64, n_bytes: u64) -> TiffResult<Bytes> {
+            self.read(n_bytes).await
+        }
+    }
+
+    fn flaky(failures: u32) -> RetryingReader<Flaky> {
+        RetryingReader::new(Flaky {
+            failures: AtomicU32::new(failures),
+        })
+        .with_backoff(Duration::from_millis(1), Duration::from_millis(2))
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        assert_eq!(flaky(3).read_image_data(0, 2).await.unwrap(), vec![42, 42]);
+        assert!(flaky(4).read_image_data(0, 2).await.is_err());
+        assert!(flaky(1)
+            .with_retry_on(|_| false)
+            .read_image_data(0, 2)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors() {
+        let reader = RetryingReader::new(vec![0u8; 4]);
+        // reading past the end won't get better by waiting
+        assert!(reader.read_image_data(2, 4).await.is_err());
+        assert!(!is_transient(&TiffError::LimitsExceeded));
+        assert_eq!(reader.backoff(0), Duration::from_millis(100));
+        assert_eq!(reader.backoff(10), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_wrappers() {
+        let file = b"II*\0\x08\0\0\0".to_vec();
+        let shared: std::sync::Arc<dyn CogReader> = std::sync::Arc::new(file.clone());
+        let boxed: Box<dyn CogReader> = Box::new(file);
+        // wrappers of wrappers, e.g. retrying reads of a shared reader
+        let retrying = RetryingReader::new(shared.clone());
+        let readers: [&dyn CogReader; 4] = [&shared, &boxed, &retrying, &Box::new(shared.clone())];
+        for reader in readers {
+            assert_eq!(reader.read_ifd(0, 4).await.unwrap(), &b"II*\0"[..]);
+            assert_eq!(reader.read_header(100).await.unwrap().len(), 8);
+            assert_eq!(reader.file_len(), Some(8));
+            assert_eq!(
+                reader.read_vectored(&[4..8, 0..2, 2..2]).await.unwrap(),
+                [&[8, 0, 0, 0][..], b"II", b""]
+            );
+            #[allow(clippy::reversed_empty_ranges)]
+            let reversed = reader.read_vectored(&[0..2, 4..2]).await;
+            assert!(matches!(reversed, Err(TiffError::IntSizeError)));
+        }
+        // futures can move to other threads
+        let task = tokio::spawn(async move { shared.read_image_data(4, 4).await });
+        assert_eq!(task.await.unwrap().unwrap(), &[8, 0, 0, 0][..]);
+    }
 }
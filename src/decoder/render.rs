@@ -0,0 +1,171 @@
+//! Rendering decoded samples to RGBA.
+//!
+//! A transparency mask (a 1-bit IFD with `PhotometricInterpretation::TransparencyMask`)
+//! or an alpha band is composited into the alpha channel here, so consumers
+//! don't have to fetch and align the mask themselves.
+
+use alloc::{borrow::Cow, vec::Vec};
+
+use crate::{
+    decoder::{u16_to_u8, Dither, PackedBitmap},
+    error::{TiffFormatError, TiffResult, TiffUnsupportedError},
+    ColorType,
+};
+
+/// Render 8 or 16-bit samples to RGBA.
+///
+/// `Gray`, `GrayA`, `RGB` and `RGBA` are supported. 16-bit samples are in
+/// native byte order, and reduced to 8 bits with [`Dither::Ordered`]. When a
+/// `mask` is given,
+/// pixels where the mask bit is unset become fully transparent, other pixels
+/// keep their alpha (or 255 if there is no alpha band).
+pub fn render_rgba(
+    data: &[u8],
+    color_type: ColorType,
+    width: usize,
+    height: usize,
+    mask: Option<&PackedBitmap>,
+) -> TiffResult<Vec<u8>> {
+    let (samples, bytes) = match color_type {
+        ColorType::Gray(bits @ (8 | 16)) => (1, usize::from(bits / 8)),
+        ColorType::GrayA(bits @ (8 | 16)) => (2, usize::from(bits / 8)),
+        ColorType::RGB(bits @ (8 | 16)) => (3, usize::from(bits / 8)),
+        ColorType::RGBA(bits @ (8 | 16)) => (4, usize::from(bits / 8)),
+        _ => return Err(TiffUnsupportedError::UnsupportedColorType(color_type).into()),
+    };
+    if data.len() != width * height * samples * bytes {
+        return Err(TiffFormatError::InconsistentStripSamples {
+            actual_samples: data.len() / bytes,
+            required_samples: width * height * samples,
+        }
+        .into());
+    }
+    let data = if bytes == 2 {
+        let samples_16: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|v| u16::from_ne_bytes([v[0], v[1]]))
+            .collect();
+        Cow::Owned(u16_to_u8(&samples_16, width, samples, Dither::Ordered))
+    } else {
+        Cow::Borrowed(data)
+    };
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for px in data.chunks_exact(samples) {
+        match *px {
+            [v] => rgba.extend_from_slice(&[v, v, v, 255]),
+            [v, a] => rgba.extend_from_slice(&[v, v, v, a]),
+            [r, g, b] => rgba.extend_from_slice(&[r, g, b, 255]),
+            _ => rgba.extend_from_slice(px),
+        }
+    }
+    if let Some(mask) = mask {
+        apply_mask(&mut rgba, width, height, mask)?;
+    }
+    Ok(rgba)
+}
+
+/// Make pixels of an RGBA buffer transparent where the mask bit is unset.
+///
+/// The mask must have the same dimensions as the image.
+pub fn apply_mask(
+    rgba: &mut [u8],
+    width: usize,
+    height: usize,
+    mask: &PackedBitmap,
+) -> TiffResult<()> {
+    if mask.width != width || mask.height != height {
+        return Err(TiffFormatError::InvalidDimensions(
+            mask.width.try_into()?,
+            mask.height.try_into()?,
+        )
+        .into());
+    }
+    for (y, row) in rgba.chunks_exact_mut(width * 4).enumerate() {
+        for (x, px) in row.chunks_exact_mut(4).enumerate() {
+            if !mask.get(x, y) {
+                px[3] = 0;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::structs::tags::PhotometricInterpretation;
+    use alloc::vec;
+
+    fn mask(bits: u8, width: usize) -> PackedBitmap {
+        PackedBitmap::from_rows(
+            vec![bits],
+            width,
+            1,
+            PhotometricInterpretation::TransparencyMask,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_render_without_mask() {
+        assert_eq!(
+            render_rgba(&[1, 2], ColorType::Gray(8), 2, 1, None).unwrap(),
+            vec![1, 1, 1, 255, 2, 2, 2, 255]
+        );
+        assert_eq!(
+            render_rgba(&[1, 2, 3], ColorType::RGB(8), 1, 1, None).unwrap(),
+            vec![1, 2, 3, 255]
+        );
+    }
+
+    #[test]
+    fn test_render_16_bit() {
+        let data: Vec<u8> = [0u16, 65535, 257 * 7]
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect();
+        assert_eq!(
+            render_rgba(&data, ColorType::RGB(16), 1, 1, None).unwrap(),
+            vec![0, 255, 7, 255]
+        );
+        assert_eq!(
+            render_rgba(&data[..4], ColorType::GrayA(16), 1, 1, None).unwrap(),
+            vec![0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn test_render_with_mask() {
+        assert_eq!(
+            render_rgba(
+                &[1, 2, 3, 4, 5, 6],
+                ColorType::RGB(8),
+                2,
+                1,
+                Some(&mask(0b0100_0000, 2))
+            )
+            .unwrap(),
+            vec![1, 2, 3, 0, 4, 5, 6, 255]
+        );
+        // an existing alpha band is kept where the mask is set
+        assert_eq!(
+            render_rgba(
+                &[1, 100, 2, 200],
+                ColorType::GrayA(8),
+                2,
+                1,
+                Some(&mask(0b1000_0000, 2))
+            )
+            .unwrap(),
+            vec![1, 1, 1, 100, 2, 2, 2, 0]
+        );
+    }
+
+    #[test]
+    fn test_render_errors() {
+        assert!(render_rgba(&[1, 2], ColorType::Gray(12), 1, 1, None).is_err());
+        assert!(render_rgba(&[1, 2], ColorType::Gray(16), 2, 1, None).is_err());
+        assert!(render_rgba(&[1, 2], ColorType::Gray(8), 1, 1, None).is_err());
+        assert!(render_rgba(&[1], ColorType::Gray(8), 1, 1, Some(&mask(0, 2))).is_err());
+    }
+}
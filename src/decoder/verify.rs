@@ -0,0 +1,411 @@
+//! Checking that every chunk of a file decodes, for archival ingest.
+//!
+//! [`verify_full`] reads every IFD, SubIfds included, and fetches and decodes
+//! every chunk of every image in it. Failures don't stop the pass: they are
+//! recorded per chunk in a [`VerifyReport`], which can be written as JSON with
+//! [`VerifyReport::to_json`] to keep along with the file.
+//!
+//! [`verify_full`]: crate::decoder::verify_full
+//! [`VerifyReport`]: crate::decoder::VerifyReport
+//! [`VerifyReport::to_json`]: crate::decoder::VerifyReport::to_json
+
+use std::{collections::BTreeMap, fmt::Write, sync::Arc};
+
+use crate::{
+    decoder::{ifd_decoder::JoinBounded, ChunkIndex, CogDecoder, CogReader, DecoderOptions},
+    error::{TiffError, TiffFormatError, TiffResult, UsageError},
+    structs::{Ifd, Image, Tag, Tiff},
+    ChunkType,
+};
+
+/// Hash function of decoded chunks
+pub type ChunkHash = fn(&[u8]) -> Vec<u8>;
+
+/// Options of [`verify_full`]
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    /// Options to read the file with. Its caches are disabled, as every chunk
+    /// is decoded once.
+    pub decoder: DecoderOptions,
+    /// Hash of decoded chunks, like SHA-256, reported for every chunk. `None`
+    /// by default.
+    pub hash: Option<ChunkHash>,
+    /// Hashes the decoded chunks must have, by index of the image in
+    /// [`VerifyReport::images`] and chunk index. Only checked if
+    /// [`Self::hash`] is set.
+    pub expected_hashes: BTreeMap<(usize, ChunkIndex), Vec<u8>>,
+}
+
+/// Outcome of [`verify_full`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    /// Every IFD with image data, in the order of the chain, each followed by
+    /// its SubIfds depth first
+    pub images: Vec<ImageReport>,
+    /// Oddities that don't keep the file from decoding, like overview levels
+    /// tiled differently from the full resolution image, which some tools
+    /// don't expect. They don't count against [`Self::is_ok`].
+    pub warnings: Vec<String>,
+}
+
+/// Outcome of verifying a single image
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageReport {
+    /// Index of the IFD in the chain, followed by the index of each SubIfd on
+    /// the way to it
+    pub ifd: Vec<usize>,
+    /// Why the IFD doesn't describe an image that can be decoded, in which
+    /// case none of its chunks were checked
+    pub error: Option<String>,
+    pub chunks: Vec<ChunkReport>,
+}
+
+/// Outcome of verifying a single chunk
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChunkReport {
+    pub index: ChunkIndex,
+    pub offset: u64,
+    /// Compressed size, 0 for a sparse chunk
+    pub bytes: u64,
+    /// Size once decoded
+    pub decoded_bytes: u64,
+    /// See [`VerifyOptions::hash`]
+    pub hash: Option<Vec<u8>>,
+    /// Why the chunk couldn't be fetched or decoded, or isn't what was
+    /// expected
+    pub error: Option<String>,
+}
+
+impl VerifyReport {
+    /// Whether every chunk of every image decoded, to the expected size and
+    /// hash
+    pub fn is_ok(&self) -> bool {
+        self.images.iter().all(|image| {
+            image.error.is_none() && image.chunks.iter().all(|chunk| chunk.error.is_none())
+        })
+    }
+
+    /// The report as a JSON object, with hashes as hex strings
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"ok\":{},\"images\":[", self.is_ok());
+        for (i, image) in self.images.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let path: Vec<String> = image.ifd.iter().map(usize::to_string).collect();
+            let _ = write!(json, "{{\"ifd\":[{}],\"error\":", path.join(","));
+            push_json_string(&mut json, image.error.as_deref());
+            json.push_str(",\"chunks\":[");
+            for (j, chunk) in image.chunks.iter().enumerate() {
+                if j > 0 {
+                    json.push(',');
+                }
+                let _ = write!(
+                    json,
+                    "{{\"index\":{},\"offset\":{},\"bytes\":{},\"decoded_bytes\":{},\"hash\":",
+                    chunk.index, chunk.offset, chunk.bytes, chunk.decoded_bytes
+                );
+                let hash = chunk.hash.as_ref().map(|hash| hex(hash));
+                push_json_string(&mut json, hash.as_deref());
+                json.push_str(",\"error\":");
+                push_json_string(&mut json, chunk.error.as_deref());
+                json.push('}');
+            }
+            json.push_str("]}");
+        }
+        json.push_str("],\"warnings\":[");
+        for (i, warning) in self.warnings.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            push_json_string(&mut json, Some(warning));
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Read every IFD of a file and fetch and decode every chunk of every image,
+/// checking its size and, with [`VerifyOptions::hash`], its hash.
+///
+/// Only fails if the IFDs can't be read. Images and chunks that can't be
+/// decoded are reported as such, see [`VerifyReport::is_ok`]. Chunks of an
+/// image are fetched [`DecoderOptions::prefetch_concurrency`] at once.
+///
+/// Overview levels, the images of the main IFD chain other than masks, with
+/// tiles of another size than the first level's are reported in
+/// [`VerifyReport::warnings`], as each level is decoded with its own.
+pub async fn verify_full(
+    reader: Arc<dyn CogReader>,
+    options: &VerifyOptions,
+) -> TiffResult<VerifyReport> {
+    let decoder_options = DecoderOptions {
+        raw_cache_size: 0,
+        decoded_cache_size: 0,
+        ..options.decoder.clone()
+    };
+    let mut tiff = Tiff::read(&*reader, &decoder_options).await?;
+    let mut ifds = Vec::new();
+    for (i, ifd) in core::mem::take(&mut tiff.ifds).into_iter().enumerate() {
+        image_ifds(ifd, vec![i], &mut ifds);
+    }
+    let byte_order = tiff.byte_order;
+    let mut decoder = CogDecoder::new(reader.clone(), tiff, &decoder_options);
+    let mut images = Vec::with_capacity(ifds.len());
+    let mut warnings = Vec::new();
+    // IFD and tile size of the first tiled level
+    let mut level_tiles = None;
+    for (i_image, (path, ifd)) in ifds.into_iter().enumerate() {
+        let image = match Image::from_ifd(ifd, byte_order) {
+            Ok(image) => image,
+            Err(e) => {
+                images.push(ImageReport {
+                    ifd: path,
+                    error: Some(e.to_string()),
+                    chunks: Vec::new(),
+                });
+                continue;
+            }
+        };
+        let is_level = path.len() == 1 && !image.ifd.is_transparency_mask().unwrap_or(false);
+        if let Some(tile) = image
+            .chunk_meta
+            .tile_attributes
+            .as_ref()
+            .filter(|_| is_level)
+        {
+            let size = (tile.tile_width, tile.tile_length);
+            match level_tiles {
+                None => level_tiles = Some((path[0], size)),
+                Some((first, first_size)) if first_size != size => warnings.push(format!(
+                    "IFD {} has {}x{} tiles, unlike the {}x{} ones of IFD {first}",
+                    path[0], size.0, size.1, first_size.0, first_size.1
+                )),
+                Some(_) => {}
+            }
+        }
+        let n_chunks = usize::try_from(image.chunk_offsets.count())?;
+        decoder.insert_image(0, image);
+        let image = decoder.image(0).ok_or(UsageError::OverviewNotLoaded(0))?;
+        let chunks = (0..n_chunks)
+            .map(|i_chunk| verify_chunk(&decoder, image, &*reader, options, i_image, i_chunk))
+            .collect();
+        images.push(ImageReport {
+            ifd: path,
+            error: None,
+            chunks: JoinBounded::new(chunks, decoder_options.prefetch_concurrency).await,
+        });
+    }
+    Ok(VerifyReport { images, warnings })
+}
+
+/// Collect `ifd` if it has image data, and then its SubIfds, with the path
+/// leading to each
+fn image_ifds(mut ifd: Ifd, path: Vec<usize>, out: &mut Vec<(Vec<usize>, Ifd)>) {
+    let sub_ifds = ifd.take_sub_ifds();
+    if ifd.contains_key(&Tag::StripOffsets) || ifd.contains_key(&Tag::TileOffsets) {
+        out.push((path.clone(), ifd));
+    }
+    for (i, sub_ifd) in sub_ifds.into_iter().enumerate() {
+        let mut sub_path = path.clone();
+        sub_path.push(i);
+        image_ifds(sub_ifd, sub_path, out);
+    }
+}
+
+/// Verify chunk `i_chunk` of `image`, which is at level 0 of `decoder` and
+/// image `i_image` of the report
+async fn verify_chunk(
+    decoder: &CogDecoder,
+    image: &Image,
+    reader: &dyn CogReader,
+    options: &VerifyOptions,
+    i_image: usize,
+    i_chunk: ChunkIndex,
+) -> ChunkReport {
+    let mut report = ChunkReport {
+        index: i_chunk,
+        ..Default::default()
+    };
+    if let Err(e) = check_chunk(decoder, image, reader, options, i_image, &mut report).await {
+        report.error = Some(e.to_string());
+    }
+    report
+}
+
+async fn check_chunk(
+    decoder: &CogDecoder,
+    image: &Image,
+    reader: &dyn CogReader,
+    options: &VerifyOptions,
+    i_image: usize,
+    report: &mut ChunkReport,
+) -> TiffResult<()> {
+    let i_chunk = report.index;
+    report.offset = image.chunk_offsets.load_u64(i_chunk, reader).await?;
+    report.bytes = image.chunk_bytes.load_u64(i_chunk, reader).await?;
+    let end = report.offset.saturating_add(report.bytes);
+    if let Some(file_len) = reader.file_len().filter(|&len| end > len) {
+        return Err(invalid(format!(
+            "chunk ends at byte {end}, past the end of the file at {file_len}"
+        )));
+    }
+    let data = decoder.get_chunk(i_chunk, 0)?.await?;
+    report.decoded_bytes = data.len() as u64;
+    // the last strip may leave out the rows past the end of the image
+    let meta = image.chunk_meta();
+    let expected = meta.chunk_len().zip(meta.chunk_dims(i_chunk));
+    if let Some((full, dims)) = expected {
+        let min = match meta.chunk_type {
+            ChunkType::Tile => full,
+            ChunkType::Strip => full / u64::from(dims.height) * u64::from(dims.valid_height),
+        };
+        if !(min..=full).contains(&report.decoded_bytes) {
+            return Err(invalid(format!(
+                "chunk decoded to {} bytes instead of {min}",
+                report.decoded_bytes
+            )));
+        }
+    }
+    if let Some(hash) = options.hash {
+        let hash = hash(&data);
+        let expected = options.expected_hashes.get(&(i_image, i_chunk));
+        report.hash = Some(hash);
+        if expected.is_some_and(|expected| Some(expected) != report.hash.as_ref()) {
+            return Err(invalid("chunk doesn't have the expected hash".into()));
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: String) -> TiffError {
+    TiffFormatError::Format(message).into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+/// Append `s` as a JSON string, or `null`
+fn push_json_string(json: &mut String, s: Option<&str>) {
+    let Some(s) = s else {
+        json.push_str("null");
+        return;
+    };
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        encoder::{CogEncoder, CogLayout, Level},
+        structs::tags::CompressionMethod,
+    };
+    use sha2::{Digest, Sha256};
+
+    fn sha256(data: &[u8]) -> Vec<u8> {
+        Sha256::digest(data).to_vec()
+    }
+
+    /// 32x32 gray COG in 16x16 deflated tiles of their index, with a 16x16
+    /// overview in tiles of `overview_tile` pixels square
+    fn cog(overview_tile: u32) -> Vec<u8> {
+        CogEncoder::new(Vec::new(), CogLayout::HeaderFirst)
+            .unwrap()
+            .with_compression(CompressionMethod::Deflate)
+            .encode_levels(
+                [(32, 16), (16, overview_tile)]
+                    .map(|(size, tile)| Level::gray(size, size, tile, |i, _| i as u8)),
+            )
+    }
+
+    #[tokio::test]
+    async fn test_verify_full() {
+        let cog = cog(16);
+        let mut options = VerifyOptions {
+            hash: Some(sha256),
+            ..Default::default()
+        };
+        let report = verify_full(Arc::new(cog.clone()), &options).await.unwrap();
+        assert!(report.is_ok());
+        assert!(report.warnings.is_empty());
+        let paths: Vec<_> = report
+            .images
+            .iter()
+            .map(|image| image.ifd.clone())
+            .collect();
+        assert_eq!(paths, [[0], [1]]);
+        assert_eq!(report.images[0].chunks.len(), 4);
+        let chunk = &report.images[0].chunks[1];
+        assert_eq!(chunk.decoded_bytes, 256);
+        assert_eq!(chunk.hash, Some(sha256(&[1; 256])));
+
+        // a chunk that doesn't inflate, and one with another hash
+        let mut corrupt = cog;
+        let chunk = &report.images[0].chunks[2];
+        corrupt[chunk.offset as usize..(chunk.offset + chunk.bytes) as usize].fill(0xff);
+        options.expected_hashes.insert((1, 0), sha256(&[1; 256]));
+        let report = verify_full(Arc::new(corrupt), &options).await.unwrap();
+        assert!(!report.is_ok());
+        let failed: Vec<_> = report
+            .images
+            .iter()
+            .enumerate()
+            .flat_map(|(i, image)| {
+                image
+                    .chunks
+                    .iter()
+                    .filter(|chunk| chunk.error.is_some())
+                    .map(move |chunk| (i, chunk.index))
+            })
+            .collect();
+        assert_eq!(failed, [(0, 2), (1, 0)]);
+        let json = report.to_json();
+        assert!(json
+            .starts_with(r#"{"ok":false,"images":[{"ifd":[0],"error":null,"chunks":[{"index":0,"#));
+        assert!(json.contains(&format!(r#""hash":"{}""#, hex(&sha256(&[0; 256])))));
+        assert!(json.ends_with(r#"]}],"warnings":[]}"#));
+    }
+
+    #[tokio::test]
+    async fn test_verify_level_tile_sizes() {
+        let report = verify_full(Arc::new(cog(32)), &VerifyOptions::default())
+            .await
+            .unwrap();
+        // a warning, but each level decodes in tiles of its own size
+        assert!(report.is_ok());
+        assert_eq!(
+            report.warnings,
+            ["IFD 1 has 32x32 tiles, unlike the 16x16 ones of IFD 0"]
+        );
+        let overview = &report.images[1].chunks;
+        assert_eq!(overview.len(), 1);
+        assert_eq!(overview[0].decoded_bytes, 32 * 32);
+        assert!(report
+            .to_json()
+            .ends_with(r#""warnings":["IFD 1 has 32x32 tiles, unlike the 16x16 ones of IFD 0"]}"#));
+    }
+
+    #[test]
+    fn test_json_string() {
+        let mut json = String::new();
+        push_json_string(&mut json, Some("a \"b\"\\\n"));
+        push_json_string(&mut json, None);
+        assert_eq!(json, r#""a \"b\"\\\u000a"null"#);
+    }
+}
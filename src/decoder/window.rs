@@ -0,0 +1,223 @@
+//! Windows that may extend past the edges of an image.
+//!
+//! Tile servers cut tiles of their own grid out of a raster, and the tiles
+//! along its edges straddle the boundary. [`CogDecoder::decode_window`] takes
+//! a window anywhere relative to the image, decodes the part that overlaps
+//! it, and fills in the rest according to an [`EdgePolicy`].
+//!
+//! [`CogDecoder::decode_window`]: crate::decoder::CogDecoder::decode_window
+
+use alloc::vec::Vec;
+
+use crate::{
+    error::{TiffResult, UsageError},
+    structs::Rect,
+};
+
+/// What to do with the pixels of a window that lie outside the image
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EdgePolicy {
+    /// Fail with [`UsageError::WindowOutOfBounds`]
+    #[default]
+    Error,
+    /// Repeat the pixels on the edges of the image, so a window that doesn't
+    /// overlap the image at all gets the nearest edge or corner
+    Clamp,
+    /// Fill with a value, typically the nodata value of the image, converted
+    /// to the type of the samples like [`BandMath`] results are
+    ///
+    /// [`BandMath`]: crate::decoder::BandMath
+    Fill(f64),
+}
+
+/// A window of `width * height` pixels with its top left pixel at `(x, y)`,
+/// which may lie outside the image
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Window {
+    pub x: i64,
+    pub y: i64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Window {
+    /// Part of the image to decode for the window: the part they overlap, or
+    /// with [`EdgePolicy::Clamp`] also the edge pixels nearest to a window
+    /// that lies beside it. `None` if nothing is needed.
+    pub fn source(
+        &self,
+        edge: EdgePolicy,
+        image_width: u32,
+        image_height: u32,
+    ) -> TiffResult<Option<Rect>> {
+        let clamp = edge == EdgePolicy::Clamp;
+        let columns = span(self.x, self.width, image_width, clamp);
+        let rows = span(self.y, self.height, image_height, clamp);
+        let inside = columns == Some((self.x, self.width)) && rows == Some((self.y, self.height));
+        if edge == EdgePolicy::Error && !inside && self.width > 0 && self.height > 0 {
+            return Err(UsageError::WindowOutOfBounds {
+                x: self.x,
+                y: self.y,
+                width: self.width,
+                height: self.height,
+            }
+            .into());
+        }
+        Ok(match (columns, rows) {
+            (Some((x, width)), Some((y, height))) => {
+                Some(Rect::new(x as u32, y as u32, width, height))
+            }
+            _ => None,
+        })
+    }
+
+    /// Lay out the window, given the decoded `source` pixels of each of
+    /// `planes` sample planes of `pixel_bytes` per pixel, and the bytes of a
+    /// pixel of a plane to fill with
+    pub fn assemble(
+        &self,
+        edge: EdgePolicy,
+        source: Option<(Rect, Vec<u8>)>,
+        planes: usize,
+        pixel_bytes: usize,
+        fill: &[u8],
+    ) -> Vec<u8> {
+        let px = pixel_bytes;
+        let row_len = self.width as usize * px;
+        let plane_len = row_len * self.height as usize;
+        let mut out = fill.repeat(self.width as usize * self.height as usize * planes);
+        let Some((rect, data)) = source else {
+            return out;
+        };
+        let (rect_x, rect_y) = (i64::from(rect.x), i64::from(rect.y));
+        let (rect_right, rect_bottom) = (
+            rect_x + i64::from(rect.width),
+            rect_y + i64::from(rect.height),
+        );
+        // window columns that have a pixel of their own in the source
+        let start = self.x.max(rect_x);
+        let end = (self.x + i64::from(self.width)).min(rect_right);
+        let src_row_len = rect.width as usize * px;
+        for plane in 0..planes {
+            let src_plane = &data[plane * src_row_len * rect.height as usize..];
+            let dst_plane = &mut out[plane * plane_len..(plane + 1) * plane_len];
+            for (oy, dst) in dst_plane.chunks_exact_mut(row_len).enumerate() {
+                let y = self.y + oy as i64;
+                let y = match edge {
+                    EdgePolicy::Clamp => y.clamp(rect_y, rect_bottom - 1),
+                    _ if (rect_y..rect_bottom).contains(&y) => y,
+                    _ => continue,
+                };
+                let src = &src_plane[(y - rect_y) as usize * src_row_len..][..src_row_len];
+                if start < end {
+                    let dst_start = (start - self.x) as usize * px;
+                    let src_start = (start - rect_x) as usize * px;
+                    let len = (end - start) as usize * px;
+                    dst[dst_start..dst_start + len]
+                        .copy_from_slice(&src[src_start..src_start + len]);
+                }
+                if edge == EdgePolicy::Clamp {
+                    let (first, last) = (&src[..px], &src[src_row_len - px..]);
+                    let left = (rect_x - self.x).clamp(0, i64::from(self.width)) as usize;
+                    let right = (rect_right - self.x).clamp(0, i64::from(self.width)) as usize;
+                    for pixel in dst[..left * px].chunks_exact_mut(px) {
+                        pixel.copy_from_slice(first);
+                    }
+                    for pixel in dst[right * px..].chunks_exact_mut(px) {
+                        pixel.copy_from_slice(last);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Start and length of the pixels of an axis of an image of `size` pixels
+/// that `len` pixels starting at `start` need
+fn span(start: i64, len: u32, size: u32, clamp: bool) -> Option<(i64, u32)> {
+    if len == 0 || size == 0 {
+        return None;
+    }
+    let size = i64::from(size);
+    let (lo, hi) = if clamp {
+        (
+            start.clamp(0, size - 1),
+            (start + i64::from(len)).clamp(1, size),
+        )
+    } else {
+        (start.max(0), (start + i64::from(len)).min(size))
+    };
+    (lo < hi).then(|| (lo, (hi - lo) as u32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_source() {
+        let window = |x, y| Window {
+            x,
+            y,
+            width: 4,
+            height: 2,
+        };
+        let source = |x, y, edge| window(x, y).source(edge, 10, 5).unwrap();
+        assert_eq!(source(1, 1, EdgePolicy::Error), Some(Rect::new(1, 1, 4, 2)));
+        assert!(window(-1, 1).source(EdgePolicy::Error, 10, 5).is_err());
+        assert!(window(7, 4).source(EdgePolicy::Error, 10, 5).is_err());
+        let fill = EdgePolicy::Fill(0.0);
+        assert_eq!(source(-1, 4, fill), Some(Rect::new(0, 4, 3, 1)));
+        assert_eq!(source(8, -1, fill), Some(Rect::new(8, 0, 2, 1)));
+        assert_eq!(source(-4, 0, fill), None);
+        assert_eq!(source(3, 5, fill), None);
+        // the nearest edge or corner
+        assert_eq!(
+            source(-10, -10, EdgePolicy::Clamp),
+            Some(Rect::new(0, 0, 1, 1))
+        );
+        assert_eq!(
+            source(12, 2, EdgePolicy::Clamp),
+            Some(Rect::new(9, 2, 1, 2))
+        );
+        assert_eq!(
+            source(-1, 4, EdgePolicy::Clamp),
+            Some(Rect::new(0, 4, 3, 1))
+        );
+    }
+
+    #[test]
+    fn test_assemble() {
+        // 3x2 pixels of 2 planes, of the image at (0, 0)
+        let rect = Rect::new(0, 0, 3, 2);
+        let data: Vec<u8> = (1..=12).collect();
+        let window = Window {
+            x: -1,
+            y: 1,
+            width: 5,
+            height: 2,
+        };
+        let filled = window.assemble(
+            EdgePolicy::Fill(0.0),
+            Some((rect, data.clone())),
+            2,
+            1,
+            &[0],
+        );
+        assert_eq!(
+            filled,
+            [0, 4, 5, 6, 0, 0, 0, 0, 0, 0, 0, 10, 11, 12, 0, 0, 0, 0, 0, 0]
+        );
+        let clamped = window.assemble(EdgePolicy::Clamp, Some((rect, data)), 2, 1, &[0]);
+        assert_eq!(
+            clamped,
+            [4, 4, 5, 6, 6, 4, 4, 5, 6, 6, 10, 10, 11, 12, 12, 10, 10, 11, 12, 12]
+        );
+        // nothing to copy
+        assert_eq!(
+            window.assemble(EdgePolicy::Fill(0.0), None, 1, 2, &[1, 2]),
+            [1, 2].repeat(10)
+        );
+    }
+}
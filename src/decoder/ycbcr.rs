@@ -0,0 +1,162 @@
+//! Converting YCbCr samples to RGB, and back.
+//!
+//! Chroma subsampling is undone while decoding chunks, and JPEG leaves
+//! samples in YCbCr too, so a conversion works on full pixels of 8-bit
+//! samples. It follows section 21 of the TIFF 6.0 spec, with the luma
+//! coefficients of `YCbCrCoefficients` and the ranges of `ReferenceBlackWhite`.
+//! The encoder converts the other way when writing RGB as YCbCr.
+
+use crate::{
+    error::{TiffFormatError, TiffResult},
+    structs::{Ifd, Tag, TagType},
+};
+
+/// Parameters of the conversion of YCbCr samples to RGB
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct YCbCrConversion {
+    /// Contributions of red, green and blue to luma
+    pub coefficients: [f32; 3],
+    /// Samples of black and white for Y, Cb and Cr
+    pub reference_black_white: [f32; 6],
+}
+
+impl Default for YCbCrConversion {
+    /// The full range BT.601 conversion of JFIF, which the encoder writes
+    fn default() -> Self {
+        YCbCrConversion {
+            coefficients: [0.299, 0.587, 0.114],
+            reference_black_white: [0.0, 255.0, 128.0, 255.0, 128.0, 255.0],
+        }
+    }
+}
+
+impl YCbCrConversion {
+    /// The conversion of an IFD, defaults being used for missing tags.
+    ///
+    /// Fails with [`TiffFormatError::InvalidTagValueType`] if a tag doesn't
+    /// hold the right number of rationals, or values that would divide by
+    /// zero.
+    pub fn from_ifd(ifd: &Ifd) -> TiffResult<Self> {
+        let mut conversion = Self::default();
+        if let Some(coefficients) = rationals(ifd, Tag::YCbCrCoefficients)? {
+            if coefficients[1] == 0.0 {
+                return Err(invalid(Tag::YCbCrCoefficients));
+            }
+            conversion.coefficients = coefficients;
+        }
+        if let Some(reference) = rationals(ifd, Tag::ReferenceBlackWhite)? {
+            if reference.chunks_exact(2).any(|range| range[0] == range[1]) {
+                return Err(invalid(Tag::ReferenceBlackWhite));
+            }
+            conversion.reference_black_white = reference;
+        }
+        Ok(conversion)
+    }
+
+    /// Convert pixels of 8-bit YCbCr samples to RGB, in place
+    pub fn to_rgb(&self, data: &mut [u8]) {
+        let [luma_red, luma_green, luma_blue] = self.coefficients;
+        let [y_black, y_white, cb_black, cb_white, cr_black, cr_white] = self.reference_black_white;
+        let y_scale = 255.0 / (y_white - y_black);
+        let cb_scale = 127.0 / (cb_white - cb_black);
+        let cr_scale = 127.0 / (cr_white - cr_black);
+        for pixel in data.chunks_exact_mut(3) {
+            let y = (f32::from(pixel[0]) - y_black) * y_scale;
+            let cb = (f32::from(pixel[1]) - cb_black) * cb_scale;
+            let cr = (f32::from(pixel[2]) - cr_black) * cr_scale;
+            let r = cr * (2.0 - 2.0 * luma_red) + y;
+            let b = cb * (2.0 - 2.0 * luma_blue) + y;
+            let g = (y - luma_blue * b - luma_red * r) / luma_green;
+            pixel[0] = clamp(r);
+            pixel[1] = clamp(g);
+            pixel[2] = clamp(b);
+        }
+    }
+
+    /// Convert pixels of 8-bit RGB samples to YCbCr, in place, undoing
+    /// [`YCbCrConversion::to_rgb`]
+    pub fn to_ycbcr(&self, data: &mut [u8]) {
+        let [luma_red, luma_green, luma_blue] = self.coefficients;
+        let [y_black, y_white, cb_black, cb_white, cr_black, cr_white] = self.reference_black_white;
+        let y_scale = (y_white - y_black) / 255.0;
+        let cb_scale = (cb_white - cb_black) / 127.0;
+        let cr_scale = (cr_white - cr_black) / 127.0;
+        for pixel in data.chunks_exact_mut(3) {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+            let y = luma_red * r + luma_green * g + luma_blue * b;
+            let cb = (b - y) / (2.0 - 2.0 * luma_blue);
+            let cr = (r - y) / (2.0 - 2.0 * luma_red);
+            pixel[0] = clamp(y * y_scale + y_black);
+            pixel[1] = clamp(cb * cb_scale + cb_black);
+            pixel[2] = clamp(cr * cr_scale + cr_black);
+        }
+    }
+}
+
+/// Round to the nearest 8-bit sample, `as` saturating out of range values
+fn clamp(v: f32) -> u8 {
+    (v + 0.5) as u8
+}
+
+fn invalid(tag: Tag) -> crate::error::TiffError {
+    TiffFormatError::InvalidTagValueType(tag.to_u16()).into()
+}
+
+/// The `N` rationals of `tag`, if it is present
+fn rationals<const N: usize>(ifd: &Ifd, tag: Tag) -> TiffResult<Option<[f32; N]>> {
+    let Some(entry) = ifd.get_tag_value(&tag)? else {
+        return Ok(None);
+    };
+    if entry.tag_type != TagType::RATIONAL || entry.count != N as u64 {
+        return Err(invalid(tag));
+    }
+    let mut values = [0.0; N];
+    for (value, rational) in values.iter_mut().zip(entry.data().chunks_exact(8)) {
+        let n = u32::from_ne_bytes(rational[..4].try_into().unwrap());
+        let d = u32::from_ne_bytes(rational[4..].try_into().unwrap());
+        if d == 0 {
+            return Err(invalid(tag));
+        }
+        *value = n as f32 / d as f32;
+    }
+    Ok(Some(values))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_rgb() {
+        let mut data = [128, 128, 128, 255, 128, 128, 76, 85, 255, 0, 0, 0];
+        YCbCrConversion::default().to_rgb(&mut data);
+        assert_eq!(data[..6], [128, 128, 128, 255, 255, 255]);
+        // red, and a pixel below the gamut
+        assert_eq!(data[6..9], [254, 0, 0]);
+        assert_eq!(data[9..], [0, 135, 0]);
+
+        // studio range, black and white at 16 and 235
+        let studio = YCbCrConversion {
+            reference_black_white: [16.0, 235.0, 128.0, 240.0, 128.0, 240.0],
+            ..Default::default()
+        };
+        let mut data = [16, 128, 128, 235, 128, 128];
+        studio.to_rgb(&mut data);
+        assert_eq!(data, [0, 0, 0, 255, 255, 255]);
+        studio.to_ycbcr(&mut data);
+        assert_eq!(data, [16, 128, 128, 235, 128, 128]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rgb = [255, 255, 255, 0, 0, 0, 255, 0, 0, 10, 200, 90];
+        let mut data = rgb;
+        YCbCrConversion::default().to_ycbcr(&mut data);
+        assert_eq!(data[..9], [255, 128, 128, 0, 128, 128, 76, 85, 255]);
+        YCbCrConversion::default().to_rgb(&mut data);
+        // off by at most one from rounding twice
+        for (converted, original) in data.iter().zip(rgb) {
+            assert!(converted.abs_diff(original) <= 1, "{data:?} != {rgb:?}");
+        }
+    }
+}
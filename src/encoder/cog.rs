@@ -0,0 +1,1758 @@
+//! Opinionated COG encoder.
+//!
+//! A COG consists of a full resolution image followed by its overviews, each
+//! in their own IFD. Two layouts are supported:
+//!
+//! - [`CogLayout::HeaderFirst`]: all IFDs come first, followed by the tile
+//!   data of the smallest overview up to the full resolution image. This is
+//!   what GDAL writes, and lets readers get all metadata in a single request.
+//!   All levels are buffered until [`CogEncoder::finish`].
+//! - [`CogLayout::Interleaved`]: each level's IFD immediately precedes its
+//!   tile data. Levels are written as soon as they are added, so overviews can
+//!   be generated on the fly in a single streaming pass, without `Seek`.
+//!
+//! Both are plain TIFFs whose IFDs form a chain, so any reader that follows
+//! the chain (including this crate's decoder and libtiff) can read either.
+//! With the interleaved layout, getting the metadata of all levels takes one
+//! request per level, and GDAL's COG validator reports the IFDs not being at
+//! the start of the file.
+//!
+//! Tiles are written in little-endian byte order, uncompressed unless
+//! [`CogEncoder::with_compression`] asks for Deflate, and optionally with a
+//! predictor, see [`CogEncoder::with_predictor`]. RGB is kept RGB, unless
+//! [`CogEncoder::with_photometric`] asks for YCbCr. Georeferencing and other
+//! tags can be added through [`Level::extra_tags`]. Overviews never get geo
+//! or resolution tags: readers take those from the full resolution image,
+//! see [`Tiff::geotransform`](crate::structs::Tiff::geotransform).
+//!
+//! Tiles of a level are written back to back in row-major order, so without
+//! compression its tile offsets increase by a constant stride and its byte
+//! counts are all the same. These arrays make up most of the header of a
+//! BigTIFF with millions of tiles, and compress to almost nothing when the
+//! file is served with transfer compression.
+//! [`CogEncoder::with_compact_offsets`] additionally writes them as LONG
+//! instead of LONG8 when the file is small enough.
+//!
+//! Masked or padded mosaics are mostly tiles of a single value.
+//! [`CogEncoder::with_uniform_tiles`] writes such tiles once per level and
+//! points the offsets of the others at that copy, or leaves tiles of the
+//! nodata value out entirely as GDAL's sparse tiles, with an offset and byte
+//! count of 0. Readers that don't know sparse tiles fail on those.
+//!
+//! A level can come with a 1-bit transparency mask, see
+//! [`CogEncoder::write_level_with_mask`]. Like GDAL's internal masks, it is
+//! written as its own IFD right after that of the level, with the same
+//! tiling, and its tile data follows that of the level.
+//!
+//! Pyramids that lack coarse levels can be topped up with
+//! [`CogEncoder::write_level_with_overviews`], which downsamples the smallest
+//! level given until it fits in a single tile.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use alloc::vec::Vec;
+use miniz_oxide::deflate;
+
+use crate::{
+    decoder::{SampleType, YCbCrConversion},
+    encoder::{
+        directory::{canonicalize, encode_ifd, entry, entry_len, ifd_len, EncodedDirectory},
+        options::{CogWriterOptions, Raster, Resampling},
+        photometric::PhotometricPolicy,
+        tiff_value::Rational,
+        writer::TiffWriter,
+    },
+    error::{TiffError, TiffFormatError, TiffResult, TiffUnsupportedError, UsageError},
+    nodata::Nodata,
+    predictor,
+    progress::{ProgressObserver, ProgressTracker},
+    structs::{
+        geo::GEO_TAGS,
+        tags::{
+            CompressionMethod, ExtraSample, PhotometricInterpretation, PlanarConfiguration,
+            Predictor, SampleFormat,
+        },
+        BufferedEntry, Tag,
+    },
+    util::fix_endianness,
+    ByteOrder, ColorType,
+};
+
+/// Order of IFDs and tile data in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CogLayout {
+    /// All IFDs first, then the tile data from the smallest overview up to the
+    /// full resolution image. Levels are buffered until [`CogEncoder::finish`].
+    #[default]
+    HeaderFirst,
+    /// Each level's IFD directly followed by its tile data, written as soon as
+    /// the level is added
+    Interleaved,
+}
+
+/// What the encoder does with tiles whose pixels all have the same value, see
+/// [`CogEncoder::with_uniform_tiles`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UniformTiles {
+    /// Write every tile
+    #[default]
+    Keep,
+    /// Write the first tile of each value in a level once, and point the
+    /// offsets of the other tiles of that value at it
+    Share,
+    /// Like [`UniformTiles::Share`], but write no data at all for tiles of the
+    /// level's [`Tag::GdalNodata`] value, or of zeros if it has none, giving
+    /// them an offset and byte count of 0. GDAL reads such sparse tiles as
+    /// nodata. Samples are compared to nodata with [`Nodata::matches`], and
+    /// nodata that the samples can't hold makes no tiles sparse.
+    Sparse,
+}
+
+/// `NewSubfileType` bit of overviews
+const REDUCED_RESOLUTION: u32 = 1;
+/// `NewSubfileType` bit of transparency masks
+const TRANSPARENCY_MASK: u32 = 4;
+
+/// Where the data of a tile is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TileData {
+    /// Written for the tile itself
+    Own,
+    /// That of an earlier tile of the same level
+    Shared(usize),
+    /// Nowhere, the tile is sparse
+    Sparse,
+}
+
+/// Why the IFDs of a COG don't fit in its header budget, see
+/// [`CogEncoder::with_header_budget`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderReport {
+    /// Bytes the header may take
+    pub budget: u64,
+    /// Bytes up to the end of the last IFD that was to go in the header, tag
+    /// data included
+    pub header_bytes: u64,
+    /// Level and bytes of each IFD that was to go in the header, tag data
+    /// included
+    pub ifds: Vec<(usize, u64)>,
+    /// Level, tag and bytes of the largest entries of these IFDs, largest
+    /// first, their values included
+    pub largest_tags: Vec<(usize, Tag, u64)>,
+}
+
+impl HeaderReport {
+    /// Number of entries listed in [`HeaderReport::largest_tags`]
+    const LARGEST_TAGS: usize = 5;
+
+    fn new<'a>(
+        budget: u64,
+        header_bytes: u64,
+        dirs: impl IntoIterator<Item = (usize, &'a EncodedDirectory)>,
+        bigtiff: bool,
+    ) -> Self {
+        let mut ifds = Vec::new();
+        let mut largest_tags = Vec::new();
+        for (level, dir) in dirs {
+            ifds.push((level, ifd_len(dir, bigtiff)));
+            largest_tags.extend(
+                dir.iter()
+                    .map(|(tag, e)| (level, *tag, entry_len(e, bigtiff))),
+            );
+        }
+        largest_tags.sort_by_key(|(level, _, bytes)| (core::cmp::Reverse(*bytes), *level));
+        largest_tags.truncate(Self::LARGEST_TAGS);
+        HeaderReport {
+            budget,
+            header_bytes,
+            ifds,
+            largest_tags,
+        }
+    }
+}
+
+impl core::fmt::Display for HeaderReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Header of {} bytes exceeds the budget of {} bytes, largest entries:",
+            self.header_bytes, self.budget
+        )?;
+        for (level, tag, bytes) in &self.largest_tags {
+            write!(f, " {tag:?} of level {level} ({bytes} bytes)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Tags describing the full resolution image, which are left out of overviews
+pub(super) fn overview_disallowed(tag: &Tag) -> bool {
+    GEO_TAGS.contains(tag)
+        || matches!(
+            tag,
+            Tag::XResolution | Tag::YResolution | Tag::ResolutionUnit
+        )
+}
+
+/// A single resolution level (full image or overview) to be written
+#[derive(Debug, Clone)]
+pub struct Level {
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub color_type: ColorType,
+    pub sample_format: SampleFormat,
+    /// Uncompressed tiles in row-major order, with native-endian samples
+    pub tiles: Vec<Vec<u8>>,
+    /// Additional tags, e.g. GeoTIFF's. Tags the encoder writes itself take
+    /// precedence. Geo and resolution tags are dropped from overviews, so the
+    /// same tags can be given for every level.
+    pub extra_tags: EncodedDirectory,
+}
+
+impl Level {
+    pub(super) fn tiles_across(&self) -> u32 {
+        self.width.div_ceil(self.tile_width)
+    }
+
+    pub(super) fn tiles_down(&self) -> u32 {
+        self.height.div_ceil(self.tile_height)
+    }
+
+    /// Photometric interpretation, samples per pixel and extra samples
+    fn color_tags(&self) -> TiffResult<(PhotometricInterpretation, u16, Vec<u16>)> {
+        let alpha = ExtraSample::UnassociatedAlpha.to_u16();
+        Ok(match self.color_type {
+            ColorType::Gray(_) => (PhotometricInterpretation::BlackIsZero, 1, Vec::new()),
+            ColorType::GrayA(_) => (PhotometricInterpretation::BlackIsZero, 2, vec![alpha]),
+            ColorType::RGB(_) => (PhotometricInterpretation::RGB, 3, Vec::new()),
+            ColorType::RGBA(_) => (PhotometricInterpretation::RGB, 4, vec![alpha]),
+            ColorType::CMYK(_) => (PhotometricInterpretation::CMYK, 4, Vec::new()),
+            ColorType::YCbCr(_) => (PhotometricInterpretation::YCbCr, 3, Vec::new()),
+            ColorType::Lab(_) => (PhotometricInterpretation::CIELab, 3, Vec::new()),
+            ColorType::Multiband { num_samples, .. } if num_samples > 0 => (
+                PhotometricInterpretation::BlackIsZero,
+                num_samples,
+                vec![0; usize::from(num_samples - 1)],
+            ),
+            _ => return Err(TiffUnsupportedError::UnsupportedColorType(self.color_type).into()),
+        })
+    }
+
+    /// Check the tiles, and build the IFD with zeroed tile offsets, which are
+    /// LONG8 if `long8` is set and LONG otherwise
+    pub(super) fn directory(
+        &self,
+        is_overview: bool,
+        long8: bool,
+        predictor: Predictor,
+    ) -> TiffResult<EncodedDirectory> {
+        self.check(predictor)?;
+        let subfile_type = if is_overview { REDUCED_RESOLUTION } else { 0 };
+        self.encoded_directory(subfile_type, long8, predictor, CompressionMethod::None, &[])
+    }
+
+    /// Check the size of the level and its tiles, and that `predictor` works
+    /// on its samples
+    fn check(&self, predictor: Predictor) -> TiffResult<()> {
+        if self.width == 0
+            || self.height == 0
+            || !self.tile_width.is_multiple_of(16)
+            || !self.tile_height.is_multiple_of(16)
+            || self.tile_width == 0
+            || self.tile_height == 0
+        {
+            return Err(TiffFormatError::InvalidDimensions(self.width, self.height).into());
+        }
+        let (_, samples, _) = self.color_tags()?;
+        let bits = self.color_type.bit_depth();
+        let is_float = self.sample_format == SampleFormat::IEEEFP;
+        match predictor {
+            Predictor::None => {}
+            Predictor::Horizontal if !is_float && matches!(bits, 8 | 16 | 32 | 64) => {}
+            Predictor::Horizontal => {
+                return Err(TiffUnsupportedError::HorizontalPredictor(self.color_type).into())
+            }
+            Predictor::FloatingPoint if is_float && matches!(bits, 16 | 32 | 64) => {}
+            Predictor::FloatingPoint => {
+                return Err(TiffUnsupportedError::FloatingPointPredictor(self.color_type).into())
+            }
+        }
+
+        let n_tiles = usize::try_from(self.tiles_across() * self.tiles_down())?;
+        if self.tiles.len() != n_tiles {
+            return Err(UsageError::InvalidChunkCount {
+                actual: self.tiles.len(),
+                expected: n_tiles,
+            }
+            .into());
+        }
+        let row_bytes =
+            (u64::from(self.tile_width) * u64::from(samples) * u64::from(bits)).div_ceil(8);
+        let tile_bytes = usize::try_from(row_bytes * u64::from(self.tile_height))?;
+        if let Some(tile) = self.tiles.iter().find(|t| t.len() != tile_bytes) {
+            return Err(UsageError::InvalidChunkLength {
+                actual: tile.len(),
+                expected: tile_bytes,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Build the IFD of the level once its tiles were encoded with
+    /// `predictor` and `compression`, with zeroed tile offsets and the byte
+    /// counts of the tiles as they are. `subfile_type` is its
+    /// `NewSubfileType`, geo and resolution tags being left out of anything
+    /// but the full resolution image.
+    fn encoded_directory(
+        &self,
+        subfile_type: u32,
+        long8: bool,
+        predictor: Predictor,
+        compression: CompressionMethod,
+        tile_data: &[TileData],
+    ) -> TiffResult<EncodedDirectory> {
+        let (mut photometric, samples, extra_samples) = self.color_tags()?;
+        if subfile_type & TRANSPARENCY_MASK != 0 {
+            photometric = PhotometricInterpretation::TransparencyMask;
+        }
+        let bits = self.color_type.bit_depth();
+        let mut dir: EncodedDirectory = self
+            .extra_tags
+            .iter()
+            .filter(|(tag, _)| !(subfile_type != 0 && overview_disallowed(tag)))
+            .map(|(tag, entry)| (*tag, entry.clone()))
+            .collect();
+        if subfile_type != 0 {
+            // reduced resolution version of another image, or its mask
+            dir.insert(Tag::NewSubfileType, entry(&subfile_type));
+        }
+        dir.insert(Tag::ImageWidth, entry(&self.width));
+        dir.insert(Tag::ImageLength, entry(&self.height));
+        dir.insert(
+            Tag::BitsPerSample,
+            entry(&vec![u16::from(bits); usize::from(samples)][..]),
+        );
+        dir.insert(Tag::Compression, entry(&compression.to_u16()));
+        dir.insert(Tag::PhotometricInterpretation, entry(&photometric.to_u16()));
+        dir.insert(Tag::SamplesPerPixel, entry(&samples));
+        dir.insert(
+            Tag::PlanarConfiguration,
+            entry(&PlanarConfiguration::Chunky.to_u16()),
+        );
+        if predictor != Predictor::None {
+            dir.insert(Tag::Predictor, entry(&predictor.to_u16()));
+        }
+        dir.insert(Tag::TileWidth, entry(&self.tile_width));
+        dir.insert(Tag::TileLength, entry(&self.tile_height));
+        let n_tiles = self.tiles.len();
+        let byte_counts = self.byte_counts(tile_data);
+        if long8 {
+            dir.insert(Tag::TileOffsets, entry(&vec![0u64; n_tiles][..]));
+            dir.insert(Tag::TileByteCounts, entry(&byte_counts[..]));
+        } else {
+            let byte_counts = byte_counts
+                .into_iter()
+                .map(u32::try_from)
+                .collect::<Result<Vec<_>, _>>()?;
+            dir.insert(Tag::TileOffsets, entry(&vec![0u32; n_tiles][..]));
+            dir.insert(Tag::TileByteCounts, entry(&byte_counts[..]));
+        }
+        if !extra_samples.is_empty() {
+            dir.insert(Tag::ExtraSamples, entry(&extra_samples[..]));
+        }
+        if photometric == PhotometricInterpretation::YCbCr {
+            // full range and no subsampling, instead of the defaults of 2x2
+            dir.insert(Tag::YCbCrSubSampling, entry(&[1u16, 1][..]));
+            let reference = [0, 255, 128, 255, 128, 255].map(|n| Rational { n, d: 1 });
+            dir.insert(Tag::ReferenceBlackWhite, entry(&reference[..]));
+        }
+        dir.insert(
+            Tag::SampleFormat,
+            entry(&vec![self.sample_format.to_u16(); usize::from(samples)][..]),
+        );
+        Ok(dir)
+    }
+
+    /// Byte count of each tile, where tiles that aren't written themselves
+    /// are given by `tile_data`, all tiles being written if it is empty
+    fn byte_counts(&self, tile_data: &[TileData]) -> Vec<u64> {
+        (0..self.tiles.len())
+            .map(|i| match tile_data.get(i) {
+                None | Some(TileData::Own) => self.tiles[i].len() as u64,
+                Some(TileData::Shared(j)) => self.tiles[*j].len() as u64,
+                Some(TileData::Sparse) => 0,
+            })
+            .collect()
+    }
+
+    /// Fill in tile offsets, given where the tile data starts and which tiles
+    /// are written, see [`Level::byte_counts`]
+    fn set_tile_offsets(
+        &self,
+        dir: &mut EncodedDirectory,
+        data_offset: u64,
+        long8: bool,
+        tile_data: &[TileData],
+    ) -> TiffResult<()> {
+        let mut offset = data_offset;
+        let mut offsets = Vec::with_capacity(self.tiles.len());
+        for (i, tile) in self.tiles.iter().enumerate() {
+            match tile_data.get(i) {
+                None | Some(TileData::Own) => {
+                    offsets.push(offset);
+                    offset += tile.len() as u64;
+                }
+                Some(TileData::Shared(j)) => offsets.push(offsets[*j]),
+                Some(TileData::Sparse) => offsets.push(0),
+            }
+        }
+        let offsets = if long8 {
+            entry(&offsets[..])
+        } else {
+            entry(
+                &offsets
+                    .into_iter()
+                    .map(u32::try_from)
+                    .collect::<Result<Vec<_>, _>>()?[..],
+            )
+        };
+        dir.insert(Tag::TileOffsets, offsets);
+        Ok(())
+    }
+
+    fn data_len(&self) -> u64 {
+        self.tiles.iter().map(|t| t.len() as u64).sum()
+    }
+}
+
+/// Writes a COG level by level, in the given [`CogLayout`]
+///
+/// ```
+/// # use tiff2::{encoder::{CogEncoder, CogLayout, Level}, structs::tags::SampleFormat, ColorType};
+/// let mut encoder = CogEncoder::new(Vec::new(), CogLayout::Interleaved).unwrap();
+/// for (size, is_last) in [(32, false), (16, true)] {
+///     let level = Level {
+///         width: size,
+///         height: size,
+///         tile_width: 16,
+///         tile_height: 16,
+///         color_type: ColorType::Gray(8),
+///         sample_format: SampleFormat::Uint,
+///         tiles: vec![vec![0u8; 16 * 16]; (size as usize / 16).pow(2)],
+///         extra_tags: Default::default(),
+///     };
+///     encoder.write_level(level, is_last).unwrap();
+/// }
+/// let cog: Vec<u8> = encoder.finish().unwrap();
+/// ```
+pub struct CogEncoder<W> {
+    writer: TiffWriter<W>,
+    layout: CogLayout,
+    bigtiff: bool,
+    /// levels and masks waiting to be written by `finish` for
+    /// [`CogLayout::HeaderFirst`]
+    levels: Vec<Buffered>,
+    /// number of levels added so far
+    n_levels: usize,
+    /// whether the last level was added
+    closed: bool,
+    /// write BigTIFF tile offsets and byte counts as LONG where they fit
+    compact_offsets: bool,
+    /// bytes the header, IFDs and their tag data may take
+    header_budget: Option<u64>,
+    /// fail on tags that can't be written the way the spec asks for
+    strict: bool,
+    predictor: Predictor,
+    compression: CompressionMethod,
+    uniform_tiles: UniformTiles,
+    photometric: PhotometricPolicy,
+    progress: Option<ProgressTracker>,
+    cancelled: Option<Arc<AtomicBool>>,
+}
+
+/// A level or mask waiting to be written by [`CogEncoder::finish`]
+struct Buffered {
+    level: Level,
+    /// where the data of each of its tiles is
+    tile_data: Vec<TileData>,
+    subfile_type: u32,
+    /// index of the level, which a mask shares with its level
+    i_level: usize,
+}
+
+impl<W: Write> CogEncoder<W> {
+    /// Create an encoder for a classic TIFF, writing its header
+    pub fn new(writer: W, layout: CogLayout) -> TiffResult<Self> {
+        Self::with_bigtiff(writer, layout, false)
+    }
+
+    /// Create an encoder for a classic TIFF or BigTIFF, writing its header
+    pub fn with_bigtiff(writer: W, layout: CogLayout, bigtiff: bool) -> TiffResult<Self> {
+        let mut writer = TiffWriter::new(writer);
+        if bigtiff {
+            // version, offset size, padding, first IFD offset
+            writer.write_bytes(b"II")?;
+            writer.write_bytes(&43u16.to_le_bytes())?;
+            writer.write_bytes(&8u16.to_le_bytes())?;
+            writer.write_bytes(&[0, 0])?;
+            writer.write_bytes(&16u64.to_le_bytes())?;
+        } else {
+            writer.write_bytes(b"II")?;
+            writer.write_bytes(&42u16.to_le_bytes())?;
+            writer.write_bytes(&8u32.to_le_bytes())?;
+        }
+        Ok(CogEncoder {
+            writer,
+            layout,
+            bigtiff,
+            levels: Vec::new(),
+            n_levels: 0,
+            closed: false,
+            compact_offsets: false,
+            header_budget: None,
+            strict: false,
+            predictor: Predictor::None,
+            compression: CompressionMethod::None,
+            uniform_tiles: UniformTiles::Keep,
+            photometric: PhotometricPolicy::Auto,
+            progress: None,
+            cancelled: None,
+        })
+    }
+
+    /// For BigTIFF, write tile offsets and byte counts as LONG instead of
+    /// LONG8 when all tile data of a level ends before 4 GiB, halving the
+    /// size of these arrays. With [`CogLayout::HeaderFirst`], this applies to
+    /// all levels or none. Classic TIFFs always use LONG.
+    pub fn with_compact_offsets(mut self) -> Self {
+        self.compact_offsets = true;
+        self
+    }
+
+    /// Require the file header and all IFDs with their tag data to fit in the
+    /// first `max_bytes` of the file, e.g. 16 KiB, so readers can open it with
+    /// a single range request.
+    ///
+    /// With [`CogLayout::HeaderFirst`], tile offsets and byte counts of a
+    /// BigTIFF are written as LONG if that makes the header fit, like with
+    /// [`CogEncoder::with_compact_offsets`]. If the header still doesn't fit,
+    /// [`CogEncoder::finish`] fails with [`UsageError::HeaderBudgetExceeded`],
+    /// saying which tags take up the space, before any tile is written. With
+    /// [`CogLayout::Interleaved`], IFDs come between tile data, so writing a
+    /// level fails if its IFD would end past the budget.
+    pub fn with_header_budget(mut self, max_bytes: u64) -> Self {
+        self.header_budget = Some(max_bytes);
+        self
+    }
+
+    /// Fail if IFDs ending at `header_bytes` exceed the header budget
+    fn check_header_budget<'a>(
+        &self,
+        header_bytes: u64,
+        dirs: impl IntoIterator<Item = (usize, &'a EncodedDirectory)>,
+    ) -> TiffResult<()> {
+        match self.header_budget {
+            Some(budget) if header_bytes > budget => Err(UsageError::HeaderBudgetExceeded(
+                HeaderReport::new(budget, header_bytes, dirs, self.bigtiff),
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether tile offsets ending at `data_end`, as laid out with LONG8
+    /// offsets, need to be LONG8
+    fn long8(&self, data_end: u64) -> bool {
+        self.bigtiff && !(self.compact_offsets && data_end <= u64::from(u32::MAX))
+    }
+
+    /// Fail on extra tags of baseline tags that can't be given the type or
+    /// count the spec asks for, instead of writing them as given. Tags are
+    /// canonicalized where possible either way, see
+    /// [`canonicalize`](crate::encoder::directory::canonicalize).
+    pub fn with_strict_tags(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// The canonicalized IFD of a level or mask whose tiles were encoded, see
+    /// [`Level::directory`]
+    fn directory(
+        &self,
+        level: &Level,
+        tile_data: &[TileData],
+        subfile_type: u32,
+        long8: bool,
+    ) -> TiffResult<EncodedDirectory> {
+        let mut dir = level.encoded_directory(
+            subfile_type,
+            long8,
+            self.predictor(subfile_type),
+            self.compression,
+            tile_data,
+        )?;
+        canonicalize(&mut dir, self.strict)?;
+        Ok(dir)
+    }
+
+    /// The predictor of an IFD, masks having none
+    fn predictor(&self, subfile_type: u32) -> Predictor {
+        if subfile_type & TRANSPARENCY_MASK != 0 {
+            Predictor::None
+        } else {
+            self.predictor
+        }
+    }
+
+    /// Apply `predictor` to the tiles of all levels, which usually makes them
+    /// compress better. [`Predictor::Horizontal`] works on integer samples,
+    /// [`Predictor::FloatingPoint`] on 16, 32 and 64 bit floats. Levels it
+    /// doesn't work on are rejected.
+    pub fn with_predictor(mut self, predictor: Predictor) -> Self {
+        self.predictor = predictor;
+        self
+    }
+
+    /// Compress tiles with `compression`, after applying the predictor.
+    /// [`CompressionMethod::None`], the default, and
+    /// [`CompressionMethod::Deflate`] are supported, other methods fail when
+    /// a level is written.
+    pub fn with_compression(mut self, compression: CompressionMethod) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Write tiles whose pixels all have the same value once per level, or not
+    /// at all, instead of [writing every tile](UniformTiles::Keep). This
+    /// shrinks mosaics with large masked or padded areas, but tile offsets no
+    /// longer increase, which GDAL's COG validator reports.
+    pub fn with_uniform_tiles(mut self, uniform_tiles: UniformTiles) -> Self {
+        self.uniform_tiles = uniform_tiles;
+        self
+    }
+
+    /// Choose the color space RGB and YCbCr levels are written in. With the
+    /// default [`PhotometricPolicy::Auto`], YCbCr is converted to RGB, as
+    /// tiles are compressed losslessly.
+    pub fn with_photometric(mut self, policy: PhotometricPolicy) -> Self {
+        self.photometric = policy;
+        self
+    }
+
+    /// Report each tile that was written to `observer`. The total number of
+    /// tiles grows as levels are added.
+    pub fn with_progress(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.progress = Some(ProgressTracker::new(observer, 0));
+        self
+    }
+
+    /// Stop with [`TiffError::Cancelled`] once `cancelled` is set, e.g. from a
+    /// GUI's cancel button or a job queue's shutdown. It is checked between
+    /// tiles, and the file written so far is incomplete.
+    pub fn with_cancellation(mut self, cancelled: Arc<AtomicBool>) -> Self {
+        self.cancelled = Some(cancelled);
+        self
+    }
+
+    fn check_cancelled(&self) -> TiffResult<()> {
+        match &self.cancelled {
+            Some(cancelled) if cancelled.load(Ordering::Relaxed) => Err(TiffError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Add the next level, starting with full resolution followed by
+    /// decreasing overviews. `is_last` must be set on the smallest overview.
+    ///
+    /// With [`CogLayout::Interleaved`], the level is written immediately.
+    pub fn write_level(&mut self, level: Level, is_last: bool) -> TiffResult<()> {
+        self.add_level(level, None, is_last)
+    }
+
+    /// Like [`CogEncoder::write_level`], together with the transparency mask
+    /// of the level: a level of [`ColorType::Gray`]`(1)` unsigned samples of
+    /// the same size and tiling, with the bits of pixels that have data set.
+    ///
+    /// The mask is written with `PhotometricInterpretation::TransparencyMask`
+    /// and a `NewSubfileType` of 4, or 5 for the mask of an overview, without
+    /// predictor. Fails with [`TiffUnsupportedError::UnsupportedColorType`]
+    /// or [`TiffFormatError::InvalidDimensions`] for a mask that doesn't fit
+    /// the level, before anything of either is written.
+    pub fn write_level_with_mask(
+        &mut self,
+        level: Level,
+        mask: Level,
+        is_last: bool,
+    ) -> TiffResult<()> {
+        self.add_level(level, Some(mask), is_last)
+    }
+
+    /// Like [`CogEncoder::write_level`] for the smallest level of a pyramid,
+    /// followed by overviews downsampled from it with `resampling` until one
+    /// fits in a single tile, returning how many were added.
+    ///
+    /// This tops up a pyramid whose coarse levels are missing, see
+    /// [`OverviewCoverage::missing_levels`]. The overviews get the tiling and
+    /// extra tags of `level`, and are padded with `nodata`, which is also
+    /// left out of averages like with [`CogWriterOptions::write`]. Tiles must
+    /// be square, and samples whole bytes.
+    ///
+    /// [`OverviewCoverage::missing_levels`]: crate::structs::coverage::OverviewCoverage::missing_levels
+    pub fn write_level_with_overviews(
+        &mut self,
+        level: Level,
+        resampling: Resampling,
+        nodata: Option<f64>,
+    ) -> TiffResult<usize> {
+        level.check(Predictor::None)?;
+        if level.tile_width != level.tile_height {
+            return Err(
+                TiffFormatError::InvalidDimensions(level.tile_width, level.tile_height).into(),
+            );
+        }
+        let options = CogWriterOptions {
+            tile_size: level.tile_width,
+            overview_resampling: resampling,
+            nodata,
+            ..Default::default()
+        };
+        let mut raster = Raster::from_level(&level)?;
+        let sample_type = raster.sample_type()?;
+        let extra_tags = level.extra_tags.clone();
+        let fits = |raster: &Raster| {
+            raster.width <= options.tile_size && raster.height <= options.tile_size
+        };
+        self.write_level(level, fits(&raster))?;
+        let mut n_overviews = 0;
+        while !fits(&raster) {
+            raster = options.downsample(&raster, sample_type);
+            let overview = options.level(&raster, sample_type, extra_tags.clone())?;
+            self.write_level(overview, fits(&raster))?;
+            n_overviews += 1;
+        }
+        Ok(n_overviews)
+    }
+
+    fn add_level(
+        &mut self,
+        mut level: Level,
+        mask: Option<Level>,
+        is_last: bool,
+    ) -> TiffResult<()> {
+        if self.closed {
+            return Err(UsageError::LevelAfterLastLevel.into());
+        }
+        self.check_cancelled()?;
+        if !matches!(
+            self.compression,
+            CompressionMethod::None | CompressionMethod::Deflate
+        ) {
+            return Err(
+                TiffUnsupportedError::UnsupportedCompressionMethod(self.compression).into(),
+            );
+        }
+        let color_type = self
+            .photometric
+            .resolve(level.color_type, self.compression)?;
+        match (level.color_type, color_type) {
+            (ColorType::RGB(_), ColorType::YCbCr(_)) => level
+                .tiles
+                .iter_mut()
+                .for_each(|t| YCbCrConversion::default().to_ycbcr(t)),
+            (ColorType::YCbCr(_), ColorType::RGB(_)) => level
+                .tiles
+                .iter_mut()
+                .for_each(|t| YCbCrConversion::default().to_rgb(t)),
+            _ => {}
+        }
+        level.color_type = color_type;
+        level.check(self.predictor)?;
+        if let Some(mask) = &mask {
+            if mask.color_type != ColorType::Gray(1) || mask.sample_format != SampleFormat::Uint {
+                return Err(TiffUnsupportedError::UnsupportedColorType(mask.color_type).into());
+            }
+            let tiling = |l: &Level| (l.width, l.height, l.tile_width, l.tile_height);
+            if tiling(mask) != tiling(&level) {
+                return Err(TiffFormatError::InvalidDimensions(mask.width, mask.height).into());
+            }
+            mask.check(Predictor::None)?;
+        }
+        let subfile_type = if self.n_levels > 0 {
+            REDUCED_RESOLUTION
+        } else {
+            0
+        };
+        self.add(level, subfile_type, is_last && mask.is_none())?;
+        if let Some(mask) = mask {
+            self.add(mask, subfile_type | TRANSPARENCY_MASK, is_last)?;
+        }
+        self.n_levels += 1;
+        self.closed = is_last;
+        Ok(())
+    }
+
+    /// Encode the tiles of a checked level or mask, and write or buffer it
+    /// according to the layout. `is_last` is set if no IFD follows its own.
+    fn add(&mut self, mut level: Level, subfile_type: u32, is_last: bool) -> TiffResult<()> {
+        let tile_data = self.uniform_tile_data(&mut level)?;
+        self.encode_tiles(&mut level, &tile_data, self.predictor(subfile_type))?;
+        if let Some(progress) = &self.progress {
+            progress.add_total(level.tiles.len());
+        }
+        match self.layout {
+            CogLayout::HeaderFirst => {
+                // check early, so errors show up on the offending level
+                self.directory(&level, &tile_data, subfile_type, self.bigtiff)?;
+                self.levels.push(Buffered {
+                    level,
+                    tile_data,
+                    subfile_type,
+                    i_level: self.n_levels,
+                });
+            }
+            CogLayout::Interleaved => {
+                let mut dir = self.directory(&level, &tile_data, subfile_type, self.bigtiff)?;
+                let ifd_offset = self.writer.offset();
+                let data_end = ifd_offset + ifd_len(&dir, self.bigtiff) + level.data_len();
+                let long8 = self.long8(data_end);
+                if long8 != self.bigtiff {
+                    dir = self.directory(&level, &tile_data, subfile_type, long8)?;
+                }
+                let data_offset = ifd_offset + ifd_len(&dir, self.bigtiff);
+                self.check_header_budget(data_offset, [(self.n_levels, &dir)])?;
+                level.set_tile_offsets(&mut dir, data_offset, long8, &tile_data)?;
+                let data_end = data_offset + level.data_len();
+                let next_ifd = if is_last { 0 } else { data_end + data_end % 2 };
+                let ifd = encode_ifd(
+                    &dir,
+                    ifd_offset,
+                    next_ifd,
+                    ByteOrder::LittleEndian,
+                    self.bigtiff,
+                )?;
+                self.writer.write_bytes(&ifd)?;
+                self.write_tiles(&level, self.n_levels)?;
+                self.writer.pad_word_boundary()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write everything that is still buffered, and return the inner writer
+    pub fn finish(mut self) -> TiffResult<W> {
+        if !self.closed {
+            return Err(UsageError::LastLevelMissing.into());
+        }
+        if self.layout == CogLayout::HeaderFirst {
+            self.write_header_first()?;
+        }
+        Ok(self.writer.into_inner())
+    }
+
+    fn write_header_first(&mut self) -> TiffResult<()> {
+        let levels = core::mem::take(&mut self.levels);
+        let directories = |long8| {
+            levels
+                .iter()
+                .map(|b| self.directory(&b.level, &b.tile_data, b.subfile_type, long8))
+                .collect::<TiffResult<Vec<_>>>()
+        };
+        let mut dirs = directories(self.bigtiff)?;
+        let data_end = self.writer.offset()
+            + dirs
+                .iter()
+                .map(|dir| ifd_len(dir, self.bigtiff))
+                .sum::<u64>()
+            + levels.iter().map(|b| b.level.data_len()).sum::<u64>();
+        let header_end = |dirs: &[EncodedDirectory]| {
+            self.writer.offset()
+                + dirs
+                    .iter()
+                    .map(|dir| ifd_len(dir, self.bigtiff))
+                    .sum::<u64>()
+        };
+        let mut long8 = self.long8(data_end);
+        if long8
+            && data_end <= u64::from(u32::MAX)
+            && self
+                .header_budget
+                .is_some_and(|budget| header_end(&dirs) > budget)
+        {
+            // LONG offsets may get the header within its budget
+            long8 = false;
+        }
+        if long8 != self.bigtiff {
+            // smaller IFDs only move the data closer to the start
+            dirs = directories(long8)?;
+        }
+        self.check_header_budget(
+            header_end(&dirs),
+            levels.iter().map(|b| b.i_level).zip(&dirs),
+        )?;
+        let mut ifd_offsets = Vec::with_capacity(dirs.len());
+        let mut offset = self.writer.offset();
+        for dir in &dirs {
+            ifd_offsets.push(offset);
+            offset += ifd_len(dir, self.bigtiff);
+        }
+        // tile data goes from the smallest overview to full resolution, that
+        // of each mask following that of its level
+        let mut order: Vec<usize> = (0..levels.len()).collect();
+        order.sort_by_key(|&i| core::cmp::Reverse(levels[i].i_level));
+        for &i in &order {
+            let b = &levels[i];
+            b.level
+                .set_tile_offsets(&mut dirs[i], offset, long8, &b.tile_data)?;
+            offset += b.level.data_len();
+        }
+        for (i, dir) in dirs.iter().enumerate() {
+            let next_ifd = ifd_offsets.get(i + 1).copied().unwrap_or(0);
+            let ifd = encode_ifd(
+                dir,
+                ifd_offsets[i],
+                next_ifd,
+                ByteOrder::LittleEndian,
+                self.bigtiff,
+            )?;
+            self.writer.write_bytes(&ifd)?;
+        }
+        for &i in &order {
+            self.write_tiles(&levels[i].level, levels[i].i_level)?;
+        }
+        Ok(())
+    }
+
+    /// Where the data of each tile of a level goes, according to
+    /// [`CogEncoder::with_uniform_tiles`]. Tiles that aren't written
+    /// themselves are cleared.
+    fn uniform_tile_data(&self, level: &mut Level) -> TiffResult<Vec<TileData>> {
+        let mut tile_data = vec![TileData::Own; level.tiles.len()];
+        if self.uniform_tiles == UniformTiles::Keep {
+            return Ok(tile_data);
+        }
+        let bits = level.color_type.bit_depth();
+        let (_, samples, _) = level.color_tags()?;
+        // bytes of a pixel, or single bytes for samples smaller than a byte
+        let pixel_bytes = match bits % 8 {
+            0 => usize::from(samples) * usize::from(bits / 8),
+            _ => 1,
+        };
+        let sparse = match self.uniform_tiles {
+            UniformTiles::Sparse => match level.extra_tags.get(&Tag::GdalNodata) {
+                Some(nodata) => nodata_value(nodata, level),
+                None => Some(Nodata::new(0.0)),
+            },
+            _ => None,
+        };
+        let sample_type = SampleType::from_format(level.sample_format, bits);
+        // first tile of each value
+        let mut first = HashMap::<Vec<u8>, usize>::new();
+        for (i, tile) in level.tiles.iter_mut().enumerate() {
+            let Some(pixel) = tile.get(..pixel_bytes).map(<[u8]>::to_vec) else {
+                continue;
+            };
+            if !tile.chunks_exact(pixel_bytes).all(|p| p == pixel) {
+                continue;
+            }
+            let is_sparse = match (sparse, sample_type) {
+                (Some(nodata), Some(sample_type)) => nodata.matches_pixel(&pixel, sample_type),
+                // samples smaller than a byte, which can only be sparse if
+                // nodata is 0
+                (Some(nodata), None) => nodata.value() == 0.0 && pixel.iter().all(|&b| b == 0),
+                (None, _) => false,
+            };
+            if is_sparse {
+                tile_data[i] = TileData::Sparse;
+            } else if let Some(&j) = first.get(&pixel) {
+                tile_data[i] = TileData::Shared(j);
+            } else {
+                first.insert(pixel, i);
+                continue;
+            }
+            tile.clear();
+        }
+        Ok(tile_data)
+    }
+
+    /// Apply the predictor and compression to the tiles of a level that are
+    /// written, in place, and make their samples little-endian
+    fn encode_tiles(
+        &self,
+        level: &mut Level,
+        tile_data: &[TileData],
+        predictor: Predictor,
+    ) -> TiffResult<()> {
+        let bits = level.color_type.bit_depth();
+        let (_, samples, _) = level.color_tags()?;
+        let samples = usize::from(samples);
+        let row_samples = usize::try_from(level.tile_width)? * samples;
+        let swap = bits > 8 && cfg!(target_endian = "big");
+        for (tile, data) in level.tiles.iter_mut().zip(tile_data) {
+            self.check_cancelled()?;
+            if *data != TileData::Own {
+                continue;
+            }
+            match predictor {
+                Predictor::None => {}
+                Predictor::Horizontal => {
+                    predictor::horizontal_encode(tile, bits, samples, row_samples)?
+                }
+                // byte planes don't depend on the byte order
+                Predictor::FloatingPoint => {
+                    predictor::float_encode(tile, bits, samples, row_samples)?
+                }
+            }
+            if swap && predictor != Predictor::FloatingPoint {
+                fix_endianness(tile, ByteOrder::LittleEndian, bits);
+            }
+            if self.compression == CompressionMethod::Deflate {
+                *tile = deflate::compress_to_vec_zlib(tile, 6);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the encoded tiles of the `i_level`th level
+    fn write_tiles(&mut self, level: &Level, i_level: usize) -> TiffResult<()> {
+        if let Some(progress) = &self.progress {
+            progress.set_level(i_level);
+        }
+        for tile in &level.tiles {
+            self.check_cancelled()?;
+            self.writer.write_bytes(tile)?;
+            if let Some(progress) = &self.progress {
+                progress.done(tile.len() as u64);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The nodata value given by a [`Tag::GdalNodata`] entry, `None` if it can't
+/// be parsed or stored in the samples of the level
+fn nodata_value(nodata: &BufferedEntry, level: &Level) -> Option<Nodata> {
+    let nodata = Nodata::parse(core::str::from_utf8(&nodata.data).ok()?)?;
+    match SampleType::from_format(level.sample_format, level.color_type.bit_depth()) {
+        Some(sample_type) => nodata.to_type(sample_type),
+        None => Some(nodata),
+    }
+}
+
+#[cfg(test)]
+impl Level {
+    /// Level of `width` by `height` pixels in square tiles of `tile` pixels,
+    /// for tests
+    pub(crate) fn tiled(
+        width: u32,
+        height: u32,
+        tile: u32,
+        color_type: ColorType,
+        tiles: Vec<Vec<u8>>,
+    ) -> Self {
+        Level {
+            width,
+            height,
+            tile_width: tile,
+            tile_height: tile,
+            color_type,
+            sample_format: SampleFormat::Uint,
+            tiles,
+            extra_tags: EncodedDirectory::new(),
+        }
+    }
+
+    /// 8-bit gray level for tests, pixel `j` of tile `i` being `pixel(i, j)`
+    pub(crate) fn gray(width: u32, height: u32, tile: u32, pixel: impl Fn(u32, u32) -> u8) -> Self {
+        let n_tiles = width.div_ceil(tile) * height.div_ceil(tile);
+        let tiles = (0..n_tiles)
+            .map(|i| (0..tile * tile).map(|j| pixel(i, j)).collect())
+            .collect();
+        Self::tiled(width, height, tile, ColorType::Gray(8), tiles)
+    }
+}
+
+#[cfg(test)]
+impl CogEncoder<Vec<u8>> {
+    /// Write `levels` and finish the file, for tests
+    pub(crate) fn encode_levels(mut self, levels: impl IntoIterator<Item = Level>) -> Vec<u8> {
+        let mut levels = levels.into_iter().peekable();
+        while let Some(level) = levels.next() {
+            self.write_level(level, levels.peek().is_none()).unwrap();
+        }
+        self.finish().unwrap()
+    }
+}
+
+/// COG of `levels` written with the default options, for tests
+#[cfg(test)]
+pub(crate) fn test_cog(levels: impl IntoIterator<Item = Level>) -> Vec<u8> {
+    CogEncoder::new(Vec::new(), CogLayout::HeaderFirst)
+        .unwrap()
+        .encode_levels(levels)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        decoder::{decode_chunk_data, DecoderOptions, DecodingResult, Limits},
+        progress::Progress,
+        structs::{
+            coverage::OverviewCoverage, layout::LayoutReport, ChunkMetaData, Ifd, IfdEntry,
+            TagType, Tiff, TileAttributes,
+        },
+        ChunkType,
+    };
+
+    fn level(size: u32, value: u8) -> Level {
+        Level::gray(size, size, 16, |_, _| value)
+    }
+
+    fn u32_at(buf: &[u8], offset: u64) -> u32 {
+        let offset = offset as usize;
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Walk the IFD chain of a classic TIFF, returning IFD offsets and tile offsets
+    fn read_chain(buf: &[u8]) -> Vec<(u64, Vec<u64>)> {
+        let mut levels = Vec::new();
+        let mut ifd_offset = u64::from(u32_at(buf, 4));
+        while ifd_offset != 0 {
+            let ifd = Ifd::from_buffer(
+                &buf[ifd_offset as usize..],
+                ByteOrder::LittleEndian,
+                false,
+                &Limits::default(),
+            )
+            .unwrap();
+            let tile_offsets = match ifd.require_tag(&Tag::TileOffsets).unwrap() {
+                IfdEntry::Offset { count, offset, .. } => (0..*count)
+                    .map(|i| u64::from(u32_at(buf, offset + 4 * i)))
+                    .collect(),
+                IfdEntry::Value(v) => vec![v.get_u64(0).unwrap()],
+            };
+            levels.push((ifd_offset, tile_offsets));
+            let n_entries = u64::from(u16::from_le_bytes([
+                buf[ifd_offset as usize],
+                buf[ifd_offset as usize + 1],
+            ]));
+            ifd_offset = u64::from(u32_at(buf, ifd_offset + 2 + 12 * n_entries));
+        }
+        levels
+    }
+
+    /// The `i`th IFD of the chain, without the values that don't fit in their
+    /// entries
+    fn read_ifd(buf: &[u8], i: usize) -> Ifd {
+        let offset = read_chain(buf)[i].0 as usize;
+        Ifd::from_buffer(
+            &buf[offset..],
+            ByteOrder::LittleEndian,
+            false,
+            &Limits::default(),
+        )
+        .unwrap()
+    }
+
+    fn write(layout: CogLayout) -> Vec<u8> {
+        let mut encoder = CogEncoder::new(Vec::new(), layout).unwrap();
+        encoder.write_level(level(40, 1), false).unwrap();
+        encoder.write_level(level(20, 2), false).unwrap();
+        encoder.write_level(level(10, 3), true).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_interleaved() {
+        let buf = write(CogLayout::Interleaved);
+        let levels = read_chain(&buf);
+        assert_eq!(levels.len(), 3);
+        for (i, (ifd_offset, tile_offsets)) in levels.iter().enumerate() {
+            // the IFD comes right before its own tiles, and after the previous level's
+            assert!(tile_offsets.iter().all(|o| o > ifd_offset));
+            if let Some((next_ifd, _)) = levels.get(i + 1) {
+                assert!(tile_offsets.iter().all(|o| o < next_ifd));
+            }
+            for &o in tile_offsets {
+                assert_eq!(buf[o as usize..o as usize + 256], [i as u8 + 1; 256]);
+            }
+        }
+        assert_eq!(levels[0].1.len(), 9);
+    }
+
+    #[test]
+    fn test_header_first() {
+        let buf = write(CogLayout::HeaderFirst);
+        let levels = read_chain(&buf);
+        assert_eq!(levels.len(), 3);
+        let last_ifd = levels[2].0;
+        for (i, (_, tile_offsets)) in levels.iter().enumerate() {
+            assert!(tile_offsets.iter().all(|&o| o > last_ifd));
+            for &o in tile_offsets {
+                assert_eq!(buf[o as usize..o as usize + 256], [i as u8 + 1; 256]);
+            }
+        }
+        // smallest overview's data comes first
+        assert!(levels[2].1[0] < levels[1].1[0] && levels[1].1[0] < levels[0].1[0]);
+    }
+
+    #[test]
+    fn test_overview_tags() {
+        let mut extra_tags = EncodedDirectory::new();
+        extra_tags.insert(Tag::ModelPixelScaleTag, entry(&[1.0f64, 1.0, 0.0][..]));
+        extra_tags.insert(Tag::XResolution, entry(&[72u32, 1][..]));
+        extra_tags.insert(Tag::GdalNodata, entry("0"));
+        // not overridden
+        extra_tags.insert(Tag::ImageWidth, entry(&1u32));
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::HeaderFirst).unwrap();
+        for (size, is_last) in [(32, false), (16, true)] {
+            let level = Level {
+                extra_tags: extra_tags.clone(),
+                ..level(size, 0)
+            };
+            encoder.write_level(level, is_last).unwrap();
+        }
+        let buf = encoder.finish().unwrap();
+        let (base, overview) = (read_ifd(&buf, 0), read_ifd(&buf, 1));
+        for tag in [Tag::ModelPixelScaleTag, Tag::XResolution, Tag::GdalNodata] {
+            assert!(base.contains_key(&tag));
+        }
+        assert!(!overview.contains_key(&Tag::ModelPixelScaleTag));
+        assert!(!overview.contains_key(&Tag::XResolution));
+        assert!(overview.contains_key(&Tag::GdalNodata));
+        assert_eq!(
+            u32::try_from(base.require_tag_value(&Tag::ImageWidth).unwrap()).unwrap(),
+            32
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compact_offsets() {
+        for layout in [CogLayout::Interleaved, CogLayout::HeaderFirst] {
+            let mut header_bytes = Vec::new();
+            for compact in [false, true] {
+                let mut encoder = CogEncoder::with_bigtiff(Vec::new(), layout, true).unwrap();
+                if compact {
+                    encoder = encoder.with_compact_offsets();
+                }
+                encoder.write_level(level(40, 1), false).unwrap();
+                encoder.write_level(level(20, 2), true).unwrap();
+                let buf = encoder.finish().unwrap();
+
+                let tiff = Tiff::read(&buf, &DecoderOptions::default()).await.unwrap();
+                for (i, ifd) in tiff.ifds.iter().enumerate() {
+                    let offsets = ifd.require_tag_value(&Tag::TileOffsets).unwrap();
+                    let expected = if compact {
+                        TagType::LONG
+                    } else {
+                        TagType::LONG8
+                    };
+                    assert_eq!(offsets.tag_type, expected);
+                    assert_eq!(
+                        ifd.require_tag_value(&Tag::TileByteCounts)
+                            .unwrap()
+                            .tag_type,
+                        expected
+                    );
+                    for j in 0..offsets.count as usize {
+                        let o = offsets.get_u64(j).unwrap() as usize;
+                        assert_eq!(buf[o..o + 256], [i as u8 + 1; 256]);
+                    }
+                }
+                let report = LayoutReport::new(&tiff, buf.len() as u64).unwrap();
+                header_bytes.push(report.header_bytes);
+            }
+            if layout == CogLayout::HeaderFirst {
+                // 4 bytes less for each of the 9 + 4 offsets and byte counts
+                assert_eq!(header_bytes[0] - header_bytes[1], 13 * 2 * 4);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_uniform_tiles() {
+        // a gradient, then uniform tiles of 0 and 5
+        let mut tiles = vec![(0..=255).collect::<Vec<u8>>()];
+        tiles.extend([0, 5, 5, 0, 5, 0, 0, 5].map(|v| vec![v; 256]));
+        let mut extra_tags = EncodedDirectory::new();
+        extra_tags.insert(Tag::GdalNodata, entry("5"));
+        let encode = |layout, uniform_tiles| {
+            let level = Level {
+                tiles: tiles.clone(),
+                extra_tags: extra_tags.clone(),
+                ..level(40, 0)
+            };
+            let mut encoder = CogEncoder::new(Vec::new(), layout)
+                .unwrap()
+                .with_compression(CompressionMethod::Deflate)
+                .with_uniform_tiles(uniform_tiles);
+            encoder.write_level(level, true).unwrap();
+            encoder.finish().unwrap()
+        };
+        for layout in [CogLayout::Interleaved, CogLayout::HeaderFirst] {
+            let kept = encode(layout, UniformTiles::Keep);
+            for (mode, sparse) in [(UniformTiles::Share, None), (UniformTiles::Sparse, Some(5))] {
+                let buf = encode(layout, mode);
+                assert!(buf.len() < kept.len());
+                let tiff = Tiff::read(&buf, &DecoderOptions::default()).await.unwrap();
+                let ifd = &tiff.ifds[0];
+                let offsets = ifd.require_tag_value(&Tag::TileOffsets).unwrap();
+                let counts = ifd.require_tag_value(&Tag::TileByteCounts).unwrap();
+                let mut first = HashMap::new();
+                for (i, tile) in tiles.iter().enumerate() {
+                    let offset = offsets.get_u64(i).unwrap();
+                    let count = counts.get_u64(i).unwrap();
+                    if sparse == Some(tile[0]) {
+                        assert_eq!((offset, count), (0, 0));
+                        continue;
+                    }
+                    let &mut (o, c) = first.entry(tile).or_insert((offset, count));
+                    assert_eq!((offset, count), (o, c), "tile {i}");
+                    let raw = buf[offset as usize..(offset + count) as usize].to_vec();
+                    let data = miniz_oxide::inflate::decompress_to_vec_zlib(&raw).unwrap();
+                    assert_eq!(&data, tile);
+                }
+                // written once per value
+                assert_eq!(first.len(), if sparse.is_some() { 2 } else { 3 });
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_header_budget() {
+        let encode = |layout, bigtiff, budget| {
+            let mut encoder = CogEncoder::with_bigtiff(Vec::new(), layout, bigtiff)
+                .unwrap()
+                .with_header_budget(budget);
+            // 625 tiles, and 169
+            encoder.write_level(level(400, 1), false)?;
+            encoder.write_level(level(200, 2), true)?;
+            encoder.finish()
+        };
+        // 2 * 4 bytes per tile, and less than 400 bytes of other entries
+        let buf = encode(CogLayout::HeaderFirst, false, 8 * 794 + 400).unwrap();
+        let tiff = Tiff::read(&buf, &DecoderOptions::default()).await.unwrap();
+        let report = LayoutReport::new(&tiff, buf.len() as u64).unwrap();
+        assert!(report.header_bytes <= 8 * 794 + 400);
+
+        let Err(TiffError::UsageError(UsageError::HeaderBudgetExceeded(report))) =
+            encode(CogLayout::HeaderFirst, false, 4096)
+        else {
+            panic!("the header should exceed its budget");
+        };
+        assert_eq!(report.budget, 4096);
+        assert_eq!(
+            report.header_bytes,
+            8 + report.ifds.iter().map(|(_, bytes)| bytes).sum::<u64>()
+        );
+        assert_eq!(report.ifds.len(), 2);
+        assert_eq!(report.largest_tags[0].2, 12 + 625 * 4);
+        assert_eq!(
+            report.largest_tags[..2]
+                .iter()
+                .map(|(level, tag, _)| (*level, *tag))
+                .collect::<Vec<_>>(),
+            [(0, Tag::TileOffsets), (0, Tag::TileByteCounts)]
+        );
+        assert!(report
+            .to_string()
+            .contains("TileOffsets of level 0 (2512 bytes)"));
+
+        // BigTIFF offsets become LONG to fit
+        let buf = encode(CogLayout::HeaderFirst, true, 8 * 794 + 800).unwrap();
+        let tiff = Tiff::read(&buf, &DecoderOptions::default()).await.unwrap();
+        let offsets = tiff.ifds[0].require_tag_value(&Tag::TileOffsets).unwrap();
+        assert_eq!(offsets.tag_type, TagType::LONG);
+        assert!(encode(CogLayout::HeaderFirst, true, u64::MAX).is_ok());
+
+        // the IFD of the overview comes after the tiles of full resolution
+        let Err(TiffError::UsageError(UsageError::HeaderBudgetExceeded(report))) =
+            encode(CogLayout::Interleaved, false, 8 * 794 + 400)
+        else {
+            panic!("the overview's IFD should exceed the budget");
+        };
+        assert_eq!(report.ifds.len(), 1);
+        assert_eq!(report.ifds[0].0, 1);
+    }
+
+    #[derive(Default)]
+    struct Recorded(std::sync::Mutex<Vec<Progress>>);
+
+    impl ProgressObserver for Recorded {
+        fn on_progress(&self, progress: &Progress) {
+            self.0.lock().unwrap().push(*progress);
+        }
+    }
+
+    #[test]
+    fn test_progress() {
+        for (layout, levels) in [
+            (CogLayout::Interleaved, [0, 0, 0, 0, 1]),
+            // the overview's tile comes first
+            (CogLayout::HeaderFirst, [1, 0, 0, 0, 0]),
+        ] {
+            let observer = Arc::new(Recorded::default());
+            let mut encoder = CogEncoder::new(Vec::new(), layout)
+                .unwrap()
+                .with_progress(observer.clone());
+            encoder.write_level(level(32, 0), false).unwrap();
+            encoder.write_level(level(16, 0), true).unwrap();
+            encoder.finish().unwrap();
+            let reports = observer.0.lock().unwrap();
+            assert_eq!(reports.len(), 5);
+            assert_eq!(
+                reports.last(),
+                Some(&Progress {
+                    chunks_total: 5,
+                    chunks_fetched: 0,
+                    chunks_done: 5,
+                    bytes: 5 * 256,
+                    level: levels[4],
+                })
+            );
+            assert!(reports.iter().map(|p| p.level).eq(levels));
+        }
+    }
+
+    /// Sets the cancellation flag once `after` tiles are done
+    struct CancelAfter {
+        after: usize,
+        cancelled: Arc<AtomicBool>,
+    }
+
+    impl ProgressObserver for CancelAfter {
+        fn on_progress(&self, progress: &Progress) {
+            if progress.chunks_done >= self.after {
+                self.cancelled.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let observer = Arc::new(CancelAfter {
+            after: 2,
+            cancelled: cancelled.clone(),
+        });
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::Interleaved)
+            .unwrap()
+            .with_progress(observer)
+            .with_cancellation(cancelled.clone());
+        let Err(TiffError::Cancelled) = encoder.write_level(level(32, 0), false) else {
+            panic!("the encoder should be cancelled after 2 of 4 tiles");
+        };
+        // the file is cut short after the 2 tiles
+        let buf = encoder.writer.into_inner();
+        let dir = level(32, 0)
+            .directory(false, false, Predictor::None)
+            .unwrap();
+        assert_eq!(buf.len() as u64, 8 + ifd_len(&dir, false) + 2 * 256);
+        // later levels aren't started
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::HeaderFirst)
+            .unwrap()
+            .with_cancellation(cancelled);
+        let Err(TiffError::Cancelled) = encoder.write_level(level(16, 0), true) else {
+            panic!("the encoder should be cancelled before adding a level");
+        };
+    }
+
+    #[tokio::test]
+    async fn test_predictor() {
+        // a 16x16 tile of doubles, and of RGB u16
+        let floats: Vec<f64> = (0..256).map(|i| (i as f64).sqrt() * 1e-3).collect();
+        let rgb: Vec<u16> = (0..3 * 256).map(|i| i * 77).collect();
+        let cases = [
+            (
+                Predictor::FloatingPoint,
+                ColorType::Gray(64),
+                SampleFormat::IEEEFP,
+                DecodingResult::F64(floats.clone()),
+                floats.iter().flat_map(|f| f.to_ne_bytes()).collect(),
+            ),
+            (
+                Predictor::Horizontal,
+                ColorType::RGB(16),
+                SampleFormat::Uint,
+                DecodingResult::U16(rgb.clone()),
+                rgb.iter()
+                    .flat_map(|v| v.to_ne_bytes())
+                    .collect::<Vec<u8>>(),
+            ),
+        ];
+        for (predictor, color_type, sample_format, expected, tile) in cases {
+            let level = Level {
+                color_type,
+                sample_format,
+                tiles: vec![tile.clone()],
+                ..level(16, 0)
+            };
+            let (_, samples, _) = level.color_tags().unwrap();
+            let mut encoder = CogEncoder::new(Vec::new(), CogLayout::HeaderFirst)
+                .unwrap()
+                .with_predictor(predictor);
+            encoder.write_level(level, true).unwrap();
+            let buf = encoder.finish().unwrap();
+
+            let tiff = Tiff::read(&buf, &DecoderOptions::default()).await.unwrap();
+            let ifd = &tiff.ifds[0];
+            assert_eq!(
+                ifd.require_tag_value(&Tag::Predictor)
+                    .unwrap()
+                    .get_u64(0)
+                    .unwrap(),
+                u64::from(predictor.to_u16())
+            );
+            let offset = ifd
+                .require_tag_value(&Tag::TileOffsets)
+                .unwrap()
+                .get_u64(0)
+                .unwrap() as usize;
+            let raw = buf[offset..offset + tile.len()].to_vec();
+            assert_ne!(raw, tile);
+            let meta = ChunkMetaData {
+                byte_order: ByteOrder::LittleEndian,
+                image_width: 16,
+                image_height: 16,
+                bits_per_sample: color_type.bit_depth(),
+                samples,
+                sample_format,
+                photometric_interpretation: PhotometricInterpretation::BlackIsZero,
+                compression_method: CompressionMethod::None,
+                predictor,
+                jpeg_tables: None,
+                planar_config: PlanarConfiguration::Chunky,
+                chunk_type: ChunkType::Tile,
+                strip_decoder: None,
+                tile_attributes: Some(TileAttributes {
+                    image_width: 16,
+                    image_height: 16,
+                    tile_width: 16,
+                    tile_length: 16,
+                }),
+                ycbcr_subsampling: (1, 1),
+                nodata: None,
+            };
+            let data = decode_chunk_data(raw, 0, &meta, &Limits::default()).unwrap();
+            assert_eq!(DecodingResult::new(&data, &meta).unwrap(), expected);
+        }
+
+        // the floating point predictor only works on floats, and vice versa
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::Interleaved)
+            .unwrap()
+            .with_predictor(Predictor::FloatingPoint);
+        assert!(matches!(
+            encoder.write_level(level(16, 0), true),
+            Err(TiffError::UnsupportedError(
+                TiffUnsupportedError::FloatingPointPredictor(ColorType::Gray(8))
+            ))
+        ));
+        let float = Level {
+            color_type: ColorType::Gray(32),
+            sample_format: SampleFormat::IEEEFP,
+            tiles: vec![vec![0; 16 * 16 * 4]],
+            ..level(16, 0)
+        };
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::Interleaved)
+            .unwrap()
+            .with_predictor(Predictor::Horizontal);
+        assert!(matches!(
+            encoder.write_level(float, true),
+            Err(TiffError::UnsupportedError(
+                TiffUnsupportedError::HorizontalPredictor(ColorType::Gray(32))
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_photometric() {
+        let rgb = |color_type| Level {
+            color_type,
+            tiles: vec![[255u8, 0, 0].repeat(16 * 16)],
+            ..level(16, 0)
+        };
+        let photometric = |buf: &[u8]| {
+            let ifd = read_ifd(buf, 0);
+            u16::try_from(
+                ifd.require_tag_value(&Tag::PhotometricInterpretation)
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+        let write = |policy, level| {
+            let mut encoder = CogEncoder::new(Vec::new(), CogLayout::Interleaved)
+                .unwrap()
+                .with_photometric(policy);
+            encoder.write_level(level, true).unwrap();
+            encoder.finish().unwrap()
+        };
+        let tile = |buf: &[u8]| read_chain(buf)[0].1[0] as usize;
+
+        // RGB is converted to YCbCr when asked for
+        let buf = write(PhotometricPolicy::YCbCr, rgb(ColorType::RGB(8)));
+        assert_eq!(photometric(&buf), 6);
+        let ifd = read_ifd(&buf, 0);
+        assert_eq!(
+            <&[u16]>::try_from(ifd.require_tag_value(&Tag::YCbCrSubSampling).unwrap()).unwrap(),
+            [1, 1]
+        );
+        assert!(ifd.contains_key(&Tag::ReferenceBlackWhite));
+        assert_eq!(buf[tile(&buf)..tile(&buf) + 3], [76, 85, 255]);
+
+        // and kept as is otherwise
+        let buf = write(PhotometricPolicy::Auto, rgb(ColorType::RGB(8)));
+        assert_eq!(photometric(&buf), 2);
+        assert_eq!(buf[tile(&buf)..tile(&buf) + 3], [255, 0, 0]);
+
+        // YCbCr becomes RGB for uncompressed tiles, unless asked otherwise
+        let mut ycbcr = rgb(ColorType::YCbCr(8));
+        ycbcr.tiles = vec![[76u8, 85, 255].repeat(16 * 16)];
+        let buf = write(PhotometricPolicy::Auto, ycbcr.clone());
+        assert_eq!(photometric(&buf), 2);
+        assert_eq!(buf[tile(&buf)..tile(&buf) + 3], [254, 0, 0]);
+        let buf = write(PhotometricPolicy::AsGiven, ycbcr);
+        assert_eq!(photometric(&buf), 6);
+
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::Interleaved)
+            .unwrap()
+            .with_photometric(PhotometricPolicy::YCbCr);
+        let rgb16 = Level {
+            tiles: vec![vec![0; 16 * 16 * 6]],
+            ..rgb(ColorType::RGB(16))
+        };
+        assert!(encoder.write_level(rgb16, true).is_err());
+    }
+
+    #[test]
+    fn test_write_level_with_overviews() {
+        // a pyramid of 64x64 and 40x40, the 9 tiles of 40x40 being 0 to 8
+        let mut smallest = level(40, 0);
+        for (value, tile) in (0..).zip(&mut smallest.tiles) {
+            tile.fill(value);
+        }
+        smallest.extra_tags.insert(Tag::Software, entry("abc"));
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::HeaderFirst).unwrap();
+        encoder.write_level(level(64, 1), false).unwrap();
+        let n_overviews = encoder
+            .write_level_with_overviews(smallest, Resampling::Nearest, Some(99.0))
+            .unwrap();
+        assert_eq!(n_overviews, 2);
+        let buf = encoder.finish().unwrap();
+
+        let (mut tiff, _) = Tiff::from_header(&buf).unwrap();
+        tiff.ifds = (0..4).map(|i| read_ifd(&buf, i)).collect();
+        let coverage = OverviewCoverage::new(&tiff).unwrap();
+        assert_eq!(coverage.levels, [(64, 64), (40, 40), (20, 20), (10, 10)]);
+        assert!(coverage.missing_levels().is_empty());
+        let software = |ifd: &Ifd| {
+            ifd.get_tag_value(&Tag::Software)
+                .unwrap()
+                .map(|e| e.data().to_vec())
+        };
+        assert!(software(&tiff.ifds[3]).is_some());
+        assert_eq!(software(&tiff.ifds[3]), software(&tiff.ifds[1]));
+        // the top left pixels of 2x2 pixels of 40x40
+        let chain = read_chain(&buf);
+        let tile = |i: usize| &buf[chain[2].1[i] as usize..][..16 * 16];
+        assert_eq!(tile(0)[..16], [[0; 8], [1; 8]].concat());
+        assert_eq!(tile(0)[8 * 16..8 * 16 + 8], [3; 8]);
+        // the edge tile is padded with nodata
+        assert_eq!(tile(3)[..5], [8, 8, 8, 8, 99]);
+        assert_eq!(tile(3)[16 * 16 - 1], 99);
+
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::HeaderFirst).unwrap();
+        let mut wide_tiles = level(40, 0);
+        (wide_tiles.tile_width, wide_tiles.tiles) = (32, vec![vec![0; 32 * 16]; 6]);
+        assert!(encoder
+            .write_level_with_overviews(wide_tiles, Resampling::Nearest, None)
+            .is_err());
+        // a level that fits in a single tile needs no overviews
+        let n_overviews = encoder
+            .write_level_with_overviews(level(16, 0), Resampling::Average, None)
+            .unwrap();
+        assert_eq!(n_overviews, 0);
+        assert_eq!(read_chain(&encoder.finish().unwrap()).len(), 1);
+    }
+
+    #[test]
+    fn test_masks() {
+        let mask = |size: u32, value: u8| Level {
+            color_type: ColorType::Gray(1),
+            tiles: vec![vec![value; 16 * 16 / 8]; (size as usize / 16).pow(2)],
+            ..level(size, 0)
+        };
+        for layout in [CogLayout::Interleaved, CogLayout::HeaderFirst] {
+            let mut encoder = CogEncoder::new(Vec::new(), layout)
+                .unwrap()
+                .with_predictor(Predictor::Horizontal);
+            // masks that don't fit their level
+            let gray = Level {
+                tiles: vec![vec![0; 16 * 16]; 4],
+                ..mask(32, 0)
+            };
+            assert!(encoder
+                .write_level_with_mask(level(32, 1), gray, false)
+                .is_err());
+            assert!(encoder
+                .write_level_with_mask(level(32, 1), mask(16, 0), false)
+                .is_err());
+
+            encoder
+                .write_level_with_mask(level(32, 1), mask(32, 0xaa), false)
+                .unwrap();
+            encoder
+                .write_level_with_mask(level(16, 2), mask(16, 0x55), true)
+                .unwrap();
+            let buf = encoder.finish().unwrap();
+            let chain = read_chain(&buf);
+            assert_eq!(chain.len(), 4);
+            for (i, (subfile_type, photometric, value)) in [
+                (None, 1, 1),
+                (Some(4), 4, 0xaa),
+                (Some(1), 1, 2),
+                (Some(5), 4, 0x55),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let ifd = read_ifd(&buf, i);
+                let get = |tag| {
+                    ifd.get_tag_value(&tag)
+                        .unwrap()
+                        .map(|v| v.get_u64(0).unwrap())
+                };
+                assert_eq!(get(Tag::NewSubfileType), subfile_type);
+                assert_eq!(get(Tag::PhotometricInterpretation), Some(photometric));
+                assert_eq!(get(Tag::Predictor), (photometric == 1).then_some(2));
+                let offset = chain[i].1[0] as usize;
+                assert_eq!(buf[offset], value, "{layout:?} IFD {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        let mut encoder = CogEncoder::new(Vec::new(), CogLayout::Interleaved).unwrap();
+        let mut wrong = level(32, 0);
+        wrong.tiles.pop();
+        assert!(encoder.write_level(wrong, false).is_err());
+        let mut wrong = level(32, 0);
+        wrong.tiles[0].pop();
+        assert!(encoder.write_level(wrong, false).is_err());
+        encoder.write_level(level(16, 0), true).unwrap();
+        assert!(encoder.write_level(level(16, 0), true).is_err());
+
+        let encoder = CogEncoder::new(Vec::new(), CogLayout::HeaderFirst).unwrap();
+        assert!(encoder.finish().is_err());
+    }
+}
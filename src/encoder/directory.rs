@@ -0,0 +1,344 @@
+//! Encoding of IFDs, given the offset they will be written at.
+//!
+//! Entries are always written in ascending order of their tag code, as the
+//! spec requires, whatever the order of [`Tag`]'s variants. An entry of
+//! [`Tag::Unknown`] with the code of a known tag is merged into the known one.
+//! [`canonicalize`] additionally gives the entries of baseline tags the types
+//! and counts the spec asks for, so strict readers accept them.
+//!
+//! [`Tag`]: crate::structs::Tag
+//! [`Tag::Unknown`]: crate::structs::Tag::Unknown
+//! [`canonicalize`]: crate::encoder::directory::canonicalize
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+use crate::{
+    encoder::{tiff_value::TiffValue, EndianWriter},
+    error::{TiffFormatError, TiffResult, UsageError},
+    structs::{BufferedEntry, Tag, TagType},
+    util::fix_endianness,
+    ByteOrder,
+};
+
+/// Tags and their (native-endian) values, as they will be written to a file
+pub type EncodedDirectory = BTreeMap<Tag, BufferedEntry>;
+
+/// Create an entry from anything that can be written to a tiff
+pub fn entry<T: TiffValue + ?Sized>(value: &T) -> BufferedEntry {
+    BufferedEntry {
+        tag_type: value.is_type(),
+        count: value.count() as u64,
+        data: value.data().into_owned(),
+    }
+}
+
+/// Entries in ascending order of their tag code, without duplicate codes. The
+/// known tag wins over a [`Tag::Unknown`] with the same code.
+fn sorted(dir: &EncodedDirectory) -> Vec<(u16, &BufferedEntry)> {
+    // `Tag::Unknown` comes after the known tags in the map
+    let mut entries: Vec<_> = dir.iter().map(|(tag, e)| (tag.to_u16(), e)).collect();
+    entries.sort_by_key(|(code, _)| *code);
+    entries.dedup_by_key(|(code, _)| *code);
+    entries
+}
+
+/// Types the spec allows for a tag, narrowest first, and its count if that
+/// is fixed. `None` for tags that are written as given.
+fn spec(tag: &Tag) -> Option<(&'static [TagType], Option<u64>)> {
+    const SHORT: &[TagType] = &[TagType::SHORT];
+    const SHORT_LONG: &[TagType] = &[TagType::SHORT, TagType::LONG];
+    Some(match tag {
+        Tag::NewSubfileType => (&[TagType::LONG], Some(1)),
+        Tag::ImageWidth
+        | Tag::ImageLength
+        | Tag::RowsPerStrip
+        | Tag::TileWidth
+        | Tag::TileLength => (SHORT_LONG, Some(1)),
+        Tag::Compression
+        | Tag::PhotometricInterpretation
+        | Tag::FillOrder
+        | Tag::Orientation
+        | Tag::SamplesPerPixel
+        | Tag::PlanarConfiguration
+        | Tag::ResolutionUnit
+        | Tag::Predictor
+        | Tag::YCbCrPositioning => (SHORT, Some(1)),
+        Tag::YCbCrSubSampling => (SHORT, Some(2)),
+        Tag::BitsPerSample | Tag::ExtraSamples | Tag::SampleFormat => (SHORT, None),
+        _ => return None,
+    })
+}
+
+/// `values` as an entry of the narrowest of `types` they fit in
+fn narrowest(values: &[u64], types: &[TagType]) -> Option<BufferedEntry> {
+    let max = values.iter().copied().max().unwrap_or(0);
+    types.iter().find_map(|tag_type| match tag_type {
+        TagType::SHORT if max <= u64::from(u16::MAX) => Some(entry(
+            &values.iter().map(|&v| v as u16).collect::<Vec<_>>()[..],
+        )),
+        TagType::LONG if max <= u64::from(u32::MAX) => Some(entry(
+            &values.iter().map(|&v| v as u32).collect::<Vec<_>>()[..],
+        )),
+        _ => None,
+    })
+}
+
+/// Bring the entries of baseline tags in the form the spec asks for: merge
+/// [`Tag::Unknown`] entries into the known tag with their code, write integers
+/// as the narrowest type the spec allows, e.g. SHORT for an `ImageWidth` that
+/// fits, and repeat a single `BitsPerSample` or `SampleFormat` for each sample.
+///
+/// Entries that can't be canonicalized, such as a `Compression` that doesn't
+/// fit in a SHORT or a count that doesn't match, are left as is, unless
+/// `strict` is set, which makes them an error. So do duplicate entries with
+/// different values.
+pub fn canonicalize(dir: &mut EncodedDirectory, strict: bool) -> TiffResult<()> {
+    let unknown: Vec<_> = dir
+        .keys()
+        .filter(|tag| matches!(tag, Tag::Unknown(_)))
+        .copied()
+        .collect();
+    for tag in unknown {
+        let known = Tag::from_u16_exhaustive(tag.to_u16());
+        if matches!(known, Tag::Unknown(_)) {
+            continue;
+        }
+        let entry = dir.remove(&tag).expect("key of the map");
+        match dir.get(&known) {
+            Some(existing) if *existing != entry && strict => {
+                return Err(UsageError::DuplicateTagData.into())
+            }
+            Some(_) => {}
+            None => {
+                dir.insert(known, entry);
+            }
+        }
+    }
+
+    let samples = dir
+        .get(&Tag::SamplesPerPixel)
+        .and_then(|e| e.get_u64(0).ok());
+    for (tag, entry) in dir.iter_mut() {
+        let Some((types, count)) = spec(tag) else {
+            continue;
+        };
+        let invalid = || TiffFormatError::InvalidTagValueType(tag.to_u16()).into();
+        let values: Vec<u64> = match (0..usize::try_from(entry.count)?)
+            .map(|i| entry.get_u64(i))
+            .collect::<TiffResult<_>>()
+        {
+            Ok(values) => values,
+            Err(_) if strict => return Err(invalid()),
+            Err(_) => continue,
+        };
+        let per_sample = matches!(tag, Tag::BitsPerSample | Tag::SampleFormat);
+        let values = match (count, samples) {
+            (Some(count), _) if values.len() as u64 != count => {
+                if strict {
+                    return Err(
+                        TiffFormatError::InconsistentSizesEncountered((&*entry).into()).into(),
+                    );
+                }
+                continue;
+            }
+            (None, Some(samples)) if per_sample && values.len() as u64 != samples => {
+                match values[..] {
+                    [value] => vec![value; usize::try_from(samples)?],
+                    _ if strict => {
+                        return Err(
+                            TiffFormatError::InconsistentSizesEncountered((&*entry).into()).into(),
+                        )
+                    }
+                    _ => continue,
+                }
+            }
+            _ => values,
+        };
+        match narrowest(&values, types) {
+            Some(canonical) => *entry = canonical,
+            None if strict => return Err(invalid()),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// (count field, entry, offset field) sizes
+fn field_sizes(bigtiff: bool) -> (u64, u64, u64) {
+    if bigtiff {
+        (8, 20, 8)
+    } else {
+        (2, 12, 4)
+    }
+}
+
+/// Size in bytes of an encoded IFD, including the values that don't fit in
+/// their entry.
+///
+/// This only depends on the types and counts of the entries, so offsets can be
+/// filled in after computing where things go.
+pub fn ifd_len(dir: &EncodedDirectory, bigtiff: bool) -> u64 {
+    let (count_len, entry_len, offset_len) = field_sizes(bigtiff);
+    let entries = sorted(dir);
+    count_len + entry_len * entries.len() as u64 + offset_len + values_len(&entries, offset_len)
+}
+
+/// Size in bytes of an entry of an encoded IFD, including its value if that
+/// doesn't fit in the entry
+pub fn entry_len(entry: &BufferedEntry, bigtiff: bool) -> u64 {
+    let (_, entry_len, offset_len) = field_sizes(bigtiff);
+    entry_len + values_len(&[(0, entry)], offset_len)
+}
+
+/// Encode an IFD that will be written at `ifd_offset`, followed by the values
+/// that don't fit in their entries.
+pub fn encode_ifd(
+    dir: &EncodedDirectory,
+    ifd_offset: u64,
+    next_ifd: u64,
+    byte_order: ByteOrder,
+    bigtiff: bool,
+) -> TiffResult<Vec<u8>> {
+    let (_, _, offset_len) = field_sizes(bigtiff);
+    let len = usize::try_from(ifd_len(dir, bigtiff))?;
+    let mut ifd = EndianWriter::wrap(Vec::with_capacity(len), byte_order);
+    let mut values = Vec::new();
+    let entries = sorted(dir);
+    let values_offset = ifd_offset + len as u64 - values_len(&entries, offset_len);
+
+    let write_offset = |ifd: &mut EndianWriter<Vec<u8>>, v: u64| -> TiffResult<()> {
+        if bigtiff {
+            ifd.write_u64(v);
+        } else {
+            ifd.write_u32(u32::try_from(v)?);
+        }
+        Ok(())
+    };
+
+    if bigtiff {
+        ifd.write_u64(entries.len() as u64);
+    } else {
+        ifd.write_u16(u16::try_from(entries.len())?);
+    }
+    for (code, entry) in entries {
+        ifd.write_u16(code);
+        ifd.write_u16(entry.tag_type.to_u16());
+        write_offset(&mut ifd, entry.count)?;
+        let mut data = entry.data.clone();
+        fix_endianness(&mut data, byte_order, 8 * entry.tag_type.primitive_size());
+        if data.len() as u64 <= offset_len {
+            data.resize(usize::try_from(offset_len)?, 0);
+            ifd.write_bytes(&data);
+        } else {
+            write_offset(&mut ifd, values_offset + values.len() as u64)?;
+            values.extend_from_slice(&data);
+            if values.len() % 2 == 1 {
+                values.push(0);
+            }
+        }
+    }
+    write_offset(&mut ifd, next_ifd)?;
+    ifd.write_bytes(&values);
+    let ifd = ifd.into_inner();
+    debug_assert_eq!(ifd.len(), len);
+    Ok(ifd)
+}
+
+/// Size of the values that don't fit in their entries
+fn values_len(entries: &[(u16, &BufferedEntry)], offset_len: u64) -> u64 {
+    entries
+        .iter()
+        .map(|(_, e)| e.data.len() as u64)
+        .filter(|&len| len > offset_len)
+        // values start on a word boundary
+        .map(|len| len + len % 2)
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{decoder::Limits, structs::Ifd};
+
+    #[test]
+    fn test_roundtrip() {
+        let mut dir = EncodedDirectory::new();
+        dir.insert(Tag::ImageWidth, entry(&300u32));
+        dir.insert(Tag::BitsPerSample, entry(&[8u16, 8, 8][..]));
+        for (byte_order, bigtiff) in [
+            (ByteOrder::LittleEndian, false),
+            (ByteOrder::BigEndian, false),
+            (ByteOrder::LittleEndian, true),
+        ] {
+            let buf = encode_ifd(&dir, 8, 0, byte_order, bigtiff).unwrap();
+            assert_eq!(buf.len() as u64, ifd_len(&dir, bigtiff));
+            let ifd = Ifd::from_buffer(&buf, byte_order, bigtiff, &Limits::default()).unwrap();
+            assert_eq!(
+                u32::try_from(ifd.require_tag_value(&Tag::ImageWidth).unwrap()).unwrap(),
+                300
+            );
+            // 6 bytes of BitsPerSample only fit inline in BigTIFF
+            assert_eq!(ifd.get_tag_value(&Tag::BitsPerSample).is_ok(), bigtiff);
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        // Artist (315) is declared before BitsPerSample (258)
+        let mut dir = EncodedDirectory::new();
+        dir.insert(Tag::Artist, entry("me"));
+        dir.insert(Tag::BitsPerSample, entry(&8u16));
+        dir.insert(Tag::Unknown(258), entry(&16u16));
+        dir.insert(Tag::ImageWidth, entry(&1u32));
+        let buf = encode_ifd(&dir, 8, 0, ByteOrder::LittleEndian, false).unwrap();
+        assert_eq!(buf.len() as u64, ifd_len(&dir, false));
+        let codes: Vec<_> = (0..usize::from(u16::from_le_bytes([buf[0], buf[1]])))
+            .map(|i| u16::from_le_bytes([buf[2 + 12 * i], buf[3 + 12 * i]]))
+            .collect();
+        assert_eq!(codes, [256, 258, 315]);
+        // the known tag wins
+        assert_eq!(buf[2 + 12 + 8], 8);
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let mut dir = EncodedDirectory::new();
+        dir.insert(Tag::ImageWidth, entry(&300u32));
+        dir.insert(Tag::ImageLength, entry(&100_000u64));
+        dir.insert(Tag::NewSubfileType, entry(&1u16));
+        dir.insert(Tag::SamplesPerPixel, entry(&3u32));
+        dir.insert(Tag::BitsPerSample, entry(&8u8));
+        dir.insert(Tag::Unknown(259), entry(&1u16));
+        dir.insert(Tag::Artist, entry("me"));
+        let original = dir.clone();
+        canonicalize(&mut dir, true).unwrap();
+        assert_eq!(dir[&Tag::ImageWidth], entry(&300u16));
+        assert_eq!(dir[&Tag::ImageLength], entry(&100_000u32));
+        assert_eq!(dir[&Tag::NewSubfileType], entry(&1u32));
+        assert_eq!(dir[&Tag::SamplesPerPixel], entry(&3u16));
+        assert_eq!(dir[&Tag::BitsPerSample], entry(&[8u16, 8, 8][..]));
+        assert_eq!(dir[&Tag::Compression], entry(&1u16));
+        assert!(!dir.contains_key(&Tag::Unknown(259)));
+        assert_eq!(dir[&Tag::Artist], original[&Tag::Artist]);
+
+        // impossible ones are kept, or rejected when strict
+        let mut dir = EncodedDirectory::new();
+        dir.insert(Tag::Compression, entry(&70_000u32));
+        dir.insert(Tag::SamplesPerPixel, entry(&3u16));
+        dir.insert(Tag::BitsPerSample, entry(&[8u16, 8][..]));
+        dir.insert(Tag::ImageWidth, entry("wide"));
+        let original = dir.clone();
+        canonicalize(&mut dir, false).unwrap();
+        assert_eq!(dir, original);
+        for tag in [Tag::Compression, Tag::BitsPerSample, Tag::ImageWidth] {
+            let mut dir = EncodedDirectory::new();
+            dir.insert(Tag::SamplesPerPixel, entry(&3u16));
+            dir.insert(tag, original[&tag].clone());
+            assert!(canonicalize(&mut dir, true).is_err(), "{tag:?}");
+        }
+        let mut dir = EncodedDirectory::new();
+        dir.insert(Tag::Compression, entry(&1u16));
+        dir.insert(Tag::Unknown(259), entry(&5u16));
+        assert!(canonicalize(&mut dir, true).is_err());
+    }
+}
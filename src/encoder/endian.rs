@@ -0,0 +1,122 @@
+use crate::{structs::value::PrimitiveValue, ByteOrder};
+
+/// Writer of values in a byte order, the counterpart of
+/// [`EndianReader`](crate::decoder::EndianReader)
+///
+/// Writes to anything bytes can be appended to, like a `Vec<u8>`, so it is
+/// available without `std`.
+pub struct EndianWriter<W> {
+    writer: W,
+    pub byte_order: ByteOrder,
+}
+
+macro_rules! write_fn {
+    ($name:ident, $type:ty) => {
+        /// writes an $type, respecting byte order
+        #[inline(always)]
+        pub fn $name(&mut self, n: $type) {
+            self.writer.extend(match self.byte_order {
+                ByteOrder::LittleEndian => n.to_le_bytes(),
+                ByteOrder::BigEndian => n.to_be_bytes(),
+            });
+        }
+    };
+}
+
+impl<W: Extend<u8>> EndianWriter<W> {
+    /// Wraps a writer
+    pub fn wrap(writer: W, byte_order: ByteOrder) -> Self {
+        EndianWriter { writer, byte_order }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes bytes as they are
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.writer.extend(bytes.iter().copied());
+    }
+
+    write_fn!(write_u8, u8);
+    write_fn!(write_i8, i8);
+    write_fn!(write_u16, u16);
+    write_fn!(write_i16, i16);
+    write_fn!(write_u32, u32);
+    write_fn!(write_i32, i32);
+    write_fn!(write_u64, u64);
+    write_fn!(write_i64, i64);
+
+    write_fn!(write_f32, f32);
+    write_fn!(write_f64, f64);
+
+    /// Writes a single value of its tag type, respecting byte order
+    pub fn write_value(&mut self, value: PrimitiveValue) {
+        match value {
+            PrimitiveValue::Byte(v) | PrimitiveValue::Undefined(v) | PrimitiveValue::Ascii(v) => {
+                self.write_u8(v)
+            }
+            PrimitiveValue::SByte(v) => self.write_i8(v),
+            PrimitiveValue::Short(v) => self.write_u16(v),
+            PrimitiveValue::SShort(v) => self.write_i16(v),
+            PrimitiveValue::Long(v) | PrimitiveValue::Ifd(v) => self.write_u32(v),
+            PrimitiveValue::SLong(v) => self.write_i32(v),
+            PrimitiveValue::Long8(v) | PrimitiveValue::Ifd8(v) => self.write_u64(v),
+            PrimitiveValue::SLong8(v) => self.write_i64(v),
+            PrimitiveValue::Float(v) => self.write_f32(v),
+            PrimitiveValue::Double(v) => self.write_f64(v),
+            PrimitiveValue::Rational(n, d) => {
+                self.write_u32(n);
+                self.write_u32(d);
+            }
+            PrimitiveValue::SRational(n, d) => {
+                self.write_i32(n);
+                self.write_i32(d);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{decoder::EndianReader, structs::TagType};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_value_roundtrip() {
+        let values = [
+            PrimitiveValue::Byte(7),
+            PrimitiveValue::SByte(-7),
+            PrimitiveValue::Ascii(b'a'),
+            PrimitiveValue::SShort(-300),
+            PrimitiveValue::Long(70000),
+            PrimitiveValue::Ifd(8),
+            PrimitiveValue::SLong8(-1 << 40),
+            PrimitiveValue::Float(0.5),
+            PrimitiveValue::Double(-2.25),
+            PrimitiveValue::Rational(1, 3),
+            PrimitiveValue::SRational(-1, 3),
+        ];
+        for byte_order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut w = EndianWriter::wrap(Vec::new(), byte_order);
+            for value in values {
+                w.write_value(value);
+            }
+            let buf = w.into_inner();
+            assert_eq!(
+                buf.len(),
+                values.iter().map(|v| v.tag_type().size()).sum::<usize>()
+            );
+            let mut r = EndianReader::wrap(&buf[..], byte_order);
+            for value in values {
+                assert_eq!(r.read_value(value.tag_type()).unwrap(), value);
+            }
+        }
+        let mut w = EndianWriter::wrap(Vec::new(), ByteOrder::BigEndian);
+        w.write_value(PrimitiveValue::Short(0x0102));
+        assert_eq!(w.into_inner(), [1, 2]);
+        let mut r = EndianReader::wrap(&[0u8; 4][..], ByteOrder::LittleEndian);
+        assert!(r.read_value(TagType::Unknown(0x8000, 4)).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! Encoding into memory, for serverless functions that return the file they
+//! encode as a response body and for tests.
+//!
+//! A [`CogEncoder`](crate::encoder::CogEncoder) writes to any [`Write`],
+//! `Vec<u8>` included. [`MemoryWriter`] additionally hands the file over as
+//! [`Bytes`] or as a [`MemoryReader`] to decode it again, without copying it.
+
+use std::io::{self, Write};
+
+use bytes::Bytes;
+
+use crate::decoder::MemoryReader;
+
+/// Writes a file into memory
+#[derive(Debug, Clone, Default)]
+pub struct MemoryWriter {
+    data: Vec<u8>,
+}
+
+impl MemoryWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserve room for `capacity` bytes up front, e.g. the size of the
+    /// uncompressed image
+    pub fn with_capacity(capacity: usize) -> Self {
+        MemoryWriter {
+            data: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Bytes written so far
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.data.into()
+    }
+
+    /// Read back what was written
+    pub fn into_reader(self) -> MemoryReader {
+        MemoryReader::new(self.data)
+    }
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.data.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        decoder::{CogDecoder, CogReader, DecoderOptions},
+        encoder::{directory::EncodedDirectory, CogEncoder, CogLayout, Level},
+        structs::{tags::SampleFormat, Image, Tag, Tiff},
+        ByteOrder, ColorType,
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let level = Level {
+            width: 32,
+            height: 16,
+            tile_width: 16,
+            tile_height: 16,
+            color_type: ColorType::Gray(8),
+            sample_format: SampleFormat::Uint,
+            tiles: vec![vec![1; 256], vec![2; 256]],
+            extra_tags: EncodedDirectory::new(),
+        };
+        let mut encoder =
+            CogEncoder::new(MemoryWriter::with_capacity(1024), CogLayout::HeaderFirst).unwrap();
+        encoder.write_level(level, true).unwrap();
+        let reader = Arc::new(encoder.finish().unwrap().into_reader());
+
+        let options = DecoderOptions::default();
+        let mut tiff = Tiff::read(&*reader, &options).await.unwrap();
+        let offsets = tiff.ifds[0].require_tag_value(&Tag::TileOffsets).unwrap();
+        let tile = reader.slice(offsets.get_u64(1).unwrap(), 256).unwrap();
+        assert_eq!(tile, vec![2; 256]);
+        // shares memory with the file
+        assert_eq!(
+            tile.as_ptr(),
+            reader.bytes()[offsets.get_u64(1).unwrap() as usize..].as_ptr()
+        );
+        assert_eq!(
+            reader
+                .read_image_data(offsets.get_u64(0).unwrap(), 2)
+                .await
+                .unwrap(),
+            vec![1, 1]
+        );
+
+        let image = Image::from_ifd(tiff.ifds.remove(0), ByteOrder::LittleEndian).unwrap();
+        let mut decoder = CogDecoder::new(reader, tiff, &options);
+        decoder.insert_image(0, image);
+        let region = decoder
+            .decode_region(0, 14, 0, 4, 1)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(region, [1, 1, 2, 2]);
+    }
+}
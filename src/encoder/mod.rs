@@ -1 +1,28 @@
+/// Encoding IFDs at known offsets
+pub mod directory;
+mod endian;
+pub use endian::EndianWriter;
+pub mod photometric;
 pub mod tiff_value;
+#[cfg(feature = "std")]
+mod writer;
+#[cfg(feature = "std")]
+pub use writer::TiffWriter;
+#[cfg(feature = "std")]
+mod cog;
+#[cfg(all(test, feature = "std"))]
+pub(crate) use cog::test_cog;
+#[cfg(feature = "std")]
+pub use cog::{CogEncoder, CogLayout, HeaderReport, Level, UniformTiles};
+#[cfg(feature = "std")]
+mod memory;
+#[cfg(feature = "std")]
+pub use memory::MemoryWriter;
+#[cfg(feature = "std")]
+mod options;
+#[cfg(feature = "std")]
+pub use options::{CogWriterOptions, InternalMask, Raster, Resampling};
+#[cfg(feature = "std")]
+mod split;
+#[cfg(feature = "std")]
+pub use split::{write_split, SplitManifest, SplitPart};
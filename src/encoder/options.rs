@@ -0,0 +1,582 @@
+//! Preset options for writing a COG from a single image.
+//!
+//! [`CogEncoder`] takes levels that were already tiled and downsampled.
+//! [`CogWriterOptions`] bundles everything else that goes into a COG: tile
+//! size, compression, predictor, how overviews are resampled, which value
+//! marks missing data and whether it is masked. [`CogWriterOptions::write`] then tiles the image and
+//! builds its overviews, halving it until it fits in a single tile, like
+//! GDAL does by default.
+//!
+//! Presets for common kinds of data are a starting point, whose fields can be
+//! overridden like those of any struct.
+
+use std::io::Write;
+
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    decoder::SampleType,
+    encoder::{
+        directory::{entry, EncodedDirectory},
+        photometric::PhotometricPolicy,
+        CogEncoder, CogLayout, Level,
+    },
+    error::{TiffFormatError, TiffResult, TiffUnsupportedError, UsageError},
+    nodata::Nodata,
+    structs::{
+        tags::{CompressionMethod, Predictor, SampleFormat},
+        Tag,
+    },
+    ColorType,
+};
+
+/// How each pixel of an overview is computed from the 2x2 pixels of the
+/// level above it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Resampling {
+    /// The top left pixel, which keeps classes and other categorical values
+    /// intact
+    #[default]
+    Nearest,
+    /// The mean of each sample over the pixels that aren't nodata, rounded
+    /// to the nearest integer for integer samples
+    Average,
+}
+
+/// Whether [`CogWriterOptions::write`] adds an internal mask of the pixels
+/// that have data, like GDAL's `-mask` option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InternalMask {
+    /// No mask, missing data only being marked by `GDAL_NODATA`
+    #[default]
+    None,
+    /// A mask, with `GDAL_NODATA` kept for readers that ignore masks
+    KeepNodata,
+    /// A mask instead of `GDAL_NODATA`
+    DropNodata,
+}
+
+/// An image to write, with samples interleaved pixel by pixel, row by row,
+/// in native byte order
+#[derive(Debug, Clone)]
+pub struct Raster {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub sample_format: SampleFormat,
+    pub data: Vec<u8>,
+}
+
+impl Raster {
+    /// The pixels of the tiles of a level that lie within the image, which
+    /// must have been checked to hold whole tiles of whole bytes per sample
+    pub(super) fn from_level(level: &Level) -> TiffResult<Raster> {
+        let mut raster = Raster {
+            width: level.width,
+            height: level.height,
+            color_type: level.color_type,
+            sample_format: level.sample_format,
+            data: Vec::new(),
+        };
+        let pixel = raster.samples() * raster.sample_type()?.size();
+        let (width, height) = (level.width as usize, level.height as usize);
+        let (tile_width, tile_height) = (level.tile_width as usize, level.tile_height as usize);
+        let tiles_across = level.tiles_across() as usize;
+        raster.data.reserve(width * height * pixel);
+        for y in 0..height {
+            for tx in (0..width).step_by(tile_width) {
+                let tile = &level.tiles[y / tile_height * tiles_across + tx / tile_width];
+                let start = y % tile_height * tile_width * pixel;
+                let cols = tile_width.min(width - tx);
+                raster
+                    .data
+                    .extend_from_slice(&tile[start..start + cols * pixel]);
+            }
+        }
+        Ok(raster)
+    }
+
+    pub(super) fn sample_type(&self) -> TiffResult<SampleType> {
+        SampleType::from_format(self.sample_format, self.color_type.bit_depth()).ok_or_else(|| {
+            TiffUnsupportedError::UnsupportedBitsPerChannel(self.color_type.bit_depth()).into()
+        })
+    }
+
+    fn samples(&self) -> usize {
+        match self.color_type {
+            ColorType::Gray(_) | ColorType::Palette(_) => 1,
+            ColorType::GrayA(_) => 2,
+            ColorType::RGB(_) | ColorType::YCbCr(_) | ColorType::Lab(_) => 3,
+            ColorType::RGBA(_) | ColorType::CMYK(_) => 4,
+            ColorType::Multiband { num_samples, .. } => usize::from(num_samples),
+        }
+    }
+}
+
+/// Everything that goes into writing a COG from a [`Raster`] with
+/// [`CogWriterOptions::write`]
+///
+/// Start from a preset, and override what doesn't fit:
+///
+/// ```
+/// # use tiff2::encoder::CogWriterOptions;
+/// let options = CogWriterOptions {
+///     tile_size: 256,
+///     ..CogWriterOptions::preset("dem-float32").unwrap()
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CogWriterOptions {
+    pub layout: CogLayout,
+    pub bigtiff: bool,
+    /// Width and height of the tiles, a multiple of 16. 512 by default.
+    pub tile_size: u32,
+    /// [`CompressionMethod::None`] or [`CompressionMethod::Deflate`], the
+    /// default
+    pub compression: CompressionMethod,
+    pub predictor: Predictor,
+    pub photometric: PhotometricPolicy,
+    pub overview_resampling: Resampling,
+    /// Value of pixels without data, written as the `GDAL_NODATA` tag of
+    /// every level. It is left out of averages, and fills the part of edge
+    /// tiles outside the image, which is 0 otherwise. `None` by default.
+    pub nodata: Option<f64>,
+    /// Write a 1-bit transparency mask with every level, whose bits are
+    /// unset for pixels whose samples are all nodata and outside the image,
+    /// see [`CogEncoder::write_level_with_mask`]. Without nodata, there is
+    /// nothing to mask and no mask is written.
+    pub mask: InternalMask,
+}
+
+impl Default for CogWriterOptions {
+    fn default() -> Self {
+        CogWriterOptions {
+            layout: CogLayout::HeaderFirst,
+            bigtiff: false,
+            tile_size: 512,
+            compression: CompressionMethod::Deflate,
+            predictor: Predictor::None,
+            photometric: PhotometricPolicy::Auto,
+            overview_resampling: Resampling::Nearest,
+            nodata: None,
+            mask: InternalMask::None,
+        }
+    }
+}
+
+impl CogWriterOptions {
+    /// Names of the presets [`CogWriterOptions::preset`] knows
+    pub const PRESETS: [&'static str; 3] = ["web-mercator-rgb", "analytic-int16", "dem-float32"];
+
+    /// The preset called `name`, one of [`CogWriterOptions::PRESETS`]
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "web-mercator-rgb" => Some(Self::web_mercator_rgb()),
+            "analytic-int16" => Some(Self::analytic_int16()),
+            "dem-float32" => Some(Self::dem_float32()),
+            _ => None,
+        }
+    }
+
+    /// 8-bit RGB(A) imagery for web maps: tiles of 256x256 like those of
+    /// web map tile grids, and averaged overviews. Georeferencing is taken
+    /// as given, the image isn't reprojected.
+    pub fn web_mercator_rgb() -> Self {
+        CogWriterOptions {
+            tile_size: 256,
+            predictor: Predictor::Horizontal,
+            overview_resampling: Resampling::Average,
+            ..Default::default()
+        }
+    }
+
+    /// 16-bit integer measurements, such as reflectances: averaged overviews
+    /// and -32768 as nodata
+    pub fn analytic_int16() -> Self {
+        CogWriterOptions {
+            predictor: Predictor::Horizontal,
+            overview_resampling: Resampling::Average,
+            nodata: Some(-32768.0),
+            ..Default::default()
+        }
+    }
+
+    /// 32-bit float elevation models: the floating point predictor, averaged
+    /// overviews and -9999 as nodata
+    pub fn dem_float32() -> Self {
+        CogWriterOptions {
+            predictor: Predictor::FloatingPoint,
+            overview_resampling: Resampling::Average,
+            nodata: Some(-9999.0),
+            ..Default::default()
+        }
+    }
+
+    /// Write `raster` and its overviews as a COG, with `extra_tags` on every
+    /// level, see [`Level::extra_tags`]
+    pub fn write<W: Write>(
+        &self,
+        writer: W,
+        mut raster: Raster,
+        mut extra_tags: EncodedDirectory,
+    ) -> TiffResult<W> {
+        if self.tile_size == 0 || !self.tile_size.is_multiple_of(16) {
+            return Err(TiffFormatError::InvalidDimensions(self.tile_size, self.tile_size).into());
+        }
+        let sample_type = raster.sample_type()?;
+        let expected =
+            raster.width as usize * raster.height as usize * raster.samples() * sample_type.size();
+        if raster.data.len() != expected {
+            return Err(UsageError::InvalidChunkLength {
+                actual: raster.data.len(),
+                expected,
+            }
+            .into());
+        }
+        let mask_nodata = match self.mask {
+            InternalMask::None => None,
+            InternalMask::KeepNodata | InternalMask::DropNodata => self.nodata.map(Nodata::new),
+        };
+        match self.nodata {
+            Some(_) if self.mask == InternalMask::DropNodata => {}
+            Some(nodata) => {
+                extra_tags.insert(Tag::GdalNodata, entry(nodata_text(nodata).as_str()));
+            }
+            None => {}
+        }
+        let mut encoder = CogEncoder::with_bigtiff(writer, self.layout, self.bigtiff)?
+            .with_compression(self.compression)
+            .with_predictor(self.predictor)
+            .with_photometric(self.photometric);
+        loop {
+            let is_last = raster.width <= self.tile_size && raster.height <= self.tile_size;
+            let level = self.level(&raster, sample_type, extra_tags.clone())?;
+            match mask_nodata {
+                Some(nodata) => {
+                    let mask = self.mask_level(&raster, sample_type, nodata);
+                    encoder.write_level_with_mask(level, mask, is_last)?;
+                }
+                None => encoder.write_level(level, is_last)?,
+            }
+            if is_last {
+                break;
+            }
+            raster = self.downsample(&raster, sample_type);
+        }
+        encoder.finish()
+    }
+
+    /// Cut `raster` into tiles, padding them with nodata
+    pub(super) fn level(
+        &self,
+        raster: &Raster,
+        sample_type: SampleType,
+        extra_tags: EncodedDirectory,
+    ) -> TiffResult<Level> {
+        let size = self.tile_size as usize;
+        let pixel = raster.samples() * sample_type.size();
+        let mut fill = Vec::with_capacity(pixel);
+        for _ in 0..raster.samples() {
+            sample_type.write_f64(self.nodata.unwrap_or(0.0), &mut fill);
+        }
+        let (width, height) = (raster.width as usize, raster.height as usize);
+        let mut tiles = Vec::new();
+        for ty in (0..height).step_by(size) {
+            for tx in (0..width).step_by(size) {
+                let mut tile = Vec::with_capacity(size * size * pixel);
+                for y in ty..ty + size {
+                    let cols = size.min(width - tx);
+                    if y < height {
+                        let start = (y * width + tx) * pixel;
+                        tile.extend_from_slice(&raster.data[start..start + cols * pixel]);
+                    }
+                    let padding = if y < height { size - cols } else { size };
+                    for _ in 0..padding {
+                        tile.extend_from_slice(&fill);
+                    }
+                }
+                tiles.push(tile);
+            }
+        }
+        Ok(Level {
+            width: raster.width,
+            height: raster.height,
+            tile_width: self.tile_size,
+            tile_height: self.tile_size,
+            color_type: raster.color_type,
+            sample_format: raster.sample_format,
+            tiles,
+            extra_tags,
+        })
+    }
+
+    /// Tiles of the mask of `raster`, with the bits of pixels that aren't
+    /// `nodata` set
+    fn mask_level(&self, raster: &Raster, sample_type: SampleType, nodata: Nodata) -> Level {
+        let size = self.tile_size as usize;
+        let pixel = raster.samples() * sample_type.size();
+        let (width, height) = (raster.width as usize, raster.height as usize);
+        let mut tiles = Vec::new();
+        for ty in (0..height).step_by(size) {
+            for tx in (0..width).step_by(size) {
+                // tile rows are whole bytes, as the tile size is a multiple of 16
+                let mut tile = vec![0u8; size * size / 8];
+                for y in ty..(ty + size).min(height) {
+                    for x in tx..(tx + size).min(width) {
+                        let start = (y * width + x) * pixel;
+                        if !nodata.matches_pixel(&raster.data[start..start + pixel], sample_type) {
+                            let bit = (y - ty) * size + x - tx;
+                            tile[bit / 8] |= 0x80 >> (bit % 8);
+                        }
+                    }
+                }
+                tiles.push(tile);
+            }
+        }
+        Level {
+            width: raster.width,
+            height: raster.height,
+            tile_width: self.tile_size,
+            tile_height: self.tile_size,
+            color_type: ColorType::Gray(1),
+            sample_format: SampleFormat::Uint,
+            tiles,
+            extra_tags: EncodedDirectory::new(),
+        }
+    }
+
+    /// Halve `raster`, rounding up
+    pub(super) fn downsample(&self, raster: &Raster, sample_type: SampleType) -> Raster {
+        let (width, height) = (raster.width as usize, raster.height as usize);
+        let (out_width, out_height) = (width.div_ceil(2), height.div_ceil(2));
+        let samples = raster.samples();
+        let size = sample_type.size();
+        let pixel = samples * size;
+        let nodata = self.nodata.map(Nodata::new);
+        let mut data = Vec::with_capacity(out_width * out_height * pixel);
+        for y in 0..out_height {
+            for x in 0..out_width {
+                let (x0, y0) = (2 * x, 2 * y);
+                match self.overview_resampling {
+                    Resampling::Nearest => {
+                        let start = (y0 * width + x0) * pixel;
+                        data.extend_from_slice(&raster.data[start..start + pixel]);
+                    }
+                    Resampling::Average => {
+                        for s in 0..samples {
+                            let (mut sum, mut n) = (0.0, 0u32);
+                            for yy in y0..(y0 + 2).min(height) {
+                                for xx in x0..(x0 + 2).min(width) {
+                                    let start = (yy * width + xx) * pixel + s * size;
+                                    let sample = &raster.data[start..start + size];
+                                    if !nodata.is_some_and(|n| n.matches(sample, sample_type)) {
+                                        sum += sample_type.read_f64(sample);
+                                        n += 1;
+                                    }
+                                }
+                            }
+                            let mean = match (n, sample_type) {
+                                (0, _) => self.nodata.unwrap_or(0.0),
+                                (_, SampleType::F16 | SampleType::F32 | SampleType::F64) => {
+                                    sum / f64::from(n)
+                                }
+                                _ => (sum / f64::from(n)).round(),
+                            };
+                            sample_type.write_f64(mean, &mut data);
+                        }
+                    }
+                }
+            }
+        }
+        Raster {
+            width: out_width as u32,
+            height: out_height as u32,
+            color_type: raster.color_type,
+            sample_format: raster.sample_format,
+            data,
+        }
+    }
+}
+
+/// Nodata as GDAL writes it
+fn nodata_text(nodata: f64) -> String {
+    if nodata.is_nan() {
+        String::from("nan")
+    } else {
+        format!("{nodata}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        decoder::{CogDecoder, CogReader, DecoderOptions},
+        structs::{Image, Tiff},
+        ByteOrder,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_presets() {
+        for name in CogWriterOptions::PRESETS {
+            let options = CogWriterOptions::preset(name).unwrap();
+            assert_eq!(options.compression, CompressionMethod::Deflate);
+        }
+        assert!(CogWriterOptions::preset("webp-everything").is_none());
+        assert_eq!(
+            CogWriterOptions::dem_float32().predictor,
+            Predictor::FloatingPoint
+        );
+        assert_eq!(nodata_text(-9999.0), "-9999");
+        assert_eq!(nodata_text(f64::NAN), "nan");
+    }
+
+    /// Decode all levels of a COG, native-endian
+    async fn decode(cog: Vec<u8>) -> Vec<Vec<u8>> {
+        let reader: Arc<dyn CogReader> = Arc::new(cog);
+        let mut tiff = Tiff::read(&*reader, &DecoderOptions::default())
+            .await
+            .unwrap();
+        let ifds = core::mem::take(&mut tiff.ifds);
+        let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+        let mut levels = Vec::new();
+        for (i, ifd) in (0..).zip(ifds) {
+            let image = Image::from_ifd(ifd, ByteOrder::LittleEndian).unwrap();
+            let meta = image.chunk_meta();
+            let (width, height) = (meta.image_width, meta.image_height);
+            decoder.insert_image(i, image);
+            let region = decoder.decode_region(i, 0, 0, width, height).unwrap();
+            levels.push(region.await.unwrap());
+        }
+        levels
+    }
+
+    #[tokio::test]
+    async fn test_write() {
+        // 40x40 of x + 100 * y, with a nodata pixel in the top left corner
+        let mut samples: Vec<i16> = (0..40 * 40)
+            .map(|i| (i % 40 + 100 * (i / 40)) as i16)
+            .collect();
+        samples[0] = -32768;
+        let raster = Raster {
+            width: 40,
+            height: 40,
+            color_type: ColorType::Gray(16),
+            sample_format: SampleFormat::Int,
+            data: samples.iter().flat_map(|v| v.to_ne_bytes()).collect(),
+        };
+        let options = CogWriterOptions {
+            tile_size: 16,
+            ..CogWriterOptions::analytic_int16()
+        };
+        let cog = options
+            .write(Vec::new(), raster, EncodedDirectory::new())
+            .unwrap();
+        let mut tiff = Tiff::read(&cog, &DecoderOptions::default()).await.unwrap();
+        // 40x40, 20x20 and 10x10
+        assert_eq!(tiff.ifds.len(), 3);
+        for ifd in &tiff.ifds {
+            let compression = ifd.require_tag_value(&Tag::Compression).unwrap();
+            assert_eq!(compression.get_u64(0).unwrap(), 8);
+            let nodata = ifd.require_tag_value(&Tag::GdalNodata).unwrap();
+            assert_eq!(nodata.data, b"-32768\0");
+        }
+        let image = Image::from_ifd(tiff.ifds.remove(0), ByteOrder::LittleEndian).unwrap();
+        assert_eq!(image.nodata(), Some(Nodata::new(-32768.0)));
+        let levels = decode(cog).await;
+        let sample = |level: &[u8], i: usize| i16::from_ne_bytes([level[2 * i], level[2 * i + 1]]);
+        assert_eq!(levels[0].len(), 40 * 40 * 2);
+        assert_eq!(sample(&levels[0], 41), 101);
+        // in a partial edge tile
+        assert_eq!(sample(&levels[0], 40 * 40 - 1), 3939);
+        // nodata is left out: (1 + 100 + 101) / 3
+        assert_eq!(sample(&levels[1], 0), 67);
+        // (2 + 3 + 102 + 103) / 4, rounded away from zero
+        assert_eq!(sample(&levels[1], 1), 53);
+        assert_eq!(levels[2].len(), 10 * 10 * 2);
+    }
+
+    #[tokio::test]
+    async fn test_mask() {
+        // 40x40 of 1, with nodata in the top left 2x2 pixels
+        let mut samples = vec![1i16; 40 * 40];
+        for i in [0, 1, 40, 41] {
+            samples[i] = -32768;
+        }
+        let raster = Raster {
+            width: 40,
+            height: 40,
+            color_type: ColorType::Gray(16),
+            sample_format: SampleFormat::Int,
+            data: samples.iter().flat_map(|v| v.to_ne_bytes()).collect(),
+        };
+        for (mask, has_nodata) in [
+            (InternalMask::KeepNodata, true),
+            (InternalMask::DropNodata, false),
+        ] {
+            let options = CogWriterOptions {
+                tile_size: 16,
+                mask,
+                ..CogWriterOptions::analytic_int16()
+            };
+            let cog = options
+                .write(Vec::new(), raster.clone(), EncodedDirectory::new())
+                .unwrap();
+            let reader: Arc<dyn CogReader> = Arc::new(cog);
+            let mut tiff = Tiff::read(&*reader, &DecoderOptions::default())
+                .await
+                .unwrap();
+            // each of the 3 levels followed by its mask
+            assert_eq!(tiff.ifds.len(), 6);
+            let ifds = core::mem::take(&mut tiff.ifds);
+            let mut decoder = CogDecoder::new(reader, tiff, &DecoderOptions::default());
+            for (i, ifd) in (0..).zip(ifds) {
+                let nodata = ifd.get_tag_value(&Tag::GdalNodata).unwrap().is_some();
+                let image = Image::from_ifd(ifd, ByteOrder::LittleEndian).unwrap();
+                if i % 2 == 0 {
+                    assert_eq!(nodata, has_nodata);
+                    decoder.insert_image(i / 2, image);
+                } else {
+                    assert!(!nodata);
+                    decoder.insert_mask(i / 2, image);
+                }
+            }
+            let (_, mask) = decoder.read_chunk_with_mask(0, 0).unwrap().await.unwrap();
+            assert_eq!(mask[..4], [0x3f, 0xff, 0x3f, 0xff]);
+            // the edge tile is masked outside the image
+            let (_, mask) = decoder.read_chunk_with_mask(8, 0).unwrap().await.unwrap();
+            assert_eq!(mask[..2], [0xff, 0]);
+            assert_eq!(mask[8 * 2..], [0; 8 * 2]);
+            // a pixel of the overview that is nodata
+            let (_, mask) = decoder.read_chunk_with_mask(0, 1).unwrap().await.unwrap();
+            assert_eq!(mask[..4], [0x7f, 0xff, 0xff, 0xff]);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let raster = Raster {
+            width: 4,
+            height: 4,
+            color_type: ColorType::Gray(8),
+            sample_format: SampleFormat::Uint,
+            data: vec![0; 15],
+        };
+        let options = CogWriterOptions::default();
+        assert!(options
+            .write(Vec::new(), raster.clone(), EncodedDirectory::new())
+            .is_err());
+        let options = CogWriterOptions {
+            compression: CompressionMethod::LZW,
+            ..Default::default()
+        };
+        let raster = Raster {
+            data: vec![0; 16],
+            ..raster
+        };
+        assert!(options
+            .write(Vec::new(), raster, EncodedDirectory::new())
+            .is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! Choosing the color space RGB imagery is written in.
+//!
+//! JPEG compresses much better in YCbCr, so libtiff and GDAL convert RGB to
+//! YCbCr for JPEG compressed files and keep it RGB otherwise. A
+//! [`PhotometricPolicy`] does the same by default, but can force either color
+//! space.
+//!
+//! Conversions use the full range BT.601 matrix of JFIF, see
+//! [`YCbCrConversion::default`], the samples being written with a
+//! `ReferenceBlackWhite` of `[0, 255, 128, 255, 128, 255]` and without
+//! subsampling, so readers need no knowledge of JPEG to undo them.
+//!
+//! [`PhotometricPolicy`]: crate::encoder::photometric::PhotometricPolicy
+//! [`YCbCrConversion::default`]: crate::decoder::YCbCrConversion
+
+use crate::{
+    error::{TiffResult, TiffUnsupportedError},
+    structs::tags::CompressionMethod,
+    ColorType,
+};
+
+/// Color space to write RGB and YCbCr imagery in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PhotometricPolicy {
+    /// YCbCr for lossy compression, RGB for lossless compression
+    #[default]
+    Auto,
+    /// Write samples in the color space they are given in
+    AsGiven,
+    /// Always write RGB, converting YCbCr
+    Rgb,
+    /// Always write YCbCr, converting RGB
+    YCbCr,
+}
+
+/// Whether `compression` loses information, and works better on YCbCr
+fn is_lossy(compression: CompressionMethod) -> bool {
+    matches!(
+        compression,
+        CompressionMethod::JPEG | CompressionMethod::ModernJPEG
+    )
+}
+
+impl PhotometricPolicy {
+    /// Color type to write samples of `color_type` as, when compressing them
+    /// with `compression`.
+    ///
+    /// Conversions are only supported for 8-bit samples. Other depths are
+    /// written as given for [`PhotometricPolicy::Auto`], and rejected when a
+    /// color space is forced.
+    pub fn resolve(
+        self,
+        color_type: ColorType,
+        compression: CompressionMethod,
+    ) -> TiffResult<ColorType> {
+        let target = match (self, color_type) {
+            (PhotometricPolicy::AsGiven, _) => return Ok(color_type),
+            (PhotometricPolicy::Auto, ColorType::RGB(8)) if is_lossy(compression) => {
+                ColorType::YCbCr(8)
+            }
+            (PhotometricPolicy::Auto, ColorType::YCbCr(8)) if !is_lossy(compression) => {
+                ColorType::RGB(8)
+            }
+            (PhotometricPolicy::Auto, _) => return Ok(color_type),
+            (PhotometricPolicy::Rgb, ColorType::YCbCr(b)) => ColorType::RGB(b),
+            (PhotometricPolicy::YCbCr, ColorType::RGB(b)) => ColorType::YCbCr(b),
+            _ => return Ok(color_type),
+        };
+        if target.bit_depth() != 8 {
+            return Err(TiffUnsupportedError::UnsupportedColorType(color_type).into());
+        }
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let none = CompressionMethod::None;
+        let jpeg = CompressionMethod::ModernJPEG;
+        let auto = PhotometricPolicy::Auto;
+        assert_eq!(
+            auto.resolve(ColorType::RGB(8), jpeg).unwrap(),
+            ColorType::YCbCr(8)
+        );
+        assert_eq!(
+            auto.resolve(ColorType::RGB(8), none).unwrap(),
+            ColorType::RGB(8)
+        );
+        assert_eq!(
+            auto.resolve(ColorType::YCbCr(8), none).unwrap(),
+            ColorType::RGB(8)
+        );
+        assert_eq!(
+            auto.resolve(ColorType::RGB(16), jpeg).unwrap(),
+            ColorType::RGB(16)
+        );
+        assert_eq!(
+            auto.resolve(ColorType::Gray(8), jpeg).unwrap(),
+            ColorType::Gray(8)
+        );
+        assert_eq!(
+            PhotometricPolicy::AsGiven
+                .resolve(ColorType::RGB(8), jpeg)
+                .unwrap(),
+            ColorType::RGB(8)
+        );
+        assert_eq!(
+            PhotometricPolicy::YCbCr
+                .resolve(ColorType::RGB(8), none)
+                .unwrap(),
+            ColorType::YCbCr(8)
+        );
+        assert!(PhotometricPolicy::YCbCr
+            .resolve(ColorType::RGB(16), none)
+            .is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! Splitting levels too large for a classic TIFF over several files.
+//!
+//! Classic TIFFs can't address data past 4 GiB, and some consumers can't
+//! read BigTIFF. [`write_split`] partitions each level into rectangles of
+//! whole tiles that fit in `max_file_bytes`, and writes each as a classic
+//! TIFF of its own, with a [`SplitManifest`] saying where each part goes.
+//!
+//! Levels that fit are written as a single part. Tie points and
+//! transformations of the full resolution image are shifted to the origin of
+//! each part, so the parts stay georeferenced. Like for overviews in a COG,
+//! geo and resolution tags are dropped from the parts of overviews.
+
+use std::{fmt::Write as _, io::Write};
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    encoder::{
+        cog::overview_disallowed,
+        directory::{entry, ifd_len, EncodedDirectory},
+        CogEncoder, Level,
+    },
+    error::{TiffError, TiffResult},
+    structs::{tags::Predictor, Tag},
+};
+
+/// Room for the tags that options of the [`CogEncoder`] may add to a part,
+/// such as the predictor or YCbCr tags
+const TAG_SLACK: u64 = 256;
+
+/// A rectangle of whole tiles of a level, written as a file of its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitPart {
+    /// Position among all parts, which are ordered by level and then row by
+    /// row
+    pub index: usize,
+    /// Level the part belongs to, 0 being full resolution
+    pub level: usize,
+    /// Pixel column of the level the part starts at
+    pub x: u32,
+    /// Pixel row of the level the part starts at
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Where the parts written by [`write_split`] go
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SplitManifest {
+    pub parts: Vec<SplitPart>,
+}
+
+impl SplitManifest {
+    /// Parts of level `level`
+    pub fn level(&self, level: usize) -> impl Iterator<Item = &SplitPart> {
+        self.parts.iter().filter(move |p| p.level == level)
+    }
+
+    /// The manifest as text, a header line followed by an
+    /// `index level x y width height` line per part
+    pub fn to_text(&self) -> String {
+        let mut out = String::from("index level x y width height\n");
+        for p in &self.parts {
+            let _ = writeln!(
+                out,
+                "{} {} {} {} {} {}",
+                p.index, p.level, p.x, p.y, p.width, p.height
+            );
+        }
+        out
+    }
+}
+
+/// Write `levels`, full resolution first, as classic TIFFs of at most
+/// `max_file_bytes` each, e.g. `u32::MAX`.
+///
+/// `create` makes the encoder of each part, so it decides where parts are
+/// written and with which options. Each part is the only level of its file.
+/// Returns the manifest and the writers of the parts, in order.
+///
+/// Fails with [`TiffError::LimitsExceeded`] if a single tile doesn't fit.
+pub fn write_split<W: Write>(
+    levels: Vec<Level>,
+    max_file_bytes: u64,
+    mut create: impl FnMut(&SplitPart) -> TiffResult<CogEncoder<W>>,
+) -> TiffResult<(SplitManifest, Vec<W>)> {
+    let mut manifest = SplitManifest::default();
+    let mut writers = Vec::new();
+    for (i_level, mut level) in levels.into_iter().enumerate() {
+        let (across, down) = partition(&level, max_file_bytes)?;
+        let mut tiles: Vec<_> = core::mem::take(&mut level.tiles)
+            .into_iter()
+            .map(Some)
+            .collect();
+        for row in (0..level.tiles_down()).step_by(down as usize) {
+            for col in (0..level.tiles_across()).step_by(across as usize) {
+                let rows = down.min(level.tiles_down() - row);
+                let cols = across.min(level.tiles_across() - col);
+                let (x, y) = (col * level.tile_width, row * level.tile_height);
+                let part = SplitPart {
+                    index: manifest.parts.len(),
+                    level: i_level,
+                    x,
+                    y,
+                    width: (cols * level.tile_width).min(level.width - x),
+                    height: (rows * level.tile_height).min(level.height - y),
+                };
+                let mut part_tiles = Vec::with_capacity((rows * cols) as usize);
+                for r in row..row + rows {
+                    for c in col..col + cols {
+                        let i = (r * level.tiles_across() + c) as usize;
+                        part_tiles.extend(tiles[i].take());
+                    }
+                }
+                let mut extra_tags = level.extra_tags.clone();
+                if i_level > 0 {
+                    extra_tags.retain(|tag, _| !overview_disallowed(tag));
+                } else {
+                    shift_georeference(&mut extra_tags, x, y)?;
+                }
+                let part_level = Level {
+                    width: part.width,
+                    height: part.height,
+                    tile_width: level.tile_width,
+                    tile_height: level.tile_height,
+                    color_type: level.color_type,
+                    sample_format: level.sample_format,
+                    tiles: part_tiles,
+                    extra_tags,
+                };
+                let mut encoder = create(&part)?;
+                encoder.write_level(part_level, true)?;
+                writers.push(encoder.finish()?);
+                manifest.parts.push(part);
+            }
+        }
+    }
+    Ok((manifest, writers))
+}
+
+/// Tiles across and down of the parts of `level`: all of it if it fits,
+/// otherwise as many full rows of tiles as fit, or a part of a single row
+fn partition(level: &Level, max_file_bytes: u64) -> TiffResult<(u32, u32)> {
+    let dir = level.directory(false, false, Predictor::None)?;
+    let tile_bytes = level.tiles.first().map_or(0, |t| t.len() as u64);
+    let fits = |across: u32, down: u32| {
+        part_len(&dir, tile_bytes, (across * down) as usize) <= max_file_bytes
+    };
+    let (across, down) = (level.tiles_across(), level.tiles_down());
+    if fits(across, 1) {
+        Ok((across, largest(down, |d| fits(across, d))))
+    } else if fits(1, 1) {
+        Ok((largest(across, |a| fits(a, 1)), 1))
+    } else {
+        Err(TiffError::LimitsExceeded)
+    }
+}
+
+/// Upper bound on the size of a classic TIFF holding `n_tiles` tiles of
+/// `tile_bytes`, with the tags of `dir`
+fn part_len(dir: &EncodedDirectory, tile_bytes: u64, n_tiles: usize) -> u64 {
+    let mut dir = dir.clone();
+    dir.insert(Tag::TileOffsets, entry(&vec![0u32; n_tiles][..]));
+    dir.insert(Tag::TileByteCounts, entry(&vec![0u32; n_tiles][..]));
+    8 + ifd_len(&dir, false) + TAG_SLACK + n_tiles as u64 * tile_bytes
+}
+
+/// Largest `n` in `1..=max` for which `fits`, given that it holds for 1 and
+/// holding for `n` means it holds for anything smaller
+fn largest(max: u32, fits: impl Fn(u32) -> bool) -> u32 {
+    let (mut lo, mut hi) = (1, max);
+    while lo < hi {
+        let mid = lo + (hi - lo).div_ceil(2);
+        if fits(mid) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    lo
+}
+
+/// Move the raster origin of tie points and transformations to pixel `(x, y)`
+fn shift_georeference(tags: &mut EncodedDirectory, x: u32, y: u32) -> TiffResult<()> {
+    let (x, y) = (f64::from(x), f64::from(y));
+    if let Some(tiepoints) = tags.get_mut(&Tag::ModelTiepointTag) {
+        // (column, row, z, x, y, z) each
+        let mut values = Vec::<f64>::try_from(&*tiepoints)?;
+        for tiepoint in values.chunks_exact_mut(6) {
+            tiepoint[0] -= x;
+            tiepoint[1] -= y;
+        }
+        *tiepoints = entry(&values[..]);
+    }
+    if let Some(transformation) = tags.get_mut(&Tag::ModelTransformationTag) {
+        // row-major 4x4, mapping (column, row, z, 1)
+        let mut m = Vec::<f64>::try_from(&*transformation)?;
+        if m.len() == 16 {
+            for row in 0..3 {
+                m[4 * row + 3] += m[4 * row] * x + m[4 * row + 1] * y;
+            }
+            *transformation = entry(&m[..]);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        decoder::DecoderOptions,
+        encoder::CogLayout,
+        structs::{geo, Tiff},
+    };
+
+    /// Square gray level in tiles of 16x16, each filled with its index
+    fn level(size: u32) -> Level {
+        let mut extra_tags = EncodedDirectory::new();
+        extra_tags.insert(
+            Tag::ModelTiepointTag,
+            entry(&[0.0f64, 0.0, 0.0, 1000.0, 2000.0, 0.0][..]),
+        );
+        extra_tags.insert(Tag::ModelPixelScaleTag, entry(&[2.0f64, 2.0, 0.0][..]));
+        Level {
+            extra_tags,
+            ..Level::gray(size, size, 16, |i, _| i as u8)
+        }
+    }
+
+    fn split(max_file_bytes: u64) -> (SplitManifest, Vec<Vec<u8>>) {
+        write_split(vec![level(40), level(16)], max_file_bytes, |_| {
+            CogEncoder::new(Vec::new(), CogLayout::HeaderFirst)
+        })
+        .unwrap()
+    }
+
+    /// Value of the first tile of a file
+    async fn first_tile(file: &Vec<u8>) -> (u8, Tiff) {
+        let tiff = Tiff::read(file, &DecoderOptions::default()).await.unwrap();
+        let offsets = tiff.ifds[0].require_tag_value(&Tag::TileOffsets).unwrap();
+        (file[offsets.get_u64(0).unwrap() as usize], tiff)
+    }
+
+    #[tokio::test]
+    async fn test_split() {
+        // everything fits
+        let (manifest, files) = split(u64::from(u32::MAX));
+        assert_eq!(manifest.parts.len(), 2);
+        assert_eq!(files.len(), 2);
+
+        // room for a row of 3 tiles, but not two
+        let dir = level(40).directory(false, false, Predictor::None).unwrap();
+        let max = part_len(&dir, 256, 3);
+        let (manifest, files) = split(max);
+        let origins: Vec<_> = manifest.level(0).map(|p| (p.x, p.y, p.height)).collect();
+        assert_eq!(origins, [(0, 0, 16), (0, 16, 16), (0, 32, 8)]);
+        assert_eq!(manifest.level(1).count(), 1);
+        for (part, file) in manifest.parts.iter().zip(&files) {
+            assert!(file.len() as u64 <= max);
+            let (first, tiff) = first_tile(file).await;
+            let transform = geo::geotransform(&tiff.ifds[0]).unwrap();
+            if part.level == 0 {
+                // tiles hold their index in the full level
+                assert_eq!(u32::from(first), part.y / 16 * 3);
+                assert_eq!(transform.unwrap()[3], 2000.0 - 2.0 * f64::from(part.y));
+            } else {
+                assert!(transform.is_none());
+            }
+        }
+        assert_eq!(
+            manifest.to_text(),
+            "index level x y width height\n0 0 0 0 40 16\n1 0 0 16 40 16\n2 0 0 32 40 8\n3 1 0 0 16 16\n"
+        );
+
+        // not even a row fits
+        let (manifest, files) = split(part_len(&dir, 256, 2));
+        let origins: Vec<_> = manifest.level(0).map(|p| (p.x, p.y, p.width)).collect();
+        assert_eq!(origins[..3], [(0, 0, 32), (32, 0, 8), (0, 16, 32)]);
+        assert_eq!(first_tile(&files[1]).await.0, 2);
+
+        let Err(TiffError::LimitsExceeded) = write_split(
+            vec![level(40)],
+            100,
+            |_| -> TiffResult<CogEncoder<Vec<u8>>> { unreachable!() },
+        ) else {
+            panic!("a single tile doesn't fit");
+        };
+    }
+}
@@ -0,0 +1,40 @@
+use std::io::Write;
+
+use crate::error::TiffResult;
+
+/// Writer that keeps track of how many bytes were written, so offsets can be
+/// computed without needing `Seek`
+pub struct TiffWriter<W> {
+    writer: W,
+    offset: u64,
+}
+
+impl<W: Write> TiffWriter<W> {
+    pub fn new(writer: W) -> Self {
+        TiffWriter { writer, offset: 0 }
+    }
+
+    /// Number of bytes written so far
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> TiffResult<()> {
+        self.writer.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Pad with a zero byte if needed, so the next write starts on a word
+    /// boundary
+    pub fn pad_word_boundary(&mut self) -> TiffResult<()> {
+        if self.offset % 2 == 1 {
+            self.write_bytes(&[0])?;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
@@ -1,23 +1,28 @@
-use std::error::Error;
-use std::fmt;
-use std::fmt::write;
-use std::fmt::Display;
-use std::io;
-use std::str;
-use std::string;
-use std::sync;
-use std::sync::Arc;
-
+use alloc::{string, string::String, vec::Vec};
+use core::error::Error;
+use core::fmt;
+use core::str;
+
+#[cfg(feature = "std")]
+use crate::encoder::HeaderReport;
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use core::fmt::Display;
+#[cfg(feature = "std")]
 use jpeg::UnsupportedFeature;
+use miniz_oxide::inflate::DecompressError;
 use weezl::LzwError;
 
 use crate::{
+    decoder::SampleType,
+    io,
     structs::{
         tags::{
-            CompressionMethod, PhotometricInterpretation, PlanarConfiguration, SampleFormat, Tag,
-            TagType,
+            CompressionMethod, PhotometricInterpretation, PlanarConfiguration, Predictor,
+            SampleFormat, Tag, TagType,
         },
-        BufferedEntry,
+        EntryPreview, Rect,
     },
     ChunkType, ColorType,
 };
@@ -43,6 +48,9 @@ pub enum TiffError {
 
     /// The image does not support the requested operation
     UsageError(UsageError),
+
+    /// The operation was cancelled through its cancellation flag
+    Cancelled,
 }
 
 /// The image is not formatted properly.
@@ -58,7 +66,7 @@ pub enum TiffFormatError {
     TiffSignatureNotFound,
     TiffSignatureInvalid,
     ImageFileDirectoryNotFound,
-    InconsistentSizesEncountered(BufferedEntry),
+    InconsistentSizesEncountered(EntryPreview),
     UnexpectedCompressedData {
         actual_bytes: usize,
         required_bytes: usize,
@@ -73,19 +81,33 @@ pub enum TiffFormatError {
     RequiredTagNotFound(Tag),
     UnknownPredictor(u16),
     UnknownPlanarConfiguration(u16),
-    ByteExpected(BufferedEntry),
-    SignedByteExpected(BufferedEntry),
-    SignedShortExpected(BufferedEntry),
-    UnsignedIntegerExpected(BufferedEntry),
-    SignedIntegerExpected(BufferedEntry),
-    FloatExpected(BufferedEntry),
-    AsciiExpected(BufferedEntry),
+    ByteExpected(EntryPreview),
+    SignedByteExpected(EntryPreview),
+    SignedShortExpected(EntryPreview),
+    UnsignedIntegerExpected(EntryPreview),
+    SignedIntegerExpected(EntryPreview),
+    FloatExpected(EntryPreview),
+    AsciiExpected(EntryPreview),
     Format(String),
     RequiredTagEmpty(Tag),
     StripTileTagConflict,
     CycleInOffsets,
+    /// SubIfds are nested deeper than the configured maximum depth
+    IfdTooDeep(usize),
+    /// An IFD has more SubIfds than the configured maximum
+    TooManySubIfds {
+        count: u64,
+        max: usize,
+    },
+    #[cfg(feature = "std")]
     JpegDecoder(JpegDecoderError),
     SamplesPerPixelIsZero,
+    /// A chunk offsets or byte counts tag doesn't have a value for each chunk
+    InconsistentChunkCount {
+        tag: Tag,
+        count: u64,
+        expected: u64,
+    },
 }
 
 impl fmt::Display for TiffFormatError {
@@ -95,7 +117,7 @@ impl fmt::Display for TiffFormatError {
             TiffSignatureNotFound => write!(fmt, "TIFF signature not found."),
             TiffSignatureInvalid => write!(fmt, "TIFF signature invalid."),
             ImageFileDirectoryNotFound => write!(fmt, "Image file directory not found."),
-            InconsistentSizesEncountered(val) => write!(fmt, "Inconsistent sizes encountered. {val:?}"),
+            InconsistentSizesEncountered(val) => write!(fmt, "Inconsistent sizes encountered: {val}."),
             UnexpectedCompressedData {
                 actual_bytes,
                 required_bytes,
@@ -128,23 +150,27 @@ impl fmt::Display for TiffFormatError {
             UnknownPlanarConfiguration(ref planar_config) =>  {
                 write!(fmt, "Unknown planar configuration “{}” encountered", planar_config)
             }
-            ByteExpected(ref val) => write!(fmt, "Expected byte, {:?} found.", val),
-            SignedByteExpected(ref val) => write!(fmt, "Expected signed byte, {:?} found.", val),
-            SignedShortExpected(ref val) => write!(fmt, "Expected signed short, {:?} found.", val),
+            ByteExpected(ref val) => write!(fmt, "Expected byte, {} found.", val),
+            SignedByteExpected(ref val) => write!(fmt, "Expected signed byte, {} found.", val),
+            SignedShortExpected(ref val) => write!(fmt, "Expected signed short, {} found.", val),
             UnsignedIntegerExpected(ref val) => {
-                write!(fmt, "Expected unsigned integer, {:?} found.", val)
+                write!(fmt, "Expected unsigned integer, {} found.", val)
             }
             SignedIntegerExpected(ref val) => {
-                write!(fmt, "Expected signed integer, {:?} found.", val)
+                write!(fmt, "Expected signed integer, {} found.", val)
             }
-            FloatExpected(val) => write!(fmt, "Expected float or double, {val:?} found"),
-            AsciiExpected(val) => write!(fmt, "Expected Ascii, Byte or Undefined, {val:?} found"),
+            FloatExpected(val) => write!(fmt, "Expected float or double, {val} found"),
+            AsciiExpected(val) => write!(fmt, "Expected Ascii, Byte or Undefined, {val} found"),
             Format(ref val) => write!(fmt, "Invalid format: {:?}.", val),
             RequiredTagEmpty(ref val) => write!(fmt, "Required tag {:?} was empty.", val),
             StripTileTagConflict => write!(fmt, "File should contain either (StripByteCounts and StripOffsets) or (TileByteCounts and TileOffsets), other combination was found."),
             CycleInOffsets => write!(fmt, "File contained a cycle in the list of IFDs"),
+            IfdTooDeep(max) => write!(fmt, "SubIfds are nested deeper than {max} levels"),
+            TooManySubIfds { count, max } => write!(fmt, "IFD has {count} SubIfds, at most {max} are allowed"),
+            #[cfg(feature = "std")]
             JpegDecoder(ref error) => write!(fmt, "{}",  error),
             SamplesPerPixelIsZero => write!(fmt, "Samples per pixel is zero"),
+            InconsistentChunkCount { tag, count, expected } => write!(fmt, "{tag:?} has {count} values, expected one for each of {expected} chunks"),
         }
     }
 }
@@ -172,9 +198,13 @@ pub enum TiffUnsupportedError {
     UnsupportedColorType(ColorType),
     UnsupportedBitsPerChannel(u8),
     UnsupportedPlanarConfig(Option<PlanarConfiguration>),
+    UnsupportedPredictor(Predictor),
     UnsupportedDataType,
     UnsupportedInterpretation(PhotometricInterpretation),
+    #[cfg(feature = "std")]
     UnsupportedJpegFeature(UnsupportedFeature),
+    UnsupportedLercFeature(&'static str),
+    UnsupportedFaxFeature(&'static str),
     MisalignedTileBoundaries,
 }
 
@@ -223,6 +253,9 @@ impl fmt::Display for TiffUnsupportedError {
             UnsupportedPlanarConfig(config) => {
                 write!(fmt, "Unsupported planar configuration “{:?}”.", config)
             }
+            UnsupportedPredictor(predictor) => {
+                write!(fmt, "Predictor {:?} is unsupported.", predictor)
+            }
             UnsupportedDataType => write!(fmt, "Unsupported data type."),
             UnsupportedInterpretation(interpretation) => {
                 write!(
@@ -231,9 +264,12 @@ impl fmt::Display for TiffUnsupportedError {
                     interpretation
                 )
             }
+            #[cfg(feature = "std")]
             UnsupportedJpegFeature(ref unsupported_feature) => {
                 write!(fmt, "Unsupported JPEG feature {:?}", unsupported_feature)
             }
+            UnsupportedLercFeature(feature) => write!(fmt, "Unsupported LERC feature: {feature}"),
+            UnsupportedFaxFeature(feature) => write!(fmt, "Unsupported fax feature: {feature}"),
             MisalignedTileBoundaries => write!(fmt, "Tile rows are not aligned to byte boundaries"),
         }
     }
@@ -252,7 +288,7 @@ pub enum UsageError {
     PredictorUnavailable,
     /// IFDs should be handled separately, not read into a BufferedEntry
     /// Correct usage:
-    /// ```
+    /// ```ignore
     /// # use tiff2::ifd::Ifd;
     /// # use tiff2::ByteOrder;
     /// let ifd = Ifd::default() ;
@@ -268,6 +304,47 @@ pub enum UsageError {
     IfdReadIntoEntry,
     DuplicateTagData,
     RequiredTagNotLoaded(Tag, TagType, u64, u64),
+    InvalidChunkCount {
+        actual: usize,
+        expected: usize,
+    },
+    InvalidChunkLength {
+        actual: usize,
+        expected: usize,
+    },
+    LevelAfterLastLevel,
+    LastLevelMissing,
+    /// The IFD of this overview level has not been read into the decoder yet
+    OverviewNotLoaded(u8),
+    /// No mask was added to the decoder for this overview level
+    MaskNotLoaded(u8),
+    /// The requested region doesn't lie within the image
+    RegionOutOfBounds(Rect),
+    /// The requested window doesn't lie within the image, and its edge policy
+    /// doesn't allow that
+    WindowOutOfBounds {
+        x: i64,
+        y: i64,
+        width: u32,
+        height: u32,
+    },
+    /// A sample index that pixels of the image don't have
+    InvalidBand(u16),
+    /// Output rows would be longer than the requested row stride
+    RowStrideTooSmall {
+        stride: usize,
+        row_len: usize,
+    },
+    /// The value at this index of a paged entry wasn't loaded yet
+    PageNotLoaded(usize),
+    /// Samples were requested as another type than that of the image
+    SampleTypeMismatch {
+        requested: SampleType,
+        actual: SampleType,
+    },
+    /// The IFDs of a COG don't fit in the header budget of its encoder
+    #[cfg(feature = "std")]
+    HeaderBudgetExceeded(HeaderReport),
 }
 
 impl fmt::Display for UsageError {
@@ -293,7 +370,23 @@ impl fmt::Display for UsageError {
             PredictorUnavailable => write!(fmt, "The requested predictor is not available"),
             IfdReadIntoEntry => write!(fmt, "sub-IFDs should be added to an ifd through `ifd.insert_ifd_from_buf`, not read as an Entry"),
             DuplicateTagData => write!(fmt, "Tried loading tag data into an IFD, while it was already present"),
-            RequiredTagNotLoaded(tag, tag_type, count, offset) => write!(fmt, "Required tag {tag:?} with type {tag_type:?} and count {count} not loaded from {offset:?}")
+            RequiredTagNotLoaded(tag, tag_type, count, offset) => write!(fmt, "Required tag {tag:?} with type {tag_type:?} and count {count} not loaded from {offset:?}"),
+            InvalidChunkCount { actual, expected } => write!(fmt, "Got {actual} chunks, expected {expected}"),
+            InvalidChunkLength { actual, expected } => write!(fmt, "Got a chunk of {actual} bytes, expected {expected}"),
+            LevelAfterLastLevel => write!(fmt, "Tried adding a level after the last level was written"),
+            LastLevelMissing => write!(fmt, "The encoder was finished without writing a last level"),
+            OverviewNotLoaded(level) => write!(fmt, "Overview level {level} is not loaded"),
+            MaskNotLoaded(level) => write!(fmt, "The mask of overview level {level} is not loaded"),
+            RegionOutOfBounds(rect) => write!(fmt, "Region {rect:?} is not within the image"),
+            WindowOutOfBounds { x, y, width, height } => write!(fmt, "Window of {width}x{height} pixels at ({x}, {y}) is not within the image"),
+            InvalidBand(band) => write!(fmt, "Pixels have no sample with index {band}"),
+            RowStrideTooSmall { stride, row_len } => write!(fmt, "Row stride of {stride} bytes is less than a row of {row_len} bytes"),
+            PageNotLoaded(index) => write!(fmt, "The page holding value {index} is not loaded"),
+            SampleTypeMismatch { requested, actual } => {
+                write!(fmt, "Samples of type {actual:?} were requested as {requested:?}")
+            }
+            #[cfg(feature = "std")]
+            HeaderBudgetExceeded(ref report) => write!(fmt, "{report}"),
         }
     }
 }
@@ -315,6 +408,7 @@ impl fmt::Display for TiffError {
             TiffError::TryLockError => {
                 write!(fmt, "Poisoned lock encountered, good luck recovering!")
             }
+            TiffError::Cancelled => write!(fmt, "The operation was cancelled"),
         }
     }
 }
@@ -329,6 +423,7 @@ impl Error for TiffError {
             TiffError::IntSizeError => "Platform or format size limits exceeded",
             TiffError::UsageError(..) => "Invalid usage",
             TiffError::TryLockError => "Lock acquiring failed",
+            TiffError::Cancelled => "Operation cancelled",
         }
     }
 
@@ -346,13 +441,29 @@ impl From<io::Error> for TiffError {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> From<std::sync::TryLockError<T>> for TiffError {
     fn from(err: std::sync::TryLockError<T>) -> Self {
-        println!("undocumented error: {err}");
+        log::warn!("undocumented error: {err}");
         TiffError::TryLockError
     }
 }
 
+#[cfg(feature = "std")]
+impl<T> From<std::sync::PoisonError<T>> for TiffError {
+    fn from(err: std::sync::PoisonError<T>) -> Self {
+        log::warn!("undocumented error: {err}");
+        TiffError::TryLockError
+    }
+}
+
+#[cfg(feature = "object_store")]
+impl From<object_store::Error> for TiffError {
+    fn from(err: object_store::Error) -> TiffError {
+        TiffError::IoError(err.into())
+    }
+}
+
 impl From<str::Utf8Error> for TiffError {
     fn from(_err: str::Utf8Error) -> TiffError {
         TiffError::FormatError(TiffFormatError::InvalidTag)
@@ -383,8 +494,8 @@ impl From<UsageError> for TiffError {
     }
 }
 
-impl From<std::num::TryFromIntError> for TiffError {
-    fn from(_err: std::num::TryFromIntError) -> TiffError {
+impl From<core::num::TryFromIntError> for TiffError {
+    fn from(_err: core::num::TryFromIntError) -> TiffError {
         TiffError::IntSizeError
     }
 }
@@ -399,11 +510,22 @@ impl From<LzwError> for TiffError {
     }
 }
 
+impl From<DecompressError> for TiffError {
+    fn from(err: DecompressError) -> TiffError {
+        TiffError::FormatError(TiffFormatError::Format(alloc::format!(
+            "Deflate compressed data corrupted: {:?}",
+            err.status
+        )))
+    }
+}
+
+#[cfg(feature = "std")]
 #[derive(Debug, Clone)]
 pub struct JpegDecoderError {
     inner: Arc<jpeg::Error>,
 }
 
+#[cfg(feature = "std")]
 impl JpegDecoderError {
     fn new(error: jpeg::Error) -> Self {
         Self {
@@ -412,27 +534,36 @@ impl JpegDecoderError {
     }
 }
 
+#[cfg(feature = "std")]
 impl PartialEq for JpegDecoderError {
     fn eq(&self, other: &Self) -> bool {
         Arc::ptr_eq(&self.inner, &other.inner)
     }
 }
 
+#[cfg(feature = "std")]
 impl Display for JpegDecoderError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.inner.fmt(f)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<JpegDecoderError> for TiffError {
     fn from(error: JpegDecoderError) -> Self {
         TiffError::FormatError(TiffFormatError::JpegDecoder(error))
     }
 }
 
+#[cfg(feature = "std")]
 impl From<jpeg::Error> for TiffError {
     fn from(error: jpeg::Error) -> Self {
-        JpegDecoderError::new(error).into()
+        match error {
+            jpeg::Error::Unsupported(feature) => {
+                TiffUnsupportedError::UnsupportedJpegFeature(feature).into()
+            }
+            error => JpegDecoderError::new(error).into(),
+        }
     }
 }
 
@@ -0,0 +1,488 @@
+//! Decoding of CCITT Group 4 (T.6) fax compressed chunks.
+//!
+//! Group 4 codes each row of a bilevel image by where its color changes,
+//! relative to the changes of the row above, falling back to run lengths
+//! where that doesn't work out. The first row is coded relative to an
+//! imaginary white row.
+//!
+//! Like libtiff, decoded rows hold a set bit for every black pixel of the
+//! code, whatever the `PhotometricInterpretation`, which then says how to
+//! display them as for uncompressed data. Uncompressed mode, an extension of
+//! the code that encoders hardly use, is not supported.
+
+use alloc::{format, vec, vec::Vec};
+
+use crate::error::{TiffError, TiffFormatError, TiffResult, TiffUnsupportedError};
+
+fn corrupt(what: &str) -> TiffError {
+    TiffFormatError::Format(format!("Group 4 fax data corrupted: {what}")).into()
+}
+
+/// Bits of the longest run length code
+const MAX_CODE_BITS: u8 = 13;
+
+/// Codes of white runs, as `(code, bits, run)`
+const WHITE_CODES: [(u16, u8, u16); 104] = [
+    (0b00110101, 8, 0),
+    (0b000111, 6, 1),
+    (0b0111, 4, 2),
+    (0b1000, 4, 3),
+    (0b1011, 4, 4),
+    (0b1100, 4, 5),
+    (0b1110, 4, 6),
+    (0b1111, 4, 7),
+    (0b10011, 5, 8),
+    (0b10100, 5, 9),
+    (0b00111, 5, 10),
+    (0b01000, 5, 11),
+    (0b001000, 6, 12),
+    (0b000011, 6, 13),
+    (0b110100, 6, 14),
+    (0b110101, 6, 15),
+    (0b101010, 6, 16),
+    (0b101011, 6, 17),
+    (0b0100111, 7, 18),
+    (0b0001100, 7, 19),
+    (0b0001000, 7, 20),
+    (0b0010111, 7, 21),
+    (0b0000011, 7, 22),
+    (0b0000100, 7, 23),
+    (0b0101000, 7, 24),
+    (0b0101011, 7, 25),
+    (0b0010011, 7, 26),
+    (0b0100100, 7, 27),
+    (0b0011000, 7, 28),
+    (0b00000010, 8, 29),
+    (0b00000011, 8, 30),
+    (0b00011010, 8, 31),
+    (0b00011011, 8, 32),
+    (0b00010010, 8, 33),
+    (0b00010011, 8, 34),
+    (0b00010100, 8, 35),
+    (0b00010101, 8, 36),
+    (0b00010110, 8, 37),
+    (0b00010111, 8, 38),
+    (0b00101000, 8, 39),
+    (0b00101001, 8, 40),
+    (0b00101010, 8, 41),
+    (0b00101011, 8, 42),
+    (0b00101100, 8, 43),
+    (0b00101101, 8, 44),
+    (0b00000100, 8, 45),
+    (0b00000101, 8, 46),
+    (0b00001010, 8, 47),
+    (0b00001011, 8, 48),
+    (0b01010010, 8, 49),
+    (0b01010011, 8, 50),
+    (0b01010100, 8, 51),
+    (0b01010101, 8, 52),
+    (0b00100100, 8, 53),
+    (0b00100101, 8, 54),
+    (0b01011000, 8, 55),
+    (0b01011001, 8, 56),
+    (0b01011010, 8, 57),
+    (0b01011011, 8, 58),
+    (0b01001010, 8, 59),
+    (0b01001011, 8, 60),
+    (0b00110010, 8, 61),
+    (0b00110011, 8, 62),
+    (0b00110100, 8, 63),
+    (0b11011, 5, 64),
+    (0b10010, 5, 128),
+    (0b010111, 6, 192),
+    (0b0110111, 7, 256),
+    (0b00110110, 8, 320),
+    (0b00110111, 8, 384),
+    (0b01100100, 8, 448),
+    (0b01100101, 8, 512),
+    (0b01101000, 8, 576),
+    (0b01100111, 8, 640),
+    (0b011001100, 9, 704),
+    (0b011001101, 9, 768),
+    (0b011010010, 9, 832),
+    (0b011010011, 9, 896),
+    (0b011010100, 9, 960),
+    (0b011010101, 9, 1024),
+    (0b011010110, 9, 1088),
+    (0b011010111, 9, 1152),
+    (0b011011000, 9, 1216),
+    (0b011011001, 9, 1280),
+    (0b011011010, 9, 1344),
+    (0b011011011, 9, 1408),
+    (0b010011000, 9, 1472),
+    (0b010011001, 9, 1536),
+    (0b010011010, 9, 1600),
+    (0b011000, 6, 1664),
+    (0b010011011, 9, 1728),
+    (0b00000001000, 11, 1792),
+    (0b00000001100, 11, 1856),
+    (0b00000001101, 11, 1920),
+    (0b000000010010, 12, 1984),
+    (0b000000010011, 12, 2048),
+    (0b000000010100, 12, 2112),
+    (0b000000010101, 12, 2176),
+    (0b000000010110, 12, 2240),
+    (0b000000010111, 12, 2304),
+    (0b000000011100, 12, 2368),
+    (0b000000011101, 12, 2432),
+    (0b000000011110, 12, 2496),
+    (0b000000011111, 12, 2560),
+];
+
+/// Codes of black runs, as `(code, bits, run)`
+const BLACK_CODES: [(u16, u8, u16); 104] = [
+    (0b0000110111, 10, 0),
+    (0b010, 3, 1),
+    (0b11, 2, 2),
+    (0b10, 2, 3),
+    (0b011, 3, 4),
+    (0b0011, 4, 5),
+    (0b0010, 4, 6),
+    (0b00011, 5, 7),
+    (0b000101, 6, 8),
+    (0b000100, 6, 9),
+    (0b0000100, 7, 10),
+    (0b0000101, 7, 11),
+    (0b0000111, 7, 12),
+    (0b00000100, 8, 13),
+    (0b00000111, 8, 14),
+    (0b000011000, 9, 15),
+    (0b0000010111, 10, 16),
+    (0b0000011000, 10, 17),
+    (0b0000001000, 10, 18),
+    (0b00001100111, 11, 19),
+    (0b00001101000, 11, 20),
+    (0b00001101100, 11, 21),
+    (0b00000110111, 11, 22),
+    (0b00000101000, 11, 23),
+    (0b00000010111, 11, 24),
+    (0b00000011000, 11, 25),
+    (0b000011001010, 12, 26),
+    (0b000011001011, 12, 27),
+    (0b000011001100, 12, 28),
+    (0b000011001101, 12, 29),
+    (0b000001101000, 12, 30),
+    (0b000001101001, 12, 31),
+    (0b000001101010, 12, 32),
+    (0b000001101011, 12, 33),
+    (0b000011010010, 12, 34),
+    (0b000011010011, 12, 35),
+    (0b000011010100, 12, 36),
+    (0b000011010101, 12, 37),
+    (0b000011010110, 12, 38),
+    (0b000011010111, 12, 39),
+    (0b000001101100, 12, 40),
+    (0b000001101101, 12, 41),
+    (0b000011011010, 12, 42),
+    (0b000011011011, 12, 43),
+    (0b000001010100, 12, 44),
+    (0b000001010101, 12, 45),
+    (0b000001010110, 12, 46),
+    (0b000001010111, 12, 47),
+    (0b000001100100, 12, 48),
+    (0b000001100101, 12, 49),
+    (0b000001010010, 12, 50),
+    (0b000001010011, 12, 51),
+    (0b000000100100, 12, 52),
+    (0b000000110111, 12, 53),
+    (0b000000111000, 12, 54),
+    (0b000000100111, 12, 55),
+    (0b000000101000, 12, 56),
+    (0b000001011000, 12, 57),
+    (0b000001011001, 12, 58),
+    (0b000000101011, 12, 59),
+    (0b000000101100, 12, 60),
+    (0b000001011010, 12, 61),
+    (0b000001100110, 12, 62),
+    (0b000001100111, 12, 63),
+    (0b0000001111, 10, 64),
+    (0b000011001000, 12, 128),
+    (0b000011001001, 12, 192),
+    (0b000001011011, 12, 256),
+    (0b000000110011, 12, 320),
+    (0b000000110100, 12, 384),
+    (0b000000110101, 12, 448),
+    (0b0000001101100, 13, 512),
+    (0b0000001101101, 13, 576),
+    (0b0000001001010, 13, 640),
+    (0b0000001001011, 13, 704),
+    (0b0000001001100, 13, 768),
+    (0b0000001001101, 13, 832),
+    (0b0000001110010, 13, 896),
+    (0b0000001110011, 13, 960),
+    (0b0000001110100, 13, 1024),
+    (0b0000001110101, 13, 1088),
+    (0b0000001110110, 13, 1152),
+    (0b0000001110111, 13, 1216),
+    (0b0000001010010, 13, 1280),
+    (0b0000001010011, 13, 1344),
+    (0b0000001010100, 13, 1408),
+    (0b0000001010101, 13, 1472),
+    (0b0000001011010, 13, 1536),
+    (0b0000001011011, 13, 1600),
+    (0b0000001100100, 13, 1664),
+    (0b0000001100101, 13, 1728),
+    (0b00000001000, 11, 1792),
+    (0b00000001100, 11, 1856),
+    (0b00000001101, 11, 1920),
+    (0b000000010010, 12, 1984),
+    (0b000000010011, 12, 2048),
+    (0b000000010100, 12, 2112),
+    (0b000000010101, 12, 2176),
+    (0b000000010110, 12, 2240),
+    (0b000000010111, 12, 2304),
+    (0b000000011100, 12, 2368),
+    (0b000000011101, 12, 2432),
+    (0b000000011110, 12, 2496),
+    (0b000000011111, 12, 2560),
+];
+
+/// `run << 4 | bits` of the run length code the next [`MAX_CODE_BITS`] bits
+/// start with, 0 where there is none
+type Lookup = [u16; 1 << MAX_CODE_BITS];
+
+const fn lookup(codes: &[(u16, u8, u16)]) -> Lookup {
+    let mut lookup = [0; 1 << MAX_CODE_BITS];
+    let mut i = 0;
+    while i < codes.len() {
+        let (code, bits, run) = codes[i];
+        let shift = MAX_CODE_BITS - bits;
+        let start = (code as usize) << shift;
+        let mut j = 0;
+        while j < 1 << shift {
+            lookup[start + j] = run << 4 | bits as u16;
+            j += 1;
+        }
+        i += 1;
+    }
+    lookup
+}
+
+static WHITE: Lookup = lookup(&WHITE_CODES);
+static BLACK: Lookup = lookup(&BLACK_CODES);
+
+/// Reads bits most significant first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    /// The next `n` bits, at most 25, with zeros past the end
+    fn peek(&self, n: u8) -> u32 {
+        let byte = self.pos / 8;
+        let mut bits = 0u32;
+        for i in 0..4 {
+            bits = bits << 8 | u32::from(self.data.get(byte + i).copied().unwrap_or(0));
+        }
+        (bits << (self.pos % 8)) >> (32 - n)
+    }
+
+    fn consume(&mut self, n: u8) -> TiffResult<()> {
+        self.pos += usize::from(n);
+        if self.pos > self.data.len() * 8 {
+            return Err(corrupt("cut off"));
+        }
+        Ok(())
+    }
+
+    /// Whether all that is left is padding of the last byte
+    fn at_end(&self) -> bool {
+        self.pos / 8 + 1 >= self.data.len() && self.peek(8) == 0
+    }
+
+    /// Whether an end-of-line code follows, the first half of an
+    /// end-of-block code in Group 4
+    fn at_eol(&self) -> bool {
+        self.peek(12) == 1
+    }
+}
+
+/// How the next changes of a row are coded
+enum Mode {
+    /// The row keeps its color up to b2, the second change of the row above
+    Pass,
+    /// Two runs follow, the first of the current color
+    Horizontal,
+    /// The color changes at b1, the next change of the row above, plus this
+    Vertical(i8),
+}
+
+fn mode(bits: &mut Bits) -> TiffResult<Mode> {
+    let (mode, len) = match bits.peek(7) {
+        0b1000000..=0b1111111 => (Mode::Vertical(0), 1),
+        0b0110000..=0b0111111 => (Mode::Vertical(1), 3),
+        0b0100000..=0b0101111 => (Mode::Vertical(-1), 3),
+        0b0010000..=0b0011111 => (Mode::Horizontal, 3),
+        0b0001000..=0b0001111 => (Mode::Pass, 4),
+        0b0000110 | 0b0000111 => (Mode::Vertical(2), 6),
+        0b0000100 | 0b0000101 => (Mode::Vertical(-2), 6),
+        0b0000011 => (Mode::Vertical(3), 7),
+        0b0000010 => (Mode::Vertical(-3), 7),
+        0b0000001 => {
+            return Err(TiffUnsupportedError::UnsupportedFaxFeature("uncompressed mode").into())
+        }
+        _ => return Err(corrupt("invalid mode code")),
+    };
+    bits.consume(len)?;
+    Ok(mode)
+}
+
+/// Length of a run: makeup codes of multiples of 64, then a terminating
+/// code of less than 64
+fn run(bits: &mut Bits, lookup: &Lookup) -> TiffResult<usize> {
+    let mut len = 0usize;
+    loop {
+        let entry = lookup[bits.peek(MAX_CODE_BITS) as usize];
+        if entry == 0 {
+            return Err(corrupt("invalid run length code"));
+        }
+        bits.consume((entry & 0xf) as u8)?;
+        let run = usize::from(entry >> 4);
+        len = len.saturating_add(run);
+        if run < 64 {
+            return Ok(len);
+        }
+    }
+}
+
+/// Set the bits of pixels `start..end` of a packed row
+fn fill(row: &mut [u8], start: usize, end: usize) {
+    for x in start..end {
+        row[x / 8] |= 0x80 >> (x % 8);
+    }
+}
+
+/// Decode `rows` packed rows of `width` pixels.
+///
+/// Rows after the end of the data, or after an end-of-block code, are
+/// white: encoders may leave out trailing white rows, and the last strip of
+/// an image holds fewer rows than the others.
+pub fn decode_g4(data: &[u8], width: usize, rows: usize) -> TiffResult<Vec<u8>> {
+    let stride = width.div_ceil(8);
+    let mut out = vec![0u8; stride * rows];
+    if width == 0 {
+        return Ok(out);
+    }
+    let mut bits = Bits { data, pos: 0 };
+    // where the color changes, the first change being to black
+    let mut reference = Vec::new();
+    let mut current = Vec::new();
+    for row in out.chunks_exact_mut(stride) {
+        if bits.at_end() || bits.at_eol() {
+            break;
+        }
+        current.clear();
+        // a0 is an imaginary white pixel before the first one until the
+        // first change is coded
+        let (mut a0, mut start, mut black) = (0, true, false);
+        // index of b1 in `reference`
+        let mut i = 0;
+        while start || a0 < width {
+            // b1 is the first change of the row above after a0 to the
+            // color opposite of a0, b2 the change after it
+            while i > 0 && reference[i - 1] > a0 {
+                i -= 1;
+            }
+            while i < reference.len()
+                && ((!start && reference[i] <= a0) || i % 2 != usize::from(black))
+            {
+                i += 1;
+            }
+            let b1 = reference.get(i).copied().unwrap_or(width);
+            let b2 = reference.get(i + 1).copied().unwrap_or(width);
+            match mode(&mut bits)? {
+                Mode::Pass => a0 = b2,
+                Mode::Vertical(delta) => {
+                    a0 = b1
+                        .checked_add_signed(isize::from(delta))
+                        .filter(|&a1| a1 <= width && (start || a1 >= a0))
+                        .ok_or_else(|| corrupt("change outside of the row"))?;
+                    current.push(a0);
+                    black = !black;
+                }
+                Mode::Horizontal => {
+                    let (first, second) = if black {
+                        (&BLACK, &WHITE)
+                    } else {
+                        (&WHITE, &BLACK)
+                    };
+                    let a1 = a0.saturating_add(run(&mut bits, first)?);
+                    let a2 = a1.saturating_add(run(&mut bits, second)?);
+                    if a2 > width {
+                        return Err(corrupt("run past the end of the row"));
+                    }
+                    current.extend([a1, a2]);
+                    a0 = a2;
+                }
+            }
+            start = false;
+        }
+        let mut x = 0;
+        for (n, &change) in current.iter().enumerate() {
+            if n % 2 == 1 {
+                fill(row, x, change);
+            }
+            x = change;
+        }
+        if current.len() % 2 == 1 {
+            fill(row, x, width);
+        }
+        core::mem::swap(&mut reference, &mut current);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 2000x6 image coded by libtiff 4, with runs needing every kind of code
+    const IMAGE: &[u8] = b"\
+        \x9d\x8a\x80\x80\x59\x98\x03\xe2\x6e\x0a\x06\xa0\x49\x04\x53\x94\xe5\x39\x4e\x53\
+        \x94\xe5\x39\x4e\x53\x94\xe5\x39\x4e\x53\x94\xe5\x39\x4e\x53\x94\xe5\x39\x40\x34\
+        \x18\xa4\x92\x49\x24\x92\x49\x24\x92\x49\x24\x92\x49\x24\x92\x45\xd6\x36\xc5\x07\
+        \x1b\x62\x83\x8d\xb1\x41\x98\x66\x19\x86\x61\x98\x66\x19\x86\x61\x98\x66\x19\x86\
+        \x61\x98\x66\x19\x86\x61\x98\x66\x19\x86\x61\x98\x66\x19\x86\x61\x98\x66\x19\x86\
+        \x61\x98\x66\x19\x86\x61\x98\x66\x1c\x00\x40\x04\
+    ";
+
+    fn is_black(x: usize, y: usize) -> bool {
+        match y {
+            1 => (100..1950).contains(&x),
+            2 => (3..70).contains(&x) || (500..1000).contains(&x),
+            3 => x < 60 && x.is_multiple_of(3),
+            4 => x < 60 && (x + 1).is_multiple_of(3),
+            5 => x < 300 && (x * x + 5) % 7 < 3,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        // two more rows than coded, which are white
+        let decoded = decode_g4(IMAGE, 2000, 8).unwrap();
+        assert_eq!(decoded.len(), 250 * 8);
+        for y in 0..8 {
+            for x in 0..2000 {
+                let bit = decoded[y * 250 + x / 8] & (0x80 >> (x % 8)) != 0;
+                assert_eq!(bit, is_black(x, y), "pixel ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let Err(TiffError::FormatError(_)) = decode_g4(&IMAGE[..40], 2000, 6) else {
+            panic!("data cut off within a row");
+        };
+        let Err(TiffError::FormatError(_)) = decode_g4(IMAGE, 1000, 6) else {
+            panic!("runs past the end of the row");
+        };
+        let Err(TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedFaxFeature(_))) =
+            decode_g4(&[0x02, 0x00], 16, 1)
+        else {
+            panic!("uncompressed mode");
+        };
+    }
+}
@@ -0,0 +1,413 @@
+//! Deterministic entry points for fuzzing, and the seeds to start from.
+//!
+//! Each function takes the raw bytes of a fuzz input, runs one part of the
+//! decoder on them synchronously, and panics only when an invariant breaks,
+//! e.g. when an entry doesn't survive being written and read back. Errors on
+//! malformed input are expected and ignored by the targets of the `fuzz`
+//! crate, which call these with [`limits`] so a single input can't take all
+//! memory.
+//!
+//! Fuzzing needs nightly and `cargo-fuzz`, which builds with the address
+//! sanitizer by default:
+//! ```sh
+//! cd fuzz
+//! cargo run --bin seed_corpus
+//! cargo +nightly fuzz run parse_file corpus/parse_file -- -max_total_time=60
+//! cargo +nightly fuzz run decompress -s memory
+//! ```
+
+use std::collections::BTreeSet;
+
+use futures_lite::future;
+
+use crate::{
+    decoder::{decode_chunk_data, DecoderOptions, Limits},
+    encoder::directory::{encode_ifd, entry, EncodedDirectory},
+    error::TiffResult,
+    structs::{
+        tags::{
+            CompressionMethod, PhotometricInterpretation, PlanarConfiguration, Predictor,
+            SampleFormat,
+        },
+        value::Value,
+        BufferedEntry, ChunkMetaData, EntryPreview, Ifd, IfdEntry, Image, Rect, StripDecodeState,
+        Tag, TagType, Tiff, TileAttributes,
+    },
+    test_util::{FixtureIfd, NextIfd, TiffBuilder},
+    ByteOrder, ChunkType,
+};
+
+/// Names of the fuzz targets, which are also the names of the directories
+/// of their corpora
+pub const TARGETS: [&str; 4] = ["parse_file", "convert_entry", "decompress", "plan_region"];
+
+/// Number of chunks [`plan_region`] checks at most, so huge layouts of tiny
+/// chunks don't just make the fuzzer slow
+const MAX_PLANNED_CHUNKS: usize = 4096;
+
+/// Limits small enough that no input makes the fuzzer run out of memory
+pub fn limits() -> Limits {
+    Limits {
+        max_ifd_entries: 256,
+        max_tag_bytes: 1 << 16,
+        max_chunk_bytes: 1 << 20,
+        max_total_bytes: 1 << 22,
+    }
+}
+
+/// Fields taken from the front of a fuzz input, zero once it runs out
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn u8(&mut self) -> u8 {
+        let (&first, rest) = self.0.split_first().unwrap_or((&0, &[]));
+        self.0 = rest;
+        first
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes([self.u8(), self.u8()])
+    }
+
+    /// One of `options`, picked by the next byte
+    fn pick<T: Copy>(&mut self, options: &[T]) -> T {
+        options[usize::from(self.u8()) % options.len()]
+    }
+
+    fn rest(self) -> &'a [u8] {
+        self.0
+    }
+}
+
+/// Read the header and all IFDs of a file, and the chunk layout of each image
+/// in it
+pub fn parse_file(data: &[u8], limits: &Limits) -> TiffResult<()> {
+    let options = DecoderOptions {
+        limits: limits.clone(),
+        ifd_concurrency: 1,
+        ..Default::default()
+    };
+    let mut tiff = future::block_on(Tiff::read(&data.to_vec(), &options))?;
+    let byte_order = tiff.byte_order();
+    for ifd in std::mem::take(&mut tiff.ifds) {
+        let Ok(image) = Image::from_ifd(ifd, byte_order) else {
+            continue;
+        };
+        let chunk_meta = image.chunk_meta();
+        let _ = image.color_type();
+        // planning any window mustn't panic, whatever the layout
+        let rect = Rect::new(
+            0,
+            0,
+            chunk_meta.image_width.min(256),
+            chunk_meta.image_height.min(256),
+        );
+        for i_chunk in chunk_meta.chunks_covering(&rect) {
+            assert!(chunk_meta.chunk_rect(i_chunk).is_some());
+            let _ = chunk_meta.chunk_dims(i_chunk);
+        }
+    }
+    Ok(())
+}
+
+/// Convert an entry of the type in the first 3 bytes, a type code and a size
+/// hint for vendor types, to everything it could be, and check it reads back
+/// the same after writing it to an IFD
+pub fn convert_entry(data: &[u8], limits: &Limits) -> TiffResult<()> {
+    let mut input = Input(data);
+    let tag_type = TagType::from_u16_with_size_hint(input.u16(), input.u8());
+    let data = input.rest();
+    if tag_type.size() == 0 {
+        return Ok(());
+    }
+    let count = data.len() / tag_type.size();
+    let entry = BufferedEntry {
+        tag_type,
+        count: count as u64,
+        data: data[..count * tag_type.size()].to_vec(),
+    };
+    let _ = EntryPreview::from(&entry).to_string();
+    let _ = entry.get_u64(0);
+    let _ = u64::try_from(&entry);
+    let _ = i64::try_from(&entry);
+    let _ = f64::try_from(&entry);
+    let _ = Vec::<f64>::try_from(&entry);
+    let _ = <&str>::try_from(&entry);
+    let _ = <&[u16]>::try_from(&entry);
+    let _ = <&[u64]>::try_from(&entry);
+    if let Ok(value) = Value::try_from(entry.clone()) {
+        let _ = value.to_string();
+    }
+
+    if matches!(
+        tag_type,
+        TagType::Unknown(..) | TagType::IFD | TagType::IFD8
+    ) || count == 0
+    {
+        // kept as an offset when read, whatever their size
+        return Ok(());
+    }
+    for (byte_order, bigtiff) in [
+        (ByteOrder::LittleEndian, false),
+        (ByteOrder::BigEndian, true),
+    ] {
+        let mut dir = EncodedDirectory::new();
+        dir.insert(Tag::Software, entry.clone());
+        let buf = encode_ifd(&dir, 0, 0, byte_order, bigtiff)?;
+        let ifd = Ifd::from_buffer(&buf, byte_order, bigtiff, limits)?;
+        match ifd.get_tag(&Tag::Software) {
+            Some(IfdEntry::Value(read)) => assert_eq!(read, &entry),
+            Some(IfdEntry::Offset {
+                tag_type: read_type,
+                count: read_count,
+                ..
+            }) => assert_eq!((*read_type, *read_count), (tag_type, count as u64)),
+            None => panic!("entry of {tag_type:?} got lost"),
+        }
+    }
+    Ok(())
+}
+
+/// Decode a chunk of the compression and layout in the first 9 bytes
+pub fn decompress(data: &[u8], limits: &Limits) -> TiffResult<()> {
+    let mut input = Input(data);
+    let compression_method = input.pick(&[
+        CompressionMethod::None,
+        CompressionMethod::Deflate,
+        CompressionMethod::PackBits,
+        CompressionMethod::Fax4,
+        CompressionMethod::LERC,
+        CompressionMethod::JPEG,
+        CompressionMethod::ZSTD,
+        CompressionMethod::LZMA,
+    ]);
+    let tile_width = usize::from(input.u8() % 4 + 1) * 16;
+    let tile_length = usize::from(input.u8() % 4 + 1) * 16;
+    let bits_per_sample = input.pick(&[1, 8, 16, 32, 64]);
+    let samples = u16::from(input.u8() % 4 + 1);
+    let sample_format = input.pick(&[SampleFormat::Uint, SampleFormat::Int, SampleFormat::IEEEFP]);
+    let predictor = input.pick(&[
+        Predictor::None,
+        Predictor::Horizontal,
+        Predictor::FloatingPoint,
+    ]);
+    let photometric_interpretation = input.pick(&[
+        PhotometricInterpretation::BlackIsZero,
+        PhotometricInterpretation::RGB,
+        PhotometricInterpretation::YCbCr,
+    ]);
+    let byte_order = input.pick(&[ByteOrder::LittleEndian, ByteOrder::BigEndian]);
+    let chunk_meta = ChunkMetaData {
+        byte_order,
+        image_width: tile_width as u32,
+        image_height: tile_length as u32,
+        bits_per_sample,
+        samples,
+        sample_format,
+        photometric_interpretation,
+        compression_method,
+        predictor,
+        jpeg_tables: None,
+        planar_config: PlanarConfiguration::Chunky,
+        chunk_type: ChunkType::Tile,
+        strip_decoder: None,
+        tile_attributes: Some(TileAttributes {
+            image_width: tile_width,
+            image_height: tile_length,
+            tile_width,
+            tile_length,
+        }),
+        ycbcr_subsampling: (1, 1),
+        nodata: None,
+    };
+    decode_chunk_data(input.rest().to_vec(), 0, &chunk_meta, limits)?;
+    Ok(())
+}
+
+/// Check that the chunks planned for a window of the layout in the first 15
+/// bytes are those that overlap it
+pub fn plan_region(data: &[u8]) {
+    let mut input = Input(data);
+    let image_width = u32::from(input.u16());
+    let image_height = u32::from(input.u16());
+    let chunk_width = u32::from(input.u16()).max(1);
+    let chunk_height = u32::from(input.u16()).max(1);
+    let tiled = input.u8() % 2 == 0;
+    let samples = u16::from(input.u8() % 4 + 1);
+    let planar_config = input.pick(&[PlanarConfiguration::Chunky, PlanarConfiguration::Planar]);
+    let rect = Rect::new(
+        u32::from(input.u16()),
+        u32::from(input.u16()),
+        u32::from(input.u16()),
+        u32::from(input.u16()),
+    );
+    let chunk_meta = ChunkMetaData {
+        byte_order: ByteOrder::LittleEndian,
+        image_width,
+        image_height,
+        bits_per_sample: 8,
+        samples,
+        sample_format: SampleFormat::Uint,
+        photometric_interpretation: PhotometricInterpretation::BlackIsZero,
+        compression_method: CompressionMethod::None,
+        predictor: Predictor::None,
+        jpeg_tables: None,
+        planar_config,
+        chunk_type: if tiled {
+            ChunkType::Tile
+        } else {
+            ChunkType::Strip
+        },
+        strip_decoder: (!tiled).then_some(StripDecodeState {
+            rows_per_strip: chunk_height,
+        }),
+        tile_attributes: tiled.then_some(TileAttributes {
+            image_width: image_width as usize,
+            image_height: image_height as usize,
+            tile_width: chunk_width as usize,
+            tile_length: chunk_height as usize,
+        }),
+        ycbcr_subsampling: (1, 1),
+        nodata: None,
+    };
+    if !rect.fits_in(image_width, image_height) {
+        return;
+    }
+    let mut overlapping = BTreeSet::new();
+    for i_chunk in 0.. {
+        let Some((_, chunk_rect)) = chunk_meta.chunk_rect(i_chunk) else {
+            break;
+        };
+        if i_chunk == MAX_PLANNED_CHUNKS {
+            return;
+        }
+        if chunk_rect.intersection(&rect).is_some() {
+            overlapping.insert(i_chunk);
+        }
+    }
+    let planned: BTreeSet<_> = chunk_meta.chunks_covering(&rect).into_iter().collect();
+    assert_eq!(planned, overlapping, "{chunk_meta:?} {rect:?}");
+}
+
+/// Inputs to start fuzzing `target` from, made with the [`TiffBuilder`] for
+/// [`parse_file`]. Empty for targets not in [`TARGETS`].
+pub fn seed_corpus(target: &str) -> Vec<Vec<u8>> {
+    match target {
+        "parse_file" => file_seeds(),
+        "convert_entry" => [
+            (TagType::SHORT, entry(&[1u16, 2, 3][..])),
+            (TagType::ASCII, entry("GDAL")),
+            (TagType::DOUBLE, entry(&[0.5f64, -1.0][..])),
+            (TagType::RATIONAL, entry(&[72u32, 1][..])),
+        ]
+        .into_iter()
+        .map(|(tag_type, entry)| {
+            let mut seed = tag_type.to_u16().to_le_bytes().to_vec();
+            seed.push(0);
+            seed.extend_from_slice(&entry.data);
+            seed
+        })
+        .collect(),
+        "decompress" => {
+            let zeros = miniz_oxide::deflate::compress_to_vec_zlib(&[0; 16 * 16], 6);
+            vec![
+                // a 16x16 8-bit gray tile, uncompressed and deflated
+                [&[0, 0, 0, 1, 0, 0, 0, 0, 0][..], &[0; 16 * 16]].concat(),
+                [&[1, 0, 0, 1, 0, 0, 0, 0, 0][..], &zeros].concat(),
+                // PackBits, repeating 0 256 times in runs of 128
+                vec![2, 0, 0, 1, 0, 0, 0, 0, 0, 0x81, 0, 0x81, 0],
+            ]
+        }
+        "plan_region" => [
+            // 300x200 image in 256x256 tiles, window across the first two
+            [300u16, 200, 256, 256, 0, 0, 0, 200, 10, 100, 50],
+            // planar RGB in strips of 16 rows
+            [64, 64, 64, 16, 1, 2, 1, 0, 8, 64, 32],
+        ]
+        .into_iter()
+        .map(|fields| {
+            let mut seed = Vec::new();
+            for (i, field) in fields.into_iter().enumerate() {
+                if (4..7).contains(&i) {
+                    seed.push(field as u8);
+                } else {
+                    seed.extend_from_slice(&field.to_le_bytes());
+                }
+            }
+            seed
+        })
+        .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Small files with the odd layouts the decoder was hardened against
+fn file_seeds() -> Vec<Vec<u8>> {
+    let image = |offset: u64| {
+        FixtureIfd::new()
+            .entry(Tag::ImageWidth, &4u32)
+            .entry(Tag::ImageLength, &4u32)
+            .entry(Tag::BitsPerSample, &8u16)
+            .entry(Tag::PhotometricInterpretation, &1u16)
+            .entry(Tag::TileWidth, &16u32)
+            .entry(Tag::TileLength, &16u32)
+            .entry(Tag::TileOffsets, &u32::try_from(offset).unwrap())
+            .entry(Tag::TileByteCounts, &256u32)
+    };
+    let mut seeds = Vec::new();
+    for (byte_order, bigtiff) in [
+        (ByteOrder::LittleEndian, false),
+        (ByteOrder::BigEndian, true),
+    ] {
+        let mut builder = TiffBuilder::new(byte_order, bigtiff);
+        let offset = builder.push_data(&[0; 256]);
+        builder.push_ifd(image(offset));
+        builder.push_ifd(image(offset).entry(Tag::NewSubfileType, &1u32));
+        seeds.push(builder.build().unwrap());
+    }
+    // unsorted tags, offsets running past the end and an IFD chain in a cycle
+    let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false).unaligned();
+    let offset = builder.push_data(&[0; 256]);
+    builder.push_ifd(
+        image(offset)
+            .unsorted()
+            .truncated(Tag::StripByteCounts, &[4u32, 4][..], 1)
+            .next_ifd(NextIfd::Ifd(0)),
+    );
+    seeds.push(builder.build().unwrap());
+    seeds
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seed_corpus() {
+        let limits = limits();
+        for target in TARGETS {
+            let seeds = seed_corpus(target);
+            assert!(!seeds.is_empty(), "{target}");
+            for seed in seeds {
+                match target {
+                    "parse_file" => {
+                        let _ = parse_file(&seed, &limits);
+                    }
+                    "convert_entry" => convert_entry(&seed, &limits).unwrap(),
+                    "decompress" => decompress(&seed, &limits).unwrap(),
+                    _ => plan_region(&seed),
+                }
+            }
+        }
+        // the well-formed files parse
+        parse_file(&seed_corpus("parse_file")[0], &limits).unwrap();
+        // and nothing panics on inputs cut short
+        for target_seed in seed_corpus("decompress") {
+            for len in 0..target_seed.len() {
+                let _ = decompress(&target_seed[..len], &limits);
+                plan_region(&target_seed[..len]);
+                let _ = convert_entry(&target_seed[..len], &limits);
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! I/O traits used by the parsing core.
+//!
+//! With the `std` feature this is just `std::io`. Without it, this provides the
+//! small subset of `std::io` that tag and IFD parsing needs: reading exact
+//! amounts of bytes from in-memory buffers.
+
+#[cfg(feature = "std")]
+pub use std::io::{Cursor, Error, ErrorKind, Read, Result};
+
+#[cfg(not(feature = "std"))]
+pub use self::core_io::{Cursor, Error, ErrorKind, Read, Result};
+
+#[cfg(not(feature = "std"))]
+mod core_io {
+    use core::fmt;
+
+    /// Subset of `std::io::ErrorKind` that can occur when reading from memory
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        UnexpectedEof,
+    }
+
+    /// Stand-in for `std::io::Error`
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+    }
+
+    impl Error {
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Error { kind }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.kind {
+                ErrorKind::UnexpectedEof => write!(fmt, "failed to fill whole buffer"),
+            }
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// Stand-in for `std::io::Read`
+    pub trait Read {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf)? {
+                    0 => return Err(ErrorKind::UnexpectedEof.into()),
+                    n => buf = &mut buf[n..],
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = buf.len().min(self.len());
+            let (a, b) = self.split_at(n);
+            buf[..n].copy_from_slice(a);
+            *self = b;
+            Ok(n)
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    /// Stand-in for `std::io::Cursor`
+    #[derive(Debug, Default, Clone)]
+    pub struct Cursor<T> {
+        inner: T,
+        pos: u64,
+    }
+
+    impl<T> Cursor<T> {
+        pub fn new(inner: T) -> Self {
+            Cursor { inner, pos: 0 }
+        }
+
+        pub fn into_inner(self) -> T {
+            self.inner
+        }
+
+        pub fn get_ref(&self) -> &T {
+            &self.inner
+        }
+
+        pub fn position(&self) -> u64 {
+            self.pos
+        }
+
+        pub fn set_position(&mut self, pos: u64) {
+            self.pos = pos;
+        }
+    }
+
+    impl<T: AsRef<[u8]>> Read for Cursor<T> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let data = self.inner.as_ref();
+            let start = usize::try_from(self.pos)
+                .unwrap_or(usize::MAX)
+                .min(data.len());
+            let n = (&data[start..]).read(buf)?;
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+}
@@ -0,0 +1,703 @@
+//! Decoding of LERC (34887) chunks, as written by GDAL and Esri.
+//!
+//! LERC quantizes samples to within a maximum error, lossless for integers
+//! with a maximum error of 0.5, and bit-packs the quantized values of each
+//! block of pixels. A chunk holds one Lerc2 blob, with all samples of a pixel
+//! as its depth. GDAL's LERC_DEFLATE and LERC_ZSTD compress that blob again,
+//! as its `LercParameters` tag says. Both are recognized from the start of the
+//! chunk, as a Lerc2 blob, zlib stream and Zstandard frame are easy to tell
+//! apart.
+//!
+//! Versions 2 to 6 of Lerc2 are supported, except for Huffman coding, which
+//! LERC uses for lossless 8-bit images and, since version 6, lossless floats.
+//! Pixels the mask of a blob leaves out are NaN for float samples and 0 for
+//! integer ones, like GDAL decodes them.
+
+use alloc::{format, vec, vec::Vec};
+
+use crate::{
+    error::{TiffError, TiffFormatError, TiffResult, TiffUnsupportedError},
+    ByteOrder,
+};
+
+fn corrupt(what: &str) -> TiffError {
+    TiffFormatError::Format(format!("LERC blob corrupted: {what}")).into()
+}
+
+fn unsupported(feature: &'static str) -> TiffError {
+    TiffUnsupportedError::UnsupportedLercFeature(feature).into()
+}
+
+/// Type of the samples of a blob
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DataType {
+    Char,
+    Byte,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl DataType {
+    fn from_i32(dt: i32) -> Option<Self> {
+        use DataType::*;
+        [Char, Byte, Short, UShort, Int, UInt, Float, Double]
+            .get(usize::try_from(dt).ok()?)
+            .copied()
+    }
+
+    /// Size of a sample in bytes
+    pub fn size(self) -> usize {
+        match self {
+            DataType::Char | DataType::Byte => 1,
+            DataType::Short | DataType::UShort => 2,
+            DataType::Int | DataType::UInt | DataType::Float => 4,
+            DataType::Double => 8,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, DataType::Float | DataType::Double)
+    }
+
+    /// Type the offset of a block is stored as, given the two bits of its
+    /// flags saying how much narrower than the samples it is
+    fn narrowed(self, code: u8) -> TiffResult<Self> {
+        use DataType::*;
+        let code = usize::from(code);
+        let dt = match self {
+            Short | Int => DataType::from_i32(self as i32 - code as i32),
+            UShort | UInt => DataType::from_i32(self as i32 - 2 * code as i32),
+            Float => [Float, Short, Byte, Byte].get(code).copied(),
+            Double => [Double, Float, Int, Short].get(code).copied(),
+            Char | Byte => Some(self),
+        };
+        dt.ok_or_else(|| corrupt("offset type"))
+    }
+
+    /// `value` converted to this type and back, like it is stored
+    fn round(self, value: f64) -> f64 {
+        match self {
+            DataType::Char => f64::from(value as i8),
+            DataType::Byte => f64::from(value as u8),
+            DataType::Short => f64::from(value as i16),
+            DataType::UShort => f64::from(value as u16),
+            DataType::Int => f64::from(value as i32),
+            DataType::UInt => f64::from(value as u32),
+            DataType::Float => f64::from(value as f32),
+            DataType::Double => value,
+        }
+    }
+
+    /// Write `value` as a sample of this type
+    fn write(self, value: f64, out: &mut Vec<u8>, byte_order: ByteOrder) {
+        macro_rules! put {
+            ($type:ty) => {{
+                let v = value as $type;
+                match byte_order {
+                    ByteOrder::LittleEndian => out.extend_from_slice(&v.to_le_bytes()),
+                    ByteOrder::BigEndian => out.extend_from_slice(&v.to_be_bytes()),
+                }
+            }};
+        }
+        match self {
+            DataType::Char => put!(i8),
+            DataType::Byte => put!(u8),
+            DataType::Short => put!(i16),
+            DataType::UShort => put!(u16),
+            DataType::Int => put!(i32),
+            DataType::UInt => put!(u32),
+            DataType::Float => put!(f32),
+            DataType::Double => put!(f64),
+        }
+    }
+}
+
+/// A decoded blob
+#[derive(Debug)]
+pub struct Decoded {
+    pub width: u32,
+    pub height: u32,
+    /// Samples per pixel
+    pub depth: u32,
+    pub data_type: DataType,
+    /// Pixel-interleaved samples in the requested byte order
+    pub data: Vec<u8>,
+}
+
+/// Little-endian reads from the blob
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> TiffResult<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(corrupt("cut off"));
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> TiffResult<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> TiffResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> TiffResult<i32> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> TiffResult<f64> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    /// A count of `len` bytes
+    fn count(&mut self, len: usize) -> TiffResult<usize> {
+        Ok(match len {
+            1 => usize::from(self.u8()?),
+            2 => usize::from(u16::from_le_bytes(self.array()?)),
+            _ => usize::try_from(u32::from_le_bytes(self.array()?))?,
+        })
+    }
+
+    fn value(&mut self, data_type: DataType) -> TiffResult<f64> {
+        Ok(match data_type {
+            DataType::Char => f64::from(self.u8()? as i8),
+            DataType::Byte => f64::from(self.u8()?),
+            DataType::Short => f64::from(i16::from_le_bytes(self.array()?)),
+            DataType::UShort => f64::from(u16::from_le_bytes(self.array()?)),
+            DataType::Int => f64::from(self.i32()?),
+            DataType::UInt => f64::from(u32::from_le_bytes(self.array()?)),
+            DataType::Float => f64::from(f32::from_le_bytes(self.array()?)),
+            DataType::Double => self.f64()?,
+        })
+    }
+}
+
+struct Header {
+    version: i32,
+    width: usize,
+    height: usize,
+    depth: usize,
+    n_valid: usize,
+    micro_block: usize,
+    data_type: DataType,
+    max_z_error: f64,
+    z_min: f64,
+    z_max: f64,
+    /// value written for samples that were no data, and the value to restore
+    no_data: Option<(f64, f64)>,
+}
+
+/// Fletcher's checksum of Lerc2, over the blob past the checksum itself
+fn fletcher32(data: &[u8]) -> u32 {
+    let (mut sum1, mut sum2) = (0xffffu32, 0xffffu32);
+    let (words, straggler) = data.split_at(data.len() & !1);
+    for block in words.chunks(2 * 359) {
+        for word in block.chunks_exact(2) {
+            sum1 += u32::from(word[0]) << 8;
+            sum1 += u32::from(word[1]);
+            sum2 += sum1;
+        }
+        sum1 = (sum1 & 0xffff) + (sum1 >> 16);
+        sum2 = (sum2 & 0xffff) + (sum2 >> 16);
+    }
+    if let [byte] = straggler {
+        sum1 += u32::from(*byte) << 8;
+        sum2 += sum1;
+    }
+    sum1 = (sum1 & 0xffff) + (sum1 >> 16);
+    sum2 = (sum2 & 0xffff) + (sum2 >> 16);
+    (sum2 << 16) | sum1
+}
+
+fn read_header(blob: &[u8]) -> TiffResult<(Header, Cursor<'_>)> {
+    let mut r = Cursor { data: blob };
+    match r.take(6) {
+        Ok(b"Lerc2 ") => {}
+        Ok(b"CntZIm") => return Err(unsupported("LERC 1")),
+        _ => return Err(corrupt("no Lerc2 signature")),
+    }
+    let version = r.i32()?;
+    if !(2..=6).contains(&version) {
+        return Err(unsupported("Lerc2 version"));
+    }
+    let checksum = if version >= 3 {
+        Some(u32::from_le_bytes(r.array()?))
+    } else {
+        None
+    };
+    let mut int = || -> TiffResult<usize> { Ok(usize::try_from(r.i32()?)?) };
+    let height = int()?;
+    let width = int()?;
+    let depth = if version >= 4 { int()? } else { 1 };
+    let n_valid = int()?;
+    let micro_block = int()?;
+    let blob_size = int()?;
+    let data_type = DataType::from_i32(r.i32()?).ok_or_else(|| corrupt("data type"))?;
+    if version >= 6 && r.i32()? != 0 {
+        return Err(unsupported("more than one band per chunk"));
+    }
+    let pass_no_data = version >= 6 && r.take(4)?[0] != 0;
+    let max_z_error = r.f64()?;
+    let z_min = r.f64()?;
+    let z_max = r.f64()?;
+    let no_data = if version >= 6 {
+        let no_data = (r.f64()?, r.f64()?);
+        pass_no_data.then_some(no_data)
+    } else {
+        None
+    };
+
+    if width == 0 || height == 0 || depth == 0 || micro_block == 0 {
+        return Err(corrupt("dimensions"));
+    }
+    if n_valid > width.saturating_mul(height) {
+        return Err(corrupt("number of valid pixels"));
+    }
+    if blob_size > blob.len() || blob_size < blob.len() - r.data.len() {
+        return Err(corrupt("blob size"));
+    }
+    if let Some(checksum) = checksum {
+        // everything after the checksum field
+        if fletcher32(&blob[14..blob_size]) != checksum {
+            return Err(corrupt("checksum mismatch"));
+        }
+    }
+    let header = Header {
+        version,
+        width,
+        height,
+        depth,
+        n_valid,
+        micro_block,
+        data_type,
+        max_z_error,
+        z_min,
+        z_max,
+        no_data,
+    };
+    r.data = &r.data[..r.data.len() - (blob.len() - blob_size)];
+    Ok((header, r))
+}
+
+/// Undo the run-length encoding of the mask, into `len` bytes
+fn unrle(r: &mut Cursor<'_>, len: usize) -> TiffResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    loop {
+        match i16::from_le_bytes(r.array()?) {
+            -32768 => break,
+            n @ 1.. => out.extend_from_slice(r.take(n as usize)?),
+            n => {
+                let byte = r.u8()?;
+                out.resize(out.len() + usize::from(n.unsigned_abs()), byte);
+            }
+        }
+        if out.len() > len {
+            return Err(corrupt("mask too long"));
+        }
+    }
+    if out.len() != len {
+        return Err(corrupt("mask too short"));
+    }
+    Ok(out)
+}
+
+/// Unpack `n` values of `bits` bits. Since version 3 values are packed from
+/// the least significant bit up, before that from the most significant bit
+/// down within little-endian 32-bit words, the last word only holding the
+/// bytes that are needed.
+fn unstuff(r: &mut Cursor<'_>, n: usize, bits: u32, version: i32) -> TiffResult<Vec<u32>> {
+    if bits == 0 {
+        return Ok(vec![0; n]);
+    }
+    let total_bits = n
+        .checked_mul(bits as usize)
+        .ok_or_else(|| corrupt("size"))?;
+    let bytes = r.take(total_bits.div_ceil(8))?;
+    let mask = (1u64 << bits) - 1;
+    let mut padded = bytes.to_vec();
+    if version >= 3 {
+        padded.resize(bytes.len() + 8, 0);
+        Ok((0..n)
+            .map(|i| {
+                let bit = i * bits as usize;
+                let window = u64::from_le_bytes(padded[bit / 8..bit / 8 + 8].try_into().unwrap());
+                ((window >> (bit % 8)) & mask) as u32
+            })
+            .collect())
+    } else {
+        let n_words = total_bits.div_ceil(32);
+        padded.resize(4 * n_words, 0);
+        let mut words: Vec<u32> = padded
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        if let Some(last) = words.last_mut() {
+            // the tail bytes were shifted down to drop the unused ones
+            *last <<= 8 * (4 * n_words - bytes.len());
+        }
+        words.push(0);
+        Ok((0..n)
+            .map(|i| {
+                let bit = i * bits as usize;
+                let window = u64::from(words[bit / 32]) << 32 | u64::from(words[bit / 32 + 1]);
+                ((window >> (64 - bit % 32 - bits as usize)) & mask) as u32
+            })
+            .collect())
+    }
+}
+
+/// Unpack the quantized values of a block, possibly through a lookup table
+fn bit_unstuff(r: &mut Cursor<'_>, max_count: usize, version: i32) -> TiffResult<Vec<u32>> {
+    let flags = r.u8()?;
+    let count_len = match flags >> 6 {
+        0 => 4,
+        1 => 2,
+        2 => 1,
+        _ => return Err(corrupt("count size")),
+    };
+    let bits = u32::from(flags & 31);
+    let n = r.count(count_len)?;
+    if n > max_count {
+        return Err(corrupt("too many values in block"));
+    }
+    if flags & 32 == 0 {
+        return unstuff(r, n, bits, version);
+    }
+    if bits == 0 {
+        return Err(corrupt("lookup table without bits"));
+    }
+    // the table leaves out its first value, 0
+    let n_lut = usize::from(r.u8()?)
+        .checked_sub(1)
+        .ok_or_else(|| corrupt("empty lookup table"))?;
+    let mut lut = vec![0];
+    lut.extend(unstuff(r, n_lut, bits, version)?);
+    let index_bits = usize::BITS - n_lut.leading_zeros();
+    unstuff(r, n, index_bits, version)?
+        .into_iter()
+        .map(|i| {
+            lut.get(i as usize)
+                .copied()
+                .ok_or_else(|| corrupt("lookup"))
+        })
+        .collect()
+}
+
+/// Decode a Lerc2 blob, writing samples in `byte_order`. Blobs that would
+/// decode to more than `max_len` bytes fail with
+/// [`TiffError::LimitsExceeded`].
+pub fn decode(blob: &[u8], byte_order: ByteOrder, max_len: u64) -> TiffResult<Decoded> {
+    let (h, mut r) = read_header(blob)?;
+    let n_pixels = h
+        .width
+        .checked_mul(h.height)
+        .ok_or(TiffError::LimitsExceeded)?;
+    let n_samples = n_pixels
+        .checked_mul(h.depth)
+        .ok_or(TiffError::LimitsExceeded)?;
+    if n_samples as u64 * h.data_type.size() as u64 > max_len {
+        return Err(TiffError::LimitsExceeded);
+    }
+
+    let mask_len = usize::try_from(r.i32()?)?;
+    let mask = if h.n_valid == 0 || h.n_valid == n_pixels {
+        if mask_len != 0 {
+            return Err(corrupt("mask of an image without one"));
+        }
+        None
+    } else if mask_len == 0 {
+        return Err(corrupt("mask missing"));
+    } else {
+        let mut mask_data = Cursor {
+            data: r.take(mask_len)?,
+        };
+        Some(unrle(&mut mask_data, n_pixels.div_ceil(8))?)
+    };
+    let is_valid = |k: usize| match &mask {
+        _ if h.n_valid == 0 => false,
+        Some(mask) => mask[k / 8] & (128 >> (k % 8)) != 0,
+        None => true,
+    };
+
+    let depth = h.depth;
+    let mut values = vec![0f64; n_samples];
+    let fill = |values: &mut [f64], constants: &dyn Fn(usize) -> f64| {
+        for k in (0..n_pixels).filter(|&k| is_valid(k)) {
+            for d in 0..depth {
+                values[k * depth + d] = constants(d);
+            }
+        }
+    };
+    if h.n_valid == 0 {
+        // nothing to decode
+    } else if h.z_min == h.z_max {
+        fill(&mut values, &|_| h.z_min);
+    } else {
+        let ranges = if h.version >= 4 {
+            let mins = (0..depth)
+                .map(|_| r.value(h.data_type))
+                .collect::<TiffResult<Vec<_>>>()?;
+            let maxs = (0..depth)
+                .map(|_| r.value(h.data_type))
+                .collect::<TiffResult<Vec<_>>>()?;
+            Some((mins, maxs))
+        } else {
+            None
+        };
+        match &ranges {
+            Some((mins, maxs)) if mins == maxs => fill(&mut values, &|d| mins[d]),
+            _ => {
+                let z_max = |d: usize| match &ranges {
+                    Some((_, maxs)) if depth > 1 => maxs[d],
+                    _ => h.z_max,
+                };
+                if r.u8()? != 0 {
+                    // all valid samples stored as they are
+                    for k in (0..n_pixels).filter(|&k| is_valid(k)) {
+                        for value in &mut values[k * depth..(k + 1) * depth] {
+                            *value = r.value(h.data_type)?;
+                        }
+                    }
+                } else {
+                    let huffman_int = h.data_type <= DataType::Byte && h.max_z_error == 0.5;
+                    let huffman_float =
+                        h.version >= 6 && h.data_type.is_float() && h.max_z_error == 0.0;
+                    if (huffman_int || huffman_float) && r.u8()? != 0 {
+                        return Err(unsupported("Huffman coding"));
+                    }
+                    read_blocks(&h, &mut r, &mut values, &is_valid, &z_max)?;
+                }
+            }
+        }
+    }
+
+    if let Some((no_data, original)) = h.no_data {
+        for value in values.iter_mut().filter(|v| **v == no_data) {
+            *value = original;
+        }
+    }
+    let mut data = Vec::with_capacity(n_samples * h.data_type.size());
+    for (i, &value) in values.iter().enumerate() {
+        let value = match is_valid(i / depth) {
+            true => value,
+            false if h.data_type.is_float() => f64::NAN,
+            false => 0.0,
+        };
+        h.data_type.write(value, &mut data, byte_order);
+    }
+    Ok(Decoded {
+        width: u32::try_from(h.width)?,
+        height: u32::try_from(h.height)?,
+        depth: u32::try_from(h.depth)?,
+        data_type: h.data_type,
+        data,
+    })
+}
+
+/// Decode the micro blocks of an image, row by row, each with a block per
+/// sample of a pixel
+fn read_blocks(
+    h: &Header,
+    r: &mut Cursor<'_>,
+    values: &mut [f64],
+    is_valid: &dyn Fn(usize) -> bool,
+    z_max: &dyn Fn(usize) -> f64,
+) -> TiffResult<()> {
+    let (width, depth, size) = (h.width, h.depth, h.micro_block);
+    for i0 in (0..h.height).step_by(size) {
+        for j0 in (0..width).step_by(size) {
+            let rows = i0..(i0 + size).min(h.height);
+            let cols = j0..(j0 + size).min(width);
+            let valid: Vec<usize> = rows
+                .flat_map(|i| cols.clone().map(move |j| i * width + j))
+                .filter(|&k| is_valid(k))
+                .collect();
+            for d in 0..depth {
+                let flags = r.u8()?;
+                // bits 2 to 5 check that blocks are where they should be.
+                // Since version 5, bit 2 flags differences with the previous
+                // sample of each pixel instead.
+                let check = ((j0 >> 3) & 15) as u8;
+                let (diff, misplaced) = match h.version {
+                    5.. => (flags & 4 != 0, (flags >> 3) & 7 != check >> 1),
+                    _ => (false, (flags >> 2) & 15 != check),
+                };
+                if misplaced || (diff && d == 0) {
+                    return Err(corrupt("block out of place"));
+                }
+                let previous = |values: &[f64], k: usize| match diff {
+                    true => values[k * depth + d - 1],
+                    false => 0.0,
+                };
+                match flags & 3 {
+                    // all 0
+                    2 => {
+                        for &k in &valid {
+                            values[k * depth + d] = previous(values, k);
+                        }
+                    }
+                    // stored as they are
+                    0 => {
+                        for &k in &valid {
+                            values[k * depth + d] = r.value(h.data_type)?;
+                        }
+                    }
+                    compressed => {
+                        // differences of integers may be negative
+                        let data_type = match h.data_type {
+                            dt if diff && !dt.is_float() => DataType::Int,
+                            dt => dt,
+                        };
+                        let offset = r.value(data_type.narrowed(flags >> 6)?)?;
+                        if compressed == 3 {
+                            // constant
+                            for &k in &valid {
+                                values[k * depth + d] =
+                                    h.data_type.round(offset + previous(values, k));
+                            }
+                            continue;
+                        }
+                        let quantized = bit_unstuff(r, size * size, h.version)?;
+                        if quantized.len() < valid.len() {
+                            return Err(corrupt("too few values in block"));
+                        }
+                        let scale = 2.0 * h.max_z_error;
+                        for (&k, &q) in valid.iter().zip(&quantized) {
+                            let value = offset + f64::from(q) * scale + previous(values, k);
+                            // stay within the original range
+                            values[k * depth + d] = h.data_type.round(value.min(z_max(d)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// [`wave`] as 10x9 floats with a maximum error of 0.01, leaving out every
+    /// 7th pixel from the 4th on, as written by libLerc 4
+    const FLOAT_MASKED: &[u8] = b"\
+        \x4c\x65\x72\x63\x32\x20\x06\x00\x00\x00\x3c\x03\x63\xe8\x09\x00\x00\x00\x0a\x00\
+        \x00\x00\x01\x00\x00\x00\x4d\x00\x00\x00\x08\x00\x00\x00\x16\x01\x00\x00\x06\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x7b\x14\xae\x47\xe1\x7a\x84\x3f\x00\x00\
+        \x00\x00\x00\x00\x59\xc0\x00\x00\x00\x00\x00\x00\x59\x40\x00\x00\x00\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x10\x00\x00\x00\x0c\x00\xef\xdf\xbf\x7e\
+        \xfd\xfb\xf7\xef\xdf\xbf\x7e\xff\x00\x80\x00\x00\xc8\xc2\x00\x00\xc8\x42\x00\x41\
+        \x9c\xff\x8e\x37\x88\x13\x94\x95\x90\x61\x78\x53\x20\x93\x78\x3f\x42\x9c\xdd\x26\
+        \x91\xb9\x54\xd2\x8f\x48\xe2\x40\xa6\x64\x09\x4e\xba\x10\xd9\x32\x8f\xe0\x1a\xc0\
+        \x81\x0c\x00\x00\xd0\x00\xcd\x00\x72\xf0\x31\x08\x24\x76\xcb\x84\xd3\x0c\x55\x4e\
+        \x15\x59\xec\x46\x25\xfa\x8f\x4a\xe6\xb7\x09\x71\x6e\x9b\x41\xe6\x8f\x58\x04\x22\
+        \x78\x95\xdb\x3d\xd6\x63\xb5\x42\xe8\x88\xab\x81\x4b\xec\x06\xc5\x00\x0c\x00\x00\
+        \x00\x01\x81\xd5\xc5\xc2\x8e\x0e\x17\x25\x84\xd9\xdd\xa9\x6d\x8a\xc4\xb7\x00\x69\
+        \xb4\x77\x27\xe0\x43\xe9\xdb\x8c\x2c\x00\xc0\x26\x00\x01\xfa\xbe\xa7\xc2\x8c\x06\
+        \x00\xe0\x1a\xa9\x73\x5e\x5b\x98\xaf\x00\x37\x89\x01\x3f\xd9\xce\x6b\x41\
+    ";
+
+    /// The same without mask, as Lerc2 version 2
+    const FLOAT_V2: &[u8] = b"\
+        \x4c\x65\x72\x63\x32\x20\x02\x00\x00\x00\x09\x00\x00\x00\x0a\x00\x00\x00\x5a\x00\
+        \x00\x00\x08\x00\x00\x00\xf3\x00\x00\x00\x06\x00\x00\x00\x7b\x14\xae\x47\xe1\x7a\
+        \x84\x3f\x00\x00\x00\x00\x00\x00\x59\xc0\x00\x00\x00\x00\x00\x00\x59\x40\x00\x00\
+        \x00\x00\x00\x41\x9c\xff\x8e\x40\x06\x65\x21\x4e\x78\xa6\x5b\x42\x93\x38\x05\x62\
+        \x76\x9b\xf7\x23\x66\xb7\x09\x71\x3f\x2e\x95\x44\x4f\x20\x92\x48\xa4\x64\x0d\x64\
+        \x38\xba\x90\xe0\x3c\x42\xb6\x04\x00\x07\xb8\xc6\x80\x0c\x80\x0c\x0c\xd0\x00\x00\
+        \x1f\x03\x72\xd0\x63\xb7\x08\x24\x4e\xcd\xd0\x84\x45\xc6\x65\x55\x52\x89\xb1\x5b\
+        \xa6\x54\xe9\x3f\x71\x7e\x9b\x4a\x41\xe6\xb6\x09\x57\x04\xfe\x88\x63\x95\x1b\x82\
+        \xdd\xd4\x63\xdd\xa0\x23\xad\x50\x03\x2e\xe1\x6a\x0c\xec\x06\x0e\x00\x00\x0c\x50\
+        \x05\x81\xd5\xc5\xc2\x8e\x10\x07\x61\x5e\x94\x12\x6a\x5b\x77\x25\xf1\x2d\x28\xb6\
+        \x77\x90\x86\xe3\x43\x79\x02\xb2\xf8\x36\xb7\x9b\x00\x00\x30\x01\xc1\x8a\xb5\xc2\
+        \x8c\x08\x30\x59\x01\x00\x09\x74\x02\x75\x0c\x2f\xc5\xb4\x04\x37\x89\x01\x3f\xd9\
+        \xce\x6b\x41\
+    ";
+
+    /// `i * 937` as 6x5 pixels of 3 lossless u16 samples
+    const U16_RGB: &[u8] = b"\
+        \x4c\x65\x72\x63\x32\x20\x06\x00\x00\x00\x90\xa0\xe4\x39\x05\x00\x00\x00\x06\x00\
+        \x00\x00\x03\x00\x00\x00\x1e\x00\x00\x00\x08\x00\x00\x00\xe8\x00\x00\x00\x03\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xe0\x3f\x00\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xa0\x91\xef\x40\x00\x00\x00\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x36\x00\xdf\x03\
+        \x8d\xfc\x3b\xf5\xe4\xf8\x00\x00\x00\x00\xfb\x0a\xf6\x15\xf1\x20\xec\x2b\xe7\x36\
+        \xe2\x41\xdd\x4c\xd8\x57\xd3\x62\xce\x6d\xc9\x78\xc4\x83\xbf\x8e\xba\x99\xb5\xa4\
+        \xb0\xaf\xab\xba\xa6\xc5\xa1\xd0\x9c\xdb\x97\xe6\x92\xf1\x8d\xfc\x88\x07\x83\x12\
+        \x7e\x1d\x79\x28\x74\x33\x6f\x3e\x00\xa9\x03\xa4\x0e\x9f\x19\x9a\x24\x95\x2f\x90\
+        \x3a\x8b\x45\x86\x50\x81\x5b\x7c\x66\x77\x71\x72\x7c\x6d\x87\x68\x92\x63\x9d\x5e\
+        \xa8\x59\xb3\x54\xbe\x4f\xc9\x4a\xd4\x45\xdf\x40\xea\x3b\xf5\x36\x00\x31\x0b\x2c\
+        \x16\x27\x21\x22\x2c\x1d\x37\x18\x42\x87\xa9\x03\
+    ";
+
+    /// Samples the float blobs were made from
+    fn wave(i: usize) -> f64 {
+        (i as f64 / 7.0).sin() * 100.0
+    }
+
+    #[test]
+    fn test_float() {
+        for (blob, masked) in [(FLOAT_MASKED, true), (FLOAT_V2, false)] {
+            let decoded = decode(blob, ByteOrder::LittleEndian, 1 << 20).unwrap();
+            assert_eq!((decoded.width, decoded.height, decoded.depth), (10, 9, 1));
+            assert_eq!(decoded.data_type, DataType::Float);
+            for (i, bytes) in decoded.data.chunks_exact(4).enumerate() {
+                let value = f32::from_le_bytes(bytes.try_into().unwrap());
+                if masked && i % 7 == 3 {
+                    assert!(value.is_nan());
+                } else {
+                    // rounded to 3 decimals before encoding
+                    let error = (f64::from(value) - wave(i)).abs();
+                    assert!(error <= 0.0105, "{value} at {i}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_integer() {
+        let expected: Vec<u16> = (0..6 * 5 * 3).map(|i| (i * 937) as u16).collect();
+        let decoded = decode(U16_RGB, ByteOrder::BigEndian, 1 << 20).unwrap();
+        assert_eq!((decoded.depth, decoded.data_type), (3, DataType::UShort));
+        let values: Vec<u16> = decoded
+            .data
+            .chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let Err(TiffError::LimitsExceeded) = decode(U16_RGB, ByteOrder::LittleEndian, 100) else {
+            panic!("180 bytes of samples exceed the limit");
+        };
+        let mut flipped = FLOAT_MASKED.to_vec();
+        flipped[200] ^= 1;
+        let mut old = FLOAT_MASKED.to_vec();
+        old[..10].copy_from_slice(b"CntZImage ");
+        for (blob, unsupported) in [
+            (&flipped[..], false),
+            (&FLOAT_MASKED[..100], false),
+            (&FLOAT_V2[..200], false),
+            (&old[..], true),
+        ] {
+            match decode(blob, ByteOrder::LittleEndian, 1 << 20) {
+                Err(TiffError::UnsupportedError(_)) if unsupported => {}
+                Err(TiffError::FormatError(_)) if !unsupported => {}
+                result => panic!("{result:?}"),
+            }
+        }
+    }
+}
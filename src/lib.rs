@@ -1,10 +1,30 @@
+//! Building blocks for (async) TIFF decoding and encoding, geared towards COGs.
 //!
-//!
+//! Tag/IFD parsing and the codecs that allow it form a `no_std + alloc` core.
+//! The `std` feature (on by default) adds the async reader machinery on top.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+// the test harness needs std anyway, and tests of the core use its macros
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
 
 /// for byte casting. Not sure if we can actually stomp in bytemuck as dependency.
 pub mod bytecast;
+/// Query which features this build can decode
+pub mod capabilities;
+pub use capabilities::capabilities;
 /// Errors
 pub mod error;
+/// CCITT Group 4 fax decoding
+pub mod fax;
+/// `std::io`, or the subset of it needed for parsing when building without `std`
+pub mod io;
+/// LERC decoding
+pub mod lerc;
+/// Nodata values, compared the same way when encoding and decoding
+pub mod nodata;
 /// Generic utility functions that can be used for both decoding and encoding
 pub mod util;
 
@@ -13,9 +33,22 @@ pub mod structs;
 /// static decoding functions to be used with the Tiff/Image struct. Additionally an
 /// opinionated decoder, optimized for COGs (without the geo part).
 pub mod decoder;
+/// Half-precision float, the type of [`decoder::DecodingResult::F16`] samples
+pub use half::f16;
 /// static encoding functions to be used with Tiff/Image struct. Additionally,
 /// opinionated COG-building encoder
 pub mod encoder;
+/// Entry points and seed inputs for fuzzing
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+/// Predictors, for both decoding and encoding
+pub mod predictor;
+/// Progress reporting for operations on many chunks
+#[cfg(feature = "std")]
+pub mod progress;
+/// Synthetic TIFF files for testing decoder hardening
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ByteOrder {
@@ -37,6 +70,16 @@ macro_rules! cast_fn {
 }
 
 impl ByteOrder {
+    /// Byte order of the target, which buffered entries and decoded samples
+    /// are in
+    pub const fn native() -> Self {
+        if cfg!(target_endian = "little") {
+            ByteOrder::LittleEndian
+        } else {
+            ByteOrder::BigEndian
+        }
+    }
+
     cast_fn!(u8, u8, 1);
     cast_fn!(i8, i8, 1);
     cast_fn!(u16, u16, 2);
@@ -81,12 +124,15 @@ pub enum ColorType {
     /// Pixel is YCbCr
     YCbCr(u8),
 
+    /// Pixel is CIE L*a*b*, of either the CIE or the ICC encoding
+    Lab(u8),
+
     /// Pixel has multiple bands/channels
     Multiband { bit_depth: u8, num_samples: u16 },
 }
 
 impl ColorType {
-    fn bit_depth(&self) -> u8 {
+    pub fn bit_depth(&self) -> u8 {
         match *self {
             ColorType::Gray(b)
             | ColorType::RGB(b)
@@ -95,6 +141,7 @@ impl ColorType {
             | ColorType::RGBA(b)
             | ColorType::CMYK(b)
             | ColorType::YCbCr(b)
+            | ColorType::Lab(b)
             | ColorType::Multiband { bit_depth: b, .. } => b,
         }
     }
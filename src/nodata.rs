@@ -0,0 +1,307 @@
+//! Telling which samples are nodata.
+//!
+//! Sparse tiles, overview resampling and masks all need to know whether a
+//! sample holds the nodata value of an image. [`Nodata`] decides that the
+//! same way for all of them: integer samples match exactly, float samples
+//! match NaN if the value is NaN, and otherwise match within an optional
+//! tolerance.
+//!
+//! GDAL stores the value as text in the `GDAL_NODATA` tag, which
+//! [`Nodata::from_ifd`] parses for the sample type of an image.
+//!
+//! [`Nodata`]: crate::nodata::Nodata
+//! [`Nodata::from_ifd`]: crate::nodata::Nodata::from_ifd
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    decoder::{PackedBitmap, Sample, SampleType},
+    error::{TiffFormatError, TiffResult},
+    structs::{tags::PhotometricInterpretation, Ifd, Tag},
+};
+
+/// Value of pixels without data, compared to samples according to their type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Nodata {
+    value: f64,
+    /// The value if it is an integer, exactly, as a float may not hold every
+    /// 64-bit integer
+    integer: Option<i128>,
+    tolerance: f64,
+}
+
+impl Nodata {
+    /// Nodata that samples have to match exactly
+    pub fn new(value: f64) -> Self {
+        Nodata {
+            value,
+            integer: integer(value),
+            tolerance: 0.0,
+        }
+    }
+
+    /// Nodata written as text the way GDAL does, like `-9999`, `1e+20` or
+    /// `nan`, `None` if it isn't a number. Integers are kept exactly, even
+    /// beyond the precision of a float.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim_end_matches('\0').trim();
+        if let Ok(n) = text.parse::<i128>() {
+            return Some(Nodata {
+                value: n as f64,
+                integer: Some(n),
+                tolerance: 0.0,
+            });
+        }
+        text.parse().ok().map(Nodata::new)
+    }
+
+    /// Nodata of an IFD with samples of `sample_type`, from `GDAL_NODATA`, as
+    /// given by [`Self::to_type`]. `None` if there is no such tag, or if the
+    /// samples can't hold the value, like -9999 in a `u8`, so no sample is
+    /// nodata.
+    ///
+    /// Fails with [`TiffFormatError::InvalidTagValueType`] if the tag doesn't
+    /// hold a number.
+    pub fn from_ifd(ifd: &Ifd, sample_type: SampleType) -> TiffResult<Option<Self>> {
+        let Some(entry) = ifd.get_tag_value(&Tag::GdalNodata)? else {
+            return Ok(None);
+        };
+        let nodata = core::str::from_utf8(entry.data())
+            .ok()
+            .and_then(Nodata::parse)
+            .ok_or(TiffFormatError::InvalidTagValueType(
+                Tag::GdalNodata.to_u16(),
+            ))?;
+        Ok(nodata.to_type(sample_type))
+    }
+
+    /// Let float samples within `tolerance` of the value match too, for data
+    /// that went through a lossy conversion. Integer samples still have to
+    /// match exactly.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// Whether a float is nodata
+    pub fn matches_f64(&self, v: f64) -> bool {
+        v == self.value
+            || (v.is_nan() && self.value.is_nan())
+            || (v - self.value).abs() <= self.tolerance
+    }
+
+    /// Whether a native-endian sample of `sample_type` is nodata
+    pub fn matches(&self, sample: &[u8], sample_type: SampleType) -> bool {
+        match sample_type.read(sample) {
+            // exact, as a float may not hold every 64-bit integer
+            Sample::Uint(v) => self.integer.and_then(|n| u64::try_from(n).ok()) == Some(v),
+            Sample::Int(v) => self.integer.and_then(|n| i64::try_from(n).ok()) == Some(v),
+            Sample::Float(v) => self.matches_f64(v),
+        }
+    }
+
+    /// Whether all samples of a native-endian pixel are nodata
+    pub fn matches_pixel(&self, pixel: &[u8], sample_type: SampleType) -> bool {
+        pixel
+            .chunks_exact(sample_type.size())
+            .all(|sample| self.matches(sample, sample_type))
+    }
+
+    /// The value as a native-endian sample of `sample_type`, `None` if that
+    /// can't hold it, like -9999 in a `u8`, 0.5 in any integer or 1e40 in an
+    /// `f32`. Floats are rounded to the nearest sample.
+    pub fn to_sample(&self, sample_type: SampleType) -> Option<Vec<u8>> {
+        let exact = self.integer.and_then(|n| match i64::try_from(n) {
+            Ok(n) => Some(Sample::Int(n)),
+            Err(_) => u64::try_from(n).ok().map(Sample::Uint),
+        });
+        let mut sample = Vec::with_capacity(sample_type.size());
+        sample_type.write(exact.unwrap_or(Sample::Float(self.value)), &mut sample);
+        let fits = match sample_type.read(&sample) {
+            Sample::Uint(v) => self.integer == Some(v.into()),
+            Sample::Int(v) => self.integer == Some(v.into()),
+            // rounded, but not to infinity
+            Sample::Float(v) => {
+                v.is_nan() == self.value.is_nan() && v.is_finite() == self.value.is_finite()
+            }
+        };
+        fits.then_some(sample)
+    }
+
+    /// The value rounded to a sample of `sample_type`, which samples of that
+    /// type are compared to, `None` if that can't hold it as for
+    /// [`Self::to_sample`]
+    pub fn to_type(&self, sample_type: SampleType) -> Option<Self> {
+        let sample = self.to_sample(sample_type)?;
+        let (value, integer) = match sample_type.read(&sample) {
+            Sample::Uint(v) => (v as f64, Some(v.into())),
+            Sample::Int(v) => (v as f64, Some(v.into())),
+            Sample::Float(v) => (v, integer(v)),
+        };
+        Some(Nodata {
+            value,
+            integer,
+            tolerance: self.tolerance,
+        })
+    }
+
+    /// Mask of `width` by `height` pixels of `samples` native-endian samples
+    /// of `sample_type` each, with the bits of pixels that aren't nodata set,
+    /// to render with [`render_rgba`] for instance.
+    ///
+    /// Fails with [`TiffFormatError::InconsistentStripSamples`] if `data`
+    /// holds another number of samples.
+    ///
+    /// [`render_rgba`]: crate::decoder::render_rgba
+    pub fn mask(
+        &self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        samples: usize,
+        sample_type: SampleType,
+    ) -> TiffResult<PackedBitmap> {
+        let pixel_bytes = samples * sample_type.size();
+        if pixel_bytes == 0 || data.len() != width * height * pixel_bytes {
+            return Err(TiffFormatError::InconsistentStripSamples {
+                actual_samples: data.len() / sample_type.size(),
+                required_samples: width * height * samples,
+            }
+            .into());
+        }
+        let stride = PackedBitmap::stride_for(width);
+        let mut bits = vec![0u8; stride * height];
+        for (i, pixel) in data.chunks_exact(pixel_bytes).enumerate() {
+            let (x, y) = (i % width, i / width);
+            if !self.matches_pixel(pixel, sample_type) {
+                bits[y * stride + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        PackedBitmap::from_rows(bits, width, height, PhotometricInterpretation::BlackIsZero)
+    }
+}
+
+/// A float if it is an integer that an `i128` holds exactly
+fn integer(value: f64) -> Option<i128> {
+    // the range of `u64` and `i64` combined, as floats
+    const MIN: f64 = -9_223_372_036_854_775_808.0;
+    const MAX: f64 = 18_446_744_073_709_551_616.0;
+    let n = value as i128;
+    ((MIN..MAX).contains(&value) && n as f64 == value).then_some(n)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoder::directory::entry;
+
+    fn bytes(sample_type: SampleType, v: f64) -> Vec<u8> {
+        let mut out = Vec::new();
+        sample_type.write_f64(v, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_matches() {
+        let nodata = Nodata::new(-9999.0);
+        assert!(nodata.matches(&bytes(SampleType::I16, -9999.0), SampleType::I16));
+        assert!(!nodata.matches(&bytes(SampleType::I16, -9998.0), SampleType::I16));
+        assert!(nodata.matches(&bytes(SampleType::F32, -9999.0), SampleType::F32));
+        // saturated to 0, which isn't nodata
+        assert!(!nodata.matches(&bytes(SampleType::U8, -9999.0), SampleType::U8));
+
+        // integers match exactly, even beyond the precision of floats
+        let big = Nodata::new(2f64.powi(60));
+        let sample = (2u64.pow(60) + 1).to_ne_bytes();
+        assert!(!big.matches(&sample, SampleType::U64));
+        assert!(big.matches(&2u64.pow(60).to_ne_bytes(), SampleType::U64));
+
+        // NaN matches any NaN
+        let nan = Nodata::new(f64::NAN);
+        let other_nan = f32::from_bits(0x7fc0_0001).to_ne_bytes();
+        assert!(nan.matches(&other_nan, SampleType::F32));
+        assert!(!nan.matches(&bytes(SampleType::F32, 0.0), SampleType::F32));
+        assert!(!nan.matches(&[0], SampleType::U8));
+
+        // a tolerance only applies to floats
+        let near = Nodata::new(1.0).with_tolerance(0.01);
+        assert!(near.matches(&bytes(SampleType::F64, 1.005), SampleType::F64));
+        assert!(!near.matches(&bytes(SampleType::F64, 1.02), SampleType::F64));
+        let near_int = Nodata::new(1.5).with_tolerance(1.0);
+        assert!(!near_int.matches(&[1], SampleType::U8));
+
+        let pixel = [bytes(SampleType::I16, -9999.0), bytes(SampleType::I16, 1.0)].concat();
+        assert!(!nodata.matches_pixel(&pixel, SampleType::I16));
+        assert!(nodata.matches_pixel(&pixel[..2], SampleType::I16));
+    }
+
+    #[test]
+    fn test_to_sample() {
+        let nodata = Nodata::new(-9999.0);
+        assert_eq!(nodata.to_sample(SampleType::U8), None);
+        assert_eq!(
+            nodata.to_sample(SampleType::I16),
+            Some((-9999i16).to_ne_bytes().to_vec())
+        );
+        assert_eq!(Nodata::new(0.5).to_sample(SampleType::U16), None);
+        assert_eq!(Nodata::new(f64::NAN).to_sample(SampleType::I32), None);
+        let nan = Nodata::new(f64::NAN).to_sample(SampleType::F32).unwrap();
+        assert!(f32::from_ne_bytes(nan.try_into().unwrap()).is_nan());
+        // rounded to the nearest float, but not to infinity
+        assert_eq!(
+            Nodata::new(0.1).to_sample(SampleType::F32),
+            Some(0.1f32.to_ne_bytes().to_vec())
+        );
+        assert_eq!(Nodata::new(1e40).to_sample(SampleType::F32), None);
+        assert!(Nodata::new(f64::INFINITY)
+            .to_sample(SampleType::F16)
+            .is_some());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Nodata::parse("-9999\0"), Some(Nodata::new(-9999.0)));
+        assert_eq!(Nodata::parse(" 1e+20 "), Some(Nodata::new(1e20)));
+        assert!(Nodata::parse("nan").unwrap().value().is_nan());
+        assert_eq!(Nodata::parse("none"), None);
+        // exactly, unlike as a float
+        let max = Nodata::parse("18446744073709551615").unwrap();
+        assert!(max.matches(&u64::MAX.to_ne_bytes(), SampleType::U64));
+        assert!(!max.matches(&(u64::MAX - 1).to_ne_bytes(), SampleType::U64));
+    }
+
+    #[test]
+    fn test_from_ifd() {
+        let mut ifd = Ifd::default();
+        assert_eq!(Nodata::from_ifd(&ifd, SampleType::F32).unwrap(), None);
+        ifd.insert_tag_data_from_buffer(&Tag::GdalNodata, entry("0.1"));
+        // rounded, so samples of the type match
+        let nodata = Nodata::from_ifd(&ifd, SampleType::F32).unwrap().unwrap();
+        assert_eq!(nodata.value(), f64::from(0.1f32));
+        assert!(nodata.matches(&0.1f32.to_ne_bytes(), SampleType::F32));
+        // no integer is nodata
+        assert_eq!(Nodata::from_ifd(&ifd, SampleType::U8).unwrap(), None);
+        ifd.insert_tag_data_from_buffer(&Tag::GdalNodata, entry("-9999"));
+        let nodata = Nodata::from_ifd(&ifd, SampleType::I16).unwrap().unwrap();
+        assert!(nodata.matches(&(-9999i16).to_ne_bytes(), SampleType::I16));
+        ifd.insert_tag_data_from_buffer(&Tag::GdalNodata, entry("none"));
+        assert!(Nodata::from_ifd(&ifd, SampleType::I16).is_err());
+    }
+
+    #[test]
+    fn test_mask() {
+        // 3x2 RGB, the middle column nodata
+        let data = [0, 0, 1, 5, 5, 5, 0, 0, 0, 0, 5, 0, 5, 5, 5, 2, 2, 2];
+        let mask = Nodata::new(5.0)
+            .mask(&data, 3, 2, 3, SampleType::U8)
+            .unwrap();
+        assert_eq!(mask.data, [0b1010_0000; 2]);
+        assert!(Nodata::new(5.0)
+            .mask(&data[3..], 3, 2, 3, SampleType::U8)
+            .is_err());
+    }
+}
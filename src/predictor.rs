@@ -0,0 +1,197 @@
+//! Horizontal (2) and floating point (3) predictors, undone after
+//! decompression and applied before compression.
+//!
+//! Both work on rows of samples, differencing each sample with the one
+//! `samples_per_pixel` before it. The floating point predictor of Adobe's
+//! TIFF Technote 3 first splits each row into byte planes, most significant
+//! bytes first, so it works for 16, 32 and 64 bit floats alike, and its
+//! output doesn't depend on the byte order of the file.
+
+use alloc::vec;
+
+use crate::error::{TiffResult, TiffUnsupportedError};
+
+/// Horizontal differencing, or undoing it, on rows of native-endian samples
+fn horizontal(
+    data: &mut [u8],
+    bits_per_sample: u8,
+    samples_per_pixel: usize,
+    row_samples: usize,
+    encode: bool,
+) -> TiffResult<()> {
+    macro_rules! rows {
+        ($type:ty) => {{
+            const SIZE: usize = core::mem::size_of::<$type>();
+            let get = |row: &[u8], i: usize| {
+                <$type>::from_ne_bytes(row[i * SIZE..(i + 1) * SIZE].try_into().unwrap())
+            };
+            for row in data.chunks_exact_mut(row_samples * SIZE) {
+                if encode {
+                    // differences need the original previous sample
+                    for i in (samples_per_pixel..row_samples).rev() {
+                        let v = get(row, i).wrapping_sub(get(row, i - samples_per_pixel));
+                        row[i * SIZE..(i + 1) * SIZE].copy_from_slice(&v.to_ne_bytes());
+                    }
+                } else {
+                    for i in samples_per_pixel..row_samples {
+                        let v = get(row, i).wrapping_add(get(row, i - samples_per_pixel));
+                        row[i * SIZE..(i + 1) * SIZE].copy_from_slice(&v.to_ne_bytes());
+                    }
+                }
+            }
+        }};
+    }
+    match bits_per_sample {
+        8 => rows!(u8),
+        16 => rows!(u16),
+        32 => rows!(u32),
+        64 => rows!(u64),
+        bits => return Err(TiffUnsupportedError::UnsupportedBitsPerChannel(bits).into()),
+    }
+    Ok(())
+}
+
+/// Undo horizontal differencing on native-endian samples, in place.
+///
+/// `data` holds rows of `row_samples` samples of `bits_per_sample` bits,
+/// trailing bytes that don't make up a full row are left as is.
+pub fn horizontal_decode(
+    data: &mut [u8],
+    bits_per_sample: u8,
+    samples_per_pixel: usize,
+    row_samples: usize,
+) -> TiffResult<()> {
+    horizontal(data, bits_per_sample, samples_per_pixel, row_samples, false)
+}
+
+/// Apply horizontal differencing to native-endian samples, in place. The
+/// inverse of [`horizontal_decode`].
+pub fn horizontal_encode(
+    data: &mut [u8],
+    bits_per_sample: u8,
+    samples_per_pixel: usize,
+    row_samples: usize,
+) -> TiffResult<()> {
+    horizontal(data, bits_per_sample, samples_per_pixel, row_samples, true)
+}
+
+/// Bytes per float, if the floating point predictor supports the depth
+fn float_size(bits_per_sample: u8) -> TiffResult<usize> {
+    match bits_per_sample {
+        16 | 32 | 64 => Ok(usize::from(bits_per_sample / 8)),
+        bits => Err(TiffUnsupportedError::UnsupportedBitsPerChannel(bits).into()),
+    }
+}
+
+/// Undo the floating point predictor, in place, leaving native-endian
+/// floats. Rows are laid out like for [`horizontal_decode`].
+pub fn float_decode(
+    data: &mut [u8],
+    bits_per_sample: u8,
+    samples_per_pixel: usize,
+    row_samples: usize,
+) -> TiffResult<()> {
+    let size = float_size(bits_per_sample)?;
+    let mut planes = vec![0; row_samples * size];
+    for row in data.chunks_exact_mut(row_samples * size) {
+        for i in samples_per_pixel..row.len() {
+            row[i] = row[i].wrapping_add(row[i - samples_per_pixel]);
+        }
+        planes.copy_from_slice(row);
+        for (i, sample) in row.chunks_exact_mut(size).enumerate() {
+            // plane 0 holds the most significant bytes
+            for (byte, plane) in sample.iter_mut().zip(planes.chunks_exact(row_samples)) {
+                *byte = plane[i];
+            }
+            if cfg!(target_endian = "little") {
+                sample.reverse();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Apply the floating point predictor to native-endian floats, in place. The
+/// inverse of [`float_decode`].
+pub fn float_encode(
+    data: &mut [u8],
+    bits_per_sample: u8,
+    samples_per_pixel: usize,
+    row_samples: usize,
+) -> TiffResult<()> {
+    let size = float_size(bits_per_sample)?;
+    let mut samples = vec![0; row_samples * size];
+    for row in data.chunks_exact_mut(row_samples * size) {
+        samples.copy_from_slice(row);
+        for (i, sample) in samples.chunks_exact_mut(size).enumerate() {
+            if cfg!(target_endian = "little") {
+                sample.reverse();
+            }
+            for (byte, plane) in sample.iter().zip(row.chunks_exact_mut(row_samples)) {
+                plane[i] = *byte;
+            }
+        }
+        for i in (samples_per_pixel..row.len()).rev() {
+            row[i] = row[i].wrapping_sub(row[i - samples_per_pixel]);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_horizontal() {
+        // two rows of 2 RGB pixels
+        let samples: [u16; 12] = [1, 2, 3, 4, 4, 4, 100, 0, 7, 99, 1, 9];
+        let mut data: Vec<u8> = samples.iter().flat_map(|s| s.to_ne_bytes()).collect();
+        horizontal_encode(&mut data, 16, 3, 6).unwrap();
+        let predicted: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(predicted, [1, 2, 3, 3, 2, 1, 100, 0, 7, 65535, 1, 2]);
+        horizontal_decode(&mut data, 16, 3, 6).unwrap();
+        assert_eq!(
+            data,
+            samples
+                .iter()
+                .flat_map(|s| s.to_ne_bytes())
+                .collect::<Vec<_>>()
+        );
+        assert!(horizontal_decode(&mut data, 12, 3, 6).is_err());
+    }
+
+    #[test]
+    fn test_float() {
+        // 1.0 and 2.0 are 3ff0.. and 4000.., so the planes start with
+        // 3f 40 f0 00, differenced to 3f 01 b0 10
+        let mut data: Vec<u8> = [1.0f64, 2.0].iter().flat_map(|f| f.to_ne_bytes()).collect();
+        float_encode(&mut data, 64, 1, 2).unwrap();
+        assert_eq!(
+            data,
+            [0x3f, 0x01, 0xb0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        float_decode(&mut data, 64, 1, 2).unwrap();
+        assert_eq!(
+            data,
+            [1.0f64, 2.0]
+                .iter()
+                .flat_map(|f| f.to_ne_bytes())
+                .collect::<Vec<_>>()
+        );
+
+        let floats = [0.5f32, -3.25, 1e30, f32::NAN, 0.0, 7.0];
+        let mut data: Vec<u8> = floats.iter().flat_map(|f| f.to_ne_bytes()).collect();
+        let original = data.clone();
+        // two rows of 3
+        float_encode(&mut data, 32, 1, 3).unwrap();
+        assert_ne!(data, original);
+        float_decode(&mut data, 32, 1, 3).unwrap();
+        assert_eq!(data, original);
+        assert!(float_decode(&mut data, 8, 1, 1).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Progress of operations that handle many chunks.
+//!
+//! Pass a [`ProgressObserver`] to e.g.
+//! [`CogDecoder::decode_region_with_progress`] or
+//! [`CogEncoder::with_progress`] to render a progress bar. Observers are
+//! called from whichever task finished a chunk, so they should return quickly.
+//!
+//! Encoding can also be cancelled cooperatively, see
+//! [`CogEncoder::with_cancellation`].
+//!
+//! [`ProgressObserver`]: crate::progress::ProgressObserver
+//! [`CogDecoder::decode_region_with_progress`]: crate::decoder::CogDecoder::decode_region_with_progress
+//! [`CogEncoder::with_progress`]: crate::encoder::CogEncoder::with_progress
+//! [`CogEncoder::with_cancellation`]: crate::encoder::CogEncoder::with_cancellation
+
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Snapshot of an operation's progress, as reported to a [`ProgressObserver`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Progress {
+    /// Number of chunks the operation handles. When encoding, this grows as
+    /// levels are added.
+    pub chunks_total: usize,
+    /// Chunks whose compressed data was fetched, from the reader or a cache.
+    /// Always 0 when encoding.
+    pub chunks_fetched: usize,
+    /// Chunks decoded, or written when encoding
+    pub chunks_done: usize,
+    /// Bytes transferred: read from the reader, or written when encoding
+    pub bytes: u64,
+    /// Level whose chunks are being written, 0 being full resolution. Always
+    /// 0 when decoding.
+    pub level: usize,
+}
+
+/// Gets notified whenever a chunk was fetched or done
+pub trait ProgressObserver: Send + Sync {
+    fn on_progress(&self, progress: &Progress);
+}
+
+/// Counts progress from concurrent tasks and reports it to an observer
+pub(crate) struct ProgressTracker {
+    observer: Arc<dyn ProgressObserver>,
+    chunks_total: AtomicUsize,
+    chunks_fetched: AtomicUsize,
+    chunks_done: AtomicUsize,
+    bytes: AtomicU64,
+    level: AtomicUsize,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(observer: Arc<dyn ProgressObserver>, chunks_total: usize) -> Self {
+        ProgressTracker {
+            observer,
+            chunks_total: AtomicUsize::new(chunks_total),
+            chunks_fetched: AtomicUsize::new(0),
+            chunks_done: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            level: AtomicUsize::new(0),
+        }
+    }
+
+    /// Chunks of another level are being handled from now on
+    pub(crate) fn set_level(&self, level: usize) {
+        self.level.store(level, Ordering::Relaxed);
+    }
+
+    /// More chunks are to be handled
+    pub(crate) fn add_total(&self, chunks: usize) {
+        self.chunks_total.fetch_add(chunks, Ordering::Relaxed);
+    }
+
+    /// A chunk was fetched, `bytes` of which came from the reader
+    pub(crate) fn fetched(&self, bytes: u64) {
+        self.chunks_fetched.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.report();
+    }
+
+    /// A chunk was decoded, or written taking `bytes`
+    pub(crate) fn done(&self, bytes: u64) {
+        self.chunks_done.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.report();
+    }
+
+    fn report(&self) {
+        self.observer.on_progress(&Progress {
+            chunks_total: self.chunks_total.load(Ordering::Relaxed),
+            chunks_fetched: self.chunks_fetched.load(Ordering::Relaxed),
+            chunks_done: self.chunks_done.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            level: self.level.load(Ordering::Relaxed),
+        });
+    }
+}
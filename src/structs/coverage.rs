@@ -0,0 +1,159 @@
+//! Whether a pyramid has enough overviews.
+//!
+//! Zoomed out all the way, a reader should get by with a tile or two of the
+//! smallest level, rather than reading the whole full resolution image. An
+//! [`OverviewCoverage`] compares the size of that level to its tiles from the
+//! IFDs alone, and lists the overviews that are missing, which
+//! [`CogEncoder::write_level_with_overviews`] can add.
+//!
+//! [`OverviewCoverage`]: crate::structs::coverage::OverviewCoverage
+//! [`CogEncoder::write_level_with_overviews`]: crate::encoder::CogEncoder::write_level_with_overviews
+
+use alloc::vec::Vec;
+
+use crate::{
+    error::{TiffFormatError, TiffResult},
+    structs::{Ifd, Tag, Tiff},
+};
+
+/// Number of tiles the smallest level may have for the overviews to be
+/// sufficient
+pub const MAX_SMALLEST_LEVEL_TILES: u64 = 2;
+
+/// Sizes of the levels of a pyramid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverviewCoverage {
+    /// Width and height of each level in the order of the IFD chain,
+    /// transparency masks left out
+    pub levels: Vec<(u32, u32)>,
+    /// Tile width and height of the smallest level
+    pub tile_size: (u32, u32),
+}
+
+impl OverviewCoverage {
+    /// Coverage of the IFD chain of a file.
+    ///
+    /// Fails with [`TiffFormatError::RequiredTagNotFound`] if a level isn't
+    /// tiled, and with [`TiffFormatError::ImageFileDirectoryNotFound`] if the
+    /// file has no images.
+    pub fn new(tiff: &Tiff) -> TiffResult<Self> {
+        let mut levels = Vec::new();
+        let mut smallest = None;
+        for ifd in &tiff.ifds {
+            if ifd.is_transparency_mask()? {
+                continue;
+            }
+            let size = (
+                u32_tag(ifd, Tag::ImageWidth)?,
+                u32_tag(ifd, Tag::ImageLength)?,
+            );
+            let tile = (
+                u32_tag(ifd, Tag::TileWidth)?,
+                u32_tag(ifd, Tag::TileLength)?,
+            );
+            if tile.0 == 0 || tile.1 == 0 {
+                return Err(TiffFormatError::InvalidDimensions(tile.0, tile.1).into());
+            }
+            if smallest.is_none_or(|(smallest, _)| pixels(size) < pixels(smallest)) {
+                smallest = Some((size, tile));
+            }
+            levels.push(size);
+        }
+        let (_, tile_size) = smallest.ok_or(TiffFormatError::ImageFileDirectoryNotFound)?;
+        Ok(OverviewCoverage { levels, tile_size })
+    }
+
+    /// Width and height of the level with the fewest pixels
+    pub fn smallest_level(&self) -> (u32, u32) {
+        self.levels
+            .iter()
+            .copied()
+            .min_by_key(|&size| pixels(size))
+            .unwrap_or_default()
+    }
+
+    /// Number of tiles of the smallest level
+    pub fn smallest_level_tiles(&self) -> u64 {
+        let (width, height) = self.smallest_level();
+        u64::from(width.div_ceil(self.tile_size.0)) * u64::from(height.div_ceil(self.tile_size.1))
+    }
+
+    /// Whether the smallest level has at most [`MAX_SMALLEST_LEVEL_TILES`]
+    /// tiles, as recommended for COGs
+    pub fn is_sufficient(&self) -> bool {
+        self.smallest_level_tiles() <= MAX_SMALLEST_LEVEL_TILES
+    }
+
+    /// Sizes of the overviews to add after the smallest level, each half the
+    /// size of the one before rounded up, until one fits in a single tile
+    pub fn missing_levels(&self) -> Vec<(u32, u32)> {
+        let (mut width, mut height) = self.smallest_level();
+        let mut missing = Vec::new();
+        while width > self.tile_size.0 || height > self.tile_size.1 {
+            (width, height) = (width.div_ceil(2), height.div_ceil(2));
+            missing.push((width, height));
+        }
+        missing
+    }
+}
+
+fn u32_tag(ifd: &Ifd, tag: Tag) -> TiffResult<u32> {
+    ifd.require_tag_value(&tag)?.try_into()
+}
+
+fn pixels((width, height): (u32, u32)) -> u64 {
+    u64::from(width) * u64::from(height)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoder::directory::entry;
+
+    fn level(width: u32, height: u32, subfile_type: u32) -> Ifd {
+        let mut ifd = Ifd::default();
+        for (tag, value) in [
+            (Tag::NewSubfileType, subfile_type),
+            (Tag::ImageWidth, width),
+            (Tag::ImageLength, height),
+            (Tag::TileWidth, 256),
+            (Tag::TileLength, 256),
+        ] {
+            ifd.insert_tag_data_from_buffer(&tag, entry(&value));
+        }
+        ifd
+    }
+
+    #[test]
+    fn test_coverage() {
+        let (mut tiff, _) = Tiff::from_header(b"II*\0\x08\0\0\0").unwrap();
+        assert!(OverviewCoverage::new(&tiff).is_err());
+        tiff.ifds.push(level(2000, 1000, 0));
+        tiff.ifds.push(level(2000, 1000, 4));
+        tiff.ifds.push(level(1000, 500, 1));
+        let coverage = OverviewCoverage::new(&tiff).unwrap();
+        // the mask isn't a level
+        assert_eq!(coverage.levels, [(2000, 1000), (1000, 500)]);
+        assert_eq!(coverage.smallest_level_tiles(), 4 * 2);
+        assert!(!coverage.is_sufficient());
+        assert_eq!(coverage.missing_levels(), [(500, 250), (250, 125)]);
+
+        // 2 tiles are enough, but still leave a level to add
+        tiff.ifds.push(level(500, 250, 1));
+        let coverage = OverviewCoverage::new(&tiff).unwrap();
+        assert!(coverage.is_sufficient());
+        assert_eq!(coverage.missing_levels(), [(250, 125)]);
+
+        // a small image needs no overviews
+        tiff.ifds = vec![level(200, 100, 0)];
+        let coverage = OverviewCoverage::new(&tiff).unwrap();
+        assert!(coverage.is_sufficient());
+        assert!(coverage.missing_levels().is_empty());
+
+        let mut stripped = Ifd::default();
+        stripped.insert_tag_data_from_buffer(&Tag::ImageWidth, entry(&200u32));
+        stripped.insert_tag_data_from_buffer(&Tag::ImageLength, entry(&100u32));
+        tiff.ifds = vec![stripped];
+        assert!(OverviewCoverage::new(&tiff).is_err());
+    }
+}
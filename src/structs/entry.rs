@@ -1,6 +1,8 @@
 use crate::{
-    decoder::EndianReader,
+    decoder::{EndianReader, Limits},
+    encoder::EndianWriter,
     error::{TiffError, TiffFormatError, TiffResult, UsageError},
+    io::Read,
     structs::{
         value::Value,
         Tag,
@@ -11,12 +13,15 @@ use crate::{
         },
     },
     util::fix_endianness,
+    ByteOrder,
 };
 
-use std::{collections::BTreeMap, io::Read};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::fmt;
 pub type Directory = BTreeMap<Tag, IfdEntry>;
 
-///
+/// An entry of an IFD, which is either loaded or an offset to where its data
+/// can be read from
 #[derive(Debug, PartialEq)]
 pub enum IfdEntry {
     Offset {
@@ -32,46 +37,72 @@ impl IfdEntry {
     ///
     /// The reader should have its cursor at the start of tag_type, not at tag
     ///
+    /// Fails with [`TiffError::LimitsExceeded`] if the value is larger than
+    /// [`Limits::max_tag_bytes`].
+    ///
     /// If the value fits in the offset field, it will be converted
     /// ```
     /// # use tiff2::ByteOrder;
-    /// # use tiff2::{tags::TagType, value::Value, entry::IfdEntry, decoder::EndianReader};
+    /// # use tiff2::{structs::{value::Value, IfdEntry}, decoder::{EndianReader, Limits}};
     /// let entry_buf = [
     ///     0x03, 0x00,                         // Type (SHORT)
     ///     0x01, 0x00, 0x00, 0x00,             // Count (1)
     ///     0x2C, 0x01, 0x00, 0x00,             // Offset = Value (300)
     /// ];
     /// let mut r = EndianReader::wrap(std::io::Cursor::new(entry_buf), ByteOrder::LittleEndian);
-    /// assert_eq!(IfdEntry::from_reader(&mut r, false).unwrap(), IfdEntry::Value(Value::Short(300)));
+    /// assert_eq!(
+    ///     IfdEntry::from_reader(&mut r, false, &Limits::default()).unwrap(),
+    ///     IfdEntry::Value(Value::Short(300).try_into().unwrap())
+    /// );
     /// ```
     /// Otherwise an offset is saved
     /// ```
     /// # use tiff2::ByteOrder;
-    /// # use tiff2::{tags::TagType, value::Value, entry::IfdEntry, decoder::EndianReader};
+    /// # use tiff2::{structs::{TagType, IfdEntry}, decoder::{EndianReader, Limits}};
     /// let entry_buf = [
     ///     0x03, 0x00,                         // Type (SHORT)
     ///     0x03, 0x00, 0x00, 0x00,             // Count (3)
     ///     0x2C, 0x01, 0x00, 0x00,             // Offset = Value (300)
     /// ];
     /// let mut r = EndianReader::wrap(std::io::Cursor::new(entry_buf), ByteOrder::LittleEndian);
-    /// assert_eq!(IfdEntry::from_reader(&mut r, false).unwrap(), IfdEntry::Offset{
+    /// assert_eq!(IfdEntry::from_reader(&mut r, false, &Limits::default()).unwrap(), IfdEntry::Offset{
     ///     tag_type: TagType::SHORT,
     ///     count: 3,
     ///     offset: 300,
     /// });
     /// ```
-    pub fn from_reader<R: Read>(r: &mut EndianReader<R>, bigtiff: bool) -> TiffResult<Self> {
-        let t_u16 = r.read_u16()?;
-        let tag_type =
-            TagType::from_u16(t_u16).ok_or(TiffFormatError::InvalidTagValueType(t_u16))?;
+    ///
+    /// Entries of vendor types keep their offset field as is, read as an
+    /// offset, with a size hint of 0. See [`IfdEntry::with_size_hint`].
+    pub fn from_reader<R: Read>(
+        r: &mut EndianReader<R>,
+        bigtiff: bool,
+        limits: &Limits,
+    ) -> TiffResult<Self> {
+        let tag_type = TagType::from_u16_with_size_hint(r.read_u16()?, 0);
         let count: u64 = if bigtiff {
             r.read_u64()?
         } else {
             r.read_u32()?.into()
         };
+        if let TagType::Unknown(..) = tag_type {
+            // without the size of its values, there is no telling whether the
+            // field holds them or an offset, see `with_size_hint`
+            let offset = if bigtiff {
+                r.read_u64()?
+            } else {
+                r.read_u32()?.into()
+            };
+            return Ok(IfdEntry::Offset {
+                tag_type,
+                count,
+                offset,
+            });
+        }
         let Some(value_bytes) = count.checked_mul(tag_type.size().try_into()?) else {
             return Err(TiffError::LimitsExceeded);
         };
+        Limits::check(value_bytes, limits.max_tag_bytes)?;
         if !bigtiff && value_bytes > 4
             || value_bytes > 8
             || tag_type == TagType::IFD
@@ -88,8 +119,16 @@ impl IfdEntry {
                 },
             })
         } else {
-            let mut offset = vec![0u8; usize::try_from(count)? * tag_type.size()];
-            r.read_exact(&mut offset)?;
+            // always consume the whole offset field, so the reader ends up at
+            // the next entry
+            let mut field = [0u8; 8];
+            let field = if bigtiff {
+                &mut field[..]
+            } else {
+                &mut field[..4]
+            };
+            r.read_exact(field)?;
+            let mut offset = field[..usize::try_from(value_bytes)?].to_vec();
             fix_endianness(&mut offset, r.byte_order, 8 * tag_type.primitive_size());
             Ok(IfdEntry::Value(BufferedEntry {
                 tag_type,
@@ -98,6 +137,50 @@ impl IfdEntry {
             }))
         }
     }
+
+    /// Give an entry of a vendor type that was read without knowing the size
+    /// of its values, values of `size_hint` bytes. Its offset field, as
+    /// read in `byte_order`, then becomes the data itself if that fits, like
+    /// for other types. Other entries are returned as is.
+    pub fn with_size_hint(
+        self,
+        size_hint: u8,
+        byte_order: ByteOrder,
+        bigtiff: bool,
+    ) -> TiffResult<Self> {
+        let IfdEntry::Offset {
+            tag_type: TagType::Unknown(code, 0),
+            count,
+            offset,
+        } = self
+        else {
+            return Ok(self);
+        };
+        let tag_type = TagType::Unknown(code, size_hint);
+        let value_bytes = count
+            .checked_mul(size_hint.into())
+            .ok_or(TiffError::LimitsExceeded)?;
+        if value_bytes > if bigtiff { 8 } else { 4 } {
+            return Ok(IfdEntry::Offset {
+                tag_type,
+                count,
+                offset,
+            });
+        }
+        // the field as it is in the file
+        let mut field = EndianWriter::wrap(Vec::with_capacity(8), byte_order);
+        if bigtiff {
+            field.write_u64(offset);
+        } else {
+            field.write_u32(u32::try_from(offset)?);
+        }
+        let field = field.into_inner();
+        Ok(IfdEntry::Value(BufferedEntry {
+            tag_type,
+            count,
+            data: field[..usize::try_from(value_bytes)?].to_vec(),
+        }))
+    }
 }
 
 /// Entry with buffered data.
@@ -111,6 +194,57 @@ pub struct BufferedEntry {
     pub data: Vec<u8>,
 }
 
+/// What errors keep of an entry: its type, count and the first bytes of its
+/// data, so a failed conversion of a large entry doesn't copy all of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryPreview {
+    pub tag_type: TagType,
+    pub count: u64,
+    /// Length of the whole data
+    pub len: usize,
+    preview: [u8; EntryPreview::MAX_BYTES],
+}
+
+impl EntryPreview {
+    /// Number of bytes of data that are kept
+    pub const MAX_BYTES: usize = 16;
+
+    /// The first bytes of the data, native-endian like in the entry
+    pub fn data(&self) -> &[u8] {
+        &self.preview[..self.len.min(Self::MAX_BYTES)]
+    }
+}
+
+impl From<&BufferedEntry> for EntryPreview {
+    fn from(entry: &BufferedEntry) -> Self {
+        let mut preview = [0; EntryPreview::MAX_BYTES];
+        let n = entry.data.len().min(EntryPreview::MAX_BYTES);
+        preview[..n].copy_from_slice(&entry.data[..n]);
+        EntryPreview {
+            tag_type: entry.tag_type,
+            count: entry.count,
+            len: entry.data.len(),
+            preview,
+        }
+    }
+}
+
+impl fmt::Display for EntryPreview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} x {}, data {:?}",
+            self.tag_type,
+            self.count,
+            self.data()
+        )?;
+        if self.len > Self::MAX_BYTES {
+            write!(f, " and {} more bytes", self.len - Self::MAX_BYTES)?;
+        }
+        Ok(())
+    }
+}
+
 impl BufferedEntry {
     pub fn data(&self) -> &[u8] {
         &self.data
@@ -133,8 +267,8 @@ impl BufferedEntry {
                 TagType::BYTE                  => Ok(<&[u8 ]>::try_from(self)?[index].into()),
                 TagType::SHORT                 => Ok(<&[u16]>::try_from(self)?[index].into()),
                 TagType::LONG  | TagType::IFD  => Ok(<&[u32]>::try_from(self)?[index].into()),
-                TagType::LONG8 | TagType::IFD8 => Ok(<&[u64]>::try_from(self)?[index].into()),
-                _ => Err(TiffFormatError::UnsignedIntegerExpected(self.clone()).into()),
+                TagType::LONG8 | TagType::IFD8 => Ok(<&[u64]>::try_from(self)?[index]),
+                _ => Err(TiffFormatError::UnsignedIntegerExpected(self.into()).into()),
             }
         }
 }
@@ -156,11 +290,11 @@ impl TryFrom<&BufferedEntry> for f32 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             TagType::FLOAT => Ok(bytemuck::cast(<[u8; 4]>::try_from(val.data()).unwrap())),
-            _ => Err(TiffFormatError::FloatExpected(val.clone()).into()),
+            _ => Err(TiffFormatError::FloatExpected(val.into()).into()),
         }
     }
 }
@@ -171,12 +305,12 @@ impl TryFrom<&BufferedEntry> for f64 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             TagType::FLOAT  => Ok(Self::from(bytemuck::cast::<_, f32>(<[u8; 4]>::try_from(val.data()).unwrap()))),
             TagType::DOUBLE => Ok(           bytemuck::cast          (<[u8; 8]>::try_from(val.data()).unwrap()) ),
-            _ =>  Err(TiffFormatError::FloatExpected(val.clone()).into())
+            _ =>  Err(TiffFormatError::FloatExpected(val.into()).into())
         }
     }
 }
@@ -187,8 +321,7 @@ impl TryFrom<&BufferedEntry> for u8 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            dbg!(val.data.len() != val.tag_type.size());
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             // because we do `<[u8; n]>::try_from()` in stead of
@@ -197,7 +330,7 @@ impl TryFrom<&BufferedEntry> for u8 {
             TagType::SHORT                 => Ok(Self::try_from(bytemuck::cast::<_, u16>(<[u8; 2]>::try_from(val.data()).unwrap()))?),
             TagType::LONG  | TagType::IFD  => Ok(Self::try_from(bytemuck::cast::<_, u32>(<[u8; 4]>::try_from(val.data()).unwrap()))?),
             TagType::LONG8 | TagType::IFD8 => Ok(Self::try_from(bytemuck::cast::<_, u64>(<[u8; 8]>::try_from(val.data()).unwrap()))?),
-            _ => Err(TiffFormatError::UnsignedIntegerExpected(val.clone()).into()),
+            _ => Err(TiffFormatError::UnsignedIntegerExpected(val.into()).into()),
         }
     }
 }
@@ -208,8 +341,7 @@ impl TryFrom<&BufferedEntry> for u16 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            dbg!(val.data.len() != val.tag_type.size());
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             // because we do `<[u8; n]>::try_from()` in stead of
@@ -218,7 +350,7 @@ impl TryFrom<&BufferedEntry> for u16 {
             TagType::SHORT                 => Ok(               bytemuck::cast::<_, u16>(<[u8; 2]>::try_from(val.data()).unwrap())  ),
             TagType::LONG  | TagType::IFD  => Ok(Self::try_from(bytemuck::cast::<_, u32>(<[u8; 4]>::try_from(val.data()).unwrap()))?),
             TagType::LONG8 | TagType::IFD8 => Ok(Self::try_from(bytemuck::cast::<_, u64>(<[u8; 8]>::try_from(val.data()).unwrap()))?),
-            _ => Err(TiffFormatError::UnsignedIntegerExpected(val.clone()).into()),
+            _ => Err(TiffFormatError::UnsignedIntegerExpected(val.into()).into()),
         }
     }
 }
@@ -229,8 +361,7 @@ impl TryFrom<&BufferedEntry> for u32 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            dbg!(val.data.len() != val.tag_type.size());
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             // because we do `<[u8; n]>::try_from()` in stead of
@@ -239,7 +370,7 @@ impl TryFrom<&BufferedEntry> for u32 {
             TagType::SHORT                 => Ok(Self::    from(bytemuck::cast::<_, u16>(<[u8; 2]>::try_from(val.data()).unwrap())) ),
             TagType::LONG  | TagType::IFD  => Ok(               bytemuck::cast::<_, u32>(<[u8; 4]>::try_from(val.data()).unwrap())  ),
             TagType::LONG8 | TagType::IFD8 => Ok(Self::try_from(bytemuck::cast::<_, u64>(<[u8; 8]>::try_from(val.data()).unwrap()))?),
-            _ => Err(TiffFormatError::UnsignedIntegerExpected(val.clone()).into()),
+            _ => Err(TiffFormatError::UnsignedIntegerExpected(val.into()).into()),
         }
     }
 }
@@ -250,8 +381,7 @@ impl TryFrom<&BufferedEntry> for u64 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            dbg!(val.data.len() != val.tag_type.size());
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             // because we do `<[u8; n]>::try_from()` in stead of
@@ -260,7 +390,7 @@ impl TryFrom<&BufferedEntry> for u64 {
             TagType::SHORT                 => Ok(Self::    from(bytemuck::cast::<_, u16>(<[u8; 2]>::try_from(val.data()).unwrap())) ),
             TagType::LONG  | TagType::IFD  => Ok(Self::    from(bytemuck::cast::<_, u32>(<[u8; 4]>::try_from(val.data()).unwrap())) ),
             TagType::LONG8 | TagType::IFD8 => Ok(               bytemuck::cast::<_, u64>(<[u8; 8]>::try_from(val.data()).unwrap())  ),
-            _ => Err(TiffFormatError::UnsignedIntegerExpected(val.clone()).into()),
+            _ => Err(TiffFormatError::UnsignedIntegerExpected(val.into()).into()),
         }
     }
 }
@@ -271,7 +401,7 @@ impl TryFrom<&BufferedEntry> for i8 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             // because we do `<[u8; n]>::try_from()` in stead of
@@ -280,7 +410,7 @@ impl TryFrom<&BufferedEntry> for i8 {
             TagType::SSHORT => Ok(Self::try_from(bytemuck::cast::<_, i16>(<[u8; 2]>::try_from(val.data()).unwrap()))?),
             TagType::SLONG  => Ok(Self::try_from(bytemuck::cast::<_, i32>(<[u8; 4]>::try_from(val.data()).unwrap()))?),
             TagType::SLONG8 => Ok(Self::try_from(bytemuck::cast::<_, i64>(<[u8; 8]>::try_from(val.data()).unwrap()))?),
-            _ => Err(TiffFormatError::SignedIntegerExpected(val.clone()).into())
+            _ => Err(TiffFormatError::SignedIntegerExpected(val.into()).into())
         }
     }
 }
@@ -291,7 +421,7 @@ impl TryFrom<&BufferedEntry> for i16 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             // because we do `<[u8; n]>::try_from()` in stead of
@@ -300,7 +430,7 @@ impl TryFrom<&BufferedEntry> for i16 {
             TagType::SSHORT => Ok(               bytemuck::cast::<_, i16>(<[u8; 2]>::try_from(val.data()).unwrap())  ),
             TagType::SLONG  => Ok(Self::try_from(bytemuck::cast::<_, i32>(<[u8; 4]>::try_from(val.data()).unwrap()))?),
             TagType::SLONG8 => Ok(Self::try_from(bytemuck::cast::<_, i64>(<[u8; 8]>::try_from(val.data()).unwrap()))?),
-            _ => Err(TiffFormatError::SignedIntegerExpected(val.clone()).into())
+            _ => Err(TiffFormatError::SignedIntegerExpected(val.into()).into())
         }
     }
 }
@@ -311,7 +441,7 @@ impl TryFrom<&BufferedEntry> for i32 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             // because we do `<[u8; n]>::try_from()` in stead of
@@ -320,7 +450,7 @@ impl TryFrom<&BufferedEntry> for i32 {
             TagType::SSHORT => Ok(Self::    from(bytemuck::cast::<_, i16>(<[u8; 2]>::try_from(val.data()).unwrap())) ),
             TagType::SLONG  => Ok(               bytemuck::cast::<_, i32>(<[u8; 4]>::try_from(val.data()).unwrap())  ),
             TagType::SLONG8 => Ok(Self::try_from(bytemuck::cast::<_, i64>(<[u8; 8]>::try_from(val.data()).unwrap()))?),
-            _ => Err(TiffFormatError::SignedIntegerExpected(val.clone()).into())
+            _ => Err(TiffFormatError::SignedIntegerExpected(val.into()).into())
         }
     }
 }
@@ -331,7 +461,7 @@ impl TryFrom<&BufferedEntry> for i64 {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() {
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             // because we do `<[u8; n]>::try_from()` in stead of
@@ -340,7 +470,7 @@ impl TryFrom<&BufferedEntry> for i64 {
             TagType::SSHORT => Ok(Self::    from(bytemuck::cast::<_, i16>(<[u8; 2]>::try_from(val.data()).unwrap())) ),
             TagType::SLONG  => Ok(Self::    from(bytemuck::cast::<_, i32>(<[u8; 4]>::try_from(val.data()).unwrap())) ),
             TagType::SLONG8 => Ok(               bytemuck::cast::<_, i64>(<[u8; 8]>::try_from(val.data()).unwrap())  ),
-            _ => Err(TiffFormatError::SignedIntegerExpected(val.clone()).into())
+            _ => Err(TiffFormatError::SignedIntegerExpected(val.into()).into())
         }
     }
 }
@@ -354,11 +484,11 @@ impl TryFrom<&BufferedEntry> for i64 {
 
 //     fn try_from(val: &'a BufferedEntry) -> Result<Self, Self::Error> {
 //         if val.data.len() != val.tag_type.size() * usize::try_from(val.count)? {
-//             return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+//             return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
 //         }
 //         match val.tag_type {
-//             TagType::FLOAT => Ok(bytemuck::cast_slice(&val.data()[..])),
-//             _ => Err(TiffFormatError::FloatExpected(val.clone()).into()),
+//             TagType::FLOAT => Ok(bytemuck::cast_slice(val.data())),
+//             _ => Err(TiffFormatError::FloatExpected(val.into()).into()),
 //         }
 //     }
 // }
@@ -370,11 +500,11 @@ impl TryFrom<&BufferedEntry> for i64 {
 
 //     fn try_from(val: &'a BufferedEntry) -> Result<Self, Self::Error> {
 //         if val.data.len() != val.tag_type.size() * usize::try_from(val.count)? {
-//             return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+//             return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
 //         }
 //         match val.tag_type {
-//             TagType::DOUBLE => Ok(           bytemuck::cast_slice          (&val.data()[..]) ),
-//             _ =>  Err(TiffFormatError::FloatExpected(val.clone()).into())
+//             TagType::DOUBLE => Ok(           bytemuck::cast_slice          (val.data()) ),
+//             _ =>  Err(TiffFormatError::FloatExpected(val.into()).into())
 //         }
 //     }
 // }
@@ -387,14 +517,13 @@ macro_rules! entry_tryfrom_slice {
 
             fn try_from(val: &'a BufferedEntry) -> Result<Self, Self::Error> {
                 if val.data.len() != val.tag_type.size() * usize::try_from(val.count)? {
-                    dbg!(val.data.len() != val.tag_type.size());
-                    return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+                    return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
                 }
                 match val.tag_type {
                     $(
-                        $tag_type => Ok(bytemuck::cast_slice(&val.data()[..])),
+                        $tag_type => Ok(bytemuck::cast_slice(val.data())),
                     )+
-                    _ => Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into()),
+                    _ => Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into()),
                 }
             }
         }
@@ -422,12 +551,12 @@ impl TryFrom<&BufferedEntry> for Vec<f64> {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() * usize::try_from(val.count)? {
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
-            TagType::DOUBLE => Ok(bytemuck::cast_slice(&val.data()[..]).to_vec()),
-            TagType::FLOAT =>  Ok(bytemuck::cast_slice::<_, f32>(&val.data()[..]).iter().map(|v| f64::from(*v)).collect()),
-            _ =>  Err(TiffFormatError::FloatExpected(val.clone()).into())
+            TagType::DOUBLE => Ok(bytemuck::cast_slice(val.data()).to_vec()),
+            TagType::FLOAT =>  Ok(bytemuck::cast_slice::<_, f32>(val.data()).iter().map(|v| f64::from(*v)).collect()),
+            _ =>  Err(TiffFormatError::FloatExpected(val.into()).into())
         }
     }
 }
@@ -438,12 +567,12 @@ impl TryFrom<&BufferedEntry> for Vec<f32> {
 
     fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
         if val.data.len() != val.tag_type.size() * usize::try_from(val.count)? {
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
-            TagType::FLOAT =>   Ok(bytemuck::cast_slice(&val.data()[..]).to_vec()),
-            // TagType::DOUBLE =>  Ok(bytemuck::cast_slice::<_, f64>(&val.data()[..]).iter().map(|v| f32::try_from(*v)).collect()),
-            _ =>  Err(TiffFormatError::FloatExpected(val.clone()).into())
+            TagType::FLOAT =>   Ok(bytemuck::cast_slice(val.data()).to_vec()),
+            // TagType::DOUBLE =>  Ok(bytemuck::cast_slice::<_, f64>(val.data()).iter().map(|v| f32::try_from(*v)).collect()),
+            _ =>  Err(TiffFormatError::FloatExpected(val.into()).into())
         }
     }
 }
@@ -456,19 +585,19 @@ impl<'a> TryFrom<&'a BufferedEntry> for &'a str {
 
     fn try_from(val: &'a BufferedEntry) -> Result<Self, Self::Error> {
         if val.data().len() != usize::try_from(val.count)? {
-            return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+            return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
         }
         match val.tag_type {
             TagType::ASCII | TagType::BYTE | TagType::UNDEFINED => {
                 if val.data().is_ascii() && val.data().ends_with(&[0]) {
-                    let v = std::str::from_utf8(val.data())?;
+                    let v = core::str::from_utf8(val.data())?;
                     let v = v.trim_matches(char::from(0));
                     Ok(v)
                 } else {
                     Err(TiffFormatError::InvalidTag.into())
                 }
             }
-            _ => Err(TiffFormatError::AsciiExpected(val.clone()).into()),
+            _ => Err(TiffFormatError::AsciiExpected(val.into()).into()),
         }
     }
 }
@@ -482,7 +611,7 @@ impl<'a> TryFrom<&'a BufferedEntry> for &'a str {
 //             fn try_from(val: &BufferedEntry) -> Result<Self, Self::Error> {
 //                 if val.data.len() != val.tag_type.size() {
 //                     dbg!(val.data.len() != val.tag_type.size());
-//                     return Err(TiffFormatError::InconsistentSizesEncountered(val.clone()).into());
+//                     return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
 //                 }
 //                 match val.tag_type {
 //                     // because we do `<[u8; n]>::try_from()` in stead of
@@ -491,7 +620,7 @@ impl<'a> TryFrom<&'a BufferedEntry> for &'a str {
 //                     TagType::SHORT                 => Ok(Self::try_from(bytemuck::cast_slice::<_, u16>(val.data())).unwrap()),
 //                     TagType::LONG  | TagType::IFD  => Ok(Self::try_from(bytemuck::cast_slice::<_, u32>(val.data())).unwrap()),
 //                     TagType::LONG8 | TagType::IFD8 => Ok(Self::try_from(bytemuck::cast_slice::<_, u64>(val.data())).unwrap()),
-//                     _ => Err(TiffFormatError::UnsignedIntegerExpected(val.clone()).into()),
+//                     _ => Err(TiffFormatError::UnsignedIntegerExpected(val.into()).into()),
 //                 }
 //             }
 //         }
@@ -502,51 +631,26 @@ impl<'a> TryFrom<&'a BufferedEntry> for &'a str {
 
 /// Should not be needed in future, since we do everything from BufferedEntry
 fn from_single(tag_type: TagType, data: &[u8]) -> TiffResult<Value> {
-    Ok(match tag_type {
-        TagType::BYTE => Value::Byte(data[0]),
-        TagType::SBYTE => Value::SignedByte(data[0] as i8),
-        TagType::UNDEFINED => Value::Undefined(data[0]),
-
-        TagType::SHORT => Value::Short(u16::from_ne_bytes(data[..2].try_into().unwrap())),
-        TagType::SSHORT => Value::SShort(i16::from_ne_bytes(data[..2].try_into().unwrap())),
-
-        TagType::LONG => Value::Long(u32::from_ne_bytes(data[..4].try_into().unwrap())),
-        TagType::SLONG => Value::SLong(i32::from_ne_bytes(data[..4].try_into().unwrap())),
-
-        TagType::LONG8 => Value::Long8(u64::from_ne_bytes(data[..8].try_into().unwrap())),
-        TagType::SLONG8 => Value::SLong8(i64::from_ne_bytes(data[..8].try_into().unwrap())),
-
-        TagType::RATIONAL => Value::Rational(
-            u32::from_ne_bytes(data[..4].try_into().unwrap()),
-            u32::from_ne_bytes(data[4..8].try_into().unwrap()),
-        ),
-        TagType::SRATIONAL => Value::SRational(
-            i32::from_ne_bytes(data[..4].try_into().unwrap()),
-            i32::from_ne_bytes(data[4..8].try_into().unwrap()),
-        ),
-        TagType::FLOAT => Value::Float(f32::from_ne_bytes(data[..4].try_into().unwrap())),
-        TagType::DOUBLE => Value::Double(f64::from_ne_bytes(data[..8].try_into().unwrap())),
-
-        TagType::ASCII => {
-            if data[0] == 0 {
-                Value::Ascii("".to_string())
-            } else {
-                return Err(TiffFormatError::InvalidTag.into());
-            }
-        }
-        TagType::IFD | TagType::IFD8 => return Err(UsageError::IfdReadIntoEntry.into()),
-    })
+    match tag_type {
+        TagType::ASCII if data[0] != 0 => Err(TiffFormatError::InvalidTag.into()),
+        TagType::IFD | TagType::IFD8 => Err(UsageError::IfdReadIntoEntry.into()),
+        _ => Ok(EndianReader::wrap(data, ByteOrder::native())
+            .read_value(tag_type)?
+            .into()),
+    }
 }
 
 impl TryFrom<BufferedEntry> for Value {
     type Error = TiffError;
     fn try_from(entry: BufferedEntry) -> Result<Self, TiffError> {
+        if let TagType::Unknown(code, _) = entry.tag_type {
+            return Err(TiffFormatError::InvalidTagValueType(code).into());
+        }
         if entry.count == 1 {
             Ok(from_single(entry.tag_type, &entry.data)?)
         } else if entry.tag_type == TagType::ASCII {
-            println!("decoding ascii value: {:?}", entry.data);
             if entry.data.is_ascii() && entry.data.ends_with(&[0]) {
-                let v = std::str::from_utf8(&entry.data)?;
+                let v = core::str::from_utf8(&entry.data)?;
                 let v = v.trim_matches(char::from(0));
                 Ok(Value::Ascii(v.into()))
             } else {
@@ -601,11 +705,13 @@ impl TryFrom<Value> for BufferedEntry {
     }
 }
 
+#[cfg(test)]
 #[allow(unused_imports)]
 mod test_entry {
     use super::*;
+    use crate::io;
     use crate::ByteOrder;
-    use std::io;
+    use alloc::string::ToString;
     use TagType::{
         ASCII,
         // SINGLE BYTE
@@ -631,8 +737,8 @@ mod test_entry {
 
     #[test]
     fn test_bufferedentry_into_u8slice() {
-        let data = vec![42u8;43];
-        let entry = BufferedEntry{
+        let data = vec![42u8; 43];
+        let entry = BufferedEntry {
             tag_type: BYTE,
             count: 43,
             data: data.clone(),
@@ -640,8 +746,38 @@ mod test_entry {
         assert_eq!(<&[u8]>::try_from(&entry).unwrap(), data);
     }
 
+    #[test]
+    fn test_entry_preview() {
+        let entry = BufferedEntry {
+            tag_type: SHORT,
+            count: 1000,
+            data: (0..2000).map(|i| i as u8).collect(),
+        };
+        let TiffError::FormatError(TiffFormatError::InconsistentSizesEncountered(preview)) =
+            u16::try_from(&entry).unwrap_err()
+        else {
+            panic!("a SHORT with 1000 values isn't a single u16");
+        };
+        assert_eq!(
+            (preview.tag_type, preview.count, preview.len),
+            (SHORT, 1000, 2000)
+        );
+        assert_eq!(preview.data(), &entry.data[..EntryPreview::MAX_BYTES]);
+        assert_eq!(
+            preview.to_string(),
+            "SHORT x 1000, data [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15] and 1984 more bytes"
+        );
+        let short = EntryPreview::from(&BufferedEntry {
+            tag_type: BYTE,
+            count: 1,
+            data: vec![7],
+        });
+        assert_eq!(short.data(), [7]);
+        assert_eq!(short.to_string(), "BYTE x 1, data [7]");
+    }
+
     /// test conversion for single value, slice and too big numbers
-    /// actually not nice that 
+    /// actually not nice that
     macro_rules! test_bufferedentry_into {
         ($t:ty,  $name:ident, $(($tag_type:expr, $st:ty)),+) => {
             #[test]
@@ -681,9 +817,9 @@ mod test_entry {
                             },
                         }
                     }
-                    
+
                 )+
-                
+
             }
         };
     }
@@ -701,7 +837,7 @@ mod test_entry {
                 };
                 assert_eq!(
                     err,
-                    TiffFormatError::InconsistentSizesEncountered(e.clone()),
+                    TiffFormatError::InconsistentSizesEncountered((&e).into()),
                 );
 
                 let e = BufferedEntry{tag_type: $tag_type, count: 2, data: vec![0; size * 2]};
@@ -711,7 +847,7 @@ mod test_entry {
                 };
                 assert_eq!(
                     err,
-                    TiffFormatError::InconsistentSizesEncountered(e.clone()),
+                    TiffFormatError::InconsistentSizesEncountered((&e).into()),
                 );
               )+
             }
@@ -736,7 +872,7 @@ mod test_entry {
                     };
                     assert_eq!(
                         err,
-                        TiffFormatError::SignedIntegerExpected(e.clone()),
+                        TiffFormatError::SignedIntegerExpected((&e).into()),
                     );
                 )+
             }
@@ -761,7 +897,7 @@ mod test_entry {
                     };
                     assert_eq!(
                         err,
-                        TiffFormatError::UnsignedIntegerExpected(e.clone()),
+                        TiffFormatError::UnsignedIntegerExpected((&e).into()),
                     );
                 )+
             }
@@ -786,7 +922,7 @@ mod test_entry {
                     };
                     assert_eq!(
                         err,
-                        TiffFormatError::FloatExpected(e.clone()),
+                        TiffFormatError::FloatExpected((&e).into()),
                     );
                 )+
             }
@@ -900,7 +1036,7 @@ mod test_entry {
         ];
         for (buf, byte_order, res) in cases {
             let mut r = EndianReader::wrap(io::Cursor::new(buf), byte_order);
-            assert_eq!(IfdEntry::from_reader(&mut r, false).unwrap(), IfdEntry::Value(res.try_into().unwrap()));
+            assert_eq!(IfdEntry::from_reader(&mut r, false, &Limits::default()).unwrap(), IfdEntry::Value(res.try_into().unwrap()));
         }
     }
 
@@ -944,7 +1080,7 @@ mod test_entry {
         ];
         for (buf, byte_order, res) in cases {
             let mut r = EndianReader::wrap(io::Cursor::new(buf), byte_order);
-            assert_eq!(IfdEntry::from_reader(&mut r, true).unwrap(), IfdEntry::Value(res.try_into().unwrap()));
+            assert_eq!(IfdEntry::from_reader(&mut r, true, &Limits::default()).unwrap(), IfdEntry::Value(res.try_into().unwrap()));
         }
     }
 
@@ -978,7 +1114,7 @@ mod test_entry {
         ];
         for (buf, byte_order, res) in cases {
             let mut r = EndianReader::wrap(io::Cursor::new(buf), byte_order);
-            assert_eq!(IfdEntry::from_reader(&mut r, false).unwrap(), IfdEntry::Value(res.try_into().unwrap()));
+            assert_eq!(IfdEntry::from_reader(&mut r, false, &Limits::default()).unwrap(), IfdEntry::Value(res.try_into().unwrap()));
         }
     }
 
@@ -1016,7 +1152,7 @@ mod test_entry {
         ];
         for (buf, byte_order, res) in cases {
             let mut r = EndianReader::wrap(io::Cursor::new(buf), byte_order);
-            assert_eq!(IfdEntry::from_reader(&mut r, true).unwrap(), IfdEntry::Value(res.try_into().unwrap()));
+            assert_eq!(IfdEntry::from_reader(&mut r, true, &Limits::default()).unwrap(), IfdEntry::Value(res.try_into().unwrap()));
         }
     }
 
@@ -1060,7 +1196,7 @@ mod test_entry {
         ];
         for (buf, byte_order, count, tag_type) in cases {
             let mut r = EndianReader::wrap(io::Cursor::new(buf), byte_order);
-            assert_eq!(IfdEntry::from_reader(&mut r, false).unwrap(), IfdEntry::Offset { tag_type, count, offset: 42 });
+            assert_eq!(IfdEntry::from_reader(&mut r, false, &Limits::default()).unwrap(), IfdEntry::Offset { tag_type, count, offset: 42 });
         }
     }
 
@@ -1104,7 +1240,7 @@ mod test_entry {
         ];
         for (buf, byte_order, count, tag_type) in cases {
             let mut r = EndianReader::wrap(io::Cursor::new(buf), byte_order);
-            assert_eq!(IfdEntry::from_reader(&mut r, true).unwrap(), IfdEntry::Offset { tag_type, count, offset: 42 });
+            assert_eq!(IfdEntry::from_reader(&mut r, true, &Limits::default()).unwrap(), IfdEntry::Offset { tag_type, count, offset: 42 });
         }
     }
 }
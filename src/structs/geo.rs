@@ -0,0 +1,364 @@
+//! Georeferencing through GeoTIFF's model tags and keys
+//!
+//! The model tags place the image, see [`geotransform`]. The keys of
+//! `GeoKeyDirectoryTag` say in which coordinate reference systems, see
+//! [`GeoKeys`]. Values of keys that aren't a single SHORT are stored in
+//! `GeoDoubleParamsTag` and `GeoAsciiParamsTag`.
+//!
+//! [`geotransform`]: crate::structs::geo::geotransform
+//! [`GeoKeys`]: crate::structs::geo::GeoKeys
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    error::{TiffFormatError, TiffResult},
+    structs::{tags::GeoKey, BufferedEntry, Ifd, Tag, TagType},
+};
+
+/// Affine transform from pixel to model coordinates, in GDAL's order:
+/// `x = t[0] + column * t[1] + row * t[2]` and
+/// `y = t[3] + column * t[4] + row * t[5]`
+pub type GeoTransform = [f64; 6];
+
+/// GeoTIFF tags and RPCs, which describe the full resolution image. COG
+/// overviews don't carry them, but inherit them from the full resolution
+/// image.
+pub const GEO_TAGS: [Tag; 7] = [
+    Tag::ModelPixelScaleTag,
+    Tag::ModelTiepointTag,
+    Tag::ModelTransformationTag,
+    Tag::GeoKeyDirectoryTag,
+    Tag::GeoDoubleParamsTag,
+    Tag::GeoAsciiParamsTag,
+    Tag::RpcCoefficientTag,
+];
+
+/// Transform of an IFD from its own tags, `None` if it isn't georeferenced.
+///
+/// Uses `ModelTransformationTag` if present, otherwise the first tie point
+/// and `ModelPixelScaleTag`. The half pixel shift of `PixelIsPoint` rasters
+/// isn't applied.
+pub fn geotransform(ifd: &Ifd) -> TiffResult<Option<GeoTransform>> {
+    if let Some(entry) = ifd.get_tag_value(&Tag::ModelTransformationTag)? {
+        let m = doubles(entry, 16)?;
+        return Ok(Some([m[3], m[0], m[1], m[7], m[4], m[5]]));
+    }
+    let (Some(tiepoint), Some(scale)) = (
+        ifd.get_tag_value(&Tag::ModelTiepointTag)?,
+        ifd.get_tag_value(&Tag::ModelPixelScaleTag)?,
+    ) else {
+        return Ok(None);
+    };
+    // (column, row, z) -> (x, y, z)
+    let tiepoint = doubles(tiepoint, 6)?;
+    let scale = doubles(scale, 2)?;
+    Ok(Some([
+        tiepoint[3] - tiepoint[0] * scale[0],
+        scale[0],
+        0.0,
+        tiepoint[4] + tiepoint[1] * scale[1],
+        0.0,
+        -scale[1],
+    ]))
+}
+
+/// Transform of an image that covers the same area with pixels `x_factor`
+/// and `y_factor` times as large, like an overview
+pub fn scale_geotransform(t: GeoTransform, x_factor: f64, y_factor: f64) -> GeoTransform {
+    [
+        t[0],
+        t[1] * x_factor,
+        t[2] * y_factor,
+        t[3],
+        t[4] * x_factor,
+        t[5] * y_factor,
+    ]
+}
+
+/// Code of keys whose CRS, datum or unit isn't in the EPSG registry, but
+/// described by other keys
+pub const USER_DEFINED: u16 = 32767;
+
+/// Value of a GeoKey
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoKeyValue {
+    /// SHORTs, usually a single EPSG or GeoTIFF code
+    Shorts(Vec<u16>),
+    Doubles(Vec<f64>),
+    /// Text, without the `|` that terminates it in `GeoAsciiParamsTag`
+    Ascii(String),
+}
+
+/// The keys of a `GeoKeyDirectoryTag`, with their values
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeoKeys {
+    keys: BTreeMap<GeoKey, GeoKeyValue>,
+}
+
+/// Vertical coordinate reference system of e.g. an elevation model, from the
+/// vertical keys. Codes are `None` if the key is missing or
+/// [`USER_DEFINED`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerticalCrs {
+    /// EPSG code of the CRS, e.g. 5703 for NAVD88 height
+    pub code: Option<u16>,
+    pub citation: Option<String>,
+    /// EPSG code of the datum
+    pub datum: Option<u16>,
+    /// EPSG code of the unit of heights, e.g. 9001 for metres
+    pub units: Option<u16>,
+}
+
+impl GeoKeys {
+    /// Keys of an IFD, `None` if it has no `GeoKeyDirectoryTag`.
+    ///
+    /// Fails with [`TiffFormatError::InconsistentSizesEncountered`] if the
+    /// directory or a value it points to is cut short, and if a key points
+    /// to a params tag the IFD doesn't have.
+    pub fn from_ifd(ifd: &Ifd) -> TiffResult<Option<Self>> {
+        let Some(entry) = ifd.get_tag_value(&Tag::GeoKeyDirectoryTag)? else {
+            return Ok(None);
+        };
+        let invalid =
+            |entry: &BufferedEntry| TiffFormatError::InconsistentSizesEncountered(entry.into());
+        if entry.tag_type != TagType::SHORT {
+            return Err(invalid(entry).into());
+        }
+        let shorts: Vec<u16> = entry
+            .data()
+            .chunks_exact(2)
+            .map(|s| u16::from_ne_bytes([s[0], s[1]]))
+            .collect();
+        // version, revision, minor revision and number of keys
+        let n_keys = match shorts.get(..4) {
+            Some(&[_, _, _, n_keys]) => usize::from(n_keys),
+            _ => return Err(invalid(entry).into()),
+        };
+        let Some(directory) = shorts.get(4..4 + 4 * n_keys) else {
+            return Err(invalid(entry).into());
+        };
+        let mut keys = BTreeMap::new();
+        for key in directory.chunks_exact(4) {
+            let &[id, location, count, value] = key else {
+                unreachable!()
+            };
+            let (count, offset) = (usize::from(count), usize::from(value));
+            let value = match Tag::from_u16_exhaustive(location) {
+                // the value itself
+                Tag::Unknown(0) => GeoKeyValue::Shorts(vec![value]),
+                Tag::GeoKeyDirectoryTag => match shorts.get(offset..offset + count) {
+                    Some(values) => GeoKeyValue::Shorts(values.to_vec()),
+                    None => return Err(invalid(entry).into()),
+                },
+                Tag::GeoDoubleParamsTag => {
+                    let params = ifd.require_tag_value(&Tag::GeoDoubleParamsTag)?;
+                    match Vec::<f64>::try_from(params)?.get(offset..offset + count) {
+                        Some(values) => GeoKeyValue::Doubles(values.to_vec()),
+                        None => return Err(invalid(params).into()),
+                    }
+                }
+                Tag::GeoAsciiParamsTag => {
+                    let params = ifd.require_tag_value(&Tag::GeoAsciiParamsTag)?;
+                    match params.data().get(offset..offset + count) {
+                        Some(text) => GeoKeyValue::Ascii(
+                            String::from_utf8_lossy(text)
+                                .trim_end_matches(['|', '\0'])
+                                .to_string(),
+                        ),
+                        None => return Err(invalid(params).into()),
+                    }
+                }
+                _ => return Err(invalid(entry).into()),
+            };
+            keys.insert(GeoKey::from_u16_exhaustive(id), value);
+        }
+        Ok(Some(GeoKeys { keys }))
+    }
+
+    pub fn get(&self, key: GeoKey) -> Option<&GeoKeyValue> {
+        self.keys.get(&key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&GeoKey, &GeoKeyValue)> {
+        self.keys.iter()
+    }
+
+    /// The value of a key holding a single SHORT
+    pub fn code(&self, key: GeoKey) -> Option<u16> {
+        match self.keys.get(&key)? {
+            GeoKeyValue::Shorts(values) => values.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// The value of a key holding text
+    pub fn text(&self, key: GeoKey) -> Option<&str> {
+        match self.keys.get(&key)? {
+            GeoKeyValue::Ascii(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// EPSG code of the projected CRS, or of the geographic CRS if the image
+    /// isn't projected, `None` for user-defined ones
+    pub fn epsg(&self) -> Option<u16> {
+        self.registered(GeoKey::ProjectedCSType)
+            .or_else(|| self.registered(GeoKey::GeographicType))
+    }
+
+    /// Description of the whole CRS, `GTCitationGeoKey`
+    pub fn citation(&self) -> Option<&str> {
+        self.text(GeoKey::GTCitation)
+    }
+
+    /// Description of the geographic CRS, `GeogCitationGeoKey`
+    pub fn geog_citation(&self) -> Option<&str> {
+        self.text(GeoKey::GeogCitation)
+    }
+
+    /// EPSG code of the unit of projected coordinates, e.g. 9001 for metres,
+    /// from `ProjLinearUnitsGeoKey`, or `GeogLinearUnitsGeoKey` if the image
+    /// isn't projected
+    pub fn linear_units(&self) -> Option<u16> {
+        self.code(GeoKey::ProjLinearUnits)
+            .or_else(|| self.code(GeoKey::GeogLinearUnits))
+    }
+
+    /// EPSG code of the unit of geographic coordinates, e.g. 9102 for
+    /// degrees, from `GeogAngularUnitsGeoKey`
+    pub fn angular_units(&self) -> Option<u16> {
+        self.code(GeoKey::GeogAngularUnits)
+    }
+
+    /// The vertical CRS, `None` if there are no vertical keys
+    pub fn vertical(&self) -> Option<VerticalCrs> {
+        let vertical = VerticalCrs {
+            code: self.registered(GeoKey::VerticalCSType),
+            citation: self.text(GeoKey::VerticalCitation).map(String::from),
+            datum: self.registered(GeoKey::VerticalDatum),
+            units: self.registered(GeoKey::VerticalUnits),
+        };
+        let has_keys = [
+            GeoKey::VerticalCSType,
+            GeoKey::VerticalCitation,
+            GeoKey::VerticalDatum,
+            GeoKey::VerticalUnits,
+        ]
+        .iter()
+        .any(|key| self.keys.contains_key(key));
+        has_keys.then_some(vertical)
+    }
+
+    /// The code of a key, unless it is [`USER_DEFINED`]
+    fn registered(&self, key: GeoKey) -> Option<u16> {
+        self.code(key).filter(|&code| code != USER_DEFINED)
+    }
+}
+
+/// At least `min_count` doubles
+fn doubles(entry: &BufferedEntry, min_count: usize) -> TiffResult<Vec<f64>> {
+    let values = Vec::<f64>::try_from(entry)?;
+    if values.len() < min_count {
+        return Err(TiffFormatError::InconsistentSizesEncountered(entry.into()).into());
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoder::directory::entry;
+
+    #[test]
+    fn test_geotransform() {
+        let mut ifd = Ifd::default();
+        ifd.insert_tag_data_from_buffer(&Tag::ImageWidth, entry(&10u32));
+        assert_eq!(geotransform(&ifd).unwrap(), None);
+
+        ifd.insert_tag_data_from_buffer(
+            &Tag::ModelTiepointTag,
+            entry(&[1.0f64, 2.0, 0.0, 100.0, 50.0, 0.0][..]),
+        );
+        ifd.insert_tag_data_from_buffer(&Tag::ModelPixelScaleTag, entry(&[0.5f64, 0.25, 0.0][..]));
+        let t = geotransform(&ifd).unwrap().unwrap();
+        assert_eq!(t, [99.5, 0.5, 0.0, 50.5, 0.0, -0.25]);
+        assert_eq!(
+            scale_geotransform(t, 2.0, 4.0),
+            [99.5, 1.0, 0.0, 50.5, 0.0, -1.0]
+        );
+
+        #[rustfmt::skip]
+        let matrix = [
+            2.0f64, 0.5, 0.0, 10.0,
+            0.5, -2.0, 0.0, 20.0,
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ];
+        ifd.insert_tag_data_from_buffer(&Tag::ModelTransformationTag, entry(&matrix[..]));
+        assert_eq!(
+            geotransform(&ifd).unwrap(),
+            Some([10.0, 2.0, 0.5, 20.0, 0.5, -2.0])
+        );
+
+        ifd.insert_tag_data_from_buffer(&Tag::ModelTransformationTag, entry(&[1.0f64; 4][..]));
+        assert!(geotransform(&ifd).is_err());
+    }
+
+    #[test]
+    fn test_geo_keys() {
+        let citation = "WGS 84 / UTM zone 33N|NAVD88|";
+        #[rustfmt::skip]
+        let directory: [u16; 40] = [
+            1, 1, 0, 9,
+            1024, 0, 1, 1,
+            1026, 34737, 22, 0,
+            2054, 0, 1, 9102,
+            2059, 34736, 1, 0,
+            3072, 0, 1, 32633,
+            3076, 0, 1, 9001,
+            4096, 0, 1, 5703,
+            4097, 34737, 7, 22,
+            4098, 0, 1, USER_DEFINED,
+        ];
+        let mut ifd = Ifd::default();
+        assert_eq!(GeoKeys::from_ifd(&ifd).unwrap(), None);
+        ifd.insert_tag_data_from_buffer(&Tag::GeoKeyDirectoryTag, entry(&directory[..]));
+        ifd.insert_tag_data_from_buffer(&Tag::GeoDoubleParamsTag, entry(&[298.25f64][..]));
+        // a key pointing at params the IFD doesn't have
+        assert!(GeoKeys::from_ifd(&ifd).is_err());
+        ifd.insert_tag_data_from_buffer(&Tag::GeoAsciiParamsTag, entry(citation));
+        let keys = GeoKeys::from_ifd(&ifd).unwrap().unwrap();
+
+        assert_eq!(keys.code(GeoKey::GTModelType), Some(1));
+        assert_eq!(keys.epsg(), Some(32633));
+        assert_eq!(keys.citation(), Some("WGS 84 / UTM zone 33N"));
+        assert_eq!(keys.geog_citation(), None);
+        assert_eq!(keys.linear_units(), Some(9001));
+        assert_eq!(keys.angular_units(), Some(9102));
+        assert_eq!(
+            keys.get(GeoKey::Unknown(2059)),
+            Some(&GeoKeyValue::Doubles(vec![298.25]))
+        );
+        assert_eq!(
+            keys.vertical(),
+            Some(VerticalCrs {
+                code: Some(5703),
+                citation: Some("NAVD88".into()),
+                datum: None,
+                units: None,
+            })
+        );
+
+        // a directory with fewer keys than it says
+        ifd.insert_tag_data_from_buffer(&Tag::GeoKeyDirectoryTag, entry(&directory[..36]));
+        assert!(GeoKeys::from_ifd(&ifd).is_err());
+        ifd.insert_tag_data_from_buffer(&Tag::GeoKeyDirectoryTag, entry(&[1u16, 1, 0, 0][..]));
+        let keys = GeoKeys::from_ifd(&ifd).unwrap().unwrap();
+        assert_eq!((keys.epsg(), keys.vertical()), (None, None));
+    }
+}
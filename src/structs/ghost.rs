@@ -0,0 +1,109 @@
+//! GDAL's "ghost" header, describing how a COG is laid out.
+//!
+//! GDAL writes it right after the TIFF header, before the first IFD, as
+//! `GDAL_STRUCTURAL_METADATA_SIZE=XXXXXX bytes\n` followed by that many bytes
+//! of `KEY=VALUE\n` lines. Readers that know the layout can e.g. fetch a tile
+//! together with its mask tile in a single request.
+
+use alloc::string::String;
+
+const PREFIX: &[u8] = b"GDAL_STRUCTURAL_METADATA_SIZE=";
+
+/// Layout options of a COG written by GDAL. Options that are absent are
+/// `false`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GhostHeader {
+    /// `LAYOUT=IFDS_BEFORE_DATA`: all IFDs precede the tile data
+    pub ifds_before_data: bool,
+    /// `BLOCK_ORDER=ROW_MAJOR`: tiles of a level are written row by row
+    pub block_order_row_major: bool,
+    /// `BLOCK_LEADER=SIZE_AS_UINT4`: the 4 bytes before each tile hold its
+    /// size
+    pub block_leader_size_as_uint4: bool,
+    /// `BLOCK_TRAILER=LAST_4_BYTES_REPEATED`: each tile is followed by a copy
+    /// of its last 4 bytes
+    pub block_trailer_last_4_bytes_repeated: bool,
+    /// `KNOWN_INCOMPATIBLE_EDITION=YES`: the file was modified after being
+    /// written, so the other options may not hold anymore
+    pub known_incompatible_edition: bool,
+    /// `MASK_INTERLEAVED_WITH_IMAGERY=YES`: each tile of the mask directly
+    /// follows the same tile of the image
+    pub mask_interleaved_with_imagery: bool,
+}
+
+impl GhostHeader {
+    /// Parse the ghost header at the start of `buf`, which starts right after
+    /// the TIFF header. `None` if there is none, or it is cut off.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let rest = buf.strip_prefix(PREFIX)?;
+        let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+        let size: usize = core::str::from_utf8(&rest[..digits]).ok()?.parse().ok()?;
+        let rest = rest[digits..].strip_prefix(b" bytes\n")?;
+        let content = String::from_utf8_lossy(rest.get(..size)?);
+
+        let mut header = GhostHeader::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim_end();
+            match key {
+                "LAYOUT" => header.ifds_before_data = value == "IFDS_BEFORE_DATA",
+                "BLOCK_ORDER" => header.block_order_row_major = value == "ROW_MAJOR",
+                "BLOCK_LEADER" => header.block_leader_size_as_uint4 = value == "SIZE_AS_UINT4",
+                "BLOCK_TRAILER" => {
+                    header.block_trailer_last_4_bytes_repeated = value == "LAST_4_BYTES_REPEATED"
+                }
+                "KNOWN_INCOMPATIBLE_EDITION" => header.known_incompatible_edition = value == "YES",
+                "MASK_INTERLEAVED_WITH_IMAGERY" => {
+                    header.mask_interleaved_with_imagery = value == "YES"
+                }
+                _ => {}
+            }
+        }
+        Some(header)
+    }
+
+    /// Bytes between the end of a tile and the start of the mask tile that
+    /// follows it, if masks are interleaved and the file wasn't edited since
+    pub fn mask_gap(&self) -> Option<u64> {
+        if !self.mask_interleaved_with_imagery || self.known_incompatible_edition {
+            return None;
+        }
+        Some(
+            4 * u64::from(self.block_trailer_last_4_bytes_repeated)
+                + 4 * u64::from(self.block_leader_size_as_uint4),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let content = "LAYOUT=IFDS_BEFORE_DATA\nBLOCK_ORDER=ROW_MAJOR\nBLOCK_LEADER=SIZE_AS_UINT4\nBLOCK_TRAILER=LAST_4_BYTES_REPEATED\nKNOWN_INCOMPATIBLE_EDITION=NO\nMASK_INTERLEAVED_WITH_IMAGERY=YES\n ";
+        let buf = alloc::format!(
+            "GDAL_STRUCTURAL_METADATA_SIZE={:06} bytes\n{content}",
+            content.len()
+        );
+        let header = GhostHeader::parse(buf.as_bytes()).unwrap();
+        assert_eq!(
+            header,
+            GhostHeader {
+                ifds_before_data: true,
+                block_order_row_major: true,
+                block_leader_size_as_uint4: true,
+                block_trailer_last_4_bytes_repeated: true,
+                known_incompatible_edition: false,
+                mask_interleaved_with_imagery: true,
+            }
+        );
+        assert_eq!(header.mask_gap(), Some(8));
+        // cut off
+        assert_eq!(GhostHeader::parse(&buf.as_bytes()[..60]), None);
+        // an IFD
+        assert_eq!(GhostHeader::parse(&[1, 0, 0, 1]), None);
+    }
+}
@@ -1,11 +1,12 @@
 use crate::{
-    decoder::{CogReader, EndianReader},
+    decoder::{EndianReader, Limits},
     error::{TiffError, TiffFormatError, TiffResult, UsageError},
-    structs::{BufferedEntry, IfdEntry, Tag},
+    io,
+    structs::{BufferedEntry, IfdEntry, Tag, TagType},
     ByteOrder,
 };
 
-use std::{collections::BTreeMap, io};
+use alloc::{collections::BTreeMap, vec::Vec};
 pub type Directory = BTreeMap<Tag, IfdEntry>;
 
 #[derive(Debug, PartialEq, Default)]
@@ -19,12 +20,15 @@ impl Ifd {
     /// Creates this ifd from a buffer.
     ///
     /// Tags that fit in the offset field are directly added as an
-    /// `IfdEntry::Value`, otherwise it will be a `type, count, offset` struct
+    /// `IfdEntry::Value`, otherwise it will be a `type, count, offset` struct.
+    /// Fails with [`TiffError::LimitsExceeded`] if there are more than
+    /// [`Limits::max_ifd_entries`] entries, or an entry is too large.
     pub fn from_buffer(
         buf: &[u8],
         // num_entries: u64,
         byte_order: ByteOrder,
         bigtiff: bool,
+        limits: &Limits,
     ) -> TiffResult<Self> {
         // let n_offset_bytes =
         let mut ifd = Ifd::default();
@@ -34,14 +38,60 @@ impl Ifd {
         } else {
             r.read_u16()?.into()
         };
+        Limits::check(num_entries, limits.max_ifd_entries)?;
         for _ in 0..num_entries {
             let tag = Tag::from_u16_exhaustive(r.read_u16()?);
             ifd.data
-                .insert(tag, IfdEntry::from_reader(&mut r, bigtiff)?);
+                .insert(tag, IfdEntry::from_reader(&mut r, bigtiff, limits)?);
         }
         Ok(ifd)
     }
 
+    /// Like [`Ifd::from_buffer`], but also reads the offset of the next IFD
+    /// that follows the entries
+    pub fn from_buffer_with_next(
+        buf: &[u8],
+        byte_order: ByteOrder,
+        bigtiff: bool,
+        limits: &Limits,
+    ) -> TiffResult<(Self, u64)> {
+        let ifd = Self::from_buffer(buf, byte_order, bigtiff, limits)?;
+        let mut r = EndianReader::wrap(buf, byte_order);
+        let num_entries: u64 = if bigtiff {
+            r.read_u64()?
+        } else {
+            r.read_u16()?.into()
+        };
+        let offset_len = if bigtiff { 8 } else { 4 };
+        let next_start = usize::try_from(Self::encoded_len(num_entries, bigtiff) - offset_len)?;
+        let mut r = EndianReader::wrap(
+            buf.get(next_start..)
+                .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?,
+            byte_order,
+        );
+        let next = if bigtiff {
+            r.read_u64()?
+        } else {
+            r.read_u32()?.into()
+        };
+        Ok((ifd, next))
+    }
+
+    /// Number of bytes an IFD with `num_entries` entries takes up, from its
+    /// entry count up to and including the next IFD offset
+    pub fn encoded_len(num_entries: u64, bigtiff: bool) -> u64 {
+        if bigtiff {
+            8 + 20 * num_entries + 8
+        } else {
+            2 + 12 * num_entries + 4
+        }
+    }
+
+    /// Iterate over all entries, ordered by tag
+    pub fn iter(&self) -> impl Iterator<Item = (&Tag, &IfdEntry)> {
+        self.data.iter()
+    }
+
     /// Get a tag. Will return None if the tag isn't present (in this tiff/Image)
     pub fn get_tag(&self, tag: &Tag) -> Option<&IfdEntry> {
         self.data.get(tag)
@@ -58,7 +108,7 @@ impl Ifd {
 
     /// Get a tag, returning error if not present or loaded
     pub fn require_tag_value(&self, tag: &Tag) -> TiffResult<&BufferedEntry> {
-        match self.require_tag(&tag)? {
+        match self.require_tag(tag)? {
             IfdEntry::Offset {
                 tag_type,
                 count,
@@ -84,22 +134,92 @@ impl Ifd {
         }
     }
 
+    /// The value of a tag holding a single unsigned integer, read straight
+    /// from the loaded entry. `None` if the tag is missing, not loaded, not a
+    /// single BYTE, SHORT, LONG or LONG8, or inconsistent.
+    ///
+    /// Never builds an error, which may clone the whole entry, so scanning
+    /// the metadata of many files doesn't allocate for each tag.
+    pub fn peek_u64(&self, tag: &Tag) -> Option<u64> {
+        let Some(IfdEntry::Value(entry)) = self.data.get(tag) else {
+            return None;
+        };
+        if entry.count != 1 {
+            return None;
+        }
+        match (entry.tag_type, entry.data()) {
+            (TagType::BYTE, &[b]) => Some(b.into()),
+            (TagType::SHORT, &[a, b]) => Some(u16::from_ne_bytes([a, b]).into()),
+            (TagType::LONG | TagType::IFD, data) => {
+                Some(u32::from_ne_bytes(data.try_into().ok()?).into())
+            }
+            (TagType::LONG8 | TagType::IFD8, data) => {
+                Some(u64::from_ne_bytes(data.try_into().ok()?))
+            }
+            _ => None,
+        }
+    }
+
+    /// Like [`Ifd::peek_u64`], `None` if the value doesn't fit in a `u16`
+    pub fn peek_u16(&self, tag: &Tag) -> Option<u16> {
+        self.peek_u64(tag)?.try_into().ok()
+    }
+
     pub fn contains_key(&self, tag: &Tag) -> bool {
         self.data.contains_key(tag)
     }
+
+    /// IFDs that this IFD points to through its `SubIfds` tag
+    pub fn sub_ifds(&self) -> &[Ifd] {
+        &self.sub_ifds
+    }
+
+    pub fn push_sub_ifd(&mut self, ifd: Ifd) {
+        self.sub_ifds.push(ifd)
+    }
+
+    /// Whether bit 2 (`FILETYPE_MASK`) of the `NewSubfileType` is set, making
+    /// the IFD the transparency mask of the image of the same size
+    pub fn is_transparency_mask(&self) -> TiffResult<bool> {
+        Ok(self
+            .get_tag_value(&Tag::NewSubfileType)?
+            .map(u32::try_from)
+            .transpose()?
+            .is_some_and(|subfile_type| subfile_type & 4 != 0))
+    }
+
+    /// Remove the IFDs of the SubIfds tag, to own them
+    pub fn take_sub_ifds(&mut self) -> Vec<Ifd> {
+        core::mem::take(&mut self.sub_ifds)
+    }
+
+    /// Insert an entry, returning the one it replaces
+    pub fn insert(&mut self, tag: Tag, entry: IfdEntry) -> Option<IfdEntry> {
+        self.data.insert(tag, entry)
+    }
+
     /// Put the data corresponding to tag in self
     ///
     /// Can be used like:
     /// ```
-    /// # let ifd = Ifd::default();
-    /// # ifd.data.insert(Tag::TileOffsets, IfdEntry::Offset(TagType::LONG8, 1, 42));
+    /// # use tiff2::{decoder::Limits, structs::{BufferedEntry, Ifd, IfdEntry, Tag}, util::fix_endianness, ByteOrder};
+    /// let byte_order = ByteOrder::LittleEndian;
+    /// let ifd_buf = [
+    ///     0x01, 0x00,                         // Number of entries (1)
+    ///     0x44, 0x01, 0x04, 0x00,             // Tag (TileOffsets), Type (LONG)
+    ///     0x02, 0x00, 0x00, 0x00,             // Count (2)
+    ///     0x2A, 0x00, 0x00, 0x00,             // Offset (42)
+    /// ];
+    /// let mut ifd = Ifd::from_buffer(&ifd_buf, byte_order, false, &Limits::default()).unwrap();
     /// let tag = Tag::TileOffsets;
-    /// if let IfdEntry::Offset(tag_type, count, offset) = ifd.get(Tag::TileOffsets) {
-    ///     let mut buf = BufferedEntry::new(tag_type, count);
-    ///     reader.read_tag_data(offset, &mut buf).await?;
-    ///     fix_endianness(&mut buf, byte_order);
-    ///     ifd.insert_tag_data_from_buffer(tag, buf);
+    /// if let Some(&IfdEntry::Offset { tag_type, count, offset }) = ifd.get_tag(&tag) {
+    ///     let mut buf = BufferedEntry::new(tag_type, count).unwrap();
+    ///     // read `buf.data.len()` bytes at `offset` into `buf.data`, e.g. using
+    ///     // `CogReader::read_tag_data`
+    ///     fix_endianness(&mut buf.data, byte_order, 8 * tag_type.primitive_size());
+    ///     ifd.insert_tag_data_from_buffer(&tag, buf);
     /// }
+    /// assert!(ifd.require_tag_value(&tag).is_ok());
     /// ```
     ///
     /// # returns
@@ -114,10 +234,86 @@ impl Ifd {
     }
 }
 
+#[cfg(test)]
 #[allow(unused_imports)]
 mod test_ifd {
     use super::*;
-    use crate::structs::{value::Value, TagType};
+    use crate::structs::value::Value;
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_next_ifd() {
+        let buf = [
+            1,0,                                   // n_tags
+            0,1, 3,0, 1,0,0,0, 42, 0, 0, 0,        // ImageWidth  SHORT 42
+            0x20,0,0,0,                            // next IFD
+        ];
+        let (ifd, next) = Ifd::from_buffer_with_next(&buf, ByteOrder::LittleEndian, false, &Limits::default()).unwrap();
+        assert_eq!(next, 0x20);
+        assert_eq!(ifd.iter().count(), 1);
+        assert_eq!(Ifd::encoded_len(1, false), buf.len() as u64);
+        assert!(Ifd::from_buffer_with_next(&buf[..14], ByteOrder::LittleEndian, false, &Limits::default()).is_err());
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut ifd = Ifd::default();
+        let mut insert = |tag, tag_type, count, data: &[u8]| {
+            let entry = BufferedEntry {
+                tag_type,
+                count,
+                data: data.to_vec(),
+            };
+            ifd.insert(tag, IfdEntry::Value(entry));
+        };
+        insert(Tag::ImageWidth, TagType::SHORT, 1, &300u16.to_ne_bytes());
+        insert(
+            Tag::ImageLength,
+            TagType::LONG8,
+            1,
+            &70_000u64.to_ne_bytes(),
+        );
+        insert(Tag::NewSubfileType, TagType::BYTE, 1, &[4]);
+        insert(Tag::BitsPerSample, TagType::SHORT, 2, &[8, 0, 8, 0]);
+        insert(Tag::XResolution, TagType::RATIONAL, 1, &[0; 8]);
+        // cut short
+        insert(Tag::RowsPerStrip, TagType::LONG, 1, &[1, 0]);
+        ifd.insert(
+            Tag::TileWidth,
+            IfdEntry::Offset {
+                tag_type: TagType::LONG,
+                count: 1,
+                offset: 100,
+            },
+        );
+        assert_eq!(ifd.peek_u64(&Tag::ImageWidth), Some(300));
+        assert_eq!(ifd.peek_u16(&Tag::ImageWidth), Some(300));
+        assert_eq!(ifd.peek_u64(&Tag::ImageLength), Some(70_000));
+        assert_eq!(ifd.peek_u16(&Tag::ImageLength), None);
+        assert_eq!(ifd.peek_u16(&Tag::NewSubfileType), Some(4));
+        for tag in [
+            Tag::BitsPerSample,
+            Tag::XResolution,
+            Tag::RowsPerStrip,
+            Tag::TileWidth,
+            Tag::Compression,
+        ] {
+            assert_eq!(ifd.peek_u64(&tag), None, "{tag:?}");
+        }
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_multiple_entries() {
+        let buf = [
+            2,0,                                   // n_tags
+            0,1, 3,0, 1,0,0,0, 42, 0, 0, 0,        // ImageWidth  SHORT 42
+            1,1, 4,0, 1,0,0,0, 43, 0, 0, 0,        // ImageLength LONG  43
+        ];
+        let ifd = Ifd::from_buffer(&buf, ByteOrder::LittleEndian, false, &Limits::default()).unwrap();
+        assert_eq!(u16::try_from(ifd.require_tag_value(&Tag::ImageWidth).unwrap()).unwrap(), 42);
+        assert_eq!(u32::try_from(ifd.require_tag_value(&Tag::ImageLength).unwrap()).unwrap(), 43);
+    }
 
     // -----------------------------------------------------------------
     // tests below are copy-pasted from Entry. Make sure to update there
@@ -161,7 +357,7 @@ mod test_ifd {
             println!("Trying {buf:?}, with {byte_order:?} should become {res:?}");
             let mut dir = Directory::new();
             dir.insert(Tag::from_u16_exhaustive(0x01_01), IfdEntry::Value(res.try_into().unwrap()));
-            assert_eq!(Ifd::from_buffer(&buf, byte_order, false).unwrap(), Ifd{
+            assert_eq!(Ifd::from_buffer(&buf, byte_order, false, &Limits::default()).unwrap(), Ifd{
                 sub_ifds: Vec::new(),
                 data: dir
             });
@@ -212,7 +408,7 @@ mod test_ifd {
             println!("Trying {buf:?}, with {byte_order:?} should become {res:?}");
             let mut dir = Directory::new();
             dir.insert(Tag::from_u16_exhaustive(0x01_01), IfdEntry::Value(res.try_into().unwrap()));
-            assert_eq!(Ifd::from_buffer(&buf, byte_order, true).unwrap(), Ifd{
+            assert_eq!(Ifd::from_buffer(&buf, byte_order, true, &Limits::default()).unwrap(), Ifd{
                 sub_ifds: Vec::new(),
                 data: dir
             });
@@ -251,7 +447,7 @@ mod test_ifd {
             println!("Trying {buf:?}, with {byte_order:?} should become {res:?}");
             let mut dir = Directory::new();
             dir.insert(Tag::from_u16_exhaustive(0x01_01), IfdEntry::Value(res.try_into().unwrap()));
-            assert_eq!(Ifd::from_buffer(&buf, byte_order, false).unwrap(), Ifd{
+            assert_eq!(Ifd::from_buffer(&buf, byte_order, false, &Limits::default()).unwrap(), Ifd{
                 sub_ifds: Vec::new(),
                 data: dir
             });
@@ -296,7 +492,7 @@ mod test_ifd {
             println!("Trying {buf:?}, with {byte_order:?} should become {res:?}");
             let mut dir = Directory::new();
             dir.insert(Tag::from_u16_exhaustive(0x01_01), IfdEntry::Value(res.try_into().unwrap()));
-            assert_eq!(Ifd::from_buffer(&buf, byte_order, true).unwrap(), Ifd{
+            assert_eq!(Ifd::from_buffer(&buf, byte_order, true, &Limits::default()).unwrap(), Ifd{
                 sub_ifds: Vec::new(),
                 data: dir
             });
@@ -345,7 +541,7 @@ mod test_ifd {
             println!("Trying {buf:?}, with {byte_order:?}");
             let mut dir = Directory::new();
             dir.insert(Tag::from_u16_exhaustive(0x01_01), IfdEntry::Offset { tag_type, count, offset: 42 });
-            assert_eq!(Ifd::from_buffer(&buf, byte_order, false).unwrap(), Ifd{
+            assert_eq!(Ifd::from_buffer(&buf, byte_order, false, &Limits::default()).unwrap(), Ifd{
                 sub_ifds: Vec::new(),
                 data: dir
             });
@@ -396,7 +592,7 @@ mod test_ifd {
             println!("Trying {buf:?}, with {byte_order:?}");
             let mut dir = Directory::new();
             dir.insert(Tag::from_u16_exhaustive(0x01_01), IfdEntry::Offset { tag_type, count, offset: 42 });
-            assert_eq!(Ifd::from_buffer(&buf, byte_order, true).unwrap(), Ifd{
+            assert_eq!(Ifd::from_buffer(&buf, byte_order, true, &Limits::default()).unwrap(), Ifd{
                 sub_ifds: Vec::new(),
                 data: dir
             });
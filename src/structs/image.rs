@@ -1,27 +1,106 @@
+#[cfg(feature = "std")]
+use crate::{decoder::CogReader, error::UsageError, structs::tags::TagType, util::fix_endianness};
 use crate::{
+    decoder::SampleType,
     error::{TiffError, TiffFormatError, TiffResult, TiffUnsupportedError},
+    nodata::Nodata,
     structs::{
         tags::{
-            CompressionMethod, PhotometricInterpretation, PlanarConfiguration, Predictor,
-            SampleFormat, Tag,
+            CompressionMethod, ExtraSample, Orientation, PhotometricInterpretation,
+            PlanarConfiguration, Predictor, SampleFormat, Tag,
         },
         BufferedEntry, Ifd, IfdEntry,
     },
-    ByteOrder, ChunkType,
+    ByteOrder, ChunkType, ColorType,
 };
 
-use std::{
-    collections::HashMap,
-    sync::{Arc, Condvar, Mutex, RwLock},
-};
-
-use super::tags::TagType;
+use alloc::{sync::Arc, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::{collections::BTreeMap, io, sync::Mutex};
+#[cfg(feature = "std")]
+use tokio::sync::OnceCell;
 
 #[derive(Debug, Clone)]
 pub struct StripDecodeState {
     pub rows_per_strip: u32,
 }
 
+/// Rectangle of pixels in an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Clamp to an image of the given size, `None` if nothing is left
+    fn clamp(&self, image_width: u32, image_height: u32) -> Option<Rect> {
+        self.intersection(&Rect::new(0, 0, image_width, image_height))
+    }
+
+    /// The part covered by both rectangles, `None` if they don't overlap
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let x_end = self
+            .x
+            .saturating_add(self.width)
+            .min(other.x.saturating_add(other.width));
+        let y_end = self
+            .y
+            .saturating_add(self.height)
+            .min(other.y.saturating_add(other.height));
+        (x < x_end && y < y_end).then(|| Rect::new(x, y, x_end - x, y_end - y))
+    }
+
+    /// Whether this lies within an image of the given size
+    pub fn fits_in(&self, image_width: u32, image_height: u32) -> bool {
+        self.x
+            .checked_add(self.width)
+            .is_some_and(|end| end <= image_width)
+            && self
+                .y
+                .checked_add(self.height)
+                .is_some_and(|end| end <= image_height)
+    }
+}
+
+/// Size in pixels of a decoded chunk, and of the part of it that lies within
+/// the image. Tiles in the last column and row are padded to the full tile
+/// size, the last strip only holds the rows that are left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkDims {
+    /// Width of the rows of the chunk, padding included
+    pub width: u32,
+    /// Number of rows of the chunk, padding included
+    pub height: u32,
+    /// Width of the part of each row that lies within the image
+    pub valid_width: u32,
+    /// Number of rows that lie within the image
+    pub valid_height: u32,
+}
+
+impl ChunkDims {
+    /// Number of columns and rows of padding on the right and bottom
+    pub fn padding(&self) -> (u32, u32) {
+        (
+            self.width - self.valid_width,
+            self.height - self.valid_height,
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Computed values useful for tile decoding
 pub struct TileAttributes {
@@ -34,10 +113,10 @@ pub struct TileAttributes {
 
 impl TileAttributes {
     pub fn tiles_across(&self) -> usize {
-        (self.image_width + self.tile_width - 1) / self.tile_width
+        self.image_width.div_ceil(self.tile_width)
     }
     pub fn tiles_down(&self) -> usize {
-        (self.image_height + self.tile_length - 1) / self.tile_length
+        self.image_height.div_ceil(self.tile_length)
     }
     fn padding_right(&self) -> usize {
         (self.tile_width - self.image_width % self.tile_width) % self.tile_width
@@ -45,6 +124,30 @@ impl TileAttributes {
     fn padding_down(&self) -> usize {
         (self.tile_length - self.image_height % self.tile_length) % self.tile_length
     }
+    /// Indices of the tiles that overlap `rect`, row by row
+    pub fn tiles_covering(&self, rect: &Rect) -> Vec<usize> {
+        let (Ok(width), Ok(height)) = (self.image_width.try_into(), self.image_height.try_into())
+        else {
+            return Vec::new();
+        };
+        let Some(rect) = rect.clamp(width, height) else {
+            return Vec::new();
+        };
+        let columns = rect.x as usize / self.tile_width
+            ..((rect.x + rect.width) as usize).div_ceil(self.tile_width);
+        let rows = rect.y as usize / self.tile_length
+            ..((rect.y + rect.height) as usize).div_ceil(self.tile_length);
+        rows.flat_map(|row| {
+            columns
+                .clone()
+                .map(move |column| row * self.tiles_across() + column)
+        })
+        .collect()
+    }
+
+    /// Columns and rows of padding of `tile`, which only tiles in the last
+    /// column and row have. See [`ChunkMetaData::chunk_dims`] for the size of
+    /// any chunk.
     pub fn get_padding(&self, tile: usize) -> (usize, usize) {
         let row = tile / self.tiles_across();
         let column = tile % self.tiles_across();
@@ -69,7 +172,8 @@ impl TileAttributes {
 /// (strip or tile).
 /// this does not include chunkoffsets or -bytes, since those may be partial and
 /// then mutated.
-pub struct ChunkOpts {
+#[derive(Debug, Clone)]
+pub struct ChunkMetaData {
     pub byte_order: ByteOrder,
     pub image_width: u32,
     pub image_height: u32,
@@ -84,75 +188,339 @@ pub struct ChunkOpts {
     pub chunk_type: ChunkType,
     pub strip_decoder: Option<StripDecodeState>,
     pub tile_attributes: Option<TileAttributes>,
+    /// Horizontal and vertical subsampling of the chroma samples of
+    /// uncompressed YCbCr, `(1, 1)` for everything else
+    pub ycbcr_subsampling: (u16, u16),
+    /// Value of pixels without data, from `GDAL_NODATA` and rounded to a
+    /// sample, see [`Nodata::from_ifd`]. `None` for samples that aren't
+    /// byte-aligned numbers.
+    pub nodata: Option<Nodata>,
 }
 
-// pub enum MaybePartial {
-//     Whole(BufferedEntry),
-//     Partial {
-//         // tag_type: TagType,
-//         offset: u64,
-//         chunk_size: usize,
-//         data: Arc<RwLock<HashMap<u64, BufferedEntry>>>,
-//         pending_chunks: Arc<Mutex<HashMap<u64, Condvar>>>,
-//     },
-// }
-
-// pub enum MaybePartialIndex<T> {
-//     Ok(T),
-//     NeedRead {
-//         offset: u64,
-//         count: u64,
-//         buf: Vec<u8>,
-//     },
-//     Pending(Condvar),
-// }
-
-// impl MaybePartial {
-//     fn get_u64(&self, index: usize) -> TiffResult<MaybePartialIndex<u64>> {
-//         match self {
-//             MaybePartial::Whole(e) => Ok(MaybePartialIndex::Ok(e.get_u64(index)?)),
-//             MaybePartial::Partial {
-//                 offset,
-//                 chunk_size,
-//                 data,
-//                 pending_chunks,
-//             } => {
-//                 let i_chunk: usize = index / chunk_size;
-//                 let subindex: usize = index % chunk_size;
-//                 if let Some(entry) = data.try_read()?.get(&i_chunk.try_into()?) {
-//                     Ok(MaybePartialIndex::Ok(entry.get_u64(subindex)?))
-//                 } else {
-//                     if let Some(cv) = pending_chunks.try_lock()?.get(&i_chunk.try_into()?) {
-//                         Ok(MaybePartialIndex::Pending(cv.clone()))
-//                     } else {
-//                         pending_chunks
-//                             .try_lock()?
-//                             .insert(i_chunk.try_into()?, Condvar::new());
-//                         Ok(MaybePartialIndex::NeedRead {
-//                             offset: *offset,
-//                             count: u64::try_from(*chunk_size)?,
-//                             buf: vec![0u8; *chunk_size],
-//                         })
-//                     }
-//                 }
-//             }
-//         }
-//     }
-// }
+impl ChunkMetaData {
+    /// Width and rows in pixels of a full chunk, padding included
+    pub(crate) fn full_chunk_dims(&self) -> Option<(u64, u64)> {
+        match (self.chunk_type, &self.tile_attributes, &self.strip_decoder) {
+            (ChunkType::Tile, Some(tile), _) => {
+                Some((tile.tile_width as u64, tile.tile_length as u64))
+            }
+            (ChunkType::Strip, _, Some(strip)) => Some((
+                self.image_width.into(),
+                u64::from(strip.rows_per_strip.min(self.image_height)),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Number of bytes of a full, decompressed chunk, if the tags needed to
+    /// determine it were given.
+    ///
+    /// Subsampled chroma is upsampled when decoding, so this is the size of a
+    /// chunk with every sample of every pixel. See [`Self::stored_chunk_len`]
+    /// for the size before that.
+    pub fn chunk_len(&self) -> Option<u64> {
+        let (width, rows) = self.full_chunk_dims()?;
+        let samples = match self.planar_config {
+            PlanarConfiguration::Chunky => u64::from(self.samples),
+            PlanarConfiguration::Planar => 1,
+        };
+        let row_bits = width * samples * u64::from(self.bits_per_sample);
+        Some(row_bits.div_ceil(8) * rows)
+    }
+
+    /// Whether chroma samples are subsampled, so chunks are stored with fewer
+    /// samples than [`Self::chunk_len`] accounts for
+    pub fn is_subsampled(&self) -> bool {
+        self.ycbcr_subsampling != (1, 1)
+    }
+
+    /// Number of bytes of a full chunk of sample `plane` as it is stored,
+    /// after decompression but before upsampling chroma.
+    ///
+    /// Chunky subsampled YCbCr is stored in blocks of `h * v` luma samples
+    /// followed by one Cb and one Cr sample, rows of blocks covering `v` rows
+    /// of pixels. Planar chroma chunks cover the same pixels as luma chunks,
+    /// with `h * v` times fewer samples.
+    pub fn stored_chunk_len(&self, plane: usize) -> Option<u64> {
+        if !self.is_subsampled() {
+            return self.chunk_len();
+        }
+        let (width, rows) = self.full_chunk_dims()?;
+        let (h, v) = (
+            u64::from(self.ycbcr_subsampling.0),
+            u64::from(self.ycbcr_subsampling.1),
+        );
+        let bits = u64::from(self.bits_per_sample);
+        match (self.planar_config, plane) {
+            (PlanarConfiguration::Chunky, _) => {
+                let block_row_bits = width.div_ceil(h) * (h * v + 2) * bits;
+                Some(block_row_bits.div_ceil(8) * rows.div_ceil(v))
+            }
+            (PlanarConfiguration::Planar, 0) => self.chunk_len(),
+            (PlanarConfiguration::Planar, _) => {
+                Some((width.div_ceil(h) * bits).div_ceil(8) * rows.div_ceil(v))
+            }
+        }
+    }
+
+    /// Width in pixels of the rows stored in a chunk, padding included
+    pub fn chunk_width(&self) -> Option<u32> {
+        match (self.chunk_type, &self.tile_attributes) {
+            (ChunkType::Tile, Some(tile)) => tile.tile_width.try_into().ok(),
+            (ChunkType::Tile, None) => None,
+            (ChunkType::Strip, _) => Some(self.image_width),
+        }
+    }
+
+    /// Size of chunk `i_chunk` as stored and of the part of it that lies
+    /// within the image, computed from the chunk layout. `None` if that is
+    /// unknown or the index is out of range.
+    pub fn chunk_dims(&self, i_chunk: usize) -> Option<ChunkDims> {
+        let (width, height) = self.full_chunk_dims()?;
+        let (_, rect) = self.chunk_rect(i_chunk)?;
+        Some(ChunkDims {
+            width: width.try_into().ok()?,
+            height: height.try_into().ok()?,
+            valid_width: rect.width,
+            valid_height: rect.height,
+        })
+    }
+
+    /// Sample plane of a chunk and the part of the image it covers, leaving
+    /// out the padding of tiles in the last column and row. `None` if the
+    /// chunk layout is unknown or the index is out of range.
+    pub fn chunk_rect(&self, i_chunk: usize) -> Option<(usize, Rect)> {
+        let (chunks_per_plane, rect) =
+            match (self.chunk_type, &self.tile_attributes, &self.strip_decoder) {
+                (ChunkType::Tile, Some(tile), _) => {
+                    let chunks_per_plane = tile.tiles_across() * tile.tiles_down();
+                    // empty images have no chunks
+                    let i_tile = i_chunk.checked_rem(chunks_per_plane)?;
+                    let (padding_right, padding_down) = tile.get_padding(i_tile);
+                    let rect = Rect::new(
+                        (i_tile % tile.tiles_across() * tile.tile_width)
+                            .try_into()
+                            .ok()?,
+                        (i_tile / tile.tiles_across() * tile.tile_length)
+                            .try_into()
+                            .ok()?,
+                        (tile.tile_width - padding_right).try_into().ok()?,
+                        (tile.tile_length - padding_down).try_into().ok()?,
+                    );
+                    (chunks_per_plane, rect)
+                }
+                (ChunkType::Strip, _, Some(strip)) if strip.rows_per_strip > 0 => {
+                    let chunks_per_plane =
+                        self.image_height.div_ceil(strip.rows_per_strip) as usize;
+                    let y = u32::try_from(i_chunk.checked_rem(chunks_per_plane)?).ok()?
+                        * strip.rows_per_strip;
+                    let rows = strip.rows_per_strip.min(self.image_height - y);
+                    (chunks_per_plane, Rect::new(0, y, self.image_width, rows))
+                }
+                _ => return None,
+            };
+        let plane = i_chunk / chunks_per_plane;
+        let planes = match self.planar_config {
+            PlanarConfiguration::Chunky => 1,
+            PlanarConfiguration::Planar => usize::from(self.samples),
+        };
+        (plane < planes).then_some((plane, rect))
+    }
+
+    /// Indices of the chunks that overlap `rect`, in all sample planes for
+    /// planar images. Empty if the chunk layout is unknown.
+    pub fn chunks_covering(&self, rect: &Rect) -> Vec<usize> {
+        let (chunks, chunks_per_plane) =
+            match (self.chunk_type, &self.tile_attributes, &self.strip_decoder) {
+                (ChunkType::Tile, Some(tile), _) => (
+                    tile.tiles_covering(rect),
+                    tile.tiles_across() * tile.tiles_down(),
+                ),
+                (ChunkType::Strip, _, Some(strip)) if strip.rows_per_strip > 0 => {
+                    let rows_per_strip = strip.rows_per_strip;
+                    let chunks = match rect.clamp(self.image_width, self.image_height) {
+                        Some(rect) => (rect.y / rows_per_strip
+                            ..(rect.y + rect.height).div_ceil(rows_per_strip))
+                            .map(|s| s as usize)
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    (chunks, self.image_height.div_ceil(rows_per_strip) as usize)
+                }
+                _ => return Vec::new(),
+            };
+        match self.planar_config {
+            PlanarConfiguration::Chunky => chunks,
+            PlanarConfiguration::Planar => (0..usize::from(self.samples))
+                .flat_map(|plane| chunks.iter().map(move |c| plane * chunks_per_plane + c))
+                .collect(),
+        }
+    }
+}
+
+/// Number of values per page of a [`MaybePartial::Partial`] entry, 32 KiB of
+/// LONG8 offsets
+#[cfg(feature = "std")]
+pub const PAGE_LEN: usize = 4096;
+
+/// Chunk offsets or byte counts, either loaded whole or a page at a time.
+///
+/// These arrays take 16 bytes per chunk in a BigTIFF, so tens of megabytes for
+/// a COG with millions of tiles, of which e.g. a map viewer only ever needs a
+/// few pages.
+#[derive(Debug, Clone)]
+pub enum MaybePartial {
+    /// All values, loaded with the IFD
+    Whole(BufferedEntry),
+    /// Values still in the file, loaded in pages as they are needed. Clones
+    /// share loaded pages.
+    #[cfg(feature = "std")]
+    Partial(Arc<PagedEntry>),
+}
+
+impl From<BufferedEntry> for MaybePartial {
+    fn from(entry: BufferedEntry) -> Self {
+        MaybePartial::Whole(entry)
+    }
+}
+
+impl MaybePartial {
+    /// Number of values
+    pub fn count(&self) -> u64 {
+        match self {
+            MaybePartial::Whole(entry) => entry.count,
+            #[cfg(feature = "std")]
+            MaybePartial::Partial(paged) => paged.count,
+        }
+    }
+
+    /// Value at `index`, failing with [`UsageError::PageNotLoaded`] if it
+    /// lies in a page that wasn't loaded yet
+    pub fn get_u64(&self, index: usize) -> TiffResult<u64> {
+        match self {
+            MaybePartial::Whole(entry) => entry.get_u64(index),
+            #[cfg(feature = "std")]
+            MaybePartial::Partial(paged) => paged.get_u64(index),
+        }
+    }
+
+    /// Value at `index`, loading its page from `reader` first if needed
+    #[cfg(feature = "std")]
+    pub async fn load_u64(&self, index: usize, reader: &dyn CogReader) -> TiffResult<u64> {
+        match self {
+            MaybePartial::Whole(entry) => entry.get_u64(index),
+            MaybePartial::Partial(paged) => paged.load_u64(index, reader).await,
+        }
+    }
+}
+
+/// Values of a tag that are read from the file a page at a time.
+///
+/// Concurrent requests for values in a page that is being read wait for that
+/// read instead of starting their own. A failed read is retried by the next
+/// request.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct PagedEntry {
+    tag_type: TagType,
+    count: u64,
+    /// Where the values start in the file
+    offset: u64,
+    byte_order: ByteOrder,
+    /// Values per page
+    page_len: usize,
+    pages: Mutex<BTreeMap<usize, Arc<OnceCell<BufferedEntry>>>>,
+}
+
+#[cfg(feature = "std")]
+impl PagedEntry {
+    /// `count` values of type `tag_type`, starting at `offset` in a file of
+    /// the given byte order, loaded `page_len` at a time
+    pub fn new(
+        tag_type: TagType,
+        count: u64,
+        offset: u64,
+        byte_order: ByteOrder,
+        page_len: usize,
+    ) -> Self {
+        PagedEntry {
+            tag_type,
+            count,
+            offset,
+            byte_order,
+            page_len: page_len.max(1),
+            pages: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Page holding `index`, and the index within it
+    fn page(&self, index: usize) -> TiffResult<(Arc<OnceCell<BufferedEntry>>, usize)> {
+        if usize::try_from(self.count)? <= index {
+            return Err(TiffError::LimitsExceeded);
+        }
+        let i_page = index / self.page_len;
+        let page = self.pages.lock()?.entry(i_page).or_default().clone();
+        Ok((page, index % self.page_len))
+    }
+
+    /// Value at `index`, if its page was loaded
+    pub fn get_u64(&self, index: usize) -> TiffResult<u64> {
+        let (page, i) = self.page(index)?;
+        match page.get() {
+            Some(page) => page.get_u64(i),
+            None => Err(UsageError::PageNotLoaded(index).into()),
+        }
+    }
+
+    /// Value at `index`, loading its page from `reader` first if needed
+    pub async fn load_u64(&self, index: usize, reader: &dyn CogReader) -> TiffResult<u64> {
+        let (page, i) = self.page(index)?;
+        let page = page
+            .get_or_try_init(|| async {
+                let first = u64::try_from(index - i)?;
+                let count = (self.count - first).min(u64::try_from(self.page_len)?);
+                let size = self.tag_type.size() as u64;
+                let mut data: Vec<u8> = reader
+                    .read_tag_data(self.offset + first * size, count * size)
+                    .await?
+                    .into();
+                if data.len() as u64 != count * size {
+                    return Err(TiffError::from(io::Error::from(
+                        io::ErrorKind::UnexpectedEof,
+                    )));
+                }
+                fix_endianness(
+                    &mut data,
+                    self.byte_order,
+                    8 * self.tag_type.primitive_size(),
+                );
+                Ok(BufferedEntry {
+                    tag_type: self.tag_type,
+                    count,
+                    data,
+                })
+            })
+            .await?;
+        page.get_u64(i)
+    }
+}
 
 /// Image struct that holds all relevant metadata for locating an image's data in the file and which decoding method to use
+#[derive(Debug)]
 pub struct Image {
     /// IFD holding all data
     pub ifd: Ifd,
     /// Data that doesn't change between chunks
-    pub chunk_opts: Arc<ChunkOpts>,
+    pub chunk_meta: Arc<ChunkMetaData>,
     /// Chunk offsets (maybe partially loaded)
-    pub chunk_offsets: BufferedEntry,
-    // Number of bytes per chunk (maybe partially loaded)
-    pub chunk_bytes: BufferedEntry,
+    pub chunk_offsets: MaybePartial,
+    /// Number of bytes per chunk (maybe partially loaded)
+    pub chunk_bytes: MaybePartial,
 }
 
-const IMAGE_TAGS: [Tag; 14] = [
+/// Tags needed to decode an image's chunks and convert their colors
+#[cfg(feature = "std")]
+pub(crate) const IMAGE_TAGS: [Tag; 19] = [
     Tag::ImageWidth,
     Tag::ImageLength,
     Tag::BitsPerSample,
@@ -167,13 +535,23 @@ const IMAGE_TAGS: [Tag; 14] = [
     Tag::StripOffsets,
     Tag::TileByteCounts,
     Tag::TileOffsets,
+    Tag::YCbCrCoefficients,
+    Tag::ReferenceBlackWhite,
+    Tag::ColorMap,
+    Tag::ExtraSamples,
+    Tag::GdalNodata,
 ];
 
-impl Image {
-    // pub fn chunk_offsets(&self) -> &BufferedEntry {
-    //     match self.
-    // }
+/// Chunk offsets and byte counts, which can be paged in instead
+#[cfg(feature = "std")]
+pub(crate) const CHUNK_TAGS: [Tag; 4] = [
+    Tag::StripByteCounts,
+    Tag::StripOffsets,
+    Tag::TileByteCounts,
+    Tag::TileOffsets,
+];
 
+impl Image {
     pub fn chunk_offset(&self, index: usize) -> TiffResult<u64> {
         self.chunk_offsets.get_u64(index)
     }
@@ -182,16 +560,105 @@ impl Image {
         self.chunk_bytes.get_u64(index)
     }
 
-    pub fn chunk_opts(&self) -> Arc<ChunkOpts> {
-        self.chunk_opts.clone()
+    pub fn chunk_meta(&self) -> Arc<ChunkMetaData> {
+        self.chunk_meta.clone()
     }
 
-    pub fn from_ifd(
-        // reader: &mut SmartReader<R>,
-        ifd: Ifd,
-        // limits: &Limits,
-        bigtiff: bool,
-    ) -> TiffResult<Image> {
+    /// Meaning of the samples after those of the photometric
+    /// interpretation, from `ExtraSamples`. Empty if there is no such tag.
+    pub fn extra_samples(&self) -> TiffResult<Vec<ExtraSample>> {
+        let Some(entry) = self.ifd.get_tag_value(&Tag::ExtraSamples)? else {
+            return Ok(Vec::new());
+        };
+        (0..usize::try_from(entry.count)?)
+            .map(|i| {
+                Ok(ExtraSample::from_u16_exhaustive(u16::try_from(
+                    entry.get_u64(i)?,
+                )?))
+            })
+            .collect()
+    }
+
+    /// Value of pixels without data, see [`ChunkMetaData::nodata`]
+    pub fn nodata(&self) -> Option<Nodata> {
+        self.chunk_meta.nodata
+    }
+
+    /// How the stored pixels are displayed, from `Orientation`. `TopLeft` if
+    /// there is no such tag, which overviews often leave out, see
+    /// [`CogDecoder::decode_region_oriented`] for taking that of the full
+    /// resolution image instead.
+    ///
+    /// [`CogDecoder::decode_region_oriented`]: crate::decoder::CogDecoder::decode_region_oriented
+    pub fn orientation(&self) -> TiffResult<Orientation> {
+        Orientation::from_ifd(&self.ifd)
+    }
+
+    /// Band holding alpha, the first extra sample that is associated or
+    /// unassociated alpha, and which of the two it is. `None` if there is no
+    /// alpha.
+    pub fn alpha(&self) -> TiffResult<Option<(usize, ExtraSample)>> {
+        let extra_samples = self.extra_samples()?;
+        // extra samples come last
+        let first = usize::from(self.chunk_meta.samples).saturating_sub(extra_samples.len());
+        Ok(extra_samples
+            .into_iter()
+            .enumerate()
+            .find(|(_, sample)| {
+                matches!(
+                    sample,
+                    ExtraSample::AssociatedAlpha | ExtraSample::UnassociatedAlpha
+                )
+            })
+            .map(|(i, sample)| (first + i, sample)))
+    }
+
+    /// Color type of the decoded samples.
+    ///
+    /// Gray and RGB with alpha as their only extra sample are
+    /// [`ColorType::GrayA`] and [`ColorType::RGBA`], see [`Image::alpha`] for
+    /// whether it is associated. Other samples than those of the photometric
+    /// interpretation, unspecified extra samples included, make for
+    /// [`ColorType::Multiband`].
+    pub fn color_type(&self) -> TiffResult<ColorType> {
+        let meta = &self.chunk_meta;
+        let bits = meta.bits_per_sample;
+        let alpha = self.alpha()?.map(|(band, _)| band);
+        Ok(
+            match (meta.photometric_interpretation, meta.samples, alpha) {
+                (
+                    PhotometricInterpretation::WhiteIsZero | PhotometricInterpretation::BlackIsZero,
+                    1,
+                    _,
+                ) => ColorType::Gray(bits),
+                (
+                    PhotometricInterpretation::WhiteIsZero | PhotometricInterpretation::BlackIsZero,
+                    2,
+                    Some(1),
+                ) => ColorType::GrayA(bits),
+                (PhotometricInterpretation::RGB, 3, _) => ColorType::RGB(bits),
+                (PhotometricInterpretation::RGB, 4, Some(3)) => ColorType::RGBA(bits),
+                (PhotometricInterpretation::RGBPalette, 1, _) => ColorType::Palette(bits),
+                (PhotometricInterpretation::CMYK, 4, _) => ColorType::CMYK(bits),
+                (PhotometricInterpretation::YCbCr, 3, _) => ColorType::YCbCr(bits),
+                (PhotometricInterpretation::CIELab | PhotometricInterpretation::ICCLab, 3, _) => {
+                    ColorType::Lab(bits)
+                }
+                (_, samples, _) => ColorType::Multiband {
+                    bit_depth: bits,
+                    num_samples: samples,
+                },
+            },
+        )
+    }
+
+    /// Resolve everything needed to decode the chunks of the image described
+    /// by `ifd`, in a file of the given byte order.
+    ///
+    /// The chunk offsets and byte counts, and any other tag data that doesn't
+    /// fit in its entry, must have been loaded, otherwise this fails with
+    /// `UsageError::RequiredTagNotLoaded`.
+    pub fn from_ifd(ifd: Ifd, byte_order: ByteOrder) -> TiffResult<Image> {
         // ------------------------------
         // Tags that fit in offset fields
         // ------------------------------
@@ -251,154 +718,466 @@ impl Image {
 
         let planes = match planar_config {
             PlanarConfiguration::Chunky => 1,
-            PlanarConfiguration::Planar => samples,
+            PlanarConfiguration::Planar => usize::from(samples),
+        };
+
+        // ----------------------------------
+        // Tags that may not fit, one per sample
+        // ----------------------------------
+        let jpeg_tables = match ifd.get_tag_value(&Tag::JPEGTables)? {
+            // the header of an old-style JPEG stream is loaded as such
+            Some(tables)
+                if matches!(
+                    compression_method,
+                    CompressionMethod::JPEG | CompressionMethod::ModernJPEG
+                ) =>
+            {
+                if tables.data.len() < 2 {
+                    return Err(TiffError::FormatError(
+                        TiffFormatError::InvalidTagValueType(Tag::JPEGTables.to_u16()),
+                    ));
+                }
+                Some(tables.clone())
+            }
+            _ => None,
         };
 
-        // let jpeg_tables = if compression_method == CompressionMethod::ModernJPEG
-        //     && ifd.contains_key(&Tag::JPEGTables)
-        // {
-        //     let vec = ifd.find_tag(Tag::JPEGTables)?.unwrap().into_u8_vec()?;
-        //     if vec.len() < 2 {
-        //         return Err(TiffError::FormatError(
-        //             TiffFormatError::InvalidTagValueType(Tag::JPEGTables.to_u16()),
-        //         ));
-        //     }
-
-        //     Some(Arc::new(vec))
-        // } else {
-        //     None
-        // };
-
-        // let sample_format = match tag_reader.find_tag_uint_vec(Tag::SampleFormat)? {
-        //     Some(vals) => {
-        //         let sample_format: Vec<_> = vals
-        //             .into_iter()
-        //             .map(SampleFormat::from_u16_exhaustive)
-        //             .collect();
-
-        //         // TODO: for now, only homogenous formats across samples are supported.
-        //         if !sample_format.windows(2).all(|s| s[0] == s[1]) {
-        //             return Err(TiffUnsupportedError::UnsupportedSampleFormat(sample_format).into());
-        //         }
-
-        //         sample_format[0]
-        //     }
-        //     None => SampleFormat::Uint,
-        // };
-
-        // let bits_per_sample: Vec<u8> = tag_reader
-        //     .find_tag_uint_vec(Tag::BitsPerSample)?
-        //     .unwrap_or_else(|| vec![1]);
-
-        // // Technically bits_per_sample.len() should be *equal* to samples, but libtiff also allows
-        // // it to be a single value that applies to all samples.
-        // if bits_per_sample.len() != usize::from(samples) && bits_per_sample.len() != 1 {
-        //     return Err(TiffError::FormatError(
-        //         TiffFormatError::InconsistentSizesEncountered,
-        //     ));
-        // }
-
-        // // This library (and libtiff) do not support mixed sample formats and zero bits per sample
-        // // doesn't make sense.
-        // if bits_per_sample.iter().any(|&b| b != bits_per_sample[0]) || bits_per_sample[0] == 0 {
-        //     return Err(TiffUnsupportedError::InconsistentBitsPerSample(bits_per_sample).into());
-        // }
-
-        // let chunk_type;
-        // let chunk_offsets;
-        // let chunk_bytes;
-        // let strip_decoder;
-        // let tile_attributes;
-        // match (
-        //     ifd.contains_key(&Tag::StripByteCounts),
-        //     ifd.contains_key(&Tag::StripOffsets),
-        //     ifd.contains_key(&Tag::TileByteCounts),
-        //     ifd.contains_key(&Tag::TileOffsets),
-        // ) {
-        //     (true, true, false, false) => {
-        //         chunk_type = ChunkType::Strip;
-
-        //         chunk_offsets = tag_reader
-        //             .find_tag(Tag::StripOffsets)?
-        //             .unwrap()
-        //             .into_u64_vec()?;
-        //         chunk_bytes = tag_reader
-        //             .find_tag(Tag::StripByteCounts)?
-        //             .unwrap()
-        //             .into_u64_vec()?;
-        //         let rows_per_strip = tag_reader
-        //             .find_tag(Tag::RowsPerStrip)?
-        //             .map(Value::into_u32)
-        //             .transpose()?
-        //             .unwrap_or(height);
-        //         strip_decoder = Some(StripDecodeState { rows_per_strip });
-        //         tile_attributes = None;
-
-        //         if chunk_offsets.len() != chunk_bytes.len()
-        //             || rows_per_strip == 0
-        //             || u32::try_from(chunk_offsets.len())?
-        //                 != (height.saturating_sub(1) / rows_per_strip + 1) * planes as u32
-        //         {
-        //             return Err(TiffError::FormatError(
-        //                 TiffFormatError::InconsistentSizesEncountered,
-        //             ));
-        //         }
-        //     }
-        //     (false, false, true, true) => {
-        //         chunk_type = ChunkType::Tile;
-
-        //         let tile_width =
-        //             usize::try_from(tag_reader.require_tag(Tag::TileWidth)?.into_u32()?)?;
-        //         let tile_length =
-        //             usize::try_from(tag_reader.require_tag(Tag::TileLength)?.into_u32()?)?;
-
-        //         if tile_width == 0 {
-        //             return Err(TiffFormatError::InvalidTagValueType(Tag::TileWidth).into());
-        //         } else if tile_length == 0 {
-        //             return Err(TiffFormatError::InvalidTagValueType(Tag::TileLength).into());
-        //         }
-
-        //         strip_decoder = None;
-        //         tile_attributes = Some(TileAttributes {
-        //             image_width: usize::try_from(width)?,
-        //             image_height: usize::try_from(height)?,
-        //             tile_width,
-        //             tile_length,
-        //         });
-        //         chunk_offsets = tag_reader
-        //             .find_tag(Tag::TileOffsets)?
-        //             .unwrap()
-        //             .into_u64_vec()?;
-        //         chunk_bytes = tag_reader
-        //             .find_tag(Tag::TileByteCounts)?
-        //             .unwrap()
-        //             .into_u64_vec()?;
-
-        //         let tile = tile_attributes.as_ref().unwrap();
-        //         if chunk_offsets.len() != chunk_bytes.len()
-        //             || chunk_offsets.len()
-        //                 != tile.tiles_down() * tile.tiles_across() * planes as usize
-        //         {
-        //             return Err(TiffError::FormatError(
-        //                 TiffFormatError::InconsistentSizesEncountered,
-        //             ));
-        //         }
-        //     }
-        //     (_, _, _, _) => {
-        //         return Err(TiffError::FormatError(
-        //             TiffFormatError::StripTileTagConflict,
-        //         ))
-        //     }
-        // };
-        todo!()
+        let sample_format = match ifd.get_tag_value(&Tag::SampleFormat)? {
+            Some(val) => {
+                let sample_format = (0..usize::try_from(val.count)?)
+                    .map(|i| {
+                        Ok(SampleFormat::from_u16_exhaustive(u16::try_from(
+                            val.get_u64(i)?,
+                        )?))
+                    })
+                    .collect::<TiffResult<Vec<_>>>()?;
+
+                // TODO: for now, only homogenous formats across samples are supported.
+                if sample_format.is_empty() || !sample_format.windows(2).all(|s| s[0] == s[1]) {
+                    return Err(TiffUnsupportedError::UnsupportedSampleFormat(sample_format).into());
+                }
+
+                sample_format[0]
+            }
+            None => SampleFormat::Uint,
+        };
+
+        let bits_per_sample = match ifd.get_tag_value(&Tag::BitsPerSample)? {
+            Some(val) => {
+                let bits_per_sample = (0..usize::try_from(val.count)?)
+                    .map(|i| Ok(u8::try_from(val.get_u64(i)?)?))
+                    .collect::<TiffResult<Vec<u8>>>()?;
+                // Technically bits_per_sample.len() should be *equal* to samples, but libtiff also allows
+                // it to be a single value that applies to all samples.
+                if bits_per_sample.len() != usize::from(samples) && bits_per_sample.len() != 1 {
+                    return Err(TiffFormatError::InconsistentSizesEncountered(val.into()).into());
+                }
+                bits_per_sample
+            }
+            None => vec![1],
+        };
+
+        // This library (and libtiff) do not support mixed sample formats and zero bits per sample
+        // doesn't make sense.
+        if bits_per_sample.iter().any(|&b| b != bits_per_sample[0]) || bits_per_sample[0] == 0 {
+            return Err(TiffUnsupportedError::InconsistentBitsPerSample(bits_per_sample).into());
+        }
+
+        // Subsampling is only stored as is by uncompressed YCbCr, JPEG takes
+        // care of it itself
+        let stores_subsampling = photometric_interpretation == PhotometricInterpretation::YCbCr
+            && samples == 3
+            && !matches!(
+                compression_method,
+                CompressionMethod::JPEG | CompressionMethod::ModernJPEG
+            );
+        let ycbcr_subsampling = match ifd.get_tag_value(&Tag::YCbCrSubSampling)? {
+            _ if !stores_subsampling => (1, 1),
+            Some(val) if val.count == 2 => (
+                u16::try_from(val.get_u64(0)?)?,
+                u16::try_from(val.get_u64(1)?)?,
+            ),
+            Some(_) => {
+                return Err(
+                    TiffFormatError::InvalidTagValueType(Tag::YCbCrSubSampling.to_u16()).into(),
+                )
+            }
+            // the default of the spec
+            None => (2, 2),
+        };
+        let (h, v) = ycbcr_subsampling;
+        if ![1, 2, 4].contains(&h) || ![1, 2, 4].contains(&v) || v > h {
+            return Err(
+                TiffFormatError::InvalidTagValueType(Tag::YCbCrSubSampling.to_u16()).into(),
+            );
+        }
+
+        let nodata = match SampleType::from_format(sample_format, bits_per_sample[0]) {
+            Some(sample_type) => Nodata::from_ifd(&ifd, sample_type)?,
+            None => None,
+        };
+
+        // ----------------------
+        // Strips or tiles
+        // ----------------------
+        let (chunk_type, chunk_offsets, chunk_bytes, strip_decoder, tile_attributes) = match (
+            ifd.contains_key(&Tag::StripByteCounts),
+            ifd.contains_key(&Tag::StripOffsets),
+            ifd.contains_key(&Tag::TileByteCounts),
+            ifd.contains_key(&Tag::TileOffsets),
+        ) {
+            (true, true, false, false) => {
+                let chunk_offsets = chunk_entry(&ifd, Tag::StripOffsets, byte_order)?;
+                let chunk_bytes = chunk_entry(&ifd, Tag::StripByteCounts, byte_order)?;
+                let rows_per_strip = ifd
+                    .get_tag_value(&Tag::RowsPerStrip)?
+                    .map(u32::try_from)
+                    .transpose()?
+                    .unwrap_or(height);
+                if rows_per_strip == 0 {
+                    return Err(
+                        TiffFormatError::InvalidTagValueType(Tag::RowsPerStrip.to_u16()).into(),
+                    );
+                }
+                let n_strips = u64::from(height.div_ceil(rows_per_strip)) * planes as u64;
+                check_chunk_count(Tag::StripOffsets, &chunk_offsets, n_strips)?;
+                check_chunk_count(Tag::StripByteCounts, &chunk_bytes, n_strips)?;
+                (
+                    ChunkType::Strip,
+                    chunk_offsets,
+                    chunk_bytes,
+                    Some(StripDecodeState { rows_per_strip }),
+                    None,
+                )
+            }
+            (false, false, true, true) => {
+                let tile_width =
+                    usize::try_from(u32::try_from(ifd.require_tag_value(&Tag::TileWidth)?)?)?;
+                let tile_length =
+                    usize::try_from(u32::try_from(ifd.require_tag_value(&Tag::TileLength)?)?)?;
+                if tile_width == 0 {
+                    return Err(
+                        TiffFormatError::InvalidTagValueType(Tag::TileWidth.to_u16()).into(),
+                    );
+                } else if tile_length == 0 {
+                    return Err(
+                        TiffFormatError::InvalidTagValueType(Tag::TileLength.to_u16()).into(),
+                    );
+                }
+                let tile = TileAttributes {
+                    image_width: usize::try_from(width)?,
+                    image_height: usize::try_from(height)?,
+                    tile_width,
+                    tile_length,
+                };
+                let chunk_offsets = chunk_entry(&ifd, Tag::TileOffsets, byte_order)?;
+                let chunk_bytes = chunk_entry(&ifd, Tag::TileByteCounts, byte_order)?;
+                let n_tiles = (tile.tiles_down() * tile.tiles_across() * planes) as u64;
+                check_chunk_count(Tag::TileOffsets, &chunk_offsets, n_tiles)?;
+                check_chunk_count(Tag::TileByteCounts, &chunk_bytes, n_tiles)?;
+                (
+                    ChunkType::Tile,
+                    chunk_offsets,
+                    chunk_bytes,
+                    None,
+                    Some(tile),
+                )
+            }
+            (_, _, _, _) => {
+                return Err(TiffError::FormatError(
+                    TiffFormatError::StripTileTagConflict,
+                ))
+            }
+        };
+        Ok(Image {
+            chunk_meta: Arc::new(ChunkMetaData {
+                byte_order,
+                image_width: width,
+                image_height: height,
+                bits_per_sample: bits_per_sample[0],
+                samples,
+                sample_format,
+                photometric_interpretation,
+                compression_method,
+                predictor,
+                jpeg_tables,
+                planar_config,
+                chunk_type,
+                strip_decoder,
+                tile_attributes,
+                ycbcr_subsampling,
+                nodata,
+            }),
+            chunk_offsets,
+            chunk_bytes,
+            ifd,
+        })
+    }
+}
+
+/// Chunk offsets or byte counts, paged if they weren't loaded with the IFD
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+fn chunk_entry(ifd: &Ifd, tag: Tag, byte_order: ByteOrder) -> TiffResult<MaybePartial> {
+    match ifd.require_tag(&tag)? {
+        IfdEntry::Value(entry) => Ok(entry.clone().into()),
+        #[cfg(feature = "std")]
+        IfdEntry::Offset {
+            tag_type,
+            count,
+            offset,
+        } => Ok(MaybePartial::Partial(Arc::new(PagedEntry::new(
+            *tag_type, *count, *offset, byte_order, PAGE_LEN,
+        )))),
+        #[cfg(not(feature = "std"))]
+        IfdEntry::Offset { .. } => Ok(ifd.require_tag_value(&tag)?.clone().into()),
+    }
+}
+
+/// Fail unless `entry` has a value for each of `expected` chunks
+fn check_chunk_count(tag: Tag, entry: &MaybePartial, expected: u64) -> TiffResult<()> {
+    if entry.count() != expected {
+        return Err(TiffFormatError::InconsistentChunkCount {
+            tag,
+            count: entry.count(),
+            expected,
+        }
+        .into());
     }
+    Ok(())
 }
 
+#[cfg(test)]
 mod test {
-    use crate::structs::tags::TagType;
+    use crate::{encoder::directory::entry, structs::tags::TagType};
 
     use super::*;
 
+    /// IFD with the given SHORT or LONG tags
+    fn ifd_with(tags: &[(Tag, BufferedEntry)]) -> Ifd {
+        let mut ifd = Ifd::default();
+        for (tag, value) in tags {
+            ifd.insert_tag_data_from_buffer(tag, value.clone());
+        }
+        ifd
+    }
+
+    #[cfg(feature = "std")]
+    #[tokio::test]
+    async fn test_paged_entry() {
+        use crate::decoder::{ObservedReader, ReadKind, ReadMetrics};
+        use futures_lite::future;
+
+        // 10 big-endian LONGs after 4 bytes of something else
+        let mut file = vec![0xff; 4];
+        for v in 0..10u32 {
+            file.extend_from_slice(&(100 * v).to_be_bytes());
+        }
+        let metrics = Arc::new(ReadMetrics::default());
+        let reader = ObservedReader::new(file, metrics.clone());
+        let paged = MaybePartial::Partial(Arc::new(PagedEntry::new(
+            TagType::LONG,
+            10,
+            4,
+            ByteOrder::BigEndian,
+            4,
+        )));
+        assert_eq!(paged.count(), 10);
+        assert!(matches!(
+            paged.get_u64(9),
+            Err(TiffError::UsageError(UsageError::PageNotLoaded(9)))
+        ));
+        // the last page is short
+        assert_eq!(paged.load_u64(9, &reader).await.unwrap(), 900);
+        assert_eq!(paged.get_u64(8).unwrap(), 800);
+        assert!(paged.get_u64(10).is_err());
+        assert!(paged.load_u64(10, &reader).await.is_err());
+        assert_eq!(metrics.get(ReadKind::TagData).requests, 1);
+
+        // a clone shares pages, and concurrent loads share a read
+        let clone = paged.clone();
+        let values = future::zip(paged.load_u64(1, &reader), clone.load_u64(2, &reader)).await;
+        assert_eq!((values.0.unwrap(), values.1.unwrap()), (100, 200));
+        assert_eq!(metrics.get(ReadKind::TagData).requests, 2);
+        assert_eq!(paged.get_u64(3).unwrap(), 300);
+    }
+
+    #[test]
+    fn test_from_ifd_strips() {
+        // 3 planes of 3 strips of 2 rows
+        let ifd = ifd_with(&[
+            (Tag::ImageWidth, entry(&10u32)),
+            (Tag::ImageLength, entry(&5u32)),
+            (Tag::PhotometricInterpretation, entry(&2u16)),
+            (Tag::SamplesPerPixel, entry(&3u16)),
+            (Tag::BitsPerSample, entry(&[16u16, 16, 16][..])),
+            (Tag::PlanarConfiguration, entry(&2u16)),
+            (Tag::RowsPerStrip, entry(&2u32)),
+            (Tag::StripOffsets, entry(&[100u32; 9][..])),
+            (Tag::StripByteCounts, entry(&[40u32; 9][..])),
+        ]);
+        let image = Image::from_ifd(ifd, ByteOrder::BigEndian).unwrap();
+        let meta = image.chunk_meta();
+        assert_eq!(meta.byte_order, ByteOrder::BigEndian);
+        assert_eq!(meta.chunk_type, ChunkType::Strip);
+        assert_eq!(meta.bits_per_sample, 16);
+        assert_eq!(meta.sample_format, SampleFormat::Uint);
+        assert_eq!(
+            meta.photometric_interpretation,
+            PhotometricInterpretation::RGB
+        );
+        assert_eq!(meta.chunk_len(), Some(40));
+        assert_eq!(meta.chunk_rect(8), Some((2, Rect::new(0, 4, 10, 1))));
+        // the last strip only holds the row that is left
+        let dims = meta.chunk_dims(8).unwrap();
+        assert_eq!((dims.width, dims.height), (10, 2));
+        assert_eq!(dims.padding(), (0, 1));
+        assert_eq!(image.chunk_offset(8).unwrap(), 100);
+
+        // one strip less than needed
+        let ifd = ifd_with(&[
+            (Tag::ImageWidth, entry(&10u32)),
+            (Tag::ImageLength, entry(&5u32)),
+            (Tag::PhotometricInterpretation, entry(&1u16)),
+            (Tag::RowsPerStrip, entry(&2u32)),
+            (Tag::StripOffsets, entry(&[100u32; 2][..])),
+            (Tag::StripByteCounts, entry(&[40u32; 2][..])),
+        ]);
+        assert!(matches!(
+            Image::from_ifd(ifd, ByteOrder::LittleEndian),
+            Err(TiffError::FormatError(
+                TiffFormatError::InconsistentChunkCount {
+                    tag: Tag::StripOffsets,
+                    count: 2,
+                    expected: 3,
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_from_ifd_tiles() {
+        // 3x2 tiles of doubles
+        let tags = [
+            (Tag::ImageWidth, entry(&40u32)),
+            (Tag::ImageLength, entry(&20u32)),
+            (Tag::PhotometricInterpretation, entry(&1u16)),
+            (Tag::BitsPerSample, entry(&64u16)),
+            (Tag::SampleFormat, entry(&3u16)),
+            (Tag::Predictor, entry(&3u16)),
+            (Tag::TileWidth, entry(&16u32)),
+            (Tag::TileLength, entry(&16u32)),
+            (Tag::TileOffsets, entry(&[0u64, 1, 2, 3, 4, 5][..])),
+            (Tag::TileByteCounts, entry(&[2048u64; 6][..])),
+        ];
+        let image = Image::from_ifd(ifd_with(&tags), ByteOrder::LittleEndian).unwrap();
+        let meta = image.chunk_meta();
+        assert_eq!(meta.chunk_type, ChunkType::Tile);
+        assert_eq!(meta.sample_format, SampleFormat::IEEEFP);
+        assert_eq!(meta.predictor, Predictor::FloatingPoint);
+        assert_eq!(meta.chunk_len(), Some(2048));
+        assert_eq!(meta.chunk_rect(5), Some((0, Rect::new(32, 16, 8, 4))));
+        assert_eq!(
+            meta.chunk_dims(5),
+            Some(ChunkDims {
+                width: 16,
+                height: 16,
+                valid_width: 8,
+                valid_height: 4,
+            })
+        );
+        assert_eq!(meta.chunk_dims(1).unwrap().padding(), (0, 0));
+        assert_eq!(meta.chunk_dims(6), None);
+        assert_eq!(image.chunk_offset(4).unwrap(), 4);
+        // an empty image has no chunks
+        let mut empty = (*meta).clone();
+        empty.tile_attributes.as_mut().unwrap().image_width = 0;
+        assert_eq!(empty.chunk_rect(0), None);
+
+        // both strips and tiles
+        let mut ifd = ifd_with(&tags);
+        ifd.insert_tag_data_from_buffer(&Tag::StripOffsets, entry(&0u32));
+        ifd.insert_tag_data_from_buffer(&Tag::StripByteCounts, entry(&0u32));
+        assert!(matches!(
+            Image::from_ifd(ifd, ByteOrder::LittleEndian),
+            Err(TiffError::FormatError(
+                TiffFormatError::StripTileTagConflict
+            ))
+        ));
+        // mixed bit depths
+        let mut ifd = ifd_with(&tags);
+        ifd.insert_tag_data_from_buffer(&Tag::SamplesPerPixel, entry(&2u16));
+        ifd.insert_tag_data_from_buffer(&Tag::BitsPerSample, entry(&[8u16, 16][..]));
+        assert!(matches!(
+            Image::from_ifd(ifd, ByteOrder::LittleEndian),
+            Err(TiffError::UnsupportedError(
+                TiffUnsupportedError::InconsistentBitsPerSample(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_color_type() {
+        let image = |photometric: u16, bits: &[u16], extra_samples: &[u16]| {
+            let mut tags = vec![
+                (Tag::ImageWidth, entry(&4u32)),
+                (Tag::ImageLength, entry(&4u32)),
+                (Tag::PhotometricInterpretation, entry(&photometric)),
+                (Tag::SamplesPerPixel, entry(&(bits.len() as u16))),
+                (Tag::BitsPerSample, entry(bits)),
+                (Tag::StripOffsets, entry(&8u32)),
+                (Tag::StripByteCounts, entry(&(16 * bits.len() as u32))),
+            ];
+            if !extra_samples.is_empty() {
+                tags.push((Tag::ExtraSamples, entry(extra_samples)));
+            }
+            Image::from_ifd(ifd_with(&tags), ByteOrder::LittleEndian).unwrap()
+        };
+
+        let rgba = image(2, &[8; 4], &[1]);
+        assert_eq!(rgba.color_type().unwrap(), ColorType::RGBA(8));
+        assert_eq!(
+            rgba.alpha().unwrap(),
+            Some((3, ExtraSample::AssociatedAlpha))
+        );
+        let gray_alpha = image(1, &[16; 2], &[2]);
+        assert_eq!(gray_alpha.color_type().unwrap(), ColorType::GrayA(16));
+        assert_eq!(
+            gray_alpha.alpha().unwrap(),
+            Some((1, ExtraSample::UnassociatedAlpha))
+        );
+
+        // an unspecified extra sample isn't alpha
+        let rgbx = image(2, &[8; 4], &[0]);
+        assert_eq!(
+            rgbx.color_type().unwrap(),
+            ColorType::Multiband {
+                bit_depth: 8,
+                num_samples: 4
+            }
+        );
+        assert_eq!(rgbx.alpha().unwrap(), None);
+        assert_eq!(rgbx.extra_samples().unwrap(), [ExtraSample::Unspecified]);
+        assert_eq!(rgbx.orientation().unwrap(), Orientation::TopLeft);
+        // alpha after a band of unknown meaning
+        let bands = image(1, &[8; 3], &[7, 2]);
+        assert_eq!(
+            bands.extra_samples().unwrap(),
+            [ExtraSample::Unknown(7), ExtraSample::UnassociatedAlpha]
+        );
+        assert_eq!(
+            bands.alpha().unwrap(),
+            Some((2, ExtraSample::UnassociatedAlpha))
+        );
+        assert!(matches!(
+            bands.color_type().unwrap(),
+            ColorType::Multiband { .. }
+        ));
+        assert_eq!(
+            image(2, &[8; 3], &[]).color_type().unwrap(),
+            ColorType::RGB(8)
+        );
+    }
+
     #[test]
     fn test_arcyness() {
         let asdf = Arc::new(BufferedEntry {
@@ -406,6 +1185,41 @@ mod test {
             count: 5,
             data: vec![42, 43, 44, 45, 46],
         });
-        assert_eq!(asdf.get_u64(2).unwrap(), 43);
+        assert_eq!(asdf.get_u64(1).unwrap(), 43);
+    }
+
+    #[test]
+    fn test_tiles_covering() {
+        // 3x2 tiles, the last column and row padded
+        let tiles = TileAttributes {
+            image_width: 40,
+            image_height: 20,
+            tile_width: 16,
+            tile_length: 16,
+        };
+        assert_eq!(
+            tiles.tiles_covering(&Rect::new(0, 0, 40, 20)),
+            vec![0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(
+            tiles.tiles_covering(&Rect::new(15, 15, 2, 2)),
+            vec![0, 1, 3, 4]
+        );
+        assert_eq!(tiles.tiles_covering(&Rect::new(32, 16, 100, 100)), vec![5]);
+        assert!(tiles.tiles_covering(&Rect::new(16, 0, 0, 10)).is_empty());
+        assert!(tiles.tiles_covering(&Rect::new(40, 0, 10, 10)).is_empty());
+    }
+
+    #[test]
+    fn test_rect() {
+        let rect = Rect::new(10, 10, 20, 20);
+        assert_eq!(
+            rect.intersection(&Rect::new(0, 25, 15, 100)),
+            Some(Rect::new(10, 25, 5, 5))
+        );
+        assert_eq!(rect.intersection(&Rect::new(30, 10, 5, 5)), None);
+        assert!(rect.fits_in(30, 30));
+        assert!(!rect.fits_in(29, 30));
+        assert!(!Rect::new(u32::MAX, 0, 1, 1).fits_in(u32::MAX, 1));
     }
 }
@@ -0,0 +1,218 @@
+//! Where the bytes of a file go, to spot poorly encoded files.
+//!
+//! A [`LayoutReport`] is built from the IFDs alone, so no chunks need to be
+//! read: sizes come from the byte counts, decoded sizes from the image tags.
+//!
+//! [`LayoutReport`]: crate::structs::layout::LayoutReport
+
+use alloc::vec::Vec;
+
+use crate::{
+    error::TiffResult,
+    structs::{Ifd, Tag, Tiff},
+};
+
+/// Layout of a whole file
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutReport {
+    /// Bytes before the first chunk, which holds the header, IFDs and tag data
+    /// in a well laid out COG
+    pub header_bytes: u64,
+    /// Bytes after the header that no chunk references
+    pub wasted_bytes: u64,
+    /// Statistics of each IFD, in the order of [`Tiff::ifds`]
+    pub ifds: Vec<IfdStats>,
+}
+
+/// Chunk statistics of a single IFD. Sparse chunks, that have no data, are
+/// left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfdStats {
+    /// Number of chunks that have data
+    pub chunks: usize,
+    /// Total size of the chunks
+    pub chunk_bytes: u64,
+    /// Mean size of a chunk, 0 if there are none
+    pub mean_chunk_bytes: f64,
+    /// 50th, 90th and 99th percentile of the chunk sizes
+    pub percentile_chunk_bytes: [u64; 3],
+    /// Size of the chunks once decompressed, `None` if the tags needed to
+    /// compute it are missing
+    pub decoded_bytes: Option<u64>,
+    /// `decoded_bytes / chunk_bytes`
+    pub compression_ratio: Option<f64>,
+}
+
+impl LayoutReport {
+    /// Report on a file of `file_len` bytes, whose chunk offsets and byte
+    /// counts were loaded
+    pub fn new(tiff: &Tiff, file_len: u64) -> TiffResult<Self> {
+        let mut chunks = Vec::new();
+        let mut ifds = Vec::with_capacity(tiff.ifds.len());
+        for ifd in &tiff.ifds {
+            let (offsets, byte_counts) = match (
+                ifd.get_tag_value(&Tag::TileOffsets)?,
+                ifd.get_tag_value(&Tag::StripOffsets)?,
+            ) {
+                (Some(offsets), _) => (offsets, ifd.require_tag_value(&Tag::TileByteCounts)?),
+                (None, Some(offsets)) => (offsets, ifd.require_tag_value(&Tag::StripByteCounts)?),
+                (None, None) => {
+                    ifds.push(IfdStats::new(Vec::new(), Some(0)));
+                    continue;
+                }
+            };
+            let mut sizes = Vec::new();
+            let mut present = Vec::new();
+            for i in 0..usize::try_from(offsets.count.min(byte_counts.count))? {
+                let (offset, n_bytes) = (offsets.get_u64(i)?, byte_counts.get_u64(i)?);
+                if offset != 0 && n_bytes != 0 {
+                    chunks.push((offset, offset.saturating_add(n_bytes).min(file_len)));
+                    sizes.push(n_bytes);
+                    present.push(i);
+                }
+            }
+            let decoded = decoded_chunk_bytes(ifd, offsets.count)?
+                .map(|decoded| present.iter().map(|&i| decoded(i)).sum());
+            ifds.push(IfdStats::new(sizes, decoded));
+        }
+
+        chunks.sort_unstable();
+        let header_bytes = chunks.first().map_or(file_len, |c| c.0.min(file_len));
+        // bytes covered by a chunk, counting overlapping chunks once
+        let mut covered = 0;
+        let mut end = header_bytes;
+        for (start, stop) in chunks {
+            covered += stop.saturating_sub(start.max(end));
+            end = end.max(stop);
+        }
+        Ok(LayoutReport {
+            header_bytes,
+            wasted_bytes: file_len - header_bytes - covered,
+            ifds,
+        })
+    }
+}
+
+impl IfdStats {
+    fn new(mut sizes: Vec<u64>, decoded_bytes: Option<u64>) -> Self {
+        sizes.sort_unstable();
+        let chunk_bytes: u64 = sizes.iter().sum();
+        // nearest rank
+        let percentile = |p: usize| match sizes.len() {
+            0 => 0,
+            n => sizes[(p * n).div_ceil(100).max(1) - 1],
+        };
+        IfdStats {
+            chunks: sizes.len(),
+            chunk_bytes,
+            mean_chunk_bytes: match sizes.len() {
+                0 => 0.0,
+                n => chunk_bytes as f64 / n as f64,
+            },
+            percentile_chunk_bytes: [percentile(50), percentile(90), percentile(99)],
+            decoded_bytes,
+            compression_ratio: decoded_bytes
+                .filter(|_| chunk_bytes > 0)
+                .map(|decoded| decoded as f64 / chunk_bytes as f64),
+        }
+    }
+}
+
+/// Decoded size of each of the `n_chunks` chunks, by index
+fn decoded_chunk_bytes(ifd: &Ifd, n_chunks: u64) -> TiffResult<Option<impl Fn(usize) -> u64>> {
+    let value = |tag: Tag| -> TiffResult<Option<u64>> {
+        ifd.get_tag_value(&tag)?
+            .map(|entry| entry.get_u64(0))
+            .transpose()
+    };
+    let (Some(width), Some(height)) = (value(Tag::ImageWidth)?, value(Tag::ImageLength)?) else {
+        return Ok(None);
+    };
+    let bits = value(Tag::BitsPerSample)?.unwrap_or(1);
+    let samples = value(Tag::SamplesPerPixel)?.unwrap_or(1);
+    let (planes, chunk_samples) = match value(Tag::PlanarConfiguration)? {
+        Some(2) => (samples, 1),
+        _ => (1, samples),
+    };
+    let row_bytes = |width: u64| (width * chunk_samples * bits).div_ceil(8);
+    let (chunk_row_bytes, rows_per_chunk, full_rows) =
+        match (value(Tag::TileWidth)?, value(Tag::TileLength)?) {
+            // tiles are padded to their full size
+            (Some(tile_width), Some(tile_length)) => (row_bytes(tile_width), tile_length, false),
+            _ => {
+                let rows_per_strip = value(Tag::RowsPerStrip)?.unwrap_or(height).max(1);
+                (row_bytes(width), rows_per_strip, true)
+            }
+        };
+    let chunks_per_plane = (n_chunks / planes.max(1)).max(1);
+    Ok(Some(move |i: usize| {
+        let rows = if full_rows {
+            // the last strip of a plane holds the remaining rows
+            let first_row = (i as u64 % chunks_per_plane) * rows_per_chunk;
+            rows_per_chunk.min(height.saturating_sub(first_row))
+        } else {
+            rows_per_chunk
+        };
+        chunk_row_bytes * rows
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoder::directory::entry;
+
+    #[test]
+    fn test_layout_report() {
+        let (mut tiff, _) = Tiff::from_header(b"II*\0\x08\0\0\0").unwrap();
+        // 16x16 RGB tiles, one of them sparse
+        let mut tiled = Ifd::default();
+        for (tag, value) in [
+            (Tag::ImageWidth, 32u32),
+            (Tag::ImageLength, 16),
+            (Tag::TileWidth, 16),
+            (Tag::TileLength, 16),
+            (Tag::SamplesPerPixel, 3),
+            (Tag::BitsPerSample, 8),
+        ] {
+            tiled.insert_tag_data_from_buffer(&tag, entry(&value));
+        }
+        tiled.insert_tag_data_from_buffer(&Tag::TileOffsets, entry(&[1000u32, 0][..]));
+        tiled.insert_tag_data_from_buffer(&Tag::TileByteCounts, entry(&[192u32, 0][..]));
+        tiff.ifds.push(tiled);
+        // 3 strips of 2 rows of 10 gray bytes, with a gap after the first
+        let mut stripped = Ifd::default();
+        for (tag, value) in [
+            (Tag::ImageWidth, 10u32),
+            (Tag::ImageLength, 5),
+            (Tag::RowsPerStrip, 2),
+        ] {
+            stripped.insert_tag_data_from_buffer(&tag, entry(&value));
+        }
+        stripped.insert_tag_data_from_buffer(&Tag::BitsPerSample, entry(&8u16));
+        stripped.insert_tag_data_from_buffer(&Tag::StripOffsets, entry(&[500u32, 600, 610][..]));
+        stripped.insert_tag_data_from_buffer(&Tag::StripByteCounts, entry(&[10u32, 10, 5][..]));
+        tiff.ifds.push(stripped);
+
+        let report = LayoutReport::new(&tiff, 1300).unwrap();
+        assert_eq!(report.header_bytes, 500);
+        // 510..600, 615..1000 and 1192..1300
+        assert_eq!(report.wasted_bytes, 90 + 385 + 108);
+        assert_eq!(
+            report.ifds[0],
+            IfdStats {
+                chunks: 1,
+                chunk_bytes: 192,
+                mean_chunk_bytes: 192.0,
+                percentile_chunk_bytes: [192; 3],
+                decoded_bytes: Some(16 * 16 * 3),
+                compression_ratio: Some(4.0),
+            }
+        );
+        let strips = &report.ifds[1];
+        assert_eq!(strips.chunks, 3);
+        assert_eq!(strips.percentile_chunk_bytes, [10, 10, 10]);
+        assert_eq!(strips.decoded_bytes, Some(50));
+        assert_eq!(strips.compression_ratio, Some(2.0));
+    }
+}
@@ -1,17 +1,34 @@
+/// Whether a pyramid has enough overviews
+pub mod coverage;
 mod entry;
-pub use entry::{BufferedEntry, Directory, IfdEntry};
+/// Georeferencing through GeoTIFF's model tags and keys
+pub mod geo;
+/// Layout options GDAL writes before the first IFD of a COG
+pub mod ghost;
+pub use entry::{BufferedEntry, Directory, EntryPreview, IfdEntry};
 /// IFD struct for non-images
 mod ifd;
 pub use ifd::Ifd;
 /// IFD struct and functions for IFDs related to images
 mod image;
-pub use image::{ChunkOpts, Image};
+pub use image::{
+    ChunkDims, ChunkMetaData, Image, MaybePartial, Rect, StripDecodeState, TileAttributes,
+};
+#[cfg(feature = "std")]
+pub use image::{PagedEntry, PAGE_LEN};
+#[cfg(feature = "std")]
+pub(crate) use image::{CHUNK_TAGS, IMAGE_TAGS};
+/// Chunk size and compression statistics, for spotting poorly encoded files
+pub mod layout;
+/// Rational polynomial coefficients of satellite imagery
+pub mod rpc;
 /// Tags: type, and important ones here
 pub mod tags;
 pub use tags::{Tag, TagType};
 /// Tiff struct that can hold multiple images. This should be thin and ideally
 /// re-implemented for more specific tiff types
 pub mod tiff;
+pub use tiff::Tiff;
 /// Tag Value type and convenience functions
 /// to be deprecated in favour of `BufferedEntry`
 pub mod value;
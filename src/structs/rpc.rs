@@ -0,0 +1,194 @@
+//! Rational polynomial coefficients (RPCs) of satellite imagery.
+//!
+//! Instead of a geotransform, imagery that wasn't orthorectified yet often
+//! comes with a sensor model: ratios of cubic polynomials that give the line
+//! and sample at which a point on the ground is seen. GDAL stores them in
+//! `RpcCoefficientTag` as 92 doubles, in the order of the RPC00B TRE of
+//! NITF: two error estimates, the offsets and scales that normalize
+//! coordinates, and 20 coefficients for each of the four polynomials.
+
+use alloc::vec::Vec;
+
+use crate::{
+    encoder::directory::entry,
+    error::{TiffFormatError, TiffResult},
+    structs::{BufferedEntry, Ifd, Tag},
+};
+
+/// Number of values in `RpcCoefficientTag`
+const N_VALUES: usize = 92;
+
+/// Sensor model of an image, mapping ground coordinates to pixels, see
+/// [`RpcModel::project`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcModel {
+    /// Bias error in meters, -1 if unknown
+    pub err_bias: f64,
+    /// Random error in meters, -1 if unknown
+    pub err_rand: f64,
+    pub line_off: f64,
+    pub samp_off: f64,
+    pub lat_off: f64,
+    pub long_off: f64,
+    pub height_off: f64,
+    pub line_scale: f64,
+    pub samp_scale: f64,
+    pub lat_scale: f64,
+    pub long_scale: f64,
+    pub height_scale: f64,
+    pub line_num_coeff: [f64; 20],
+    pub line_den_coeff: [f64; 20],
+    pub samp_num_coeff: [f64; 20],
+    pub samp_den_coeff: [f64; 20],
+}
+
+impl RpcModel {
+    /// The model of an IFD, `None` if it has no `RpcCoefficientTag`.
+    ///
+    /// Fails with [`TiffFormatError::InconsistentSizesEncountered`] if the tag
+    /// doesn't hold 92 values.
+    pub fn from_ifd(ifd: &Ifd) -> TiffResult<Option<Self>> {
+        let Some(entry) = ifd.get_tag_value(&Tag::RpcCoefficientTag)? else {
+            return Ok(None);
+        };
+        let values = Vec::<f64>::try_from(entry)?;
+        let Ok(values) = <[f64; N_VALUES]>::try_from(values) else {
+            return Err(TiffFormatError::InconsistentSizesEncountered(entry.into()).into());
+        };
+        let coefficients = |i: usize| -> [f64; 20] { values[i..i + 20].try_into().unwrap() };
+        Ok(Some(RpcModel {
+            err_bias: values[0],
+            err_rand: values[1],
+            line_off: values[2],
+            samp_off: values[3],
+            lat_off: values[4],
+            long_off: values[5],
+            height_off: values[6],
+            line_scale: values[7],
+            samp_scale: values[8],
+            lat_scale: values[9],
+            long_scale: values[10],
+            height_scale: values[11],
+            line_num_coeff: coefficients(12),
+            line_den_coeff: coefficients(32),
+            samp_num_coeff: coefficients(52),
+            samp_den_coeff: coefficients(72),
+        }))
+    }
+
+    /// The model as the entry of `RpcCoefficientTag`, e.g. for
+    /// [`Level::extra_tags`](crate::encoder::Level::extra_tags)
+    pub fn to_entry(&self) -> BufferedEntry {
+        let mut values = Vec::with_capacity(N_VALUES);
+        values.extend_from_slice(&[
+            self.err_bias,
+            self.err_rand,
+            self.line_off,
+            self.samp_off,
+            self.lat_off,
+            self.long_off,
+            self.height_off,
+            self.line_scale,
+            self.samp_scale,
+            self.lat_scale,
+            self.long_scale,
+            self.height_scale,
+        ]);
+        for coefficients in [
+            &self.line_num_coeff,
+            &self.line_den_coeff,
+            &self.samp_num_coeff,
+            &self.samp_den_coeff,
+        ] {
+            values.extend_from_slice(coefficients);
+        }
+        entry(&values[..])
+    }
+
+    /// Line and sample, in pixels of the full resolution image, at which the
+    /// point at `longitude` and `latitude` in degrees and `height` in meters
+    /// above the ellipsoid is seen
+    pub fn project(&self, longitude: f64, latitude: f64, height: f64) -> (f64, f64) {
+        let l = (longitude - self.long_off) / self.long_scale;
+        let p = (latitude - self.lat_off) / self.lat_scale;
+        let h = (height - self.height_off) / self.height_scale;
+        // terms of the polynomials, in the order of RPC00B
+        let terms = [
+            1.0,
+            l,
+            p,
+            h,
+            l * p,
+            l * h,
+            p * h,
+            l * l,
+            p * p,
+            h * h,
+            p * l * h,
+            l * l * l,
+            l * p * p,
+            l * h * h,
+            l * l * p,
+            p * p * p,
+            p * h * h,
+            l * l * h,
+            p * p * h,
+            h * h * h,
+        ];
+        let polynomial = |coefficients: &[f64; 20]| -> f64 {
+            terms.iter().zip(coefficients).map(|(t, c)| t * c).sum()
+        };
+        let line = polynomial(&self.line_num_coeff) / polynomial(&self.line_den_coeff);
+        let sample = polynomial(&self.samp_num_coeff) / polynomial(&self.samp_den_coeff);
+        (
+            line * self.line_scale + self.line_off,
+            sample * self.samp_scale + self.samp_off,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rpc() {
+        // lines going south and samples east, with some height parallax
+        let mut line_num_coeff = [0.0; 20];
+        line_num_coeff[2] = -1.0;
+        let mut samp_num_coeff = [0.0; 20];
+        samp_num_coeff[1] = 1.0;
+        samp_num_coeff[3] = 0.1;
+        let mut den_coeff = [0.0; 20];
+        den_coeff[0] = 1.0;
+        let model = RpcModel {
+            err_bias: -1.0,
+            err_rand: -1.0,
+            line_off: 5000.0,
+            samp_off: 4000.0,
+            lat_off: 45.0,
+            long_off: 7.0,
+            height_off: 500.0,
+            line_scale: 5000.0,
+            samp_scale: 4000.0,
+            lat_scale: 0.1,
+            long_scale: 0.1,
+            height_scale: 500.0,
+            line_num_coeff,
+            line_den_coeff: den_coeff,
+            samp_num_coeff,
+            samp_den_coeff: den_coeff,
+        };
+        assert_eq!(model.project(7.0, 45.0, 500.0), (5000.0, 4000.0));
+        let (line, sample) = model.project(7.05, 45.1, 1000.0);
+        assert!((line - 0.0).abs() < 1e-6);
+        assert!((sample - (4000.0 + 0.6 * 4000.0)).abs() < 1e-6);
+
+        let mut ifd = Ifd::default();
+        assert_eq!(RpcModel::from_ifd(&ifd).unwrap(), None);
+        ifd.insert_tag_data_from_buffer(&Tag::RpcCoefficientTag, model.to_entry());
+        assert_eq!(RpcModel::from_ifd(&ifd).unwrap(), Some(model));
+        ifd.insert_tag_data_from_buffer(&Tag::RpcCoefficientTag, entry(&[0.0f64; 90][..]));
+        assert!(RpcModel::from_ifd(&ifd).is_err());
+    }
+}
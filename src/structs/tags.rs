@@ -31,7 +31,7 @@ macro_rules! tags {
             fn __to_inner_type(&self) -> $ty {
                 match *self {
                     $( $name::$tag => $val, )*
-                    $( $name::Unknown(n) => { $unknown_doc; n }, )*
+                    $( $name::Unknown(n) => { let _ = $unknown_doc; n }, )*
                 }
             }
         }
@@ -49,7 +49,7 @@ macro_rules! tags {
             $(
             #[inline(always)]
             pub fn from_u16_exhaustive(val: u16) -> Self {
-                $unknown_doc;
+                let _ = $unknown_doc;
                 Self::__from_inner_type(val).unwrap_or_else(|_| $name::Unknown(val))
             }
             )*
@@ -76,11 +76,11 @@ pub enum Tag(u16) unknown("A private or extension tag") {
     CellLength = 265, // TODO add support
     CellWidth = 264, // TODO add support
     // palette-color images (PhotometricInterpretation 3)
-    ColorMap = 320, // TODO add support
+    ColorMap = 320,
     Compression = 259, // TODO add support for 2 and 32773
     Copyright = 33_432,
     DateTime = 306,
-    ExtraSamples = 338, // TODO add support
+    ExtraSamples = 338,
     FillOrder = 266, // TODO add support
     FreeByteCounts = 289, // TODO add support
     FreeOffsets = 288, // TODO add support
@@ -94,8 +94,8 @@ pub enum Tag(u16) unknown("A private or extension tag") {
     MaxSampleValue = 281, // TODO add support
     MinSampleValue = 280, // TODO add support
     Model = 272,
-    NewSubfileType = 254, // TODO add support
-    Orientation = 274, // TODO add support
+    NewSubfileType = 254,
+    Orientation = 274,
     PhotometricInterpretation = 262,
     PlanarConfiguration = 284,
     ResolutionUnit = 296, // TODO add support
@@ -110,6 +110,7 @@ pub enum Tag(u16) unknown("A private or extension tag") {
     YResolution = 283,
     // Advanced tags
     Predictor = 317,
+    SubIfds = 330,
     TileWidth = 322,
     TileLength = 323,
     TileOffsets = 324,
@@ -120,6 +121,14 @@ pub enum Tag(u16) unknown("A private or extension tag") {
     SMaxSampleValue = 341, // TODO add support
     // JPEG
     JPEGTables = 347,
+    // old-style JPEG
+    JPEGInterchangeFormat = 513,
+    JPEGInterchangeFormatLength = 514,
+    // YCbCr
+    YCbCrCoefficients = 529,
+    YCbCrSubSampling = 530,
+    YCbCrPositioning = 531,
+    ReferenceBlackWhite = 532,
     // GeoTIFF
     ModelPixelScaleTag = 33550, // (SoftDesk)
     ModelTransformationTag = 34264, // (JPL Carto Group)
@@ -128,55 +137,117 @@ pub enum Tag(u16) unknown("A private or extension tag") {
     GeoDoubleParamsTag = 34736, // (SPOT)
     GeoAsciiParamsTag = 34737, // (SPOT)
     GdalNodata = 42113, // Contains areas with missing data
+    RpcCoefficientTag = 50844, // (GDAL) Rational polynomial coefficients
 }
 }
 
-tags! {
 /// The type of an IFD entry (a 2 byte field).
-/// Should be kept in sync with [`Value`]
-pub enum TagType(u16) {
+/// Should be kept in sync with [`Value`](crate::structs::value::Value)
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum TagType {
     /// 8-bit unsigned integer
-    BYTE = 1,
+    BYTE,
     /// 8-bit byte that contains a 7-bit ASCII code; the last byte must be zero
-    ASCII = 2,
+    ASCII,
     /// 16-bit unsigned integer
-    SHORT = 3,
+    SHORT,
     /// 32-bit unsigned integer
-    LONG = 4,
+    LONG,
     /// Fraction stored as two 32-bit unsigned integers
-    RATIONAL = 5,
+    RATIONAL,
     /// 8-bit signed integer
-    SBYTE = 6,
+    SBYTE,
     /// 8-bit byte that may contain anything, depending on the field
-    UNDEFINED = 7,
+    UNDEFINED,
     /// 16-bit signed integer
-    SSHORT = 8,
+    SSHORT,
     /// 32-bit signed integer
-    SLONG = 9,
+    SLONG,
     /// Fraction stored as two 32-bit signed integers
-    SRATIONAL = 10,
+    SRATIONAL,
     /// 32-bit IEEE floating point
-    FLOAT = 11,
+    FLOAT,
     /// 64-bit IEEE floating point
-    DOUBLE = 12,
+    DOUBLE,
     /// 32-bit unsigned integer (offset)
-    IFD = 13,
+    IFD,
     /// BigTIFF 64-bit unsigned integer
-    LONG8 = 16,
+    LONG8,
     /// BigTIFF 64-bit signed integer
-    SLONG8 = 17,
+    SLONG8,
     /// BigTIFF 64-bit unsigned integer (offset)
-    IFD8 = 18,
-}
+    IFD8,
+    /// A vendor-defined type code, and the size of one of its values in
+    /// bytes.
+    ///
+    /// Files don't record that size, so entries of these types are read with
+    /// a size hint of 0, keeping their raw offset field, unless the size is
+    /// given in [`DecoderOptions::vendor_tag_types`]. Their data is kept as
+    /// it is in the file, without fixing its byte order.
+    ///
+    /// [`DecoderOptions::vendor_tag_types`]: crate::decoder::DecoderOptions::vendor_tag_types
+    Unknown(u16, u8),
 }
 
 mod tag_type {
     use super::*;
     use TagType::{
-        self, ASCII, BYTE, DOUBLE, FLOAT, IFD, IFD8, LONG, LONG8, RATIONAL, SBYTE, SHORT, SLONG,
-        SLONG8, SRATIONAL, SSHORT, UNDEFINED,
+        self, Unknown, ASCII, BYTE, DOUBLE, FLOAT, IFD, IFD8, LONG, LONG8, RATIONAL, SBYTE, SHORT,
+        SLONG, SLONG8, SRATIONAL, SSHORT, UNDEFINED,
     };
     impl TagType {
+        /// The known type with code `val`
+        pub fn from_u16(val: u16) -> Option<Self> {
+            Some(match val {
+                1 => BYTE,
+                2 => ASCII,
+                3 => SHORT,
+                4 => LONG,
+                5 => RATIONAL,
+                6 => SBYTE,
+                7 => UNDEFINED,
+                8 => SSHORT,
+                9 => SLONG,
+                10 => SRATIONAL,
+                11 => FLOAT,
+                12 => DOUBLE,
+                13 => IFD,
+                16 => LONG8,
+                17 => SLONG8,
+                18 => IFD8,
+                _ => return None,
+            })
+        }
+
+        /// Like [`TagType::from_u16`], but an unknown code is a vendor type
+        /// with values of `size_hint` bytes
+        pub fn from_u16_with_size_hint(val: u16, size_hint: u8) -> Self {
+            Self::from_u16(val).unwrap_or(Unknown(val, size_hint))
+        }
+
+        pub fn to_u16(&self) -> u16 {
+            match *self {
+                BYTE => 1,
+                ASCII => 2,
+                SHORT => 3,
+                LONG => 4,
+                RATIONAL => 5,
+                SBYTE => 6,
+                UNDEFINED => 7,
+                SSHORT => 8,
+                SLONG => 9,
+                SRATIONAL => 10,
+                FLOAT => 11,
+                DOUBLE => 12,
+                IFD => 13,
+                LONG8 => 16,
+                SLONG8 => 17,
+                IFD8 => 18,
+                Unknown(val, _) => val,
+            }
+        }
+
         /// Returns the size of the type in bytes. Useful for determining total
         /// buffer size.
         pub fn size(&self) -> usize {
@@ -186,6 +257,7 @@ mod tag_type {
                 LONG | SLONG | FLOAT | IFD => 4,
                 RATIONAL | SRATIONAL | DOUBLE => 8,
                 LONG8 | SLONG8 | IFD8 => 8,
+                Unknown(_, size_hint) => usize::from(*size_hint),
             }
         }
         /// returns the size of the underlying datatype. Useful for byte-order manipulations.
@@ -197,6 +269,8 @@ mod tag_type {
                 RATIONAL | SRATIONAL => 4,
                 DOUBLE => 8,
                 LONG8 | SLONG8 | IFD8 => 8,
+                // opaque bytes, left in the byte order of the file
+                Unknown(..) => 1,
             }
         }
     }
@@ -217,6 +291,13 @@ pub enum CompressionMethod(u16) unknown("A custom compression method") {
     Deflate = 8,
     OldDeflate = 0x80B2,
     PackBits = 0x8005,
+    // Esri's Limited Error Raster Compression
+    LERC = 34887,
+    // xz streams, as written by libtiff
+    LZMA = 34925,
+    ZSTD = 50000,
+    // as written by GDAL, decoded with the `webp` feature
+    WebP = 50001,
 }
 }
 
@@ -230,6 +311,35 @@ pub enum PhotometricInterpretation(u16) {
     CMYK = 5,
     YCbCr = 6,
     CIELab = 8,
+    ICCLab = 9,
+}
+}
+
+tags! {
+/// Where the first stored row and column of an image go when it is
+/// displayed, e.g. `RightTop` for a first row along the right edge and a first
+/// column along the top, which is an image rotated 90° clockwise for display
+pub enum Orientation(u16) {
+    TopLeft = 1,
+    TopRight = 2,
+    BottomRight = 3,
+    BottomLeft = 4,
+    LeftTop = 5,
+    RightTop = 6,
+    RightBottom = 7,
+    LeftBottom = 8,
+}
+}
+
+tags! {
+/// Meaning of a sample beyond those of the photometric interpretation, from
+/// `ExtraSamples`
+pub enum ExtraSample(u16) unknown("A sample of unknown meaning") {
+    Unspecified = 0,
+    /// Alpha that the color samples were premultiplied with
+    AssociatedAlpha = 1,
+    /// Alpha that wasn't applied to the color samples
+    UnassociatedAlpha = 2,
 }
 }
 
@@ -253,7 +363,8 @@ pub enum Predictor(u16) {
     /// This means that instead of having in order `[r1, g1. b1, r2, g2 ...]` you will find
     /// `[r1, g1, b1, r2-r1, g2-g1, b2-b1, r3-r2, g3-g2, ...]`
     Horizontal = 2,
-    /// Not currently supported
+    /// Like `Horizontal`, but on byte planes of floating point samples, see
+    /// [`crate::predictor`]
     FloatingPoint = 3,
 }
 }
@@ -275,3 +386,31 @@ pub enum SampleFormat(u16) unknown("An unknown extension sample format") {
     Void = 4,
 }
 }
+
+tags! {
+/// Keys of a GeoTIFF `GeoKeyDirectoryTag`, see [`GeoKeys`](crate::structs::geo::GeoKeys)
+pub enum GeoKey(u16) unknown("A key this crate has no name for") {
+    /// Projected, geographic or geocentric model
+    GTModelType = 1024,
+    /// Whether pixels are areas or points
+    GTRasterType = 1025,
+    /// Description of the whole coordinate reference system
+    GTCitation = 1026,
+    /// EPSG code of the geographic CRS
+    GeographicType = 2048,
+    GeogCitation = 2049,
+    GeogGeodeticDatum = 2050,
+    GeogLinearUnits = 2052,
+    GeogAngularUnits = 2054,
+    /// EPSG code of the projected CRS
+    ProjectedCSType = 3072,
+    PCSCitation = 3073,
+    ProjLinearUnits = 3076,
+    /// EPSG code of the vertical CRS
+    VerticalCSType = 4096,
+    VerticalCitation = 4097,
+    /// EPSG code of the vertical datum
+    VerticalDatum = 4098,
+    VerticalUnits = 4099,
+}
+}
@@ -1,12 +1,232 @@
 //! Tiff struct that holds all *meta*data of a tiff
 //! Can be used for both decoding and encoding purposes
 
-use crate::{structs::Image, ByteOrder};
+use alloc::vec::Vec;
 
-pub struct tiff<R> {
+use crate::{
+    error::{TiffFormatError, TiffResult},
+    structs::{
+        geo::{self, GeoKeys, GeoTransform},
+        ghost::GhostHeader,
+        Ifd, Image, Tag,
+    },
+    ByteOrder,
+};
+
+/// Number of bytes needed to parse any header (BigTIFF's is the longest)
+pub const HEADER_LEN: u64 = 16;
+
+#[derive(Debug)]
+pub struct Tiff {
+    /// IFDs in the order of the IFD chain
+    pub ifds: Vec<Ifd>,
     pub images: Vec<Image>,
-    bigtiff: bool,
-    byte_order: ByteOrder,
-    reader: R,
+    /// GDAL's layout options, if the header buffer held them
+    pub ghost_header: Option<GhostHeader>,
+    /// Whether the byte order of the header contradicted that of the rest of
+    /// the file, and was overridden, see [`DecoderOptions::detect_byte_order`]
+    ///
+    /// [`DecoderOptions::detect_byte_order`]: crate::decoder::DecoderOptions::detect_byte_order
+    pub byte_order_mismatch: bool,
+    pub(crate) bigtiff: bool,
+    pub(crate) byte_order: ByteOrder,
+    pub(crate) first_ifd: u64,
+    pub(crate) file_len: Option<u64>,
     // add additional global stuff such as geo-info here
 }
+
+impl Tiff {
+    /// Parse the header at the start of `buf`, returning a `Tiff` without any
+    /// IFDs and the offset of the first IFD. A ghost header following it is
+    /// parsed as well, if `buf` is long enough to hold it.
+    pub fn from_header(buf: &[u8]) -> TiffResult<(Self, u64)> {
+        let byte_order = match buf.get(..2) {
+            Some(b"II") => ByteOrder::LittleEndian,
+            Some(b"MM") => ByteOrder::BigEndian,
+            _ => return Err(TiffFormatError::TiffSignatureNotFound.into()),
+        };
+        let u16_at = |i: usize| -> TiffResult<u16> {
+            buf.get(i..i + 2)
+                .map(|b| byte_order.u16([b[0], b[1]]))
+                .ok_or(TiffFormatError::TiffSignatureNotFound.into())
+        };
+        let (bigtiff, first_ifd) = match u16_at(2)? {
+            42 => {
+                let b: [u8; 4] = buf
+                    .get(4..8)
+                    .and_then(|b| b.try_into().ok())
+                    .ok_or(TiffFormatError::TiffSignatureNotFound)?;
+                (false, u64::from(byte_order.u32(b)))
+            }
+            43 => {
+                // offset size (always 8) and a reserved 0
+                if u16_at(4)? != 8 || u16_at(6)? != 0 {
+                    return Err(TiffFormatError::TiffSignatureInvalid.into());
+                }
+                let b: [u8; 8] = buf
+                    .get(8..16)
+                    .and_then(|b| b.try_into().ok())
+                    .ok_or(TiffFormatError::TiffSignatureNotFound)?;
+                (true, byte_order.u64(b))
+            }
+            _ => return Err(TiffFormatError::TiffSignatureInvalid.into()),
+        };
+        let header_len = if bigtiff { 16 } else { 8 };
+        Ok((
+            Tiff {
+                ifds: Vec::new(),
+                images: Vec::new(),
+                ghost_header: buf.get(header_len..).and_then(GhostHeader::parse),
+                byte_order_mismatch: false,
+                bigtiff,
+                byte_order,
+                first_ifd,
+                file_len: None,
+            },
+            first_ifd,
+        ))
+    }
+
+    /// Byte order of the file, which is that of its IFDs if the header
+    /// contradicted them, see [`Tiff::byte_order_mismatch`]
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    pub fn is_bigtiff(&self) -> bool {
+        self.bigtiff
+    }
+
+    /// Offset of the first IFD, as given by the header
+    pub fn first_ifd_offset(&self) -> u64 {
+        self.first_ifd
+    }
+
+    /// Length of the file, if the reader it was read with knew it
+    pub fn file_len(&self) -> Option<u64> {
+        self.file_len
+    }
+
+    /// Transform from pixel to model coordinates of the image in IFD `ifd`,
+    /// `None` if the file isn't georeferenced.
+    ///
+    /// Overviews without geo tags of their own inherit those of the first
+    /// IFD, scaled by how much smaller they are.
+    pub fn geotransform(&self, ifd: usize) -> TiffResult<Option<GeoTransform>> {
+        let image = self
+            .ifds
+            .get(ifd)
+            .ok_or(TiffFormatError::ImageFileDirectoryNotFound)?;
+        if let Some(transform) = geo::geotransform(image)? {
+            return Ok(Some(transform));
+        }
+        let Some(base) = self.ifds.first().filter(|_| ifd > 0) else {
+            return Ok(None);
+        };
+        let Some(transform) = geo::geotransform(base)? else {
+            return Ok(None);
+        };
+        let factor = |tag: &Tag| -> TiffResult<f64> {
+            let base_len = u32::try_from(base.require_tag_value(tag)?)?;
+            let len = u32::try_from(image.require_tag_value(tag)?)?;
+            if len == 0 {
+                return Err(TiffFormatError::RequiredTagEmpty(*tag).into());
+            }
+            Ok(f64::from(base_len) / f64::from(len))
+        };
+        Ok(Some(geo::scale_geotransform(
+            transform,
+            factor(&Tag::ImageWidth)?,
+            factor(&Tag::ImageLength)?,
+        )))
+    }
+
+    /// GeoKeys of the image in IFD `ifd`, `None` if the file has none.
+    /// Overviews without keys of their own inherit those of the first IFD.
+    pub fn geo_keys(&self, ifd: usize) -> TiffResult<Option<GeoKeys>> {
+        let image = self
+            .ifds
+            .get(ifd)
+            .ok_or(TiffFormatError::ImageFileDirectoryNotFound)?;
+        match (GeoKeys::from_ifd(image)?, self.ifds.first()) {
+            (None, Some(base)) if ifd > 0 => GeoKeys::from_ifd(base),
+            (keys, _) => Ok(keys),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoder::directory::entry;
+
+    #[test]
+    fn test_headers() {
+        let (tiff, first_ifd) = Tiff::from_header(b"II*\0\x08\0\0\0").unwrap();
+        assert_eq!(
+            (tiff.byte_order, tiff.bigtiff, first_ifd),
+            (ByteOrder::LittleEndian, false, 8)
+        );
+        let (tiff, first_ifd) = Tiff::from_header(b"MM\0*\0\0\0\x08").unwrap();
+        assert_eq!(
+            (tiff.byte_order, tiff.bigtiff, first_ifd),
+            (ByteOrder::BigEndian, false, 8)
+        );
+        let (tiff, first_ifd) = Tiff::from_header(b"II+\0\x08\0\0\0\x10\0\0\0\0\0\0\0").unwrap();
+        assert_eq!(
+            (tiff.byte_order, tiff.bigtiff, first_ifd),
+            (ByteOrder::LittleEndian, true, 16)
+        );
+        assert_eq!(tiff.ghost_header, None);
+        assert_eq!(
+            (
+                tiff.byte_order(),
+                tiff.is_bigtiff(),
+                tiff.first_ifd_offset()
+            ),
+            (ByteOrder::LittleEndian, true, 16)
+        );
+        assert_eq!(tiff.file_len(), None);
+        let (tiff, _) =
+            Tiff::from_header(b"II*\0\x08\0\0\0GDAL_STRUCTURAL_METADATA_SIZE=000000 bytes\n")
+                .unwrap();
+        assert_eq!(tiff.ghost_header, Some(GhostHeader::default()));
+        assert!(Tiff::from_header(b"II+\0\x04\0\0\0\x10\0\0\0\0\0\0\0").is_err());
+        assert!(Tiff::from_header(b"XX*\0\x08\0\0\0").is_err());
+        assert!(Tiff::from_header(b"II*").is_err());
+    }
+
+    #[test]
+    fn test_geotransform() {
+        let (mut tiff, _) = Tiff::from_header(b"II*\0\x08\0\0\0").unwrap();
+        for size in [128u32, 32] {
+            let mut ifd = Ifd::default();
+            ifd.insert_tag_data_from_buffer(&Tag::ImageWidth, entry(&size));
+            ifd.insert_tag_data_from_buffer(&Tag::ImageLength, entry(&(size / 2)));
+            tiff.ifds.push(ifd);
+        }
+        assert_eq!(tiff.geotransform(1).unwrap(), None);
+
+        tiff.ifds[0].insert_tag_data_from_buffer(
+            &Tag::ModelTiepointTag,
+            entry(&[0.0f64, 0.0, 0.0, 10.0, 20.0, 0.0][..]),
+        );
+        tiff.ifds[0]
+            .insert_tag_data_from_buffer(&Tag::ModelPixelScaleTag, entry(&[1.0f64, 2.0, 0.0][..]));
+        assert_eq!(
+            tiff.geotransform(0).unwrap(),
+            Some([10.0, 1.0, 0.0, 20.0, 0.0, -2.0])
+        );
+        // the overview's pixels are 4 times as large
+        assert_eq!(
+            tiff.geotransform(1).unwrap(),
+            Some([10.0, 4.0, 0.0, 20.0, 0.0, -8.0])
+        );
+        assert!(tiff.geotransform(2).is_err());
+
+        assert_eq!(tiff.geo_keys(1).unwrap(), None);
+        let directory = [1u16, 1, 0, 1, 3072, 0, 1, 32633];
+        tiff.ifds[0].insert_tag_data_from_buffer(&Tag::GeoKeyDirectoryTag, entry(&directory[..]));
+        assert_eq!(tiff.geo_keys(1).unwrap().unwrap().epsg(), Some(32633));
+    }
+}
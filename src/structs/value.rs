@@ -1,12 +1,6 @@
-use std::io::Read;
+use alloc::{string::String, vec::Vec};
 
-use crate::error::{TiffError, TiffFormatError, TiffResult};
-use crate::structs::{Tag, TagType};
-
-use self::Value::{
-    Ascii, Byte, Double, Float, List, Long, Long8, Rational, SLong, SLong8, SRational, SShort,
-    Short, SignedByte,
-};
+use crate::structs::TagType;
 
 /// Tag value
 ///
@@ -44,8 +38,87 @@ pub enum Value {
     Ifd8(u64),
 }
 
-impl std::fmt::Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+/// A single value of a [`TagType`], as read by
+/// [`EndianReader::read_value`] and written by [`EndianWriter::write_value`]
+///
+/// [`EndianReader::read_value`]: crate::decoder::EndianReader::read_value
+/// [`EndianWriter::write_value`]: crate::encoder::EndianWriter::write_value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrimitiveValue {
+    Byte(u8),
+    SByte(i8),
+    Undefined(u8),
+    /// A byte of an ASCII string
+    Ascii(u8),
+
+    Short(u16),
+    SShort(i16),
+
+    Long(u32),
+    SLong(i32),
+    Ifd(u32),
+
+    Long8(u64),
+    SLong8(i64),
+    Ifd8(u64),
+
+    Float(f32),
+    Double(f64),
+
+    Rational(u32, u32),
+    SRational(i32, i32),
+}
+
+impl PrimitiveValue {
+    /// Tag type this value is of
+    pub fn tag_type(&self) -> TagType {
+        match self {
+            PrimitiveValue::Byte(_) => TagType::BYTE,
+            PrimitiveValue::SByte(_) => TagType::SBYTE,
+            PrimitiveValue::Undefined(_) => TagType::UNDEFINED,
+            PrimitiveValue::Ascii(_) => TagType::ASCII,
+            PrimitiveValue::Short(_) => TagType::SHORT,
+            PrimitiveValue::SShort(_) => TagType::SSHORT,
+            PrimitiveValue::Long(_) => TagType::LONG,
+            PrimitiveValue::SLong(_) => TagType::SLONG,
+            PrimitiveValue::Ifd(_) => TagType::IFD,
+            PrimitiveValue::Long8(_) => TagType::LONG8,
+            PrimitiveValue::SLong8(_) => TagType::SLONG8,
+            PrimitiveValue::Ifd8(_) => TagType::IFD8,
+            PrimitiveValue::Float(_) => TagType::FLOAT,
+            PrimitiveValue::Double(_) => TagType::DOUBLE,
+            PrimitiveValue::Rational(..) => TagType::RATIONAL,
+            PrimitiveValue::SRational(..) => TagType::SRATIONAL,
+        }
+    }
+}
+
+impl From<PrimitiveValue> for Value {
+    fn from(value: PrimitiveValue) -> Self {
+        match value {
+            PrimitiveValue::Byte(v) => Value::Byte(v),
+            PrimitiveValue::SByte(v) => Value::SignedByte(v),
+            PrimitiveValue::Undefined(v) => Value::Undefined(v),
+            PrimitiveValue::Ascii(0) => Value::Ascii(String::new()),
+            PrimitiveValue::Ascii(v) => Value::Ascii(char::from(v).into()),
+            PrimitiveValue::Short(v) => Value::Short(v),
+            PrimitiveValue::SShort(v) => Value::SShort(v),
+            PrimitiveValue::Long(v) => Value::Long(v),
+            PrimitiveValue::SLong(v) => Value::SLong(v),
+            PrimitiveValue::Ifd(v) => Value::Ifd(v),
+            PrimitiveValue::Long8(v) => Value::Long8(v),
+            PrimitiveValue::SLong8(v) => Value::SLong8(v),
+            PrimitiveValue::Ifd8(v) => Value::Ifd8(v),
+            PrimitiveValue::Float(v) => Value::Float(v),
+            PrimitiveValue::Double(v) => Value::Double(v),
+            PrimitiveValue::Rational(n, d) => Value::Rational(n, d),
+            PrimitiveValue::SRational(n, d) => Value::SRational(n, d),
+        }
+    }
+}
+
+impl core::fmt::Display for Value {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
         match self {
             Value::Byte(e) => write!(f, "{e}"),
             Value::SignedByte(e) => write!(f, "{e}"),
@@ -81,7 +154,15 @@ impl std::fmt::Display for Value {
             Value::SRational(e1, e2) => write!(f, "{e1}/{e2}"),
             Value::Ascii(e) => write!(f, "{e}"),
 
-            Value::List(_) => todo!(),
+            Value::List(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -118,14 +199,14 @@ impl Value {
             Value::Ascii(_) => TagType::ASCII,
             Value::Undefined(_) => TagType::UNDEFINED,
             Value::List(v) => {
-                if v.len() == 0 {
+                if v.is_empty() {
                     TagType::UNDEFINED
                 } else {
                     let first = &v[0];
                     let first_type = first.tag_type();
-                    let first_disc = std::mem::discriminant(first);
+                    let first_disc = core::mem::discriminant(first);
                     for it in v {
-                        if std::mem::discriminant(it) != first_disc {
+                        if core::mem::discriminant(it) != first_disc {
                             return TagType::UNDEFINED;
                         }
                     }
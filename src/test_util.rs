@@ -0,0 +1,430 @@
+//! Fabricating TIFF files byte by byte, for testing how the decoder copes with
+//! layouts that well-behaved encoders never write: unsorted tags, values at
+//! odd offsets, arrays running past the end of the file and odd IFD chains.
+//!
+//! Chunk data pushed with [`TiffBuilder::push_data`] comes right after the
+//! header, so its offset is known before the IFDs pointing to it are built.
+//! The IFDs follow, each directly followed by its values.
+//!
+//! ```
+//! # use tiff2::{structs::Tag, test_util::{FixtureIfd, TiffBuilder}, ByteOrder};
+//! let mut builder = TiffBuilder::new(ByteOrder::BigEndian, false);
+//! let offset = builder.push_data(&[0u8; 4]);
+//! builder.push_ifd(
+//!     FixtureIfd::new()
+//!         .unsorted()
+//!         .entry(Tag::StripOffsets, &u32::try_from(offset).unwrap())
+//!         .entry(Tag::ImageWidth, &2u32)
+//!         // claims 2 values, but the file ends after the first
+//!         .truncated(Tag::StripByteCounts, &[4u32, 4][..], 1),
+//! );
+//! let file: Vec<u8> = builder.build().unwrap();
+//! ```
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    encoder::{directory::entry, tiff_value::TiffValue},
+    error::TiffResult,
+    structs::{BufferedEntry, Ifd, Tag},
+    util::fix_endianness,
+    ByteOrder,
+};
+
+/// Where the next IFD offset of a [`FixtureIfd`] points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextIfd {
+    /// The IFD pushed after this one, or 0 for the last one
+    Following,
+    /// The IFD at this index in the builder, e.g. to make a cycle
+    Ifd(usize),
+    /// Any offset, e.g. past the end of the file
+    Offset(u64),
+}
+
+/// An IFD whose entries are written exactly as specified
+#[derive(Debug, Clone)]
+pub struct FixtureIfd {
+    /// Entries and, for truncated ones, how many of their values are written
+    entries: Vec<(Tag, BufferedEntry, Option<usize>)>,
+    sorted: bool,
+    next: NextIfd,
+}
+
+impl Default for FixtureIfd {
+    fn default() -> Self {
+        FixtureIfd {
+            entries: Vec::new(),
+            sorted: true,
+            next: NextIfd::Following,
+        }
+    }
+}
+
+impl FixtureIfd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry with the type and count of `value`
+    pub fn entry<T: TiffValue + ?Sized>(self, tag: Tag, value: &T) -> Self {
+        self.raw_entry(tag, entry(value))
+    }
+
+    /// Add an entry as is, so its count doesn't need to match its data
+    pub fn raw_entry(mut self, tag: Tag, entry: BufferedEntry) -> Self {
+        self.entries.push((tag, entry, None));
+        self
+    }
+
+    /// Add an entry with the count of `value`, of which only the first
+    /// `written` values are written.
+    ///
+    /// Truncated values go at the very end of the file, so the last one runs
+    /// past it. `value` shouldn't fit in the entry itself.
+    pub fn truncated<T: TiffValue + ?Sized>(mut self, tag: Tag, value: &T, written: usize) -> Self {
+        self.entries.push((tag, entry(value), Some(written)));
+        self
+    }
+
+    /// Write the entries in the order they were added, instead of sorted by
+    /// tag as the spec requires
+    pub fn unsorted(mut self) -> Self {
+        self.sorted = false;
+        self
+    }
+
+    pub fn next_ifd(mut self, next: NextIfd) -> Self {
+        self.next = next;
+        self
+    }
+
+    fn ordered_entries(&self) -> Vec<&(Tag, BufferedEntry, Option<usize>)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        if self.sorted {
+            entries.sort_by_key(|(tag, ..)| *tag);
+        }
+        entries
+    }
+}
+
+/// Builds a TIFF file out of chunk data and [`FixtureIfd`]s
+#[derive(Debug, Clone)]
+pub struct TiffBuilder {
+    byte_order: ByteOrder,
+    bigtiff: bool,
+    aligned: bool,
+    /// Everything after the header, up to the first IFD
+    data: Vec<u8>,
+    ifds: Vec<FixtureIfd>,
+}
+
+impl TiffBuilder {
+    pub fn new(byte_order: ByteOrder, bigtiff: bool) -> Self {
+        TiffBuilder {
+            byte_order,
+            bigtiff,
+            aligned: true,
+            data: Vec::new(),
+            ifds: Vec::new(),
+        }
+    }
+
+    /// Put chunk data, IFDs and values at odd offsets, instead of on word
+    /// boundaries as the spec requires. Data pushed before calling this keeps
+    /// its offset.
+    pub fn unaligned(mut self) -> Self {
+        self.aligned = false;
+        self
+    }
+
+    fn header_len(&self) -> u64 {
+        if self.bigtiff {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Where something starting at or after `pos` is placed
+    fn place(&self, pos: u64) -> u64 {
+        match (self.aligned, pos % 2) {
+            (true, 1) | (false, 0) => pos + 1,
+            _ => pos,
+        }
+    }
+
+    /// Append chunk data, returning its offset in the file
+    pub fn push_data(&mut self, data: &[u8]) -> u64 {
+        let offset = self.place(self.header_len() + self.data.len() as u64);
+        self.data
+            .resize(usize::try_from(offset - self.header_len()).unwrap(), 0);
+        self.data.extend_from_slice(data);
+        offset
+    }
+
+    pub fn push_ifd(&mut self, ifd: FixtureIfd) {
+        self.ifds.push(ifd);
+    }
+
+    /// Write the header, chunk data and IFDs
+    pub fn build(&self) -> TiffResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        let offset_len = if self.bigtiff { 8 } else { 4 };
+        match self.byte_order {
+            ByteOrder::LittleEndian => buf.extend_from_slice(b"II"),
+            ByteOrder::BigEndian => buf.extend_from_slice(b"MM"),
+        }
+        if self.bigtiff {
+            buf.extend_from_slice(&self.u16_bytes(43));
+            buf.extend_from_slice(&self.u16_bytes(8));
+            buf.extend_from_slice(&[0, 0]);
+        } else {
+            buf.extend_from_slice(&self.u16_bytes(42));
+        }
+        // first IFD offset, filled in later
+        let first_ifd_field = buf.len();
+        buf.resize(buf.len() + offset_len, 0);
+        buf.extend_from_slice(&self.data);
+
+        let mut ifd_offsets = Vec::with_capacity(self.ifds.len());
+        // (offset field, target) to fill in once everything is placed
+        let mut next_fields = Vec::with_capacity(self.ifds.len());
+        let mut truncated = Vec::new();
+        for ifd in &self.ifds {
+            let ifd_offset = self.pad(&mut buf);
+            ifd_offsets.push(ifd_offset);
+            let entries = ifd.ordered_entries();
+
+            // values that don't fit go right after the IFD
+            let mut values = Vec::new();
+            let mut value_pos = ifd_offset + Ifd::encoded_len(entries.len() as u64, self.bigtiff);
+            if self.bigtiff {
+                buf.extend_from_slice(&self.offset_bytes(entries.len() as u64)?);
+            } else {
+                buf.extend_from_slice(&self.u16_bytes(u16::try_from(entries.len())?));
+            }
+            for (tag, entry, written) in entries {
+                buf.extend_from_slice(&self.u16_bytes(tag.to_u16()));
+                buf.extend_from_slice(&self.u16_bytes(entry.tag_type.to_u16()));
+                buf.extend_from_slice(&self.offset_bytes(entry.count)?);
+                let mut data = entry.data.clone();
+                fix_endianness(
+                    &mut data,
+                    self.byte_order,
+                    8 * entry.tag_type.primitive_size(),
+                );
+                if let Some(written) = written {
+                    data.truncate(written * entry.tag_type.size());
+                    truncated.push((buf.len(), data));
+                    buf.resize(buf.len() + offset_len, 0);
+                } else if data.len() <= offset_len {
+                    data.resize(offset_len, 0);
+                    buf.extend_from_slice(&data);
+                } else {
+                    value_pos = self.place(value_pos);
+                    buf.extend_from_slice(&self.offset_bytes(value_pos)?);
+                    let len = data.len() as u64;
+                    values.push((value_pos, data));
+                    value_pos += len;
+                }
+            }
+            next_fields.push((buf.len(), ifd.next));
+            buf.resize(buf.len() + offset_len, 0);
+            for (pos, data) in values {
+                buf.resize(usize::try_from(pos)?, 0);
+                buf.extend_from_slice(&data);
+            }
+        }
+        for (field, data) in truncated {
+            let pos = self.pad(&mut buf);
+            self.put_offset(&mut buf, field, pos)?;
+            buf.extend_from_slice(&data);
+        }
+
+        self.put_offset(
+            &mut buf,
+            first_ifd_field,
+            ifd_offsets.first().copied().unwrap_or(0),
+        )?;
+        for (i, (field, next)) in next_fields.into_iter().enumerate() {
+            let next = match next {
+                NextIfd::Following => ifd_offsets.get(i + 1).copied().unwrap_or(0),
+                NextIfd::Ifd(j) => ifd_offsets[j],
+                NextIfd::Offset(offset) => offset,
+            };
+            self.put_offset(&mut buf, field, next)?;
+        }
+        Ok(buf)
+    }
+
+    /// Pad `buf` so the next write is placed correctly, returning its offset
+    fn pad(&self, buf: &mut Vec<u8>) -> u64 {
+        let pos = self.place(buf.len() as u64);
+        buf.resize(pos as usize, 0);
+        pos
+    }
+
+    fn u16_bytes(&self, v: u16) -> [u8; 2] {
+        match self.byte_order {
+            ByteOrder::LittleEndian => v.to_le_bytes(),
+            ByteOrder::BigEndian => v.to_be_bytes(),
+        }
+    }
+
+    fn offset_bytes(&self, v: u64) -> TiffResult<Vec<u8>> {
+        Ok(match (self.byte_order, self.bigtiff) {
+            (ByteOrder::LittleEndian, true) => v.to_le_bytes().to_vec(),
+            (ByteOrder::BigEndian, true) => v.to_be_bytes().to_vec(),
+            (ByteOrder::LittleEndian, false) => u32::try_from(v)?.to_le_bytes().to_vec(),
+            (ByteOrder::BigEndian, false) => u32::try_from(v)?.to_be_bytes().to_vec(),
+        })
+    }
+
+    fn put_offset(&self, buf: &mut [u8], field: usize, v: u64) -> TiffResult<()> {
+        let bytes = self.offset_bytes(v)?;
+        buf[field..field + bytes.len()].copy_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+/// Tables of a baseline JPEG stream, just enough for [`jpeg_frame`]:
+/// quantizers of 1, DC codes for differences of 0 (`00`) and 128
+/// (`01 10000000`), and an AC code for the end of a block (`0`)
+pub fn jpeg_tables() -> Vec<u8> {
+    let mut tables = vec![0xff, 0xd8, 0xff, 0xdb, 0, 67, 0];
+    tables.extend([1; 64]);
+    tables.extend([0xff, 0xc4, 0, 21, 0x00, 0, 2]);
+    tables.extend([0; 14]);
+    tables.extend([0, 8, 0xff, 0xc4, 0, 20, 0x10, 1]);
+    tables.extend([0; 15]);
+    tables.extend([0, 0xff, 0xd9]);
+    tables
+}
+
+/// Abbreviated baseline JPEG stream of a frame of `size` x `size` pixels
+/// with a component per `(id, sampling factors)`, and a scan of all of them
+/// holding `scan`.
+///
+/// With [`jpeg_tables`], `[0x60, 0x1f]` is a gray 8x8 block of 144, and
+/// `[0x60, 0, 0, 0x3f]` a 16x16 YCbCr MCU of luma 144 and chroma 128 for
+/// components `[(1, 0x22), (2, 0x11), (3, 0x11)]`.
+pub fn jpeg_frame(size: u8, components: &[(u8, u8)], scan: &[u8]) -> Vec<u8> {
+    let n = components.len() as u8;
+    let mut stream = vec![0xff, 0xd8, 0xff, 0xc0, 0, 8 + 3 * n, 8, 0, size, 0, size, n];
+    for &(id, sampling) in components {
+        stream.extend([id, sampling, 0]);
+    }
+    stream.extend([0xff, 0xda, 0, 6 + 2 * n, n]);
+    for &(id, _) in components {
+        stream.extend([id, 0x00]);
+    }
+    stream.extend([0, 63, 0]);
+    stream.extend(scan);
+    stream.extend([0xff, 0xd9]);
+    stream
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decoder::Limits;
+    #[cfg(feature = "std")]
+    use crate::{
+        decoder::DecoderOptions,
+        error::TiffError,
+        structs::{IfdEntry, TagType, Tiff},
+    };
+
+    fn ifd() -> FixtureIfd {
+        FixtureIfd::new()
+            .entry(Tag::ImageLength, &[3u16, 4][..])
+            .entry(Tag::ImageWidth, &[1u16, 2][..])
+            .entry(Tag::BitsPerSample, &[8u16; 5][..])
+    }
+
+    #[cfg(feature = "std")]
+    #[tokio::test]
+    async fn test_unsorted() {
+        for (byte_order, bigtiff) in [
+            (ByteOrder::LittleEndian, false),
+            (ByteOrder::BigEndian, false),
+            (ByteOrder::BigEndian, true),
+        ] {
+            let mut builder = TiffBuilder::new(byte_order, bigtiff);
+            builder.push_ifd(ifd().unsorted());
+            let file = builder.build().unwrap();
+            // ImageLength is the first entry
+            let first_tag = if bigtiff { 24..26 } else { 10..12 };
+            assert_eq!(
+                file[first_tag],
+                builder.u16_bytes(Tag::ImageLength.to_u16())
+            );
+            let tiff = Tiff::read(&file, &DecoderOptions::default()).await.unwrap();
+            assert_eq!(
+                tiff.ifds[0].require_tag_value(&Tag::BitsPerSample).unwrap(),
+                &entry(&[8u16; 5][..])
+            );
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[tokio::test]
+    async fn test_unaligned() {
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false).unaligned();
+        assert_eq!(builder.push_data(&[1, 2]), 9);
+        assert_eq!(builder.push_data(&[3]), 11);
+        builder.push_ifd(ifd());
+        builder.push_ifd(ifd());
+        let file = builder.build().unwrap();
+        let first_ifd = u32::from_le_bytes(file[4..8].try_into().unwrap());
+        assert_eq!(first_ifd % 2, 1);
+        let tiff = Tiff::read(&file, &DecoderOptions::default()).await.unwrap();
+        assert_eq!(tiff.ifds.len(), 2);
+        let Some(IfdEntry::Value(bits)) = tiff.ifds[1].get_tag(&Tag::BitsPerSample) else {
+            panic!("BitsPerSample should be loaded");
+        };
+        assert_eq!(bits.tag_type, TagType::SHORT);
+        assert_eq!(bits.count, 5);
+    }
+
+    #[cfg(feature = "std")]
+    #[tokio::test]
+    async fn test_truncated() {
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        builder.push_ifd(ifd().truncated(Tag::TileOffsets, &[8u32; 4][..], 3));
+        let file = builder.build().unwrap();
+        let options = DecoderOptions {
+            header_prefetch: 0,
+            ..Default::default()
+        };
+        let Err(TiffError::IoError(e)) = Tiff::read(&file, &options).await else {
+            panic!("reading the offsets should run past the end");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_next_ifd() {
+        let mut builder = TiffBuilder::new(ByteOrder::LittleEndian, false);
+        builder.push_ifd(ifd());
+        builder.push_ifd(ifd().next_ifd(NextIfd::Ifd(0)));
+        let file = builder.build().unwrap();
+        let first_ifd = u32::from_le_bytes(file[4..8].try_into().unwrap());
+        let (_, second_ifd) = Ifd::from_buffer_with_next(
+            &file[first_ifd as usize..],
+            ByteOrder::LittleEndian,
+            false,
+            &Limits::default(),
+        )
+        .unwrap();
+        let (_, next) = Ifd::from_buffer_with_next(
+            &file[second_ifd as usize..],
+            ByteOrder::LittleEndian,
+            false,
+            &Limits::default(),
+        )
+        .unwrap();
+        assert_eq!(next, u64::from(first_ifd));
+    }
+}
@@ -0,0 +1,92 @@
+//! Golden-file compatibility tests against files written by GDAL and libtiff.
+//!
+//! The corpus isn't checked in. Generate it with `tests/golden/generate.sh`,
+//! which needs GDAL and libtiff's tools, then run
+//! `cargo test --test golden -- --ignored`. Set `TIFF2_GOLDEN_DIR` to use a
+//! corpus somewhere else.
+//!
+//! Each line of the corpus' `manifest.txt` holds a file, an IFD index and the
+//! SHA-256 of that image's pixels as decoded by GDAL: pixel-interleaved, in
+//! native byte order. Files needing features this crate can't decode yet are
+//! skipped, so adding a codec puts its files under test.
+#![cfg(feature = "std")]
+
+use std::{env, fs, path::PathBuf, sync::Arc};
+
+use sha2::{Digest, Sha256};
+use tiff2::{
+    decoder::{CogDecoder, CogReader, DecoderOptions},
+    error::{TiffError, TiffFormatError, TiffResult},
+    structs::{Image, Tiff},
+    ByteOrder,
+};
+
+fn corpus_dir() -> PathBuf {
+    env::var_os("TIFF2_GOLDEN_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/corpus"))
+}
+
+/// Decode an image into a contiguous, pixel-interleaved buffer
+async fn decode(file: Vec<u8>, level: usize) -> TiffResult<Vec<u8>> {
+    let byte_order = match file.get(..2) {
+        Some(b"MM") => ByteOrder::BigEndian,
+        _ => ByteOrder::LittleEndian,
+    };
+    let (header, _) = Tiff::from_header(&file)?;
+    let reader: Arc<dyn CogReader + Send + Sync> = Arc::new(file);
+    let mut tiff = Tiff::read(&*reader, &DecoderOptions::default()).await?;
+    if level >= tiff.ifds.len() {
+        return Err(TiffFormatError::ImageFileDirectoryNotFound.into());
+    }
+    let image = Image::from_ifd(tiff.ifds.swap_remove(level), byte_order)?;
+    let meta = image.chunk_meta();
+    let mut decoder = CogDecoder::new(reader, header, &DecoderOptions::default());
+    decoder.insert_image(0, image);
+    // GDAL's reference is pixel-interleaved
+    decoder
+        .decode_region_interleaved(0, 0, 0, meta.image_width, meta.image_height)?
+        .await
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[tokio::test]
+#[ignore = "needs the corpus from tests/golden/generate.sh"]
+async fn test_golden() {
+    let dir = corpus_dir();
+    let manifest = fs::read_to_string(dir.join("manifest.txt")).unwrap_or_else(|e| {
+        panic!(
+            "no manifest in {}, run tests/golden/generate.sh first: {e}",
+            dir.display()
+        )
+    });
+
+    let mut passed = 0;
+    let mut skipped = Vec::new();
+    let mut failed = Vec::new();
+    for line in manifest.lines().filter(|l| !l.trim().is_empty()) {
+        let [name, level, expected] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+            panic!("invalid manifest line {line:?}");
+        };
+        let level: usize = level.parse().unwrap();
+        let file = fs::read(dir.join(name)).unwrap();
+        match decode(file, level).await {
+            Ok(pixels) if hex(&Sha256::digest(&pixels)) == expected => passed += 1,
+            Ok(_) => failed.push(format!("{name} [{level}]: pixels differ from GDAL's")),
+            Err(TiffError::UnsupportedError(e)) => skipped.push(format!("{name} [{level}]: {e}")),
+            Err(e) => failed.push(format!("{name} [{level}]: {e}")),
+        }
+    }
+    println!("{passed} passed, {} skipped", skipped.len());
+    for skip in &skipped {
+        println!("skipped {skip}");
+    }
+    assert!(
+        failed.is_empty(),
+        "golden files failed:\n{}",
+        failed.join("\n")
+    );
+}
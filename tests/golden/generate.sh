@@ -0,0 +1,101 @@
+#!/bin/sh
+# Generate the golden corpus for `tests/golden.rs` with GDAL and libtiff:
+#
+#   tests/golden/generate.sh [corpus dir]
+#   cargo test --test golden -- --ignored
+#
+# Every file gets a reference decode by GDAL, hashed into manifest.txt as
+# `<file> <ifd index> <sha256 of pixel-interleaved, native-endian pixels>`.
+# JPEG and lossy WebP are left out, since decoders may legitimately differ in
+# rounding.
+set -eu
+
+out=${1:-"$(dirname "$0")/corpus"}
+mkdir -p "$out"
+cd "$out"
+rm -f manifest.txt
+
+# 3-band 8-bit gradient, with an odd size so the last tiles and strips are
+# partial
+width=301
+height=203
+perl -e "print pack('C*', map { (\$_ * 7 + int(\$_ / ($width * 3)) * 13) % 256 } 0 .. $width * $height * 3 - 1)" > src.raw
+cat > src.hdr <<HDR
+ENVI
+samples = $width
+lines = $height
+bands = 3
+header offset = 0
+file type = ENVI Standard
+data type = 1
+interleave = bip
+byte order = 0
+HDR
+
+gdal_translate -q src.raw u8.tif
+gdal_translate -q -ot UInt16 -scale 0 255 0 65535 src.raw u16.tif
+gdal_translate -q -ot Float32 -scale 0 255 -1 1 src.raw f32.tif
+gdal_translate -q -ot Float64 -scale 0 255 -1 1 src.raw f64.tif
+
+# <output> <input> <gdal_translate options>
+gdal() {
+    name=$1
+    src=$2
+    shift 2
+    gdal_translate -q "$@" "$src" "$name"
+}
+
+# <output> <input> <tiffcp options>
+libtiff() {
+    name=$1
+    src=$2
+    shift 2
+    tiffcp "$@" "$src" "$name"
+}
+
+tiles="-co TILED=YES -co BLOCKXSIZE=64 -co BLOCKYSIZE=64"
+# shellcheck disable=SC2086
+{
+    gdal gdal_none_strips.tif u8.tif -co COMPRESS=NONE
+    gdal gdal_none_tiles.tif u8.tif -co COMPRESS=NONE $tiles
+    gdal gdal_none_u16_be.tif u16.tif -co ENDIANNESS=BIG $tiles
+    gdal gdal_none_f64.tif f64.tif $tiles
+    gdal gdal_none_bigtiff.tif u8.tif -co BIGTIFF=YES $tiles
+    gdal gdal_deflate.tif u8.tif -co COMPRESS=DEFLATE $tiles
+    gdal gdal_deflate_pred2_u16.tif u16.tif -co COMPRESS=DEFLATE -co PREDICTOR=2 $tiles
+    gdal gdal_deflate_pred3_f32.tif f32.tif -co COMPRESS=DEFLATE -co PREDICTOR=3 $tiles
+    gdal gdal_lzw_pred2.tif u8.tif -co COMPRESS=LZW -co PREDICTOR=2
+    gdal gdal_zstd.tif u16.tif -co COMPRESS=ZSTD $tiles
+    gdal gdal_packbits.tif u8.tif -co COMPRESS=PACKBITS
+    gdal gdal_lzma.tif u8.tif -co COMPRESS=LZMA $tiles
+    gdal gdal_lerc_f32.tif f32.tif -co COMPRESS=LERC -co MAX_Z_ERROR=0 $tiles
+    gdal gdal_lerc_deflate_u16.tif u16.tif -co COMPRESS=LERC_DEFLATE $tiles
+    gdal gdal_lerc_zstd_f32.tif f32.tif -co COMPRESS=LERC_ZSTD -co MAX_Z_ERROR=0.001 $tiles
+    gdal gdal_webp_lossless.tif u8.tif -co COMPRESS=WEBP -co WEBP_LOSSLESS=YES $tiles
+    gdal gdal_cog.tif u8.tif -of COG -co COMPRESS=DEFLATE -co BLOCKSIZE=64 -co OVERVIEW_RESAMPLING=NEAREST
+
+    libtiff libtiff_lzw_pred2.tif u8.tif -c lzw:2
+    libtiff libtiff_zip_tiles.tif u8.tif -c zip -t -w 64 -l 64
+    libtiff libtiff_packbits_be.tif u8.tif -c packbits -B
+    libtiff libtiff_none_u16_be.tif u16.tif -c none -B
+}
+
+rm -f src.raw src.hdr src.raw.aux.xml u8.tif u16.tif f32.tif f64.tif
+
+# reference decodes of every IFD, overviews included
+for file in gdal_*.tif libtiff_*.tif; do
+    n_overviews=$(gdalinfo "$file" | grep -c "^  Overviews:" || true)
+    levels=0
+    if [ "$n_overviews" -gt 0 ]; then
+        levels=$(gdalinfo "$file" | grep -m1 "^  Overviews:" | tr ',' '\n' | wc -l)
+    fi
+    for level in $(seq 0 "$levels"); do
+        if [ "$level" -eq 0 ]; then
+            gdal_translate -q -of ENVI -co INTERLEAVE=BIP "$file" ref.raw
+        else
+            gdal_translate -q -of ENVI -co INTERLEAVE=BIP -ovr $((level - 1)) "$file" ref.raw
+        fi
+        echo "$file $level $(sha256sum ref.raw | cut -d' ' -f1)" >> manifest.txt
+        rm -f ref.raw ref.hdr ref.raw.aux.xml
+    done
+done